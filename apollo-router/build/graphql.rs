@@ -0,0 +1,18 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+pub fn main() -> Result<(), Box<dyn Error>> {
+    let proto_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap())
+        .join("src")
+        .join("axum_factory")
+        .join("proto");
+    let graphql_src = proto_dir.join("graphql.proto");
+
+    println!("cargo:rerun-if-changed={}", graphql_src.to_str().unwrap());
+
+    tonic_build::configure()
+        .emit_rerun_if_changed(false)
+        .compile(&[graphql_src], &[proto_dir])?;
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
+mod coprocessor;
+mod graphql;
 mod studio;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    studio::main()
+    studio::main()?;
+    coprocessor::main()?;
+    graphql::main()
 }
@@ -0,0 +1,108 @@
+//! Allocation counting, enabled by the `allocation_metrics` feature
+//!
+//! The global allocator counts the allocations of each thread, so that the allocations made by
+//! a synchronous piece of work, such as query parsing, can be reported as metrics.
+//!
+//! Allocations are only counted: they are still served by the system allocator. Parsed queries
+//! and query plans are cached and shared across requests, so they cannot be allocated in an arena
+//! freed at the end of the request. The metrics measure how much a cheaper allocation of their
+//! transient data would save.
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::cell::Cell;
+
+#[cfg(feature = "dhat-heap")]
+compile_error!("the allocation_metrics and dhat-heap features cannot be enabled together");
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    // const initializers do not allocate, which would recurse into the allocator
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The system allocator, counting the allocations of the current thread
+struct CountingAllocator;
+
+fn count(size: usize) {
+    // the thread locals are not available while a thread is destroyed
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    let _ = ALLOCATED_BYTES.try_with(|bytes| bytes.set(bytes.get() + size as u64));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Allocations made by a piece of work
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Allocations {
+    /// Number of allocations and reallocations
+    pub(crate) count: u64,
+    /// Number of bytes requested
+    pub(crate) bytes: u64,
+}
+
+fn current() -> Allocations {
+    Allocations {
+        count: ALLOCATIONS.with(Cell::get),
+        bytes: ALLOCATED_BYTES.with(Cell::get),
+    }
+}
+
+/// Runs `f` and returns the allocations it made. Only the current thread is counted: `f` must
+/// not hand work to other threads or be a future
+pub(crate) fn measure<T>(f: impl FnOnce() -> T) -> (T, Allocations) {
+    let before = current();
+    let result = f();
+    let after = current();
+    (
+        result,
+        Allocations {
+            count: after.count.wrapping_sub(before.count),
+            bytes: after.bytes.wrapping_sub(before.bytes),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_allocations_of_the_current_thread() {
+        let (vec, allocations) = measure(|| Vec::<u8>::with_capacity(100));
+        assert_eq!(vec.capacity(), 100);
+        assert_eq!(
+            allocations,
+            Allocations {
+                count: 1,
+                bytes: 100
+            }
+        );
+
+        let (_, allocations) = measure(|| 1 + 1);
+        assert_eq!(allocations, Allocations::default());
+    }
+}
@@ -0,0 +1,355 @@
+//! Admin API
+//!
+//! Operational actions exposed on their own listener, for the bearer token of the configuration:
+//! purging the caches, reading the effective configuration and the loaded plugins, changing the
+//! log filter, and toggling the maintenance mode, where the router answers with a configurable response, a 503 status by
+//! default, to all requests but the health check and the admin API. CPU profiles can be
+//! captured on demand when they are enabled, and routers built with the `jemalloc` feature also
+//! serve their heap statistics and profiles.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+#[cfg(unix)]
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::header::AUTHORIZATION;
+use http::header::CONTENT_TYPE;
+use http::header::RETRY_AFTER;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use tower::service_fn;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::configuration::Configuration;
+use crate::configuration::MaintenanceResponse;
+#[cfg(unix)]
+use crate::cpu_profiling;
+#[cfg(unix)]
+use crate::cpu_profiling::ProfileFormat;
+#[cfg(unix)]
+use crate::cpu_profiling::DEFAULT_MAX_PROFILE_DURATION;
+#[cfg(unix)]
+use crate::cpu_profiling::DEFAULT_PROFILE_DURATION;
+use crate::graphql;
+use crate::log_filter;
+use crate::router_factory::Endpoint;
+use crate::router_factory::RouterFactory;
+use crate::services::router;
+
+/// Maintenance mode of the router. It is kept by the HTTP server factory, so that it survives
+/// configuration and schema reloads
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed)
+    }
+}
+
+/// Response to the requests received in maintenance, built from the configuration
+#[derive(Clone, Debug)]
+pub(super) struct MaintenanceReply {
+    status: StatusCode,
+    body: Bytes,
+    retry_after: Option<HeaderValue>,
+}
+
+impl MaintenanceReply {
+    pub(super) fn new(configuration: &MaintenanceResponse) -> Result<Self, BoxError> {
+        let mut error = graphql::Error::builder()
+            .message(configuration.message.clone())
+            .extension_code(configuration.extension_code.clone())
+            .build();
+        for (key, value) in &configuration.extensions {
+            error
+                .extensions
+                .insert(key.as_str(), serde_json_bytes::to_value(value)?);
+        }
+        let response = graphql::Response::builder().error(error).build();
+        Ok(MaintenanceReply {
+            status: StatusCode::from_u16(configuration.status_code)?,
+            body: serde_json::to_vec(&response)?.into(),
+            retry_after: configuration
+                .retry_after
+                .map(|delay| HeaderValue::from(delay.as_secs())),
+        })
+    }
+}
+
+/// Answers the requests with the maintenance response while the router is in maintenance
+pub(super) async fn maintenance(
+    State((maintenance, reply)): State<(Maintenance, MaintenanceReply)>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if !maintenance.is_enabled() {
+        return next.run(request).await;
+    }
+    let mut response = (
+        reply.status,
+        [(CONTENT_TYPE, APPLICATION_JSON.essence_str())],
+        reply.body,
+    )
+        .into_response();
+    if let Some(retry_after) = reply.retry_after {
+        response.headers_mut().insert(RETRY_AFTER, retry_after);
+    }
+    response
+}
+
+#[derive(Deserialize)]
+struct MaintenanceToggle {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct LogFilterChange {
+    /// Directives of the new filter, or none to reset the startup filter
+    filter: Option<String>,
+}
+
+#[cfg(unix)]
+#[derive(Deserialize)]
+struct CpuProfileQuery {
+    seconds: Option<u64>,
+    #[serde(default)]
+    format: ProfileFormat,
+}
+
+/// Successful response of an admin action
+enum Reply {
+    Json(Value),
+    /// A file, such as a profile
+    #[cfg_attr(not(unix), allow(dead_code))]
+    File {
+        content_type: &'static str,
+        body: Vec<u8>,
+    },
+}
+
+/// The endpoints of the admin API
+pub(super) fn endpoints<RF>(
+    service_factory: RF,
+    configuration: &Configuration,
+    maintenance: Maintenance,
+) -> Vec<Endpoint>
+where
+    RF: RouterFactory,
+{
+    let token: Arc<str> = configuration.admin.token.clone().unwrap_or_default().into();
+    let config = configuration.redacted_yaml.clone().unwrap_or_default();
+
+    let status_factory = service_factory.clone();
+    let status_maintenance = maintenance.clone();
+    #[allow(unused_mut)]
+    let mut endpoints = vec![
+        endpoint("/admin/caches/purge", Method::POST, &token, move |_| {
+            let clear = service_factory.clear_caches();
+            Box::pin(async move {
+                clear.await;
+                tracing::info!("caches purged from the admin API");
+                Ok(Reply::Json(
+                    json!({ "purged": ["apq", "query_plans", "introspection"] }),
+                ))
+            })
+        }),
+        endpoint("/admin/config", Method::GET, &token, move |_| {
+            let config = config.clone();
+            Box::pin(async move { Ok(Reply::Json(config)) })
+        }),
+        endpoint("/admin/status", Method::GET, &token, move |_| {
+            let status = json!({
+                "schema_id": status_factory.schema_id(),
+                "plugins": status_factory.plugin_names(),
+                "maintenance": status_maintenance.is_enabled(),
+                "log_filter": log_filter::current(),
+            });
+            Box::pin(async move { Ok(Reply::Json(status)) })
+        }),
+        endpoint("/admin/maintenance", Method::POST, &token, move |request| {
+            let maintenance = maintenance.clone();
+            Box::pin(async move {
+                let toggle: MaintenanceToggle = serde_json::from_slice(request.body())?;
+                maintenance.set(toggle.enabled);
+                tracing::info!(
+                    "maintenance mode {} from the admin API",
+                    if toggle.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+                Ok(Reply::Json(json!({ "maintenance": toggle.enabled })))
+            })
+        }),
+        endpoint("/admin/log/filter", Method::POST, &token, |request| {
+            Box::pin(async move {
+                let change: LogFilterChange = serde_json::from_slice(request.body())?;
+                let filter = log_filter::set(change.filter.as_deref())?;
+                tracing::info!("log filter set to '{filter}' from the admin API");
+                Ok(Reply::Json(json!({ "log_filter": filter })))
+            })
+        }),
+    ];
+
+    #[cfg(unix)]
+    if configuration.admin.cpu_profiling.enabled {
+        let max_duration = configuration
+            .admin
+            .cpu_profiling
+            .max_duration
+            .unwrap_or(DEFAULT_MAX_PROFILE_DURATION);
+        endpoints.push(endpoint(
+            "/admin/profile/cpu",
+            Method::GET,
+            &token,
+            move |request| {
+                Box::pin(async move {
+                    let query: CpuProfileQuery =
+                        serde_urlencoded::from_str(request.uri().query().unwrap_or_default())?;
+                    let duration = query
+                        .seconds
+                        .map(Duration::from_secs)
+                        .unwrap_or(DEFAULT_PROFILE_DURATION)
+                        .min(max_duration);
+                    tracing::info!("capturing a CPU profile for {duration:?} from the admin API");
+                    let body = tokio::task::spawn_blocking(move || {
+                        cpu_profiling::profile(duration, query.format)
+                    })
+                    .await??;
+                    Ok(Reply::File {
+                        content_type: query.format.content_type(),
+                        body,
+                    })
+                })
+            },
+        ));
+    }
+
+    #[cfg(all(feature = "jemalloc", unix))]
+    endpoints.extend(heap_endpoints(&token));
+
+    endpoints
+}
+
+/// Heap statistics and profiles of the jemalloc allocator
+#[cfg(all(feature = "jemalloc", unix))]
+fn heap_endpoints(token: &Arc<str>) -> Vec<Endpoint> {
+    vec![
+        endpoint("/admin/heap/stats", Method::GET, token, |_| {
+            Box::pin(async {
+                Ok(Reply::Json(serde_json::to_value(
+                    crate::jemalloc::HeapStats::read()?,
+                )?))
+            })
+        }),
+        endpoint("/admin/heap/profile", Method::POST, token, |_| {
+            Box::pin(async {
+                let path = tokio::task::spawn_blocking(crate::jemalloc::dump_profile).await??;
+                tracing::info!("heap profile dumped to {}", path.display());
+                Ok(Reply::Json(json!({ "path": path })))
+            })
+        }),
+    ]
+}
+
+fn endpoint(
+    path: &str,
+    method: Method,
+    token: &Arc<str>,
+    action: impl Fn(Request<Bytes>) -> BoxFuture<'static, Result<Reply, BoxError>>
+        + Send
+        + Sync
+        + 'static,
+) -> Endpoint {
+    let token = token.clone();
+    let action = Arc::new(action);
+    Endpoint::from_router_service(
+        path.to_string(),
+        service_fn(move |request: router::Request| {
+            let method = method.clone();
+            let token = token.clone();
+            let action = action.clone();
+            async move {
+                let router::Request {
+                    router_request,
+                    context,
+                } = request;
+                let (parts, body) = router_request.into_parts();
+                let (status, reply) = if !authorized(&parts.headers, &token) {
+                    (
+                        StatusCode::UNAUTHORIZED,
+                        Reply::Json(json!({ "error": "missing or invalid admin token" })),
+                    )
+                } else if parts.method != method {
+                    (
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        Reply::Json(json!({ "error": format!("use the {method} method") })),
+                    )
+                } else {
+                    let body = hyper::body::to_bytes(body).await?;
+                    match action(Request::from_parts(parts, body)).await {
+                        Ok(reply) => (StatusCode::OK, reply),
+                        Err(error) => (
+                            StatusCode::BAD_REQUEST,
+                            Reply::Json(json!({ "error": error.to_string() })),
+                        ),
+                    }
+                };
+                let (content_type, body) = match reply {
+                    Reply::Json(value) => {
+                        (APPLICATION_JSON.essence_str(), serde_json::to_vec(&value)?)
+                    }
+                    Reply::File { content_type, body } => (content_type, body),
+                };
+                Ok::<_, BoxError>(router::Response {
+                    response: http::Response::builder()
+                        .status(status)
+                        .header(CONTENT_TYPE, content_type)
+                        .body::<hyper::Body>(body.into())?,
+                    context,
+                })
+            }
+        })
+        .boxed(),
+    )
+}
+
+/// Compares the bearer token in constant time
+fn authorized(headers: &http::HeaderMap, token: &str) -> bool {
+    let provided = match headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(provided) => provided.as_bytes(),
+        None => return false,
+    };
+    !token.is_empty()
+        && provided.len() == token.len()
+        && provided
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
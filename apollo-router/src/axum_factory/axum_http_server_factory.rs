@@ -29,17 +29,28 @@ use tower_http::compression::DefaultPredicate;
 use tower_http::compression::Predicate;
 use tower_http::trace::TraceLayer;
 
+use super::admin;
+use super::admin::Maintenance;
+use super::admin::MaintenanceReply;
+use super::grpc;
 use super::listeners::ensure_endpoints_consistency;
 use super::listeners::ensure_listenaddrs_consistency;
 use super::listeners::extra_endpoints;
 use super::listeners::ListenersAndRouters;
+use super::rest;
 use super::utils::decompress_request_body;
+use super::utils::problem_details;
+use super::utils::security_headers;
 use super::utils::PropagatingMakeSpan;
+use super::websocket;
 use super::ListenAddrAndRouter;
 use crate::axum_factory::listeners::get_extra_listeners;
 use crate::axum_factory::listeners::serve_router_on_listen_addr;
+use crate::configuration::cors::CorsLayer;
 use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
+use crate::error::router_error_response;
+use crate::graphql;
 use crate::http_server_factory::HttpServerFactory;
 use crate::http_server_factory::HttpServerHandle;
 use crate::http_server_factory::Listener;
@@ -53,17 +64,20 @@ use crate::services::router;
 /// A basic http server using Axum.
 /// Uses streaming as primary method of response.
 #[derive(Debug)]
-pub(crate) struct AxumHttpServerFactory;
+pub(crate) struct AxumHttpServerFactory {
+    maintenance: Maintenance,
+}
 
 impl AxumHttpServerFactory {
     pub(crate) fn new() -> Self {
-        Self
+        Self {
+            maintenance: Maintenance::default(),
+        }
     }
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "UPPERCASE")]
-#[allow(dead_code)]
 enum HealthStatus {
     Up,
     Down,
@@ -78,10 +92,19 @@ pub(crate) fn make_axum_router<RF>(
     service_factory: RF,
     configuration: &Configuration,
     mut endpoints: MultiMap<ListenAddr, Endpoint>,
+    maintenance: Maintenance,
 ) -> Result<ListenersAndRouters, ApolloRouterError>
 where
     RF: RouterFactory,
 {
+    if configuration.admin.enabled {
+        tracing::info!("admin API exposed at {}/admin", configuration.admin.listen);
+        endpoints.insert_many(
+            configuration.admin.listen.clone(),
+            admin::endpoints(service_factory.clone(), configuration, maintenance.clone()),
+        );
+    }
+
     ensure_listenaddrs_consistency(configuration, &endpoints)?;
 
     if configuration.health_check.enabled {
@@ -89,19 +112,26 @@ where
             "healthcheck endpoint exposed at {}/health",
             configuration.health_check.listen
         );
+        let health_maintenance = maintenance.clone();
         endpoints.insert(
             configuration.health_check.listen.clone(),
             Endpoint::from_router_service(
                 "/health".to_string(),
                 service_fn(move |req: router::Request| {
-                    let health = Health {
-                        status: HealthStatus::Up,
+                    // load balancers stop sending traffic to routers in maintenance
+                    let (status, health) = if health_maintenance.is_enabled() {
+                        (StatusCode::SERVICE_UNAVAILABLE, HealthStatus::Down)
+                    } else {
+                        (StatusCode::OK, HealthStatus::Up)
                     };
+                    let health = Health { status: health };
                     async move {
                         Ok(router::Response {
-                            response: http::Response::builder().body::<hyper::Body>(
-                                serde_json::to_vec(&health).map_err(BoxError::from)?.into(),
-                            )?,
+                            response: http::Response::builder()
+                                .status(status)
+                                .body::<hyper::Body>(
+                                    serde_json::to_vec(&health).map_err(BoxError::from)?.into(),
+                                )?,
                             context: req.context,
                         })
                     }
@@ -113,14 +143,32 @@ where
 
     ensure_endpoints_consistency(configuration, &endpoints)?;
 
+    let grpc_router = if configuration.grpc.enabled {
+        tracing::info!(
+            "GraphQL over gRPC endpoint exposed at {}",
+            configuration.grpc.listen
+        );
+        Some(grpc::router(
+            service_factory.clone(),
+            configuration,
+            maintenance.clone(),
+        ))
+    } else {
+        None
+    };
+
     let mut main_endpoint = main_endpoint(
         service_factory,
         configuration,
+        maintenance,
         endpoints
             .remove(&configuration.supergraph.listen)
             .unwrap_or_default(),
     )?;
-    let mut extra_endpoints = extra_endpoints(endpoints);
+    let mut extra_endpoints = extra_endpoints(configuration, endpoints)?;
+    if let Some(grpc_router) = grpc_router {
+        extra_endpoints.insert(configuration.grpc.listen.clone(), grpc_router);
+    }
 
     // put any extra endpoint that uses the main ListenAddr into the main router
     if let Some(routers) = extra_endpoints.remove(&main_endpoint.0) {
@@ -149,8 +197,14 @@ impl HttpServerFactory for AxumHttpServerFactory {
     where
         RF: RouterFactory,
     {
+        let maintenance = self.maintenance.clone();
         Box::pin(async move {
-            let all_routers = make_axum_router(service_factory, &configuration, extra_endpoints)?;
+            let all_routers = make_axum_router(
+                service_factory,
+                &configuration,
+                extra_endpoints,
+                maintenance,
+            )?;
 
             // serve main router
 
@@ -272,6 +326,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
 fn main_endpoint<RF>(
     service_factory: RF,
     configuration: &Configuration,
+    maintenance: Maintenance,
     endpoints_on_main_listener: Vec<Endpoint>,
 ) -> Result<ListenAddrAndRouter, ApolloRouterError>
 where
@@ -281,8 +336,38 @@ where
         ApolloRouterError::ServiceCreationError(format!("CORS configuration error: {e}").into())
     })?;
 
-    let main_route = main_router::<RF>(configuration)
+    let headers = configuration
+        .server
+        .security_headers
+        .header_map()
+        .map_err(|e| {
+            ApolloRouterError::ServiceCreationError(
+                format!("security headers configuration error: {e}").into(),
+            )
+        })?;
+
+    let maintenance_reply =
+        MaintenanceReply::new(&configuration.admin.maintenance).map_err(|e| {
+            ApolloRouterError::ServiceCreationError(
+                format!("maintenance response configuration error: {e}").into(),
+            )
+        })?;
+
+    let problem_details_enabled = configuration
+        .server
+        .problem_details
+        .enabled_on(&configuration.supergraph.listen);
+    let mut main_route = main_router::<RF>(configuration, cors.clone())
+        .merge(rest::router(service_factory.clone(), configuration))
         .layer(middleware::from_fn(decompress_request_body))
+        .layer(middleware::from_fn_with_state(
+            (maintenance.clone(), maintenance_reply.clone()),
+            admin::maintenance,
+        ));
+    if problem_details_enabled {
+        main_route = main_route.layer(middleware::from_fn(problem_details));
+    }
+    let main_route = main_route
         .layer(TraceLayer::new_for_http().make_span_with(PropagatingMakeSpan::default()))
         .layer(Extension(service_factory))
         .layer(cors)
@@ -292,15 +377,33 @@ where
             DefaultPredicate::new().and(NotForContentType::const_new("multipart/")),
         ));
 
-    let route = endpoints_on_main_listener
+    let mut route = endpoints_on_main_listener
         .into_iter()
-        .fold(main_route, |acc, r| acc.merge(r.into_router()));
+        .fold(main_route, |acc, r| {
+            // the health check and the admin API keep serving in maintenance
+            let in_maintenance = r.path != "/health" && !r.path.starts_with("/admin/");
+            let mut router = r.into_router();
+            if in_maintenance {
+                router = router.layer(middleware::from_fn_with_state(
+                    (maintenance.clone(), maintenance_reply.clone()),
+                    admin::maintenance,
+                ));
+            }
+            if problem_details_enabled {
+                acc.merge(router.layer(middleware::from_fn(problem_details)))
+            } else {
+                acc.merge(router)
+            }
+        });
+    if !headers.is_empty() {
+        route = route.layer(security_headers(headers));
+    }
 
     let listener = configuration.supergraph.listen.clone();
     Ok(ListenAddrAndRouter(listener, route))
 }
 
-pub(super) fn main_router<RF>(configuration: &Configuration) -> axum::Router
+pub(super) fn main_router<RF>(configuration: &Configuration, cors: CorsLayer) -> axum::Router
 where
     RF: RouterFactory,
 {
@@ -310,11 +413,22 @@ where
         graphql_configuration.path = format!("{}router_extra_path", graphql_configuration.path);
     }
 
+    let websocket = Arc::new(graphql_configuration.websocket.clone());
     Router::new().route(
         &graphql_configuration.path,
         get({
             move |Extension(service): Extension<RF>, request: Request<Body>| {
-                handle_graphql(service.create().boxed(), request)
+                let websocket = websocket.clone();
+                let cors = cors.clone();
+                async move {
+                    if websocket.enabled && websocket::is_upgrade(request.headers()) {
+                        websocket::upgrade(service, &websocket, &cors, request).await
+                    } else {
+                        handle_graphql(service.create().boxed(), request)
+                            .await
+                            .into_response()
+                    }
+                }
             }
         })
         .post({
@@ -340,11 +454,11 @@ async fn handle_graphql(
                 }
             }
             tracing::error!("router service call failed: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "router service call failed",
-            )
-                .into_response()
+            let error = graphql::Error::builder()
+                .message("router service call failed")
+                .extension_code("INTERNAL_SERVER_ERROR")
+                .build();
+            router_error_response(StatusCode::INTERNAL_SERVER_ERROR, error).into_response()
         }
         Ok(response) => response.response.into_response(),
     }
@@ -0,0 +1,257 @@
+//! GraphQL over gRPC server
+//!
+//! Exposes the supergraph to gRPC clients, on its own listener by default. Each call goes through
+//! the same router pipeline as the HTTP requests: its metadata is sent as HTTP headers, and its
+//! responses, several with `@defer`, are streamed back. The `grpc-timeout` deadline of the client
+//! applies to the whole operation.
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::Stream;
+use futures::StreamExt;
+use http::header::ACCEPT;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use http::Method;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Map as JsonMap;
+use serde_json_bytes::Value;
+use tokio::time::Instant;
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+use tower::ServiceExt;
+
+use self::proto::graphql_server::Graphql;
+use self::proto::graphql_server::GraphqlServer;
+use super::admin::Maintenance;
+use crate::configuration::Configuration;
+use crate::graphql;
+use crate::http_server_factory::ConnectionInfo;
+use crate::router_factory::RouterFactory;
+
+#[allow(unreachable_pub)]
+pub(crate) mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+    tonic::include_proto!("graphql");
+}
+
+/// Media types accepted by the calls, to stream the deferred responses
+const ACCEPT_DEFER: &str = "application/json, multipart/mixed;deferSpec=20220824";
+
+type ResponseStream = Pin<Box<dyn Stream<Item = Result<proto::Response, Status>> + Send>>;
+
+/// The router serving the gRPC service
+pub(super) fn router<RF>(
+    service_factory: RF,
+    configuration: &Configuration,
+    maintenance: Maintenance,
+) -> axum::Router
+where
+    RF: RouterFactory,
+{
+    // the calls are sent to the GraphQL path, without its wildcard
+    let path = configuration
+        .supergraph
+        .path
+        .trim_end_matches('*')
+        .to_string();
+    let service = GraphqlServer::new(GraphqlService {
+        service_factory,
+        path,
+        maintenance,
+        maintenance_message: configuration.admin.maintenance.message.clone(),
+    });
+    axum::Router::new().route_service("/graphql.Graphql/*rpc", service)
+}
+
+struct GraphqlService<RF> {
+    service_factory: RF,
+    path: String,
+    maintenance: Maintenance,
+    maintenance_message: String,
+}
+
+#[tonic::async_trait]
+impl<RF> Graphql for GraphqlService<RF>
+where
+    RF: RouterFactory,
+{
+    type ExecuteStream = ResponseStream;
+
+    async fn execute(
+        &self,
+        request: tonic::Request<proto::Request>,
+    ) -> Result<tonic::Response<Self::ExecuteStream>, Status> {
+        if self.maintenance.is_enabled() {
+            return Err(Status::unavailable(self.maintenance_message.clone()));
+        }
+        let deadline = grpc_timeout(request.metadata()).map(|timeout| Instant::now() + timeout);
+        let http_request = http_request(&self.path, request)?;
+
+        let service = self.service_factory.create();
+        let call = service.oneshot(http_request.into());
+        let response = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, call)
+                .await
+                .map_err(|_| deadline_exceeded())?,
+            None => call.await,
+        }
+        .map_err(|e| {
+            tracing::error!("router service call failed: {}", e);
+            Status::internal("router service call failed")
+        })?;
+
+        let metadata = response_metadata(response.response.headers().clone());
+        let responses = response
+            .into_graphql_response_stream()
+            .await
+            .map(|response| {
+                let body = serde_json::to_string(&response?)?;
+                Ok::<_, serde_json::Error>(proto::Response { body })
+            })
+            .map(|response| {
+                response.map_err(|e| Status::internal(format!("invalid GraphQL response: {e}")))
+            });
+
+        let mut response = tonic::Response::new(with_deadline(responses, deadline));
+        *response.metadata_mut() = metadata;
+        Ok(response)
+    }
+}
+
+/// Converts the call to the HTTP request of the router pipeline
+fn http_request(
+    path: &str,
+    request: tonic::Request<proto::Request>,
+) -> Result<http::Request<Body>, Status> {
+    let headers = request.metadata().clone().into_headers();
+    let connection_info = request.extensions().get::<ConnectionInfo>().copied();
+    let request = request.into_inner();
+
+    let body = graphql::Request::builder()
+        .query(request.query)
+        .and_operation_name(request.operation_name)
+        .variables(json_object("variables", request.variables)?)
+        .extensions(json_object("extensions", request.extensions)?)
+        .build();
+    let body = serde_json::to_vec(&body).map_err(|e| Status::internal(e.to_string()))?;
+
+    let mut http_request = http::Request::builder()
+        .method(Method::POST)
+        .uri(path)
+        .body(Body::from(body))
+        .map_err(|e| Status::internal(e.to_string()))?;
+    for (name, value) in headers.iter() {
+        // gRPC and binary metadata are not HTTP headers of the operation
+        let name_str = name.as_str();
+        if name_str.starts_with("grpc-")
+            || name_str.ends_with("-bin")
+            || name_str == "te"
+            || name == CONTENT_TYPE
+            || name == CONTENT_LENGTH
+        {
+            continue;
+        }
+        http_request
+            .headers_mut()
+            .append(name.clone(), value.clone());
+    }
+    http_request.headers_mut().insert(
+        CONTENT_TYPE,
+        APPLICATION_JSON
+            .essence_str()
+            .parse()
+            .expect("valid header value"),
+    );
+    http_request
+        .headers_mut()
+        .insert(ACCEPT, ACCEPT_DEFER.parse().expect("valid header value"));
+    if let Some(connection_info) = connection_info {
+        http_request.extensions_mut().insert(connection_info);
+    }
+    Ok(http_request)
+}
+
+fn json_object(field: &str, json: Option<String>) -> Result<JsonMap<ByteString, Value>, Status> {
+    match json.as_deref() {
+        None | Some("") => Ok(JsonMap::new()),
+        Some(json) => serde_json::from_str(json)
+            .map_err(|e| Status::invalid_argument(format!("invalid JSON object in {field}: {e}"))),
+    }
+}
+
+/// The HTTP headers of the response, sent as metadata
+fn response_metadata(mut headers: HeaderMap) -> MetadataMap {
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    MetadataMap::from_headers(headers)
+}
+
+/// Parses the `grpc-timeout` metadata: at most 8 digits followed by a unit
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let timeout = metadata.get("grpc-timeout")?.to_str().ok()?;
+    if timeout.len() < 2 || timeout.len() > 9 {
+        return None;
+    }
+    let (value, unit) = timeout.split_at(timeout.len() - 1);
+    let value: u64 = value.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(value * 3600),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+/// Ends the stream with a `DEADLINE_EXCEEDED` status if its deadline is reached
+fn with_deadline(
+    responses: impl Stream<Item = Result<proto::Response, Status>> + Send + 'static,
+    deadline: Option<Instant>,
+) -> ResponseStream {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return Box::pin(responses),
+    };
+    Box::pin(futures::stream::unfold(
+        Some(Box::pin(responses)),
+        move |responses| async move {
+            let mut responses = responses?;
+            match tokio::time::timeout_at(deadline, responses.next()).await {
+                Ok(Some(response)) => Some((response, Some(responses))),
+                Ok(None) => None,
+                Err(_) => Some((Err(deadline_exceeded()), None)),
+            }
+        },
+    ))
+}
+
+fn deadline_exceeded() -> Status {
+    Status::deadline_exceeded("the deadline of the operation was exceeded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_grpc_timeouts() {
+        let timeout = |value: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert("grpc-timeout", value.parse().unwrap());
+            grpc_timeout(&metadata)
+        };
+        assert_eq!(timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(timeout("1500m"), Some(Duration::from_millis(1500)));
+        assert_eq!(timeout("1M"), Some(Duration::from_secs(60)));
+        assert_eq!(timeout("S"), None);
+        assert_eq!(timeout("123456789S"), None);
+        assert_eq!(timeout("10x"), None);
+    }
+}
@@ -6,6 +6,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use axum::middleware;
 use axum::response::*;
 use axum::Router;
 use futures::channel::oneshot;
@@ -16,9 +17,13 @@ use tokio::net::TcpListener;
 #[cfg(unix)]
 use tokio::net::UnixListener;
 use tokio::sync::Notify;
+use tower::ServiceBuilder;
 
+use super::utils::problem_details;
+use super::utils::security_headers;
 use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
+use crate::http_server_factory::ConnectionInfo;
 use crate::http_server_factory::Listener;
 use crate::http_server_factory::NetworkStream;
 use crate::router::ApolloRouterError;
@@ -76,16 +81,51 @@ pub(super) fn ensure_endpoints_consistency(
 }
 
 pub(super) fn extra_endpoints(
+    configuration: &Configuration,
     endpoints: MultiMap<ListenAddr, Endpoint>,
-) -> MultiMap<ListenAddr, Router> {
+) -> Result<MultiMap<ListenAddr, Router>, ApolloRouterError> {
+    let headers = configuration
+        .server
+        .security_headers
+        .header_map()
+        .map_err(|e| {
+            ApolloRouterError::ServiceCreationError(
+                format!("security headers configuration error: {e}").into(),
+            )
+        })?;
     let mut mm: MultiMap<ListenAddr, axum::Router> = Default::default();
-    mm.extend(endpoints.into_iter().map(|(listen_addr, e)| {
-        (
-            listen_addr,
-            e.into_iter().map(|e| e.into_router()).collect::<Vec<_>>(),
-        )
-    }));
-    mm
+    for (listen_addr, e) in endpoints.into_iter() {
+        let problem_details_enabled = configuration
+            .server
+            .problem_details
+            .enabled_on(&listen_addr);
+        let cors = configuration
+            .cors
+            .listener_layer(&listen_addr)
+            .map_err(|e| {
+                ApolloRouterError::ServiceCreationError(
+                    format!("CORS configuration error: {e}").into(),
+                )
+            })?;
+        let routers = e
+            .into_iter()
+            .map(|e| {
+                let mut router = e.into_router();
+                if problem_details_enabled {
+                    router = router.layer(middleware::from_fn(problem_details));
+                }
+                if let Some(cors) = &cors {
+                    router = router.layer(cors.clone());
+                }
+                if !headers.is_empty() {
+                    router = router.layer(security_headers(headers.clone()));
+                }
+                router
+            })
+            .collect::<Vec<_>>();
+        mm.insert_many(listen_addr, routers);
+    }
+    Ok(mm)
 }
 
 /// Binding different listen addresses to the same port will "relax" the requirements, which
@@ -122,6 +162,20 @@ pub(super) fn ensure_listenaddrs_consistency(
         }
     }
 
+    if configuration.grpc.enabled {
+        if let Some((ip, port)) = configuration.grpc.listen.ip_and_port() {
+            if let Some(previous_ip) = all_ports.insert(port, ip) {
+                if ip != previous_ip {
+                    return Err(ApolloRouterError::DifferentListenAddrsOnSamePort(
+                        previous_ip,
+                        ip,
+                        port,
+                    ));
+                }
+            }
+        }
+    }
+
     for addr in endpoints.keys() {
         if let Some((ip, port)) = addr.ip_and_port() {
             if let Some(previous_ip) = all_ports.insert(port, ip) {
@@ -224,9 +278,23 @@ pub(super) fn serve_router_on_listen_addr(
                                             .expect(
                                                 "this should not fail unless the socket is invalid",
                                             );
+                                        // the client IP of the requests is computed from the peer address
+                                        let peer_address = stream.peer_addr().ok();
+                                        let app = ServiceBuilder::new()
+                                            .map_request(move |mut request: http::Request<hyper::Body>| {
+                                                if let Some(peer_address) = peer_address {
+                                                    request
+                                                        .extensions_mut()
+                                                        .insert(ConnectionInfo { peer_address });
+                                                }
+                                                request
+                                            })
+                                            .service(app);
                                             let connection = Http::new()
                                             .http1_keep_alive(true)
-                                            .serve_connection(stream, app);
+                                            .serve_connection(stream, app)
+                                            // WebSocket connections upgrade the HTTP/1 connection
+                                            .with_upgrades();
 
                                         tokio::pin!(connection);
                                         tokio::select! {
@@ -248,7 +316,9 @@ pub(super) fn serve_router_on_listen_addr(
                                     NetworkStream::Unix(stream) => {
                                         let connection = Http::new()
                                         .http1_keep_alive(true)
-                                        .serve_connection(stream, app);
+                                        .serve_connection(stream, app)
+                                            // WebSocket connections upgrade the HTTP/1 connection
+                                            .with_upgrades();
 
                                         tokio::pin!(connection);
                                         tokio::select! {
@@ -1,10 +1,22 @@
 //! axum factory is useful to create an [`AxumHttpServerFactory`] which implements [`crate::http_server_factory::HttpServerFactory`]
+mod admin;
 mod axum_http_server_factory;
+mod grpc;
 mod listeners;
+mod rest;
 #[cfg(test)]
 pub(crate) mod tests;
 pub(crate) mod utils;
+mod websocket;
+
+use http::HeaderMap;
 
 pub(crate) use axum_http_server_factory::make_axum_router;
 pub(crate) use axum_http_server_factory::AxumHttpServerFactory;
 pub(crate) use listeners::ListenAddrAndRouter;
+
+/// Headers of the client request an operation was built from, in the extensions of the request of
+/// the operation: the upgrade request of a WebSocket connection, or the request of a REST route.
+/// The router sets the content type of these operations itself.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientHeaders(pub(crate) HeaderMap);
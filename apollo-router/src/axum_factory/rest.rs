@@ -0,0 +1,317 @@
+//! REST routes executing GraphQL operations persisted in the configuration
+//!
+//! Each route builds the variables of its operation from the path, query string and headers of
+//! the request, then sends the operation through the router pipeline, like a GraphQL request with
+//! the same headers.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::on;
+use axum::routing::MethodFilter;
+use http::header::ACCEPT;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::request::Parts;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Map as JsonMap;
+use serde_json_bytes::Value;
+use tower::ServiceExt;
+
+use super::ClientHeaders;
+use crate::configuration::rest::RestMethod;
+use crate::configuration::rest::RestParam;
+use crate::configuration::rest::RestParamLocation;
+use crate::configuration::rest::RestParamType;
+use crate::configuration::rest::RestRoute;
+use crate::configuration::Configuration;
+use crate::graphql;
+use crate::http_server_factory::ConnectionInfo;
+use crate::router_factory::RouterFactory;
+
+/// The router serving the REST routes
+pub(super) fn router<RF>(service_factory: RF, configuration: &Configuration) -> axum::Router
+where
+    RF: RouterFactory,
+{
+    // the operations are sent to the GraphQL path, without its wildcard
+    let graphql_path: Arc<str> = configuration.supergraph.path.trim_end_matches('*').into();
+    configuration
+        .rest
+        .routes
+        .iter()
+        .fold(axum::Router::new(), |router, route| {
+            let service_factory = service_factory.clone();
+            let graphql_path = graphql_path.clone();
+            let path = route.server_path();
+            let route = Arc::new(Route::new(route));
+            router.route(
+                &path,
+                on(
+                    method_filter(route.method),
+                    move |path: Option<Path<HashMap<String, String>>>, request: Request<Body>| {
+                        let service = service_factory.create();
+                        let path_params = path.map(|Path(params)| params).unwrap_or_default();
+                        async move {
+                            match graphql_request(&route, &graphql_path, path_params, request) {
+                                Ok(request) => match service.oneshot(request.into()).await {
+                                    Ok(response) => response.response.into_response(),
+                                    Err(e) => {
+                                        tracing::error!("router service call failed: {}", e);
+                                        error_response(
+                                            StatusCode::INTERNAL_SERVER_ERROR,
+                                            "router service call failed",
+                                            "INTERNAL_SERVER_ERROR",
+                                        )
+                                    }
+                                },
+                                Err(message) => error_response(
+                                    StatusCode::BAD_REQUEST,
+                                    &message,
+                                    "INVALID_REST_PARAMETER",
+                                ),
+                            }
+                        }
+                    },
+                ),
+            )
+        })
+}
+
+/// A route, with the variables of its path template
+struct Route {
+    method: RestMethod,
+    operation: String,
+    operation_name: Option<String>,
+    params: HashMap<String, RestParam>,
+}
+
+impl Route {
+    fn new(route: &RestRoute) -> Self {
+        let mut params = route.params.clone();
+        for name in route.path_params() {
+            if !params.values().any(|param| {
+                param.location == RestParamLocation::Path && param.name.as_deref() == Some(name)
+            }) {
+                params.entry(name.to_string()).or_insert(RestParam {
+                    location: RestParamLocation::Path,
+                    name: None,
+                    kind: RestParamType::String,
+                });
+            }
+        }
+        Route {
+            method: route.method,
+            operation: route.operation.clone(),
+            operation_name: route.operation_name.clone(),
+            params,
+        }
+    }
+
+    /// The variables of the operation. Missing query string and header parameters are not set
+    fn variables(
+        &self,
+        path_params: &HashMap<String, String>,
+        parts: &Parts,
+    ) -> Result<JsonMap<ByteString, Value>, String> {
+        let query_params: HashMap<String, String> =
+            serde_urlencoded::from_str(parts.uri.query().unwrap_or_default())
+                .map_err(|e| format!("invalid query string: {e}"))?;
+        let mut variables = JsonMap::new();
+        for (variable, param) in &self.params {
+            let name = param.request_name(variable);
+            let value = match param.location {
+                RestParamLocation::Path => path_params.get(name).map(String::as_str),
+                RestParamLocation::Query => query_params.get(name).map(String::as_str),
+                RestParamLocation::Header => parts
+                    .headers
+                    .get(name)
+                    .map(|value| {
+                        value
+                            .to_str()
+                            .map_err(|_| format!("the header '{name}' is not a valid string"))
+                    })
+                    .transpose()?,
+            };
+            if let Some(value) = value {
+                variables.insert(
+                    ByteString::from(variable.as_str()),
+                    convert(name, value, param.kind)?,
+                );
+            }
+        }
+        Ok(variables)
+    }
+}
+
+fn convert(name: &str, value: &str, kind: RestParamType) -> Result<Value, String> {
+    let invalid = |kind: &str| format!("the parameter '{name}' must be {kind}");
+    Ok(match kind {
+        RestParamType::String => Value::String(value.into()),
+        RestParamType::Int => Value::from(value.parse::<i64>().map_err(|_| invalid("an integer"))?),
+        RestParamType::Float => Value::from(value.parse::<f64>().map_err(|_| invalid("a number"))?),
+        RestParamType::Boolean => Value::Bool(value.parse().map_err(|_| invalid("a boolean"))?),
+    })
+}
+
+fn method_filter(method: RestMethod) -> MethodFilter {
+    match method {
+        RestMethod::Get => MethodFilter::GET,
+        RestMethod::Post => MethodFilter::POST,
+        RestMethod::Put => MethodFilter::PUT,
+        RestMethod::Patch => MethodFilter::PATCH,
+        RestMethod::Delete => MethodFilter::DELETE,
+    }
+}
+
+/// Converts the REST request to a GraphQL request of the router pipeline, with the same headers
+fn graphql_request(
+    route: &Route,
+    graphql_path: &str,
+    path_params: HashMap<String, String>,
+    request: Request<Body>,
+) -> Result<Request<Body>, String> {
+    let (parts, _body) = request.into_parts();
+    let body = graphql::Request::builder()
+        .query(route.operation.clone())
+        .and_operation_name(route.operation_name.clone())
+        .variables(route.variables(&path_params, &parts)?)
+        .build();
+    let body = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+
+    let mut graphql_request = Request::builder()
+        .method(Method::POST)
+        .uri(graphql_path)
+        .body(Body::from(body))
+        .map_err(|e| e.to_string())?;
+    for (name, value) in parts.headers.iter() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH && name != ACCEPT {
+            graphql_request
+                .headers_mut()
+                .append(name.clone(), value.clone());
+        }
+    }
+    let json = HeaderValue::from_static(APPLICATION_JSON.essence_str());
+    graphql_request
+        .headers_mut()
+        .insert(CONTENT_TYPE, json.clone());
+    graphql_request.headers_mut().insert(ACCEPT, json);
+    if let Some(connection_info) = parts.extensions.get::<ConnectionInfo>() {
+        graphql_request.extensions_mut().insert(*connection_info);
+    }
+    // the CSRF checks apply to the content type and headers of the client request
+    graphql_request
+        .extensions_mut()
+        .insert(ClientHeaders(parts.headers));
+    Ok(graphql_request)
+}
+
+fn error_response(status: StatusCode, message: &str, code: &str) -> Response {
+    let error = graphql::Error::builder()
+        .message(message)
+        .extension_code(code)
+        .build();
+    (
+        status,
+        [(CONTENT_TYPE, APPLICATION_JSON.essence_str())],
+        serde_json::to_string(&graphql::Response::builder().error(error).build())
+            .unwrap_or_default(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::plugin::test::MockSupergraphService;
+    use crate::plugin::Plugin;
+    use crate::plugin::PluginInit;
+    use crate::plugins::csrf::CSRFConfig;
+    use crate::plugins::csrf::Csrf;
+    use crate::services::supergraph;
+
+    #[test]
+    fn it_reads_the_variables_from_the_request() {
+        let route: RestRoute = serde_yaml::from_str(
+            r#"
+            path: /stores/{store}/products
+            operation: "query Products($store: ID!, $first: Int, $locale: String) { products { id } }"
+            params:
+              first:
+                in: query
+                type: int
+              locale:
+                in: header
+                name: accept-language
+            "#,
+        )
+        .unwrap();
+        let route = Route::new(&route);
+        let path_params = HashMap::from([("store".to_string(), "paris".to_string())]);
+
+        let (parts, _) = Request::get("/stores/paris/products?first=10")
+            .header("accept-language", "fr")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            Value::Object(route.variables(&path_params, &parts).unwrap()),
+            json!({ "store": "paris", "first": 10, "locale": "fr" })
+        );
+
+        let (parts, _) = Request::get("/stores/paris/products?first=ten")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            route.variables(&path_params, &parts).unwrap_err(),
+            "the parameter 'first' must be an integer"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_cross_site_form_posts() {
+        let route: RestRoute = serde_yaml::from_str(
+            r#"
+            path: /carts/{cart}/clear
+            method: POST
+            operation: "mutation Clear($cart: ID!) { clearCart(id: $cart) }"
+            "#,
+        )
+        .unwrap();
+        let route = Route::new(&route);
+        let path_params = HashMap::from([("cart".to_string(), "1".to_string())]);
+        let request = Request::post("/carts/1/clear")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("a=b"))
+            .unwrap();
+
+        let (parts, body) = graphql_request(&route, "/", path_params, request)
+            .unwrap()
+            .into_parts();
+        assert_eq!(parts.headers[CONTENT_TYPE], APPLICATION_JSON.essence_str());
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        let request = http::Request::from_parts(parts, serde_json::from_slice(&body).unwrap());
+
+        let csrf = Csrf::new(PluginInit::new(CSRFConfig::default(), Default::default()))
+            .await
+            .unwrap();
+        let response = csrf
+            .supergraph_service(MockSupergraphService::new().boxed())
+            .oneshot(supergraph::Request::from(request))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -52,9 +52,13 @@ use tower::ServiceExt;
 pub(crate) use super::axum_http_server_factory::make_axum_router;
 use super::*;
 use crate::configuration::cors::Cors;
+use crate::configuration::cors::CorsPolicy;
 use crate::configuration::HealthCheck;
 use crate::configuration::Homepage;
+use crate::configuration::MaintenanceResponse;
+use crate::configuration::ProblemDetails;
 use crate::configuration::Sandbox;
+use crate::configuration::SecurityHeaders;
 use crate::configuration::Supergraph;
 use crate::graphql;
 use crate::http_server_factory::HttpServerFactory;
@@ -909,6 +913,146 @@ async fn response_failure() -> Result<(), ApolloRouterError> {
     server.shutdown().await
 }
 
+#[tokio::test]
+async fn problem_details_for_http_errors() -> Result<(), ApolloRouterError> {
+    let conf = Configuration::fake_builder()
+        .server(
+            crate::configuration::Server::builder()
+                .problem_details(
+                    serde_json::from_value::<ProblemDetails>(json!({ "enabled": true })).unwrap(),
+                )
+                .build(),
+        )
+        .build()
+        .unwrap();
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await?;
+
+    let response = client
+        .post(format!(
+            "{}/",
+            server.graphql_listen_address().as_ref().unwrap()
+        ))
+        .header(CONTENT_ENCODING, "unknown")
+        .body(json!({ "query": "query" }).to_string())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({
+            "type": "about:blank",
+            "title": "Bad Request",
+            "status": 400,
+            "detail": "unknown content-encoding header value \"unknown\""
+        })
+    );
+    server.shutdown().await
+}
+
+#[tokio::test]
+async fn problem_details_for_router_errors() -> Result<(), ApolloRouterError> {
+    let conf = Configuration::fake_builder()
+        .server(
+            crate::configuration::Server::builder()
+                .problem_details(
+                    serde_json::from_value::<ProblemDetails>(json!({ "enabled": true })).unwrap(),
+                )
+                .build(),
+        )
+        .build()
+        .unwrap();
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await?;
+
+    let response = client
+        .post(format!(
+            "{}/",
+            server.graphql_listen_address().as_ref().unwrap()
+        ))
+        .header(CONTENT_TYPE, "text/plain")
+        .body(json!({ "query": "query" }).to_string())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({
+            "type": "about:blank",
+            "title": "Unsupported Media Type",
+            "status": 415,
+            "detail": "'content-type' header can't be different from \"application/json\" or \"application/graphql-response+json\"",
+            "code": "INVALID_CONTENT_TYPE_HEADER"
+        })
+    );
+    server.shutdown().await
+}
+
+#[tokio::test]
+async fn security_headers_on_all_responses() -> Result<(), ApolloRouterError> {
+    let conf = Configuration::fake_builder()
+        .server(
+            crate::configuration::Server::builder()
+                .security_headers(
+                    serde_json::from_value::<SecurityHeaders>(json!({
+                        "strict_transport_security": "max-age=31536000",
+                        "content_type_options_nosniff": true,
+                        "headers": { "x-frame-options": "DENY" }
+                    }))
+                    .unwrap(),
+                )
+                .build(),
+        )
+        .build()
+        .unwrap();
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await?;
+
+    // an error response, before the router service
+    let response = client
+        .post(format!(
+            "{}/",
+            server.graphql_listen_address().as_ref().unwrap()
+        ))
+        .header(CONTENT_ENCODING, "unknown")
+        .body(json!({ "query": "query" }).to_string())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let headers = response.headers();
+    assert_eq!(headers["strict-transport-security"], "max-age=31536000");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert!(headers.get("referrer-policy").is_none());
+    server.shutdown().await
+}
+
 #[tokio::test]
 async fn cors_preflight() -> Result<(), ApolloRouterError> {
     let conf = Configuration::fake_builder()
@@ -1015,7 +1159,7 @@ async fn it_send_bad_content_type() -> Result<(), ApolloRouterError> {
     );
     assert_eq!(
         response.text().await.unwrap(),
-        r#"{"message":"'content-type' header can't be different from \"application/json\" or \"application/graphql-response+json\"","extensions":{"code":"INVALID_ACCEPT_HEADER"}}"#
+        r#"{"message":"'content-type' header can't be different from \"application/json\" or \"application/graphql-response+json\"","extensions":{"code":"INVALID_CONTENT_TYPE_HEADER"}}"#
     );
 
     server.shutdown().await
@@ -1402,6 +1546,61 @@ async fn cors_origin_regex() -> Result<(), ApolloRouterError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn cors_origin_policies() -> Result<(), ApolloRouterError> {
+    let conf = Configuration::fake_builder()
+        .cors(
+            Cors::builder()
+                .origins(vec!["https://studio.apollographql.com".to_string()])
+                .policies(vec![CorsPolicy {
+                    origins: vec!["https://intranet.example.com".to_string()],
+                    match_origins: vec!["https://[a-z]+[.]example[.]com".to_string()],
+                    allow_credentials: Some(true),
+                    max_age: Some(std::time::Duration::from_secs(600)),
+                    allow_private_network: Some(true),
+                    ..Default::default()
+                }])
+                .build(),
+        )
+        .build()
+        .unwrap();
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await?;
+    let url = format!("{}/", server.graphql_listen_address().as_ref().unwrap());
+
+    let response = client
+        .request(Method::OPTIONS, url.as_str())
+        .header("Origin", "https://www.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .header("Access-Control-Request-Private-Network", "true")
+        .send()
+        .await
+        .unwrap();
+    let headers = response.headers();
+    assert_eq!(headers["access-control-allow-credentials"], "true");
+    assert_eq!(headers["access-control-max-age"], "600");
+    assert_eq!(headers["access-control-allow-private-network"], "true");
+    assert_cors_origin(response, "https://www.example.com");
+
+    // origins matching no policy get the top level settings
+    let response =
+        request_cors_with_origin(&client, url.as_str(), "https://studio.apollographql.com").await;
+    let headers = response.headers();
+    assert!(headers.get("access-control-allow-credentials").is_none());
+    assert!(headers.get("access-control-max-age").is_none());
+    assert_cors_origin(response, "https://studio.apollographql.com");
+
+    let response = request_cors_with_origin(&client, url.as_str(), "https://www.example.org").await;
+    assert_not_cors_origin(response, "https://www.example.org");
+
+    Ok(())
+}
+
 async fn request_cors_with_origin(client: &Client, url: &str, origin: &str) -> reqwest::Response {
     client
         .request(Method::OPTIONS, url)
@@ -2063,6 +2262,153 @@ async fn test_health_check_custom_listener() {
     )
 }
 
+#[tokio::test]
+async fn test_admin_api_maintenance() {
+    let conf = Configuration::fake_builder()
+        .admin(
+            crate::configuration::Admin::fake_builder()
+                .enabled(true)
+                .token("secret".to_string())
+                .build(),
+        )
+        .build()
+        .unwrap();
+
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await
+    .unwrap();
+    let address = server
+        .graphql_listen_address()
+        .as_ref()
+        .unwrap()
+        .to_string();
+
+    let response = client
+        .get(format!("{address}/admin/status"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post(format!("{address}/admin/maintenance"))
+        .bearer_auth("secret")
+        .json(&json!({ "enabled": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(format!("{address}/admin/status"))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({
+            "schema_id": null,
+            "plugins": [],
+            "maintenance": true,
+            "log_filter": crate::log_filter::current(),
+        })
+    );
+
+    let response = client
+        .post(format!("{address}/"))
+        .json(&json!({ "query": "{ me }" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let response = client
+        .get(format!("{address}/health"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        json!({"status": "DOWN" }),
+        response.json::<serde_json::Value>().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_admin_api_maintenance_response() {
+    let conf = Configuration::fake_builder()
+        .admin(
+            crate::configuration::Admin::fake_builder()
+                .enabled(true)
+                .token("secret".to_string())
+                .maintenance(
+                    serde_json::from_value::<MaintenanceResponse>(json!({
+                        "status_code": 502,
+                        "message": "draining eu-west-1",
+                        "extensions": { "region": "eu-west-1" },
+                        "retry_after": "2m",
+                    }))
+                    .unwrap(),
+                )
+                .build(),
+        )
+        .build()
+        .unwrap();
+
+    let (server, client) = init_with_config(
+        router_service::empty().await,
+        Arc::new(conf),
+        MultiMap::new(),
+    )
+    .await
+    .unwrap();
+    let address = server
+        .graphql_listen_address()
+        .as_ref()
+        .unwrap()
+        .to_string();
+
+    client
+        .post(format!("{address}/admin/maintenance"))
+        .bearer_auth("secret")
+        .json(&json!({ "enabled": true }))
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .post(format!("{address}/"))
+        .json(&json!({ "query": "{ me }" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.headers()["retry-after"], "120");
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        json!({
+            "errors": [{
+                "message": "draining eu-west-1",
+                "extensions": { "code": "MAINTENANCE", "region": "eu-west-1" },
+            }]
+        })
+    );
+
+    // the admin API keeps serving
+    let response = client
+        .post(format!("{address}/admin/maintenance"))
+        .bearer_auth("secret")
+        .json(&json!({ "enabled": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
 #[tokio::test]
 async fn test_sneaky_supergraph_and_health_check_configuration() {
     let conf = Configuration::fake_builder()
@@ -3,21 +3,31 @@
 use async_compression::tokio::write::BrotliDecoder;
 use async_compression::tokio::write::GzipDecoder;
 use async_compression::tokio::write::ZlibDecoder;
+use axum::body::boxed;
 use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::*;
 use futures::prelude::*;
 use http::header::CONTENT_ENCODING;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use http::HeaderValue;
 use http::Request;
 use hyper::Body;
 use opentelemetry::global;
 use opentelemetry::trace::TraceContextExt;
+use serde::Serialize;
 use tokio::io::AsyncWriteExt;
+use tower::util::MapResponseLayer;
 use tower_http::trace::MakeSpan;
 use tracing::Level;
 use tracing::Span;
 
+use crate::error::RouterError;
+
 pub(crate) const REQUEST_SPAN_NAME: &str = "request";
+pub(crate) const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
 
 pub(super) async fn decompress_request_body(
     req: Request<Body>,
@@ -92,6 +102,92 @@ pub(super) async fn decompress_request_body(
     }
 }
 
+/// Adds the security headers to the responses that don't already have them
+pub(super) fn security_headers(
+    headers: HeaderMap,
+) -> MapResponseLayer<impl Fn(Response) -> Response + Clone> {
+    MapResponseLayer::new(move |mut response: Response| {
+        for (name, value) in &headers {
+            if !response.headers().contains_key(name) {
+                response.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+        response
+    })
+}
+
+/// Problem details (RFC 9457) body
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    /// Extension code of the router error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+}
+
+/// Replaces the body of HTTP errors that are not GraphQL responses with problem details: the
+/// errors generated by the router, such as rejected content types or rate limited requests, and
+/// the plain text errors, such as rejected encodings
+pub(super) async fn problem_details(req: Request<Body>, next: Next<Body>) -> Response {
+    let response = next.run(req).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let (detail, code) = match parts.extensions.remove::<RouterError>() {
+        Some(RouterError(error)) => {
+            let code = error
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .map(str::to_string);
+            (Some(error.message), code)
+        }
+        None => {
+            // GraphQL responses and existing problem details are JSON
+            let structured = parts
+                .headers
+                .get(&CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .map_or(false, |content_type| {
+                    content_type.contains("json") || content_type.starts_with("multipart/")
+                });
+            if structured {
+                return Response::from_parts(parts, body);
+            }
+            let detail = hyper::body::to_bytes(body)
+                .await
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+                .filter(|detail| !detail.is_empty());
+            (detail, None)
+        }
+    };
+    let problem = Problem {
+        problem_type: "about:blank",
+        title: status.canonical_reason().unwrap_or("Unknown Error"),
+        status: status.as_u16(),
+        detail,
+        code,
+    };
+    parts.headers.remove(&CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(PROBLEM_JSON_CONTENT_TYPE),
+    );
+    match serde_json::to_vec(&problem) {
+        Ok(body) => Response::from_parts(parts, boxed(Body::from(body))),
+        Err(_) => Response::from_parts(parts, boxed(Body::empty())),
+    }
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct PropagatingMakeSpan;
 
@@ -0,0 +1,528 @@
+//! Queries and mutations over WebSocket connections
+//!
+//! Implements the server side of the `graphql-transport-ws` protocol of graphql-ws, on the
+//! GraphQL path. Each `subscribe` message is sent through the router pipeline like an HTTP
+//! request, with the headers of the upgrade request, and its responses, several with `@defer`,
+//! are sent back as `next` messages. Subscription operations are rejected by the pipeline like
+//! over HTTP.
+//!
+//! Browsers do not apply the CORS policy to WebSocket connections, so the router rejects the
+//! upgrade requests whose origin it does not allow. The CSRF checks apply to the headers of the
+//! upgrade request rather than to the ones of the operations, which are always JSON.
+//!
+//! The operations share the connection entries of their context. The payload of the
+//! `connection_init` message is set there, so that plugins authenticate the connection once.
+//!
+//! The open connections are counted by the `apollo_router_websocket_connections` metric, and the
+//! connections closed with an error code by `apollo_router_websocket_abnormal_closures_total`.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::extract::ws::CloseFrame;
+use axum::extract::ws::Message;
+use axum::extract::ws::WebSocket;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::FromRequestParts;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use futures::SinkExt;
+use futures::StreamExt;
+use http::header::ACCEPT;
+use http::header::CONNECTION;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::header::ORIGIN;
+use http::header::SEC_WEBSOCKET_PROTOCOL;
+use http::header::UPGRADE;
+use http::request::Parts;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+use super::ClientHeaders;
+use crate::configuration::cors::CorsLayer;
+use crate::configuration::WebSocket as WebSocketConf;
+use crate::graphql;
+use crate::http_server_factory::ConnectionInfo;
+use crate::router_factory::RouterFactory;
+use crate::services::router;
+use crate::Context;
+
+const PROTOCOL: &str = "graphql-transport-ws";
+/// Media types accepted by the operations, to stream the deferred responses
+const ACCEPT_DEFER: &str = "application/json, multipart/mixed;deferSpec=20220824";
+const DEFAULT_CONNECTION_INIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of messages waiting to be written on the socket before the operations wait
+const OUTGOING_BUFFER: usize = 64;
+
+// close codes of the WebSocket RFC that are not abnormal
+const NORMAL_CLOSURE: u16 = 1000;
+const GOING_AWAY: u16 = 1001;
+/// Close code of the connections closed without a close frame
+const ABNORMAL_CLOSURE: u16 = 1006;
+
+// close codes of the protocol
+const BAD_REQUEST: u16 = 4400;
+const UNAUTHORIZED: u16 = 4401;
+const CONNECTION_INIT_TIMEOUT: u16 = 4408;
+const SUBSCRIBER_ALREADY_EXISTS: u16 = 4409;
+const TOO_MANY_INITIALISATION_REQUESTS: u16 = 4429;
+
+/// Connection context key of the payload of the `connection_init` message
+pub(crate) const CONNECTION_INIT_PAYLOAD_CONTEXT_KEY: &str =
+    "apollo_websocket::connection_init_payload";
+
+/// Messages sent by the client. Their optional payloads are ignored, except for
+/// `connection_init` and `subscribe`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ConnectionInit {
+        #[serde(default)]
+        payload: Option<serde_json::Value>,
+    },
+    Ping {},
+    Pong {},
+    Subscribe {
+        id: String,
+        payload: graphql::Request,
+    },
+    Complete {
+        id: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    ConnectionAck,
+    Pong,
+    Next {
+        id: String,
+        payload: graphql::Response,
+    },
+    Error {
+        id: String,
+        payload: Vec<graphql::Error>,
+    },
+    Complete {
+        id: String,
+    },
+}
+
+impl ServerMessage {
+    fn into_message(self) -> Message {
+        Message::Text(serde_json::to_string(&self).expect("server messages serialize"))
+    }
+}
+
+/// Whether the request opens a WebSocket connection
+pub(super) fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Upgrades the connection, if the client speaks the `graphql-transport-ws` protocol and its
+/// origin is allowed by the CORS policy
+pub(super) async fn upgrade<RF>(
+    service_factory: RF,
+    configuration: &WebSocketConf,
+    cors: &CorsLayer,
+    request: Request<Body>,
+) -> Response
+where
+    RF: RouterFactory,
+{
+    let (mut parts, _body) = request.into_parts();
+    // clients that are not browsers do not send an origin
+    if let Some(origin) = parts.headers.get(ORIGIN) {
+        let allowed = origin
+            .to_str()
+            .map_or(false, |origin| cors.allows_origin(origin));
+        if !allowed {
+            return (
+                StatusCode::FORBIDDEN,
+                "the origin of the WebSocket connection is not allowed",
+            )
+                .into_response();
+        }
+    }
+    let offers_protocol = parts
+        .headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == PROTOCOL);
+    if !offers_protocol {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("the WebSocket connections use the {PROTOCOL} protocol"),
+        )
+            .into_response();
+    }
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let connection = Connection {
+        service_factory,
+        parts: Arc::new(parts),
+        context: Context::new(),
+        connection_init_timeout: configuration
+            .connection_init_timeout
+            .unwrap_or(DEFAULT_CONNECTION_INIT_TIMEOUT),
+    };
+    upgrade
+        .protocols([PROTOCOL])
+        .on_upgrade(move |socket| connection.serve(socket))
+}
+
+struct Connection<RF> {
+    service_factory: RF,
+    /// The upgrade request, whose URI and headers are used by the operations
+    parts: Arc<Parts>,
+    /// The context whose connection entries are shared by the operations
+    context: Context,
+    connection_init_timeout: Duration,
+}
+
+impl<RF> Connection<RF>
+where
+    RF: RouterFactory,
+{
+    async fn serve(self, socket: WebSocket) {
+        tracing::info!(counter.apollo_router_websocket_connections = 1i64);
+        let (mut sink, mut stream) = socket.split();
+        let (sender, mut receiver) = mpsc::channel::<Message>(OUTGOING_BUFFER);
+        let writer = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let close = matches!(message, Message::Close(_));
+                if sink.send(message).await.is_err() || close {
+                    break;
+                }
+            }
+        });
+
+        let operations: Operations = Default::default();
+        let mut acknowledged = false;
+        let init_timeout = tokio::time::sleep(self.connection_init_timeout);
+        tokio::pin!(init_timeout);
+
+        loop {
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = &mut init_timeout, if !acknowledged => {
+                    close(&sender, CONNECTION_INIT_TIMEOUT, "Connection initialisation timeout").await;
+                    break;
+                }
+            };
+            let text = match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Binary(bytes))) => match String::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(_) => {
+                        close(&sender, BAD_REQUEST, "Invalid message").await;
+                        break;
+                    }
+                },
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(frame))) => {
+                    let code = frame.map_or(NORMAL_CLOSURE, |frame| frame.code);
+                    if code != NORMAL_CLOSURE && code != GOING_AWAY {
+                        count_abnormal_closure(code);
+                    }
+                    break;
+                }
+                Some(Err(_)) | None => {
+                    count_abnormal_closure(ABNORMAL_CLOSURE);
+                    break;
+                }
+            };
+            let message = match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    close(&sender, BAD_REQUEST, &format!("Invalid message: {e}")).await;
+                    break;
+                }
+            };
+            match message {
+                ClientMessage::ConnectionInit { payload } => {
+                    if acknowledged {
+                        close(
+                            &sender,
+                            TOO_MANY_INITIALISATION_REQUESTS,
+                            "Too many initialisation requests",
+                        )
+                        .await;
+                        break;
+                    }
+                    acknowledged = true;
+                    if let Some(payload) = payload {
+                        if let Err(e) = self
+                            .context
+                            .connection()
+                            .insert(CONNECTION_INIT_PAYLOAD_CONTEXT_KEY, payload)
+                        {
+                            tracing::error!("could not keep the connection_init payload: {}", e);
+                        }
+                    }
+                    let _ = sender
+                        .send(ServerMessage::ConnectionAck.into_message())
+                        .await;
+                }
+                ClientMessage::Ping {} => {
+                    let _ = sender.send(ServerMessage::Pong.into_message()).await;
+                }
+                ClientMessage::Pong {} => {}
+                ClientMessage::Subscribe { id, payload } => {
+                    if !acknowledged {
+                        close(&sender, UNAUTHORIZED, "Unauthorized").await;
+                        break;
+                    }
+                    let already_exists = {
+                        // the operation waits for its insertion before removing itself
+                        let mut operations_guard = operations.lock().expect("lock poisoned");
+                        let already_exists = operations_guard.contains_key(&id);
+                        if !already_exists {
+                            let task = tokio::spawn(execute(
+                                self.service_factory.clone(),
+                                self.parts.clone(),
+                                Context::for_connection(&self.context),
+                                id.clone(),
+                                payload,
+                                sender.clone(),
+                                operations.clone(),
+                            ));
+                            operations_guard.insert(id.clone(), task);
+                        }
+                        already_exists
+                    };
+                    if already_exists {
+                        close(
+                            &sender,
+                            SUBSCRIBER_ALREADY_EXISTS,
+                            &format!("Subscriber for {id} already exists"),
+                        )
+                        .await;
+                        break;
+                    }
+                }
+                ClientMessage::Complete { id } => {
+                    if let Some(task) = operations.lock().expect("lock poisoned").remove(&id) {
+                        task.abort();
+                    }
+                }
+            }
+        }
+
+        // the operations of a closed connection are cancelled
+        for (_, task) in operations.lock().expect("lock poisoned").drain() {
+            task.abort();
+        }
+        drop(sender);
+        let _ = writer.await;
+        tracing::info!(counter.apollo_router_websocket_connections = -1i64);
+    }
+}
+
+/// The operations in flight on a connection, by id
+type Operations = Arc<Mutex<HashMap<String, JoinHandle<()>>>>;
+
+/// Executes an operation, and sends its responses followed by a `complete` message
+async fn execute<RF>(
+    service_factory: RF,
+    parts: Arc<Parts>,
+    context: Context,
+    id: String,
+    operation: graphql::Request,
+    sender: mpsc::Sender<Message>,
+    operations: Operations,
+) where
+    RF: RouterFactory,
+{
+    let message = match service_factory
+        .create()
+        .oneshot(router::Request {
+            router_request: http_request(&parts, &operation),
+            context,
+        })
+        .await
+    {
+        Ok(response) => {
+            let status = response.response.status();
+            let mut responses = response.into_graphql_response_stream().await;
+            let mut first = true;
+            let mut message = ServerMessage::Complete { id: id.clone() };
+            while let Some(response) = responses.next().await {
+                let response = match response {
+                    Ok(response) => response,
+                    Err(e) => {
+                        message = error_message(&id, format!("invalid GraphQL response: {e}"));
+                        break;
+                    }
+                };
+                // requests rejected before their execution have errors and no data
+                if first && !status.is_success() && response.data.is_none() {
+                    message = ServerMessage::Error {
+                        id: id.clone(),
+                        payload: response.errors,
+                    };
+                    break;
+                }
+                first = false;
+                let next = ServerMessage::Next {
+                    id: id.clone(),
+                    payload: response,
+                };
+                if sender.send(next.into_message()).await.is_err() {
+                    return;
+                }
+            }
+            message
+        }
+        Err(e) => {
+            tracing::error!("router service call failed: {}", e);
+            error_message(&id, "router service call failed".to_string())
+        }
+    };
+    // the client can reuse the id once the operation is complete
+    operations.lock().expect("lock poisoned").remove(&id);
+    let _ = sender.send(message.into_message()).await;
+}
+
+/// Converts the operation to a request of the router pipeline, with the headers of the upgrade
+/// request
+fn http_request(parts: &Parts, operation: &graphql::Request) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(parts.uri.clone())
+        .body(Body::from(
+            serde_json::to_vec(operation).expect("GraphQL requests serialize"),
+        ))
+        .expect("the URI of the upgrade request is valid");
+    for (name, value) in parts.headers.iter() {
+        let name_str = name.as_str();
+        if name_str.starts_with("sec-websocket-")
+            || name == UPGRADE
+            || name == CONNECTION
+            || name == CONTENT_TYPE
+            || name == CONTENT_LENGTH
+            || name == ACCEPT
+        {
+            continue;
+        }
+        request.headers_mut().append(name.clone(), value.clone());
+    }
+    request.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+    );
+    request
+        .headers_mut()
+        .insert(ACCEPT, HeaderValue::from_static(ACCEPT_DEFER));
+    if let Some(connection_info) = parts.extensions.get::<ConnectionInfo>() {
+        request.extensions_mut().insert(*connection_info);
+    }
+    request
+        .extensions_mut()
+        .insert(ClientHeaders(parts.headers.clone()));
+    request
+}
+
+fn error_message(id: &str, message: String) -> ServerMessage {
+    ServerMessage::Error {
+        id: id.to_string(),
+        payload: vec![graphql::Error::builder()
+            .message(message)
+            .extension_code("INTERNAL_SERVER_ERROR")
+            .build()],
+    }
+}
+
+/// Closes the connection with an error code of the protocol
+async fn close(sender: &mpsc::Sender<Message>, code: u16, reason: &str) {
+    count_abnormal_closure(code);
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: Cow::Owned(reason.to_string()),
+        })))
+        .await;
+}
+
+fn count_abnormal_closure(code: u16) {
+    tracing::info!(
+        monotonic_counter.apollo_router_websocket_abnormal_closures_total = 1u64,
+        code = %code,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_reads_client_messages() {
+        let message: ClientMessage = serde_json::from_value(json!({
+            "type": "subscribe",
+            "id": "1",
+            "payload": { "query": "{ me { id } }", "variables": { "a": 1 } }
+        }))
+        .unwrap();
+        match message {
+            ClientMessage::Subscribe { id, payload } => {
+                assert_eq!(id, "1");
+                assert_eq!(payload.query.as_deref(), Some("{ me { id } }"));
+            }
+            message => panic!("unexpected message {message:?}"),
+        }
+        match serde_json::from_value(
+            json!({ "type": "connection_init", "payload": { "token": "a" } }),
+        )
+        .unwrap()
+        {
+            ClientMessage::ConnectionInit { payload } => {
+                assert_eq!(payload, Some(json!({ "token": "a" })));
+            }
+            message => panic!("unexpected message {message:?}"),
+        }
+        assert!(matches!(
+            serde_json::from_value(json!({ "type": "connection_init" })).unwrap(),
+            ClientMessage::ConnectionInit { payload: None }
+        ));
+        assert!(serde_json::from_value::<ClientMessage>(json!({ "type": "next" })).is_err());
+    }
+
+    #[test]
+    fn it_writes_server_messages() {
+        assert_eq!(
+            serde_json::to_value(ServerMessage::ConnectionAck).unwrap(),
+            json!({ "type": "connection_ack" })
+        );
+        assert_eq!(
+            serde_json::to_value(ServerMessage::Complete {
+                id: "1".to_string()
+            })
+            .unwrap(),
+            json!({ "type": "complete", "id": "1" })
+        );
+    }
+}
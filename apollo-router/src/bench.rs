@@ -0,0 +1,305 @@
+//! Load testing of the in-process pipeline
+//!
+//! Replays a corpus of operations at a target rate against a supergraph service
+//! built with [`TestHarness`], and reports latency percentiles for the whole request,
+//! query planning and execution.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use http::header::CONTENT_TYPE;
+use http::HeaderValue;
+use http::Method;
+use mime::APPLICATION_JSON;
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::services::execution;
+use crate::services::supergraph;
+use crate::Configuration;
+use crate::SubgraphFixturesMode;
+use crate::TestHarness;
+
+const EXECUTION_TIME_CONTEXT_KEY: &str = "apollo_router::bench::execution_time_ns";
+
+/// Replays operations against the router pipeline at a target rate.
+///
+/// Subgraphs are never called over the network: they are served from fixture files
+/// recorded with [`TestHarness::subgraph_fixtures`] if a directory is given,
+/// and otherwise respond with empty data.
+pub struct Bench {
+    schema: String,
+    configuration: Option<Arc<Configuration>>,
+    operations: Vec<graphql::Request>,
+    subgraph_fixtures: Option<PathBuf>,
+    requests_per_second: u32,
+    duration: Duration,
+}
+
+#[buildstructor::buildstructor]
+impl Bench {
+    /// Creates a benchmark of `operations`, sent in turn at `requests_per_second` for `duration`.
+    #[builder(visibility = "pub")]
+    fn new(
+        schema: String,
+        configuration: Option<Arc<Configuration>>,
+        operations: Vec<graphql::Request>,
+        subgraph_fixtures: Option<PathBuf>,
+        requests_per_second: u32,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            schema,
+            configuration,
+            operations,
+            subgraph_fixtures,
+            requests_per_second,
+            duration,
+        }
+    }
+
+    /// Runs the benchmark and waits for all the requests it sent.
+    pub async fn run(self) -> Result<BenchReport, BoxError> {
+        if self.operations.is_empty() {
+            return Err("the operation corpus is empty".into());
+        }
+        if self.requests_per_second == 0 {
+            return Err("the target rate must be at least one request per second".into());
+        }
+
+        let mut harness = TestHarness::builder()
+            .schema(&self.schema)
+            .execution_hook(record_execution_time);
+        if let Some(configuration) = self.configuration {
+            harness = harness.configuration(configuration);
+        }
+        if let Some(dir) = self.subgraph_fixtures {
+            harness = harness.subgraph_fixtures(dir, SubgraphFixturesMode::Replay);
+        }
+        let service = harness.build_supergraph().await?;
+
+        let mut ticker = tokio::time::interval(Duration::from_secs_f64(
+            1.0 / self.requests_per_second as f64,
+        ));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let start = Instant::now();
+        let mut tasks = Vec::new();
+        for operation in self.operations.iter().cycle() {
+            ticker.tick().await;
+            if start.elapsed() >= self.duration {
+                break;
+            }
+            let service = service.clone();
+            let request = supergraph_request(operation.clone())?;
+            tasks.push(tokio::spawn(async move {
+                let request_start = Instant::now();
+                let response = service.oneshot(request).await;
+                (request_start.elapsed(), response)
+            }));
+        }
+
+        let mut samples = Samples::default();
+        for task in tasks {
+            let (total, response) = task.await?;
+            match response {
+                Ok(mut response) => {
+                    let execution = response
+                        .context
+                        .get::<_, u64>(EXECUTION_TIME_CONTEXT_KEY)
+                        .ok()
+                        .flatten()
+                        .map(Duration::from_nanos);
+                    let has_errors = response
+                        .next_response()
+                        .await
+                        .map_or(true, |response| !response.errors.is_empty());
+                    samples.push(total, execution, has_errors);
+                }
+                Err(_) => samples.push(total, None, true),
+            }
+        }
+
+        Ok(samples.into_report(start.elapsed()))
+    }
+}
+
+fn record_execution_time(service: execution::BoxService) -> execution::BoxService {
+    ServiceBuilder::new()
+        .map_future_with_request_data(
+            |request: &execution::Request| request.context.clone(),
+            |context: crate::Context, future| async move {
+                let start = Instant::now();
+                let response = future.await;
+                let _ = context.insert(
+                    EXECUTION_TIME_CONTEXT_KEY,
+                    start.elapsed().as_nanos() as u64,
+                );
+                response
+            },
+        )
+        .service(service)
+        .boxed()
+}
+
+fn supergraph_request(operation: graphql::Request) -> Result<supergraph::Request, BoxError> {
+    Ok(http::Request::builder()
+        .method(Method::POST)
+        .uri("http://localhost/")
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+        )
+        .body(operation)?
+        .into())
+}
+
+#[derive(Default)]
+struct Samples {
+    total: Vec<Duration>,
+    planning: Vec<Duration>,
+    execution: Vec<Duration>,
+    errors: usize,
+}
+
+impl Samples {
+    fn push(&mut self, total: Duration, execution: Option<Duration>, has_errors: bool) {
+        self.total.push(total);
+        // Requests that fail before execution, for example on validation errors,
+        // only count in the total latency.
+        if let Some(execution) = execution {
+            self.planning.push(total.saturating_sub(execution));
+            self.execution.push(execution);
+        }
+        if has_errors {
+            self.errors += 1;
+        }
+    }
+
+    fn into_report(self, elapsed: Duration) -> BenchReport {
+        BenchReport {
+            requests: self.total.len(),
+            errors: self.errors,
+            requests_per_second: self.total.len() as f64 / elapsed.as_secs_f64(),
+            total: LatencySummary::new(self.total),
+            planning: LatencySummary::new(self.planning),
+            execution: LatencySummary::new(self.execution),
+        }
+    }
+}
+
+/// Results of a [`Bench`] run
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct BenchReport {
+    /// Number of requests sent
+    pub requests: usize,
+    /// Number of requests that failed or whose response had GraphQL errors
+    pub errors: usize,
+    /// Achieved rate, which is lower than the target if the pipeline could not keep up
+    pub requests_per_second: f64,
+    /// Latency until the primary response
+    pub total: LatencySummary,
+    /// Time spent before execution: parsing, validation and query planning
+    pub planning: LatencySummary,
+    /// Time spent executing the query plan, including subgraph fetches
+    pub execution: LatencySummary,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests, {} with errors, {:.1} requests/s",
+            self.requests, self.errors, self.requests_per_second
+        )?;
+        writeln!(f, "total:     {}", self.total)?;
+        writeln!(f, "planning:  {}", self.planning)?;
+        write!(f, "execution: {}", self.execution)
+    }
+}
+
+/// Latency percentiles over the requests of a [`Bench`] run
+#[derive(Clone, Debug, Default, Serialize)]
+#[non_exhaustive]
+pub struct LatencySummary {
+    /// Median latency
+    #[serde(with = "humantime_serde")]
+    pub p50: Duration,
+    /// 90th percentile
+    #[serde(with = "humantime_serde")]
+    pub p90: Duration,
+    /// 99th percentile
+    #[serde(with = "humantime_serde")]
+    pub p99: Duration,
+    /// Maximum latency
+    #[serde(with = "humantime_serde")]
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+        Self {
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_percentiles() {
+        let samples = (1..=100).map(Duration::from_millis).collect();
+        let summary = LatencySummary::new(samples);
+        assert_eq!(summary.p50, Duration::from_millis(51));
+        assert_eq!(summary.p90, Duration::from_millis(90));
+        assert_eq!(summary.p99, Duration::from_millis(99));
+        assert_eq!(summary.max, Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn it_replays_operations() {
+        let operation = graphql::Request::builder().query("{ me }").build();
+        let report = Bench::builder()
+            .schema(include_str!("testdata/minimal_supergraph.graphql"))
+            .operation(operation)
+            .requests_per_second(100)
+            .duration(Duration::from_millis(100))
+            .build()
+            .run()
+            .await
+            .unwrap();
+
+        assert!(report.requests > 0);
+        assert_eq!(report.errors, 0);
+        assert!(report.execution.max > Duration::ZERO);
+    }
+}
@@ -131,6 +131,10 @@ where
     pub(crate) async fn in_memory_keys(&self) -> Vec<K> {
         self.storage.in_memory_keys().await
     }
+
+    pub(crate) async fn clear(&self) {
+        self.storage.clear().await
+    }
 }
 
 pub(crate) struct Entry<K: KeyType, V: ValueType> {
@@ -195,6 +195,11 @@ where
             .collect()
     }
 
+    /// Removes all the entries of the in-memory cache
+    pub(crate) async fn clear(&self) {
+        self.inner.lock().await.clear();
+    }
+
     #[cfg(test)]
     pub(crate) async fn len(&self) -> usize {
         self.inner.lock().await.len()
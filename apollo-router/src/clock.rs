@@ -0,0 +1,88 @@
+//! Source of the current time for time-dependent behavior
+//!
+//! Rate limiting windows and JWT expiry checks read the time through a [`Clock`]
+//! instead of calling [`SystemTime::now`] directly, so that tests can replace it
+//! with a [`ManualClock`] and advance it without sleeping.
+//!
+//! Timeouts are driven by tokio timers and do not use the clock. Cache entries
+//! expire on the Redis server, so their TTL does not use it either.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Provides the current time
+pub(crate) trait Clock: Debug + Send + Sync + 'static {
+    /// Returns the current time
+    fn now(&self) -> SystemTime;
+}
+
+/// Clock reading the system time
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl SystemClock {
+    pub(crate) fn shared() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+}
+
+/// Clock that only moves forward when it is advanced. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub(crate) struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// Creates a clock starting at the current system time
+    pub(crate) fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// Creates a clock starting at the given time
+    pub(crate) fn starting_at(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock forward
+    pub(crate) fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("lock poisoned") += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+
+        assert_eq!(shared.now(), start);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(shared.now(), start + Duration::from_secs(60));
+    }
+}
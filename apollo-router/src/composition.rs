@@ -0,0 +1,1259 @@
+//! Composition of the supergraph schema from subgraph schemas, for local development
+//!
+//! This is a simplified Federation 1 composition, without the validations of Rover: subgraphs
+//! are expected to compose successfully with it. Entities are declared with `@key`, extended in
+//! other subgraphs with `extend type` and `@external` fields, and their fields can use
+//! `@requires` and `@provides`. Other types are merged across subgraphs. Directives other than
+//! the federation directives are kept, with their definitions.
+//!
+//! Federation 2 subgraphs can be mixed with Federation 1 subgraphs, to migrate them one at a time:
+//! their federation directives are found with the imports of their `@link` to the federation
+//! spec, and they can define entities with `type`, reference them with `resolvable: false` keys
+//! and move fields with `@override`. Value types do not need `@shareable`.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use apollo_parser::ast;
+use apollo_parser::ast::AstNode;
+use displaydoc::Display;
+use futures::prelude::*;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use serde::Deserialize;
+use thiserror::Error;
+
+const JOIN_DEFINITIONS: &str = r#"directive @core(feature: String!) repeatable on SCHEMA
+
+directive @join__field(graph: join__Graph, requires: join__FieldSet, provides: join__FieldSet) on FIELD_DEFINITION
+
+directive @join__type(graph: join__Graph!, key: join__FieldSet) repeatable on OBJECT | INTERFACE
+
+directive @join__owner(graph: join__Graph!) on OBJECT | INTERFACE
+
+directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+
+scalar join__FieldSet
+"#;
+
+/// Error composing the supergraph
+#[derive(Debug, Error, Display)]
+pub(crate) enum CompositionError {
+    /// could not read '{path}': {error}
+    Read { path: String, error: std::io::Error },
+
+    /// invalid supergraph configuration: {0}
+    Config(serde_yaml::Error),
+
+    /// syntax errors in the schema of subgraph '{subgraph}': {errors}
+    Syntax { subgraph: String, errors: String },
+
+    /// no subgraph defines a Query type
+    NoQuery,
+}
+
+/// Subgraphs to compose, in the format of the Rover `supergraph.yaml` file
+#[derive(Debug, Deserialize)]
+struct SupergraphConfig {
+    subgraphs: IndexMap<String, SubgraphConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphConfig {
+    routing_url: String,
+    schema: SubgraphSchema,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphSchema {
+    /// Path of the schema, relative to the configuration file
+    file: PathBuf,
+}
+
+/// A subgraph schema and the URL the router sends requests to
+#[derive(Debug)]
+pub(crate) struct Subgraph {
+    pub(crate) name: String,
+    pub(crate) url: String,
+    pub(crate) sdl: String,
+}
+
+/// Composes the supergraph from the subgraphs listed in the configuration file. When watching,
+/// it is composed again when the configuration file or one of the subgraph schemas changes.
+pub(crate) fn stream_supergraph(
+    config_path: PathBuf,
+    watch: bool,
+) -> impl Stream<Item = Result<String, CompositionError>> {
+    if !watch {
+        return stream::once(future::ready(compose_files(&config_path))).boxed();
+    }
+
+    let mut paths = vec![config_path.clone()];
+    if let Ok(config) = read_config(&config_path) {
+        paths.extend(config.schema_paths(&config_path));
+    }
+    // Each watched file sends an event when the watch starts, and the files can change without
+    // changing the supergraph, so only the supergraphs that differ from the last one are sent
+    let mut last = None;
+    stream::select_all(paths.iter().map(|path| crate::files::watch(path).boxed()))
+        .filter_map(move |_| {
+            let composed = compose_files(&config_path);
+            let changed = match (&composed, &last) {
+                (Ok(supergraph), Some(last)) => supergraph != last,
+                _ => true,
+            };
+            if let Ok(supergraph) = &composed {
+                last = Some(supergraph.clone());
+            }
+            future::ready(changed.then_some(composed))
+        })
+        .boxed()
+}
+
+fn read_config(path: &Path) -> Result<SupergraphConfig, CompositionError> {
+    let config = std::fs::read_to_string(path).map_err(|error| CompositionError::Read {
+        path: path.display().to_string(),
+        error,
+    })?;
+    serde_yaml::from_str(&config).map_err(CompositionError::Config)
+}
+
+impl SupergraphConfig {
+    fn schema_paths<'a>(&'a self, config_path: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        let directory = config_path.parent().unwrap_or_else(|| Path::new(""));
+        self.subgraphs
+            .values()
+            .map(move |subgraph| directory.join(&subgraph.schema.file))
+    }
+}
+
+fn compose_files(config_path: &Path) -> Result<String, CompositionError> {
+    let config = read_config(config_path)?;
+    let subgraphs = config
+        .subgraphs
+        .iter()
+        .zip(config.schema_paths(config_path))
+        .map(|((name, subgraph), path)| {
+            let sdl = std::fs::read_to_string(&path).map_err(|error| CompositionError::Read {
+                path: path.display().to_string(),
+                error,
+            })?;
+            Ok(Subgraph {
+                name: name.clone(),
+                url: subgraph.routing_url.clone(),
+                sdl,
+            })
+        })
+        .collect::<Result<Vec<_>, CompositionError>>()?;
+    compose(&subgraphs)
+}
+
+/// Composes a supergraph schema using the join v0.1 specification
+pub(crate) fn compose(subgraphs: &[Subgraph]) -> Result<String, CompositionError> {
+    let mut documents = Vec::new();
+    for subgraph in subgraphs {
+        let tree = apollo_parser::Parser::new(&subgraph.sdl).parse();
+        let errors = tree
+            .errors()
+            .map(|err| format!("{:?}", err))
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(CompositionError::Syntax {
+                subgraph: subgraph.name.clone(),
+                errors: errors.join(", "),
+            });
+        }
+        let document = tree.document();
+        let federation = FederationDirectives::new(&document);
+        documents.push((graph_name(&subgraph.name), document, federation));
+    }
+
+    // Directives are kept if a subgraph defines them, so all the definitions are needed first
+    let mut definitions = BTreeMap::new();
+    for (_, document, federation) in &documents {
+        add_directive_definitions(&mut definitions, document, federation);
+    }
+    let mut supergraph = Supergraph::default();
+    for (graph, document, federation) in &documents {
+        let directives = DirectiveFilter {
+            federation,
+            definitions: &definitions,
+        };
+        supergraph.add(graph, document, &directives);
+    }
+
+    if !supergraph.objects.contains_key("Query") {
+        return Err(CompositionError::NoQuery);
+    }
+    Ok(supergraph.print(subgraphs, &definitions))
+}
+
+/// Name of the `join__Graph` value of a subgraph
+fn graph_name(subgraph: &str) -> String {
+    subgraph
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Supergraph {
+    objects: BTreeMap<String, ObjectType>,
+    others: BTreeMap<String, OtherType>,
+}
+
+#[derive(Default)]
+struct ObjectType {
+    description: Option<String>,
+    interfaces: IndexSet<String>,
+    directives: IndexSet<String>,
+    /// Subgraph defining the entity with `type` rather than `extend type`
+    owner: Option<String>,
+    /// Subgraph and fields of each key
+    keys: Vec<(String, String)>,
+    fields: IndexMap<String, Member>,
+}
+
+/// Interfaces, unions, enums, input types and scalars, merged across subgraphs
+struct OtherType {
+    keyword: &'static str,
+    description: Option<String>,
+    interfaces: IndexSet<String>,
+    directives: IndexSet<String>,
+    members: IndexMap<String, Member>,
+}
+
+/// A field, enum value or union member
+struct Member {
+    description: Option<String>,
+    definition: String,
+    /// Arguments of `@join__field`
+    join: Option<String>,
+}
+
+/// Another type of a subgraph, to merge in the supergraph
+struct OtherDefinition {
+    keyword: &'static str,
+    name: Option<String>,
+    description: Option<String>,
+    interfaces: Vec<String>,
+    directives: Vec<String>,
+    members: Vec<(String, Member)>,
+}
+
+impl Supergraph {
+    fn add(&mut self, graph: &str, document: &ast::Document, directives: &DirectiveFilter) {
+        // Root types of the subgraph, by supergraph root type
+        let mut roots = HashMap::from([
+            ("Query".to_string(), "Query".to_string()),
+            ("Mutation".to_string(), "Mutation".to_string()),
+            ("Subscription".to_string(), "Subscription".to_string()),
+        ]);
+        for definition in document.definitions() {
+            let operations = match definition {
+                ast::Definition::SchemaDefinition(schema) => {
+                    schema.root_operation_type_definitions()
+                }
+                ast::Definition::SchemaExtension(schema) => {
+                    schema.root_operation_type_definitions()
+                }
+                _ => continue,
+            };
+            for operation in operations {
+                let kind = text(operation.operation_type());
+                let supergraph_root = match kind.as_str() {
+                    "query" => "Query",
+                    "mutation" => "Mutation",
+                    _ => "Subscription",
+                };
+                if let Some(name) = operation.named_type().and_then(|named| name(named.name())) {
+                    roots.retain(|_, root| root != supergraph_root);
+                    roots.insert(name, supergraph_root.to_string());
+                }
+            }
+        }
+
+        for definition in document.definitions() {
+            let other = match definition {
+                ast::Definition::ObjectTypeDefinition(object) => {
+                    self.add_object(
+                        graph,
+                        &roots,
+                        directives,
+                        ObjectDefinition {
+                            description: description(object.description()),
+                            name: name(object.name()),
+                            interfaces: interfaces(object.implements_interfaces()),
+                            directives: object.directives(),
+                            fields: object.fields_definition(),
+                            extension: false,
+                        },
+                    );
+                    continue;
+                }
+                ast::Definition::ObjectTypeExtension(object) => {
+                    self.add_object(
+                        graph,
+                        &roots,
+                        directives,
+                        ObjectDefinition {
+                            description: None,
+                            name: name(object.name()),
+                            interfaces: interfaces(object.implements_interfaces()),
+                            directives: object.directives(),
+                            fields: object.fields_definition(),
+                            extension: true,
+                        },
+                    );
+                    continue;
+                }
+                ast::Definition::InterfaceTypeDefinition(interface) => OtherDefinition {
+                    keyword: "interface",
+                    name: name(interface.name()),
+                    description: description(interface.description()),
+                    interfaces: interfaces(interface.implements_interfaces()),
+                    directives: directives.kept(interface.directives()),
+                    members: fields(interface.fields_definition(), directives),
+                },
+                ast::Definition::InterfaceTypeExtension(interface) => OtherDefinition {
+                    keyword: "interface",
+                    name: name(interface.name()),
+                    description: None,
+                    interfaces: interfaces(interface.implements_interfaces()),
+                    directives: directives.kept(interface.directives()),
+                    members: fields(interface.fields_definition(), directives),
+                },
+                ast::Definition::UnionTypeDefinition(union) => OtherDefinition {
+                    keyword: "union",
+                    name: name(union.name()),
+                    description: description(union.description()),
+                    interfaces: Vec::new(),
+                    directives: directives.kept(union.directives()),
+                    members: union_members(union.union_member_types()),
+                },
+                ast::Definition::UnionTypeExtension(union) => OtherDefinition {
+                    keyword: "union",
+                    name: name(union.name()),
+                    description: None,
+                    interfaces: Vec::new(),
+                    directives: directives.kept(union.directives()),
+                    members: union_members(union.union_member_types()),
+                },
+                ast::Definition::EnumTypeDefinition(enum_type) => OtherDefinition {
+                    keyword: "enum",
+                    name: name(enum_type.name()),
+                    description: description(enum_type.description()),
+                    interfaces: Vec::new(),
+                    directives: directives.kept(enum_type.directives()),
+                    members: enum_values(enum_type.enum_values_definition(), directives),
+                },
+                ast::Definition::EnumTypeExtension(enum_type) => OtherDefinition {
+                    keyword: "enum",
+                    name: name(enum_type.name()),
+                    description: None,
+                    interfaces: Vec::new(),
+                    directives: directives.kept(enum_type.directives()),
+                    members: enum_values(enum_type.enum_values_definition(), directives),
+                },
+                ast::Definition::InputObjectTypeDefinition(input) => OtherDefinition {
+                    keyword: "input",
+                    name: name(input.name()),
+                    description: description(input.description()),
+                    interfaces: Vec::new(),
+                    directives: directives.kept(input.directives()),
+                    members: input_fields(input.input_fields_definition(), directives),
+                },
+                ast::Definition::InputObjectTypeExtension(input) => OtherDefinition {
+                    keyword: "input",
+                    name: name(input.name()),
+                    description: None,
+                    interfaces: Vec::new(),
+                    directives: directives.kept(input.directives()),
+                    members: input_fields(input.input_fields_definition(), directives),
+                },
+                ast::Definition::ScalarTypeDefinition(scalar) => OtherDefinition {
+                    keyword: "scalar",
+                    name: name(scalar.name()),
+                    description: description(scalar.description()),
+                    interfaces: Vec::new(),
+                    directives: directives.kept(scalar.directives()),
+                    members: Vec::new(),
+                },
+                ast::Definition::ScalarTypeExtension(scalar) => OtherDefinition {
+                    keyword: "scalar",
+                    name: name(scalar.name()),
+                    description: None,
+                    interfaces: Vec::new(),
+                    directives: directives.kept(scalar.directives()),
+                    members: Vec::new(),
+                },
+                // Directive definitions are added before the types
+                _ => continue,
+            };
+            self.add_other(other);
+        }
+    }
+
+    fn add_object(
+        &mut self,
+        graph: &str,
+        roots: &HashMap<String, String>,
+        directives: &DirectiveFilter,
+        definition: ObjectDefinition,
+    ) {
+        let federation = directives.federation;
+        let type_name = match definition.name {
+            Some(name) if !is_federation_type(&name) => name,
+            _ => return,
+        };
+        let root = roots.get(&type_name).cloned();
+        let all_keys = keys(&definition.directives, &federation.name("key"));
+        // Federation 2 subgraphs reference the entities they do not resolve with
+        // `resolvable: false` keys, and only define their key fields
+        if !all_keys.is_empty() && all_keys.iter().all(|(_, resolvable)| !resolvable) {
+            return;
+        }
+        let keys = all_keys
+            .into_iter()
+            .filter_map(|(fields, resolvable)| resolvable.then_some(fields))
+            .collect::<Vec<_>>();
+        let extension = definition.extension
+            || count_directives(&definition.directives, &federation.name("extends")) > 0;
+
+        let object = self
+            .objects
+            .entry(root.clone().unwrap_or(type_name))
+            .or_default();
+        if object.description.is_none() {
+            object.description = definition.description;
+        }
+        object.interfaces.extend(definition.interfaces);
+        object
+            .directives
+            .extend(directives.kept(definition.directives));
+        if !keys.is_empty() && !extension && object.owner.is_none() {
+            object.owner = Some(graph.to_string());
+        }
+        // Fields of root types and entities are resolved by the subgraphs defining them, while
+        // value types are resolved by any subgraph
+        let joined = root.is_some() || !keys.is_empty();
+        object
+            .keys
+            .extend(keys.into_iter().map(|key| (graph.to_string(), key)));
+
+        for field in definition
+            .fields
+            .iter()
+            .flat_map(|fields| fields.field_definitions())
+        {
+            let field_name = match name(field.name()) {
+                Some(field_name) => field_name,
+                None => continue,
+            };
+            let field_directives = field.directives();
+            // `_entities` and `_service` are added to subgraphs by federation libraries
+            if count_directives(&field_directives, &federation.name("external")) > 0
+                || (root.is_some() && field_name.starts_with('_'))
+            {
+                continue;
+            }
+            let join = joined.then(|| {
+                let mut join = format!("graph: {graph}");
+                for directive in ["requires", "provides"] {
+                    if let Some(fields) = directive_arguments(
+                        &field_directives,
+                        &federation.name(directive),
+                        "fields",
+                    )
+                    .into_iter()
+                    .next()
+                    {
+                        let _ = write!(join, ", {directive}: {}", string(&field_set(&fields)));
+                    }
+                }
+                join
+            });
+            let member = Member {
+                description: description(field.description()),
+                definition: print_field(&field, directives),
+                join,
+            };
+            // the subgraph overriding a field resolves it instead of the subgraph it is moved from
+            if count_directives(&field_directives, &federation.name("override")) > 0 {
+                object.fields.insert(field_name, member);
+            } else {
+                object.fields.entry(field_name).or_insert(member);
+            }
+        }
+    }
+
+    fn add_other(&mut self, definition: OtherDefinition) {
+        let name = match definition.name {
+            Some(name) if !is_federation_type(&name) => name,
+            _ => return,
+        };
+        let other = self.others.entry(name).or_insert_with(|| OtherType {
+            keyword: definition.keyword,
+            description: None,
+            interfaces: IndexSet::new(),
+            directives: IndexSet::new(),
+            members: IndexMap::new(),
+        });
+        if other.description.is_none() {
+            other.description = definition.description;
+        }
+        other.interfaces.extend(definition.interfaces);
+        other.directives.extend(definition.directives);
+        for (name, member) in definition.members {
+            other.members.entry(name).or_insert(member);
+        }
+    }
+
+    fn print(&self, subgraphs: &[Subgraph], directives: &BTreeMap<String, String>) -> String {
+        let mut schema = String::from(
+            "schema\n  @core(feature: \"https://specs.apollo.dev/core/v0.1\"),\n  @core(feature: \"https://specs.apollo.dev/join/v0.1\")\n{\n",
+        );
+        for (operation, root) in [
+            ("query", "Query"),
+            ("mutation", "Mutation"),
+            ("subscription", "Subscription"),
+        ] {
+            if self.objects.contains_key(root) {
+                let _ = writeln!(schema, "  {operation}: {root}");
+            }
+        }
+        schema.push_str("}\n\n");
+        schema.push_str(JOIN_DEFINITIONS);
+        for definition in directives.values() {
+            let _ = write!(schema, "\n{definition}\n");
+        }
+
+        schema.push_str("\nenum join__Graph {\n");
+        for subgraph in subgraphs {
+            let _ = writeln!(
+                schema,
+                "  {} @join__graph(name: {}, url: {})",
+                graph_name(&subgraph.name),
+                string(&subgraph.name),
+                string(&subgraph.url)
+            );
+        }
+        schema.push_str("}\n");
+
+        for (name, object) in &self.objects {
+            schema.push('\n');
+            print_description(&mut schema, &object.description, "");
+            let _ = write!(schema, "type {name}");
+            print_interfaces(&mut schema, &object.interfaces);
+            print_directives(&mut schema, &object.directives);
+            if !object.keys.is_empty() {
+                let owner = object.owner.as_ref().unwrap_or_else(|| &object.keys[0].0);
+                let _ = write!(schema, "\n  @join__owner(graph: {owner})");
+                for (graph, key) in &object.keys {
+                    let _ = write!(
+                        schema,
+                        "\n  @join__type(graph: {graph}, key: {})",
+                        string(key)
+                    );
+                }
+                schema.push('\n');
+            } else {
+                schema.push(' ');
+            }
+            print_members(&mut schema, &object.fields);
+        }
+
+        for (name, other) in &self.others {
+            schema.push('\n');
+            print_description(&mut schema, &other.description, "");
+            match other.keyword {
+                "scalar" => {
+                    let _ = write!(schema, "scalar {name}");
+                    print_directives(&mut schema, &other.directives);
+                    schema.push('\n');
+                }
+                "union" => {
+                    let members = other.members.keys().cloned().collect::<Vec<_>>();
+                    let _ = write!(schema, "union {name}");
+                    print_directives(&mut schema, &other.directives);
+                    let _ = writeln!(schema, " = {}", members.join(" | "));
+                }
+                keyword => {
+                    let _ = write!(schema, "{keyword} {name}");
+                    print_interfaces(&mut schema, &other.interfaces);
+                    print_directives(&mut schema, &other.directives);
+                    schema.push(' ');
+                    print_members(&mut schema, &other.members);
+                }
+            }
+        }
+        schema
+    }
+}
+
+struct ObjectDefinition {
+    description: Option<String>,
+    name: Option<String>,
+    interfaces: Vec<String>,
+    directives: Option<ast::Directives>,
+    fields: Option<ast::FieldsDefinition>,
+    extension: bool,
+}
+
+fn is_federation_type(name: &str) -> bool {
+    name.starts_with('_')
+        || name.starts_with("link__")
+        || name.starts_with("federation__")
+        || name == "FieldSet"
+}
+
+fn print_description(schema: &mut String, description: &Option<String>, indent: &str) {
+    if let Some(description) = description {
+        let _ = writeln!(schema, "{indent}{description}");
+    }
+}
+
+fn print_interfaces(schema: &mut String, interfaces: &IndexSet<String>) {
+    if !interfaces.is_empty() {
+        let interfaces = interfaces.iter().cloned().collect::<Vec<_>>();
+        let _ = write!(schema, " implements {}", interfaces.join(" & "));
+    }
+}
+
+fn print_directives(schema: &mut String, directives: &IndexSet<String>) {
+    for directive in directives {
+        let _ = write!(schema, " {directive}");
+    }
+}
+
+fn print_members(schema: &mut String, members: &IndexMap<String, Member>) {
+    schema.push_str("{\n");
+    for member in members.values() {
+        print_description(schema, &member.description, "  ");
+        let _ = write!(schema, "  {}", member.definition);
+        if let Some(join) = &member.join {
+            let _ = write!(schema, " @join__field({join})");
+        }
+        schema.push('\n');
+    }
+    schema.push_str("}\n");
+}
+
+fn print_field(field: &ast::FieldDefinition, directives: &DirectiveFilter) -> String {
+    let mut printed = name(field.name()).unwrap_or_default();
+    if let Some(arguments) = field.arguments_definition() {
+        let arguments = arguments
+            .input_value_definitions()
+            .map(|argument| print_input_value(&argument, directives))
+            .collect::<Vec<_>>();
+        let _ = write!(printed, "({})", arguments.join(", "));
+    }
+    let _ = write!(printed, ": {}", text(field.ty()));
+    printed.push_str(&directives.print(field.directives()));
+    printed
+}
+
+fn print_input_value(value: &ast::InputValueDefinition, directives: &DirectiveFilter) -> String {
+    let mut printed = format!(
+        "{}: {}",
+        name(value.name()).unwrap_or_default(),
+        text(value.ty())
+    );
+    if let Some(default_value) = value.default_value() {
+        let _ = write!(printed, " {}", text(Some(default_value)));
+    }
+    printed.push_str(&directives.print(value.directives()));
+    printed
+}
+
+fn fields(
+    fields: Option<ast::FieldsDefinition>,
+    directives: &DirectiveFilter,
+) -> Vec<(String, Member)> {
+    fields
+        .iter()
+        .flat_map(|fields| fields.field_definitions())
+        .filter_map(|field| {
+            Some((
+                name(field.name())?,
+                Member {
+                    description: description(field.description()),
+                    definition: print_field(&field, directives),
+                    join: None,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn input_fields(
+    fields: Option<ast::InputFieldsDefinition>,
+    directives: &DirectiveFilter,
+) -> Vec<(String, Member)> {
+    fields
+        .iter()
+        .flat_map(|fields| fields.input_value_definitions())
+        .filter_map(|field| {
+            Some((
+                name(field.name())?,
+                Member {
+                    description: description(field.description()),
+                    definition: print_input_value(&field, directives),
+                    join: None,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn enum_values(
+    values: Option<ast::EnumValuesDefinition>,
+    directives: &DirectiveFilter,
+) -> Vec<(String, Member)> {
+    values
+        .iter()
+        .flat_map(|values| values.enum_value_definitions())
+        .filter_map(|value| {
+            let name = name(value.enum_value()?.name())?;
+            Some((
+                name.clone(),
+                Member {
+                    description: description(value.description()),
+                    definition: format!("{name}{}", directives.print(value.directives())),
+                    join: None,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn union_members(members: Option<ast::UnionMemberTypes>) -> Vec<(String, Member)> {
+    members
+        .iter()
+        .flat_map(|members| members.named_types())
+        .filter_map(|member| {
+            let name = name(member.name())?;
+            Some((
+                name.clone(),
+                Member {
+                    description: None,
+                    definition: name,
+                    join: None,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn interfaces(interfaces: Option<ast::ImplementsInterfaces>) -> Vec<String> {
+    interfaces
+        .iter()
+        .flat_map(|interfaces| interfaces.named_types())
+        .filter_map(|interface| name(interface.name()))
+        .collect()
+}
+
+fn name(name: Option<ast::Name>) -> Option<String> {
+    name.map(|name| name.text().to_string())
+}
+
+fn text(node: Option<impl AstNode>) -> String {
+    node.map(|node| node.syntax().text().to_string().trim().to_string())
+        .unwrap_or_default()
+}
+
+fn description(description: Option<ast::Description>) -> Option<String> {
+    description.map(|description| text(Some(description)))
+}
+
+/// A GraphQL string value
+fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04X}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Adds the definitions of the directives of a subgraph, other than the federation directives
+/// and the built-in directives
+fn add_directive_definitions(
+    definitions: &mut BTreeMap<String, String>,
+    document: &ast::Document,
+    federation: &FederationDirectives,
+) {
+    for definition in document.definitions() {
+        if let ast::Definition::DirectiveDefinition(directive) = definition {
+            if let Some(name) = name(directive.name()) {
+                if !federation.is_federation_directive(&name)
+                    && !BUILT_IN_DIRECTIVES.contains(&name.as_str())
+                {
+                    definitions
+                        .entry(name)
+                        .or_insert_with(|| text(Some(directive)));
+                }
+            }
+        }
+    }
+}
+
+const BUILT_IN_DIRECTIVES: &[&str] = &["skip", "include", "deprecated", "specifiedBy"];
+
+/// Directives of the subgraph schemas that are kept in the supergraph: the built-in directives,
+/// and the directives defined by a subgraph
+struct DirectiveFilter<'a> {
+    federation: &'a FederationDirectives,
+    /// Definitions of the directives of all the subgraphs, by name
+    definitions: &'a BTreeMap<String, String>,
+}
+
+impl DirectiveFilter<'_> {
+    fn kept(&self, directives: Option<ast::Directives>) -> Vec<String> {
+        directives
+            .iter()
+            .flat_map(|directives| directives.directives())
+            .filter(|directive| {
+                name(directive.name()).map_or(false, |name| {
+                    !self.federation.is_federation_directive(&name)
+                        && (BUILT_IN_DIRECTIVES.contains(&name.as_str())
+                            || self.definitions.contains_key(&name))
+                })
+            })
+            .map(|directive| text(Some(directive)))
+            .collect()
+    }
+
+    /// The kept directives, each preceded by a space
+    fn print(&self, directives: Option<ast::Directives>) -> String {
+        self.kept(directives)
+            .iter()
+            .map(|directive| format!(" {directive}"))
+            .collect()
+    }
+}
+
+fn count_directives(directives: &Option<ast::Directives>, directive: &str) -> usize {
+    directives
+        .iter()
+        .flat_map(|directives| directives.directives())
+        .filter(|d| name(d.name()).as_deref() == Some(directive))
+        .count()
+}
+
+/// String values of an argument of a directive
+fn directive_arguments(
+    directives: &Option<ast::Directives>,
+    directive: &str,
+    argument: &str,
+) -> Vec<String> {
+    directives
+        .iter()
+        .flat_map(|directives| directives.directives())
+        .filter(|d| name(d.name()).as_deref() == Some(directive))
+        .filter_map(|d| self::argument(&d, argument))
+        .filter_map(|value| match value {
+            ast::Value::StringValue(value) => Some(value.into()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Directives of the federation specs, which are not kept in the supergraph
+const FEDERATION_DIRECTIVES: &[&str] = &[
+    "key",
+    "external",
+    "requires",
+    "provides",
+    "extends",
+    "shareable",
+    "override",
+    "inaccessible",
+    "tag",
+    "composeDirective",
+    "interfaceObject",
+];
+
+/// Names of the federation directives in a subgraph schema
+struct FederationDirectives {
+    /// Prefix of the directives that Federation 2 subgraphs do not import, `None` for Federation 1
+    /// subgraphs, which use the directives without `@link`
+    prefix: Option<String>,
+    /// Name in the subgraph of the imported directives, by name in the federation spec
+    imports: HashMap<String, String>,
+}
+
+impl FederationDirectives {
+    fn new(document: &ast::Document) -> Self {
+        let link = document
+            .definitions()
+            .filter_map(|definition| match definition {
+                ast::Definition::SchemaDefinition(schema) => schema.directives(),
+                ast::Definition::SchemaExtension(schema) => schema.directives(),
+                _ => None,
+            })
+            .flat_map(|directives| directives.directives())
+            .filter(|directive| name(directive.name()).as_deref() == Some("link"))
+            .find(|directive| {
+                argument(directive, "url").map_or(false, |url| match url {
+                    ast::Value::StringValue(url) => {
+                        String::from(url).contains("specs.apollo.dev/federation/v2")
+                    }
+                    _ => false,
+                })
+            });
+        let link = match link {
+            Some(link) => link,
+            None => {
+                return FederationDirectives {
+                    prefix: None,
+                    imports: HashMap::new(),
+                }
+            }
+        };
+
+        let prefix = match argument(&link, "as") {
+            Some(ast::Value::StringValue(prefix)) => String::from(prefix),
+            _ => "federation".to_string(),
+        };
+        let mut imports = HashMap::new();
+        if let Some(ast::Value::ListValue(list)) = argument(&link, "import") {
+            for import in list.values() {
+                // imports are either "@name" or { name: "@name", as: "@renamed" }
+                let (imported, renamed) = match import {
+                    ast::Value::StringValue(imported) => {
+                        let imported = String::from(imported);
+                        (imported.clone(), imported)
+                    }
+                    ast::Value::ObjectValue(import) => {
+                        let mut imported = None;
+                        let mut renamed = None;
+                        for field in import.object_fields() {
+                            if let Some(ast::Value::StringValue(value)) = field.value() {
+                                match name(field.name()).as_deref() {
+                                    Some("name") => imported = Some(String::from(value)),
+                                    Some("as") => renamed = Some(String::from(value)),
+                                    _ => {}
+                                }
+                            }
+                        }
+                        match imported {
+                            Some(imported) => (imported.clone(), renamed.unwrap_or(imported)),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                // the types of the spec, such as FieldSet, are imported without `@`
+                if let (Some(imported), Some(renamed)) =
+                    (imported.strip_prefix('@'), renamed.strip_prefix('@'))
+                {
+                    imports.insert(imported.to_string(), renamed.to_string());
+                }
+            }
+        }
+        FederationDirectives {
+            prefix: Some(prefix),
+            imports,
+        }
+    }
+
+    /// Whether a directive of the subgraph schema is a federation directive
+    fn is_federation_directive(&self, directive: &str) -> bool {
+        let prefixed = self.prefix.as_ref().map_or(false, |prefix| {
+            directive
+                .strip_prefix(prefix.as_str())
+                .map_or(false, |name| name.starts_with("__"))
+        });
+        prefixed
+            || directive == "link"
+            || FEDERATION_DIRECTIVES
+                .iter()
+                .any(|federation| self.name(federation) == directive)
+    }
+
+    /// The name of the directive in the subgraph schema
+    fn name(&self, directive: &str) -> String {
+        match &self.prefix {
+            None => directive.to_string(),
+            Some(prefix) => self
+                .imports
+                .get(directive)
+                .cloned()
+                .unwrap_or_else(|| format!("{prefix}__{directive}")),
+        }
+    }
+}
+
+fn argument(directive: &ast::Directive, argument: &str) -> Option<ast::Value> {
+    directive
+        .arguments()?
+        .arguments()
+        .find(|a| name(a.name()).as_deref() == Some(argument))?
+        .value()
+}
+
+/// Fields of the `@key` directives, and whether the keys are resolvable
+fn keys(directives: &Option<ast::Directives>, key: &str) -> Vec<(String, bool)> {
+    directives
+        .iter()
+        .flat_map(|directives| directives.directives())
+        .filter(|d| name(d.name()).as_deref() == Some(key))
+        .filter_map(|d| {
+            let fields = match argument(&d, "fields")? {
+                ast::Value::StringValue(fields) => field_set(&String::from(fields)),
+                _ => return None,
+            };
+            let resolvable = !matches!(
+                argument(&d, "resolvable"),
+                Some(ast::Value::BooleanValue(resolvable)) if text(Some(resolvable.clone())) == "false"
+            );
+            Some((fields, resolvable))
+        })
+        .collect()
+}
+
+/// Federation 1 accepted field sets wrapped in braces, which the query planner rejects
+fn field_set(fields: &str) -> String {
+    let fields = fields.trim();
+    fields
+        .strip_prefix('{')
+        .and_then(|fields| fields.strip_suffix('}'))
+        .unwrap_or(fields)
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::Schema;
+
+    fn subgraph(name: &str, sdl: &str) -> Subgraph {
+        Subgraph {
+            name: name.to_string(),
+            url: format!("http://localhost/{name}"),
+            sdl: sdl.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_composes_entities_and_value_types() {
+        let supergraph = compose(&[
+            subgraph(
+                "products",
+                r#"
+                type Query {
+                  topProducts(first: Int = 5): [Product]
+                }
+                "A product"
+                type Product @key(fields: "upc") {
+                  upc: String!
+                  price: Int
+                  weight: Int
+                  currency: Currency @deprecated(reason: "always USD")
+                }
+                enum Currency { USD }
+                "#,
+            ),
+            subgraph(
+                "inventory",
+                r#"
+                schema { query: RootQuery }
+                type RootQuery {
+                  _service: String
+                }
+                extend type Product @key(fields: "upc") {
+                  upc: String! @external
+                  price: Int @external
+                  weight: Int @external
+                  shippingEstimate: Int @requires(fields: "price weight")
+                }
+                enum Currency { EUR }
+                "#,
+            ),
+        ])
+        .unwrap();
+
+        assert!(supergraph.contains(
+            r#"INVENTORY @join__graph(name: "inventory", url: "http://localhost/inventory")"#
+        ));
+        assert!(supergraph.contains(
+            "type Product\n  @join__owner(graph: PRODUCTS)\n  @join__type(graph: PRODUCTS, key: \"upc\")\n  @join__type(graph: INVENTORY, key: \"upc\")\n"
+        ));
+        assert!(supergraph.contains(
+            "shippingEstimate: Int @join__field(graph: INVENTORY, requires: \"price weight\")"
+        ));
+        assert!(supergraph
+            .contains("topProducts(first: Int = 5): [Product] @join__field(graph: PRODUCTS)"));
+        assert!(supergraph.contains("currency: Currency @deprecated(reason: \"always USD\")"));
+        assert!(supergraph.contains("enum Currency {\n  USD\n  EUR\n}"));
+        assert!(!supergraph.contains("_service"));
+        assert!(!supergraph.contains("RootQuery"));
+        let schema = Schema::parse(&supergraph, &Default::default()).unwrap();
+        assert_eq!(schema.subgraphs().count(), 2);
+    }
+
+    #[test]
+    fn it_composes_federation_1_and_federation_2_subgraphs() {
+        let supergraph = compose(&[
+            subgraph(
+                "products",
+                r#"
+                type Query {
+                  topProducts: [Product]
+                }
+                type Product @key(fields: "{ upc }") {
+                  upc: String!
+                  name: String
+                  inStock: Boolean
+                }
+                "#,
+            ),
+            subgraph(
+                "reviews",
+                r#"
+                extend schema @link(
+                  url: "https://specs.apollo.dev/federation/v2.0",
+                  import: [{ name: "@key", as: "@primaryKey" }, "@shareable", "FieldSet"]
+                )
+                directive @federation__tag(name: String!) repeatable on FIELD_DEFINITION
+                directive @audited on FIELD_DEFINITION
+                type Product @primaryKey(fields: "upc") {
+                  upc: String!
+                  reviews: [Review]
+                }
+                type Review {
+                  body: String @shareable @audited @federation__tag(name: "public")
+                  author: User
+                }
+                type User @primaryKey(fields: "id", resolvable: false) {
+                  id: ID!
+                }
+                "#,
+            ),
+            subgraph(
+                "inventory",
+                r#"
+                extend schema @link(url: "https://specs.apollo.dev/federation/v2.0", import: ["@key"])
+                type Product @key(fields: "upc") {
+                  upc: String!
+                  inStock: Boolean @federation__override(from: "products")
+                }
+                "#,
+            ),
+            subgraph(
+                "accounts",
+                r#"
+                type User @key(fields: "id") {
+                  id: ID!
+                  name: String
+                }
+                "#,
+            ),
+        ])
+        .unwrap();
+
+        assert!(supergraph.contains(
+            "type Product\n  @join__owner(graph: PRODUCTS)\n  @join__type(graph: PRODUCTS, key: \"upc\")\n  @join__type(graph: REVIEWS, key: \"upc\")\n  @join__type(graph: INVENTORY, key: \"upc\")\n"
+        ));
+        assert!(supergraph.contains("reviews: [Review] @join__field(graph: REVIEWS)"));
+        assert!(supergraph.contains("inStock: Boolean @join__field(graph: INVENTORY)"));
+        assert!(supergraph.contains("type Review {\n  body: String @audited\n  author: User\n}"));
+        // federation directives are removed, even when renamed or prefixed
+        assert!(!supergraph.contains("@primaryKey"));
+        assert!(!supergraph.contains("@federation__"));
+        assert!(!supergraph.contains("@shareable"));
+        // the subgraph referencing users does not resolve them
+        assert!(supergraph.contains(
+            "type User\n  @join__owner(graph: ACCOUNTS)\n  @join__type(graph: ACCOUNTS, key: \"id\")\n{"
+        ));
+        let schema = Schema::parse(&supergraph, &Default::default()).unwrap();
+        assert_eq!(schema.subgraphs().count(), 4);
+    }
+
+    #[test]
+    fn it_keeps_the_directives_defined_by_subgraphs() {
+        let supergraph = compose(&[subgraph(
+            "products",
+            r#"
+                directive @key(fields: _FieldSet!) repeatable on OBJECT | INTERFACE
+                directive @cacheControl(maxAge: Int) on FIELD_DEFINITION | OBJECT
+                type Query {
+                  topProducts: [Product] @cacheControl(maxAge: 60) @undefined
+                }
+                type Product @key(fields: "upc") @cacheControl(maxAge: 30) {
+                  upc: String!
+                  sku: String @deprecated(reason: "use \"upc\"")
+                }
+                scalar Url @specifiedBy(url: "https://tools.ietf.org/html/rfc3986")
+                "#,
+        )])
+        .unwrap();
+
+        assert!(supergraph
+            .contains("\ndirective @cacheControl(maxAge: Int) on FIELD_DEFINITION | OBJECT\n"));
+        assert!(!supergraph.contains("directive @key"));
+        assert!(supergraph.contains(
+            "topProducts: [Product] @cacheControl(maxAge: 60) @join__field(graph: PRODUCTS)"
+        ));
+        assert!(supergraph.contains("type Product @cacheControl(maxAge: 30)\n  @join__owner"));
+        assert!(supergraph.contains(r#"sku: String @deprecated(reason: "use \"upc\"")"#));
+        assert!(supergraph
+            .contains(r#"scalar Url @specifiedBy(url: "https://tools.ietf.org/html/rfc3986")"#));
+        assert!(!supergraph.contains("@undefined"));
+        let schema = Schema::parse(&supergraph, &Default::default()).unwrap();
+        assert_eq!(schema.subgraphs().count(), 1);
+    }
+
+    #[test]
+    fn it_escapes_strings() {
+        assert_eq!(string("upc"), r#""upc""#);
+        assert_eq!(
+            string("a \"quoted\" \\ path\nwith\tcontrol\u{1}"),
+            r#""a \"quoted\" \\ path\nwith\tcontrol\u0001""#
+        );
+        let supergraph = compose(&[Subgraph {
+            name: "products".to_string(),
+            url: "http://localhost/products?name=\"products\"".to_string(),
+            sdl: "type Query { upc: String }".to_string(),
+        }])
+        .unwrap();
+        assert!(supergraph.contains(r#"url: "http://localhost/products?name=\"products\"""#));
+        Schema::parse(&supergraph, &Default::default()).unwrap();
+    }
+
+    #[test]
+    fn it_reports_syntax_errors_and_missing_query() {
+        assert!(matches!(
+            compose(&[subgraph("products", "type Query {")]),
+            Err(CompositionError::Syntax { subgraph, .. }) if subgraph == "products"
+        ));
+        assert!(matches!(
+            compose(&[subgraph("products", "type Product { upc: String }")]),
+            Err(CompositionError::NoQuery)
+        ));
+    }
+}
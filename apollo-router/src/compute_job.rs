@@ -0,0 +1,126 @@
+//! Thread pool for CPU-heavy work
+//!
+//! Parsing and validating complex queries, or formatting large responses, can take milliseconds.
+//! On the Tokio worker threads, a burst of such work would delay the I/O tasks sharing these
+//! threads, such as socket handling and health checks. [`execute`] runs it on a dedicated pool of
+//! threads instead.
+//!
+//! The pool has `server.compute_threads` threads if that option is set in the configuration,
+//! `APOLLO_ROUTER_COMPUTE_THREADS` threads if that environment variable is set, and one thread per
+//! CPU core otherwise. The pool is started once, so changing the option requires a restart. Query
+//! planning already runs on the threads of the planner.
+
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+
+const COMPUTE_THREADS_ENV: &str = "APOLLO_ROUTER_COMPUTE_THREADS";
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Number of threads of the pool, set by [`configure`] or when the pool starts
+static THREADS: OnceCell<usize> = OnceCell::new();
+
+static POOL: Lazy<mpsc::UnboundedSender<Job>> =
+    Lazy::new(|| spawn_pool(*THREADS.get_or_init(|| default_threads(None))));
+
+fn default_threads(configured: Option<NonZeroUsize>) -> usize {
+    configured
+        .map(usize::from)
+        .or_else(|| {
+            std::env::var(COMPUTE_THREADS_ENV)
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|threads| *threads > 0)
+        })
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(1)
+        })
+}
+
+/// Sets the number of threads of the pool, from the `server.compute_threads` option.
+///
+/// The pool keeps the size it started with, so a different size is only reported.
+pub(crate) fn configure(configured: Option<NonZeroUsize>) {
+    let threads = default_threads(configured);
+    let current = *THREADS.get_or_init(|| threads);
+    if current != threads {
+        tracing::warn!(
+            "the compute thread pool has {current} threads, restart the router to use {threads} threads"
+        );
+    }
+}
+
+/// Starts `threads` threads running the jobs sent to the returned sender
+fn spawn_pool(threads: usize) -> mpsc::UnboundedSender<Job> {
+    let (sender, receiver) = mpsc::unbounded_channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for index in 0..threads {
+        let receiver = receiver.clone();
+        thread::Builder::new()
+            .name(format!("compute-{index}"))
+            .spawn(move || loop {
+                // the lock is released before running the job
+                let job = receiver
+                    .lock()
+                    .expect("the lock is not held during jobs; qed")
+                    .blocking_recv();
+                match job {
+                    Some(job) => job(),
+                    None => break,
+                }
+            })
+            .expect("could not spawn a compute thread");
+    }
+    sender
+}
+
+/// The job panicked
+#[derive(Debug, thiserror::Error)]
+#[error("compute job panicked")]
+pub(crate) struct ComputeJobError;
+
+/// Runs `job` on the compute thread pool and waits for its result without blocking the caller
+pub(crate) async fn execute<T, F>(job: F) -> Result<T, ComputeJobError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let span = tracing::Span::current();
+    let job: Job = Box::new(move || {
+        let _guard = span.enter();
+        // a panic is reported to the caller instead of stopping the thread
+        let _ = sender.send(std::panic::catch_unwind(AssertUnwindSafe(job)));
+    });
+    POOL.send(job).map_err(|_| ComputeJobError)?;
+    match receiver.await {
+        Ok(Ok(result)) => Ok(result),
+        _ => Err(ComputeJobError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_runs_jobs_on_the_compute_threads() {
+        let name = execute(|| thread::current().name().map(str::to_string))
+            .await
+            .unwrap();
+        assert!(name.unwrap().starts_with("compute-"));
+
+        assert!(execute(|| panic!("expected")).await.is_err());
+        assert_eq!(execute(|| 1 + 1).await.unwrap(), 2);
+    }
+}
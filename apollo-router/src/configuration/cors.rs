@@ -1,16 +1,37 @@
 //! Cross Origin Resource Sharing (CORS configuration)
 // This entire file is license key functionality
 
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::header::HeaderName;
+use http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use http::header::ORIGIN;
 use http::request::Parts;
 use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
 use regex::Regex;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use tower::Layer;
+use tower::Service;
 use tower_http::cors;
-use tower_http::cors::CorsLayer;
+
+use super::ListenAddr;
+
+static ACCESS_CONTROL_REQUEST_PRIVATE_NETWORK: HeaderName =
+    HeaderName::from_static("access-control-request-private-network");
+static ACCESS_CONTROL_ALLOW_PRIVATE_NETWORK: HeaderName =
+    HeaderName::from_static("access-control-allow-private-network");
 
 /// Cross origin request configuration.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
@@ -59,6 +80,68 @@ pub(crate) struct Cors {
     /// Allowed request methods. Defaults to GET, POST, OPTIONS.
     #[serde(default = "default_cors_methods")]
     pub(crate) methods: Vec<String>,
+
+    /// How long browsers can cache the result of a preflight request, with the
+    /// `Access-Control-Max-Age` header. Not sent by default.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) max_age: Option<Duration>,
+
+    /// Set to true to allow requests from public websites to a router on a private network, by
+    /// answering preflight requests with `Access-Control-Allow-Private-Network`.
+    #[serde(default)]
+    pub(crate) allow_private_network: bool,
+
+    /// Policies for specific origins, overriding the settings above.
+    /// The first policy matching the origin of a request applies, the settings above apply to
+    /// origins matching no policy.
+    #[serde(default)]
+    pub(crate) policies: Vec<CorsPolicy>,
+
+    /// Policies of the endpoints of other listen addresses than the supergraph one, such as the
+    /// health check or metrics endpoints. These endpoints only answer cross origin requests
+    /// from origins matching a policy of their listen address.
+    #[serde(default)]
+    pub(crate) listeners: HashMap<ListenAddr, Vec<CorsPolicy>>,
+}
+
+/// Cross origin request settings of specific origins. Settings that are not set are the ones of
+/// the `cors` section.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CorsPolicy {
+    /// The origins of the policy.
+    #[serde(default)]
+    pub(crate) origins: Vec<String>,
+
+    /// `Regex`es matching the origins of the policy.
+    #[serde(default)]
+    pub(crate) match_origins: Vec<String>,
+
+    /// Set to true to add the `Access-Control-Allow-Credentials` header.
+    #[serde(default)]
+    pub(crate) allow_credentials: Option<bool>,
+
+    /// The headers to allow.
+    #[serde(default)]
+    pub(crate) allow_headers: Option<Vec<String>>,
+
+    /// Which response headers should be made available to scripts running in the browser.
+    #[serde(default)]
+    pub(crate) expose_headers: Option<Vec<String>>,
+
+    /// Allowed request methods.
+    #[serde(default)]
+    pub(crate) methods: Option<Vec<String>>,
+
+    /// How long browsers can cache the result of a preflight request.
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) max_age: Option<Duration>,
+
+    /// Set to true to allow requests to a router on a private network.
+    #[serde(default)]
+    pub(crate) allow_private_network: Option<bool>,
 }
 
 impl Default for Cors {
@@ -71,6 +154,10 @@ impl Default for Cors {
             allow_headers: Default::default(),
             expose_headers: Default::default(),
             match_origins: Default::default(),
+            max_age: Default::default(),
+            allow_private_network: Default::default(),
+            policies: Default::default(),
+            listeners: Default::default(),
         }
     }
 }
@@ -96,21 +183,88 @@ impl Cors {
         origins: Option<Vec<String>>,
         match_origins: Option<Vec<String>>,
         methods: Option<Vec<String>>,
+        max_age: Option<Duration>,
+        allow_private_network: Option<bool>,
+        policies: Option<Vec<CorsPolicy>>,
+        listeners: Option<HashMap<ListenAddr, Vec<CorsPolicy>>>,
     ) -> Self {
         Self {
             expose_headers,
             match_origins,
+            max_age,
             origins: origins.unwrap_or_else(default_origins),
             methods: methods.unwrap_or_else(default_cors_methods),
             allow_any_origin: allow_any_origin.unwrap_or_default(),
             allow_credentials: allow_credentials.unwrap_or_default(),
             allow_headers: allow_headers.unwrap_or_default(),
+            allow_private_network: allow_private_network.unwrap_or_default(),
+            policies: policies.unwrap_or_default(),
+            listeners: listeners.unwrap_or_default(),
         }
     }
 }
 
 impl Cors {
+    /// Returns the layer of the supergraph listener
     pub(crate) fn into_layer(self) -> Result<CorsLayer, String> {
+        let policies = self
+            .policies
+            .iter()
+            .map(|policy| self.policy(policy))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fallback = PolicyLayer::new(self)?;
+        Ok(CorsLayer {
+            policies: Arc::new(policies),
+            fallback: Some(Arc::new(fallback)),
+        })
+    }
+
+    /// Returns the layer of another listener, if it has policies
+    pub(crate) fn listener_layer(&self, listen: &ListenAddr) -> Result<Option<CorsLayer>, String> {
+        match self.listeners.get(listen) {
+            Some(policies) if !policies.is_empty() => {
+                let policies = policies
+                    .iter()
+                    .map(|policy| self.policy(policy))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some(CorsLayer {
+                    policies: Arc::new(policies),
+                    fallback: None,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Resolves the settings of a policy, inheriting the ones it does not set
+    fn policy(&self, policy: &CorsPolicy) -> Result<PolicyLayer, String> {
+        PolicyLayer::new(Cors {
+            allow_any_origin: false,
+            allow_credentials: policy.allow_credentials.unwrap_or(self.allow_credentials),
+            allow_headers: policy
+                .allow_headers
+                .clone()
+                .unwrap_or_else(|| self.allow_headers.clone()),
+            expose_headers: policy
+                .expose_headers
+                .clone()
+                .or_else(|| self.expose_headers.clone()),
+            origins: policy.origins.clone(),
+            match_origins: Some(policy.match_origins.clone()),
+            methods: policy
+                .methods
+                .clone()
+                .unwrap_or_else(|| self.methods.clone()),
+            max_age: policy.max_age.or(self.max_age),
+            allow_private_network: policy
+                .allow_private_network
+                .unwrap_or(self.allow_private_network),
+            policies: Vec::new(),
+            listeners: HashMap::new(),
+        })
+    }
+
+    fn into_tower_layer(self) -> Result<cors::CorsLayer, String> {
         // Ensure configuration is valid before creating CorsLayer
 
         self.ensure_usable_cors_rules()?;
@@ -125,7 +279,7 @@ impl Cors {
                     .ok()
             }))
         };
-        let cors = CorsLayer::new()
+        let mut cors = cors::CorsLayer::new()
             .vary([])
             .allow_credentials(self.allow_credentials)
             .allow_headers(allow_headers)
@@ -148,6 +302,9 @@ impl Cors {
                         .ok()
                 },
             )));
+        if let Some(max_age) = self.max_age {
+            cors = cors.max_age(max_age);
+        }
 
         if self.allow_any_origin {
             Ok(cors.allow_origin(cors::Any))
@@ -220,3 +377,136 @@ impl Cors {
         Ok(())
     }
 }
+
+/// The settings of a policy and the origins it applies to
+#[derive(Debug)]
+struct PolicyLayer {
+    allow_any_origin: bool,
+    origins: Vec<String>,
+    match_origins: Vec<Regex>,
+    allow_private_network: bool,
+    layer: cors::CorsLayer,
+}
+
+impl PolicyLayer {
+    fn new(cors: Cors) -> Result<Self, String> {
+        // invalid regexes are logged when creating the tower-http layer
+        let match_origins = cors
+            .match_origins
+            .iter()
+            .flatten()
+            .filter_map(|regex| Regex::from_str(regex).ok())
+            .collect();
+        Ok(Self {
+            allow_any_origin: cors.allow_any_origin,
+            origins: cors.origins.clone(),
+            match_origins,
+            allow_private_network: cors.allow_private_network,
+            layer: cors.into_tower_layer()?,
+        })
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        self.allow_any_origin
+            || self.origins.iter().any(|o| o == origin)
+            || self
+                .match_origins
+                .iter()
+                .any(|regex| regex.is_match(origin))
+    }
+}
+
+/// Applies the policy matching the origin of each request
+#[derive(Clone, Debug)]
+pub(crate) struct CorsLayer {
+    policies: Arc<Vec<PolicyLayer>>,
+    /// Policy of the requests matching no policy. Without it, they are passed through
+    fallback: Option<Arc<PolicyLayer>>,
+}
+
+impl CorsLayer {
+    /// Whether a request from the origin is allowed, for the requests that are not subject to the
+    /// CORS checks of browsers, such as WebSocket upgrades
+    pub(crate) fn allows_origin(&self, origin: &str) -> bool {
+        self.policies.iter().any(|policy| policy.matches(origin))
+            || self
+                .fallback
+                .as_ref()
+                .map_or(true, |fallback| fallback.matches(origin))
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CorsService<S> {
+    inner: S,
+    layer: CorsLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CorsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // the service that was polled ready is the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let policy = request
+            .headers()
+            .get(ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+            .and_then(|origin| {
+                self.layer
+                    .policies
+                    .iter()
+                    .find(|policy| policy.matches(origin))
+            })
+            .or(self.layer.fallback.as_deref());
+        let policy = match policy {
+            Some(policy) => policy,
+            None => return inner.call(request).boxed(),
+        };
+
+        let private_network = policy.allow_private_network
+            && request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .get(&ACCESS_CONTROL_REQUEST_PRIVATE_NETWORK)
+                .map(|value| value == "true")
+                .unwrap_or_default();
+        let response = policy.layer.layer(inner).call(request);
+        async move {
+            let mut response = response.await?;
+            // only allowed origins get the header
+            if private_network && response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+                response.headers_mut().insert(
+                    &ACCESS_CONTROL_ALLOW_PRIVATE_NETWORK,
+                    HeaderValue::from_static("true"),
+                );
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+}
@@ -1,16 +1,26 @@
-//! Environment variable expansion in the configuration file
+//! Environment variable, file and encrypted secret expansion in the configuration file
 // This entire file is license key functionality
 
 use std::env;
 use std::env::VarError;
 use std::fs;
+use std::path::PathBuf;
 
+use once_cell::sync::Lazy;
 use proteus::Parser;
 use proteus::TransformBuilder;
+use regex::Regex;
 use serde_json::Value;
 
+use super::secrets::Kms;
+use super::vault;
 use super::ConfigurationError;
 
+const REDACTED: &str = "<redacted>";
+
+static FILE_VARIABLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$\{file\.([^}]+?)(?::-[^}]*)?\}").expect("valid regex"));
+
 #[derive(buildstructor::Builder)]
 pub(crate) struct Expansion {
     prefix: Option<String>,
@@ -28,7 +38,7 @@ impl Expansion {
         };
         let supported_expansion_modes = match env::var("APOLLO_ROUTER_CONFIG_SUPPORTED_MODES") {
             Ok(v) => v,
-            Err(VarError::NotPresent) => "env,file".to_string(),
+            Err(VarError::NotPresent) => "env,file,aws_kms,gcp_kms,vault,vault_secret".to_string(),
             Err(VarError::NotUnicode(_)) => Err(ConfigurationError::InvalidExpansionModeConfig)?,
         };
         let supported_modes = supported_expansion_modes
@@ -79,6 +89,22 @@ impl Expansion {
                     }
                 });
             }
+            if let Some(variable) = key.strip_prefix("vault_secret.") {
+                return vault::read_field(variable).map(Some).map_err(|cause| {
+                    ConfigurationError::CannotExpandVariable {
+                        key: key.to_string(),
+                        cause,
+                    }
+                });
+            }
+            if let Some((kms, variable)) = Kms::from_variable(key) {
+                return kms.decrypt(variable).map(Some).map_err(|cause| {
+                    ConfigurationError::CannotExpandVariable {
+                        key: key.to_string(),
+                        cause,
+                    }
+                });
+            }
             Err(ConfigurationError::InvalidExpansionModeConfig)
         }
     }
@@ -151,6 +177,57 @@ fn visit(value: &mut Value, expansion: &Expansion) -> Result<(), ConfigurationEr
     Ok(())
 }
 
+/// Replaces the values that were expanded from variables, so that the configuration can be
+/// displayed without leaking secrets
+pub(crate) fn redact_expanded_values(configuration: &Value, expanded: &Value) -> Value {
+    match (configuration, expanded) {
+        (Value::String(value), expanded) => {
+            if expanded.as_str() == Some(value.as_str()) {
+                expanded.clone()
+            } else {
+                Value::String(REDACTED.to_string())
+            }
+        }
+        (Value::Array(values), Value::Array(expanded)) => Value::Array(
+            expanded
+                .iter()
+                .enumerate()
+                .map(|(index, expanded)| match values.get(index) {
+                    Some(value) => redact_expanded_values(value, expanded),
+                    None => expanded.clone(),
+                })
+                .collect(),
+        ),
+        (Value::Object(values), Value::Object(expanded)) => Value::Object(
+            expanded
+                .iter()
+                .map(|(key, expanded)| {
+                    let redacted = match values.get(key) {
+                        Some(value) => redact_expanded_values(value, expanded),
+                        None => expanded.clone(),
+                    };
+                    (key.clone(), redacted)
+                })
+                .collect(),
+        ),
+        (_, expanded) => expanded.clone(),
+    }
+}
+
+/// Files referenced by `${file.path}` variables in the configuration
+pub(crate) fn referenced_files(configuration: &str) -> Vec<PathBuf> {
+    FILE_VARIABLE
+        .captures_iter(configuration)
+        .map(|captures| PathBuf::from(&captures[1]))
+        .collect()
+}
+
+/// Whether the configuration has `${vault_secret.path:field}` variables, which are expanded again
+/// when the secrets are rotated
+pub(crate) fn references_vault_secrets(configuration: &str) -> bool {
+    configuration.contains("${vault_secret.")
+}
+
 pub(crate) fn coerce(expanded: &str) -> Value {
     match serde_yaml::from_str(expanded) {
         Ok(Value::Bool(b)) => Value::Bool(b),
@@ -165,7 +242,7 @@ mod test {
     use insta::assert_yaml_snapshot;
     use serde_json::json;
 
-    use crate::configuration::expansion::env_defaults;
+    use super::*;
 
     #[test]
     fn test_env_defaults() {
@@ -175,4 +252,47 @@ mod test {
             assert_yaml_snapshot!(value);
         })
     }
+
+    #[test]
+    fn test_redact_expanded_values() {
+        let configuration = json!({
+            "password": "${env.PASSWORD}",
+            "url": "http://${env.HOST}/graphql",
+            "headers": ["x-static", "${file./run/secrets/header}"],
+            "port": "${env.PORT:-4000}",
+            "name": "static",
+        });
+        let expanded = json!({
+            "password": "secret",
+            "url": "http://example.com/graphql",
+            "headers": ["x-static", "x-secret"],
+            "port": 4000,
+            "name": "static",
+            "defaulted": true,
+        });
+        assert_eq!(
+            redact_expanded_values(&configuration, &expanded),
+            json!({
+                "password": "<redacted>",
+                "url": "<redacted>",
+                "headers": ["x-static", "<redacted>"],
+                "port": "<redacted>",
+                "name": "static",
+                "defaulted": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_referenced_files() {
+        assert_eq!(
+            referenced_files(
+                "password: ${file./run/secrets/redis}\nca: \"${file.ca.crt:-}\"\nkey: ${env.KEY}"
+            ),
+            vec![
+                std::path::PathBuf::from("/run/secrets/redis"),
+                std::path::PathBuf::from("ca.crt")
+            ]
+        );
+    }
 }
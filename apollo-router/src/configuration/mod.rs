@@ -3,10 +3,14 @@
 pub(crate) mod cors;
 mod expansion;
 mod experimental;
+mod overlay;
+pub(crate) mod rest;
 mod schema;
+mod secrets;
 #[cfg(test)]
 mod tests;
 mod upgrade;
+mod vault;
 mod yaml;
 
 use std::collections::HashMap;
@@ -15,9 +19,13 @@ use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::str::FromStr;
+use std::time::Duration;
 
 use derivative::Derivative;
 use displaydoc::Display;
+use http::header::HeaderName;
+use http::HeaderMap;
+use http::HeaderValue;
 use itertools::Itertools;
 use schemars::gen::SchemaGenerator;
 use schemars::schema::ObjectValidation;
@@ -32,13 +40,19 @@ use serde_json::Value;
 use thiserror::Error;
 
 use self::cors::Cors;
+pub(crate) use self::expansion::referenced_files;
+pub(crate) use self::expansion::references_vault_secrets;
 use self::expansion::Expansion;
 pub(crate) use self::experimental::print_all_experimental_conf;
+pub(crate) use self::overlay::merge_configurations;
+use self::rest::Rest;
 pub(crate) use self::schema::generate_config_schema;
 pub(crate) use self::schema::generate_upgrade;
+pub(crate) use self::vault::secret_rotations;
 use crate::cache::DEFAULT_CACHE_CAPACITY;
 use crate::configuration::schema::Mode;
 use crate::executable::APOLLO_ROUTER_DEV_ENV;
+use crate::introspection::DEFAULT_INTROSPECTION_CACHE_CAPACITY;
 use crate::plugin::plugins;
 
 /// Configuration error.
@@ -85,6 +99,10 @@ pub struct Configuration {
     #[serde(skip)]
     pub(crate) validated_yaml: Option<Value>,
 
+    /// The validated configuration, with the values of expanded variables redacted.
+    #[serde(skip)]
+    pub(crate) redacted_yaml: Option<Value>,
+
     /// Configuration options pertaining to the http server component.
     #[serde(default)]
     pub(crate) server: Server,
@@ -94,6 +112,18 @@ pub struct Configuration {
     #[serde(rename = "health-check")]
     pub(crate) health_check: HealthCheck,
 
+    /// Admin API configuration
+    #[serde(default)]
+    pub(crate) admin: Admin,
+
+    /// GraphQL over gRPC server configuration
+    #[serde(default)]
+    pub(crate) grpc: Grpc,
+
+    /// REST routes executing GraphQL operations
+    #[serde(default)]
+    pub(crate) rest: Rest,
+
     /// Sandbox configuration
     #[serde(default)]
     pub(crate) sandbox: Sandbox,
@@ -113,6 +143,10 @@ pub struct Configuration {
     #[serde(default)]
     pub(crate) tls: Tls,
 
+    /// Redaction of sensitive data in logs, traces, co-processor calls and request captures
+    #[serde(default)]
+    pub(crate) redaction: Redaction,
+
     /// Plugin configuration
     #[serde(default)]
     plugins: UserPlugins,
@@ -138,6 +172,12 @@ impl<'de> serde::Deserialize<'de> for Configuration {
             #[serde(rename = "health-check")]
             health_check: HealthCheck,
             #[serde(default)]
+            admin: Admin,
+            #[serde(default)]
+            grpc: Grpc,
+            #[serde(default)]
+            rest: Rest,
+            #[serde(default)]
             sandbox: Sandbox,
             #[serde(default)]
             homepage: Homepage,
@@ -152,12 +192,17 @@ impl<'de> serde::Deserialize<'de> for Configuration {
             apollo_plugins: ApolloPlugins,
             #[serde(default)]
             tls: Tls,
+            #[serde(default)]
+            redaction: Redaction,
         }
         let ad_hoc: AdHocConfiguration = serde::Deserialize::deserialize(deserializer)?;
 
         Configuration::builder()
             .server(ad_hoc.server)
             .health_check(ad_hoc.health_check)
+            .admin(ad_hoc.admin)
+            .grpc(ad_hoc.grpc)
+            .rest(ad_hoc.rest)
             .sandbox(ad_hoc.sandbox)
             .homepage(ad_hoc.homepage)
             .supergraph(ad_hoc.supergraph)
@@ -165,6 +210,7 @@ impl<'de> serde::Deserialize<'de> for Configuration {
             .plugins(ad_hoc.plugins.plugins.unwrap_or_default())
             .apollo_plugins(ad_hoc.apollo_plugins.plugins)
             .tls(ad_hoc.tls)
+            .redaction(ad_hoc.redaction)
             .build()
             .map_err(|e| serde::de::Error::custom(e.to_string()))
     }
@@ -190,6 +236,9 @@ impl Configuration {
         server: Option<Server>,
         supergraph: Option<Supergraph>,
         health_check: Option<HealthCheck>,
+        admin: Option<Admin>,
+        grpc: Option<Grpc>,
+        rest: Option<Rest>,
         sandbox: Option<Sandbox>,
         homepage: Option<Homepage>,
         cors: Option<Cors>,
@@ -197,12 +246,17 @@ impl Configuration {
         apollo_plugins: Map<String, Value>,
         dev: Option<bool>,
         tls: Option<Tls>,
+        redaction: Option<Redaction>,
     ) -> Result<Self, ConfigurationError> {
         let mut conf = Self {
             validated_yaml: Default::default(),
+            redacted_yaml: Default::default(),
             server: server.unwrap_or_default(),
             supergraph: supergraph.unwrap_or_default(),
             health_check: health_check.unwrap_or_default(),
+            admin: admin.unwrap_or_default(),
+            grpc: grpc.unwrap_or_default(),
+            rest: rest.unwrap_or_default(),
             sandbox: sandbox.unwrap_or_default(),
             homepage: homepage.unwrap_or_default(),
             cors: cors.unwrap_or_default(),
@@ -213,6 +267,7 @@ impl Configuration {
                 plugins: apollo_plugins,
             },
             tls: tls.unwrap_or_default(),
+            redaction: redaction.unwrap_or_default(),
         };
         if dev.unwrap_or_default()
             || std::env::var(APOLLO_ROUTER_DEV_ENV).ok().as_deref() == Some("true")
@@ -328,6 +383,9 @@ impl Configuration {
         server: Option<Server>,
         supergraph: Option<Supergraph>,
         health_check: Option<HealthCheck>,
+        admin: Option<Admin>,
+        grpc: Option<Grpc>,
+        rest: Option<Rest>,
         sandbox: Option<Sandbox>,
         homepage: Option<Homepage>,
         cors: Option<Cors>,
@@ -335,12 +393,17 @@ impl Configuration {
         apollo_plugins: Map<String, Value>,
         dev: Option<bool>,
         tls: Option<Tls>,
+        redaction: Option<Redaction>,
     ) -> Result<Self, ConfigurationError> {
         let mut configuration = Self {
             validated_yaml: Default::default(),
+            redacted_yaml: Default::default(),
             server: server.unwrap_or_default(),
             supergraph: supergraph.unwrap_or_else(|| Supergraph::fake_builder().build()),
             health_check: health_check.unwrap_or_else(|| HealthCheck::fake_builder().build()),
+            admin: admin.unwrap_or_else(|| Admin::fake_builder().build()),
+            grpc: grpc.unwrap_or_else(|| Grpc::fake_builder().build()),
+            rest: rest.unwrap_or_default(),
             sandbox: sandbox.unwrap_or_else(|| Sandbox::fake_builder().build()),
             homepage: homepage.unwrap_or_else(|| Homepage::fake_builder().build()),
             cors: cors.unwrap_or_default(),
@@ -351,6 +414,7 @@ impl Configuration {
                 plugins: apollo_plugins,
             },
             tls: tls.unwrap_or_default(),
+            redaction: redaction.unwrap_or_default(),
         };
         if dev.unwrap_or_default()
             || std::env::var(APOLLO_ROUTER_DEV_ENV).ok().as_deref() == Some("true")
@@ -363,6 +427,45 @@ impl Configuration {
 }
 
 impl Configuration {
+    /// Validates a YAML configuration document, without starting the router.
+    ///
+    /// On top of the validation against the configuration schema, the configuration of each
+    /// plugin is deserialized, so that the errors that would only be found when creating the
+    /// plugins are reported with the path of the plugin configuration.
+    pub fn validate_yaml(yaml: &str) -> Result<Configuration, ConfigurationError> {
+        let configuration: Configuration = yaml.parse()?;
+        let errors = configuration
+            .plugins()
+            .into_iter()
+            .filter_map(|(name, config)| {
+                let path = match name.strip_prefix(APOLLO_PLUGIN_PREFIX) {
+                    Some(apollo_plugin) => apollo_plugin.to_string(),
+                    None => format!("plugins.{name}"),
+                };
+                plugins()
+                    .find(|factory| factory.name == name)?
+                    .validate_configuration(&config)
+                    .err()
+                    .map(|error| format!("{path}: {error}"))
+            })
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(configuration)
+        } else {
+            Err(ConfigurationError::InvalidConfiguration {
+                message: "plugin configuration had errors",
+                error: format!("\n{}", errors.join("\n")),
+            })
+        }
+    }
+
+    /// The JSON schema of the configuration, including the configuration of all the registered
+    /// plugins. It can be used by editors to validate and complete configuration files.
+    pub fn json_schema() -> Value {
+        serde_json::to_value(generate_config_schema()).expect("the schema must serialize")
+    }
+
     pub(crate) fn validate(self) -> Result<Self, ConfigurationError> {
         // Sandbox and Homepage cannot be both enabled
         if self.sandbox.enabled && self.homepage.enabled {
@@ -378,6 +481,40 @@ impl Configuration {
                 error: "sandbox needs introspection to be enabled".to_string(),
             });
         }
+        if let Some(canary) = &self.supergraph.canary {
+            if !(0.0..=100.0).contains(&canary.percentage) {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'supergraph.canary.percentage' configuration",
+                    error: format!(
+                        "'{}' is invalid, it must be between 0 and 100",
+                        canary.percentage
+                    ),
+                });
+            }
+        }
+        if self.admin.enabled
+            && self
+                .admin
+                .token
+                .as_deref()
+                .map_or(true, |token| token.is_empty())
+        {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "invalid 'admin' configuration",
+                error: "the admin API requires a token".to_string(),
+            });
+        }
+        for (label, selector) in &self.supergraph.progressive_override.labels {
+            if !(0.0..=100.0).contains(&selector.percentage) {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'supergraph.progressive_override' configuration",
+                    error: format!(
+                        "the percentage of the label '{label}', '{}', is invalid, it must be between 0 and 100",
+                        selector.percentage
+                    ),
+                });
+            }
+        }
         if !self.supergraph.path.starts_with('/') {
             return Err(ConfigurationError::InvalidConfiguration {
             message: "invalid 'server.graphql_path' configuration",
@@ -409,6 +546,29 @@ impl Configuration {
             );
         }
 
+        if let Err(error) = self.server.security_headers.header_map() {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "invalid 'server.security_headers' configuration",
+                error,
+            });
+        }
+
+        if let Err(error) = self.rest.validate(&self.supergraph.path) {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "invalid 'rest' configuration",
+                error,
+            });
+        }
+
+        for pattern in &self.redaction.headers {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'redaction.headers' configuration",
+                    error: format!("'{pattern}' is not a valid regular expression: {e}"),
+                });
+            }
+        }
+
         Ok(self)
     }
 }
@@ -516,6 +676,14 @@ pub(crate) struct Supergraph {
     #[serde(default = "default_graphql_introspection")]
     pub(crate) introspection: bool,
 
+    /// Restricts introspection to some requests, when it is enabled
+    #[serde(default)]
+    pub(crate) introspection_access: IntrospectionAccess,
+
+    /// Caching and limits of introspection queries
+    #[serde(default)]
+    pub(crate) introspection_execution: IntrospectionExecution,
+
     /// Set to false to disable defer support
     #[serde(default = "default_defer_support")]
     pub(crate) defer_support: bool,
@@ -527,6 +695,34 @@ pub(crate) struct Supergraph {
     /// Query planning options
     #[serde(default)]
     pub(crate) query_planning: QueryPlanning,
+
+    /// Serve a part of the traffic with a new supergraph schema
+    #[serde(default)]
+    pub(crate) canary: Option<Canary>,
+
+    /// Webhook notified when the supergraph schema changes
+    #[serde(default)]
+    pub(crate) schema_change_webhook: Option<SchemaChangeWebhook>,
+
+    /// Validation of the variables of requests
+    #[serde(default)]
+    pub(crate) variable_validation: VariableValidation,
+
+    /// Batches of operations sent as a JSON array in a single request
+    #[serde(default)]
+    pub(crate) batching: Batching,
+
+    /// Progressive override of fields, by override label
+    #[serde(default)]
+    pub(crate) progressive_override: ProgressiveOverride,
+
+    /// Queries and mutations sent over WebSocket connections
+    #[serde(default)]
+    pub(crate) websocket: WebSocket,
+
+    /// Persisted operations referenced by the `documentId` of requests
+    #[serde(default)]
+    pub(crate) persisted_operations: PersistedOperations,
 }
 
 fn default_defer_support() -> bool {
@@ -540,17 +736,35 @@ impl Supergraph {
         listen: Option<ListenAddr>,
         path: Option<String>,
         introspection: Option<bool>,
+        introspection_access: Option<IntrospectionAccess>,
+        introspection_execution: Option<IntrospectionExecution>,
         defer_support: Option<bool>,
         apq: Option<Apq>,
         query_planning: Option<QueryPlanning>,
+        canary: Option<Canary>,
+        schema_change_webhook: Option<SchemaChangeWebhook>,
+        variable_validation: Option<VariableValidation>,
+        batching: Option<Batching>,
+        progressive_override: Option<ProgressiveOverride>,
+        websocket: Option<WebSocket>,
+        persisted_operations: Option<PersistedOperations>,
     ) -> Self {
         Self {
             listen: listen.unwrap_or_else(default_graphql_listen),
             path: path.unwrap_or_else(default_graphql_path),
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
+            introspection_access: introspection_access.unwrap_or_default(),
+            introspection_execution: introspection_execution.unwrap_or_default(),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
             apq: apq.unwrap_or_default(),
             query_planning: query_planning.unwrap_or_default(),
+            canary,
+            schema_change_webhook,
+            variable_validation: variable_validation.unwrap_or_default(),
+            batching: batching.unwrap_or_default(),
+            progressive_override: progressive_override.unwrap_or_default(),
+            websocket: websocket.unwrap_or_default(),
+            persisted_operations: persisted_operations.unwrap_or_default(),
         }
     }
 }
@@ -563,17 +777,35 @@ impl Supergraph {
         listen: Option<ListenAddr>,
         path: Option<String>,
         introspection: Option<bool>,
+        introspection_access: Option<IntrospectionAccess>,
+        introspection_execution: Option<IntrospectionExecution>,
         defer_support: Option<bool>,
         apq: Option<Apq>,
         query_planning: Option<QueryPlanning>,
+        canary: Option<Canary>,
+        schema_change_webhook: Option<SchemaChangeWebhook>,
+        variable_validation: Option<VariableValidation>,
+        batching: Option<Batching>,
+        progressive_override: Option<ProgressiveOverride>,
+        websocket: Option<WebSocket>,
+        persisted_operations: Option<PersistedOperations>,
     ) -> Self {
         Self {
             listen: listen.unwrap_or_else(test_listen),
             path: path.unwrap_or_else(default_graphql_path),
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
+            introspection_access: introspection_access.unwrap_or_default(),
+            introspection_execution: introspection_execution.unwrap_or_default(),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
             apq: apq.unwrap_or_default(),
             query_planning: query_planning.unwrap_or_default(),
+            canary,
+            schema_change_webhook,
+            variable_validation: variable_validation.unwrap_or_default(),
+            batching: batching.unwrap_or_default(),
+            progressive_override: progressive_override.unwrap_or_default(),
+            websocket: websocket.unwrap_or_default(),
+            persisted_operations: persisted_operations.unwrap_or_default(),
         }
     }
 }
@@ -584,6 +816,243 @@ impl Default for Supergraph {
     }
 }
 
+/// Queries and mutations sent over WebSocket connections, with the `graphql-transport-ws`
+/// protocol
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WebSocket {
+    /// Accept WebSocket connections on the GraphQL path
+    /// default: false
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// The longest delay between the opening of a connection and its `connection_init` message
+    /// Default: 10s
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) connection_init_timeout: Option<Duration>,
+}
+
+/// Persisted operations referenced by the `documentId` of requests, as sent by Relay and Apollo
+/// clients
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PersistedOperations {
+    /// The persisted operation manifests, in the format of Apollo or of Relay, loaded with the
+    /// configuration
+    #[serde(default)]
+    pub(crate) manifests: Vec<std::path::PathBuf>,
+}
+
+/// Batches of operations sent as a JSON array in a single request
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Batching {
+    /// Accept batches of operations
+    /// default: false
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// Maximum number of operations of a batch
+    /// default: 10
+    #[serde(default = "default_batching_max_size")]
+    pub(crate) max_size: usize,
+
+    /// Maximum number of operations of a batch executed at the same time
+    /// default: 4
+    #[serde(default = "default_batching_max_concurrency")]
+    pub(crate) max_concurrency: usize,
+
+    /// Maximum size in bytes of the body of a batch
+    /// default: 2000000
+    #[serde(default = "default_batching_max_body_size")]
+    pub(crate) max_body_size: usize,
+}
+
+fn default_batching_max_size() -> usize {
+    10
+}
+
+fn default_batching_max_concurrency() -> usize {
+    4
+}
+
+fn default_batching_max_body_size() -> usize {
+    2_000_000
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size: default_batching_max_size(),
+            max_concurrency: default_batching_max_concurrency(),
+            max_body_size: default_batching_max_body_size(),
+        }
+    }
+}
+
+/// Validation of the variables of requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VariableValidation {
+    /// Forward the variables to subgraphs without validating them
+    Lenient,
+    /// Reject variables that don't match their type, and ignore unknown variables
+    Standard,
+    /// Reject variables that don't match their type, and unknown variables
+    Strict,
+}
+
+impl Default for VariableValidation {
+    fn default() -> Self {
+        VariableValidation::Standard
+    }
+}
+
+/// Restrictions of introspection to some requests
+///
+/// Introspection is allowed for the requests matching any of the rules, and for all requests if
+/// no rule is set.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct IntrospectionAccess {
+    /// Allow requests having one of these headers, by header name and value
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
+
+    /// Allow requests authenticated with a JWT having one of these claims, by claim name and
+    /// value. A claim that is an array must contain the value
+    #[serde(default)]
+    pub(crate) claims: HashMap<String, Value>,
+
+    /// Allow requests sent from the sandbox served by the router, as told by their `Referer`
+    /// header
+    #[serde(default)]
+    pub(crate) sandbox: bool,
+}
+
+impl IntrospectionAccess {
+    pub(crate) fn is_restricted(&self) -> bool {
+        !self.headers.is_empty() || !self.claims.is_empty() || self.sandbox
+    }
+}
+
+/// Caching and limits of introspection queries
+///
+/// Introspection responses are computed by the query planner, and cached for each version of the
+/// schema.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct IntrospectionExecution {
+    /// Cache configuration
+    #[serde(default = "default_introspection_cache")]
+    pub(crate) experimental_cache: Cache,
+
+    /// Maximum depth of the fields of introspection queries
+    /// default: unlimited
+    #[serde(default)]
+    pub(crate) max_depth: Option<usize>,
+
+    /// Maximum nesting of the `fields`, `inputFields`, `interfaces` and `possibleTypes` fields in
+    /// introspection queries
+    /// default: 3
+    #[serde(default = "default_introspection_max_recursion")]
+    pub(crate) max_recursion: usize,
+}
+
+fn default_introspection_cache() -> Cache {
+    Cache {
+        in_memory: InMemoryCache {
+            limit: DEFAULT_INTROSPECTION_CACHE_CAPACITY,
+        },
+        #[cfg(feature = "experimental_cache")]
+        redis: None,
+    }
+}
+
+fn default_introspection_max_recursion() -> usize {
+    3
+}
+
+impl Default for IntrospectionExecution {
+    fn default() -> Self {
+        Self {
+            experimental_cache: default_introspection_cache(),
+            max_depth: None,
+            max_recursion: default_introspection_max_recursion(),
+        }
+    }
+}
+
+/// Canary rollout of a supergraph schema
+///
+/// The requests sent to the canary schema are planned and executed with it, with their own query
+/// plan cache.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Canary {
+    /// The path of the canary supergraph schema
+    pub(crate) schema_path: std::path::PathBuf,
+
+    /// The percentage of requests sent to the canary schema, between 0 and 100
+    #[serde(default)]
+    pub(crate) percentage: f64,
+
+    /// Requests with this header are sent to the canary schema
+    pub(crate) header: Option<CanaryHeader>,
+}
+
+/// Header selecting the canary schema
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CanaryHeader {
+    /// The name of the header
+    pub(crate) name: String,
+
+    /// The value of the header. If not set, any value selects the canary schema
+    pub(crate) value: Option<String>,
+}
+
+/// Progressive override of fields
+///
+/// Fields taken over with `@override(label: "...")` are resolved by the overriding subgraph for
+/// the requests enabling their label. `percent(<value>)` labels that are not configured enable
+/// themselves for this percentage of requests; other labels are disabled unless configured.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProgressiveOverride {
+    /// Requests enabling override labels, by label
+    #[serde(default)]
+    pub(crate) labels: HashMap<String, OverrideLabel>,
+}
+
+/// Requests enabling an override label
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OverrideLabel {
+    /// The percentage of requests enabling the label, between 0 and 100
+    #[serde(default)]
+    pub(crate) percentage: f64,
+
+    /// Requests with this header enable the label
+    pub(crate) header: Option<CanaryHeader>,
+}
+
+/// Webhook notified when the supergraph schema changes
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SchemaChangeWebhook {
+    /// The URL receiving a POST request with the changes to the schema, in JSON
+    pub(crate) url: url::Url,
+
+    /// The timeout of the request
+    /// Default: 5s
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) timeout: Option<Duration>,
+}
+
 /// Automatic Persisted Queries (APQ) configuration
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -716,6 +1185,35 @@ impl Default for TlsSubgraph {
     }
 }
 
+/// Redaction of sensitive data
+///
+/// Redacted values are replaced with `[REDACTED]` in the logs of requests and responses, in the
+/// variables and headers of traces, in the data sent to co-processors, and in captured requests.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Redaction {
+    /// Names of the variables whose values are redacted. A trailing `*` redacts the variables
+    /// starting with the name, and `*` alone redacts all variables
+    #[serde(default)]
+    pub(crate) variables: Vec<String>,
+
+    /// Regular expressions matching the names of the headers whose values are redacted. Header
+    /// names are lowercase
+    #[serde(default)]
+    pub(crate) headers: Vec<String>,
+
+    /// Paths of the response fields whose values are redacted, relative to `data`. Path elements
+    /// are separated by `/`, and `@` selects all the elements of a list: `users/@/email`
+    #[serde(default)]
+    pub(crate) response_fields: Vec<String>,
+}
+
+impl Redaction {
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.variables.is_empty() || !self.headers.is_empty() || !self.response_fields.is_empty()
+    }
+}
+
 /// Configuration options pertaining to the sandbox page.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -847,6 +1345,198 @@ impl Default for HealthCheck {
     }
 }
 
+/// Admin API, exposing operational actions on its own listener
+#[derive(Clone, Derivative, Deserialize, Serialize, JsonSchema)]
+#[derivative(Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Admin {
+    /// The socket address and port to listen on
+    /// Defaults to 127.0.0.1:8089
+    #[serde(default = "default_admin_listen")]
+    pub(crate) listen: ListenAddr,
+
+    /// Set to true to enable the admin API
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// Bearer token required in the `authorization` header of the admin requests
+    #[serde(default)]
+    #[derivative(Debug = "ignore")]
+    pub(crate) token: Option<String>,
+
+    /// On-demand CPU profiling
+    #[serde(default)]
+    pub(crate) cpu_profiling: CpuProfiling,
+
+    /// Response to the requests received in maintenance mode
+    #[serde(default)]
+    pub(crate) maintenance: MaintenanceResponse,
+}
+
+/// CPU profiles captured on demand by the admin API
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CpuProfiling {
+    /// Set to true to serve CPU profiles on the admin API
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// The longest duration of a profile
+    /// Default: 60s
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) max_duration: Option<Duration>,
+}
+
+/// Response to the requests received in maintenance mode, toggled by the admin API
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MaintenanceResponse {
+    /// The HTTP status code
+    /// Default: 503
+    #[serde(default = "default_maintenance_status_code")]
+    pub(crate) status_code: u16,
+
+    /// The message of the GraphQL error
+    #[serde(default = "default_maintenance_message")]
+    pub(crate) message: String,
+
+    /// The `code` extension of the GraphQL error
+    #[serde(default = "default_maintenance_extension_code")]
+    pub(crate) extension_code: String,
+
+    /// Other extensions of the GraphQL error
+    #[serde(default)]
+    pub(crate) extensions: serde_json::Map<String, serde_json::Value>,
+
+    /// The delay sent in the `retry-after` header, in seconds. The header is not sent if not set
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) retry_after: Option<Duration>,
+}
+
+fn default_maintenance_status_code() -> u16 {
+    503
+}
+
+fn default_maintenance_message() -> String {
+    "the router is in maintenance".to_string()
+}
+
+fn default_maintenance_extension_code() -> String {
+    "MAINTENANCE".to_string()
+}
+
+impl Default for MaintenanceResponse {
+    fn default() -> Self {
+        Self {
+            status_code: default_maintenance_status_code(),
+            message: default_maintenance_message(),
+            extension_code: default_maintenance_extension_code(),
+            extensions: Default::default(),
+            retry_after: None,
+        }
+    }
+}
+
+fn default_admin_listen() -> ListenAddr {
+    SocketAddr::from_str("127.0.0.1:8089").unwrap().into()
+}
+
+#[buildstructor::buildstructor]
+impl Admin {
+    #[builder]
+    pub(crate) fn new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        token: Option<String>,
+        cpu_profiling: Option<CpuProfiling>,
+        maintenance: Option<MaintenanceResponse>,
+    ) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(default_admin_listen),
+            enabled: enabled.unwrap_or_default(),
+            token,
+            cpu_profiling: cpu_profiling.unwrap_or_default(),
+            maintenance: maintenance.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[buildstructor::buildstructor]
+impl Admin {
+    #[builder]
+    pub(crate) fn fake_new(
+        listen: Option<ListenAddr>,
+        enabled: Option<bool>,
+        token: Option<String>,
+        cpu_profiling: Option<CpuProfiling>,
+        maintenance: Option<MaintenanceResponse>,
+    ) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(test_listen),
+            enabled: enabled.unwrap_or_default(),
+            token,
+            cpu_profiling: cpu_profiling.unwrap_or_default(),
+            maintenance: maintenance.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// GraphQL over gRPC server, exposing the supergraph to gRPC clients
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Grpc {
+    /// The socket address and port to listen on
+    /// Defaults to 127.0.0.1:4001
+    #[serde(default = "default_grpc_listen")]
+    pub(crate) listen: ListenAddr,
+
+    /// Set to true to enable the gRPC server
+    #[serde(default)]
+    pub(crate) enabled: bool,
+}
+
+fn default_grpc_listen() -> ListenAddr {
+    SocketAddr::from_str("127.0.0.1:4001").unwrap().into()
+}
+
+#[buildstructor::buildstructor]
+impl Grpc {
+    #[builder]
+    pub(crate) fn new(listen: Option<ListenAddr>, enabled: Option<bool>) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(default_grpc_listen),
+            enabled: enabled.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[buildstructor::buildstructor]
+impl Grpc {
+    #[builder]
+    pub(crate) fn fake_new(listen: Option<ListenAddr>, enabled: Option<bool>) -> Self {
+        Self {
+            listen: listen.unwrap_or_else(test_listen),
+            enabled: enabled.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Grpc {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
 /// Configuration options pertaining to the http server component.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -855,17 +1545,174 @@ pub(crate) struct Server {
     /// default: 4096
     #[serde(default = "default_parser_recursion_limit")]
     pub(crate) experimental_parser_recursion_limit: usize,
+
+    /// Problem details bodies for HTTP errors
+    #[serde(default)]
+    pub(crate) problem_details: ProblemDetails,
+
+    /// Memory budget of the responses waiting to be read by clients
+    #[serde(default)]
+    pub(crate) experimental_response_memory: ResponseMemory,
+
+    /// Security headers added to all the responses of the router
+    #[serde(default)]
+    pub(crate) security_headers: SecurityHeaders,
+
+    /// Limits on the documents of operations, checked when they are parsed
+    #[serde(default)]
+    pub(crate) parser_limits: ParserLimits,
+
+    /// Number of threads parsing and validating queries and formatting responses. Changing it
+    /// requires a restart.
+    /// default: the `APOLLO_ROUTER_COMPUTE_THREADS` environment variable, or the number of CPU cores
+    #[serde(default)]
+    pub(crate) compute_threads: Option<NonZeroUsize>,
 }
 
 #[buildstructor::buildstructor]
 impl Server {
     #[builder]
     #[allow(clippy::too_many_arguments)] // Used through a builder, not directly
-    pub(crate) fn new(parser_recursion_limit: Option<usize>) -> Self {
+    pub(crate) fn new(
+        parser_recursion_limit: Option<usize>,
+        problem_details: Option<ProblemDetails>,
+        response_memory: Option<ResponseMemory>,
+        security_headers: Option<SecurityHeaders>,
+        parser_limits: Option<ParserLimits>,
+        compute_threads: Option<NonZeroUsize>,
+    ) -> Self {
         Self {
             experimental_parser_recursion_limit: parser_recursion_limit
                 .unwrap_or_else(default_parser_recursion_limit),
+            problem_details: problem_details.unwrap_or_default(),
+            experimental_response_memory: response_memory.unwrap_or_default(),
+            security_headers: security_headers.unwrap_or_default(),
+            parser_limits: parser_limits.unwrap_or_default(),
+            compute_threads,
+        }
+    }
+}
+
+/// Problem details (RFC 9457) bodies for HTTP errors that are not GraphQL responses
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProblemDetails {
+    /// Send HTTP errors that are not GraphQL responses as `application/problem+json`
+    /// default: false
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// Overrides of `enabled` by listen address
+    #[serde(default)]
+    pub(crate) listeners: HashMap<ListenAddr, bool>,
+}
+
+impl ProblemDetails {
+    pub(crate) fn enabled_on(&self, listen: &ListenAddr) -> bool {
+        self.listeners.get(listen).copied().unwrap_or(self.enabled)
+    }
+}
+
+/// Memory budget of the responses waiting to be read by clients
+///
+/// Serialized responses and deferred parts are counted against the budget until the client reads
+/// them, so that slow clients cannot make the router run out of memory.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ResponseMemory {
+    /// Number of bytes of responses that can wait to be read by clients. Unlimited if not set
+    #[serde(default)]
+    pub(crate) limit: Option<usize>,
+
+    /// What to do when the budget is exhausted
+    #[serde(default)]
+    pub(crate) mode: ResponseMemoryMode,
+}
+
+/// What to do when the memory budget of responses is exhausted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ResponseMemoryMode {
+    /// Wait for clients to read responses before serializing more of them
+    Backpressure,
+    /// Apply backpressure, and reject new requests while the budget is exhausted
+    Shed,
+}
+
+impl Default for ResponseMemoryMode {
+    fn default() -> Self {
+        ResponseMemoryMode::Backpressure
+    }
+}
+
+/// Limits on the documents of operations, checked when they are parsed, before they are validated
+/// and planned
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ParserLimits {
+    /// Maximum number of tokens of a document, not counting whitespace, comments and commas.
+    /// Unlimited if not set
+    #[serde(default)]
+    pub(crate) max_tokens: Option<usize>,
+
+    /// Maximum number of directives of a document. Unlimited if not set
+    #[serde(default)]
+    pub(crate) max_directives: Option<usize>,
+
+    /// Maximum nesting of fragment spreads: a fragment spread in an operation has a depth of 1,
+    /// and a spread in a fragment spread by an operation has a depth of 2. Unlimited if not set
+    #[serde(default)]
+    pub(crate) max_fragment_spread_depth: Option<usize>,
+}
+
+/// Security headers added to all the responses of the router, including errors and the landing
+/// page. Headers already set on a response are kept
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SecurityHeaders {
+    /// Value of the `Strict-Transport-Security` header, such as `max-age=31536000`
+    #[serde(default)]
+    pub(crate) strict_transport_security: Option<String>,
+
+    /// Send the `X-Content-Type-Options: nosniff` header
+    /// default: false
+    #[serde(default)]
+    pub(crate) content_type_options_nosniff: bool,
+
+    /// Value of the `Referrer-Policy` header, such as `no-referrer`
+    #[serde(default)]
+    pub(crate) referrer_policy: Option<String>,
+
+    /// Other headers, by name
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
+}
+
+impl SecurityHeaders {
+    /// The headers to add to the responses
+    pub(crate) fn header_map(&self) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        let mut insert = |name: &str, value: &str| -> Result<(), String> {
+            let name = HeaderName::try_from(name)
+                .map_err(|e| format!("'{name}' is not a valid header name: {e}"))?;
+            let value = HeaderValue::try_from(value)
+                .map_err(|e| format!("'{value}' is not a valid value for '{name}': {e}"))?;
+            headers.insert(name, value);
+            Ok(())
+        };
+        if let Some(value) = &self.strict_transport_security {
+            insert("strict-transport-security", value)?;
+        }
+        if self.content_type_options_nosniff {
+            insert("x-content-type-options", "nosniff")?;
+        }
+        if let Some(value) = &self.referrer_policy {
+            insert("referrer-policy", value)?;
+        }
+        for (name, value) in &self.headers {
+            insert(name, value)?;
         }
+        Ok(headers)
     }
 }
 
@@ -933,7 +1780,7 @@ fn default_graphql_introspection() -> bool {
     false
 }
 
-fn default_parser_recursion_limit() -> usize {
+pub(crate) fn default_parser_recursion_limit() -> usize {
     // This is `apollo-parser`’s default, which protects against stack overflow
     // but is still very high for "reasonable" queries.
     // https://docs.rs/apollo-parser/0.2.8/src/apollo_parser/parser/mod.rs.html#368
@@ -0,0 +1,115 @@
+//! Merging of configuration files
+//!
+//! A base configuration can be completed by overlays, such as a file per environment. The files
+//! are merged in order: maps are merged key by key, recursively, and any other value of an
+//! overlay, including lists, replaces the value of the previous files.
+
+use serde_yaml::Value;
+
+use super::ConfigurationError;
+
+/// Merges the YAML configuration files, in order of precedence
+pub(crate) fn merge_configurations(
+    configurations: &[String],
+) -> Result<String, ConfigurationError> {
+    // A single file is kept as is, so that validation errors point to its lines
+    if let [configuration] = configurations {
+        return Ok(configuration.clone());
+    }
+
+    let mut merged = Value::Null;
+    for configuration in configurations {
+        let overlay = serde_yaml::from_str(configuration).map_err(|e| {
+            ConfigurationError::InvalidConfiguration {
+                message: "failed to parse yaml",
+                error: e.to_string(),
+            }
+        })?;
+        merge(&mut merged, overlay);
+    }
+    serde_yaml::to_string(&merged).map_err(|e| ConfigurationError::InvalidConfiguration {
+        message: "failed to merge configuration files",
+        error: e.to_string(),
+    })
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        // An empty file does not remove the previous configuration
+        (_, Value::Null) => {}
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_merges_maps_and_replaces_other_values() {
+        let merged = merge_configurations(&[
+            r#"
+supergraph:
+  listen: 127.0.0.1:4000
+  introspection: true
+cors:
+  origins:
+    - https://studio.apollographql.com
+headers:
+  all:
+    request:
+      - propagate:
+          named: x-tenant
+"#
+            .to_string(),
+            r#"
+supergraph:
+  listen: 0.0.0.0:4000
+cors:
+  origins:
+    - https://example.com
+"#
+            .to_string(),
+            String::new(),
+        ])
+        .unwrap();
+
+        let merged: serde_json::Value = serde_yaml::from_str(&merged).unwrap();
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "supergraph": {
+                    "listen": "0.0.0.0:4000",
+                    "introspection": true,
+                },
+                "cors": {
+                    "origins": ["https://example.com"],
+                },
+                "headers": {
+                    "all": {
+                        "request": [{ "propagate": { "named": "x-tenant" } }],
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn it_keeps_a_single_configuration_as_is() {
+        let configuration = "# comment\nsupergraph:\n  introspection: true\n".to_string();
+        assert_eq!(
+            merge_configurations(&[configuration.clone()]).unwrap(),
+            configuration
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! REST routes executing GraphQL operations persisted in the configuration
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// REST routes executing GraphQL operations
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Rest {
+    /// The routes, served on the listener of the GraphQL endpoint
+    #[serde(default)]
+    pub(crate) routes: Vec<RestRoute>,
+}
+
+/// A REST route executing a GraphQL operation
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RestRoute {
+    /// The HTTP method
+    /// Default: GET
+    #[serde(default)]
+    pub(crate) method: RestMethod,
+
+    /// The path template, where the `{name}` segments are parameters, such as `/products/{id}`
+    pub(crate) path: String,
+
+    /// The GraphQL document of the operation
+    pub(crate) operation: String,
+
+    /// The name of the operation, if the document has several operations
+    #[serde(default)]
+    pub(crate) operation_name: Option<String>,
+
+    /// The variables of the operation, read from the request. The parameters of the path template
+    /// are string variables, unless they are declared here
+    #[serde(default)]
+    pub(crate) params: HashMap<String, RestParam>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum RestMethod {
+    #[default]
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+/// A variable of the operation, read from the request
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RestParam {
+    /// Where the parameter is read from
+    #[serde(rename = "in")]
+    pub(crate) location: RestParamLocation,
+
+    /// The name of the parameter in the request, if it is not the name of the variable
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+
+    /// The type of the variable
+    /// Default: string
+    #[serde(rename = "type", default)]
+    pub(crate) kind: RestParamType,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RestParamLocation {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RestParamType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Boolean,
+}
+
+impl RestMethod {
+    pub(crate) fn as_http(self) -> http::Method {
+        match self {
+            RestMethod::Get => http::Method::GET,
+            RestMethod::Post => http::Method::POST,
+            RestMethod::Put => http::Method::PUT,
+            RestMethod::Patch => http::Method::PATCH,
+            RestMethod::Delete => http::Method::DELETE,
+        }
+    }
+}
+
+impl RestParam {
+    /// The name of the parameter in the request
+    pub(crate) fn request_name<'a>(&'a self, variable: &'a str) -> &'a str {
+        self.name.as_deref().unwrap_or(variable)
+    }
+}
+
+impl RestRoute {
+    /// The names of the parameters of the path template
+    pub(crate) fn path_params(&self) -> Vec<&str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .collect()
+    }
+
+    /// The path template in the syntax of the HTTP server, such as `/products/:id`
+    pub(crate) fn server_path(&self) -> String {
+        self.path
+            .split('/')
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|segment| segment.strip_suffix('}'))
+                {
+                    Some(param) => format!(":{param}"),
+                    None => segment.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.path.starts_with('/') {
+            return Err("the path must start with '/'".to_string());
+        }
+        let path_params = self.path_params();
+        for segment in self.path.split('/') {
+            let is_param = segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2;
+            if !is_param && segment.contains(['{', '}', ':', '*']) {
+                return Err(format!(
+                    "the segment '{segment}' is invalid, parameters are whole segments such as '{{id}}'"
+                ));
+            }
+        }
+        if path_params.iter().collect::<HashSet<_>>().len() != path_params.len() {
+            return Err("the path has duplicated parameters".to_string());
+        }
+        for (variable, param) in &self.params {
+            let name = param.request_name(variable);
+            let in_path = path_params.contains(&name);
+            if (param.location == RestParamLocation::Path) != in_path {
+                return Err(format!(
+                    "the parameter '{name}' of the variable '{variable}' must be in the path if and only if it is declared 'in: path'"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rest {
+    /// Checks the routes, which must not overlap with each other or with the GraphQL endpoint
+    pub(crate) fn validate(&self, graphql_path: &str) -> Result<(), String> {
+        let mut routes = HashSet::new();
+        let mut server_paths = HashMap::new();
+        for route in &self.routes {
+            route
+                .validate()
+                .map_err(|error| format!("{} {}: {error}", route.method.as_http(), route.path))?;
+            if route.path == graphql_path {
+                return Err(format!(
+                    "{} {}: the path is the path of the GraphQL endpoint",
+                    route.method.as_http(),
+                    route.path
+                ));
+            }
+            // the names of the parameters do not tell routes apart
+            let template = route
+                .path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "{}"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            if !routes.insert((route.method, template.clone())) {
+                return Err(format!(
+                    "{} {}: the route is declared twice",
+                    route.method.as_http(),
+                    route.path
+                ));
+            }
+            // the routes of the other methods must name their parameters the same way
+            let server_path = server_paths
+                .entry(template)
+                .or_insert_with(|| route.server_path());
+            if *server_path != route.server_path() {
+                return Err(format!(
+                    "{} {}: the parameters must have the same names as in the routes of the other methods",
+                    route.method.as_http(),
+                    route.path
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(path: &str) -> RestRoute {
+        RestRoute {
+            method: RestMethod::Get,
+            path: path.to_string(),
+            operation: "query Product($id: ID!) { product(id: $id) { name } }".to_string(),
+            operation_name: None,
+            params: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn it_converts_path_templates() {
+        let route = route("/stores/{store}/products/{id}");
+        assert_eq!(route.path_params(), vec!["store", "id"]);
+        assert_eq!(route.server_path(), "/stores/:store/products/:id");
+        assert!(route.validate().is_ok());
+    }
+
+    #[test]
+    fn it_rejects_invalid_routes() {
+        assert!(route("products/{id}").validate().is_err());
+        assert!(route("/products/id-{id}").validate().is_err());
+        assert!(route("/products/{id}/{id}").validate().is_err());
+
+        let mut query_param_in_path = route("/products/{id}");
+        query_param_in_path.params.insert(
+            "id".to_string(),
+            RestParam {
+                location: RestParamLocation::Query,
+                name: None,
+                kind: RestParamType::String,
+            },
+        );
+        assert!(query_param_in_path.validate().is_err());
+
+        let rest = Rest {
+            routes: vec![route("/products/{id}"), route("/products/{sku}")],
+        };
+        assert!(rest.validate("/").is_err());
+    }
+}
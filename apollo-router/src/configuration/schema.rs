@@ -15,6 +15,7 @@ use yaml_rust::scanner::Marker;
 
 use super::expansion::coerce;
 use super::expansion::expand_env_variables;
+use super::expansion::redact_expanded_values;
 use super::expansion::Expansion;
 use super::experimental::log_used_experimental_conf;
 use super::plugins;
@@ -262,6 +263,7 @@ pub(crate) fn validate_yaml_configuration(
             ),
         });
     }
+    config.redacted_yaml = Some(redact_expanded_values(&yaml, &expanded_yaml));
     config.validated_yaml = Some(expanded_yaml);
     Ok(config)
 }
@@ -0,0 +1,291 @@
+//! Decryption of the secrets of the configuration file
+//!
+//! `${aws_kms.<ciphertext>}`, `${gcp_kms.<key>:<ciphertext>}` and `${vault.<key>:<ciphertext>}`
+//! variables are decrypted with AWS KMS, Google Cloud KMS or the transit secrets engine of
+//! HashiCorp Vault when the configuration is loaded, so that secrets are only stored encrypted.
+//! Ciphertexts are decrypted once per process: reloading an unchanged configuration doesn't call
+//! the key management service again.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::object_storage::aws_region;
+use crate::object_storage::instance_token;
+use crate::object_storage::sign_aws_request;
+use crate::object_storage::AwsCredentials;
+use crate::object_storage::GCP_TOKEN_URL;
+
+const DECRYPTION_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_VAULT_TRANSIT_MOUNT: &str = "transit";
+
+/// Plaintexts by key management service and ciphertext
+static DECRYPTED: Lazy<Mutex<HashMap<(Kms, String), String>>> = Lazy::new(Default::default);
+
+/// A key management service
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum Kms {
+    Aws,
+    Gcp,
+    Vault,
+}
+
+impl Kms {
+    /// The service of an expansion mode, and the rest of the variable
+    pub(super) fn from_variable(key: &str) -> Option<(Self, &str)> {
+        if let Some(ciphertext) = key.strip_prefix("aws_kms.") {
+            Some((Kms::Aws, ciphertext))
+        } else if let Some(ciphertext) = key.strip_prefix("gcp_kms.") {
+            Some((Kms::Gcp, ciphertext))
+        } else {
+            key.strip_prefix("vault.")
+                .map(|ciphertext| (Kms::Vault, ciphertext))
+        }
+    }
+
+    /// Decrypts a ciphertext, waiting for the key management service
+    pub(super) fn decrypt(&self, ciphertext: &str) -> Result<String, String> {
+        let cache_key = (*self, ciphertext.to_string());
+        if let Some(plaintext) = DECRYPTED
+            .lock()
+            .expect("the lock is not held across panics; qed")
+            .get(&cache_key)
+        {
+            return Ok(plaintext.clone());
+        }
+
+        let kms = *self;
+        let request = ciphertext.to_string();
+        let plaintext = block_on(async move { kms.call(&request).await })?;
+
+        DECRYPTED
+            .lock()
+            .expect("the lock is not held across panics; qed")
+            .insert(cache_key, plaintext.clone());
+        Ok(plaintext)
+    }
+
+    async fn call(&self, ciphertext: &str) -> Result<String, String> {
+        let client = reqwest::Client::builder()
+            .timeout(DECRYPTION_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let plaintext = match self {
+            Kms::Aws => aws_kms_decrypt(&client, ciphertext).await?,
+            Kms::Gcp => gcp_kms_decrypt(&client, ciphertext).await?,
+            Kms::Vault => vault_decrypt(&client, &Vault::from_env()?, ciphertext).await?,
+        };
+        let plaintext =
+            base64::decode(plaintext).map_err(|e| format!("invalid plaintext encoding: {e}"))?;
+        String::from_utf8(plaintext).map_err(|_| "the plaintext is not UTF-8".to_string())
+    }
+}
+
+/// Waits for a future while the configuration is loaded. The configuration is loaded
+/// synchronously, but possibly on a thread of the Tokio runtime, which cannot block on a future
+pub(super) fn block_on<T: Send + 'static>(
+    future: impl Future<Output = Result<T, String>> + Send + 'static,
+) -> Result<T, String> {
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?
+            .block_on(future)
+    })
+    .join()
+    .map_err(|_| "the thread loading the secret panicked".to_string())?
+}
+
+/// Splits `<key>:<ciphertext>`
+fn key_and_ciphertext(variable: &str) -> Result<(&str, &str), String> {
+    variable
+        .split_once(':')
+        .ok_or_else(|| "expected the key and the ciphertext, separated by ':'".to_string())
+}
+
+pub(super) async fn send<T: for<'de> Deserialize<'de>>(
+    request: reqwest::RequestBuilder,
+) -> Result<T, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("unexpected status {status}: {body}"));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
+
+/// Decrypts with AWS KMS, authenticated with the credentials of the environment or the role of
+/// the instance. The ciphertext contains the key
+async fn aws_kms_decrypt(client: &reqwest::Client, ciphertext: &str) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Decrypted {
+        #[serde(rename = "Plaintext")]
+        plaintext: String,
+    }
+
+    let region = aws_region();
+    let url =
+        Url::parse(&format!("https://kms.{region}.amazonaws.com/")).map_err(|e| e.to_string())?;
+    let credentials = AwsCredentials::load(client)
+        .await
+        .ok_or_else(|| "no AWS credentials found".to_string())?;
+    let payload = json!({ "CiphertextBlob": ciphertext }).to_string();
+    let headers = sign_aws_request(
+        "POST",
+        &url,
+        ("kms", &region),
+        vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("x-amz-target", "TrentService.Decrypt".to_string()),
+        ],
+        payload.as_bytes(),
+        &credentials,
+        SystemTime::now(),
+    );
+    let mut request = client.post(url).body(payload);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    Ok(send::<Decrypted>(request).await?.plaintext)
+}
+
+/// Decrypts with Google Cloud KMS, authenticated with the service account of the instance. The
+/// key is the resource name of the key, `projects/<project>/locations/<location>/keyRings/<key
+/// ring>/cryptoKeys/<key>`
+async fn gcp_kms_decrypt(client: &reqwest::Client, variable: &str) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Decrypted {
+        plaintext: String,
+    }
+
+    let (key, ciphertext) = key_and_ciphertext(variable)?;
+    let token = instance_token(client, GCP_TOKEN_URL, ("Metadata-Flavor", "Google"))
+        .await
+        .ok_or_else(|| "no Google Cloud credentials found".to_string())?;
+    let request = client
+        .post(format!("https://cloudkms.googleapis.com/v1/{key}:decrypt"))
+        .bearer_auth(token)
+        .json(&json!({ "ciphertext": ciphertext }));
+    Ok(send::<Decrypted>(request).await?.plaintext)
+}
+
+/// Address and token of Vault, and mount of its transit secrets engine
+struct Vault {
+    address: String,
+    token: String,
+    mount: String,
+}
+
+impl Vault {
+    /// Vault at `VAULT_ADDR` with `VAULT_TOKEN`. The engine is mounted at
+    /// `VAULT_TRANSIT_MOUNT`, `transit` by default
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            address: std::env::var("VAULT_ADDR")
+                .map_err(|_| "VAULT_ADDR is not set".to_string())?,
+            token: std::env::var("VAULT_TOKEN")
+                .map_err(|_| "VAULT_TOKEN is not set".to_string())?,
+            mount: std::env::var("VAULT_TRANSIT_MOUNT")
+                .unwrap_or_else(|_| DEFAULT_VAULT_TRANSIT_MOUNT.to_string()),
+        })
+    }
+}
+
+/// Decrypts with the transit secrets engine of Vault
+async fn vault_decrypt(
+    client: &reqwest::Client,
+    vault: &Vault,
+    variable: &str,
+) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Decrypted {
+        data: Data,
+    }
+    #[derive(Deserialize)]
+    struct Data {
+        plaintext: String,
+    }
+
+    let (key, ciphertext) = key_and_ciphertext(variable)?;
+    let request = client
+        .post(format!(
+            "{}/v1/{}/decrypt/{key}",
+            vault.address.trim_end_matches('/'),
+            vault.mount
+        ))
+        .header("X-Vault-Token", &vault.token)
+        .json(&json!({ "ciphertext": ciphertext }));
+    Ok(send::<Decrypted>(request).await?.data.plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use axum::Server;
+    use hyper::service::make_service_fn;
+    use hyper::Body;
+    use tower::service_fn;
+
+    use super::*;
+
+    #[test]
+    fn it_parses_variables() {
+        assert_eq!(
+            Kms::from_variable("aws_kms.AQICAHh="),
+            Some((Kms::Aws, "AQICAHh="))
+        );
+        assert_eq!(
+            Kms::from_variable("vault.redis:vault:v1:abc"),
+            Some((Kms::Vault, "redis:vault:v1:abc"))
+        );
+        assert_eq!(
+            key_and_ciphertext("redis:vault:v1:abc"),
+            Ok(("redis", "vault:v1:abc"))
+        );
+        assert_eq!(Kms::from_variable("env.PASSWORD"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_decrypts_with_vault() {
+        async fn handle(request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
+            let response = if request.uri().path() == "/v1/transit/decrypt/redis"
+                && request.headers()["x-vault-token"] == "root"
+            {
+                // "c2VjcmV0" is "secret" in base64
+                json!({ "data": { "plaintext": "c2VjcmV0" } }).to_string()
+            } else {
+                "{}".to_string()
+            };
+            Ok(http::Response::new(Body::from(response)))
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(|_conn| async {
+                Ok::<_, Infallible>(service_fn(handle))
+            }));
+        tokio::task::spawn(server);
+
+        let vault = Vault {
+            address: format!("http://{address}/"),
+            token: "root".to_string(),
+            mount: DEFAULT_VAULT_TRANSIT_MOUNT.to_string(),
+        };
+        assert_eq!(
+            vault_decrypt(&reqwest::Client::new(), &vault, "redis:vault:v1:abc").await,
+            Ok("c2VjcmV0".to_string())
+        );
+    }
+}
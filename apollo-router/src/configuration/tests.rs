@@ -138,6 +138,27 @@ fn cors_defaults() {
     );
 }
 
+#[test]
+fn cors_allows_the_origins_of_its_policies() {
+    let cors = Cors::builder()
+        .origins(vec!["https://a.example.com".to_string()])
+        .match_origins(vec![r"^https://.*\.b\.example\.com$".to_string()])
+        .build()
+        .into_layer()
+        .unwrap();
+    assert!(cors.allows_origin("https://a.example.com"));
+    assert!(cors.allows_origin("https://c.b.example.com"));
+    assert!(!cors.allows_origin("https://evil.example.com"));
+    assert!(!cors.allows_origin("null"));
+
+    let cors = Cors::builder()
+        .allow_any_origin(true)
+        .build()
+        .into_layer()
+        .unwrap();
+    assert!(cors.allows_origin("https://evil.example.com"));
+}
+
 #[test]
 fn bad_graphql_path_configuration_without_slash() {
     let error = Configuration::fake_builder()
@@ -601,6 +622,31 @@ supergraph:
 #[folder = "src/configuration/testdata/migrations"]
 struct Asset;
 
+#[test]
+fn validate_yaml_reports_plugin_configuration_errors() {
+    let error = Configuration::validate_yaml(
+        r#"
+traffic_shaping:
+  router:
+    timeout: forever
+        "#,
+    )
+    .expect_err("the timeout must be a duration");
+    assert!(
+        error.to_string().contains("traffic_shaping: "),
+        "the error must contain the path of the plugin configuration: {error}"
+    );
+
+    Configuration::validate_yaml(
+        r#"
+traffic_shaping:
+  router:
+    timeout: 30s
+        "#,
+    )
+    .expect("the configuration must be valid");
+}
+
 #[test]
 fn upgrade_old_configuration() {
     for file_name in Asset::iter() {
@@ -0,0 +1,354 @@
+//! Secrets read from HashiCorp Vault
+//!
+//! `${vault_secret.<path>:<field>}` variables are expanded with a field of the secret read at
+//! `<path>` from Vault, at `VAULT_ADDR` with `VAULT_TOKEN`. Paths of KV engines are read, and
+//! paths with a query string, such as `pki/issue/<role>?common_name=<name>`, are written with
+//! the parameters of the query string, as dynamic secrets engines expect.
+//!
+//! A secret is read once for all its fields, so that the username and password of dynamic
+//! database credentials come from the same lease. The leases are renewed at two thirds of their
+//! duration: once Vault does not extend them anymore, the secret is read again and the
+//! configuration reloaded with it. KV secrets are read again periodically, and reload the
+//! configuration when they change.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use super::secrets::block_on;
+use super::secrets::send;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// The delay before reading again the secrets without lease, such as KV secrets
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// The delay before retrying to renew or read a secret after a failure
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Secrets by path
+static SECRETS: Lazy<Mutex<HashMap<String, Secret>>> = Lazy::new(Default::default);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Secret {
+    fields: Map<String, Value>,
+    lease: Option<Lease>,
+    /// When the lease must be renewed, or the secret read again
+    refresh_at: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Lease {
+    id: String,
+    duration: Duration,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    lease_id: String,
+    #[serde(default)]
+    lease_duration: u64,
+    #[serde(default)]
+    renewable: bool,
+    #[serde(default)]
+    data: Map<String, Value>,
+}
+
+/// The field of a secret, from `<path>:<field>`
+pub(super) fn read_field(variable: &str) -> Result<String, String> {
+    let (path, field) = variable
+        .rsplit_once(':')
+        .ok_or_else(|| "expected the path and the field, separated by ':'".to_string())?;
+    let cached = SECRETS
+        .lock()
+        .expect("the lock is not held across panics; qed")
+        .get(path)
+        .cloned();
+    let secret = match cached {
+        Some(secret) => secret,
+        None => {
+            let vault = Vault::from_env()?;
+            let request = path.to_string();
+            let secret = block_on(async move { vault.read(&request).await })?;
+            SECRETS
+                .lock()
+                .expect("the lock is not held across panics; qed")
+                .entry(path.to_string())
+                .or_insert(secret)
+                .clone()
+        }
+    };
+    secret.field(path, field)
+}
+
+/// Yields once the secrets read so far changed, so that the configuration is expanded again.
+/// The leases of the secrets are renewed in the meantime
+pub(crate) fn secret_rotations() -> BoxStream<'static, ()> {
+    stream::unfold((), |()| async {
+        loop {
+            let next = SECRETS
+                .lock()
+                .expect("the lock is not held across panics; qed")
+                .values()
+                .map(|secret| secret.refresh_at)
+                .min();
+            tokio::time::sleep_until(next.unwrap_or_else(|| Instant::now() + POLL_INTERVAL)).await;
+            match Vault::from_env() {
+                Ok(vault) if vault.refresh().await => return Some(((), ())),
+                Ok(_) => {}
+                Err(e) => tracing::error!("could not refresh the Vault secrets: {e}"),
+            }
+        }
+    })
+    .boxed()
+}
+
+impl Secret {
+    fn field(&self, path: &str, field: &str) -> Result<String, String> {
+        match self.fields.get(field) {
+            Some(Value::String(value)) => Ok(value.clone()),
+            Some(value) => Ok(value.to_string()),
+            None => Err(format!("the secret at '{path}' has no '{field}' field")),
+        }
+    }
+}
+
+/// Client of Vault, at `VAULT_ADDR` with `VAULT_TOKEN`
+struct Vault {
+    client: reqwest::Client,
+    address: String,
+    token: String,
+}
+
+impl Vault {
+    fn from_env() -> Result<Self, String> {
+        let address =
+            std::env::var("VAULT_ADDR").map_err(|_| "VAULT_ADDR is not set".to_string())?;
+        let token =
+            std::env::var("VAULT_TOKEN").map_err(|_| "VAULT_TOKEN is not set".to_string())?;
+        Self::new(&address, token)
+    }
+
+    fn new(address: &str, token: String) -> Result<Self, String> {
+        Ok(Vault {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?,
+            address: address.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    /// Renews or reads again the secrets that are due, and whether one of them changed
+    async fn refresh(&self) -> bool {
+        let now = Instant::now();
+        let due = SECRETS
+            .lock()
+            .expect("the lock is not held across panics; qed")
+            .iter()
+            .filter(|(_, secret)| secret.refresh_at <= now)
+            .map(|(path, secret)| (path.clone(), secret.clone()))
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+        for (path, secret) in due {
+            let refreshed = match &secret.lease {
+                Some(lease) => match self.renew(lease).await {
+                    // the lease still has its whole duration
+                    Ok(renewed) if renewed.duration >= lease.duration => Ok(Secret {
+                        refresh_at: Instant::now() + renewed.duration * 2 / 3,
+                        lease: Some(renewed),
+                        ..secret.clone()
+                    }),
+                    Ok(_) => self.read(&path).await,
+                    Err(e) => {
+                        tracing::warn!(
+                            "could not renew the lease of the Vault secret '{path}': {e}"
+                        );
+                        self.read(&path).await
+                    }
+                },
+                None => self.read(&path).await,
+            };
+            let refreshed = match refreshed {
+                Ok(refreshed) => {
+                    if refreshed.fields != secret.fields {
+                        tracing::info!("the Vault secret '{path}' was rotated");
+                        changed = true;
+                    }
+                    refreshed
+                }
+                Err(e) => {
+                    tracing::error!("could not read the Vault secret '{path}': {e}");
+                    Secret {
+                        refresh_at: Instant::now() + RETRY_DELAY,
+                        ..secret
+                    }
+                }
+            };
+            SECRETS
+                .lock()
+                .expect("the lock is not held across panics; qed")
+                .insert(path, refreshed);
+        }
+        changed
+    }
+
+    async fn read(&self, path: &str) -> Result<Secret, String> {
+        let request = match path.split_once('?') {
+            Some((path, query)) => {
+                let parameters = url::form_urlencoded::parse(query.as_bytes())
+                    .map(|(name, value)| (name.into_owned(), Value::String(value.into_owned())))
+                    .collect::<Map<_, _>>();
+                self.client
+                    .post(format!("{}/v1/{path}", self.address))
+                    .json(&parameters)
+            }
+            None => self.client.get(format!("{}/v1/{path}", self.address)),
+        };
+        let response: Response = send(request.header("X-Vault-Token", &self.token)).await?;
+
+        // the fields of KV version 2 secrets are nested with their metadata
+        let fields = match (response.data.get("data"), response.data.get("metadata")) {
+            (Some(Value::Object(fields)), Some(_)) => fields.clone(),
+            _ => response.data,
+        };
+        let lease = (response.renewable && !response.lease_id.is_empty()).then(|| Lease {
+            id: response.lease_id,
+            duration: Duration::from_secs(response.lease_duration),
+        });
+        let refresh_at = Instant::now()
+            + match (&lease, response.lease_duration) {
+                (Some(lease), _) => lease.duration * 2 / 3,
+                // the secret expires without being renewable
+                (None, seconds) if seconds > 0 => {
+                    Duration::from_secs(seconds).min(POLL_INTERVAL * 3) * 2 / 3
+                }
+                (None, _) => POLL_INTERVAL,
+            };
+        Ok(Secret {
+            fields,
+            lease,
+            refresh_at,
+        })
+    }
+
+    async fn renew(&self, lease: &Lease) -> Result<Lease, String> {
+        let request = self
+            .client
+            .put(format!("{}/v1/sys/leases/renew", self.address))
+            .header("X-Vault-Token", &self.token)
+            .json(&json!({
+                "lease_id": lease.id,
+                "increment": lease.duration.as_secs(),
+            }));
+        let response: Response = send(request).await?;
+        Ok(Lease {
+            id: response.lease_id,
+            duration: Duration::from_secs(response.lease_duration),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use axum::Server;
+    use hyper::service::make_service_fn;
+    use hyper::Body;
+    use tower::service_fn;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_reads_and_rotates_secrets() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        let handle = move |request: http::Request<Body>| {
+            let reads = counter.clone();
+            async move {
+                assert_eq!(request.headers()["x-vault-token"], "root");
+                let response = match (request.method().as_str(), request.uri().path()) {
+                    ("GET", "/v1/secret/data/subgraphs") => json!({
+                        "data": {
+                            "data": { "token": "abc", "port": 6379 },
+                            "metadata": { "version": 1 }
+                        }
+                    }),
+                    ("POST", "/v1/database/creds/router") => {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let body: Value = serde_json::from_slice(&body).unwrap();
+                        assert_eq!(body, json!({ "ttl": "1h" }));
+                        let read = reads.fetch_add(1, Ordering::SeqCst);
+                        json!({
+                            "lease_id": format!("database/creds/router/{read}"),
+                            "lease_duration": 3600,
+                            "renewable": true,
+                            "data": { "username": format!("user-{read}"), "password": "secret" }
+                        })
+                    }
+                    // the lease reached its maximum duration
+                    ("PUT", "/v1/sys/leases/renew") => json!({
+                        "lease_id": "database/creds/router/0",
+                        "lease_duration": 60,
+                        "renewable": true
+                    }),
+                    _ => json!({}),
+                };
+                Ok::<_, Infallible>(http::Response::new(Body::from(response.to_string())))
+            }
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_conn| {
+                let handle = handle.clone();
+                async move { Ok::<_, Infallible>(service_fn(handle)) }
+            }));
+        tokio::task::spawn(server);
+
+        let vault = Vault::new(&format!("http://{address}/"), "root".to_string()).unwrap();
+        let kv = vault.read("secret/data/subgraphs").await.unwrap();
+        assert_eq!(kv.field("secret/data/subgraphs", "token"), Ok("abc".into()));
+        assert_eq!(kv.field("secret/data/subgraphs", "port"), Ok("6379".into()));
+        assert!(kv.field("secret/data/subgraphs", "password").is_err());
+        assert!(kv.lease.is_none());
+
+        let path = "database/creds/router?ttl=1h";
+        let credentials = vault.read(path).await.unwrap();
+        assert_eq!(credentials.field(path, "username"), Ok("user-0".into()));
+        assert_eq!(
+            credentials.lease.as_ref().unwrap().duration,
+            Duration::from_secs(3600)
+        );
+
+        // the lease is due: it is not extended, so the credentials are read again
+        SECRETS.lock().unwrap().insert(
+            path.to_string(),
+            Secret {
+                refresh_at: Instant::now(),
+                ..credentials
+            },
+        );
+        assert!(vault.refresh().await);
+        let credentials = SECRETS.lock().unwrap()[path].clone();
+        assert_eq!(credentials.field(path, "username"), Ok("user-1".into()));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+}
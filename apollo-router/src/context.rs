@@ -20,6 +20,12 @@ pub(crate) type Entries = Arc<DashMap<String, Value>>;
 
 /// A map of arbitrary JSON values, for use by plugins.
 ///
+/// The entries of a context are scoped to a request. The entries of its [`Context::connection`]
+/// are shared by the requests of a connection: the operations of a WebSocket connection, or the
+/// operations of a batch. A plugin can keep there what it derived from the connection, such as
+/// the authentication established at `connection_init`, instead of deriving it again for every
+/// operation.
+///
 /// Context makes use of [`DashMap`] under the hood which tries to handle concurrency
 /// by allowing concurrency across threads without requiring locking. This is great
 /// for usability but could lead to surprises when updates are highly contested.
@@ -34,6 +40,10 @@ pub struct Context {
     // Allows adding custom entries to the context.
     entries: Entries,
 
+    /// Entries shared by the requests of the connection
+    #[serde(skip)]
+    connection: Entries,
+
     /// Creation time
     #[serde(skip)]
     #[serde(default = "Instant::now")]
@@ -45,18 +55,42 @@ impl Context {
     pub fn new() -> Self {
         Context {
             entries: Default::default(),
+            connection: Default::default(),
+            created_at: Instant::now(),
+        }
+    }
+
+    /// Create the context of a request of the connection of `connection`, sharing its
+    /// connection entries.
+    pub(crate) fn for_connection(connection: &Context) -> Self {
+        Context {
+            entries: Default::default(),
+            connection: connection.connection.clone(),
             created_at: Instant::now(),
         }
     }
+
+    /// The context of the connection of the request, shared by the operations of a WebSocket
+    /// connection or of a batch. A request outside of them is its own connection.
+    ///
+    /// Its entries are read and written with the functions of [`Context`], and are not
+    /// serialized with the request context, to Rhai scripts or to external coprocessors.
+    pub fn connection(&self) -> Context {
+        Context {
+            entries: self.connection.clone(),
+            connection: self.connection.clone(),
+            created_at: self.created_at,
+        }
+    }
 }
 
 impl Context {
     /// Returns true if the context contains a value for the specified key.
     pub fn contains_key<K>(&self, key: K) -> bool
     where
-        K: Into<String>,
+        K: AsRef<str>,
     {
-        self.entries.contains_key(&key.into())
+        self.entries.contains_key(key.as_ref())
     }
 
     /// Get a value from the context using the provided key.
@@ -66,12 +100,12 @@ impl Context {
     ///  - If the operation succeeds, the value is an [`Option`].
     pub fn get<K, V>(&self, key: K) -> Result<Option<V>, BoxError>
     where
-        K: Into<String>,
+        K: AsRef<str>,
         V: for<'de> serde::Deserialize<'de>,
     {
         self.entries
-            .get(&key.into())
-            .map(|v| serde_json_bytes::from_value(v.value().clone()))
+            .get(key.as_ref())
+            .map(|v| V::deserialize(v.value()))
             .transpose()
             .map_err(|e| e.into())
     }
@@ -110,9 +144,9 @@ impl Context {
     /// Get a json value from the context using the provided key.
     pub fn get_json_value<K>(&self, key: K) -> Option<Value>
     where
-        K: Into<String>,
+        K: AsRef<str>,
     {
-        self.entries.get(&key.into()).map(|v| v.value().clone())
+        self.entries.get(key.as_ref()).map(|v| v.value().clone())
     }
 
     /// Upsert a value in the context using the provided key and resolving
@@ -132,26 +166,14 @@ impl Context {
         K: Into<String>,
         V: for<'de> serde::Deserialize<'de> + Serialize + Default,
     {
-        let key = key.into();
-        self.entries
-            .entry(key.clone())
+        // the entry stays locked between the read and the write
+        let mut entry = self
+            .entries
+            .entry(key.into())
             .or_try_insert_with(|| serde_json_bytes::to_value::<V>(Default::default()))?;
-        let mut result = Ok(());
-        self.entries
-            .alter(&key, |_, v| match serde_json_bytes::from_value(v.clone()) {
-                Ok(value) => match serde_json_bytes::to_value((upsert)(value)) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        result = Err(e);
-                        v
-                    }
-                },
-                Err(e) => {
-                    result = Err(e);
-                    v
-                }
-            });
-        result.map_err(|e| e.into())
+        let value = V::deserialize(entry.value())?;
+        *entry.value_mut() = serde_json_bytes::to_value((upsert)(value))?;
+        Ok(())
     }
 
     /// Iterate over the entries.
@@ -163,6 +185,63 @@ impl Context {
     pub fn iter_mut(&self) -> impl Iterator<Item = RefMutMulti<'_, String, Value>> + '_ {
         self.entries.iter_mut()
     }
+
+    /// Copy the entries into a new context, sharing the connection entries.
+    ///
+    /// The copy is handed to hooks which don't change the context in place, such as external
+    /// coprocessors, and their changes are applied with [`Context::merge_changes`].
+    pub(crate) fn snapshot(&self) -> Context {
+        Context {
+            entries: Arc::new(
+                self.entries
+                    .iter()
+                    .map(|entry| (entry.key().clone(), entry.value().clone()))
+                    .collect(),
+            ),
+            connection: self.connection.clone(),
+            created_at: self.created_at,
+        }
+    }
+
+    /// Apply the changes made to `changed` since it was copied from `snapshot`.
+    ///
+    /// The entries inserted or updated in `changed` are set, and the entries set to null are
+    /// removed. The entries missing from `changed` are kept, so that hooks can return only the
+    /// entries they change. Hooks running concurrently, such as the hooks of parallel subgraph
+    /// fetches, only override each other when they change the same entries: the last merged
+    /// change wins.
+    pub(crate) fn merge_changes(&self, snapshot: &Context, changed: &Context) {
+        // the entries of a context changed in place are already up to date
+        if Arc::ptr_eq(&self.entries, &changed.entries) {
+            return;
+        }
+        for entry in changed.entries.iter() {
+            let is_changed = snapshot
+                .entries
+                .get(entry.key())
+                .map_or(true, |previous| previous.value() != entry.value());
+            if !is_changed {
+                continue;
+            }
+            if entry.value().is_null() {
+                self.entries.remove(entry.key());
+            } else {
+                self.entries
+                    .insert(entry.key().clone(), entry.value().clone());
+            }
+        }
+    }
+
+    /// Insert the entries of `other`, unless it shares the entries of this context.
+    pub(crate) fn extend(&self, other: &Context) {
+        if Arc::ptr_eq(&self.entries, &other.entries) {
+            return;
+        }
+        for entry in other.entries.iter() {
+            self.entries
+                .insert(entry.key().clone(), entry.value().clone());
+        }
+    }
 }
 
 impl Default for Context {
@@ -173,6 +252,7 @@ impl Default for Context {
 
 #[cfg(test)]
 mod test {
+    use crate::json_ext::Value;
     use crate::Context;
 
     #[test]
@@ -207,6 +287,58 @@ mod test {
         assert!(c.upsert("string", |v: usize| v + 1).is_err());
     }
 
+    #[test]
+    fn it_merges_the_changes_of_a_snapshot() {
+        let c = Context::new();
+        c.insert("kept", "original".to_string()).unwrap();
+        c.insert("omitted", "original".to_string()).unwrap();
+        c.insert("updated", "original".to_string()).unwrap();
+        c.insert("removed", "original".to_string()).unwrap();
+        c.insert("concurrent", "original".to_string()).unwrap();
+
+        let snapshot = c.snapshot();
+        let changed = snapshot.snapshot();
+        changed.insert("updated", "changed".to_string()).unwrap();
+        changed.insert("inserted", "changed".to_string()).unwrap();
+        changed.insert_json_value("removed", Value::Null);
+        // a partial context keeps the entries it doesn't return
+        changed.entries.remove("omitted");
+        // written by another hook since the snapshot was taken
+        c.insert("concurrent", "concurrent".to_string()).unwrap();
+
+        c.merge_changes(&snapshot, &changed);
+        let value = |key| c.get::<_, String>(key).unwrap();
+        assert_eq!(value("kept").as_deref(), Some("original"));
+        assert_eq!(value("omitted").as_deref(), Some("original"));
+        assert_eq!(value("updated").as_deref(), Some("changed"));
+        assert_eq!(value("inserted").as_deref(), Some("changed"));
+        assert_eq!(value("removed"), None);
+        assert_eq!(value("concurrent").as_deref(), Some("concurrent"));
+    }
+
+    #[test]
+    fn it_shares_connection_entries() {
+        let connection = Context::new();
+        connection
+            .connection()
+            .insert("token", "a".to_string())
+            .unwrap();
+        let operation = Context::for_connection(&connection);
+        assert_eq!(
+            operation.connection().get::<_, String>("token").unwrap(),
+            Some("a".to_string())
+        );
+        assert!(!operation.contains_key("token"));
+
+        operation.connection().insert("user", 1).unwrap();
+        assert_eq!(connection.connection().get("user").unwrap(), Some(1));
+        assert!(Context::new()
+            .connection()
+            .get::<_, usize>("user")
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn it_iterates_over_context() {
         let c = Context::new();
@@ -0,0 +1,54 @@
+//! CPU profiles captured on demand, served by the admin API
+//!
+//! The stacks of the router threads are sampled for the requested duration, and returned in the
+//! pprof format or as a flamegraph.
+
+use std::time::Duration;
+
+use pprof::protos::Message;
+use serde::Deserialize;
+use tower::BoxError;
+
+/// Duration of a profile, when it is not requested
+pub(crate) const DEFAULT_PROFILE_DURATION: Duration = Duration::from_secs(10);
+/// Longest duration of a profile, when it is not configured
+pub(crate) const DEFAULT_MAX_PROFILE_DURATION: Duration = Duration::from_secs(60);
+// Not a multiple of common timer frequencies, so that the samples do not synchronize with them
+const SAMPLING_FREQUENCY: i32 = 99;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProfileFormat {
+    /// Protobuf encoded profile, read by `go tool pprof`
+    #[default]
+    Pprof,
+    /// SVG flamegraph
+    Flamegraph,
+}
+
+impl ProfileFormat {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            ProfileFormat::Pprof => "application/octet-stream",
+            ProfileFormat::Flamegraph => "image/svg+xml",
+        }
+    }
+}
+
+/// Samples the stacks of the router threads for a duration, blocking the current thread. Only one
+/// profile can be captured at a time
+pub(crate) fn profile(duration: Duration, format: ProfileFormat) -> Result<Vec<u8>, BoxError> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(SAMPLING_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    std::thread::sleep(duration);
+    let report = guard.report().build()?;
+
+    let mut body = Vec::new();
+    match format {
+        ProfileFormat::Pprof => report.pprof()?.encode(&mut body)?,
+        ProfileFormat::Flamegraph => report.flamegraph(&mut body)?,
+    }
+    Ok(body)
+}
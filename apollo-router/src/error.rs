@@ -14,6 +14,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use thiserror::Error;
 use tokio::task::JoinError;
+use tower::BoxError;
 use tracing::level_filters::LevelFilter;
 
 pub(crate) use crate::configuration::ConfigurationError;
@@ -23,6 +24,8 @@ use crate::graphql::IntoGraphQLErrors;
 use crate::graphql::Response;
 use crate::json_ext::Path;
 use crate::json_ext::Value;
+use crate::plugins::traffic_shaping::Elapsed;
+use crate::plugins::traffic_shaping::RateLimited;
 use crate::spec::SpecError;
 
 /// Error types for execution.
@@ -41,6 +44,12 @@ pub(crate) enum FetchError {
         name: String,
     },
 
+    /// unknown variable: '{name}'
+    ValidationUnknownVariable {
+        /// Name of the variable.
+        name: String,
+    },
+
     /// query could not be planned: {reason}
     ValidationPlanningError {
         /// The failure reason.
@@ -73,6 +82,18 @@ pub(crate) enum FetchError {
         reason: String,
     },
 
+    /// request to '{service}' was rate limited
+    SubrequestRateLimited {
+        /// The service that was not called.
+        service: String,
+    },
+
+    /// request to '{service}' timed out
+    SubrequestTimeout {
+        /// The service that did not respond in time.
+        service: String,
+    },
+
     /// subquery requires field '{field}' but it was not found in the current response
     ExecutionFieldNotFound {
         /// The field that is not found.
@@ -95,6 +116,21 @@ pub(crate) enum FetchError {
 }
 
 impl FetchError {
+    /// Convert the error returned by a subgraph service to a fetch error.
+    pub(crate) fn from_subrequest_error(service: &str, error: BoxError) -> Self {
+        let service = service.to_string();
+        if is_caused_by::<RateLimited>(&*error) {
+            FetchError::SubrequestRateLimited { service }
+        } else if is_caused_by::<Elapsed>(&*error) {
+            FetchError::SubrequestTimeout { service }
+        } else {
+            FetchError::SubrequestHttpError {
+                service,
+                reason: error.to_string(),
+            }
+        }
+    }
+
     /// Convert the fetch error to a GraphQL error.
     pub(crate) fn to_graphql_error(&self, path: Option<Path>) -> Error {
         let mut value: Value = serde_json::to_value(self).unwrap_or_default().into();
@@ -107,6 +143,8 @@ impl FetchError {
                 FetchError::SubrequestMalformedResponse { service, .. }
                 | FetchError::SubrequestUnexpectedPatchResponse { service }
                 | FetchError::SubrequestHttpError { service, .. }
+                | FetchError::SubrequestRateLimited { service }
+                | FetchError::SubrequestTimeout { service }
                 | FetchError::CompressionError { service, .. } => {
                     extensions
                         .entry("service")
@@ -117,7 +155,8 @@ impl FetchError {
                         .entry("field")
                         .or_insert_with(|| field.clone().into());
                 }
-                FetchError::ValidationInvalidTypeVariable { name } => {
+                FetchError::ValidationInvalidTypeVariable { name }
+                | FetchError::ValidationUnknownVariable { name } => {
                     extensions
                         .entry("name")
                         .or_insert_with(|| name.clone().into());
@@ -147,12 +186,15 @@ impl ErrorExtension for FetchError {
     fn extension_code(&self) -> String {
         match self {
             FetchError::ValidationInvalidTypeVariable { .. } => "VALIDATION_INVALID_TYPE_VARIABLE",
+            FetchError::ValidationUnknownVariable { .. } => "VALIDATION_UNKNOWN_VARIABLE",
             FetchError::ValidationPlanningError { .. } => "VALIDATION_PLANNING_ERROR",
             FetchError::SubrequestMalformedResponse { .. } => "SUBREQUEST_MALFORMED_RESPONSE",
             FetchError::SubrequestUnexpectedPatchResponse { .. } => {
                 "SUBREQUEST_UNEXPECTED_PATCH_RESPONSE"
             }
             FetchError::SubrequestHttpError { .. } => "SUBREQUEST_HTTP_ERROR",
+            FetchError::SubrequestRateLimited { .. } => "SUBREQUEST_RATE_LIMITED",
+            FetchError::SubrequestTimeout { .. } => "SUBREQUEST_TIMEOUT",
             FetchError::ExecutionFieldNotFound { .. } => "EXECUTION_FIELD_NOT_FOUND",
             FetchError::ExecutionPathNotFound { .. } => "EXECUTION_PATH_NOT_FOUND",
             FetchError::CompressionError { .. } => "COMPRESSION_ERROR",
@@ -171,6 +213,58 @@ impl From<QueryPlannerError> for FetchError {
     }
 }
 
+/// Returns `true` if the error, or one of its sources, is a `T`
+fn is_caused_by<T: std::error::Error + 'static>(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if error.is::<T>() {
+            return true;
+        }
+        current = error.source();
+    }
+    false
+}
+
+/// Records the errors sent to clients in the `apollo_router_graphql_error_count` metric, with
+/// their extension code as `code` attribute
+pub(crate) fn count_graphql_errors<'a>(errors: impl IntoIterator<Item = &'a Error>) {
+    for error in errors {
+        tracing::info!(
+            monotonic_counter.apollo_router_graphql_error_count = 1u64,
+            code = error
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .unwrap_or_default(),
+        );
+    }
+}
+
+/// An error generated by the router, rather than by the execution of an operation, attached to
+/// the HTTP response carrying it so that it can be converted to problem details
+#[derive(Clone, Debug)]
+pub(crate) struct RouterError(pub(crate) Error);
+
+/// An HTTP response with a JSON body holding an error generated by the router. The error is
+/// counted in the `apollo_router_graphql_error_count` metric
+pub(crate) fn router_error_response(
+    status: http::StatusCode,
+    error: Error,
+) -> http::Response<hyper::Body> {
+    count_graphql_errors([&error]);
+    let body = serde_json::to_string(&error).unwrap_or_else(|_| error.message.clone());
+    let mut response = http::Response::builder()
+        .status(status)
+        .header(
+            http::header::CONTENT_TYPE,
+            mime::APPLICATION_JSON.essence_str(),
+        )
+        .body(hyper::Body::from(body))
+        .expect("cannot fail");
+    response.extensions_mut().insert(RouterError(error));
+    response
+}
+
 /// Error types for CacheResolver
 #[derive(Error, Debug, Display, Clone, Serialize, Deserialize)]
 pub(crate) enum CacheResolverError {
@@ -452,3 +546,31 @@ pub(crate) enum LicenseError {
     /// Apollo key is missing
     MissingKey,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_assigns_codes_to_subrequest_errors() {
+        let code = |error: BoxError| {
+            FetchError::from_subrequest_error("products", error)
+                .to_graphql_error(None)
+                .extensions
+                .get("code")
+                .cloned()
+        };
+        assert_eq!(
+            code(RateLimited::new().into()),
+            Some("SUBREQUEST_RATE_LIMITED".into())
+        );
+        assert_eq!(
+            code(Elapsed::new().into()),
+            Some("SUBREQUEST_TIMEOUT".into())
+        );
+        assert_eq!(
+            code("connection refused".into()),
+            Some("SUBREQUEST_HTTP_ERROR".into())
+        );
+    }
+}
@@ -4,6 +4,7 @@ use std::env;
 use std::ffi::OsStr;
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
@@ -26,11 +27,14 @@ use url::Url;
 use crate::configuration;
 use crate::configuration::generate_config_schema;
 use crate::configuration::generate_upgrade;
+use crate::configuration::Configuration;
 use crate::configuration::ConfigurationError;
+use crate::graphql;
 use crate::router::ConfigurationSource;
 use crate::router::RouterHttpServer;
 use crate::router::SchemaSource;
 use crate::router::ShutdownSource;
+use crate::Bench;
 
 // Note: the dhat-heap and dhat-ad-hoc features should not be both enabled. We name our functions
 // and variables identically to prevent this from happening.
@@ -101,6 +105,36 @@ extern "C" fn drop_ad_hoc_profiler() {
 enum Commands {
     /// Configuration subcommands.
     Config(ConfigSubcommandArgs),
+
+    /// Replay a corpus of operations against the in-process router with mocked subgraphs,
+    /// and report latencies.
+    ///
+    /// Uses the schema given with --supergraph and the configuration given with --config.
+    Bench(BenchSubcommandArgs),
+}
+
+#[derive(Args, Debug)]
+struct BenchSubcommandArgs {
+    /// JSON file containing an array of GraphQL requests, sent in turn.
+    #[clap(long, value_parser)]
+    operations: PathBuf,
+
+    /// Directory of subgraph fixtures recorded with `TestHarness::subgraph_fixtures`.
+    /// Subgraphs without fixtures respond with empty data.
+    #[clap(long, value_parser)]
+    subgraph_fixtures: Option<PathBuf>,
+
+    /// Target number of requests per second.
+    #[clap(long, default_value = "10")]
+    rps: u32,
+
+    /// Duration of the benchmark.
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    duration: Duration,
+
+    /// Print the report as JSON.
+    #[clap(action = ArgAction::SetTrue, long)]
+    json: bool,
 }
 
 #[derive(Args, Debug)]
@@ -115,6 +149,13 @@ enum ConfigSubcommand {
     /// Print the json configuration schema.
     Schema,
 
+    /// Validate a configuration file, including the configuration of each plugin.
+    Validate {
+        /// The location of the config to validate.
+        #[clap(value_parser, env = "APOLLO_ROUTER_CONFIG_PATH")]
+        config_path: PathBuf,
+    },
+
     /// Print upgraded configuration.
     Upgrade {
         /// The location of the config to upgrade.
@@ -127,6 +168,18 @@ enum ConfigSubcommand {
     },
     /// List all the available experimental configurations with related GitHub discussion
     Experimental,
+
+    /// Print the configuration after upgrade and variable expansion, with expanded values redacted.
+    Dump {
+        /// The location of the config to print, followed by the overlays merged into it.
+        #[clap(
+            value_parser,
+            env = "APOLLO_ROUTER_CONFIG_PATH",
+            value_delimiter = ',',
+            required = true
+        )]
+        config_paths: Vec<PathBuf>,
+    },
 }
 
 /// Options for the router
@@ -152,14 +205,19 @@ pub(crate) struct Opt {
     )]
     hot_reload: bool,
 
-    /// Configuration location relative to the project directory.
+    /// Configuration location relative to the project directory. Repeat it (or separate the paths with commas) to merge overlays into a base configuration.
     #[clap(
         short,
         long = "config",
         value_parser,
+        value_delimiter = ',',
         env = "APOLLO_ROUTER_CONFIG_PATH"
     )]
-    config_path: Option<PathBuf>,
+    config_path: Vec<PathBuf>,
+
+    /// Configuration location in a key of a Kubernetes ConfigMap or Secret (`k8s://configmap/<name>/<key>` or `k8s://secret/<name>/<key>`), watched with `--hot-reload`.
+    #[clap(long = "config-url", env = "APOLLO_ROUTER_CONFIG_URL")]
+    config_url: Option<String>,
 
     /// Enable development mode.
     #[clap(
@@ -179,6 +237,26 @@ pub(crate) struct Opt {
     )]
     supergraph_path: Option<PathBuf>,
 
+    /// Schema location in object storage (`s3://`, `gs://` or `az://`) or at an HTTP URL, polled for changes, or in a Kubernetes ConfigMap or Secret (`k8s://`), watched for changes.
+    #[clap(long = "supergraph-url", env = "APOLLO_ROUTER_SUPERGRAPH_URL")]
+    supergraph_url: Option<String>,
+
+    /// The time between polls of the schema URL.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration, env = "APOLLO_ROUTER_SUPERGRAPH_POLL_INTERVAL")]
+    supergraph_poll_interval: Duration,
+
+    /// The timeout for an http call to the schema URL. Defaults to 30s.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration, env = "APOLLO_ROUTER_SUPERGRAPH_TIMEOUT")]
+    supergraph_timeout: Duration,
+
+    /// Subgraph schemas to compose into the supergraph, listed in a Rover `supergraph.yaml` file. For local development.
+    #[clap(
+        long = "supergraph-config",
+        value_parser,
+        env = "APOLLO_ROUTER_SUPERGRAPH_CONFIG_PATH"
+    )]
+    supergraph_config: Option<PathBuf>,
+
     /// Prints the configuration schema.
     #[clap(long, action(ArgAction::SetTrue), hide(true))]
     schema: bool,
@@ -200,6 +278,10 @@ pub(crate) struct Opt {
     // Should be a Vec<Url> when https://github.com/clap-rs/clap/discussions/3796 is solved
     apollo_uplink_endpoints: Option<String>,
 
+    /// The base64 encoded Ed25519 public key verifying the schemas of a self-hosted registry listed in the uplink endpoints.
+    #[clap(long, env)]
+    apollo_uplink_public_key: Option<String>,
+
     /// The time between polls to Apollo uplink. Minimum 10s.
     #[clap(long, default_value = "10s", value_parser = humantime::parse_duration, env)]
     apollo_uplink_poll_interval: Duration,
@@ -374,6 +456,15 @@ impl Executable {
                 println!("{}", serde_json::to_string_pretty(&schema)?);
                 Ok(())
             }
+            Some(Commands::Config(ConfigSubcommandArgs {
+                command: ConfigSubcommand::Validate { config_path },
+            })) => {
+                let config_string = std::fs::read_to_string(config_path)
+                    .with_context(|| format!("could not read {}", config_path.display()))?;
+                Configuration::validate_yaml(&config_string)?;
+                println!("{} is valid", config_path.display());
+                Ok(())
+            }
             Some(Commands::Config(ConfigSubcommandArgs {
                 command: ConfigSubcommand::Upgrade { config_path, diff },
             })) => {
@@ -388,6 +479,22 @@ impl Executable {
                 configuration::print_all_experimental_conf();
                 Ok(())
             }
+            Some(Commands::Config(ConfigSubcommandArgs {
+                command: ConfigSubcommand::Dump { config_paths },
+            })) => {
+                let config_strings = config_paths
+                    .iter()
+                    .map(|config_path| {
+                        std::fs::read_to_string(config_path)
+                            .with_context(|| format!("could not read {}", config_path.display()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let configuration = configuration::merge_configurations(&config_strings)?
+                    .parse::<Configuration>()?;
+                print!("{}", serde_yaml::to_string(&configuration.redacted_yaml)?);
+                Ok(())
+            }
+            Some(Commands::Bench(args)) => Self::bench(&opt, args).await,
             None => {
                 // The dispatcher we created is passed explicitly here to make sure we display the logs
                 // in the initialization phase and in the state machine code, before a global subscriber
@@ -399,6 +506,52 @@ impl Executable {
         }
     }
 
+    async fn bench(opt: &Opt, args: &BenchSubcommandArgs) -> Result<()> {
+        let supergraph_path = opt
+            .supergraph_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("the bench subcommand requires --supergraph"))?;
+        let schema = std::fs::read_to_string(supergraph_path)
+            .with_context(|| format!("could not read {}", supergraph_path.display()))?;
+        let configuration = if opt.config_path.is_empty() {
+            None
+        } else {
+            let config_strings = opt
+                .config_path
+                .iter()
+                .map(|config_path| {
+                    std::fs::read_to_string(config_path)
+                        .with_context(|| format!("could not read {}", config_path.display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let config_string = configuration::merge_configurations(&config_strings)?;
+            Some(Arc::new(config_string.parse::<Configuration>()?))
+        };
+        let operations = std::fs::read_to_string(&args.operations)
+            .with_context(|| format!("could not read {}", args.operations.display()))?;
+        let operations: Vec<graphql::Request> = serde_json::from_str(&operations)
+            .with_context(|| format!("invalid operations in {}", args.operations.display()))?;
+
+        let report = Bench::builder()
+            .schema(schema)
+            .and_configuration(configuration)
+            .operations(operations)
+            .and_subgraph_fixtures(args.subgraph_fixtures.clone())
+            .requests_per_second(args.rps)
+            .duration(args.duration)
+            .build()
+            .run()
+            .await
+            .map_err(|error| anyhow!(error))?;
+
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("{}", report);
+        }
+        Ok(())
+    }
+
     async fn inner_start(
         shutdown: Option<ShutdownSource>,
         schema: Option<SchemaSource>,
@@ -410,28 +563,53 @@ impl Executable {
         // Enable hot reload when dev mode is enabled
         opt.hot_reload = opt.hot_reload || opt.dev;
 
-        let configuration = match (config, opt.config_path.as_ref()) {
-            (Some(_), Some(_)) => {
+        let mut config_paths = opt
+            .config_path
+            .iter()
+            .map(|path| {
+                if path.is_relative() {
+                    current_directory.join(path)
+                } else {
+                    path.to_path_buf()
+                }
+            })
+            .collect::<Vec<_>>();
+        let configuration = match (config, config_paths.len(), opt.config_url) {
+            (Some(_), 1.., _) => {
                 return Err(anyhow!(
                     "--config and APOLLO_ROUTER_CONFIG_PATH cannot be used when a custom configuration source is in use"
                 ));
             }
-            (Some(config), None) => config,
-            _ => match opt.config_path.as_ref().map(|path| {
-                let path = if path.is_relative() {
-                    current_directory.join(path)
-                } else {
-                    path.to_path_buf()
-                };
-
-                ConfigurationSource::File {
-                    path,
+            (Some(_), _, Some(_)) => {
+                return Err(anyhow!(
+                    "--config-url and APOLLO_ROUTER_CONFIG_URL cannot be used when a custom configuration source is in use"
+                ));
+            }
+            (Some(config), _, None) => config,
+            (None, 1.., Some(_)) => {
+                return Err(anyhow!("--config and --config-url cannot be used together"));
+            }
+            (None, _, Some(config_url)) => {
+                let url = Url::parse(config_url.trim()).map_err(|err| {
+                    ConfigurationError::InvalidConfiguration {
+                        message: "bad value for config_url, cannot parse to an url",
+                        error: err.to_string(),
+                    }
+                })?;
+                ConfigurationSource::Kubernetes {
+                    url,
                     watch: opt.hot_reload,
-                    delay: None,
                 }
-            }) {
-                Some(configuration) => configuration,
-                None => Default::default(),
+            }
+            (None, 0, None) => Default::default(),
+            (None, 1, None) => ConfigurationSource::File {
+                path: config_paths.remove(0),
+                watch: opt.hot_reload,
+                delay: None,
+            },
+            (None, _, None) => ConfigurationSource::Files {
+                paths: config_paths,
+                watch: opt.hot_reload,
             },
         };
 
@@ -442,14 +620,40 @@ impl Executable {
         };
 
         let apollo_router_msg = format!("Apollo Router v{} // (c) Apollo Graph, Inc. // Licensed as ELv2 (https://go.apollo.dev/elv2)", std::env!("CARGO_PKG_VERSION"));
-        let schema = match (schema, opt.supergraph_path, opt.apollo_key) {
-            (Some(_), Some(_), _) => {
+        let schema = match (
+            schema,
+            opt.supergraph_path,
+            opt.supergraph_url,
+            opt.supergraph_config,
+            opt.apollo_key,
+        ) {
+            (Some(_), Some(_), _, _, _) => {
                 return Err(anyhow!(
                     "--supergraph and APOLLO_ROUTER_SUPERGRAPH_PATH cannot be used when a custom schema source is in use"
                 ))
             }
-            (Some(source), None, _) => source,
-            (_, Some(supergraph_path), _) => {
+            (Some(_), _, Some(_), _, _) => {
+                return Err(anyhow!(
+                    "--supergraph-url and APOLLO_ROUTER_SUPERGRAPH_URL cannot be used when a custom schema source is in use"
+                ))
+            }
+            (Some(_), _, _, Some(_), _) => {
+                return Err(anyhow!(
+                    "--supergraph-config and APOLLO_ROUTER_SUPERGRAPH_CONFIG_PATH cannot be used when a custom schema source is in use"
+                ))
+            }
+            (Some(source), None, None, None, _) => source,
+            (_, Some(_), Some(_), _, _) => {
+                return Err(anyhow!(
+                    "--supergraph and --supergraph-url cannot be used together"
+                ))
+            }
+            (_, Some(_), _, Some(_), _) | (_, _, Some(_), Some(_), _) => {
+                return Err(anyhow!(
+                    "--supergraph-config cannot be used with --supergraph or --supergraph-url"
+                ))
+            }
+            (_, Some(supergraph_path), _, _, _) => {
                 tracing::info!("{apollo_router_msg}");
                 tracing::info!("{apollo_telemetry_msg}");
 
@@ -466,7 +670,45 @@ impl Executable {
                     delay: None,
                 }
             }
-            (_, None, Some(apollo_key)) => {
+            (_, None, Some(supergraph_url), _, _) => {
+                tracing::info!("{apollo_router_msg}");
+                tracing::info!("{apollo_telemetry_msg}");
+
+                setup_panic_handler(dispatcher.clone());
+
+                let url = Url::parse(supergraph_url.trim()).map_err(|err| {
+                    ConfigurationError::InvalidConfiguration {
+                        message: "bad value for supergraph_url, cannot parse to an url",
+                        error: err.to_string(),
+                    }
+                })?;
+                if url.scheme() == "k8s" {
+                    SchemaSource::Kubernetes { url, watch: true }
+                } else {
+                    SchemaSource::Url {
+                        url,
+                        poll_interval: opt.supergraph_poll_interval,
+                        timeout: opt.supergraph_timeout,
+                    }
+                }
+            }
+            (_, None, None, Some(supergraph_config), _) => {
+                tracing::info!("{apollo_router_msg}");
+                tracing::info!("{apollo_telemetry_msg}");
+
+                setup_panic_handler(dispatcher.clone());
+
+                let supergraph_config = if supergraph_config.is_relative() {
+                    current_directory.join(supergraph_config)
+                } else {
+                    supergraph_config
+                };
+                SchemaSource::Subgraphs {
+                    path: supergraph_config,
+                    watch: opt.hot_reload,
+                }
+            }
+            (_, None, None, None, Some(apollo_key)) => {
                 tracing::info!("{apollo_router_msg}");
                 tracing::info!("{apollo_telemetry_msg}");
 
@@ -487,12 +729,32 @@ impl Executable {
                         error: err.to_string(),
                     })?;
 
-                SchemaSource::Registry {
-                    apollo_key,
-                    apollo_graph_ref,
-                    urls: uplink_endpoints,
-                    poll_interval: opt.apollo_uplink_poll_interval,
-                    timeout: opt.apollo_uplink_timeout
+                match (opt.apollo_uplink_public_key, uplink_endpoints) {
+                    (Some(public_key), Some(urls)) => {
+                        // Fail early instead of when polling the registry
+                        crate::uplink::PublicKey::from_base64(&public_key)
+                            .map_err(|e| anyhow!("bad value for APOLLO_UPLINK_PUBLIC_KEY: {e}"))?;
+                        SchemaSource::SelfHostedRegistry {
+                            apollo_key,
+                            apollo_graph_ref,
+                            urls,
+                            public_key,
+                            poll_interval: opt.apollo_uplink_poll_interval,
+                            timeout: opt.apollo_uplink_timeout,
+                        }
+                    }
+                    (Some(_), None) => {
+                        return Err(anyhow!(
+                            "APOLLO_UPLINK_PUBLIC_KEY verifies the schemas of a self-hosted registry, and requires setting its URL in APOLLO_UPLINK_ENDPOINTS"
+                        ))
+                    }
+                    (None, urls) => SchemaSource::Registry {
+                        apollo_key,
+                        apollo_graph_ref,
+                        urls,
+                        poll_interval: opt.apollo_uplink_poll_interval,
+                        timeout: opt.apollo_uplink_timeout
+                    },
                 }
             }
             _ => {
@@ -507,6 +769,15 @@ impl Executable {
 
       $ ./router --supergraph <file_path>
 
+  * Poll a schema published to object storage with the '--supergraph-url' option:
+
+      $ ./router --supergraph-url s3://<bucket>/<key>
+
+  * Compose the subgraph schemas listed in a Rover configuration file
+    with the '--supergraph-config' option:
+
+      $ ./router --dev --supergraph-config supergraph.yaml
+
   * Fetch a registered schema from Apollo Studio by setting
     these environment variables:
 
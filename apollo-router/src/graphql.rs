@@ -4,7 +4,6 @@ use std::fmt;
 use std::pin::Pin;
 
 use futures::Stream;
-use heck::ToShoutySnakeCase;
 pub use router_bridge::planner::Location;
 use router_bridge::planner::PlanError;
 use router_bridge::planner::PlanErrorExtensions;
@@ -176,20 +175,24 @@ where
 }
 
 /// Trait used to get extension type from an error
+///
+/// Extension codes are part of the API of the router: they are documented and must not change
 pub(crate) trait ErrorExtension
 where
     Self: Sized,
 {
-    fn extension_code(&self) -> String {
-        std::any::type_name::<Self>().to_shouty_snake_case()
-    }
+    fn extension_code(&self) -> String;
 
     fn custom_extension_details(&self) -> Option<Object> {
         None
     }
 }
 
-impl ErrorExtension for PlanError {}
+impl ErrorExtension for PlanError {
+    fn extension_code(&self) -> String {
+        "QUERY_PLANNING_FAILED".to_string()
+    }
+}
 
 impl From<PlanError> for Error {
     fn from(err: PlanError) -> Self {
@@ -236,7 +239,11 @@ impl From<PlannerError> for Error {
     }
 }
 
-impl ErrorExtension for WorkerError {}
+impl ErrorExtension for WorkerError {
+    fn extension_code(&self) -> String {
+        "QUERY_PLANNER_ERROR".to_string()
+    }
+}
 
 impl From<WorkerError> for Error {
     fn from(err: WorkerError) -> Self {
@@ -256,7 +263,11 @@ impl From<WorkerError> for Error {
     }
 }
 
-impl ErrorExtension for WorkerGraphQLError {}
+impl ErrorExtension for WorkerGraphQLError {
+    fn extension_code(&self) -> String {
+        "QUERY_PLANNER_ERROR".to_string()
+    }
+}
 
 impl From<WorkerGraphQLError> for Error {
     fn from(err: WorkerGraphQLError) -> Self {
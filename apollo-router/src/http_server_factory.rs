@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -156,6 +157,13 @@ pub(crate) enum NetworkStream {
     Unix(tokio::net::UnixStream),
 }
 
+/// The connection of a request, in the extensions of the HTTP requests received on TCP listeners
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ConnectionInfo {
+    /// The address of the peer of the connection, usually a proxy or the client itself
+    pub(crate) peer_address: SocketAddr,
+}
+
 impl Listener {
     pub(crate) fn local_addr(&self) -> std::io::Result<ListenAddr> {
         match self {
@@ -1,49 +1,102 @@
-#[cfg(test)]
 use std::collections::HashMap;
 use std::num::NonZeroUsize;
+use std::sync::Arc;
 
+use apollo_parser::ast;
+use http::header::HeaderName;
+use http::header::HOST;
+use http::header::REFERER;
+use http::HeaderValue;
+use http::Uri;
 use router_bridge::introspect;
 use router_bridge::introspect::IntrospectionError;
 use router_bridge::planner::IncrementalDeliverySupport;
 use router_bridge::planner::QueryPlannerConfig;
 
 use crate::cache::storage::CacheStorage;
+use crate::error::QueryPlannerError;
+use crate::graphql;
 use crate::graphql::Response;
+use crate::plugins::authentication::JWT_CLAIMS_CONTEXT_KEY;
+use crate::spec::SpecError;
 use crate::Configuration;
+use crate::Context;
 
-const DEFAULT_INTROSPECTION_CACHE_CAPACITY: NonZeroUsize =
+pub(crate) const DEFAULT_INTROSPECTION_CACHE_CAPACITY: NonZeroUsize =
     unsafe { NonZeroUsize::new_unchecked(5) };
 
+/// Fields returning the types, fields or arguments of a type, which nest in an introspection query
+/// like the types they describe
+const RECURSIVE_INTROSPECTION_FIELDS: [&str; 4] =
+    ["fields", "inputFields", "interfaces", "possibleTypes"];
+
 /// A cache containing our well known introspection queries.
 pub(crate) struct Introspection {
-    cache: CacheStorage<String, Response>,
+    cache: CacheStorage<IntrospectionCacheKey, Response>,
     defer_support: bool,
+    max_depth: Option<usize>,
+    max_recursion: usize,
+}
+
+/// Introspection responses are cached by schema, so that they can be shared between router
+/// instances through Redis
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct IntrospectionCacheKey {
+    schema_id: String,
+    query: String,
+}
+
+impl std::fmt::Display for IntrospectionCacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "introspection\0{}\0{}", self.schema_id, self.query)
+    }
 }
 
 impl Introspection {
     pub(crate) async fn with_capacity(
         configuration: &Configuration,
         capacity: NonZeroUsize,
+        redis_urls: Option<Vec<String>>,
     ) -> Self {
+        let execution = &configuration.supergraph.introspection_execution;
         Self {
-            cache: CacheStorage::new(capacity, None, "introspection").await,
+            cache: CacheStorage::new(capacity, redis_urls, "introspection").await,
             defer_support: configuration.supergraph.defer_support,
+            max_depth: execution.max_depth,
+            max_recursion: execution.max_recursion,
         }
     }
 
     pub(crate) async fn new(configuration: &Configuration) -> Self {
-        Self::with_capacity(configuration, DEFAULT_INTROSPECTION_CACHE_CAPACITY).await
+        let cache = &configuration
+            .supergraph
+            .introspection_execution
+            .experimental_cache;
+        Self::with_capacity(
+            configuration,
+            cache.in_memory.limit,
+            #[cfg(feature = "experimental_cache")]
+            cache.redis.as_ref().map(|redis| redis.urls.clone()),
+            #[cfg(not(feature = "experimental_cache"))]
+            None,
+        )
+        .await
     }
 
     #[cfg(test)]
     pub(crate) async fn from_cache(
         configuration: &Configuration,
+        schema_id: &str,
         cache: HashMap<String, Response>,
     ) -> Self {
-        let this = Self::with_capacity(configuration, cache.len().try_into().unwrap()).await;
+        let this = Self::with_capacity(configuration, cache.len().try_into().unwrap(), None).await;
 
         for (query, response) in cache.into_iter() {
-            this.cache.insert(query, response).await;
+            let key = IntrospectionCacheKey {
+                schema_id: schema_id.to_string(),
+                query,
+            };
+            this.cache.insert(key, response).await;
         }
         this
     }
@@ -51,14 +104,34 @@ impl Introspection {
     /// Execute an introspection and cache the response.
     pub(crate) async fn execute(
         &self,
+        schema_id: &str,
         schema_sdl: &str,
         query: String,
-    ) -> Result<Response, IntrospectionError> {
-        if let Some(response) = self.cache.get(&query).await {
+    ) -> Result<Response, QueryPlannerError> {
+        let key = IntrospectionCacheKey {
+            schema_id: schema_id.to_string(),
+            query,
+        };
+        if let Some(response) = self.cache.get(&key).await {
             return Ok(response);
         }
 
-        // Do the introspection query and cache it
+        self.check_limits(&key.query)?;
+        let response = self
+            .introspect(schema_sdl, &key.query)
+            .map_err(QueryPlannerError::Introspection)?;
+
+        self.cache.insert(key, response.clone()).await;
+
+        Ok(response)
+    }
+
+    /// Removes the cached introspection responses
+    pub(crate) async fn clear(&self) {
+        self.cache.clear().await
+    }
+
+    fn introspect(&self, schema_sdl: &str, query: &str) -> Result<Response, IntrospectionError> {
         let mut response = introspect::batch_introspect(
             schema_sdl,
             vec![query.to_owned()],
@@ -88,11 +161,240 @@ impl Introspection {
                 .into(),
             })?;
 
-        let response = Response::builder().data(introspection_result).build();
+        Ok(Response::builder().data(introspection_result).build())
+    }
+
+    /// Rejects the queries nesting fields deeper than the limits, before they are computed
+    fn check_limits(&self, query: &str) -> Result<(), SpecError> {
+        let nesting = Nesting::of(query);
+        if let Some(max_depth) = self.max_depth {
+            if nesting.depth > max_depth {
+                return Err(SpecError::IntrospectionLimitExceeded(format!(
+                    "the depth of the query, {}, exceeds the maximum of {max_depth}",
+                    nesting.depth
+                )));
+            }
+        }
+        if nesting.recursion > self.max_recursion {
+            return Err(SpecError::IntrospectionLimitExceeded(format!(
+                "the query nests {} levels of {}, exceeding the maximum of {}",
+                nesting.recursion,
+                RECURSIVE_INTROSPECTION_FIELDS.join(", "),
+                self.max_recursion
+            )));
+        }
+        Ok(())
+    }
+}
 
-        self.cache.insert(query, response.clone()).await;
+/// How deep the fields of a query nest
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Nesting {
+    depth: usize,
+    /// Nesting of the recursive introspection fields
+    recursion: usize,
+}
 
-        Ok(response)
+impl Nesting {
+    fn of(query: &str) -> Self {
+        let document = apollo_parser::Parser::new(query).parse().document();
+        let mut visitor = NestingVisitor::default();
+        let mut operations = Vec::new();
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::OperationDefinition(operation) => operations.push(operation),
+                ast::Definition::FragmentDefinition(fragment) => {
+                    let name = fragment.fragment_name().and_then(|name| name.name());
+                    if let (Some(name), Some(selection_set)) = (name, fragment.selection_set()) {
+                        visitor
+                            .fragments
+                            .insert(name.text().to_string(), selection_set);
+                    }
+                }
+                _ => {}
+            }
+        }
+        operations
+            .iter()
+            .filter_map(|operation| operation.selection_set())
+            .map(|selection_set| visitor.selection_set(&selection_set))
+            .fold(Nesting::default(), Nesting::max)
+    }
+
+    fn max(self, other: Nesting) -> Self {
+        Nesting {
+            depth: self.depth.max(other.depth),
+            recursion: self.recursion.max(other.recursion),
+        }
+    }
+}
+
+/// Measures the nesting of selection sets, once for each fragment
+#[derive(Default)]
+struct NestingVisitor {
+    fragments: HashMap<String, ast::SelectionSet>,
+    measured: HashMap<String, Nesting>,
+    visiting: Vec<String>,
+}
+
+impl NestingVisitor {
+    fn selection_set(&mut self, selection_set: &ast::SelectionSet) -> Nesting {
+        selection_set
+            .selections()
+            .map(|selection| match selection {
+                ast::Selection::Field(field) => {
+                    let mut nesting = field
+                        .selection_set()
+                        .map(|selection_set| self.selection_set(&selection_set))
+                        .unwrap_or_default();
+                    nesting.depth += 1;
+                    let is_recursive = field.name().map_or(false, |name| {
+                        RECURSIVE_INTROSPECTION_FIELDS.contains(&name.text().as_str())
+                    });
+                    if is_recursive {
+                        nesting.recursion += 1;
+                    }
+                    nesting
+                }
+                ast::Selection::FragmentSpread(spread) => spread
+                    .fragment_name()
+                    .and_then(|name| name.name())
+                    .map(|name| self.fragment(name.text().to_string()))
+                    .unwrap_or_default(),
+                ast::Selection::InlineFragment(fragment) => fragment
+                    .selection_set()
+                    .map(|selection_set| self.selection_set(&selection_set))
+                    .unwrap_or_default(),
+            })
+            .fold(Nesting::default(), Nesting::max)
+    }
+
+    fn fragment(&mut self, name: String) -> Nesting {
+        if let Some(nesting) = self.measured.get(&name) {
+            return *nesting;
+        }
+        // fragment cycles are rejected by validation
+        if self.visiting.contains(&name) {
+            return Nesting::default();
+        }
+        let selection_set = match self.fragments.get(&name) {
+            Some(selection_set) => selection_set.clone(),
+            None => return Nesting::default(),
+        };
+        self.visiting.push(name.clone());
+        let nesting = self.selection_set(&selection_set);
+        self.visiting.pop();
+        self.measured.insert(name, nesting);
+        nesting
+    }
+}
+
+/// Decides which requests are answered when introspection is restricted
+pub(crate) struct IntrospectionFilter {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    claims: HashMap<String, serde_json::Value>,
+    /// Path of the sandbox page, if requests from the sandbox are allowed
+    sandbox_path: Option<String>,
+}
+
+impl IntrospectionFilter {
+    /// Returns the filter of the configuration, if introspection is restricted
+    pub(crate) fn new(configuration: &Configuration) -> Option<Arc<Self>> {
+        let access = &configuration.supergraph.introspection_access;
+        if !access.is_restricted() {
+            return None;
+        }
+        let headers = access
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                match (
+                    HeaderName::try_from(name.as_str()),
+                    HeaderValue::try_from(value.as_str()),
+                ) {
+                    (Ok(name), Ok(value)) => Some((name, value)),
+                    _ => {
+                        tracing::error!("introspection access header '{name}' is not valid");
+                        None
+                    }
+                }
+            })
+            .collect();
+        // the sandbox page is only served if it is enabled
+        let sandbox_path = (access.sandbox && configuration.sandbox.enabled)
+            .then(|| configuration.supergraph.path.clone());
+        Some(Arc::new(Self {
+            headers,
+            claims: access.claims.clone(),
+            sandbox_path,
+        }))
+    }
+
+    /// Whether introspection is allowed for the request
+    pub(crate) fn allows(
+        &self,
+        request: &http::Request<graphql::Request>,
+        context: &Context,
+    ) -> bool {
+        self.headers.iter().any(|(name, value)| {
+            request
+                .headers()
+                .get_all(name)
+                .iter()
+                .any(|header| header == value)
+        }) || self.allows_claims(context)
+            || self.allows_sandbox(request)
+    }
+
+    fn allows_claims(&self, context: &Context) -> bool {
+        if self.claims.is_empty() {
+            return false;
+        }
+        let claims = match context.get::<_, serde_json::Value>(JWT_CLAIMS_CONTEXT_KEY) {
+            Ok(Some(claims)) => claims,
+            _ => return false,
+        };
+        self.claims
+            .iter()
+            .any(|(name, expected)| match claims.get(name) {
+                Some(serde_json::Value::Array(values)) => values.contains(expected),
+                Some(value) => value == expected,
+                None => false,
+            })
+    }
+
+    fn allows_sandbox(&self, request: &http::Request<graphql::Request>) -> bool {
+        let path = match &self.sandbox_path {
+            Some(path) => path,
+            None => return false,
+        };
+        let referer = match request
+            .headers()
+            .get(REFERER)
+            .and_then(|referer| referer.to_str().ok())
+            .and_then(|referer| referer.parse::<Uri>().ok())
+        {
+            Some(referer) => referer,
+            None => return false,
+        };
+        let host = request
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| {
+                request
+                    .uri()
+                    .authority()
+                    .map(|authority| authority.as_str())
+            });
+
+        // the sandbox page is served by the router, on the GraphQL path
+        let same_host = host.is_some() && referer.authority().map(|a| a.as_str()) == host;
+        let sandbox_page = match path.strip_suffix('*') {
+            Some(prefix) => referer.path().starts_with(prefix),
+            None => referer.path() == path,
+        };
+        same_host && sandbox_page
     }
 }
 
@@ -110,14 +412,129 @@ mod introspection_tests {
             .iter()
             .cloned()
             .collect();
-        let introspection = Introspection::from_cache(&Configuration::default(), cache).await;
+        let introspection =
+            Introspection::from_cache(&Configuration::default(), "schema", cache).await;
 
         assert_eq!(
             expected_data,
             introspection
-                .execute(schema, query_to_test.to_string())
+                .execute("schema", schema, query_to_test.to_string())
                 .await
                 .unwrap()
         );
     }
+
+    #[test]
+    fn it_measures_the_nesting_of_introspection_queries() {
+        let query = r#"
+            query {
+                __schema { types { ...FullType } }
+            }
+            fragment FullType on __Type {
+                name
+                fields { name type { ...TypeRef } }
+                interfaces { name possibleTypes { name fields { name } } }
+            }
+            fragment TypeRef on __Type { kind ofType { kind ofType { name } } }
+        "#;
+        assert_eq!(
+            Nesting::of(query),
+            Nesting {
+                depth: 7,
+                recursion: 3
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_introspection_queries_over_the_limits() {
+        let configuration: Configuration = serde_json::from_value(serde_json::json!({
+            "supergraph": {
+                "introspection_execution": { "max_depth": 4, "max_recursion": 1 }
+            }
+        }))
+        .unwrap();
+        let introspection = Introspection::new(&configuration).await;
+
+        assert!(introspection
+            .check_limits("{ __schema { types { name } } }")
+            .is_ok());
+        assert!(matches!(
+            introspection.check_limits("{ __schema { types { fields { type { name } } } } }"),
+            Err(SpecError::IntrospectionLimitExceeded(_))
+        ));
+        assert!(matches!(
+            introspection
+                .check_limits("{ __type(name: \"A\") { fields { interfaces { name } } } }"),
+            Err(SpecError::IntrospectionLimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn it_restricts_introspection_to_matching_requests() {
+        let configuration = Configuration::builder()
+            .supergraph(
+                crate::configuration::Supergraph::builder()
+                    .introspection(true)
+                    .path("/graphql".to_string())
+                    .introspection_access(
+                        serde_json::from_value::<crate::configuration::IntrospectionAccess>(
+                            serde_json::json!({
+                                "headers": { "x-introspection-key": "secret" },
+                                "claims": { "roles": "admin" },
+                                "sandbox": true
+                            }),
+                        )
+                        .unwrap(),
+                    )
+                    .build(),
+            )
+            .sandbox(
+                crate::configuration::Sandbox::builder()
+                    .enabled(true)
+                    .build(),
+            )
+            .homepage(
+                crate::configuration::Homepage::builder()
+                    .enabled(false)
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let filter = IntrospectionFilter::new(&configuration).unwrap();
+        let request = |headers: &[(&str, &str)]| {
+            let mut request = http::Request::builder().uri("http://router.example.com/graphql");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            request.body(graphql::Request::default()).unwrap()
+        };
+        let context = Context::new();
+
+        assert!(!filter.allows(&request(&[]), &context));
+        assert!(filter.allows(&request(&[("x-introspection-key", "secret")]), &context));
+        assert!(!filter.allows(&request(&[("x-introspection-key", "other")]), &context));
+        assert!(filter.allows(
+            &request(&[
+                ("host", "router.example.com"),
+                ("referer", "http://router.example.com/graphql")
+            ]),
+            &context
+        ));
+        assert!(!filter.allows(
+            &request(&[
+                ("host", "router.example.com"),
+                ("referer", "http://evil.example.com/graphql")
+            ]),
+            &context
+        ));
+
+        context
+            .insert(
+                JWT_CLAIMS_CONTEXT_KEY,
+                serde_json::json!({ "roles": ["user", "admin"] }),
+            )
+            .unwrap();
+        assert!(filter.allows(&request(&[]), &context));
+    }
 }
@@ -0,0 +1,71 @@
+//! jemalloc global allocator, enabled by the `jemalloc` feature
+//!
+//! The heap statistics and the heap profiles are served by the admin API. Profiling samples an
+//! allocation every 512KiB on average, which keeps its overhead low enough to stay enabled in
+//! production, and lets profiles be dumped on demand to diagnose memory growth.
+
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+use tikv_jemalloc_ctl::epoch;
+use tikv_jemalloc_ctl::raw;
+use tikv_jemalloc_ctl::stats;
+use tower::BoxError;
+
+#[cfg(feature = "dhat-heap")]
+compile_error!("the jemalloc and dhat-heap features cannot be enabled together");
+#[cfg(feature = "allocation_metrics")]
+compile_error!("the jemalloc and allocation_metrics features cannot be enabled together");
+
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+// Options read by jemalloc when it starts: profiling samples an allocation every 2^19 bytes
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+static malloc_conf: &[u8; 45] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
+
+/// Heap statistics, in bytes
+#[derive(Debug, Serialize)]
+pub(crate) struct HeapStats {
+    /// Allocated by the application
+    allocated: usize,
+    /// In the pages used by the allocations
+    active: usize,
+    /// Used by jemalloc for its own structures
+    metadata: usize,
+    /// In the physically resident pages mapped by jemalloc
+    resident: usize,
+    /// In the chunks mapped by jemalloc
+    mapped: usize,
+    /// Kept mapped by jemalloc, but not resident, to be reused
+    retained: usize,
+}
+
+impl HeapStats {
+    pub(crate) fn read() -> Result<Self, BoxError> {
+        // the statistics are cached, and refreshed when the epoch advances
+        epoch::advance()?;
+        Ok(HeapStats {
+            allocated: stats::allocated::read()?,
+            active: stats::active::read()?,
+            metadata: stats::metadata::read()?,
+            resident: stats::resident::read()?,
+            mapped: stats::mapped::read()?,
+            retained: stats::retained::read()?,
+        })
+    }
+}
+
+/// Dumps a heap profile in the temporary directory, to be read with `jeprof`
+pub(crate) fn dump_profile() -> Result<PathBuf, BoxError> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = std::env::temp_dir().join(format!("router-heap-{timestamp}.prof"));
+    let c_path = CString::new(path.to_string_lossy().into_owned())?;
+    // Safety: `prof.dump` expects a pointer to a NUL terminated path, valid for the call
+    unsafe { raw::write(b"prof.dump\0", c_path.as_ptr()) }?;
+    Ok(path)
+}
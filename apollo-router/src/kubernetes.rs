@@ -0,0 +1,419 @@
+//! Watch of Kubernetes ConfigMaps and Secrets
+//!
+//! Mounted ConfigMaps and Secrets are only refreshed by the kubelet after its sync period, which
+//! can take more than a minute. Instead, the object is watched through the API server, with the
+//! service account of the pod the router runs in.
+//!
+//! Objects are designated by `k8s://configmap/<name>/<key>` or `k8s://secret/<name>/<key>` URLs,
+//! in the namespace of the pod, or `k8s://configmap/<namespace>/<name>/<key>` in another one.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use futures::Stream;
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::instrument::WithSubscriber;
+use url::Url;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// The API server ends watches after this duration, and the object is fetched again
+const WATCH_TIMEOUT_SECONDS: u64 = 290;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    ConfigMap,
+    Secret,
+}
+
+/// A key of a ConfigMap or Secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ObjectRef {
+    kind: Kind,
+    namespace: Option<String>,
+    name: String,
+    key: String,
+}
+
+impl ObjectRef {
+    fn parse(url: &Url) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid Kubernetes object URL '{url}', expected {}",
+                "k8s://configmap/<name>/<key> or k8s://secret/<name>/<key>"
+            )
+        };
+        if url.scheme() != "k8s" {
+            return Err(invalid());
+        }
+        let kind = match url.host_str() {
+            Some("configmap") => Kind::ConfigMap,
+            Some("secret") => Kind::Secret,
+            _ => return Err(invalid()),
+        };
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|segments| segments.collect())
+            .unwrap_or_default();
+        let (namespace, name, key) = match segments.as_slice() {
+            [name, key] => (None, name, key),
+            [namespace, name, key] => (Some(namespace.to_string()), name, key),
+            _ => return Err(invalid()),
+        };
+        if name.is_empty() || key.is_empty() || namespace.as_deref() == Some("") {
+            return Err(invalid());
+        }
+        Ok(ObjectRef {
+            kind,
+            namespace,
+            name: name.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    fn collection_path(&self, default_namespace: &str) -> String {
+        let collection = match self.kind {
+            Kind::ConfigMap => "configmaps",
+            Kind::Secret => "secrets",
+        };
+        format!(
+            "/api/v1/namespaces/{}/{collection}",
+            self.namespace.as_deref().unwrap_or(default_namespace)
+        )
+    }
+
+    /// Extracts the value of the key from the object
+    fn value(&self, object: &Object) -> Result<String, String> {
+        let decode = |value: &str| {
+            base64::decode(value)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+                .map_err(|e| format!("invalid value for key '{}' of {self}: {e}", self.key))
+        };
+        match (self.kind, object.data.get(&self.key)) {
+            (Kind::ConfigMap, Some(value)) => Ok(value.clone()),
+            (Kind::Secret, Some(value)) => decode(value),
+            (Kind::ConfigMap, None) => match object.binary_data.get(&self.key) {
+                Some(value) => decode(value),
+                None => Err(format!("{self} has no key '{}'", self.key)),
+            },
+            (Kind::Secret, None) => Err(format!("{self} has no key '{}'", self.key)),
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            Kind::ConfigMap => "ConfigMap",
+            Kind::Secret => "Secret",
+        };
+        match &self.namespace {
+            Some(namespace) => write!(f, "{kind} '{namespace}/{}'", self.name),
+            None => write!(f, "{kind} '{}'", self.name),
+        }
+    }
+}
+
+/// ConfigMap or Secret, as returned by the API server
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Object {
+    metadata: Metadata,
+    #[serde(default)]
+    data: HashMap<String, String>,
+    #[serde(default)]
+    binary_data: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    resource_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: serde_json::Value,
+}
+
+/// Client of the API server, authenticated with the service account of the pod
+struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    namespace: String,
+}
+
+impl Client {
+    fn in_cluster() -> Result<Self, String> {
+        let host = std::env::var("KUBERNETES_SERVICE_HOST").map_err(|_| {
+            "KUBERNETES_SERVICE_HOST is not set: ConfigMaps and Secrets can only be watched from a pod"
+                .to_string()
+        })?;
+        let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+        let base_url = if host.contains(':') {
+            format!("https://[{host}]:{port}")
+        } else {
+            format!("https://{host}:{port}")
+        };
+
+        let service_account = Path::new(SERVICE_ACCOUNT_DIR);
+        let ca = std::fs::read(service_account.join("ca.crt"))
+            .map_err(|e| format!("could not read the service account CA certificate: {e}"))?;
+        let namespace = std::fs::read_to_string(service_account.join("namespace"))
+            .map_err(|e| format!("could not read the service account namespace: {e}"))?;
+        let http = reqwest::Client::builder()
+            .add_root_certificate(
+                reqwest::Certificate::from_pem(&ca)
+                    .map_err(|e| format!("invalid service account CA certificate: {e}"))?,
+            )
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Client {
+            http,
+            base_url,
+            namespace: namespace.trim().to_string(),
+        })
+    }
+
+    fn request(&self, path: &str) -> Result<reqwest::RequestBuilder, String> {
+        // Projected service account tokens are rotated by the kubelet, so the token is read again
+        // for each request
+        let token = std::fs::read_to_string(Path::new(SERVICE_ACCOUNT_DIR).join("token"))
+            .map_err(|e| format!("could not read the service account token: {e}"))?;
+        Ok(self
+            .http
+            .get(format!("{}{path}", self.base_url))
+            .bearer_auth(token.trim()))
+    }
+
+    async fn get(&self, object: &ObjectRef) -> Result<Object, String> {
+        let path = format!(
+            "{}/{}",
+            object.collection_path(&self.namespace),
+            object.name
+        );
+        self.request(&path)?
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("could not get {object}: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("could not get {object}: {e}"))
+    }
+}
+
+/// Watches a key of a ConfigMap or Secret
+///
+/// The current value is sent first, then each new value. Without `watch`, the stream ends after
+/// the first value.
+pub(crate) fn stream(url: Url, watch: bool) -> impl Stream<Item = Result<String, String>> {
+    let (sender, receiver) = channel(2);
+    let _ = tokio::task::spawn(
+        async move {
+            let (object, client) = match ObjectRef::parse(&url)
+                .and_then(|object| Client::in_cluster().map(|client| (object, client)))
+            {
+                Ok(ok) => ok,
+                Err(err) => {
+                    let _ = sender.send(Err(err)).await;
+                    return;
+                }
+            };
+
+            let mut current = None;
+            while !sender.is_closed() {
+                let result = match client.get(&object).await {
+                    Ok(fetched) => {
+                        send_value(&sender, &object, &fetched, &mut current).await;
+                        if !watch {
+                            break;
+                        }
+                        let resource_version = fetched.metadata.resource_version;
+                        watch_object(&client, &object, resource_version, &sender, &mut current)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+                // When the watch ends without error, the object is fetched again
+                if let Err(err) = result {
+                    if sender.send(Err(err)).await.is_err() || !watch {
+                        break;
+                    }
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+        .with_current_subscriber(),
+    );
+
+    ReceiverStream::new(receiver)
+}
+
+/// Sends the value of the key if it changed
+async fn send_value(
+    sender: &Sender<Result<String, String>>,
+    object: &ObjectRef,
+    fetched: &Object,
+    current: &mut Option<String>,
+) {
+    match object.value(fetched) {
+        Ok(value) if current.as_ref() == Some(&value) => {
+            tracing::trace!("{object} did not change");
+        }
+        Ok(value) => {
+            *current = Some(value.clone());
+            let _ = sender.send(Ok(value)).await;
+        }
+        Err(err) => {
+            let _ = sender.send(Err(err)).await;
+        }
+    }
+}
+
+async fn watch_object(
+    client: &Client,
+    object: &ObjectRef,
+    resource_version: String,
+    sender: &Sender<Result<String, String>>,
+    current: &mut Option<String>,
+) -> Result<(), String> {
+    let response = client
+        .request(&object.collection_path(&client.namespace))?
+        .query(&[
+            ("watch", "true".to_string()),
+            ("fieldSelector", format!("metadata.name={}", object.name)),
+            ("resourceVersion", resource_version),
+            ("timeoutSeconds", WATCH_TIMEOUT_SECONDS.to_string()),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("could not watch {object}: {e}"))?;
+
+    // Events are sent as JSON documents separated by new lines
+    let mut body = response.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| format!("could not watch {object}: {e}"))?;
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let event: WatchEvent = serde_json::from_slice(&line)
+                .map_err(|e| format!("invalid watch event for {object}: {e}"))?;
+            match event.kind.as_str() {
+                "ADDED" | "MODIFIED" => {
+                    let fetched: Object = serde_json::from_value(event.object)
+                        .map_err(|e| format!("invalid watch event for {object}: {e}"))?;
+                    send_value(sender, object, &fetched, current).await;
+                    if sender.is_closed() {
+                        return Ok(());
+                    }
+                }
+                "DELETED" => {
+                    tracing::warn!("{object} was deleted, keeping its last value");
+                }
+                "ERROR" => {
+                    // 410 Gone: the resource version is too old, and the object must be fetched again
+                    if event.object.get("code").and_then(|code| code.as_u64()) == Some(410) {
+                        return Ok(());
+                    }
+                    let message = event.object.get("message").and_then(|m| m.as_str());
+                    return Err(format!(
+                        "could not watch {object}: {}",
+                        message.unwrap_or("unknown error")
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn object(url: &str) -> ObjectRef {
+        ObjectRef::parse(&Url::parse(url).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn it_parses_object_urls() {
+        assert_eq!(
+            object("k8s://configmap/router/supergraph.graphql"),
+            ObjectRef {
+                kind: Kind::ConfigMap,
+                namespace: None,
+                name: "router".to_string(),
+                key: "supergraph.graphql".to_string(),
+            }
+        );
+        let secret = object("k8s://secret/production/router/router.yaml");
+        assert_eq!(secret.kind, Kind::Secret);
+        assert_eq!(secret.namespace.as_deref(), Some("production"));
+        assert_eq!(
+            secret.collection_path("default"),
+            "/api/v1/namespaces/production/secrets"
+        );
+        assert_eq!(
+            object("k8s://configmap/router/router.yaml").collection_path("default"),
+            "/api/v1/namespaces/default/configmaps"
+        );
+
+        for url in [
+            "k8s://deployment/router/router.yaml",
+            "k8s://configmap/router",
+            "k8s://configmap/a/b/c/d",
+            "k8s://configmap//router.yaml",
+            "https://configmap/router/router.yaml",
+        ] {
+            assert!(
+                ObjectRef::parse(&Url::parse(url).unwrap()).is_err(),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_extracts_values() {
+        let fetched: Object = serde_json::from_value(json!({
+            "metadata": { "name": "router", "resourceVersion": "42" },
+            "data": { "router.yaml": "c3VwZXJncmFwaDoge30K" },
+            "binaryData": { "schema.graphql": "dHlwZSBRdWVyeSB7IGE6IEludCB9" },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            object("k8s://secret/router/router.yaml").value(&fetched),
+            Ok("supergraph: {}\n".to_string())
+        );
+        assert_eq!(
+            object("k8s://configmap/router/router.yaml").value(&fetched),
+            Ok("c3VwZXJncmFwaDoge30K".to_string())
+        );
+        assert_eq!(
+            object("k8s://configmap/router/schema.graphql").value(&fetched),
+            Ok("type Query { a: Int }".to_string())
+        );
+        assert_eq!(
+            object("k8s://secret/router/missing").value(&fetched),
+            Err("Secret 'router' has no key 'missing'".to_string())
+        );
+    }
+}
@@ -47,10 +47,18 @@ mod json_ext;
 #[macro_use]
 pub mod plugin;
 
+#[cfg(feature = "allocation_metrics")]
+mod allocation;
 pub(crate) mod axum_factory;
+mod bench;
 mod cache;
+mod clock;
+mod composition;
+mod compute_job;
 mod configuration;
 mod context;
+#[cfg(unix)]
+mod cpu_profiling;
 mod error;
 mod executable;
 mod files;
@@ -58,33 +66,55 @@ pub mod graphql;
 mod http_ext;
 mod http_server_factory;
 mod introspection;
+#[cfg(all(feature = "jemalloc", unix))]
+mod jemalloc;
+mod kubernetes;
 pub mod layers;
+mod log_filter;
+mod nats;
+mod object_storage;
 mod orbiter;
 mod plugins;
+mod progressive_override;
 mod query_planner;
+mod redaction;
 mod request;
 mod response;
 mod router;
 mod router_factory;
+mod schema_change;
+mod serverless;
 pub mod services;
 pub(crate) mod spec;
 mod state_machine;
 mod test_harness;
 pub mod tracer;
 mod uplink;
+mod version_metrics;
 
+pub use crate::bench::Bench;
+pub use crate::bench::BenchReport;
+pub use crate::bench::LatencySummary;
 pub use crate::configuration::Configuration;
 pub use crate::configuration::ListenAddr;
 pub use crate::context::Context;
 pub use crate::executable::main;
 pub use crate::executable::Executable;
+pub use crate::plugins::fault_injection::Fault;
+pub use crate::plugins::fault_injection::SubgraphFaults;
+pub use crate::query_planner::normalization::OperationSignature;
 pub use crate::router::ApolloRouterError;
 pub use crate::router::ConfigurationSource;
 pub use crate::router::RouterHttpServer;
 pub use crate::router::SchemaSource;
 pub use crate::router::ShutdownSource;
 pub use crate::router_factory::Endpoint;
+pub use crate::serverless::ServerlessHandler;
 pub use crate::test_harness::MockedSubgraphs;
+pub use crate::test_harness::MultipartResponse;
+pub use crate::test_harness::RecordedSubgraphRequest;
+pub use crate::test_harness::RecordedSubgraphRequests;
+pub use crate::test_harness::SubgraphFixturesMode;
 pub use crate::test_harness::TestHarness;
 
 /// Not part of the public API
@@ -0,0 +1,76 @@
+//! Log filter changed at runtime, from the admin API
+//!
+//! The filter of the global subscriber, set at startup from `--log` or `APOLLO_ROUTER_LOG`, can be
+//! replaced without restarting the router, to enable debug logs for a single module during an
+//! incident, then reset to the startup filter.
+
+use std::sync::Mutex;
+
+use once_cell::sync::OnceCell;
+use tower::BoxError;
+use tracing_subscriber::EnvFilter;
+
+use crate::executable::GLOBAL_ENV_FILTER;
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), BoxError> + Send + Sync>;
+
+/// The filter of the global subscriber, when it can be reloaded
+static LOG_FILTER: OnceCell<LogFilter> = OnceCell::new();
+
+struct LogFilter {
+    reload: Reload,
+    current: Mutex<String>,
+}
+
+/// Registers the reload handle of the global subscriber, set up with the startup filter
+pub(crate) fn install<F>(reload: F)
+where
+    F: Fn(EnvFilter) -> Result<(), BoxError> + Send + Sync + 'static,
+{
+    let _ = LOG_FILTER.set(LogFilter {
+        reload: Box::new(reload),
+        current: Mutex::new(startup_filter().to_string()),
+    });
+}
+
+/// The directives of the current filter, if it can be changed
+pub(crate) fn current() -> Option<String> {
+    LOG_FILTER
+        .get()
+        .map(|filter| filter.current.lock().expect("lock poisoned").clone())
+}
+
+/// Replaces the filter with the directives, such as `info,apollo_router::query_planner=debug`, or
+/// with the startup filter if there are none. Returns the directives of the new filter
+pub(crate) fn set(directives: Option<&str>) -> Result<String, BoxError> {
+    let directives = directives.unwrap_or_else(|| startup_filter()).trim();
+    let env_filter = EnvFilter::try_new(directives)
+        .map_err(|e| format!("invalid log filter '{directives}': {e}"))?;
+    let filter = LOG_FILTER
+        .get()
+        .ok_or("the log filter cannot be changed with this subscriber")?;
+    let mut current = filter.current.lock().expect("lock poisoned");
+    (filter.reload)(env_filter)?;
+    *current = directives.to_string();
+    Ok(directives.to_string())
+}
+
+fn startup_filter() -> &'static str {
+    GLOBAL_ENV_FILTER
+        .get()
+        .map(|s| s.as_str())
+        .unwrap_or("info")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rejects_invalid_directives() {
+        let error = set(Some("apollo_router=loud")).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("invalid log filter 'apollo_router=loud'"));
+    }
+}
@@ -0,0 +1,180 @@
+//! Client protocol of NATS, without TLS, shared by the subgraphs called over NATS and the
+//! subscriptions resolved from NATS events
+//!
+//! The connection is opened with the credentials of the URL, a user and password or a token.
+//! The commands are written as text by the callers, and the operations sent by the server are
+//! read with [`Reader::next`].
+
+use bytes::Bytes;
+use http::HeaderMap;
+use serde_json::json;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tower::BoxError;
+
+const DEFAULT_PORT: u16 = 4222;
+
+/// An operation sent by the server
+pub(crate) enum Incoming {
+    /// A message published to a subject the client subscribed to
+    Message {
+        subject: String,
+        /// The header block, empty for the messages without headers
+        headers: Bytes,
+        payload: Bytes,
+    },
+    /// A ping, which the client must answer with `PONG`
+    Ping,
+    /// The other operations, such as the `PONG` answering the pings of the client
+    Other,
+}
+
+/// Connects to the server. With `headers`, the messages can have headers, and requests published
+/// to subjects without subscribers get a reply with a 503 status instead of timing out
+pub(crate) async fn connect(
+    url: &url::Url,
+    headers: bool,
+) -> Result<(Reader, OwnedWriteHalf), BoxError> {
+    let host = url.host_str().ok_or("the NATS URL has no host")?;
+    let stream = TcpStream::connect((host, url.port().unwrap_or(DEFAULT_PORT))).await?;
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = Reader {
+        reader: BufReader::new(reader),
+        line: String::new(),
+    };
+
+    reader.reader.read_line(&mut reader.line).await?;
+    if !reader.line.starts_with("INFO ") {
+        return Err(format!(
+            "unexpected greeting from the NATS server: {}",
+            reader.line.trim()
+        )
+        .into());
+    }
+    let mut connect = json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "apollo-router",
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    if headers {
+        connect["headers"] = true.into();
+        connect["no_responders"] = true.into();
+    }
+    match (url.username(), url.password()) {
+        ("", _) => {}
+        (user, Some(password)) => {
+            connect["user"] = user.into();
+            connect["pass"] = password.into();
+        }
+        (token, None) => connect["auth_token"] = token.into(),
+    }
+    writer
+        .write_all(format!("CONNECT {connect}\r\n").as_bytes())
+        .await?;
+    Ok((reader, writer))
+}
+
+/// Reads the operations sent by the server
+pub(crate) struct Reader {
+    reader: BufReader<OwnedReadHalf>,
+    line: String,
+}
+
+impl Reader {
+    /// Reads the next operation. Errors sent by the server close the connection, so they are
+    /// returned as errors
+    pub(crate) async fn next(&mut self) -> Result<Incoming, BoxError> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line).await? == 0 {
+            return Err("the NATS server closed the connection".into());
+        }
+        let line = self.line.trim();
+        let mut parts = line.split_whitespace();
+        let (subject, header_size, size) = match parts.next() {
+            // MSG <subject> <sid> [reply-to] <#bytes>
+            Some("MSG") => {
+                let parts = parts.collect::<Vec<_>>();
+                let size = parts.last().and_then(|size| size.parse::<usize>().ok());
+                (parts.first().copied(), Some(0), size)
+            }
+            // HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>
+            Some("HMSG") => {
+                let parts = parts.collect::<Vec<_>>();
+                let sizes = parts.len().checked_sub(2).map(|start| &parts[start..]);
+                (
+                    parts.first().copied(),
+                    sizes.and_then(|sizes| sizes[0].parse::<usize>().ok()),
+                    sizes.and_then(|sizes| sizes[1].parse::<usize>().ok()),
+                )
+            }
+            Some("PING") => return Ok(Incoming::Ping),
+            Some("-ERR") => return Err(format!("NATS server error: {}", line[4..].trim()).into()),
+            _ => return Ok(Incoming::Other),
+        };
+        let (subject, header_size, size) = match (subject, header_size, size) {
+            (Some(subject), Some(header_size), Some(size)) if header_size <= size => {
+                (subject.to_string(), header_size, size)
+            }
+            _ => return Err(format!("invalid NATS message: {line}").into()),
+        };
+
+        // the payload is followed by CRLF
+        let mut message = vec![0; size + 2];
+        self.reader.read_exact(&mut message).await?;
+        message.truncate(size);
+        let message = Bytes::from(message);
+        Ok(Incoming::Message {
+            subject,
+            headers: message.slice(..header_size),
+            payload: message.slice(header_size..),
+        })
+    }
+}
+
+/// HPUB <subject> <reply-to> <#header bytes> <#total bytes>
+pub(crate) fn publish(
+    subject: &str,
+    reply_to: &str,
+    headers: &HeaderMap,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut header_block = String::from("NATS/1.0\r\n");
+    for (name, value) in headers {
+        // NATS headers are text
+        if let Ok(value) = value.to_str() {
+            header_block.push_str(name.as_str());
+            header_block.push_str(": ");
+            header_block.push_str(value);
+            header_block.push_str("\r\n");
+        }
+    }
+    header_block.push_str("\r\n");
+
+    let mut command = format!(
+        "HPUB {subject} {reply_to} {} {}\r\n{header_block}",
+        header_block.len(),
+        header_block.len() + payload.len()
+    )
+    .into_bytes();
+    command.extend_from_slice(payload);
+    command.extend_from_slice(b"\r\n");
+    command
+}
+
+/// The status of the header block, from its first line: `NATS/1.0 <status> [description]`
+pub(crate) fn status(headers: &[u8]) -> Option<&str> {
+    let first_line = headers.split(|byte| *byte == b'\r').next()?;
+    std::str::from_utf8(first_line)
+        .ok()?
+        .strip_prefix("NATS/1.0")?
+        .split_whitespace()
+        .next()
+}
@@ -37,8 +37,14 @@ use tower::BoxError;
 use tower::Service;
 use tower::ServiceBuilder;
 
+use crate::clock::Clock;
+use crate::clock::SystemClock;
+use crate::graphql;
 use crate::layers::ServiceBuilderExt;
+pub use crate::plugins::custom_scalars::register_scalar_validator;
+pub use crate::plugins::custom_scalars::ScalarValidator;
 use crate::router_factory::Endpoint;
+pub use crate::schema_change::SchemaChange;
 use crate::services::execution;
 use crate::services::router;
 use crate::services::subgraph;
@@ -50,6 +56,8 @@ type InstanceFactory =
 
 type SchemaFactory = fn(&mut SchemaGenerator) -> schemars::schema::Schema;
 
+type ConfigurationValidator = fn(&serde_json::Value) -> Result<(), BoxError>;
+
 /// Global list of plugins.
 #[linkme::distributed_slice]
 pub static PLUGINS: [Lazy<PluginFactory>] = [..];
@@ -61,6 +69,8 @@ pub struct PluginInit<T> {
     pub config: T,
     /// Router Supergraph Schema (schema definition language)
     pub supergraph_sdl: Arc<String>,
+    /// Source of the current time
+    pub(crate) clock: Arc<dyn Clock>,
 }
 
 impl<T> PluginInit<T>
@@ -72,6 +82,7 @@ where
         PluginInit {
             config,
             supergraph_sdl,
+            clock: SystemClock::shared(),
         }
     }
 
@@ -87,8 +98,16 @@ where
         Ok(PluginInit {
             config,
             supergraph_sdl,
+            clock: SystemClock::shared(),
         })
     }
+
+    /// Replace the source of the current time, for tests of time-dependent behavior.
+    #[cfg(test)]
+    pub(crate) fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
 }
 
 /// Factories for plugin schema and configuration.
@@ -97,6 +116,7 @@ pub struct PluginFactory {
     pub(crate) name: String,
     instance_factory: InstanceFactory,
     schema_factory: SchemaFactory,
+    configuration_validator: ConfigurationValidator,
     pub(crate) type_id: TypeId,
 }
 
@@ -128,6 +148,10 @@ impl PluginFactory {
                 })
             },
             schema_factory: |gen| gen.subschema_for::<<P as Plugin>::Config>(),
+            configuration_validator: |configuration| {
+                serde_json::from_value::<<P as Plugin>::Config>(configuration.clone())?;
+                Ok(())
+            },
             type_id: TypeId::of::<P>(),
         }
     }
@@ -151,6 +175,14 @@ impl PluginFactory {
     pub(crate) fn create_schema(&self, gen: &mut SchemaGenerator) -> schemars::schema::Schema {
         (self.schema_factory)(gen)
     }
+
+    /// Checks that the configuration can be deserialized, without creating the plugin
+    pub(crate) fn validate_configuration(
+        &self,
+        configuration: &serde_json::Value,
+    ) -> Result<(), BoxError> {
+        (self.configuration_validator)(configuration)
+    }
 }
 
 // If we wanted to create a custom subset of plugins, this is where we would do it
@@ -231,6 +263,22 @@ pub trait Plugin: Send + Sync + 'static {
         MultiMap::new()
     }
 
+    /// This is invoked when the router switched to a new supergraph schema, on the plugin instance
+    /// created for it, with the types and fields added and removed since the previous schema.
+    /// It is not invoked for the first schema, nor when only the configuration changed.
+    fn schema_changed(&self, _change: &SchemaChange) {}
+
+    /// This is invoked on each GraphQL response of the supergraph service, including the errors
+    /// of requests rejected at the supergraph stage, right before the router service serializes it.
+    /// With `@defer`, it is invoked on the primary response and on each incremental response.
+    /// Plugins are invoked in the order of the configuration.
+    /// Responses that never reach the supergraph service are not formatted: request bodies that
+    /// are not valid GraphQL requests, and responses short-circuited by the content negotiation,
+    /// CSRF protection, traffic shaping, or by `router_service` hooks.
+    /// Define `format_response` to apply conventions to responses in one place, such as the
+    /// shape of errors or extensions.
+    fn format_response(&self, _context: &crate::Context, _response: &mut graphql::Response) {}
+
     /// Support downcasting.
     #[cfg(test)]
     fn as_any(&self) -> &dyn std::any::Any
@@ -282,6 +330,12 @@ pub(crate) trait DynPlugin: Send + Sync + 'static {
     /// Return one or several `Endpoint`s and `ListenAddr` and the router will serve your custom web Endpoint(s).
     fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint>;
 
+    /// This is invoked when the router switched to a new supergraph schema.
+    fn schema_changed(&self, change: &SchemaChange);
+
+    /// This is invoked on each GraphQL response right before it is serialized.
+    fn format_response(&self, context: &crate::Context, response: &mut graphql::Response);
+
     fn as_any(&self) -> &dyn std::any::Any;
 }
 
@@ -316,6 +370,14 @@ where
         self.web_endpoints()
     }
 
+    fn schema_changed(&self, change: &SchemaChange) {
+        self.schema_changed(change)
+    }
+
+    fn format_response(&self, context: &crate::Context, response: &mut graphql::Response) {
+        self.format_response(context, response)
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
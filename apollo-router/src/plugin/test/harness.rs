@@ -0,0 +1,195 @@
+//! Harness exercising a single hook of a plugin
+
+use std::sync::Arc;
+
+use tower::BoxError;
+use tower::Service;
+
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::services::execution;
+use crate::services::router;
+use crate::services::subgraph;
+use crate::services::supergraph;
+
+/// Exercises the hooks of one plugin in isolation.
+///
+/// Each hook method wraps a downstream service with the corresponding hook of the plugin,
+/// without building the rest of the router pipeline. The downstream service is typically
+/// a mock such as [`MockSupergraphService`][super::MockSupergraphService],
+/// or a [`tower::service_fn`] returning a canned response.
+///
+/// ```
+/// use apollo_router::plugin::test::MockSupergraphService;
+/// use apollo_router::plugin::test::PluginTestHarness;
+/// # use apollo_router::plugin::Plugin;
+/// # use apollo_router::plugin::PluginInit;
+/// use apollo_router::services::supergraph;
+/// use tower::ServiceExt;
+/// # struct MyPlugin;
+/// # #[async_trait::async_trait]
+/// # impl Plugin for MyPlugin {
+/// #     type Config = ();
+/// #     async fn new(_: PluginInit<()>) -> Result<Self, tower::BoxError> { Ok(MyPlugin) }
+/// # }
+///
+/// # #[tokio::main] async fn main() -> Result<(), tower::BoxError> {
+/// let harness = PluginTestHarness::<MyPlugin>::new(serde_json::Value::Null).await?;
+///
+/// let mut downstream = MockSupergraphService::new();
+/// downstream
+///     .expect_call()
+///     .times(1)
+///     .returning(|request| supergraph::Response::fake_builder().context(request.context).build());
+///
+/// let _response = harness
+///     .supergraph_service(downstream)
+///     .oneshot(supergraph::Request::fake_builder().build()?)
+///     .await?;
+/// # Ok(()) }
+/// ```
+pub struct PluginTestHarness<P: Plugin> {
+    plugin: P,
+}
+
+impl<P: Plugin> PluginTestHarness<P> {
+    /// Creates the plugin from its configuration, with an empty supergraph schema.
+    pub async fn new(config: serde_json::Value) -> Result<Self, BoxError> {
+        Self::with_schema(config, "").await
+    }
+
+    /// Creates the plugin from its configuration and the supergraph schema given to it.
+    pub async fn with_schema(
+        config: serde_json::Value,
+        supergraph_sdl: &str,
+    ) -> Result<Self, BoxError> {
+        let init = PluginInit::try_new(config, Arc::new(supergraph_sdl.to_string()))?;
+        Ok(Self::from_plugin(P::new(init).await?))
+    }
+
+    /// Uses an already created plugin.
+    pub fn from_plugin(plugin: P) -> Self {
+        Self { plugin }
+    }
+
+    /// The plugin under test
+    pub fn plugin(&self) -> &P {
+        &self.plugin
+    }
+
+    /// Wraps `downstream` with the [`Plugin::router_service`] hook.
+    pub fn router_service<S>(&self, downstream: S) -> router::BoxService
+    where
+        S: Service<router::Request, Response = router::Response, Error = BoxError> + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        self.plugin
+            .router_service(router::BoxService::new(downstream))
+    }
+
+    /// Wraps `downstream` with the [`Plugin::supergraph_service`] hook.
+    pub fn supergraph_service<S>(&self, downstream: S) -> supergraph::BoxService
+    where
+        S: Service<supergraph::Request, Response = supergraph::Response, Error = BoxError>
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.plugin
+            .supergraph_service(supergraph::BoxService::new(downstream))
+    }
+
+    /// Wraps `downstream` with the [`Plugin::execution_service`] hook.
+    pub fn execution_service<S>(&self, downstream: S) -> execution::BoxService
+    where
+        S: Service<execution::Request, Response = execution::Response, Error = BoxError>
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.plugin
+            .execution_service(execution::BoxService::new(downstream))
+    }
+
+    /// Wraps `downstream` with the [`Plugin::subgraph_service`] hook for `subgraph_name`.
+    pub fn subgraph_service<S>(&self, subgraph_name: &str, downstream: S) -> subgraph::BoxService
+    where
+        S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.plugin
+            .subgraph_service(subgraph_name, subgraph::BoxService::new(downstream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraphService;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct Conf {
+        header_value: String,
+    }
+
+    struct InsertHeader(HeaderValue);
+
+    #[async_trait::async_trait]
+    impl Plugin for InsertHeader {
+        type Config = Conf;
+
+        async fn new(init: PluginInit<Conf>) -> Result<Self, BoxError> {
+            Ok(Self(init.config.header_value.try_into()?))
+        }
+
+        fn subgraph_service(
+            &self,
+            _name: &str,
+            service: subgraph::BoxService,
+        ) -> subgraph::BoxService {
+            let value = self.0.clone();
+            service
+                .map_request(move |mut request: subgraph::Request| {
+                    request
+                        .subgraph_request
+                        .headers_mut()
+                        .insert("x-test", value.clone());
+                    request
+                })
+                .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_exercises_one_hook() {
+        let harness =
+            PluginTestHarness::<InsertHeader>::new(serde_json::json!({"header_value": "a"}))
+                .await
+                .unwrap();
+
+        let mut downstream = MockSubgraphService::new();
+        downstream
+            .expect_call()
+            .times(1)
+            .returning(|request: subgraph::Request| {
+                assert_eq!(
+                    request.subgraph_request.headers().get("x-test").unwrap(),
+                    "a"
+                );
+                Ok(subgraph::Response::fake_builder().build())
+            });
+
+        harness
+            .subgraph_service("products", downstream)
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+    }
+}
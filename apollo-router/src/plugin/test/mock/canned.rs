@@ -20,10 +20,6 @@ pub(crate) fn accounts_subgraph() -> MockSubgraph {
                             {
                                 "__typename": "User",
                                 "id": "2"
-                            },
-                            {
-                                "__typename": "User",
-                                "id": "1"
                             }
                         ]
                     }
@@ -36,9 +32,6 @@ pub(crate) fn accounts_subgraph() -> MockSubgraph {
                             },
                             {
                                 "name": "Alan Turing"
-                            },
-                            {
-                                "name": "Ada Lovelace"
                             }
                         ]
                     }
@@ -158,10 +151,6 @@ pub(crate) fn products_subgraph() -> MockSubgraph {
                                 "__typename": "Product",
                                 "upc": "1"
                             },
-                            {
-                                "__typename": "Product",
-                                "upc": "1"
-                            },
                             {
                                 "__typename": "Product",
                                 "upc": "2"
@@ -172,9 +161,6 @@ pub(crate) fn products_subgraph() -> MockSubgraph {
             json!{{
                     "data": {
                         "_entities": [
-                            {
-                                "name": "Table"
-                            },
                             {
                                 "name": "Table"
                             },
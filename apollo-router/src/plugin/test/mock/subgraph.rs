@@ -4,6 +4,7 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::task::Poll;
 
 use futures::future;
@@ -24,13 +25,16 @@ pub struct MockSubgraph {
     // using an arc to improve efficiency when service is cloned
     mocks: Arc<MockResponses>,
     extensions: Option<Object>,
+    expected_calls: Arc<HashMap<Request, usize>>,
+    // shared between clones, so calls are counted across all the requests of a test
+    calls: Arc<Mutex<HashMap<Request, usize>>>,
 }
 
 impl MockSubgraph {
     pub fn new(mocks: MockResponses) -> Self {
         Self {
             mocks: Arc::new(mocks),
-            extensions: None,
+            ..Default::default()
         }
     }
 
@@ -42,6 +46,41 @@ impl MockSubgraph {
         self.extensions = Some(extensions);
         self
     }
+
+    /// number of times this subgraph received the request
+    ///
+    /// the argument must deserialize to `crate::graphql::Request`
+    pub fn calls(&self, request: serde_json::Value) -> usize {
+        let request: Request = serde_json::from_value(request).unwrap();
+        self.calls
+            .lock()
+            .expect("lock poisoned")
+            .get(&request)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// total number of requests received by this subgraph, including those without a mock
+    pub fn total_calls(&self) -> usize {
+        self.calls.lock().expect("lock poisoned").values().sum()
+    }
+
+    /// panics if a request registered with [`MockSubgraphBuilder::with_json_expected`]
+    /// was not received the expected number of times
+    pub fn assert_expectations(&self) {
+        let calls = self.calls.lock().expect("lock poisoned");
+        for (request, expected) in self.expected_calls.iter() {
+            let actual = calls.get(request).copied().unwrap_or_default();
+            assert_eq!(
+                actual,
+                *expected,
+                "expected {} call(s) for query {}, got {}",
+                expected,
+                serde_json::to_string(request).unwrap(),
+                actual
+            );
+        }
+    }
 }
 
 /// Builder for `MockSubgraph`
@@ -49,6 +88,7 @@ impl MockSubgraph {
 pub struct MockSubgraphBuilder {
     mocks: MockResponses,
     extensions: Option<Object>,
+    expected_calls: HashMap<Request, usize>,
 }
 impl MockSubgraphBuilder {
     pub fn with_extensions(mut self, extensions: Object) -> Self {
@@ -68,10 +108,29 @@ impl MockSubgraphBuilder {
         self
     }
 
+    /// adds a mocked response for a request that must be received exactly `times` times
+    ///
+    /// the expectation is checked by [`MockSubgraph::assert_expectations`]
+    pub fn with_json_expected(
+        mut self,
+        request: serde_json::Value,
+        response: serde_json::Value,
+        times: usize,
+    ) -> Self {
+        let request: Request = serde_json::from_value(request).unwrap();
+        self.expected_calls.insert(request.clone(), times);
+        self.mocks
+            .insert(request, serde_json::from_value(response).unwrap());
+
+        self
+    }
+
     pub fn build(self) -> MockSubgraph {
         MockSubgraph {
             mocks: Arc::new(self.mocks),
             extensions: self.extensions,
+            expected_calls: Arc::new(self.expected_calls),
+            calls: Default::default(),
         }
     }
 }
@@ -88,6 +147,13 @@ impl Service<SubgraphRequest> for MockSubgraph {
     }
 
     fn call(&mut self, req: SubgraphRequest) -> Self::Future {
+        *self
+            .calls
+            .lock()
+            .expect("lock poisoned")
+            .entry(req.subgraph_request.body().clone())
+            .or_default() += 1;
+
         let response = if let Some(response) = self.mocks.get(req.subgraph_request.body()) {
             // Build an http Response
             let http_response = http::Response::builder()
@@ -1,11 +1,14 @@
 //! Utilities which make it easy to test with [`crate::plugin`].
 
+mod harness;
 mod mock;
 #[macro_use]
 mod service;
 
+pub use harness::PluginTestHarness;
 pub use mock::subgraph::MockSubgraph;
 pub use service::MockExecutionService;
+pub use service::MockRouterService;
 pub use service::MockSubgraphService;
 pub use service::MockSupergraphService;
 
@@ -3,6 +3,8 @@
 
 use crate::services::ExecutionRequest;
 use crate::services::ExecutionResponse;
+use crate::services::RouterRequest;
+use crate::services::RouterResponse;
 use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
 use crate::services::SupergraphRequest;
@@ -43,6 +45,7 @@ macro_rules! mock_service {
     };
 }
 
+mock_service!(Router, RouterRequest, RouterResponse);
 mock_service!(Supergraph, SupergraphRequest, SupergraphResponse);
 mock_service!(Execution, ExecutionRequest, ExecutionResponse);
 mock_service!(Subgraph, SubgraphRequest, SubgraphResponse);
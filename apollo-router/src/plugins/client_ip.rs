@@ -0,0 +1,436 @@
+//! Client IP of the requests
+//!
+//! Behind proxies or load balancers, the peer address of the connections is the address of the
+//! last proxy. The proxies append the address of their own peer to the `Forwarded` or
+//! `X-Forwarded-For` header, which clients can also set to any value. The client IP is the
+//! rightmost address of the header that was not appended by a trusted proxy: the addresses on
+//! its left were set by the client or by untrusted proxies.
+//!
+//! The client IP is stored in the context, recorded in the router span, and checked against the
+//! allow and deny lists before the request is handled.
+
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use http::header::FORWARDED;
+use http::HeaderMap;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::http_server_factory::ConnectionInfo;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::router;
+
+/// The client IP of the request, in the context
+pub(crate) const CLIENT_IP_CONTEXT_KEY: &str = "apollo_router::client_ip";
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Client IP configuration
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Addresses or CIDR ranges of the proxies trusted to report the address of their peer,
+    /// such as `10.0.0.0/8`
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+
+    /// The header listing the addresses reported by the proxies
+    #[serde(default)]
+    header: ForwardedHeader,
+
+    /// Addresses or CIDR ranges of the clients allowed to send requests. All clients are
+    /// allowed if empty
+    #[serde(default)]
+    allow: Vec<String>,
+
+    /// Addresses or CIDR ranges of the clients denied from sending requests
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// The header listing the addresses reported by the proxies
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum ForwardedHeader {
+    /// The `Forwarded` header of RFC 7239
+    Forwarded,
+    /// The `X-Forwarded-For` header
+    XForwardedFor,
+}
+
+impl Default for ForwardedHeader {
+    fn default() -> Self {
+        ForwardedHeader::XForwardedFor
+    }
+}
+
+/// An IP address range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid address or CIDR range '{s}'");
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address = normalize(address.trim().parse().map_err(|_| invalid())?);
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(IpNetwork { address, prefix })
+    }
+}
+
+impl IpNetwork {
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, normalize(address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// IPv4 addresses mapped to IPv6, as reported by dual stack sockets, are compared as IPv4
+fn normalize(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => IpAddr::from([a, b, c, d]),
+            _ => address,
+        },
+        address => address,
+    }
+}
+
+fn parse_networks(networks: &[String]) -> Result<Vec<IpNetwork>, BoxError> {
+    Ok(networks
+        .iter()
+        .map(|network| network.parse())
+        .collect::<Result<_, _>>()?)
+}
+
+#[derive(Debug)]
+struct Resolver {
+    trusted_proxies: Vec<IpNetwork>,
+    header: ForwardedHeader,
+    allow: Vec<IpNetwork>,
+    deny: Vec<IpNetwork>,
+}
+
+impl Resolver {
+    fn is_trusted(&self, address: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(address))
+    }
+
+    /// Returns the client IP of a request received from `peer`
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(client) {
+            return client;
+        }
+        let forwarded = match self.header {
+            ForwardedHeader::Forwarded => forwarded_addresses(headers),
+            ForwardedHeader::XForwardedFor => x_forwarded_for_addresses(headers),
+        };
+        for address in forwarded.into_iter().rev() {
+            match address {
+                Some(address) => client = address,
+                // the address was hidden by a proxy, the client IP is unknown past it
+                None => break,
+            }
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_allowed(&self, client: IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(client)))
+            && !self.deny.iter().any(|network| network.contains(client))
+    }
+}
+
+/// The addresses of the `X-Forwarded-For` headers, from the farthest to the closest proxy
+fn x_forwarded_for_addresses(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|address| parse_address(address.trim()))
+        .collect()
+}
+
+/// The `for` addresses of the `Forwarded` headers, from the farthest to the closest proxy
+fn forwarded_addresses(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| {
+                    let node = node.trim().trim_matches('"');
+                    // IPv6 addresses are in brackets, with an optional port
+                    match node.strip_prefix('[') {
+                        Some(node) => parse_address(node.split(']').next().unwrap_or_default()),
+                        None => parse_address(node),
+                    }
+                })
+        })
+        .collect()
+}
+
+/// Parses an address, with an optional port. Obfuscated identifiers and `unknown` are `None`
+fn parse_address(address: &str) -> Option<IpAddr> {
+    address
+        .parse::<IpAddr>()
+        .or_else(|_| address.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+        .map(normalize)
+}
+
+#[derive(Debug)]
+struct ClientIp {
+    resolver: Arc<Resolver>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ClientIp {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(ClientIp {
+            resolver: Arc::new(Resolver {
+                trusted_proxies: parse_networks(&init.config.trusted_proxies)?,
+                header: init.config.header,
+                allow: parse_networks(&init.config.allow)?,
+                deny: parse_networks(&init.config.deny)?,
+            }),
+        })
+    }
+
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        let resolver = self.resolver.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |request: router::Request| {
+                let client = request
+                    .router_request
+                    .extensions()
+                    .get::<ConnectionInfo>()
+                    .map(|connection| {
+                        resolver.client_ip(
+                            connection.peer_address.ip(),
+                            request.router_request.headers(),
+                        )
+                    });
+                if let Some(client) = client {
+                    tracing::Span::current().record("client.address", client.to_string().as_str());
+                    request
+                        .context
+                        .insert(CLIENT_IP_CONTEXT_KEY, client.to_string())?;
+                }
+
+                // without a peer address, as on Unix sockets, only the allow list can reject
+                // requests
+                let allowed = match client {
+                    Some(client) => resolver.is_allowed(client),
+                    None => resolver.allow.is_empty(),
+                };
+                if allowed {
+                    Ok(ControlFlow::Continue(request))
+                } else {
+                    tracing::info!(monotonic_counter.apollo_router_client_ip_denied_total = 1u64);
+                    let response = router::Response::error_builder()
+                        .error(
+                            graphql::Error::builder()
+                                .message("the client IP is not allowed")
+                                .extension_code("CLIENT_IP_DENIED")
+                                .build(),
+                        )
+                        .status_code(StatusCode::FORBIDDEN)
+                        .context(request.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(response))
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "client_ip", ClientIp);
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use tower::Service;
+
+    use super::*;
+    use crate::plugin::test::MockRouterService;
+
+    fn resolver(header: ForwardedHeader) -> Resolver {
+        Resolver {
+            trusted_proxies: parse_networks(&["10.0.0.0/8".to_string(), "::1".to_string()])
+                .unwrap(),
+            header,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn it_parses_networks() {
+        let network: IpNetwork = "192.168.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("192.168.3.4")));
+        assert!(network.contains(ip("::ffff:192.168.3.4")));
+        assert!(!network.contains(ip("192.169.0.1")));
+        assert!("0.0.0.0/0"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(ip("1.2.3.4")));
+        assert!("2001:db8::/32"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(ip("2001:db8::1")));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("localhost".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn it_only_trusts_the_headers_set_by_trusted_proxies() {
+        let resolver = resolver(ForwardedHeader::XForwardedFor);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("1.1.1.1, 2.2.2.2, 10.0.0.2"),
+        );
+
+        // the addresses on the left of the first untrusted address can be spoofed
+        assert_eq!(resolver.client_ip(ip("10.0.0.1"), &headers), ip("2.2.2.2"));
+        // an untrusted peer is the client
+        assert_eq!(resolver.client_ip(ip("3.3.3.3"), &headers), ip("3.3.3.3"));
+        // without a header, the trusted proxy is the client
+        assert_eq!(
+            resolver.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn it_parses_the_forwarded_header() {
+        let resolver = resolver(ForwardedHeader::Forwarded);
+        let mut headers = HeaderMap::new();
+        headers.append(
+            FORWARDED,
+            HeaderValue::from_static("for=1.1.1.1;proto=https, for=\"[2001:db8::17]:4711\""),
+        );
+        headers.append(FORWARDED, HeaderValue::from_static("For=10.0.0.3:8080"));
+        assert_eq!(resolver.client_ip(ip("::1"), &headers), ip("2001:db8::17"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_static("for=1.1.1.1, for=_hidden"),
+        );
+        assert_eq!(resolver.client_ip(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_denied_clients() {
+        let plugin = ClientIp::new(PluginInit::new(
+            Conf {
+                trusted_proxies: vec!["10.0.0.0/8".to_string()],
+                deny: vec!["1.1.1.0/24".to_string()],
+                ..Default::default()
+            },
+            Default::default(),
+        ))
+        .await
+        .unwrap();
+
+        let mut mock_service = MockRouterService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |request: router::Request| {
+                assert_eq!(
+                    request
+                        .context
+                        .get::<_, String>(CLIENT_IP_CONTEXT_KEY)
+                        .unwrap()
+                        .unwrap(),
+                    "2.2.2.2"
+                );
+                router::Response::builder().context(request.context).build()
+            });
+        let mut service = plugin.router_service(router::BoxService::new(mock_service));
+
+        let request = |client: &'static str| {
+            let mut request = http::Request::builder()
+                .header(X_FORWARDED_FOR, client)
+                .body(hyper::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectionInfo {
+                peer_address: "10.0.0.1:1234".parse().unwrap(),
+            });
+            router::Request::from(request)
+        };
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request("1.1.1.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::FORBIDDEN);
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request("2.2.2.2"))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+    }
+}
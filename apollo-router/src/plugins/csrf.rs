@@ -10,6 +10,7 @@ use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use crate::axum_factory::ClientHeaders;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
@@ -37,6 +38,10 @@ pub(crate) struct CSRFConfig {
     /// `examples/cors-and-csrf/custom-headers.router.yaml` files.
     #[serde(default = "apollo_custom_preflight_headers")]
     required_headers: Vec<String>,
+    /// Paths of requests that are not checked, such as endpoints receiving form posts
+    /// from other services. A path ending with `*` exempts all the paths starting with it.
+    #[serde(default)]
+    exempt_paths: Vec<String>,
 }
 
 fn apollo_custom_preflight_headers() -> Vec<String> {
@@ -51,6 +56,7 @@ impl Default for CSRFConfig {
         Self {
             unsafe_disabled: false,
             required_headers: apollo_custom_preflight_headers(),
+            exempt_paths: Vec::new(),
         }
     }
 }
@@ -103,9 +109,13 @@ impl Plugin for Csrf {
     fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
         if !self.config.unsafe_disabled {
             let required_headers = self.config.required_headers.clone();
+            let exempt_paths = self.config.exempt_paths.clone();
             ServiceBuilder::new()
                 .checkpoint(move |req: supergraph::Request| {
-                    if is_preflighted(&req, required_headers.as_slice()) {
+                    if is_exempt(&req, exempt_paths.as_slice()) {
+                        tracing::trace!("request path is exempt from CSRF checks");
+                        Ok(ControlFlow::Continue(req))
+                    } else if is_preflighted(&req, required_headers.as_slice()) {
                         tracing::trace!("request is preflighted");
                         Ok(ControlFlow::Continue(req))
                     } else {
@@ -136,6 +146,16 @@ impl Plugin for Csrf {
     }
 }
 
+fn is_exempt(req: &supergraph::Request, exempt_paths: &[String]) -> bool {
+    let path = req.supergraph_request.uri().path();
+    exempt_paths
+        .iter()
+        .any(|exempt| match exempt.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == exempt,
+        })
+}
+
 // A `preflighted` request is the opposite of a `simple` request.
 //
 // A simple request is a request that satisfies the three predicates below:
@@ -143,9 +163,15 @@ impl Plugin for Csrf {
 // - If content-type is set, it must be with a mime type that is application/x-www-form-urlencoded OR multipart/form-data OR text/plain
 // - The only headers added by javascript code are part of the cors safelisted request headers (Accept,Accept-Language,Content-Language,Content-Type, and simple Range
 //
-// Given the first step is covered in our web browser, we'll take care of the two other steps below:
+// Given the first step is covered in our web browser, we'll take care of the two other steps below.
+//
+// The operations of WebSocket connections and REST routes are checked with the headers of the
+// client request, since their own content-type is set by the router.
 fn is_preflighted(req: &supergraph::Request, required_headers: &[String]) -> bool {
-    let headers = req.supergraph_request.headers();
+    let headers = match req.supergraph_request.extensions().get::<ClientHeaders>() {
+        Some(ClientHeaders(headers)) => headers,
+        None => req.supergraph_request.headers(),
+    };
     content_type_requires_preflight(headers)
         || recommended_header_is_provided(headers, required_headers)
 }
@@ -291,6 +317,48 @@ mod csrf_tests {
         assert_accepted(config, non_preflighted_request).await
     }
 
+    #[tokio::test]
+    async fn it_accepts_non_preflighted_requests_to_exempt_paths() {
+        let config = CSRFConfig {
+            exempt_paths: vec!["/hooks/*".to_string(), "/form".to_string()],
+            ..Default::default()
+        };
+        let request = |uri: &str| {
+            supergraph::Request::builder()
+                .uri(uri.parse::<http::Uri>().unwrap())
+                .method(http::Method::POST)
+                .header(CONTENT_TYPE, "text/plain")
+                .context(crate::Context::new())
+                .build()
+                .unwrap()
+        };
+        assert_accepted(config.clone(), request("http://localhost/hooks/github")).await;
+        assert_accepted(config.clone(), request("http://localhost/form")).await;
+        assert_rejected(config.clone(), request("http://localhost/form/other")).await;
+        assert_rejected(config, request("http://localhost/graphql")).await;
+    }
+
+    #[tokio::test]
+    async fn it_checks_the_upgrade_headers_of_websocket_operations() {
+        let config = CSRFConfig::default();
+        let request = |upgrade_headers: HeaderMap| {
+            let mut request = supergraph::Request::fake_builder()
+                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+                .build()
+                .unwrap();
+            request
+                .supergraph_request
+                .extensions_mut()
+                .insert(ClientHeaders(upgrade_headers));
+            request
+        };
+        assert_rejected(config.clone(), request(HeaderMap::new())).await;
+
+        let mut upgrade_headers = HeaderMap::new();
+        upgrade_headers.insert("apollo-require-preflight", "true".parse().unwrap());
+        assert_accepted(config, request(upgrade_headers)).await;
+    }
+
     async fn assert_accepted(config: CSRFConfig, request: supergraph::Request) {
         let mut mock_service = MockSupergraphService::new();
         mock_service.expect_call().times(1).returning(move |_| {
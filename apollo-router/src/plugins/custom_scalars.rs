@@ -0,0 +1,506 @@
+//! Validation and coercion of custom scalar variables
+//!
+//! The router cannot validate values of custom scalars by itself, and forwards them to subgraphs
+//! as they were sent. This plugin checks the variables of custom scalar types against the format
+//! configured for them, and against the validators registered by native plugins and Rhai
+//! scripts, before the operation is planned.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use apollo_parser::ast;
+use http::StatusCode;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::default_parser_recursion_limit;
+use crate::error::FetchError;
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Value;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::supergraph;
+use crate::services::SupergraphRequest;
+use crate::services::SupergraphResponse;
+use crate::spec::FieldType;
+use crate::spec::Schema;
+
+static DATE_TIME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d{4}-\d{2}-\d{2})[Tt](\d{2}:\d{2}:\d{2}(?:\.\d+)?)(?:[Zz]|[+-]\d{2}:\d{2})$")
+        .expect("valid regex")
+});
+static BIG_INT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^-?\d+$").expect("valid regex"));
+
+/// Validator of the values of a custom scalar, which can also coerce them to their canonical
+/// representation. It returns a description of the expected values, such as `an integer`, if
+/// the value is invalid
+pub type ScalarValidator = Arc<dyn Fn(&mut Value) -> Result<(), String> + Send + Sync>;
+
+static REGISTERED_VALIDATORS: Lazy<RwLock<HashMap<String, ScalarValidator>>> =
+    Lazy::new(Default::default);
+
+/// Registers a validator of the variables of a custom scalar, replacing the validator registered
+/// before for this scalar.
+///
+/// Native plugins call it when they are created. The validators are applied by the
+/// `custom_scalars` plugin, after the formats and patterns of its configuration, so the plugin
+/// must be enabled in the configuration.
+pub fn register_scalar_validator(scalar: impl Into<String>, validator: ScalarValidator) {
+    REGISTERED_VALIDATORS
+        .write()
+        .expect("lock poisoned")
+        .insert(scalar.into(), validator);
+}
+
+#[cfg(test)]
+pub(crate) fn registered_scalar_validator(scalar: &str) -> Option<ScalarValidator> {
+    REGISTERED_VALIDATORS
+        .read()
+        .expect("lock poisoned")
+        .get(scalar)
+        .cloned()
+}
+
+/// Custom scalars configuration
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Validation of the custom scalars of the schema, by scalar name
+    #[serde(default)]
+    scalars: HashMap<String, ScalarConf>,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+}
+
+/// Validation of a custom scalar
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ScalarConf {
+    /// Format of the values
+    format: Option<Format>,
+    /// Regular expression matched by the values, which must be strings
+    pattern: Option<String>,
+}
+
+/// Format of a custom scalar
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Format {
+    /// RFC 3339 date and time, such as `2022-11-28T10:00:00Z`
+    DateTime,
+    /// RFC 3339 full date, such as `2022-11-28`
+    Date,
+    /// Integer of any size, as a string of digits or a number. Numbers are coerced to strings
+    BigInt,
+    /// UUID, such as `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    Uuid,
+    /// Absolute URL
+    Url,
+}
+
+impl Format {
+    fn expected(&self) -> &'static str {
+        match self {
+            Format::DateTime => "an RFC 3339 date and time",
+            Format::Date => "an RFC 3339 date",
+            Format::BigInt => "an integer",
+            Format::Uuid => "a UUID",
+            Format::Url => "an absolute URL",
+        }
+    }
+
+    /// Checks the value, and converts it to its canonical representation
+    fn coerce(&self, value: &mut Value) -> bool {
+        match (self, &*value) {
+            (Format::DateTime, Value::String(s)) => {
+                DATE_TIME.captures(s.as_str()).map_or(false, |captures| {
+                    // The offset does not change whether the date and time are valid
+                    let utc = format!("{}T{}Z", &captures[1], &captures[2]);
+                    humantime::parse_rfc3339(&utc).is_ok()
+                })
+            }
+            (Format::Date, Value::String(s)) => {
+                s.as_str().len() == 10
+                    && humantime::parse_rfc3339(&format!("{}T00:00:00Z", s.as_str())).is_ok()
+            }
+            (Format::BigInt, Value::String(s)) => BIG_INT.is_match(s.as_str()),
+            (Format::BigInt, Value::Number(n)) if n.is_i64() || n.is_u64() => {
+                *value = n.to_string().into();
+                true
+            }
+            (Format::Uuid, Value::String(s)) => uuid::Uuid::parse_str(s.as_str()).is_ok(),
+            (Format::Url, Value::String(s)) => url::Url::parse(s.as_str()).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Validator {
+    format: Option<Format>,
+    pattern: Option<Regex>,
+}
+
+impl Validator {
+    fn new(conf: ScalarConf) -> Result<Self, BoxError> {
+        Ok(Self {
+            format: conf.format,
+            pattern: conf.pattern.as_deref().map(Regex::new).transpose()?,
+        })
+    }
+
+    /// Returns a description of the expected values if the value is invalid
+    fn coerce(&self, value: &mut Value) -> Result<(), String> {
+        if let Some(format) = self.format {
+            if !format.coerce(value) {
+                return Err(format.expected().to_string());
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            if !value
+                .as_str()
+                .map_or(false, |value| pattern.is_match(value))
+            {
+                return Err(format!("a string matching '{pattern}'"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The configured and registered validators of the custom scalars
+struct Validators<'a> {
+    configured: &'a HashMap<String, Validator>,
+    registered: &'a HashMap<String, ScalarValidator>,
+}
+
+impl Validators<'_> {
+    /// Validates the value if the scalar has validators
+    fn coerce(&self, scalar: &str, value: &mut Value) -> Option<Result<(), String>> {
+        let configured = self.configured.get(scalar);
+        let registered = self.registered.get(scalar);
+        if configured.is_none() && registered.is_none() {
+            return None;
+        }
+        let result = configured
+            .map_or(Ok(()), |validator| validator.coerce(value))
+            .and_then(|()| registered.map_or(Ok(()), |validator| validator(value)));
+        Some(result)
+    }
+}
+
+#[derive(Debug)]
+struct CustomScalars {
+    validators: Arc<HashMap<String, Validator>>,
+    schema: Arc<Schema>,
+    parser_recursion_limit: usize,
+}
+
+#[async_trait::async_trait]
+impl Plugin for CustomScalars {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let schema = Schema::parse(&init.supergraph_sdl, &Default::default())?;
+        let validators = init
+            .config
+            .scalars
+            .into_iter()
+            .map(|(name, conf)| {
+                if !schema.custom_scalars.contains(&name) {
+                    tracing::warn!("'{name}' is not a custom scalar of the schema");
+                }
+                let validator = Validator::new(conf)
+                    .map_err(|e| format!("invalid pattern for scalar '{name}': {e}"))?;
+                Ok((name, validator))
+            })
+            .collect::<Result<_, BoxError>>()?;
+
+        Ok(CustomScalars {
+            validators: Arc::new(validators),
+            schema: Arc::new(schema),
+            parser_recursion_limit: init.config.parser_recursion_limit,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let validators = self.validators.clone();
+        let schema = self.schema.clone();
+        let parser_recursion_limit = self.parser_recursion_limit;
+        ServiceBuilder::new()
+            .checkpoint(move |mut req: SupergraphRequest| {
+                let registered = REGISTERED_VALIDATORS.read().expect("lock poisoned");
+                if validators.is_empty() && registered.is_empty() {
+                    return Ok(ControlFlow::Continue(req));
+                }
+                let validators = Validators {
+                    configured: &validators,
+                    registered: &registered,
+                };
+                let body = req.supergraph_request.body_mut();
+                let variable_types = match body.query.as_deref() {
+                    Some(query) => variable_types(
+                        query,
+                        body.operation_name.as_deref(),
+                        parser_recursion_limit,
+                    ),
+                    None => Vec::new(),
+                };
+                let errors =
+                    coerce_variables(&validators, &schema, &variable_types, &mut body.variables);
+                drop(registered);
+                if errors.is_empty() {
+                    Ok(ControlFlow::Continue(req))
+                } else {
+                    let res = SupergraphResponse::builder()
+                        .errors(errors)
+                        .status_code(StatusCode::BAD_REQUEST)
+                        .context(req.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(res))
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+/// The types of the variables of the requested operation. Documents that do not parse are
+/// rejected by the query planner, and their variables are not validated here
+fn variable_types(
+    query: &str,
+    operation_name: Option<&str>,
+    parser_recursion_limit: usize,
+) -> Vec<(String, FieldType)> {
+    let tree = apollo_parser::Parser::new(query)
+        .recursion_limit(parser_recursion_limit)
+        .parse();
+    if tree.errors().next().is_some() {
+        return Vec::new();
+    }
+    let operation = tree
+        .document()
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        })
+        .find(|operation| {
+            operation_name.is_none()
+                || operation
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .as_deref()
+                    == operation_name
+        });
+    operation
+        .and_then(|operation| operation.variable_definitions())
+        .into_iter()
+        .flat_map(|definitions| definitions.variable_definitions())
+        .filter_map(|definition| {
+            let name = definition.variable()?.name()?.text().to_string();
+            let ty = FieldType::try_from(definition.ty()?).ok()?;
+            Some((name, ty))
+        })
+        .collect()
+}
+
+/// Validates and coerces the variables of the operation, returning an error for each invalid one
+fn coerce_variables(
+    validators: &Validators,
+    schema: &Schema,
+    variable_types: &[(String, FieldType)],
+    variables: &mut Object,
+) -> Vec<graphql::Error> {
+    variable_types
+        .iter()
+        .filter_map(|(name, ty)| {
+            let value = variables.get_mut(name.as_str())?;
+            coerce_value(validators, schema, ty, value)
+                .err()
+                .map(|expected| {
+                    let mut error = FetchError::ValidationInvalidTypeVariable {
+                        name: name.to_string(),
+                    }
+                    .to_graphql_error(None);
+                    error.message =
+                        format!("invalid value for variable '{name}': expected {expected}");
+                    error
+                })
+        })
+        .collect()
+}
+
+fn coerce_value(
+    validators: &Validators,
+    schema: &Schema,
+    ty: &FieldType,
+    value: &mut Value,
+) -> Result<(), String> {
+    match (ty, value) {
+        // Nullability is checked by the variable validation of the supergraph service
+        (_, Value::Null) => Ok(()),
+        (FieldType::NonNull(inner), value) => coerce_value(validators, schema, inner, value),
+        (FieldType::List(inner), Value::Array(items)) => items
+            .iter_mut()
+            .try_for_each(|item| coerce_value(validators, schema, inner, item)),
+        // For coercion from single value to list
+        (FieldType::List(inner), value) => coerce_value(validators, schema, inner, value),
+        (FieldType::Named(name), value) => {
+            if let Some(result) = validators.coerce(name, value) {
+                result
+            } else if let (Some(input_type), Value::Object(object)) =
+                (schema.input_types.get(name), value)
+            {
+                input_type.fields.iter().try_for_each(|(field, (ty, _))| {
+                    match object.get_mut(field.as_str()) {
+                        Some(value) => coerce_value(validators, schema, ty, value),
+                        None => Ok(()),
+                    }
+                })
+            } else {
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+register_plugin!("apollo", "custom_scalars", CustomScalars);
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        schema
+          @core(feature: "https://specs.apollo.dev/core/v0.1"),
+          @core(feature: "https://specs.apollo.dev/join/v0.1")
+        {
+          query: Query
+        }
+        directive @core(feature: String!) repeatable on SCHEMA
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        enum join__Graph {
+          ACCOUNTS @join__graph(name: "accounts", url: "http://localhost:4001/graphql")
+        }
+        scalar BigInt
+        scalar DateTime
+        input Range {
+          from: DateTime!
+          to: DateTime
+        }
+        type Query {
+          events(range: Range, ids: [BigInt!]): String
+        }"#;
+
+    const QUERY: &str =
+        "query($range: Range, $ids: [BigInt!]) { events(range: $range, ids: $ids) }";
+
+    fn coerce(variables: serde_json_bytes::Value) -> (Object, Vec<graphql::Error>) {
+        let configured = HashMap::from([
+            (
+                "BigInt".to_string(),
+                Validator::new(ScalarConf {
+                    format: Some(Format::BigInt),
+                    pattern: None,
+                })
+                .unwrap(),
+            ),
+            (
+                "DateTime".to_string(),
+                Validator::new(ScalarConf {
+                    format: Some(Format::DateTime),
+                    pattern: Some("^2022-".to_string()),
+                })
+                .unwrap(),
+            ),
+        ]);
+        let registered = HashMap::from([(
+            "DateTime".to_string(),
+            Arc::new(|value: &mut Value| match value.as_str() {
+                Some(value) if value.ends_with('Z') => Ok(()),
+                _ => Err("a date and time in UTC".to_string()),
+            }) as ScalarValidator,
+        )]);
+        let validators = Validators {
+            configured: &configured,
+            registered: &registered,
+        };
+        let schema = Schema::parse(SCHEMA, &Default::default()).unwrap();
+        let variable_types = variable_types(QUERY, None, default_parser_recursion_limit());
+        let mut variables = variables.as_object().unwrap().clone();
+        let errors = coerce_variables(&validators, &schema, &variable_types, &mut variables);
+        (variables, errors)
+    }
+
+    #[test]
+    fn it_coerces_valid_variables() {
+        let (variables, errors) = coerce(json!({
+            "range": { "from": "2022-11-28T10:00:00Z", "to": "2022-11-29T10:00:00.5Z" },
+            "ids": [12, "123456789012345678901234567890"],
+        }));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            variables.get("ids").unwrap(),
+            &json!(["12", "123456789012345678901234567890"])
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_variables() {
+        for (variables, name, expected) in [
+            (
+                json!({ "range": { "from": "yesterday" } }),
+                "range",
+                "an RFC 3339 date and time",
+            ),
+            (
+                json!({ "range": { "from": "2022-02-30T10:00:00Z" } }),
+                "range",
+                "an RFC 3339 date and time",
+            ),
+            (
+                json!({ "range": { "from": "2021-11-28T10:00:00Z" } }),
+                "range",
+                "a string matching '^2022-'",
+            ),
+            (
+                json!({ "range": { "from": "2022-11-28T10:00:00+02:00" } }),
+                "range",
+                "a date and time in UTC",
+            ),
+            (json!({ "ids": [1.5] }), "ids", "an integer"),
+            (json!({ "ids": "12a" }), "ids", "an integer"),
+        ] {
+            let (_, errors) = coerce(variables);
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].message,
+                format!("invalid value for variable '{name}': expected {expected}")
+            );
+            assert_eq!(
+                errors[0]
+                    .extensions
+                    .get("code")
+                    .and_then(|code| code.as_str()),
+                Some("VALIDATION_INVALID_TYPE_VARIABLE")
+            );
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Templating of error messages
+//!
+//! Errors generated by the router have messages written for developers. This plugin replaces the
+//! message of errors by extension code, from templates written in the voice of the product, and
+//! optionally translated in the languages listed in the `Accept-Language` header of the request.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use http::header::ACCEPT_LANGUAGE;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::layers::ServiceExt as _;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::supergraph;
+
+/// Templating of error messages
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Message templates by error code, such as `SUBREQUEST_HTTP_ERROR: "The {service} service is
+    /// unavailable"`. `{message}`, `{code}` and the extensions of the error can be used as
+    /// placeholders
+    #[serde(default)]
+    templates: HashMap<String, String>,
+
+    /// Message templates by language, then by error code, selected from the `Accept-Language`
+    /// header of the request. Codes without a template in the language use `templates`
+    #[serde(default)]
+    languages: HashMap<String, HashMap<String, String>>,
+}
+
+/// The templates applying to a request
+#[derive(Debug)]
+struct Templates {
+    language: Option<Arc<HashMap<String, String>>>,
+    default: Arc<HashMap<String, String>>,
+}
+
+impl Templates {
+    fn template(&self, code: &str) -> Option<&str> {
+        self.language
+            .as_ref()
+            .and_then(|templates| templates.get(code))
+            .or_else(|| self.default.get(code))
+            .map(String::as_str)
+    }
+
+    fn apply(&self, response: &mut graphql::Response) {
+        let errors = response.errors.iter_mut().chain(
+            response
+                .incremental
+                .iter_mut()
+                .flat_map(|incremental| incremental.errors.iter_mut()),
+        );
+        for error in errors {
+            let template = error
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .and_then(|code| self.template(code));
+            if let Some(template) = template {
+                error.message = render(template, error);
+            }
+        }
+    }
+}
+
+/// Replaces the `{name}` placeholders of the template. Unknown placeholders are kept as they are
+fn render(template: &str, error: &graphql::Error) -> String {
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let end = match placeholder.find('}') {
+            Some(end) => end,
+            None => {
+                rest = placeholder;
+                break;
+            }
+        };
+        let name = &placeholder[1..end];
+        let value = match name {
+            "message" => Some(error.message.clone()),
+            name => error.extensions.get(name).and_then(|value| match value {
+                Value::String(value) => Some(value.as_str().to_string()),
+                Value::Number(value) => Some(value.to_string()),
+                Value::Bool(value) => Some(value.to_string()),
+                _ => None,
+            }),
+        };
+        match value {
+            Some(value) => message.push_str(&value),
+            None => message.push_str(&placeholder[..=end]),
+        }
+        rest = &placeholder[end + 1..];
+    }
+    message.push_str(rest);
+    message
+}
+
+/// Returns the languages of an `Accept-Language` header, by decreasing preference
+fn preferred_languages(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|language| {
+            let mut parts = language.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // the sort is stable, languages with the same quality keep their order
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[derive(Debug)]
+struct ErrorMessages {
+    templates: Arc<HashMap<String, String>>,
+    languages: HashMap<String, Arc<HashMap<String, String>>>,
+}
+
+impl ErrorMessages {
+    fn templates(&self, request: &supergraph::Request) -> Arc<Templates> {
+        let language = request
+            .supergraph_request
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|header| {
+                preferred_languages(header).into_iter().find_map(|tag| {
+                    // fall back from `fr-ch` to `fr`
+                    let primary = tag.split('-').next().unwrap_or_default();
+                    self.languages
+                        .get(&tag)
+                        .or_else(|| self.languages.get(primary))
+                        .cloned()
+                })
+            });
+        Arc::new(Templates {
+            language,
+            default: self.templates.clone(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for ErrorMessages {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(ErrorMessages {
+            templates: Arc::new(init.config.templates),
+            languages: init
+                .config
+                .languages
+                .into_iter()
+                .map(|(tag, templates)| (tag.to_lowercase(), Arc::new(templates)))
+                .collect(),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.templates.is_empty() && self.languages.is_empty() {
+            return service;
+        }
+        let plugin = Arc::new(ErrorMessages {
+            templates: self.templates.clone(),
+            languages: self.languages.clone(),
+        });
+        service
+            .map_future_with_request_data(
+                move |request: &supergraph::Request| plugin.templates(request),
+                |templates: Arc<Templates>, future| async move {
+                    let response: supergraph::Response = future.await?;
+                    Ok(response.map_stream(move |mut response| {
+                        templates.apply(&mut response);
+                        response
+                    }))
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "error_messages", ErrorMessages);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::services::execution;
+
+    #[test]
+    fn it_renders_placeholders() {
+        let error = graphql::Error::builder()
+            .message("HTTP fetch failed from 'products': connection refused")
+            .extension_code("SUBREQUEST_HTTP_ERROR")
+            .extension("service", "products")
+            .build();
+        assert_eq!(
+            render("The {service} service is unavailable ({code})", &error),
+            "The products service is unavailable (SUBREQUEST_HTTP_ERROR)"
+        );
+        assert_eq!(render("{unknown} {message", &error), "{unknown} {message");
+    }
+
+    #[test]
+    fn it_orders_languages_by_quality() {
+        assert_eq!(
+            preferred_languages("en;q=0.8, fr-CH, fr;q=0.9, *;q=0.5, de;q=0"),
+            vec!["fr-ch", "fr", "en"]
+        );
+        assert!(preferred_languages("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_selects_templates_from_the_accept_language_header() {
+        let service = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "error_messages": {
+                    "templates": { "UNAVAILABLE": "{service} is unavailable" },
+                    "languages": { "fr": { "UNAVAILABLE": "{service} est indisponible" } }
+                }
+            }))
+            .unwrap()
+            .execution_hook(|service: execution::BoxService| {
+                service
+                    .map_first_graphql_response(|_context, http_parts, mut graphql_response| {
+                        graphql_response.errors.push(
+                            graphql::Error::builder()
+                                .message("connection refused")
+                                .extension_code("UNAVAILABLE")
+                                .extension("service", "products")
+                                .build(),
+                        );
+                        (http_parts, graphql_response)
+                    })
+                    .boxed()
+            })
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        for (language, message) in [
+            ("fr-CH, en;q=0.5", "products est indisponible"),
+            ("de", "products is unavailable"),
+        ] {
+            let request = supergraph::Request::canned_builder()
+                .header("accept-language", language)
+                .build()
+                .unwrap();
+            let mut response = service.clone().oneshot(request).await.unwrap();
+            let response = response.response.body_mut().next().await.unwrap();
+            assert_eq!(response.errors[0].message, message);
+            assert_eq!(
+                response.errors[0].extensions.get("service"),
+                Some(&json!("products"))
+            );
+        }
+    }
+}
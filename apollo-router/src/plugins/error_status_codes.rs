@@ -0,0 +1,203 @@
+//! Mapping of GraphQL errors to HTTP status codes
+//!
+//! GraphQL responses are sent with a 200 status code, even when they contain errors. For clients
+//! and infrastructure relying on status codes, this plugin sets the status code of responses from
+//! their errors: by extension code, or depending on whether the response has data.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::supergraph;
+
+/// Mapping of GraphQL errors to HTTP status codes
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Status codes of responses containing an error with these extension codes, such as
+    /// `UNAUTHENTICATED: 401`. The first error with a listed code sets the status code
+    #[serde(default)]
+    codes: HashMap<String, u16>,
+
+    /// Status code of responses with errors and without data
+    no_data: Option<u16>,
+
+    /// Status code of responses with errors and partial data
+    partial_data: Option<u16>,
+}
+
+#[derive(Debug)]
+struct ErrorStatusCodes {
+    codes: HashMap<String, StatusCode>,
+    no_data: Option<StatusCode>,
+    partial_data: Option<StatusCode>,
+}
+
+impl ErrorStatusCodes {
+    /// Returns the status code of a response with errors, if one is configured for them
+    fn status(&self, response: &graphql::Response) -> Option<StatusCode> {
+        if response.errors.is_empty() {
+            return None;
+        }
+        let by_code = response.errors.iter().find_map(|error| {
+            error
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .and_then(|code| self.codes.get(code))
+        });
+        match by_code {
+            Some(status) => Some(*status),
+            None if response.data.as_ref().map_or(true, |data| data.is_null()) => self.no_data,
+            None => self.partial_data,
+        }
+    }
+}
+
+fn status_code(code: u16) -> Result<StatusCode, BoxError> {
+    StatusCode::from_u16(code).map_err(|_| format!("invalid status code: {code}").into())
+}
+
+#[async_trait::async_trait]
+impl Plugin for ErrorStatusCodes {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(ErrorStatusCodes {
+            codes: init
+                .config
+                .codes
+                .into_iter()
+                .map(|(code, status)| Ok((code, status_code(status)?)))
+                .collect::<Result<_, BoxError>>()?,
+            no_data: init.config.no_data.map(status_code).transpose()?,
+            partial_data: init.config.partial_data.map(status_code).transpose()?,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.codes.is_empty() && self.no_data.is_none() && self.partial_data.is_none() {
+            return service;
+        }
+        let status_codes = Arc::new(ErrorStatusCodes {
+            codes: self.codes.clone(),
+            no_data: self.no_data,
+            partial_data: self.partial_data,
+        });
+        ServiceBuilder::new()
+            .map_first_graphql_response(move |_context, mut parts, response| {
+                // Responses already rejected with another status code, such as invalid queries,
+                // are kept as they are. With @defer, only the first response is considered
+                if parts.status == StatusCode::OK {
+                    if let Some(status) = status_codes.status(&response) {
+                        parts.status = status;
+                    }
+                }
+                (parts, response)
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "error_status_codes", ErrorStatusCodes);
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::layers::ServiceExt as _;
+    use crate::services::execution;
+
+    fn error(code: &str) -> graphql::Error {
+        graphql::Error::builder()
+            .message("error")
+            .extension_code(code.to_string())
+            .build()
+    }
+
+    #[test]
+    fn it_selects_status_codes_from_errors() {
+        let status_codes = ErrorStatusCodes {
+            codes: HashMap::from([("UNAUTHENTICATED".to_string(), StatusCode::UNAUTHORIZED)]),
+            no_data: Some(StatusCode::INTERNAL_SERVER_ERROR),
+            partial_data: None,
+        };
+
+        let response = graphql::Response::builder()
+            .data(json!({ "me": null }))
+            .build();
+        assert_eq!(status_codes.status(&response), None);
+
+        let response = graphql::Response::builder()
+            .data(json!({ "me": null }))
+            .errors(vec![error("INTERNAL"), error("UNAUTHENTICATED")])
+            .build();
+        assert_eq!(
+            status_codes.status(&response),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+
+        let response = graphql::Response::builder()
+            .errors(vec![error("INTERNAL")])
+            .build();
+        assert_eq!(
+            status_codes.status(&response),
+            Some(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+
+        let response = graphql::Response::builder()
+            .data(json!({ "me": null }))
+            .errors(vec![error("INTERNAL")])
+            .build();
+        assert_eq!(status_codes.status(&response), None);
+    }
+
+    #[tokio::test]
+    async fn it_sets_the_status_code_of_responses() {
+        let response = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "error_status_codes": { "codes": { "UNAUTHENTICATED": 401 } }
+            }))
+            .unwrap()
+            .execution_hook(|service: execution::BoxService| {
+                service
+                    .map_first_graphql_response(|_context, http_parts, mut graphql_response| {
+                        graphql_response.errors.push(error("UNAUTHENTICATED"));
+                        (http_parts, graphql_response)
+                    })
+                    .boxed()
+            })
+            .build_supergraph()
+            .await
+            .unwrap()
+            .oneshot(supergraph::Request::canned_builder().build().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_status_codes() {
+        let factory = crate::plugin::plugins()
+            .find(|factory| factory.name == "apollo.error_status_codes")
+            .expect("Plugin not found");
+        assert!(factory
+            .create_instance_without_schema(&serde_json::json!({ "no_data": 1000 }))
+            .await
+            .is_err());
+    }
+}
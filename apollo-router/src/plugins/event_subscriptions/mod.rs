@@ -0,0 +1,604 @@
+//! Subscriptions resolved from Kafka or NATS events
+//!
+//! Subgraphs publish the events of a subscription field to a Kafka topic or a NATS subject,
+//! instead of serving subscriptions over WebSocket. The router consumes the topic once for all the
+//! clients subscribed to the field, and sends each event to the subscriptions whose arguments
+//! match it, formatted with their selection set. The subscriptions are served like the deferred
+//! responses, in multipart responses or over WebSocket connections.
+//!
+//! Events are JSON values of the type of the subscription field: they must contain the fields
+//! selected by the subscriptions, as they are not resolved by subgraphs.
+//!
+//! Subscriptions are not planned, so they are validated against the schema here, and checked
+//! against the parser limits and the `operation_limits` and `operation_rules` plugins, whose
+//! configuration is injected by the router factory.
+//!
+//! The active subscriptions and the events sent to them are counted by field, since the events
+//! do not go through the subgraphs.
+
+mod source;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use apollo_compiler::ApolloCompiler;
+use apollo_parser::ast;
+use futures::StreamExt;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use self::source::Event;
+use self::source::Sources;
+use crate::configuration::default_parser_recursion_limit;
+use crate::configuration::ParserLimits;
+use crate::configuration::VariableValidation;
+use crate::error::ConfigurationError;
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Value;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::operation_limits;
+use crate::plugins::operation_limits::Limits;
+use crate::plugins::operation_rules;
+use crate::query_planner::normalization;
+use crate::query_planner::OPERATION_SIGNATURE_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::layers::content_negociation::ACCEPTS_MULTIPART_CONTEXT_KEY;
+use crate::services::supergraph;
+use crate::services::SupergraphResponse;
+use crate::services::MULTIPART_DEFER_CONTENT_TYPE;
+use crate::spec::query::parse_value;
+use crate::spec::Query;
+use crate::spec::Schema;
+use crate::Configuration;
+
+/// Subscriptions resolved from Kafka or NATS events
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// The source of the events of each subscription field, by name of the field of the
+    /// subscription type
+    #[serde(default)]
+    fields: HashMap<String, SourceConf>,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+
+    /// Limits on the documents, from `server.parser_limits`
+    #[serde(default)]
+    #[schemars(skip)]
+    parser_limits: ParserLimits,
+
+    /// Configuration of the `operation_limits` plugin, if it is enabled
+    #[serde(default)]
+    #[schemars(skip)]
+    operation_limits: Option<operation_limits::Conf>,
+
+    /// Configuration of the `operation_rules` plugin, if it is enabled
+    #[serde(default)]
+    #[schemars(skip)]
+    operation_rules: Option<operation_rules::Conf>,
+}
+
+/// Where the events of a subscription field are consumed from. Each event is a JSON value of
+/// the type of the field
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum SourceConf {
+    /// Kafka topic, where each record is an event. The records published to all the partitions
+    /// after the first subscription are consumed
+    Kafka {
+        /// Addresses of the bootstrap brokers, as `host:port`
+        brokers: Vec<String>,
+        /// The topic
+        topic: String,
+    },
+    /// NATS subject, where each message is an event
+    Nats {
+        /// The URL of the server, such as `nats://localhost:4222`, with the user and password or
+        /// the token of the connection, if any
+        url: url::Url,
+        /// The subject, which can contain wildcards
+        subject: String,
+    },
+    /// Events set by `TestHarness::subscription_events`, sent to the first subscription
+    #[schemars(skip)]
+    Mock { events: Vec<Value> },
+}
+
+#[derive(Debug)]
+struct EventSubscriptions {
+    sources: Arc<Sources>,
+    schema: Arc<Schema>,
+    validator: Arc<Validator>,
+    configuration: Arc<Configuration>,
+    limits: Option<Arc<Limits>>,
+    rules: Option<Arc<operation_rules::Conf>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for EventSubscriptions {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let schema = Schema::parse(&init.supergraph_sdl, &Default::default())?;
+        for (field, source) in &init.config.fields {
+            if let SourceConf::Kafka { brokers, .. } = source {
+                if brokers.is_empty() {
+                    return Err(ConfigurationError::InvalidConfiguration {
+                        message: "bad configuration for event_subscriptions plugin",
+                        error: format!("at least one Kafka broker is required for '{field}'"),
+                    }
+                    .into());
+                }
+            }
+        }
+        // the documents are parsed like the query planner does
+        let mut configuration = Configuration::default();
+        configuration.server.experimental_parser_recursion_limit =
+            init.config.parser_recursion_limit;
+        configuration.server.parser_limits = init.config.parser_limits;
+        let limits = init
+            .config
+            .operation_limits
+            .map(Limits::new)
+            .transpose()?
+            .map(Arc::new);
+        Ok(EventSubscriptions {
+            sources: Arc::new(Sources::new(init.config.fields)),
+            validator: Arc::new(Validator::new(schema.api_schema().as_string())),
+            schema: Arc::new(schema),
+            configuration: Arc::new(configuration),
+            limits,
+            rules: init.config.operation_rules.map(Arc::new),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.sources.is_empty() {
+            return service;
+        }
+        let sources = self.sources.clone();
+        let schema = self.schema.clone();
+        let validator = self.validator.clone();
+        let configuration = self.configuration.clone();
+        let limits = self.limits.clone();
+        let rules = self.rules.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |req: supergraph::Request| {
+                let body = req.supergraph_request.body();
+                let recursion_limit = configuration.server.experimental_parser_recursion_limit;
+                let root_field = match body.query.as_deref().and_then(|query| {
+                    RootField::find(query, body.operation_name.as_deref(), recursion_limit)
+                }) {
+                    Some(root_field) if sources.contains(&root_field.name) => root_field,
+                    // the other subscriptions are rejected by the query planner
+                    _ => return Ok(ControlFlow::Continue(req)),
+                };
+                let accepts_multipart: bool = req
+                    .context
+                    .get(ACCEPTS_MULTIPART_CONTEXT_KEY)
+                    .unwrap_or_default()
+                    .unwrap_or_default();
+                if !accepts_multipart {
+                    let error = graphql::Error::builder()
+                        .message(format!(
+                            "subscriptions are sent in multipart responses, the 'accept' header must contain {MULTIPART_DEFER_CONTENT_TYPE:?}"
+                        ))
+                        .extension_code("INVALID_ACCEPT_HEADER")
+                        .build();
+                    let res = SupergraphResponse::builder()
+                        .error(error)
+                        .status_code(StatusCode::NOT_ACCEPTABLE)
+                        .context(req.context)
+                        .build()?;
+                    return Ok(ControlFlow::Break(res));
+                }
+
+                // documents that do not parse or exceed the parser limits are rejected by the
+                // query planner
+                let query_string = body.query.clone().unwrap_or_default();
+                let query = match Query::parse(query_string.as_str(), &schema, &configuration) {
+                    Ok(query) => query,
+                    Err(_) => return Ok(ControlFlow::Continue(req)),
+                };
+                let errors = validator.validate(&query_string);
+                if !errors.is_empty() {
+                    let res = SupergraphResponse::builder()
+                        .errors(errors)
+                        .status_code(StatusCode::BAD_REQUEST)
+                        .context(req.context)
+                        .build()?;
+                    return Ok(ControlFlow::Break(res));
+                }
+                if let Some((status, error)) = rules.as_ref().and_then(|rules| rules.check(&req)) {
+                    let res = SupergraphResponse::builder()
+                        .error(error)
+                        .status_code(status)
+                        .context(req.context)
+                        .build()?;
+                    return Ok(ControlFlow::Break(res));
+                }
+                if let Err(response) =
+                    query.validate_variables(body, &schema, VariableValidation::Standard)
+                {
+                    let mut res =
+                        SupergraphResponse::new_from_graphql_response(response, req.context);
+                    *res.response.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok(ControlFlow::Break(res));
+                }
+                let signature = normalization::normalize(
+                    &query_string,
+                    body.operation_name.as_deref(),
+                    recursion_limit,
+                )
+                .map(|normalized| normalized.query);
+                if let Some(signature) = &signature {
+                    let _ = req
+                        .context
+                        .insert(OPERATION_SIGNATURE_CONTEXT_KEY, signature.clone());
+                }
+                if let Some(limits) = &limits {
+                    // subscriptions resolved from events do not fetch from subgraphs
+                    let errors = limits.check_operation(
+                        &query,
+                        body.operation_name.as_deref(),
+                        0,
+                        signature.as_deref(),
+                    );
+                    if !errors.is_empty() {
+                        let res = SupergraphResponse::builder()
+                            .errors(errors)
+                            .status_code(StatusCode::BAD_REQUEST)
+                            .context(req.context)
+                            .build()?;
+                        return Ok(ControlFlow::Break(res));
+                    }
+                }
+
+                let events = sources
+                    .subscribe(&root_field.name)
+                    .expect("the field has a source");
+                let subscription = Subscription {
+                    arguments: root_field.arguments(&query, body),
+                    field: root_field.name,
+                    operation_name: body.operation_name.clone(),
+                    variables: body.variables.clone(),
+                    query,
+                    schema: schema.clone(),
+                };
+                let active = ActiveSubscription::new(&subscription.field);
+                let responses = futures::stream::unfold(
+                    (events, subscription, active),
+                    |(mut events, subscription, active)| async move {
+                        loop {
+                            match events.recv().await {
+                                Ok(event) => {
+                                    if let Some(response) = subscription.response(&event) {
+                                        tracing::info!(
+                                            monotonic_counter.apollo_router_event_subscriptions_delivered_events_total = 1u64,
+                                            field = %subscription.field,
+                                        );
+                                        return Some((response, (events, subscription, active)));
+                                    }
+                                }
+                                Err(RecvError::Lagged(skipped)) => {
+                                    tracing::warn!(
+                                        "a subscription to '{}' skipped {} events, as it was too slow to receive them",
+                                        subscription.field,
+                                        skipped
+                                    );
+                                    tracing::info!(
+                                        monotonic_counter.apollo_router_event_subscriptions_skipped_events_total = skipped,
+                                        field = %subscription.field,
+                                    );
+                                }
+                                Err(RecvError::Closed) => return None,
+                            }
+                        }
+                    },
+                );
+                Ok(ControlFlow::Break(SupergraphResponse::new_from_response(
+                    http::Response::new(responses.boxed()),
+                    req.context,
+                )))
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+/// The root field of a subscription operation
+struct RootField {
+    name: String,
+    arguments: Vec<(String, ast::Value)>,
+}
+
+impl RootField {
+    /// Finds the root field of the requested operation, if it is a subscription. Documents that
+    /// do not parse or do not contain it are not matched: their planning fails anyway
+    fn find(query: &str, operation_name: Option<&str>, recursion_limit: usize) -> Option<Self> {
+        let tree = apollo_parser::Parser::new(query)
+            .recursion_limit(recursion_limit)
+            .parse();
+        if tree.errors().next().is_some() {
+            return None;
+        }
+        let mut operations =
+            tree.document()
+                .definitions()
+                .filter_map(|definition| match definition {
+                    ast::Definition::OperationDefinition(operation) => Some(operation),
+                    _ => None,
+                });
+        let operation = match operation_name {
+            Some(operation_name) => operations.find(|operation| {
+                operation
+                    .name()
+                    .map_or(false, |name| name.text() == operation_name)
+            })?,
+            None => operations.next()?,
+        };
+        operation.operation_type()?.subscription_token()?;
+
+        // subscriptions select a single root field
+        let mut selections = operation.selection_set()?.selections();
+        let field = match (selections.next()?, selections.next()) {
+            (ast::Selection::Field(field), None) => field,
+            _ => return None,
+        };
+        Some(RootField {
+            name: field.name()?.text().to_string(),
+            arguments: field
+                .arguments()
+                .iter()
+                .flat_map(|arguments| arguments.arguments())
+                .filter_map(|argument| {
+                    Some((argument.name()?.text().to_string(), argument.value()?))
+                })
+                .collect(),
+        })
+    }
+
+    /// The values of the arguments, with the variables of the request
+    fn arguments(&self, query: &Query, request: &graphql::Request) -> Vec<(String, Value)> {
+        self.arguments
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = argument_value(value, query, request)?;
+                Some((name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+fn argument_value(value: &ast::Value, query: &Query, request: &graphql::Request) -> Option<Value> {
+    match value {
+        ast::Value::Variable(variable) => query
+            .variable_value(
+                request.operation_name.as_deref(),
+                &variable.name()?.text(),
+                &request.variables,
+            )
+            .cloned(),
+        ast::Value::ListValue(list) => list
+            .values()
+            .map(|value| argument_value(&value, query, request))
+            .collect::<Option<_>>()
+            .map(Value::Array),
+        ast::Value::ObjectValue(object) => object
+            .object_fields()
+            .map(|field| {
+                let value = argument_value(&field.value()?, query, request)?;
+                Some((field.name()?.text().to_string().into(), value))
+            })
+            .collect::<Option<Object>>()
+            .map(Value::Object),
+        value => parse_value(value),
+    }
+}
+
+/// Validates the operations against the API schema, as the query planner does for the operations
+/// it plans
+#[derive(Debug)]
+struct Validator {
+    api_schema: Arc<String>,
+    /// Errors of the schema itself, which are not reported for the operations
+    schema_errors: HashSet<String>,
+}
+
+impl Validator {
+    fn new(api_schema: &Arc<String>) -> Self {
+        let mut compiler = ApolloCompiler::new();
+        compiler.create_schema(api_schema.as_str(), "schema.graphql");
+        Validator {
+            api_schema: api_schema.clone(),
+            schema_errors: errors(&compiler).collect(),
+        }
+    }
+
+    /// Returns the validation errors of the document
+    fn validate(&self, query: &str) -> Vec<graphql::Error> {
+        let mut compiler = ApolloCompiler::new();
+        compiler.create_schema(self.api_schema.as_str(), "schema.graphql");
+        compiler.create_executable(query, "query.graphql");
+        errors(&compiler)
+            .filter(|error| !self.schema_errors.contains(error))
+            .map(|error| {
+                graphql::Error::builder()
+                    .message(error)
+                    .extension_code("GRAPHQL_VALIDATION_FAILED")
+                    .build()
+            })
+            .collect()
+    }
+}
+
+fn errors(compiler: &ApolloCompiler) -> impl Iterator<Item = String> {
+    compiler
+        .validate()
+        .into_iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .map(|diagnostic| diagnostic.to_string())
+}
+
+/// A subscription to a field resolved from events
+struct Subscription {
+    field: String,
+    arguments: Vec<(String, Value)>,
+    operation_name: Option<String>,
+    variables: Object,
+    query: Query,
+    schema: Arc<Schema>,
+}
+
+impl Subscription {
+    /// Events are sent to the subscriptions whose arguments are all equal to the fields of the
+    /// same name of the event. Events that are not objects, or that lack one of the fields, are
+    /// not sent
+    fn matches(&self, event: &Value) -> bool {
+        let event = match event.as_object() {
+            Some(event) => event,
+            None => return false,
+        };
+        self.arguments.iter().all(|(name, argument)| {
+            event
+                .get(name.as_str())
+                .map_or(false, |value| same_value(value, argument))
+        })
+    }
+
+    /// The response sent for the event, if it matches the subscription
+    fn response(&self, event: &Event) -> Option<graphql::Response> {
+        if !self.matches(event) {
+            return None;
+        }
+        let mut data = Object::default();
+        data.insert(self.field.as_str(), (**event).clone());
+        let data = self
+            .query
+            .alias_fields(self.operation_name.as_deref(), &data);
+        let mut response = graphql::Response::builder()
+            .data(Value::Object(data))
+            .has_next(true)
+            .build();
+        self.query.format_response(
+            &mut response,
+            self.operation_name.as_deref(),
+            false,
+            self.variables.clone(),
+            &self.schema,
+        );
+        Some(response)
+    }
+}
+
+/// Counts a subscription in `apollo_router_event_subscriptions_active` until it is dropped with
+/// its response stream
+struct ActiveSubscription(String);
+
+impl ActiveSubscription {
+    fn new(field: &str) -> Self {
+        tracing::info!(
+            counter.apollo_router_event_subscriptions_active = 1i64,
+            field = %field,
+        );
+        ActiveSubscription(field.to_string())
+    }
+}
+
+impl Drop for ActiveSubscription {
+    fn drop(&mut self) {
+        tracing::info!(
+            counter.apollo_router_event_subscriptions_active = -1i64,
+            field = %self.0,
+        );
+    }
+}
+
+/// IDs can be sent as numbers or strings
+fn same_value(value: &Value, argument: &Value) -> bool {
+    match (value, argument) {
+        (Value::Number(number), Value::String(string))
+        | (Value::String(string), Value::Number(number)) => number.to_string() == string.as_str(),
+        _ => value == argument,
+    }
+}
+
+register_plugin!("apollo", "event_subscriptions", EventSubscriptions);
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    #[test]
+    fn it_finds_the_root_field_of_subscriptions() {
+        let root_field = RootField::find(
+            "query Q { me { id } } subscription S($id: ID!) { reviewAdded(productId: $id) { id } }",
+            Some("S"),
+            default_parser_recursion_limit(),
+        )
+        .unwrap();
+        assert_eq!(root_field.name, "reviewAdded");
+        assert_eq!(root_field.arguments.len(), 1);
+        assert_eq!(root_field.arguments[0].0, "productId");
+
+        let limit = default_parser_recursion_limit();
+        assert!(RootField::find("query Q { me { id } }", None, limit).is_none());
+        assert!(RootField::find("subscription { a { id } b { id } }", None, limit).is_none());
+        // the documents nested deeper than the recursion limit are not matched
+        assert!(RootField::find("subscription { a { b { c { d } } } }", None, 4).is_none());
+    }
+
+    #[test]
+    fn it_validates_operations_against_the_api_schema() {
+        let schema = Schema::parse(
+            include_str!("../../testdata/minimal_supergraph.graphql"),
+            &Default::default(),
+        )
+        .unwrap();
+        let validator = Validator::new(schema.api_schema().as_string());
+        assert!(validator.validate("{ me }").is_empty());
+        let errors = validator.validate("{ me { id } unknown }");
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| {
+            error.extensions.get("code").and_then(|code| code.as_str())
+                == Some("GRAPHQL_VALIDATION_FAILED")
+        }));
+    }
+
+    #[test]
+    fn it_matches_events_with_the_arguments() {
+        let schema = Schema::parse(
+            include_str!("../../testdata/minimal_supergraph.graphql"),
+            &Default::default(),
+        )
+        .unwrap();
+        let subscription = Subscription {
+            field: "reviewAdded".to_string(),
+            arguments: vec![("productId".to_string(), json!("1"))],
+            operation_name: None,
+            variables: Object::default(),
+            query: Query::parse("{ me }", &schema, &Default::default()).unwrap(),
+            schema: Arc::new(schema),
+        };
+        assert!(subscription.matches(&json!({ "productId": 1, "body": "great" })));
+        assert!(!subscription.matches(&json!({ "productId": "2", "body": "bad" })));
+        // events that cannot be matched are not sent
+        assert!(!subscription.matches(&json!({ "body": "great" })));
+        assert!(!subscription.matches(&json!("great")));
+    }
+}
@@ -0,0 +1,254 @@
+//! Consumers of the Kafka topics and NATS subjects, shared by the subscriptions to a field
+//!
+//! A consumer is started by the first subscription to a field, and stops once the field has no
+//! subscriptions left. Events are sent to the subscriptions through a broadcast channel: the
+//! subscriptions too slow to receive them skip the oldest ones.
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rskafka::client::partition::OffsetAt;
+use rskafka::client::partition::PartitionClient;
+use rskafka::client::ClientBuilder;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tower::BoxError;
+
+use super::SourceConf;
+use crate::json_ext::Value;
+use crate::nats;
+use crate::nats::Incoming;
+
+/// Events waiting to be received by the slowest subscription
+const CHANNEL_CAPACITY: usize = 256;
+/// The longest delay before a consumer notices that its field has no subscriptions left
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const KAFKA_MAX_FETCH_BYTES: i32 = 1024 * 1024;
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub(super) type Event = Arc<Value>;
+
+type Channels = Arc<Mutex<HashMap<String, (u64, broadcast::Sender<Event>)>>>;
+
+/// The sources of the subscription fields, and the channels of their running consumers
+#[derive(Debug)]
+pub(super) struct Sources {
+    sources: HashMap<String, SourceConf>,
+    channels: Channels,
+    next_id: AtomicU64,
+}
+
+impl Sources {
+    pub(super) fn new(sources: HashMap<String, SourceConf>) -> Self {
+        Sources {
+            sources,
+            channels: Default::default(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub(super) fn contains(&self, field: &str) -> bool {
+        self.sources.contains_key(field)
+    }
+
+    /// Receives the events of the field, starting its consumer if it has no subscriptions yet
+    pub(super) fn subscribe(&self, field: &str) -> Option<broadcast::Receiver<Event>> {
+        let source = self.sources.get(field)?;
+        let mut channels = self.channels.lock().expect("lock poisoned");
+        if let Some((_, sender)) = channels.get(field) {
+            return Some(sender.subscribe());
+        }
+        let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        channels.insert(field.to_string(), (id, sender.clone()));
+        tokio::spawn(consume(
+            source.clone(),
+            Fanout {
+                field: field.to_string(),
+                id,
+                sender,
+                channels: self.channels.clone(),
+            },
+        ));
+        Some(receiver)
+    }
+}
+
+/// The sending side of the channel of a field, held by its consumer
+struct Fanout {
+    field: String,
+    id: u64,
+    sender: broadcast::Sender<Event>,
+    channels: Channels,
+}
+
+impl Fanout {
+    /// Whether the field has no subscriptions left. Its channel is then removed, so that the next
+    /// subscription starts a new consumer
+    fn is_unused(&self) -> bool {
+        if self.sender.receiver_count() > 0 {
+            return false;
+        }
+        // the subscriptions are added while holding the lock
+        let mut channels = self.channels.lock().expect("lock poisoned");
+        if self.sender.receiver_count() > 0 {
+            return false;
+        }
+        if channels
+            .get(&self.field)
+            .map_or(false, |(id, _)| *id == self.id)
+        {
+            channels.remove(&self.field);
+        }
+        true
+    }
+
+    fn send(&self, payload: &[u8]) {
+        match serde_json::from_slice::<Value>(payload) {
+            Ok(event) => {
+                let _ = self.sender.send(Arc::new(event));
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "ignoring an event of the subscription field '{}' that is not JSON: {}",
+                    self.field,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Consumes the events until the field has no subscriptions left, reconnecting after failures
+async fn consume(source: SourceConf, fanout: Fanout) {
+    let mut retry_delay = MIN_RETRY_DELAY;
+    loop {
+        let result = match &source {
+            SourceConf::Kafka { brokers, topic } => consume_kafka(brokers, topic, &fanout).await,
+            SourceConf::Nats { url, subject } => consume_nats(url, subject, &fanout).await,
+            SourceConf::Mock { events } => {
+                send_mock_events(events, fanout);
+                return;
+            }
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) => tracing::error!(
+                "could not consume the events of the subscription field '{}': {}",
+                fanout.field,
+                e
+            ),
+        }
+        tokio::time::sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+        if fanout.is_unused() {
+            return;
+        }
+    }
+}
+
+/// Sends the events, then closes the channel of the field: its subscriptions end once they
+/// received them
+fn send_mock_events(events: &[Value], fanout: Fanout) {
+    for event in events {
+        let _ = fanout.sender.send(Arc::new(event.clone()));
+    }
+    let mut channels = fanout.channels.lock().expect("lock poisoned");
+    if channels
+        .get(&fanout.field)
+        .map_or(false, |(id, _)| *id == fanout.id)
+    {
+        channels.remove(&fanout.field);
+    }
+}
+
+/// Consumes the records published to all the partitions of the topic from now on
+async fn consume_kafka(brokers: &[String], topic: &str, fanout: &Fanout) -> Result<(), BoxError> {
+    let client = ClientBuilder::new(brokers.to_vec()).build().await?;
+    let partitions = client
+        .list_topics()
+        .await?
+        .into_iter()
+        .find(|metadata| metadata.name == topic)
+        .ok_or_else(|| format!("the Kafka topic '{topic}' does not exist"))?
+        .partitions;
+    let mut consumers = Vec::with_capacity(partitions.len());
+    for partition in &partitions {
+        let partition = client.partition_client(topic.to_string(), *partition)?;
+        let offset = partition.get_offset(OffsetAt::Latest).await?;
+        consumers.push(consume_partition(partition, offset, fanout));
+    }
+    futures::future::try_join_all(consumers).await?;
+    Ok(())
+}
+
+async fn consume_partition(
+    partition: PartitionClient,
+    mut offset: i64,
+    fanout: &Fanout,
+) -> Result<(), BoxError> {
+    let max_wait_ms = IDLE_CHECK_INTERVAL.as_millis() as i32;
+    while !fanout.is_unused() {
+        let (records, _high_watermark) = partition
+            .fetch_records(offset, 1..KAFKA_MAX_FETCH_BYTES, max_wait_ms)
+            .await?;
+        for record in records {
+            offset = record.offset + 1;
+            if let Some(value) = &record.record.value {
+                fanout.send(value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Subscribes to the subject with the client protocol of NATS, without queue group: each router
+/// instance needs every event for its own subscriptions
+async fn consume_nats(url: &url::Url, subject: &str, fanout: &Fanout) -> Result<(), BoxError> {
+    let (mut reader, writer) = nats::connect(url, false).await?;
+
+    // the writer pings the server, so that the reader checks regularly whether the field still
+    // has subscriptions
+    let (commands, mut pending_commands) = mpsc::channel::<String>(8);
+    commands.send(format!("SUB {subject} 1\r\n")).await?;
+    let writer = tokio::spawn(async move {
+        let mut writer = writer;
+        let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            let command = tokio::select! {
+                command = pending_commands.recv() => match command {
+                    Some(command) => command,
+                    None => break,
+                },
+                _ = interval.tick() => "PING\r\n".to_string(),
+            };
+            if writer.write_all(command.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = async {
+        while !fanout.is_unused() {
+            match reader.next().await? {
+                Incoming::Message { payload, .. } => fanout.send(&payload),
+                Incoming::Ping => commands.send("PONG\r\n".to_string()).await?,
+                Incoming::Other => {}
+            }
+        }
+        Ok::<_, BoxError>(())
+    }
+    .await;
+    writer.abort();
+    result
+}
@@ -1,8 +1,8 @@
 //! Externalization plugin
 // With regards to ELv2 licensing, this entire file is license key functionality
 
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fmt;
 use std::ops::ControlFlow;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -12,6 +12,9 @@ use bytes::Bytes;
 use http::header::HeaderName;
 use http::HeaderMap;
 use http::HeaderValue;
+use http::Method;
+use http::StatusCode;
+use http::Uri;
 use hyper::body;
 use hyper::Body;
 use schemars::JsonSchema;
@@ -23,16 +26,26 @@ use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use crate::configuration::Redaction;
 use crate::error::Error;
+use crate::graphql;
+use crate::json_ext::Path;
 use crate::layers::async_checkpoint::AsyncCheckpointLayer;
+use crate::layers::map_future_with_request_data::MapFutureWithRequestDataLayer;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::query_planner::fetch::FetchPath;
+use crate::redaction::Redactor;
 use crate::register_plugin;
 use crate::services::external::Control;
 use crate::services::external::Externalizable;
+use crate::services::external::GrpcClient;
 use crate::services::external::PipelineStep;
+use crate::services::external::Transport;
 use crate::services::router;
+use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::Context;
 
 pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
@@ -41,6 +54,8 @@ pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
 struct ExternalPlugin {
     configuration: Conf,
     sdl: Arc<String>,
+    transport: Arc<Transport>,
+    redactor: Option<Arc<Redactor>>,
 }
 
 /// What information is passed to a request/response stage
@@ -60,14 +75,75 @@ struct BaseConf {
     sdl: bool,
 }
 
+/// What information is passed to a request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+struct RequestConf {
+    #[serde(flatten)]
+    base: BaseConf,
+    /// Send the HTTP method
+    #[serde(default)]
+    method: bool,
+    /// Send the path of the request URI
+    #[serde(default)]
+    path: bool,
+}
+
+impl RequestConf {
+    /// Adds the parts of the HTTP request selected by the configuration
+    fn add_http_params(&self, params: &mut ExternalParams<'_>, method: &Method, uri: &Uri) {
+        if self.method {
+            params.method = Some(method.to_string());
+        }
+        if self.path {
+            params.path = Some(uri.path().to_string());
+        }
+    }
+}
+
+/// What information is passed to a response stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+struct ResponseConf {
+    #[serde(flatten)]
+    base: BaseConf,
+    /// Send the HTTP status code
+    #[serde(default)]
+    status: bool,
+}
+
+impl ResponseConf {
+    /// Adds the parts of the HTTP response selected by the configuration
+    fn add_http_params(&self, params: &mut ExternalParams<'_>, status: StatusCode) {
+        if self.status {
+            params.status_code = Some(status.as_u16());
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 struct RouterStage {
     /// The request configuration
     #[serde(default)]
-    request: Option<BaseConf>,
+    request: Option<RequestConf>,
     /// The response configuration
     #[serde(default)]
-    response: Option<BaseConf>,
+    response: Option<ResponseConf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+struct SupergraphStage {
+    /// The request configuration
+    #[serde(default)]
+    request: Option<RequestConf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+struct SubgraphStage {
+    /// The request configuration
+    #[serde(default)]
+    request: Option<RequestConf>,
+    /// The response configuration
+    #[serde(default)]
+    response: Option<ResponseConf>,
 }
 
 /// The stages request/response configuration
@@ -76,6 +152,29 @@ struct Stages {
     /// The router stage
     #[serde(default)]
     router: Option<RouterStage>,
+    /// The supergraph stage. Supergraph responses are streamed, so only requests are sent
+    #[serde(default)]
+    supergraph: Option<SupergraphStage>,
+    /// The subgraph stage, applied to the requests and responses of every subgraph
+    #[serde(default)]
+    subgraph: Option<SubgraphStage>,
+}
+
+/// The protocol used to call the co-processor
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Protocol {
+    /// A JSON encoded HTTP POST request per call
+    Http,
+    /// A message per call on a bi-directional gRPC stream, which avoids the overhead of
+    /// an HTTP request per call
+    Grpc,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::Http
+    }
 }
 
 /// Configures the externalization plugin
@@ -83,6 +182,9 @@ struct Stages {
 struct Conf {
     /// The url you'd like to offload processing to
     url: String,
+    /// The protocol used to call the co-processor
+    #[serde(default)]
+    protocol: Protocol,
     /// The timeout for external requests
     #[serde(deserialize_with = "humantime_serde::deserialize", default)]
     #[schemars(with = "String", default)]
@@ -90,6 +192,11 @@ struct Conf {
     /// The stages request/response configuration
     #[serde(default)]
     stages: Option<Stages>,
+
+    /// Redaction of the payloads sent to the co-processor
+    #[serde(default)]
+    #[schemars(skip)]
+    redaction: Redaction,
 }
 
 #[async_trait::async_trait]
@@ -97,9 +204,17 @@ impl Plugin for ExternalPlugin {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let transport = match init.config.protocol {
+            Protocol::Http => Transport::Http(init.config.url.clone()),
+            Protocol::Grpc => {
+                Transport::Grpc(GrpcClient::new(&init.config.url, init.config.timeout)?)
+            }
+        };
         Ok(ExternalPlugin {
+            redactor: Redactor::new(&init.config.redaction)?,
             configuration: init.config,
             sdl: init.supergraph_sdl,
+            transport: Arc::new(transport),
         })
     }
 
@@ -110,6 +225,12 @@ impl Plugin for ExternalPlugin {
         let request_full_config = self.configuration.clone();
         let response_full_config = self.configuration.clone();
 
+        let request_transport = self.transport.clone();
+        let response_transport = self.transport.clone();
+
+        let request_redactor = self.redactor.clone();
+        let response_redactor = self.redactor.clone();
+
         let request_layer = if self
             .configuration
             .stages
@@ -129,34 +250,42 @@ impl Plugin for ExternalPlugin {
             Some(AsyncCheckpointLayer::new(
                 move |mut request: router::Request| {
                     let my_sdl = request_sdl.to_string();
-                    let proto_url = request_full_config.url.clone();
+                    let transport = request_transport.clone();
                     let timeout = request_full_config.timeout;
                     let request_config = request_config.clone();
+                    let redactor = request_redactor.clone();
                     async move {
                         // Call into our out of process processor with a body of our body
                         // First, extract the data we need from our request and prepare our
                         // external call. Use our configuration to figure out which data to send.
 
                         let (parts, body) = request.router_request.into_parts();
-                        let b_bytes = body::to_bytes(body).await?;
+                        let (body, b_bytes) = buffer_if(request_config.base.body, body).await?;
 
-                        let (headers, payload, context, sdl) = prepare_external_params(
-                            &request_config,
+                        let mut params = prepare_external_params(
+                            &request_config.base,
                             &parts.headers,
-                            &b_bytes,
+                            || {
+                                redacted_router_body(
+                                    b_bytes.as_ref(),
+                                    redactor.as_deref(),
+                                    Redactor::request,
+                                )
+                            },
                             &request.context,
                             my_sdl,
+                            redactor.as_deref(),
                         )?;
+                        request_config.add_http_params(&mut params, &parts.method, &parts.uri);
+                        let sent_context = params.context.clone().unwrap_or_default();
 
                         // Second, call our co-processor and get a reply.
                         let co_processor_output = call_external(
-                            proto_url,
+                            transport,
                             timeout,
                             PipelineStep::RouterRequest,
-                            headers,
-                            payload,
-                            context,
-                            sdl,
+                            None,
+                            params,
                         )
                         .await?;
 
@@ -171,13 +300,7 @@ impl Plugin for ExternalPlugin {
 
                             let res = if !code.is_success() {
                                 router::Response::error_builder()
-                                    .errors(vec![Error {
-                                        message: co_processor_output
-                                            .body
-                                            .unwrap_or(serde_json::Value::Null)
-                                            .to_string(),
-                                        ..Default::default()
-                                    }])
+                                    .errors(vec![break_error(co_processor_output.body)])
                                     .status_code(code)
                                     .context(request.context)
                                     .build()?
@@ -201,19 +324,30 @@ impl Plugin for ExternalPlugin {
                         // are present in our co_processor_output.
 
                         let new_body = match co_processor_output.body {
-                            Some(bytes) => Body::from(serde_json::to_vec(&bytes)?),
-                            None => Body::from(b_bytes),
+                            Some(value) => restored_router_body(
+                                value,
+                                b_bytes.as_ref(),
+                                redactor.as_deref(),
+                                Redactor::restore_request,
+                            )?,
+                            None => body,
                         };
 
+                        let new_headers = co_processor_output
+                            .headers
+                            .map(|headers| {
+                                restored_headers(headers, &parts.headers, redactor.as_deref())
+                            })
+                            .transpose()?;
+
                         request.router_request = http::Request::from_parts(parts, new_body);
 
                         if let Some(context) = co_processor_output.context {
-                            request.context = context;
+                            request.context.merge_changes(&sent_context, &context);
                         }
 
-                        if let Some(headers) = co_processor_output.headers {
-                            *request.router_request.headers_mut() =
-                                internalize_header_map(headers)?;
+                        if let Some(headers) = new_headers {
+                            *request.router_request.headers_mut() = headers;
                         }
 
                         Ok(ControlFlow::Continue(request))
@@ -242,9 +376,10 @@ impl Plugin for ExternalPlugin {
                 .unwrap();
             Some(MapFutureLayer::new(move |fut| {
                 let my_sdl = response_sdl.to_string();
-                let proto_url = response_full_config.url.clone();
+                let transport = response_transport.clone();
                 let timeout = response_full_config.timeout;
                 let response_config = response_config.clone();
+                let redactor = response_redactor.clone();
                 async move {
                     let mut response: router::Response = fut.await?;
 
@@ -253,25 +388,32 @@ impl Plugin for ExternalPlugin {
                     // external call. Use our configuration to figure out which data to send.
 
                     let (parts, body) = response.response.into_parts();
-                    let b_bytes = body::to_bytes(body).await?;
+                    let (body, b_bytes) = buffer_if(response_config.base.body, body).await?;
 
-                    let (headers, payload, context, sdl) = prepare_external_params(
-                        &response_config,
+                    let mut params = prepare_external_params(
+                        &response_config.base,
                         &parts.headers,
-                        &b_bytes,
+                        || {
+                            redacted_router_body(
+                                b_bytes.as_ref(),
+                                redactor.as_deref(),
+                                Redactor::response,
+                            )
+                        },
                         &response.context,
                         my_sdl,
+                        redactor.as_deref(),
                     )?;
+                    response_config.add_http_params(&mut params, parts.status);
+                    let sent_context = params.context.clone().unwrap_or_default();
 
                     // Second, call our co-processor and get a reply.
                     let co_processor_output = call_external(
-                        proto_url,
+                        transport,
                         timeout,
                         PipelineStep::RouterResponse,
-                        headers,
-                        payload,
-                        context,
-                        sdl,
+                        None,
+                        params,
                     )
                     .await?;
 
@@ -283,18 +425,30 @@ impl Plugin for ExternalPlugin {
                     // bits that we sent to the co_processor.
 
                     let new_body = match co_processor_output.body {
-                        Some(bytes) => Body::from(serde_json::to_vec(&bytes)?),
-                        None => Body::from(b_bytes),
+                        Some(value) => restored_router_body(
+                            value,
+                            b_bytes.as_ref(),
+                            redactor.as_deref(),
+                            Redactor::restore_response,
+                        )?,
+                        None => body,
                     };
 
+                    let new_headers = co_processor_output
+                        .headers
+                        .map(|headers| {
+                            restored_headers(headers, &parts.headers, redactor.as_deref())
+                        })
+                        .transpose()?;
+
                     response.response = http::Response::from_parts(parts, new_body);
 
                     if let Some(context) = co_processor_output.context {
-                        response.context = context;
+                        response.context.merge_changes(&sent_context, &context);
                     }
 
-                    if let Some(headers) = co_processor_output.headers {
-                        *response.response.headers_mut() = internalize_header_map(headers)?;
+                    if let Some(headers) = new_headers {
+                        *response.response.headers_mut() = headers;
                     }
 
                     Ok::<router::Response, BoxError>(response)
@@ -322,69 +476,492 @@ impl Plugin for ExternalPlugin {
             .service(service)
             .boxed()
     }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let request_layer = self
+            .configuration
+            .stages
+            .as_ref()
+            .and_then(|x| x.supergraph.as_ref())
+            .and_then(|x| x.request.clone())
+            .map(|request_config| {
+                let sdl = self.sdl.clone();
+                let transport = self.transport.clone();
+                let timeout = self.configuration.timeout;
+                let redactor = self.redactor.clone();
+                AsyncCheckpointLayer::new(move |mut request: supergraph::Request| {
+                    let my_sdl = sdl.to_string();
+                    let transport = transport.clone();
+                    let request_config = request_config.clone();
+                    let redactor = redactor.clone();
+                    async move {
+                        let mut params = prepare_external_params(
+                            &request_config.base,
+                            request.supergraph_request.headers(),
+                            || {
+                                redacted_request(
+                                    request.supergraph_request.body(),
+                                    redactor.as_deref(),
+                                )
+                            },
+                            &request.context,
+                            my_sdl,
+                            redactor.as_deref(),
+                        )?;
+                        request_config.add_http_params(
+                            &mut params,
+                            request.supergraph_request.method(),
+                            request.supergraph_request.uri(),
+                        );
+                        let sent_context = params.context.clone().unwrap_or_default();
+
+                        let co_processor_output = call_external(
+                            transport,
+                            timeout,
+                            PipelineStep::SupergraphRequest,
+                            None,
+                            params,
+                        )
+                        .await?;
+
+                        tracing::debug!(?co_processor_output, "co-processor returned");
+
+                        if let Control::Break(_) = co_processor_output.control {
+                            let code = co_processor_output.control.get_http_status()?;
+                            let res = if !code.is_success() {
+                                supergraph::Response::error_builder()
+                                    .errors(vec![break_error(co_processor_output.body)])
+                                    .status_code(code)
+                                    .context(request.context)
+                                    .build()?
+                            } else {
+                                let mut res = supergraph::Response::new_from_graphql_response(
+                                    break_response(co_processor_output.body)?,
+                                    request.context,
+                                );
+                                *res.response.status_mut() = code;
+                                res
+                            };
+                            return Ok(ControlFlow::Break(res));
+                        }
+
+                        if let Some(body) = co_processor_output.body {
+                            let mut body = serde_json::from_value(body)?;
+                            if let Some(redactor) = &redactor {
+                                redactor
+                                    .restore_request(request.supergraph_request.body(), &mut body);
+                            }
+                            *request.supergraph_request.body_mut() = body;
+                        }
+
+                        if let Some(context) = co_processor_output.context {
+                            request.context.merge_changes(&sent_context, &context);
+                        }
+
+                        if let Some(headers) = co_processor_output.headers {
+                            *request.supergraph_request.headers_mut() = restored_headers(
+                                headers,
+                                request.supergraph_request.headers(),
+                                redactor.as_deref(),
+                            )?;
+                        }
+
+                        Ok(ControlFlow::Continue(request))
+                    }
+                })
+            });
+
+        fn external_service_span() -> impl Fn(&supergraph::Request) -> tracing::Span + Clone {
+            move |_request: &supergraph::Request| {
+                tracing::info_span!(
+                    EXTERNAL_SPAN_NAME,
+                    "external service" = stringify!(supergraph::Request),
+                    "otel.kind" = "INTERNAL"
+                )
+            }
+        }
+
+        ServiceBuilder::new()
+            .instrument(external_service_span())
+            .option_layer(request_layer)
+            .buffered()
+            .service(service)
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let stage = self
+            .configuration
+            .stages
+            .as_ref()
+            .and_then(|x| x.subgraph.as_ref());
+
+        let request_layer = stage.and_then(|x| x.request.clone()).map(|request_config| {
+            let sdl = self.sdl.clone();
+            let transport = self.transport.clone();
+            let timeout = self.configuration.timeout;
+            let service_name = name.to_string();
+            let redactor = self.redactor.clone();
+            AsyncCheckpointLayer::new(move |mut request: subgraph::Request| {
+                let my_sdl = sdl.to_string();
+                let transport = transport.clone();
+                let request_config = request_config.clone();
+                let service_name = service_name.clone();
+                let redactor = redactor.clone();
+                async move {
+                    let mut params = prepare_external_params(
+                        &request_config.base,
+                        request.subgraph_request.headers(),
+                        || redacted_request(request.subgraph_request.body(), redactor.as_deref()),
+                        &request.context,
+                        my_sdl,
+                        redactor.as_deref(),
+                    )?;
+                    request_config.add_http_params(
+                        &mut params,
+                        request.subgraph_request.method(),
+                        request.subgraph_request.uri(),
+                    );
+                    let sent_context = params.context.clone().unwrap_or_default();
+
+                    let co_processor_output = call_external(
+                        transport,
+                        timeout,
+                        PipelineStep::SubgraphRequest,
+                        Some(service_name),
+                        params,
+                    )
+                    .await?;
+
+                    tracing::debug!(?co_processor_output, "co-processor returned");
+
+                    if let Control::Break(_) = co_processor_output.control {
+                        let code = co_processor_output.control.get_http_status()?;
+                        let res = if !code.is_success() {
+                            subgraph::Response::error_builder()
+                                .errors(vec![break_error(co_processor_output.body)])
+                                .status_code(code)
+                                .context(request.context)
+                                .build()?
+                        } else {
+                            subgraph::Response::new_from_response(
+                                http::Response::builder()
+                                    .status(code)
+                                    .body(break_response(co_processor_output.body)?)?,
+                                request.context,
+                            )
+                        };
+                        return Ok(ControlFlow::Break(res));
+                    }
+
+                    if let Some(body) = co_processor_output.body {
+                        let mut body = serde_json::from_value(body)?;
+                        if let Some(redactor) = &redactor {
+                            redactor.restore_request(request.subgraph_request.body(), &mut body);
+                        }
+                        *request.subgraph_request.body_mut() = body;
+                    }
+
+                    if let Some(context) = co_processor_output.context {
+                        request.context.merge_changes(&sent_context, &context);
+                    }
+
+                    if let Some(headers) = co_processor_output.headers {
+                        *request.subgraph_request.headers_mut() = restored_headers(
+                            headers,
+                            request.subgraph_request.headers(),
+                            redactor.as_deref(),
+                        )?;
+                    }
+
+                    Ok(ControlFlow::Continue(request))
+                }
+            })
+        });
+
+        let response_layer = stage
+            .and_then(|x| x.response.clone())
+            .map(|response_config| {
+                let sdl = self.sdl.clone();
+                let transport = self.transport.clone();
+                let timeout = self.configuration.timeout;
+                let service_name = name.to_string();
+                let redactor = self.redactor.clone();
+                MapFutureWithRequestDataLayer::new(fetch_path, move |path: Path, fut| {
+                    let my_sdl = sdl.to_string();
+                    let transport = transport.clone();
+                    let response_config = response_config.clone();
+                    let service_name = service_name.clone();
+                    let redactor = redactor.clone();
+                    async move {
+                        let mut response: subgraph::Response = fut.await?;
+
+                        let mut params = prepare_external_params(
+                            &response_config.base,
+                            response.response.headers(),
+                            || {
+                                redacted_subgraph_response(
+                                    response.response.body(),
+                                    &path,
+                                    redactor.as_deref(),
+                                )
+                            },
+                            &response.context,
+                            my_sdl,
+                            redactor.as_deref(),
+                        )?;
+                        response_config.add_http_params(&mut params, response.response.status());
+                        let sent_context = params.context.clone().unwrap_or_default();
+
+                        let co_processor_output = call_external(
+                            transport,
+                            timeout,
+                            PipelineStep::SubgraphResponse,
+                            Some(service_name),
+                            params,
+                        )
+                        .await?;
+
+                        tracing::debug!(?co_processor_output, "co-processor returned");
+
+                        if let Some(body) = co_processor_output.body {
+                            let mut body = serde_json::from_value(body)?;
+                            if let Some(redactor) = &redactor {
+                                redactor.restore_subgraph_response(
+                                    response.response.body(),
+                                    &mut body,
+                                    &path,
+                                );
+                            }
+                            *response.response.body_mut() = body;
+                        }
+
+                        if let Some(context) = co_processor_output.context {
+                            response.context.merge_changes(&sent_context, &context);
+                        }
+
+                        if let Some(headers) = co_processor_output.headers {
+                            *response.response.headers_mut() = restored_headers(
+                                headers,
+                                response.response.headers(),
+                                redactor.as_deref(),
+                            )?;
+                        }
+
+                        Ok::<subgraph::Response, BoxError>(response)
+                    }
+                })
+            });
+
+        fn external_service_span() -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
+            move |_request: &subgraph::Request| {
+                tracing::info_span!(
+                    EXTERNAL_SPAN_NAME,
+                    "external service" = stringify!(subgraph::Request),
+                    "otel.kind" = "INTERNAL"
+                )
+            }
+        }
+
+        ServiceBuilder::new()
+            .instrument(external_service_span())
+            .option_layer(request_layer)
+            .option_layer(response_layer)
+            .buffered()
+            .service(service)
+            .boxed()
+    }
 }
 
-type ExternalParams<'a> = (
-    Option<&'a HeaderMap<HeaderValue>>,
-    Option<serde_json::Value>,
-    Option<Context>,
-    Option<String>,
-);
+/// The data sent to the co-processor, as selected by the stage configuration
+#[derive(Default)]
+struct ExternalParams<'a> {
+    headers: Option<Cow<'a, HeaderMap<HeaderValue>>>,
+    body: Option<serde_json::Value>,
+    context: Option<Context>,
+    sdl: Option<String>,
+    method: Option<String>,
+    path: Option<String>,
+    status_code: Option<u16>,
+}
 
 fn prepare_external_params<'a>(
     config: &'a BaseConf,
     headers: &'a HeaderMap<HeaderValue>,
-    bytes: &'a Bytes,
+    body: impl FnOnce() -> Result<serde_json::Value, BoxError>,
     context: &'a Context,
     sdl: String,
+    redactor: Option<&Redactor>,
 ) -> Result<ExternalParams<'a>, BoxError> {
-    let mut headers_opt = None;
-    // Note: We have to specify the json type or it won't deserialize correctly...
-    // let mut payload_opt: Option<serde_json::Value> = None;
-    let mut payload_opt: Option<serde_json::Value> = None;
-    let mut context_opt = None;
-    let mut sdl_opt = None;
-
-    if config.body || config.headers {
-        if config.body {
-            payload_opt = Some(serde_json::from_slice(bytes)?);
-        }
-        if config.headers {
-            headers_opt = Some(headers);
-        }
+    let mut params = ExternalParams::default();
+
+    if config.body {
+        params.body = Some(body()?);
+    }
+    if config.headers {
+        params.headers = Some(match redactor {
+            Some(redactor) => Cow::Owned(redactor.headers(headers)),
+            None => Cow::Borrowed(headers),
+        });
     }
     if config.context {
-        context_opt = Some(context.clone());
+        params.context = Some(context.snapshot());
     }
     if config.sdl {
-        sdl_opt = Some(sdl);
+        params.sdl = Some(sdl);
     }
-    Ok((headers_opt, payload_opt, context_opt, sdl_opt))
+    Ok(params)
 }
 
-async fn call_external<T>(
-    url: String,
-    timeout: Option<Duration>,
-    stage: PipelineStep,
-    headers: Option<&HeaderMap<HeaderValue>>,
-    payload: Option<T>,
-    context: Option<Context>,
-    sdl: Option<String>,
-) -> Result<Externalizable<T>, BoxError>
+/// Buffers a router body if it is sent to the co-processor, and returns a body to use
+/// in its place.
+///
+/// Bodies which are not sent are passed through without being buffered.
+async fn buffer_if(buffer: bool, body: Body) -> Result<(Body, Option<Bytes>), BoxError> {
+    if buffer {
+        let bytes = body::to_bytes(body).await?;
+        Ok((Body::from(bytes.clone()), Some(bytes)))
+    } else {
+        Ok((body, None))
+    }
+}
+
+fn parse_body(bytes: Option<&Bytes>) -> Result<serde_json::Value, BoxError> {
+    match bytes {
+        Some(bytes) => Ok(serde_json::from_slice(bytes)?),
+        None => Ok(serde_json::Value::Null),
+    }
+}
+
+/// Parses a router body, with its redacted values if it is a GraphQL request or response
+fn redacted_router_body<T>(
+    bytes: Option<&Bytes>,
+    redactor: Option<&Redactor>,
+    redact: fn(&Redactor, &T) -> T,
+) -> Result<serde_json::Value, BoxError>
 where
-    T: fmt::Debug + DeserializeOwned + Serialize + Send + Sync + 'static,
+    T: DeserializeOwned + Serialize,
 {
+    if let (Some(redactor), Some(bytes)) = (redactor, bytes) {
+        if let Ok(body) = serde_json::from_slice::<T>(bytes) {
+            return Ok(serde_json::to_value(redact(redactor, &body))?);
+        }
+    }
+    parse_body(bytes)
+}
+
+/// Serializes the router body returned by the co-processor, with the original values of the
+/// redacted values it returned unchanged
+fn restored_router_body<T>(
+    returned: serde_json::Value,
+    original: Option<&Bytes>,
+    redactor: Option<&Redactor>,
+    restore: fn(&Redactor, &T, &mut T),
+) -> Result<Body, BoxError>
+where
+    T: DeserializeOwned + Serialize,
+{
+    if let (Some(redactor), Some(original)) = (redactor, original) {
+        if let (Ok(original), Ok(mut body)) = (
+            serde_json::from_slice::<T>(original),
+            T::deserialize(&returned),
+        ) {
+            restore(redactor, &original, &mut body);
+            return Ok(Body::from(serde_json::to_vec(&body)?));
+        }
+    }
+    Ok(Body::from(serde_json::to_vec(&returned)?))
+}
+
+/// The path of the fetch a subgraph request was made for, in the client response
+fn fetch_path(request: &subgraph::Request) -> Path {
+    request
+        .subgraph_request
+        .extensions()
+        .get::<FetchPath>()
+        .map(|path| path.0.clone())
+        .unwrap_or_else(Path::empty)
+}
+
+fn redacted_subgraph_response(
+    response: &graphql::Response,
+    path: &Path,
+    redactor: Option<&Redactor>,
+) -> Result<serde_json::Value, BoxError> {
+    Ok(match redactor {
+        Some(redactor) => serde_json::to_value(redactor.subgraph_response(response, path))?,
+        None => serde_json::to_value(response)?,
+    })
+}
+
+fn redacted_request(
+    request: &graphql::Request,
+    redactor: Option<&Redactor>,
+) -> Result<serde_json::Value, BoxError> {
+    Ok(match redactor {
+        Some(redactor) => serde_json::to_value(redactor.request(request))?,
+        None => serde_json::to_value(request)?,
+    })
+}
+
+/// Converts the headers returned by the co-processor, with the original values of the redacted
+/// headers it returned unchanged
+fn restored_headers(
+    returned: HashMap<String, Vec<String>>,
+    original: &HeaderMap<HeaderValue>,
+    redactor: Option<&Redactor>,
+) -> Result<HeaderMap<HeaderValue>, BoxError> {
+    let mut headers = internalize_header_map(returned)?;
+    if let Some(redactor) = redactor {
+        redactor.restore_headers(original, &mut headers);
+    }
+    Ok(headers)
+}
+
+async fn call_external(
+    transport: Arc<Transport>,
+    timeout: Option<Duration>,
+    stage: PipelineStep,
+    service_name: Option<String>,
+    params: ExternalParams<'_>,
+) -> Result<Externalizable<serde_json::Value>, BoxError> {
     let mut converted_headers = None;
-    if let Some(hdrs) = headers {
-        converted_headers = Some(externalize_header_map(hdrs)?);
+    if let Some(hdrs) = params.headers {
+        converted_headers = Some(externalize_header_map(&hdrs)?);
     };
-    let output = Externalizable::new(stage, converted_headers, payload, context, sdl);
+    let mut output = Externalizable::new(
+        stage,
+        converted_headers,
+        params.body,
+        params.context,
+        params.sdl,
+    );
+    output.service_name = service_name;
+    output.method = params.method;
+    output.path = params.path;
+    output.status_code = params.status_code;
     tracing::debug!(?output, "externalized output");
-    output.call(&url, timeout).await
+    output.call(&transport, timeout).await
+}
+
+/// Error returned to the client when the co-processor breaks with a non success status
+fn break_error(body: Option<serde_json::Value>) -> Error {
+    Error {
+        message: body.unwrap_or(serde_json::Value::Null).to_string(),
+        ..Default::default()
+    }
+}
+
+/// Response returned to the client when the co-processor breaks with a success status
+fn break_response(body: Option<serde_json::Value>) -> Result<graphql::Response, BoxError> {
+    Ok(match body {
+        Some(body) => serde_json::from_value(body)?,
+        None => graphql::Response::default(),
+    })
 }
 
 /// Convert a HeaderMap into a HashMap
-fn externalize_header_map(
+pub(crate) fn externalize_header_map(
     input: &HeaderMap<HeaderValue>,
 ) -> Result<HashMap<String, Vec<String>>, BoxError> {
     let mut output = HashMap::new();
@@ -397,7 +974,7 @@ fn externalize_header_map(
 }
 
 /// Convert a HashMap into a HeaderMap
-fn internalize_header_map(
+pub(crate) fn internalize_header_map(
     input: HashMap<String, Vec<String>>,
 ) -> Result<HeaderMap<HeaderValue>, BoxError> {
     let mut output = HeaderMap::new();
@@ -420,14 +997,67 @@ register_plugin!("experimental", "external", ExternalPlugin);
 
 #[cfg(test)]
 mod tests {
+    use std::pin::Pin;
+    use std::sync::Mutex;
+
+    use futures::StreamExt;
     use http::header::ACCEPT;
     use http::header::CONTENT_TYPE;
     use http::HeaderMap;
     use http::HeaderValue;
     use mime::APPLICATION_JSON;
     use mime::TEXT_HTML;
+    use serde_json_bytes::json;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::Status;
+    use tonic::Streaming;
 
     use super::*;
+    use crate::plugin::test::MockSubgraphService;
+    use crate::redaction::REDACTED;
+    use crate::services::external::proto;
+    use crate::services::external::proto::coprocessor_server::Coprocessor;
+    use crate::services::external::proto::coprocessor_server::CoprocessorServer;
+
+    /// gRPC co-processor recording the bodies it receives, and returning the messages unchanged
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<serde_json::Value>>>);
+
+    #[tonic::async_trait]
+    impl Coprocessor for Recorder {
+        type ProcessStream =
+            Pin<Box<dyn futures::Stream<Item = Result<proto::Externalizable, Status>> + Send>>;
+
+        async fn process(
+            &self,
+            request: tonic::Request<Streaming<proto::Externalizable>>,
+        ) -> Result<tonic::Response<Self::ProcessStream>, Status> {
+            let bodies = self.0.clone();
+            let replies = request.into_inner().map(move |message| {
+                message.map(|message| {
+                    if let Some(body) = &message.body {
+                        bodies
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_str(body).unwrap());
+                    }
+                    message
+                })
+            });
+            Ok(tonic::Response::new(Box::pin(replies)))
+        }
+    }
+
+    async fn co_processor(recorder: Recorder) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(CoprocessorServer::new(recorder))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        format!("http://{address}")
+    }
 
     #[tokio::test]
     async fn load_plugin() {
@@ -449,6 +1079,155 @@ mod tests {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn load_plugin_with_all_stages() {
+        let config = serde_json::json!({
+            "plugins": {
+                "experimental.external": {
+                    "url": "http://127.0.0.1:8081",
+                    "stages": {
+                        "router": { "request": { "headers": true } },
+                        "supergraph": { "request": { "body": true } },
+                        "subgraph": {
+                            "request": { "headers": true, "body": true },
+                            "response": { "context": true }
+                        }
+                    }
+                }
+            }
+        });
+        let _test_harness = crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_plugin_with_grpc() {
+        let config = serde_json::json!({
+            "plugins": {
+                "experimental.external": {
+                    "url": "http://127.0.0.1:8081",
+                    "protocol": "grpc",
+                    "stages": {
+                        "subgraph": { "request": { "headers": true } }
+                    }
+                }
+            }
+        });
+        // The stream is only opened on the first call, so the co-processor does not need to run
+        let _test_harness = crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_redacts_the_subgraph_responses_sent_to_the_co_processor() {
+        let recorder = Recorder::default();
+        let config: Conf = serde_json::from_value(serde_json::json!({
+            "url": co_processor(recorder.clone()).await,
+            "protocol": "grpc",
+            "stages": { "subgraph": { "response": { "body": true } } },
+            "redaction": { "response_fields": ["topProducts/@/price"] }
+        }))
+        .unwrap();
+        let plugin = ExternalPlugin::new(PluginInit::new(config, Default::default()))
+            .await
+            .unwrap();
+
+        let mut mock_service = MockSubgraphService::new();
+        mock_service.expect_call().returning(|request| {
+            Ok(subgraph::Response::fake_builder()
+                .data(json!({ "_entities": [{ "name": "table", "price": 10 }] }))
+                .context(request.context)
+                .build())
+        });
+        let service = plugin.subgraph_service("products", mock_service.boxed());
+
+        // the entities of the products of the list are fetched at this path
+        let mut request = subgraph::Request::fake_builder().build();
+        request
+            .subgraph_request
+            .extensions_mut()
+            .insert(FetchPath(Path::from("topProducts/@")));
+        let response = service.oneshot(request).await.unwrap();
+
+        let received = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            received[0]["data"],
+            serde_json::json!({ "_entities": [{ "name": "table", "price": REDACTED }] })
+        );
+        // the co-processor returned the redacted price unchanged
+        assert_eq!(
+            response.response.body().data,
+            Some(json!({ "_entities": [{ "name": "table", "price": 10 }] }))
+        );
+    }
+
+    #[test]
+    fn it_merges_the_returned_context() {
+        let context = Context::new();
+        context.insert("kept", "original".to_string()).unwrap();
+        context.insert("replaced", "original".to_string()).unwrap();
+
+        let returned = Context::new();
+        returned.insert("replaced", "updated".to_string()).unwrap();
+        returned.insert("added", "updated".to_string()).unwrap();
+
+        // the context was not sent, the returned entries are added
+        context.merge_changes(&Context::new(), &returned);
+
+        for (key, expected) in [
+            ("kept", "original"),
+            ("replaced", "updated"),
+            ("added", "updated"),
+        ] {
+            assert_eq!(
+                context.get::<_, String>(key).unwrap().as_deref(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn it_only_sends_the_selected_fields() {
+        let config: RequestConf =
+            serde_json::from_value(serde_json::json!({ "headers": true, "path": true })).unwrap();
+        let headers = HeaderMap::new();
+        let context = Context::new();
+        let mut params = prepare_external_params(
+            &config.base,
+            &headers,
+            || panic!("the body is not selected"),
+            &context,
+            String::new(),
+            None,
+        )
+        .unwrap();
+        config.add_http_params(
+            &mut params,
+            &Method::POST,
+            &Uri::from_static("http://localhost/graphql?name=me"),
+        );
+
+        assert!(params.headers.is_some());
+        assert!(params.body.is_none());
+        assert!(params.context.is_none());
+        assert!(params.sdl.is_none());
+        assert!(params.method.is_none());
+        assert_eq!(params.path.as_deref(), Some("/graphql"));
+
+        let config: ResponseConf =
+            serde_json::from_value(serde_json::json!({ "status": true })).unwrap();
+        config.add_http_params(&mut params, StatusCode::NOT_FOUND);
+        assert_eq!(params.status_code, Some(404));
+    }
+
     #[test]
     fn it_externalizes_headers() {
         // Build our expected HashMap
@@ -0,0 +1,326 @@
+//! Fault injection for subgraph requests
+//!
+//! Used to test how the router, and plugins built on top of it, behave when
+//! subgraphs are slow or failing. This is not meant to be enabled in production.
+
+use std::collections::HashMap;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Service;
+use tower::ServiceExt;
+
+use crate::error::ConfigurationError;
+use crate::error::Error;
+use crate::error::FetchError;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::subgraph;
+
+pub(crate) const APOLLO_FAULT_INJECTION: &str = "apollo.experimental_fault_injection";
+pub(crate) const FAULT_INJECTED_EXTENSION_CODE: &str = "FAULT_INJECTED";
+
+/// Configuration for injecting faults in subgraph requests
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Faults injected in requests to all subgraphs
+    #[serde(default)]
+    pub(crate) all: Option<SubgraphFaults>,
+    /// Faults injected in requests to specific subgraphs, replacing the `all` configuration
+    #[serde(default)]
+    pub(crate) subgraphs: HashMap<String, SubgraphFaults>,
+}
+
+/// Faults injected in the requests to a subgraph
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphFaults {
+    /// Fraction of the subgraph requests affected, between 0 and 1
+    rate: f64,
+    /// Latency added to affected requests
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    latency: Option<Duration>,
+    /// Failure applied to affected requests
+    fault: Option<Fault>,
+}
+
+#[buildstructor::buildstructor]
+impl SubgraphFaults {
+    /// Creates faults affecting a `rate` fraction (between 0 and 1) of the subgraph requests.
+    #[builder(visibility = "pub")]
+    fn new(rate: f64, latency: Option<Duration>, fault: Option<Fault>) -> Self {
+        Self {
+            rate,
+            latency,
+            fault,
+        }
+    }
+
+    fn validate(&self, subgraph_name: &str) -> Result<(), ConfigurationError> {
+        if (0.0..=1.0).contains(&self.rate) {
+            Ok(())
+        } else {
+            Err(ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for experimental_fault_injection plugin",
+                error: format!(
+                    "the rate for '{subgraph_name}' must be between 0 and 1, got {}",
+                    self.rate
+                ),
+            })
+        }
+    }
+}
+
+/// Failure injected in a subgraph request
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// The connection is reset before the subgraph responds
+    ConnectionReset,
+    /// The subgraph responds with a body that is not valid JSON
+    MalformedJson,
+    /// The subgraph response gets an additional GraphQL error, its data is kept
+    PartialErrors,
+}
+
+pub(crate) struct FaultInjection {
+    config: Config,
+}
+
+impl FaultInjection {
+    pub(crate) fn from_config(config: Config) -> Result<Self, BoxError> {
+        if let Some(all) = &config.all {
+            all.validate("all")?;
+        }
+        for (name, faults) in &config.subgraphs {
+            faults.validate(name)?;
+        }
+
+        Ok(Self { config })
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for FaultInjection {
+    type Config = Config;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Self::from_config(init.config)
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        match self.config.subgraphs.get(name).or(self.config.all.as_ref()) {
+            Some(faults) if faults.rate > 0.0 => FaultInjectionService {
+                service_name: name.to_string(),
+                faults: faults.clone(),
+                inner: service,
+            }
+            .boxed(),
+            _ => service,
+        }
+    }
+}
+
+struct FaultInjectionService<S> {
+    service_name: String,
+    faults: SubgraphFaults,
+    inner: S,
+}
+
+impl<S> Service<subgraph::Request> for FaultInjectionService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: subgraph::Request) -> Self::Future {
+        if !rand::thread_rng().gen_bool(self.faults.rate) {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let latency = self.faults.latency;
+        let service = self.service_name.clone();
+        tracing::debug!(
+            "injecting fault {:?} with latency {:?} in request to subgraph {}",
+            self.faults.fault,
+            latency,
+            service
+        );
+
+        let partial_errors = self.faults.fault == Some(Fault::PartialErrors);
+        let response = match self.faults.fault {
+            Some(Fault::ConnectionReset) => Err(FetchError::SubrequestHttpError {
+                service: service.clone(),
+                reason: "connection reset by peer (injected fault)".to_string(),
+            }),
+            Some(Fault::MalformedJson) => Err(FetchError::SubrequestMalformedResponse {
+                service: service.clone(),
+                reason: "expected value at line 1 column 1 (injected fault)".to_string(),
+            }),
+            Some(Fault::PartialErrors) | None => Ok(self.inner.call(req)),
+        };
+
+        Box::pin(async move {
+            if let Some(latency) = latency {
+                tokio::time::sleep(latency).await;
+            }
+
+            let mut response = response?.await?;
+            if partial_errors {
+                response.response.body_mut().errors.push(
+                    Error::builder()
+                        .message(format!("injected fault in subgraph {service}"))
+                        .extension_code(FAULT_INJECTED_EXTENSION_CODE)
+                        .build(),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
+
+register_plugin!("apollo", "experimental_fault_injection", FaultInjection);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+    use crate::services::supergraph;
+    use crate::TestHarness;
+
+    fn products() -> MockSubgraph {
+        MockSubgraph::builder()
+            .with_json(
+                json! {{"query": "{topProducts{upc}}"}},
+                json! {{"data": {"topProducts": [{"upc": "1"}]}}},
+            )
+            .build()
+    }
+
+    async fn query(harness: TestHarness<'_>) -> crate::graphql::Response {
+        let request = supergraph::Request::fake_builder()
+            .query("{ topProducts { upc } }")
+            .build()
+            .unwrap();
+        harness
+            .configuration_json(json!({"include_subgraph_errors": { "all": true }}))
+            .unwrap()
+            .subgraph_mock("products", products())
+            .build_supergraph()
+            .await
+            .unwrap()
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_injects_connection_resets() {
+        let response = query(
+            TestHarness::builder().subgraph_faults(
+                "products",
+                SubgraphFaults::builder()
+                    .rate(1.0)
+                    .fault(Fault::ConnectionReset)
+                    .build(),
+            ),
+        )
+        .await;
+
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0]
+            .message
+            .contains("connection reset by peer (injected fault)"));
+    }
+
+    #[tokio::test]
+    async fn it_injects_malformed_responses() {
+        let response = query(
+            TestHarness::builder().subgraph_faults(
+                "products",
+                SubgraphFaults::builder()
+                    .rate(1.0)
+                    .fault(Fault::MalformedJson)
+                    .build(),
+            ),
+        )
+        .await;
+
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0]
+            .message
+            .contains("response was malformed"));
+    }
+
+    #[tokio::test]
+    async fn it_injects_partial_errors() {
+        let response = query(
+            TestHarness::builder().subgraph_faults(
+                "products",
+                SubgraphFaults::builder()
+                    .rate(1.0)
+                    .fault(Fault::PartialErrors)
+                    .build(),
+            ),
+        )
+        .await;
+
+        assert_eq!(
+            serde_json::to_value(&response.data).unwrap(),
+            json! {{"topProducts": [{"upc": "1"}]}}
+        );
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0]
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str()),
+            Some(FAULT_INJECTED_EXTENSION_CODE)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_does_not_inject_faults_at_rate_zero() {
+        let response = query(
+            TestHarness::builder().subgraph_faults(
+                "products",
+                SubgraphFaults::builder()
+                    .rate(0.0)
+                    .fault(Fault::ConnectionReset)
+                    .build(),
+            ),
+        )
+        .await;
+
+        assert!(response.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_rates() {
+        let config: Config = serde_json::from_value(json!({
+            "subgraphs": { "products": { "rate": 1.5 } }
+        }))
+        .unwrap();
+        assert!(FaultInjection::from_config(config).is_err());
+    }
+}
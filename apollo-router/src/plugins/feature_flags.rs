@@ -0,0 +1,225 @@
+//! Per-request feature flags
+//!
+//! The flags of a request are sent in a header, as a JWT signed with a secret shared with the
+//! router, so that clients can't enable them on their own: the gateway or the service issuing
+//! the token decides which requests try a feature. The flags are stored in the context, where
+//! the router features supporting them, plugins and scripts read them. Requests without a valid
+//! header get the flags configured by default.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use http::header::HeaderName;
+use http::HeaderMap;
+use jsonwebtoken::decode;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Validation;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::router;
+use crate::Context;
+
+/// The feature flags of the request, in the context
+pub(crate) const FEATURE_FLAGS_CONTEXT_KEY: &str = "apollo_feature_flags::flags";
+
+/// Sends the request to the canary schema if enabled, and to the primary schema if disabled
+pub(crate) const CANARY_FLAG: &str = "canary";
+/// Rejects the operations using `@defer` if disabled
+pub(crate) const DEFER_FLAG: &str = "defer";
+/// Plans the operation without the query plan cache if disabled
+pub(crate) const QUERY_PLAN_CACHE_FLAG: &str = "query_plan_cache";
+
+/// Feature flags configuration
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// The header of the feature flags: a JWT signed with `secret` and the HS256 algorithm,
+    /// whose `flags` claim maps flag names to their values, and which must have an `exp` claim
+    #[serde(default = "default_header")]
+    header: String,
+
+    /// The secret signing the feature flags header
+    secret: String,
+
+    /// The flags of the requests, which the flags of their header override
+    #[serde(default)]
+    defaults: HashMap<String, Value>,
+}
+
+fn default_header() -> String {
+    "x-router-feature-flags".to_string()
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    #[serde(default)]
+    flags: HashMap<String, Value>,
+}
+
+struct Parser {
+    header: HeaderName,
+    key: DecodingKey,
+    validation: Validation,
+    defaults: HashMap<String, Value>,
+}
+
+impl Parser {
+    /// The default flags, overridden by the flags of the header if its signature is valid
+    fn flags(&self, headers: &HeaderMap) -> HashMap<String, Value> {
+        let mut flags = self.defaults.clone();
+        if let Some(token) = headers.get(&self.header) {
+            match self.decode(token.as_bytes()) {
+                Ok(claims) => flags.extend(claims.flags),
+                Err(e) => tracing::debug!("invalid feature flags header: {e}"),
+            }
+        }
+        flags
+    }
+
+    fn decode(&self, token: &[u8]) -> Result<Claims, BoxError> {
+        let token = std::str::from_utf8(token)?;
+        Ok(decode::<Claims>(token, &self.key, &self.validation)?.claims)
+    }
+}
+
+/// The value of a boolean feature flag of the request, if it is set
+pub(crate) fn flag(context: &Context, name: &str) -> Option<bool> {
+    context
+        .get::<_, HashMap<String, Value>>(FEATURE_FLAGS_CONTEXT_KEY)
+        .ok()
+        .flatten()?
+        .get(name)?
+        .as_bool()
+}
+
+struct FeatureFlags {
+    parser: Arc<Parser>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for FeatureFlags {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let config = init.config;
+        Ok(FeatureFlags {
+            parser: Arc::new(Parser {
+                header: HeaderName::try_from(config.header)?,
+                key: DecodingKey::from_secret(config.secret.as_bytes()),
+                validation: Validation::new(Algorithm::HS256),
+                defaults: config.defaults,
+            }),
+        })
+    }
+
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        let parser = self.parser.clone();
+        ServiceBuilder::new()
+            .map_request(move |request: router::Request| {
+                let flags = parser.flags(request.router_request.headers());
+                let _ = request.context.insert(FEATURE_FLAGS_CONTEXT_KEY, flags);
+                request
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "feature_flags", FeatureFlags);
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::encode;
+    use jsonwebtoken::EncodingKey;
+    use jsonwebtoken::Header;
+    use serde_json::json;
+    use tower::Service;
+
+    use super::*;
+    use crate::plugin::test::MockRouterService;
+
+    fn token(secret: &str, flags: Value) -> String {
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        encode(
+            &Header::default(),
+            &json!({ "flags": flags, "exp": exp }),
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    async fn flags(header: Option<String>) -> HashMap<String, Value> {
+        let plugin = crate::plugin::plugins()
+            .find(|factory| factory.name == "apollo.feature_flags")
+            .expect("Plugin not found")
+            .create_instance_without_schema(&json!({
+                "secret": "shared secret",
+                "defaults": { "canary": false, "theme": "light" }
+            }))
+            .await
+            .unwrap();
+
+        let mut mock_service = MockRouterService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(move |request: router::Request| {
+                router::Response::builder().context(request.context).build()
+            });
+        let mut service = plugin.router_service(router::BoxService::new(mock_service));
+
+        let mut request = http::Request::builder();
+        if let Some(header) = header {
+            request = request.header("x-router-feature-flags", header);
+        }
+        let request = router::Request::from(request.body(hyper::Body::empty()).unwrap());
+        let response = service.ready().await.unwrap().call(request).await.unwrap();
+        response
+            .context
+            .get(FEATURE_FLAGS_CONTEXT_KEY)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_overrides_the_default_flags_with_the_signed_header() {
+        let flags = flags(Some(token("shared secret", json!({ "canary": true })))).await;
+        assert_eq!(flags["canary"], json!(true));
+        assert_eq!(flags["theme"], json!("light"));
+    }
+
+    #[tokio::test]
+    async fn it_ignores_headers_with_an_invalid_signature() {
+        let signed = flags(Some(token("another secret", json!({ "canary": true })))).await;
+        assert_eq!(signed["canary"], json!(false));
+
+        let unsigned = flags(None).await;
+        assert_eq!(unsigned["canary"], json!(false));
+    }
+
+    #[test]
+    fn it_reads_boolean_flags() {
+        let context = Context::new();
+        assert_eq!(flag(&context, CANARY_FLAG), None);
+        context
+            .insert(
+                FEATURE_FLAGS_CONTEXT_KEY,
+                HashMap::from([
+                    (CANARY_FLAG.to_string(), json!(true)),
+                    (DEFER_FLAG.to_string(), json!("off")),
+                ]),
+            )
+            .unwrap();
+        assert_eq!(flag(&context, CANARY_FLAG), Some(true));
+        assert_eq!(flag(&context, DEFER_FLAG), None);
+    }
+}
@@ -0,0 +1,465 @@
+//! Uploads of files with the GraphQL multipart request spec
+//!
+//! `multipart/form-data` requests start with the `operations` and `map` fields: the operation, with
+//! `null` in place of the files, and the paths of the variables of each file. The router reads
+//! them and executes the operation as a JSON request, while the files are left in the client
+//! request: they are streamed to the subgraph request using their variables, as a new multipart
+//! request, without being buffered in memory.
+//!
+//! See <https://github.com/jaydenseric/graphql-multipart-request-spec>.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::HeaderValue;
+use http::StatusCode;
+use mime::APPLICATION_JSON;
+use multer::Constraints;
+use multer::Multipart;
+use multer::SizeLimit;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::router;
+
+const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+const VARIABLES_PREFIX: &str = "variables.";
+
+/// Uploads of files with the GraphQL multipart request spec
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Maximum size of a file, in bytes
+    /// default: 10485760 (10 MiB)
+    #[serde(default = "default_max_file_size")]
+    max_file_size: u64,
+
+    /// Maximum size of a multipart request, including the operation and all its files, in bytes
+    /// default: 52428800 (50 MiB)
+    #[serde(default = "default_max_total_size")]
+    max_total_size: u64,
+
+    /// Maximum number of files of a request
+    /// default: 10
+    #[serde(default = "default_max_files")]
+    max_files: usize,
+}
+
+fn default_max_file_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_total_size() -> u64 {
+    50 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    10
+}
+
+/// The files of a multipart request, left in the client request until a subgraph request uses
+/// them
+pub(crate) struct UploadedFiles {
+    /// Paths of the variables of each file, by name of the field of the file
+    map: HashMap<String, Vec<String>>,
+    multipart: Mutex<Option<Multipart<'static>>>,
+}
+
+/// The files sent with a subgraph request
+pub(crate) struct SubgraphFiles {
+    /// Paths of the variables of the subgraph request, by name of the field of the file
+    map: HashMap<String, Vec<String>>,
+    multipart: Multipart<'static>,
+}
+
+impl UploadedFiles {
+    /// Takes the files used by the variables of a subgraph request. The files are streamed from
+    /// the client request, so they can only be sent to a single subgraph request
+    pub(crate) fn take_for(
+        &self,
+        request: &graphql::Request,
+    ) -> Option<Result<SubgraphFiles, String>> {
+        let map: HashMap<String, Vec<String>> = self
+            .map
+            .iter()
+            .filter_map(|(name, paths)| {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .filter(|path| request.variables.contains_key(variable_name(path)))
+                    .cloned()
+                    .collect();
+                (!paths.is_empty()).then(|| (name.clone(), paths))
+            })
+            .collect();
+        if map.is_empty() {
+            return None;
+        }
+        let multipart = self
+            .multipart
+            .lock()
+            .expect("the lock is not held across panics; qed")
+            .take();
+        Some(match multipart {
+            Some(multipart) => Ok(SubgraphFiles { map, multipart }),
+            None => Err("uploaded files can only be sent to a single subgraph request".to_string()),
+        })
+    }
+}
+
+/// The name of the variable of a path such as `variables.input.files.0`
+fn variable_name(path: &str) -> &str {
+    let path = path.strip_prefix(VARIABLES_PREFIX).unwrap_or(path);
+    path.split('.').next().unwrap_or(path)
+}
+
+impl SubgraphFiles {
+    /// Returns the content type and the body of the multipart subgraph request. The files are
+    /// streamed from the client request while the body is sent
+    pub(crate) fn into_body(
+        self,
+        operations: String,
+    ) -> Result<(HeaderValue, hyper::Body), BoxError> {
+        let SubgraphFiles { map, mut multipart } = self;
+        let boundary = format!("graphql-{:016x}", rand::random::<u64>());
+        let content_type =
+            HeaderValue::try_from(format!("{MULTIPART_FORM_DATA}; boundary={boundary}"))?;
+        let head = format!(
+            "--{boundary}\r\ncontent-disposition: form-data; name=\"operations\"\r\ncontent-type: application/json\r\n\r\n{operations}\r\n\
+            --{boundary}\r\ncontent-disposition: form-data; name=\"map\"\r\ncontent-type: application/json\r\n\r\n{}\r\n",
+            serde_json::to_string(&map)?
+        );
+
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::task::spawn(async move {
+            let result: Result<(), BoxError> = async {
+                sender.send_data(head.into()).await?;
+                while let Some(mut field) = multipart.next_field().await? {
+                    let name = field.name().unwrap_or_default().to_string();
+                    // the files of other subgraph requests are skipped
+                    if !map.contains_key(&name) {
+                        continue;
+                    }
+                    let file_name = field
+                        .file_name()
+                        .unwrap_or(&name)
+                        .replace('"', "%22")
+                        .replace(|c| c == '\r' || c == '\n', "");
+                    let content_type = field
+                        .content_type()
+                        .map(|mime| mime.to_string())
+                        .unwrap_or_else(|| "application/octet-stream".to_string());
+                    sender
+                        .send_data(Bytes::from(format!(
+                            "--{boundary}\r\ncontent-disposition: form-data; name=\"{name}\"; filename=\"{file_name}\"\r\ncontent-type: {content_type}\r\n\r\n"
+                        )))
+                        .await?;
+                    while let Some(chunk) = field.chunk().await? {
+                        sender.send_data(chunk).await?;
+                    }
+                    sender.send_data(Bytes::from_static(b"\r\n")).await?;
+                }
+                sender
+                    .send_data(Bytes::from(format!("--{boundary}--\r\n")))
+                    .await?;
+                Ok(())
+            }
+            .await;
+            if let Err(error) = result {
+                tracing::error!("could not stream the uploaded files: {error}");
+                sender.abort();
+            }
+        });
+        Ok((content_type, body))
+    }
+}
+
+#[derive(Debug)]
+struct FileUploads {
+    config: Conf,
+}
+
+#[async_trait::async_trait]
+impl Plugin for FileUploads {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(FileUploads {
+            config: init.config,
+        })
+    }
+
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        let config = self.config.clone();
+        ServiceBuilder::new()
+            .checkpoint_async(move |request: router::Request| {
+                let config = config.clone();
+                async move {
+                    let is_multipart = request
+                        .router_request
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .map_or(false, |value| value.starts_with(MULTIPART_FORM_DATA));
+                    if !is_multipart {
+                        return Ok(ControlFlow::Continue(request));
+                    }
+
+                    let context = request.context.clone();
+                    match read_operations(request, &config).await {
+                        Ok(request) => Ok(ControlFlow::Continue(request)),
+                        Err((message, status)) => {
+                            tracing::info!(
+                                monotonic_counter.apollo_router_file_uploads_rejected_total = 1u64,
+                            );
+                            let response = router::Response::error_builder()
+                                .error(
+                                    graphql::Error::builder()
+                                        .message(message)
+                                        .extension_code("INVALID_MULTIPART_REQUEST")
+                                        .build(),
+                                )
+                                .status_code(status)
+                                .context(context)
+                                .build()?;
+                            Ok(ControlFlow::Break(response))
+                        }
+                    }
+                }
+            })
+            .buffered()
+            .service(service)
+            .boxed()
+    }
+}
+
+/// Reads the operation of a multipart request, and returns it as a JSON request keeping the files
+/// in its extensions
+async fn read_operations(
+    request: router::Request,
+    config: &Conf,
+) -> Result<router::Request, (String, StatusCode)> {
+    let bad_request = |message: String| (message, StatusCode::BAD_REQUEST);
+    let router::Request {
+        router_request,
+        context,
+    } = request;
+    let (mut parts, body) = router_request.into_parts();
+
+    let content_length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.map_or(false, |length| length > config.max_total_size) {
+        return Err((
+            format!(
+                "the request exceeds the limit of {} bytes",
+                config.max_total_size
+            ),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+
+    let boundary = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| multer::parse_boundary(value).ok())
+        .ok_or_else(|| bad_request("the multipart boundary is missing".to_string()))?;
+    let constraints = Constraints::new().size_limit(
+        SizeLimit::new()
+            .whole_stream(config.max_total_size)
+            .per_field(config.max_file_size)
+            .for_field("operations", config.max_total_size)
+            .for_field("map", config.max_total_size),
+    );
+    let mut multipart = Multipart::with_constraints(body, boundary, constraints);
+
+    let operations = next_field(&mut multipart, "operations").await?;
+    let map = next_field(&mut multipart, "map").await?;
+
+    if operations.iter().find(|byte| !byte.is_ascii_whitespace()) != Some(&b'{') {
+        return Err(bad_request(
+            "the 'operations' field must contain a single operation".to_string(),
+        ));
+    }
+    let map: HashMap<String, Vec<String>> = serde_json::from_slice(&map)
+        .map_err(|e| bad_request(format!("invalid 'map' field: {e}")))?;
+    if map.len() > config.max_files {
+        return Err((
+            format!(
+                "the request has {} files, exceeding the limit of {}",
+                map.len(),
+                config.max_files
+            ),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+    if let Some(path) = map
+        .values()
+        .flatten()
+        .find(|path| !path.starts_with(VARIABLES_PREFIX))
+    {
+        return Err(bad_request(format!(
+            "files can only be mapped to variables, not to '{path}'"
+        )));
+    }
+
+    parts.headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+    );
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.extensions.insert(Arc::new(UploadedFiles {
+        map,
+        multipart: Mutex::new(Some(multipart)),
+    }));
+    Ok(router::Request {
+        router_request: http::Request::from_parts(parts, hyper::Body::from(operations)),
+        context,
+    })
+}
+
+/// Reads a field expected at this position of the multipart request
+async fn next_field(
+    multipart: &mut Multipart<'static>,
+    expected: &'static str,
+) -> Result<Bytes, (String, StatusCode)> {
+    let bad_request = |message: String| (message, StatusCode::BAD_REQUEST);
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(e.to_string()))?
+        .ok_or_else(|| bad_request(format!("the '{expected}' field is missing")))?;
+    if field.name() != Some(expected) {
+        return Err(bad_request(format!(
+            "expected the '{expected}' field, found '{}'",
+            field.name().unwrap_or_default()
+        )));
+    }
+    field.bytes().await.map_err(|e| bad_request(e.to_string()))
+}
+
+register_plugin!("apollo", "file_uploads", FileUploads);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    const BODY: &str = "--X
+content-disposition: form-data; name=\"operations\"
+
+{ \"query\": \"mutation ($file: Upload!) { upload(file: $file) }\", \"variables\": { \"file\": null } }
+--X
+content-disposition: form-data; name=\"map\"
+
+{ \"0\": [\"variables.file\"] }
+--X
+content-disposition: form-data; name=\"0\"; filename=\"a.txt\"
+content-type: text/plain
+
+hello
+--X--
+";
+
+    fn multipart_request() -> router::Request {
+        http::Request::builder()
+            .method("POST")
+            .uri("http://example.com/")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(hyper::Body::from(BODY.replace('\n', "\r\n")))
+            .unwrap()
+            .into()
+    }
+
+    fn default_config() -> Conf {
+        serde_json::from_value(serde_json::json!({})).unwrap()
+    }
+
+    /// Reads the operation of the request, and the body of the subgraph request using its files
+    async fn subgraph_body(config: &Conf) -> (HeaderValue, hyper::Body) {
+        let request = read_operations(multipart_request(), config).await.unwrap();
+        let (parts, body) = request.router_request.into_parts();
+        assert_eq!(parts.headers[CONTENT_TYPE], "application/json");
+        let operations: graphql::Request =
+            serde_json::from_slice(&hyper::body::to_bytes(body).await.unwrap()).unwrap();
+        let files = parts.extensions.get::<Arc<UploadedFiles>>().unwrap();
+
+        let other = graphql::Request::builder().query("{ me { id } }").build();
+        assert!(files.take_for(&other).is_none());
+
+        let subgraph_files = files.take_for(&operations).unwrap().unwrap();
+        assert!(files.take_for(&operations).unwrap().is_err());
+        subgraph_files
+            .into_body(serde_json::to_string(&operations).unwrap())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_streams_files_to_the_subgraph_request_using_them() {
+        let (content_type, body) = subgraph_body(&default_config()).await;
+        let boundary = multer::parse_boundary(content_type.to_str().unwrap()).unwrap();
+        let mut multipart = Multipart::new(body, boundary);
+        let mut fields = Vec::new();
+        while let Some(field) = multipart.next_field().await.unwrap() {
+            let name = field.name().unwrap().to_string();
+            fields.push((name, field.text().await.unwrap()));
+        }
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0].0, "operations");
+        assert_eq!(
+            serde_json::from_str::<graphql::Request>(&fields[0].1)
+                .unwrap()
+                .variables,
+            json!({ "file": null }).as_object().unwrap().clone()
+        );
+        assert_eq!(
+            fields[1],
+            ("map".to_string(), r#"{"0":["variables.file"]}"#.to_string())
+        );
+        assert_eq!(fields[2], ("0".to_string(), "hello".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_enforces_limits() {
+        let config = Conf {
+            max_files: 0,
+            ..default_config()
+        };
+        assert!(matches!(
+            read_operations(multipart_request(), &config).await,
+            Err((_, StatusCode::PAYLOAD_TOO_LARGE))
+        ));
+
+        let config = Conf {
+            max_total_size: 100,
+            ..default_config()
+        };
+        assert!(read_operations(multipart_request(), &config).await.is_err());
+
+        // files are checked while they are streamed
+        let config = Conf {
+            max_file_size: 3,
+            ..default_config()
+        };
+        let (_, body) = subgraph_body(&config).await;
+        let chunks: Vec<_> = body.collect().await;
+        assert!(chunks.iter().any(Result::is_err));
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
 
@@ -7,9 +8,11 @@ use http::header::HeaderName;
 use http::header::CONNECTION;
 use http::header::CONTENT_LENGTH;
 use http::header::CONTENT_TYPE;
+use http::header::COOKIE;
 use http::header::HOST;
 use http::header::PROXY_AUTHENTICATE;
 use http::header::PROXY_AUTHORIZATION;
+use http::header::SET_COOKIE;
 use http::header::TE;
 use http::header::TRAILER;
 use http::header::TRANSFER_ENCODING;
@@ -19,6 +22,7 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use serde::Serialize;
 use serde_json::Value;
 use tower::BoxError;
 use tower::Layer;
@@ -34,19 +38,103 @@ use crate::plugin::serde::deserialize_option_header_value;
 use crate::plugin::serde::deserialize_regex;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::authentication::JWT_CLAIMS_CONTEXT_KEY;
 use crate::register_plugin;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
 
 register_plugin!("apollo", "headers", Headers);
 
+/// The headers of the subgraph responses propagated to the client response, by name
+const RESPONSE_HEADERS_CONTEXT_KEY: &str = "apollo_headers::response_headers";
+/// The `Set-Cookie` headers of the subgraph responses sent to the client
+const SET_COOKIE_CONTEXT_KEY: &str = "apollo_headers::set_cookie";
+
 #[derive(Clone, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 struct HeadersLocation {
     /// Propagate/Insert/Remove headers from request
+    #[serde(default)]
     request: Vec<Operation>,
-    // Propagate/Insert/Remove headers from response
-    // response: Option<Operation>
+    /// Propagate headers from the subgraph responses to the client response
+    #[serde(default)]
+    response: Vec<ResponseOperation>,
+    /// Cookies forwarded to the subgraphs, applied after the request rules, and `Set-Cookie`
+    /// headers sent back to the client. The policy of a subgraph replaces the policy of `all`
+    #[serde(default)]
+    cookies: Option<CookiePolicy>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CookiePolicy {
+    /// The cookies of the client request forwarded to the subgraph. The other cookies are not
+    /// forwarded
+    #[serde(default)]
+    forward: Vec<CookieRule>,
+    /// Send the `Set-Cookie` headers of the subgraph responses to the client
+    #[serde(default)]
+    set_cookie: bool,
+    /// The cookies of the `Set-Cookie` headers sent to the client, if `set_cookie` is enabled.
+    /// All of them are sent if empty
+    #[serde(default)]
+    set_cookie_allow: Vec<CookieRule>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum CookieRule {
+    /// The cookie with this name
+    Named(String),
+    /// The cookies whose name starts with this prefix
+    Prefix(String),
+}
+
+impl CookieRule {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            CookieRule::Named(named) => name == named,
+            CookieRule::Prefix(prefix) => name.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// The name of a cookie, in a `Cookie` or `Set-Cookie` header
+fn cookie_name(cookie: &str) -> &str {
+    cookie
+        .split_once('=')
+        .map_or(cookie, |(name, _)| name)
+        .trim()
+}
+
+impl CookiePolicy {
+    /// Replaces the cookies of the subgraph request with the forwarded cookies of the client
+    /// request
+    fn forward(&self, request: &mut SubgraphRequest) {
+        let cookies = request
+            .supergraph_request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(str::trim)
+            .filter(|cookie| {
+                let name = cookie_name(cookie);
+                self.forward.iter().any(|rule| rule.matches(name))
+            })
+            .collect::<Vec<_>>();
+        let headers = request.subgraph_request.headers_mut();
+        headers.remove(COOKIE);
+        if cookies.is_empty() {
+            return;
+        }
+        if let Ok(value) = HeaderValue::try_from(cookies.join("; ")) {
+            headers.insert(COOKIE, value);
+        }
+    }
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -55,6 +143,74 @@ enum Operation {
     Insert(Insert),
     Remove(Remove),
     Propagate(Propagate),
+    Conditional(Conditional),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Apply header rules only to the requests matching conditions
+struct Conditional {
+    /// The conditions, which must all match
+    when: Vec<Condition>,
+    /// The rules applied if the conditions match
+    #[schemars(with = "Vec<ConditionalOperation>")]
+    then: Vec<Operation>,
+}
+
+/// The header rules of a conditional rule, which can't be nested
+#[derive(JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[allow(dead_code)]
+enum ConditionalOperation {
+    Insert(Insert),
+    Remove(Remove),
+    Propagate(Propagate),
+}
+
+schemar_fn!(
+    condition_subgraph_matches,
+    String,
+    "The name of the subgraph matches a regex"
+);
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Condition of a conditional rule
+enum Condition {
+    /// A claim of the JWT of the request equals a value
+    ClaimEquals {
+        /// The name of the claim
+        claim: String,
+        /// The value of the claim, compared to its JSON representation if it's not a string
+        value: String,
+    },
+    /// The context has an entry
+    ContextExists(String),
+    #[schemars(schema_with = "condition_subgraph_matches")]
+    #[serde(deserialize_with = "deserialize_regex")]
+    /// The name of the subgraph matches a regex
+    SubgraphMatches(Regex),
+}
+
+impl Condition {
+    fn matches(&self, subgraph_name: &str, request: &SubgraphRequest) -> bool {
+        match self {
+            Condition::ClaimEquals { claim, value } => request
+                .context
+                .get::<_, Value>(JWT_CLAIMS_CONTEXT_KEY)
+                .ok()
+                .flatten()
+                .and_then(|claims| {
+                    claims.get(claim).map(|claim| match claim {
+                        Value::String(claim) => claim == value,
+                        claim => &claim.to_string() == value,
+                    })
+                })
+                .unwrap_or_default(),
+            Condition::ContextExists(key) => request.context.contains_key(key),
+            Condition::SubgraphMatches(matching) => matching.is_match(subgraph_name),
+        }
+    }
 }
 
 schemar_fn!(remove_named, String, "Remove a header given a header name");
@@ -90,6 +246,8 @@ enum Insert {
     FromContext(InsertFromContext),
     /// Insert header with a value coming from body
     FromBody(InsertFromBody),
+    /// Insert header with a value built from the headers of the client request and the context
+    Template(InsertTemplate),
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -139,6 +297,88 @@ struct InsertFromBody {
     default: Option<HeaderValue>,
 }
 
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert header with a value built from a template
+struct InsertTemplate {
+    /// The target header name
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+
+    /// The value of the header, where `{header.<name>}` is replaced by a header of the client
+    /// request and `{context.<key>}` by an entry of the context. The header is not inserted if
+    /// one of them is missing
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_template")]
+    template: Vec<TemplatePart>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Header(HeaderName),
+    Context(String),
+}
+
+lazy_static! {
+    static ref TEMPLATE_PLACEHOLDER: Regex =
+        Regex::new(r"\{(header|context)\.([^}]+)\}").expect("valid regex");
+}
+
+fn deserialize_template<'de, D>(deserializer: D) -> Result<Vec<TemplatePart>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let template = String::deserialize(deserializer)?;
+    let mut parts = Vec::new();
+    let mut end = 0;
+    for captures in TEMPLATE_PLACEHOLDER.captures_iter(&template) {
+        let placeholder = captures.get(0).expect("the whole match is captured");
+        if placeholder.start() > end {
+            parts.push(TemplatePart::Literal(
+                template[end..placeholder.start()].to_string(),
+            ));
+        }
+        end = placeholder.end();
+        parts.push(match &captures[1] {
+            "header" => TemplatePart::Header(
+                HeaderName::try_from(&captures[2]).map_err(serde::de::Error::custom)?,
+            ),
+            _ => TemplatePart::Context(captures[2].to_string()),
+        });
+    }
+    if end < template.len() {
+        parts.push(TemplatePart::Literal(template[end..].to_string()));
+    }
+    Ok(parts)
+}
+
+impl InsertTemplate {
+    /// The value of the header, if the headers and context entries of the template are present
+    fn render(&self, request: &SubgraphRequest) -> Option<String> {
+        let mut value = String::new();
+        for part in &self.template {
+            match part {
+                TemplatePart::Literal(literal) => value.push_str(literal),
+                TemplatePart::Header(name) => value.push_str(
+                    request
+                        .supergraph_request
+                        .headers()
+                        .get(name)?
+                        .to_str()
+                        .ok()?,
+                ),
+                TemplatePart::Context(key) => match request.context.get::<_, Value>(key).ok()?? {
+                    Value::String(entry) => value.push_str(&entry),
+                    entry => value.push_str(&entry.to_string()),
+                },
+            }
+        }
+        Some(value)
+    }
+}
+
 schemar_fn!(
     propagate_matching,
     String,
@@ -176,6 +416,62 @@ enum Propagate {
     },
 }
 
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ResponseOperation {
+    Propagate(ResponsePropagate),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[serde(untagged)]
+/// Propagate subgraph response header
+enum ResponsePropagate {
+    /// Propagate header given a header name
+    Named {
+        /// The source header name
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        named: HeaderName,
+
+        /// An optional target header name
+        #[schemars(with = "Option<String>", default)]
+        #[serde(deserialize_with = "deserialize_option_header_name", default)]
+        rename: Option<HeaderName>,
+
+        /// How the values of the subgraph responses are merged, in the order they are received:
+        /// `first` keeps the first value, `last` the last one and `join` joins them with commas
+        #[serde(default)]
+        merge: HeaderMerge,
+    },
+    /// Propagate header given a regex to match header name
+    Matching {
+        /// The regex on header name
+        #[schemars(schema_with = "propagate_matching")]
+        #[serde(deserialize_with = "deserialize_regex")]
+        matching: Regex,
+
+        /// How the values of the subgraph responses are merged, in the order they are received:
+        /// `first` keeps the first value, `last` the last one and `join` joins them with commas
+        #[serde(default)]
+        merge: HeaderMerge,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum HeaderMerge {
+    First,
+    Last,
+    Join,
+}
+
+impl Default for HeaderMerge {
+    fn default() -> Self {
+        HeaderMerge::First
+    }
+}
+
 /// Configuration for header propagation
 #[derive(Clone, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
@@ -189,7 +485,18 @@ struct Config {
 }
 
 struct Headers {
-    config: Config,
+    /// Operations of the subgraphs without specific rules
+    all_operations: Arc<Vec<Operation>>,
+    /// Operations of the subgraphs with specific rules, after the operations of `all`
+    subgraph_operations: HashMap<String, Arc<Vec<Operation>>>,
+    /// Response operations of the subgraphs without specific rules
+    all_response_operations: Arc<Vec<ResponseOperation>>,
+    /// Response operations of the subgraphs with specific rules, after the operations of `all`
+    subgraph_response_operations: HashMap<String, Arc<Vec<ResponseOperation>>>,
+    /// Cookie policy of the subgraphs without specific policy
+    all_cookies: Option<Arc<CookiePolicy>>,
+    /// Cookie policies of the subgraphs with specific rules, replacing the policy of `all`
+    subgraph_cookies: HashMap<String, Option<Arc<CookiePolicy>>>,
 }
 
 #[async_trait::async_trait]
@@ -197,37 +504,243 @@ impl Plugin for Headers {
     type Config = Config;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        Ok(Headers {
-            config: init.config,
-        })
-    }
-    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
-        let mut operations: Vec<Operation> = self
+        // subgraph services are created for each fetch, the operations are built once
+        let (all_operations, all_response_operations, all_cookies) = init
             .config
             .all
-            .as_ref()
-            .map(|a| a.request.clone())
+            .map(|all| (all.request, all.response, all.cookies.map(Arc::new)))
             .unwrap_or_default();
-        if let Some(mut subgraph_operations) =
-            self.config.subgraphs.get(name).map(|s| s.request.clone())
+        let mut subgraph_operations = HashMap::new();
+        let mut subgraph_response_operations = HashMap::new();
+        let mut subgraph_cookies = HashMap::new();
+        for (name, subgraph) in init.config.subgraphs {
+            subgraph_cookies.insert(
+                name.clone(),
+                subgraph
+                    .cookies
+                    .map(Arc::new)
+                    .or_else(|| all_cookies.clone()),
+            );
+            let operations = all_operations
+                .iter()
+                .cloned()
+                .chain(subgraph.request)
+                .collect();
+            subgraph_operations.insert(name.clone(), Arc::new(operations));
+            let response_operations = all_response_operations
+                .iter()
+                .cloned()
+                .chain(subgraph.response)
+                .collect();
+            subgraph_response_operations.insert(name, Arc::new(response_operations));
+        }
+        Ok(Headers {
+            all_operations: Arc::new(all_operations),
+            subgraph_operations,
+            all_response_operations: Arc::new(all_response_operations),
+            subgraph_response_operations,
+            all_cookies,
+            subgraph_cookies,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let sets_cookies = |policy: &Option<Arc<CookiePolicy>>| {
+            policy.as_ref().map_or(false, |policy| policy.set_cookie)
+        };
+        if self.all_response_operations.is_empty()
+            && self
+                .subgraph_response_operations
+                .values()
+                .all(|operations| operations.is_empty())
+            && !sets_cookies(&self.all_cookies)
+            && !self.subgraph_cookies.values().any(sets_cookies)
         {
-            operations.append(&mut subgraph_operations);
+            return service;
         }
+        // the supergraph response is returned once the subgraph responses of the primary
+        // response were received
+        service
+            .map_response(|mut response: supergraph::Response| {
+                let headers = response
+                    .context
+                    .get::<_, HashMap<String, String>>(RESPONSE_HEADERS_CONTEXT_KEY)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                for (name, value) in headers {
+                    if let (Ok(name), Ok(value)) =
+                        (HeaderName::try_from(name), HeaderValue::try_from(value))
+                    {
+                        response.response.headers_mut().insert(name, value);
+                    }
+                }
+                let cookies = response
+                    .context
+                    .get::<_, Vec<String>>(SET_COOKIE_CONTEXT_KEY)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                for cookie in cookies {
+                    if let Ok(value) = HeaderValue::try_from(cookie) {
+                        response.response.headers_mut().append(SET_COOKIE, value);
+                    }
+                }
+                response
+            })
+            .boxed()
+    }
 
-        ServiceBuilder::new()
-            .layer(HeadersLayer::new(operations))
-            .service(service)
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let operations = self
+            .subgraph_operations
+            .get(name)
+            .unwrap_or(&self.all_operations)
+            .clone();
+
+        let response_operations = self
+            .subgraph_response_operations
+            .get(name)
+            .unwrap_or(&self.all_response_operations)
+            .clone();
+
+        let cookies = self
+            .subgraph_cookies
+            .get(name)
+            .unwrap_or(&self.all_cookies)
+            .clone();
+
+        let set_cookie_policy = cookies.clone().filter(|cookies| cookies.set_cookie);
+        let service = ServiceBuilder::new()
+            .layer(HeadersLayer::new(name, operations))
+            .option_layer(cookies.map(|cookies| {
+                ServiceBuilder::new().map_request(move |mut request: SubgraphRequest| {
+                    cookies.forward(&mut request);
+                    request
+                })
+            }))
+            .service(service);
+        if response_operations.is_empty() && set_cookie_policy.is_none() {
+            return service.boxed();
+        }
+        service
+            .map_response(move |response: SubgraphResponse| {
+                collect_response_headers(&response_operations, &response);
+                if let Some(policy) = &set_cookie_policy {
+                    collect_set_cookies(policy, &response);
+                }
+                response
+            })
             .boxed()
     }
 }
 
+/// Appends the allowed `Set-Cookie` headers of the subgraph response to the ones sent to the
+/// client. A subgraph fetched several times by an operation sends the same header once
+fn collect_set_cookies(policy: &CookiePolicy, response: &SubgraphResponse) {
+    let cookies = response
+        .response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter(|cookie| {
+            policy.set_cookie_allow.is_empty()
+                || policy
+                    .set_cookie_allow
+                    .iter()
+                    .any(|rule| rule.matches(cookie_name(cookie)))
+        })
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if cookies.is_empty() {
+        return;
+    }
+    let _ = response
+        .context
+        .upsert(SET_COOKIE_CONTEXT_KEY, |mut collected: Vec<String>| {
+            for cookie in &cookies {
+                if !collected.contains(cookie) {
+                    collected.push(cookie.clone());
+                }
+            }
+            collected
+        });
+}
+
+/// Merges the headers of the subgraph response propagated to the client response, by name, in
+/// the context
+fn collect_response_headers(operations: &[ResponseOperation], response: &SubgraphResponse) {
+    let headers = response.response.headers();
+    let values = |name: &HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    let mut collected = Vec::new();
+    for operation in operations {
+        match operation {
+            // the `Set-Cookie` headers are not merged, but sent by the cookie policy
+            ResponseOperation::Propagate(ResponsePropagate::Named { named, .. })
+                if named == SET_COOKIE => {}
+            ResponseOperation::Propagate(ResponsePropagate::Named {
+                named,
+                rename,
+                merge,
+            }) => collected.push((rename.as_ref().unwrap_or(named), *merge, values(named))),
+            ResponseOperation::Propagate(ResponsePropagate::Matching { matching, merge }) => {
+                collected.extend(
+                    headers
+                        .keys()
+                        .filter(|name| {
+                            !RESERVED_HEADERS.contains(name)
+                                && *name != SET_COOKIE
+                                && matching.is_match(name.as_str())
+                        })
+                        .map(|name| (name, *merge, values(name))),
+                )
+            }
+        }
+    }
+    collected.retain(|(_, _, values)| !values.is_empty());
+    if collected.is_empty() {
+        return;
+    }
+
+    let _ = response.context.upsert(
+        RESPONSE_HEADERS_CONTEXT_KEY,
+        |mut merged: HashMap<String, String>| {
+            for (name, merge, values) in &collected {
+                let value = match (merge, merged.get(name.as_str())) {
+                    (HeaderMerge::First, Some(_)) => continue,
+                    (HeaderMerge::First, None) => values[0].clone(),
+                    (HeaderMerge::Last, _) => values[values.len() - 1].clone(),
+                    (HeaderMerge::Join, Some(existing)) => {
+                        format!("{existing}, {}", values.join(", "))
+                    }
+                    (HeaderMerge::Join, None) => values.join(", "),
+                };
+                merged.insert(name.to_string(), value);
+            }
+            merged
+        },
+    );
+}
+
 struct HeadersLayer {
-    operations: Vec<Operation>,
+    subgraph_name: String,
+    operations: Arc<Vec<Operation>>,
 }
 
 impl HeadersLayer {
-    fn new(operations: Vec<Operation>) -> Self {
-        Self { operations }
+    fn new(subgraph_name: &str, operations: Arc<Vec<Operation>>) -> Self {
+        Self {
+            subgraph_name: subgraph_name.to_string(),
+            operations,
+        }
     }
 }
 
@@ -237,13 +750,15 @@ impl<S> Layer<S> for HeadersLayer {
     fn layer(&self, inner: S) -> Self::Service {
         HeadersService {
             inner,
+            subgraph_name: self.subgraph_name.clone(),
             operations: self.operations.clone(),
         }
     }
 }
 struct HeadersService<S> {
     inner: S,
-    operations: Vec<Operation>,
+    subgraph_name: String,
+    operations: Arc<Vec<Operation>>,
 }
 
 lazy_static! {
@@ -268,118 +783,154 @@ lazy_static! {
     .into();
 }
 
-impl<S> Service<SubgraphRequest> for HeadersService<S>
-where
-    S: Service<SubgraphRequest>,
-{
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = S::Future;
-
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.inner.poll_ready(cx)
-    }
-
-    fn call(&mut self, mut req: SubgraphRequest) -> Self::Future {
-        for operation in &self.operations {
-            match operation {
-                Operation::Insert(insert_config) => match insert_config {
-                    Insert::Static(static_insert) => {
-                        req.subgraph_request
-                            .headers_mut()
-                            .insert(&static_insert.name, static_insert.value.clone());
+impl Operation {
+    fn apply(&self, subgraph_name: &str, request: &mut SubgraphRequest) {
+        match self {
+            Operation::Insert(insert_config) => match insert_config {
+                Insert::Static(static_insert) => {
+                    request
+                        .subgraph_request
+                        .headers_mut()
+                        .insert(&static_insert.name, static_insert.value.clone());
+                }
+                Insert::FromContext(insert_from_context) => {
+                    if let Some(val) = request
+                        .context
+                        .get::<_, String>(&insert_from_context.from_context)
+                        .ok()
+                        .flatten()
+                    {
+                        match HeaderValue::from_str(&val) {
+                            Ok(header_value) => {
+                                request
+                                    .subgraph_request
+                                    .headers_mut()
+                                    .insert(&insert_from_context.name, header_value);
+                            }
+                            Err(err) => {
+                                tracing::error!("cannot convert from the context into a header value for header name '{}': {:?}", insert_from_context.name, err);
+                            }
+                        }
                     }
-                    Insert::FromContext(insert_from_context) => {
-                        if let Some(val) = req
-                            .context
-                            .get::<_, String>(&insert_from_context.from_context)
-                            .ok()
-                            .flatten()
-                        {
-                            match HeaderValue::from_str(&val) {
-                                Ok(header_value) => {
-                                    req.subgraph_request
-                                        .headers_mut()
-                                        .insert(&insert_from_context.name, header_value);
-                                }
-                                Err(err) => {
-                                    tracing::error!("cannot convert from the context into a header value for header name '{}': {:?}", insert_from_context.name, err);
-                                }
+                }
+                Insert::FromBody(from_body) => {
+                    let output = from_body
+                        .path
+                        .execute(request.supergraph_request.body())
+                        .ok()
+                        .flatten();
+                    if let Some(val) = output {
+                        let header_value = if let Value::String(val_str) = val {
+                            val_str
+                        } else {
+                            val.to_string()
+                        };
+                        match HeaderValue::from_str(&header_value) {
+                            Ok(header_value) => {
+                                request
+                                    .subgraph_request
+                                    .headers_mut()
+                                    .insert(&from_body.name, header_value);
+                            }
+                            Err(err) => {
+                                tracing::error!("cannot convert from the body into a header value for header name '{}': {:?}", from_body.name, err);
                             }
                         }
+                    } else if let Some(default_val) = &from_body.default {
+                        request
+                            .subgraph_request
+                            .headers_mut()
+                            .insert(&from_body.name, default_val.clone());
                     }
-                    Insert::FromBody(from_body) => {
-                        let output = from_body
-                            .path
-                            .execute(req.supergraph_request.body())
-                            .ok()
-                            .flatten();
-                        if let Some(val) = output {
-                            let header_value = if let Value::String(val_str) = val {
-                                val_str
-                            } else {
-                                val.to_string()
-                            };
-                            match HeaderValue::from_str(&header_value) {
-                                Ok(header_value) => {
-                                    req.subgraph_request
-                                        .headers_mut()
-                                        .insert(&from_body.name, header_value);
-                                }
-                                Err(err) => {
-                                    tracing::error!("cannot convert from the body into a header value for header name '{}': {:?}", from_body.name, err);
-                                }
+                }
+                Insert::Template(template) => {
+                    if let Some(value) = template.render(request) {
+                        match HeaderValue::from_str(&value) {
+                            Ok(header_value) => {
+                                request
+                                    .subgraph_request
+                                    .headers_mut()
+                                    .insert(&template.name, header_value);
+                            }
+                            Err(err) => {
+                                tracing::error!("cannot convert the template into a header value for header name '{}': {:?}", template.name, err);
                             }
-                        } else if let Some(default_val) = &from_body.default {
-                            req.subgraph_request
-                                .headers_mut()
-                                .insert(&from_body.name, default_val.clone());
                         }
                     }
-                },
-                Operation::Remove(Remove::Named(name)) => {
-                    req.subgraph_request.headers_mut().remove(name);
                 }
-                Operation::Remove(Remove::Matching(matching)) => {
-                    let headers = req.subgraph_request.headers_mut();
-                    let new_headers = headers
-                        .drain()
-                        .filter_map(|(name, value)| {
-                            name.and_then(|name| {
-                                (RESERVED_HEADERS.contains(&name)
-                                    || !matching.is_match(name.as_str()))
+            },
+            Operation::Remove(Remove::Named(name)) => {
+                request.subgraph_request.headers_mut().remove(name);
+            }
+            Operation::Remove(Remove::Matching(matching)) => {
+                let headers = request.subgraph_request.headers_mut();
+                let new_headers = headers
+                    .drain()
+                    .filter_map(|(name, value)| {
+                        name.and_then(|name| {
+                            (RESERVED_HEADERS.contains(&name) || !matching.is_match(name.as_str()))
                                 .then_some((name, value))
-                            })
                         })
-                        .collect();
+                    })
+                    .collect();
 
-                    let _ = std::mem::replace(headers, new_headers);
+                let _ = std::mem::replace(headers, new_headers);
+            }
+            Operation::Propagate(Propagate::Named {
+                named,
+                rename,
+                default,
+            }) => {
+                let headers = request.subgraph_request.headers_mut();
+                let value = request.supergraph_request.headers().get(named);
+                if let Some(value) = value.or(default.as_ref()) {
+                    headers.insert(rename.as_ref().unwrap_or(named), value.clone());
                 }
-                Operation::Propagate(Propagate::Named {
-                    named,
-                    rename,
-                    default,
-                }) => {
-                    let headers = req.subgraph_request.headers_mut();
-                    let value = req.supergraph_request.headers().get(named);
-                    if let Some(value) = value.or(default.as_ref()) {
-                        headers.insert(rename.as_ref().unwrap_or(named), value.clone());
+            }
+            Operation::Propagate(Propagate::Matching { matching }) => {
+                let headers = request.subgraph_request.headers_mut();
+                request
+                    .supergraph_request
+                    .headers()
+                    .iter()
+                    .filter(|(name, _)| {
+                        !RESERVED_HEADERS.contains(name) && matching.is_match(name.as_str())
+                    })
+                    .for_each(|(name, value)| {
+                        headers.insert(name, value.clone());
+                    });
+            }
+            Operation::Conditional(conditional) => {
+                if conditional
+                    .when
+                    .iter()
+                    .all(|condition| condition.matches(subgraph_name, request))
+                {
+                    for operation in &conditional.then {
+                        operation.apply(subgraph_name, request);
                     }
                 }
-                Operation::Propagate(Propagate::Matching { matching }) => {
-                    let headers = req.subgraph_request.headers_mut();
-                    req.supergraph_request
-                        .headers()
-                        .iter()
-                        .filter(|(name, _)| {
-                            !RESERVED_HEADERS.contains(name) && matching.is_match(name.as_str())
-                        })
-                        .for_each(|(name, value)| {
-                            headers.insert(name, value.clone());
-                        });
-                }
             }
         }
+    }
+}
+
+impl<S> Service<SubgraphRequest> for HeadersService<S>
+where
+    S: Service<SubgraphRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: SubgraphRequest) -> Self::Future {
+        for operation in self.operations.iter() {
+            operation.apply(&self.subgraph_name, &mut req);
+        }
         self.inner.call(req)
     }
 }
@@ -395,6 +946,7 @@ mod test {
     use super::*;
     use crate::graphql::Request;
     use crate::plugin::test::MockSubgraphService;
+    use crate::plugin::test::MockSupergraphService;
     use crate::plugins::headers::Config;
     use crate::plugins::headers::HeadersLayer;
     use crate::query_planner::fetch::OperationKind;
@@ -431,6 +983,44 @@ mod test {
         .unwrap();
     }
 
+    #[test]
+    fn test_insert_template_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+            - insert:
+                name: "authorization"
+                template: "Bearer {header.x-token} for {context.user}!"
+        "#,
+        )
+        .unwrap();
+        match &config.all.unwrap().request[0] {
+            Operation::Insert(Insert::Template(template)) => assert_eq!(
+                template.template,
+                vec![
+                    TemplatePart::Literal("Bearer ".to_string()),
+                    TemplatePart::Header("x-token".try_into().unwrap()),
+                    TemplatePart::Literal(" for ".to_string()),
+                    TemplatePart::Context("user".to_string()),
+                    TemplatePart::Literal("!".to_string()),
+                ]
+            ),
+            _ => panic!("expected a template"),
+        }
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+            - insert:
+                name: "test"
+                template: "{header.invalid name}"
+        "#,
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_remove_config() {
         serde_yaml::from_str::<Config>(
@@ -510,6 +1100,114 @@ mod test {
         .unwrap();
     }
 
+    #[test]
+    fn test_response_propagate_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "deprecation"
+                - propagate:
+                    named: "x-ratelimit-remaining"
+                    rename: "ratelimit-remaining"
+                    merge: "last"
+                - propagate:
+                    matching: "x-warning-.*"
+                    merge: "join"
+        subgraphs:
+          products:
+            response:
+                - propagate:
+                    named: "sunset"
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "deprecation"
+                    merge: "sum"
+        "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_conditional_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+                - conditional:
+                    when:
+                        - claim_equals:
+                            claim: "tenant"
+                            value: "staff"
+                        - context_exists: "my_key"
+                        - subgraph_matches: "^(accounts|products)$"
+                    then:
+                        - insert:
+                            name: "x-internal"
+                            value: "true"
+                        - propagate:
+                            named: "x-staff-id"
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+                - conditional:
+                    when:
+                        - claim_exists: "tenant"
+                    then:
+                        - insert:
+                            name: "x-internal"
+                            value: "true"
+        "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_cookies_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            cookies:
+                forward:
+                    - named: "session"
+                    - prefix: "sso_"
+        subgraphs:
+          accounts:
+            cookies:
+                forward:
+                    - named: "session"
+                set_cookie: true
+                set_cookie_allow:
+                    - named: "session"
+                    - prefix: "sso_"
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            cookies:
+                forward:
+                    - matching: "session"
+        "#,
+        )
+        .is_err());
+    }
+
     #[tokio::test]
     async fn test_insert_static() -> Result<(), BoxError> {
         let mut mock = MockSubgraphService::new();
@@ -525,12 +1223,14 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service =
-            HeadersLayer::new(vec![Operation::Insert(Insert::Static(InsertStatic {
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Insert(Insert::Static(InsertStatic {
                 name: "c".try_into()?,
                 value: "d".try_into()?,
-            }))])
-            .layer(mock);
+            }))]),
+        )
+        .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
         Ok(())
@@ -551,12 +1251,54 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Insert(Insert::FromContext(
-            InsertFromContext {
-                name: "header_from_context".try_into()?,
-                from_context: "my_key".to_string(),
-            },
-        ))])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Insert(Insert::FromContext(
+                InsertFromContext {
+                    name: "header_from_context".try_into()?,
+                    from_context: "my_key".to_string(),
+                },
+            ))]),
+        )
+        .layer(mock);
+
+        service.ready().await?.call(example_request()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_template() -> Result<(), BoxError> {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .withf(|request| {
+                request.assert_headers(vec![
+                    ("aa", "vaa"),
+                    ("ab", "vab"),
+                    ("ac", "vac"),
+                    ("templated", "vda-my_value_from_context"),
+                ])
+            })
+            .returning(example_response);
+
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![
+                Operation::Insert(Insert::Template(serde_json::from_value(
+                    serde_json::json!({
+                        "name": "templated",
+                        "template": "{header.da}-{context.my_key}"
+                    }),
+                )?)),
+                // not inserted, the client request has no `ea` header
+                Operation::Insert(Insert::Template(serde_json::from_value(
+                    serde_json::json!({
+                        "name": "missing",
+                        "template": "{header.ea}"
+                    }),
+                )?)),
+            ]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
@@ -578,13 +1320,15 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service =
-            HeadersLayer::new(vec![Operation::Insert(Insert::FromBody(InsertFromBody {
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Insert(Insert::FromBody(InsertFromBody {
                 name: "header_from_request".try_into()?,
                 path: JSONQuery::parse(".operationName")?,
                 default: None,
-            }))])
-            .layer(mock);
+            }))]),
+        )
+        .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
         Ok(())
@@ -598,8 +1342,11 @@ mod test {
             .withf(|request| request.assert_headers(vec![("ac", "vac"), ("ab", "vab")]))
             .returning(example_response);
 
-        let mut service =
-            HeadersLayer::new(vec![Operation::Remove(Remove::Named("aa".try_into()?))]).layer(mock);
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Remove(Remove::Named("aa".try_into()?))]),
+        )
+        .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
         Ok(())
@@ -613,9 +1360,12 @@ mod test {
             .withf(|request| request.assert_headers(vec![("ac", "vac")]))
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Remove(Remove::Matching(
-            Regex::from_str("a[ab]")?,
-        ))])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Remove(Remove::Matching(Regex::from_str(
+                "a[ab]",
+            )?))]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
@@ -638,9 +1388,12 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Propagate(Propagate::Matching {
-            matching: Regex::from_str("d[ab]")?,
-        })])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Propagate(Propagate::Matching {
+                matching: Regex::from_str("d[ab]")?,
+            })]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
@@ -662,11 +1415,14 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Propagate(Propagate::Named {
-            named: "da".try_into()?,
-            rename: None,
-            default: None,
-        })])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Propagate(Propagate::Named {
+                named: "da".try_into()?,
+                rename: None,
+                default: None,
+            })]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
@@ -688,11 +1444,14 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Propagate(Propagate::Named {
-            named: "da".try_into()?,
-            rename: Some("ea".try_into()?),
-            default: None,
-        })])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Propagate(Propagate::Named {
+                named: "da".try_into()?,
+                rename: Some("ea".try_into()?),
+                default: None,
+            })]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
@@ -714,17 +1473,271 @@ mod test {
             })
             .returning(example_response);
 
-        let mut service = HeadersLayer::new(vec![Operation::Propagate(Propagate::Named {
-            named: "ea".try_into()?,
-            rename: None,
-            default: Some("defaulted".try_into()?),
-        })])
+        let mut service = HeadersLayer::new(
+            "test",
+            Arc::new(vec![Operation::Propagate(Propagate::Named {
+                named: "ea".try_into()?,
+                rename: None,
+                default: Some("defaulted".try_into()?),
+            })]),
+        )
         .layer(mock);
 
         service.ready().await?.call(example_request()).await?;
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_conditional() -> Result<(), BoxError> {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .withf(|request| {
+                request.assert_headers(vec![
+                    ("aa", "vaa"),
+                    ("ab", "vab"),
+                    ("ac", "vac"),
+                    ("x-internal", "true"),
+                ])
+            })
+            .returning(example_response);
+
+        let operations: Vec<Operation> = serde_json::from_value(serde_json::json!([
+            {
+                "conditional": {
+                    "when": [
+                        { "claim_equals": { "claim": "tenant", "value": "staff" } },
+                        { "context_exists": "my_key" },
+                        { "subgraph_matches": "^test$" }
+                    ],
+                    "then": [{ "insert": { "name": "x-internal", "value": "true" } }]
+                }
+            },
+            // not applied, the claim has another value
+            {
+                "conditional": {
+                    "when": [{ "claim_equals": { "claim": "admin", "value": "true" } }],
+                    "then": [{ "insert": { "name": "x-admin", "value": "true" } }]
+                }
+            },
+            // not applied, the subgraph name doesn't match
+            {
+                "conditional": {
+                    "when": [{ "subgraph_matches": "^accounts$" }],
+                    "then": [{ "propagate": { "named": "da" } }]
+                }
+            }
+        ]))?;
+        let mut service = HeadersLayer::new("test", Arc::new(operations)).layer(mock);
+
+        let request = example_request();
+        request.context.insert(
+            JWT_CLAIMS_CONTEXT_KEY,
+            serde_json::json!({ "tenant": "staff", "admin": false }),
+        )?;
+        service.ready().await?.call(request).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_response_propagate() -> Result<(), BoxError> {
+        let response_operations = |operations: &str| -> Arc<Vec<ResponseOperation>> {
+            Arc::new(serde_yaml::from_str(operations).unwrap())
+        };
+        let headers = Headers {
+            all_operations: Default::default(),
+            subgraph_operations: Default::default(),
+            all_response_operations: response_operations(
+                r#"
+                - propagate:
+                    named: "deprecation"
+                - propagate:
+                    named: "x-ratelimit-remaining"
+                    rename: "ratelimit-remaining"
+                    merge: "last"
+                - propagate:
+                    matching: "x-warning-.*"
+                    merge: "join"
+                "#,
+            ),
+            subgraph_response_operations: Default::default(),
+            all_cookies: None,
+            subgraph_cookies: Default::default(),
+        };
+
+        let context = Context::new();
+        for (name, subgraph_headers) in [
+            (
+                "accounts",
+                vec![
+                    ("deprecation", "accounts"),
+                    ("x-ratelimit-remaining", "10"),
+                    ("x-warning-slow", "accounts"),
+                ],
+            ),
+            (
+                "products",
+                vec![
+                    ("deprecation", "products"),
+                    ("x-ratelimit-remaining", "5"),
+                    ("x-warning-slow", "products"),
+                    ("x-warning-cache", "stale"),
+                ],
+            ),
+        ] {
+            let mut mock = MockSubgraphService::new();
+            mock.expect_call().times(1).returning(move |request| {
+                let mut response = http::Response::builder();
+                for (name, value) in &subgraph_headers {
+                    response = response.header(*name, *value);
+                }
+                Ok(SubgraphResponse::new_from_response(
+                    response.body(Default::default()).unwrap(),
+                    request.context,
+                ))
+            });
+            headers
+                .subgraph_service(name, mock.boxed())
+                .oneshot(
+                    SubgraphRequest::fake_builder()
+                        .context(context.clone())
+                        .build(),
+                )
+                .await?;
+        }
+
+        let mut mock = MockSupergraphService::new();
+        mock.expect_call().times(1).returning(move |request| {
+            Ok(supergraph::Response::fake_builder()
+                .context(request.context)
+                .build()
+                .unwrap())
+        });
+        let response = headers
+            .supergraph_service(mock.boxed())
+            .oneshot(
+                supergraph::Request::fake_builder()
+                    .context(context)
+                    .build()
+                    .unwrap(),
+            )
+            .await?;
+        let response_headers = response.response.headers();
+        assert_eq!(response_headers["deprecation"], "accounts");
+        assert_eq!(response_headers["ratelimit-remaining"], "5");
+        assert!(!response_headers.contains_key("x-ratelimit-remaining"));
+        assert_eq!(response_headers["x-warning-slow"], "accounts, products");
+        assert_eq!(response_headers["x-warning-cache"], "stale");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cookies() -> Result<(), BoxError> {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+                - propagate:
+                    named: "cookie"
+            cookies:
+                forward:
+                    - named: "session"
+                    - prefix: "sso_"
+                set_cookie: true
+        subgraphs:
+          products:
+            cookies:
+                forward: []
+          inventory:
+            cookies:
+                forward: []
+                set_cookie: true
+                set_cookie_allow:
+                    - named: "cart"
+        "#,
+        )?;
+        let headers = Headers::new(PluginInit::new(config, Default::default())).await?;
+
+        let context = Context::new();
+        for (name, cookie, set_cookies) in [
+            (
+                "accounts",
+                Some("session=1; sso_id=3"),
+                vec!["session=2", "sso_id=4"],
+            ),
+            ("products", None, vec!["cart=1"]),
+            ("inventory", None, vec!["cart=2; Path=/", "tracking=1"]),
+            (
+                "reviews",
+                Some("session=1; sso_id=3"),
+                vec!["session=2", "theme=dark"],
+            ),
+        ] {
+            let mut mock = MockSubgraphService::new();
+            mock.expect_call()
+                .times(1)
+                .withf(move |request| {
+                    request
+                        .subgraph_request
+                        .headers()
+                        .get(COOKIE)
+                        .map(|value| value.to_str().unwrap())
+                        == cookie
+                })
+                .returning(move |request| {
+                    let mut response = http::Response::builder();
+                    for set_cookie in &set_cookies {
+                        response = response.header(SET_COOKIE, *set_cookie);
+                    }
+                    Ok(SubgraphResponse::new_from_response(
+                        response.body(Default::default()).unwrap(),
+                        request.context,
+                    ))
+                });
+            let supergraph_request = http::Request::builder()
+                .header(COOKIE, "session=1; other=2")
+                .header(COOKIE, "sso_id=3")
+                .body(Request::default())
+                .unwrap();
+            headers
+                .subgraph_service(name, mock.boxed())
+                .oneshot(
+                    SubgraphRequest::fake_builder()
+                        .supergraph_request(Arc::new(supergraph_request))
+                        .context(context.clone())
+                        .build(),
+                )
+                .await?;
+        }
+
+        let mut mock = MockSupergraphService::new();
+        mock.expect_call().times(1).returning(move |request| {
+            Ok(supergraph::Response::fake_builder()
+                .context(request.context)
+                .build()
+                .unwrap())
+        });
+        let response = headers
+            .supergraph_service(mock.boxed())
+            .oneshot(
+                supergraph::Request::fake_builder()
+                    .context(context)
+                    .build()
+                    .unwrap(),
+            )
+            .await?;
+        assert_eq!(
+            response
+                .response
+                .headers()
+                .get_all(SET_COOKIE)
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["session=2", "sso_id=4", "cart=2; Path=/", "theme=dark"]
+        );
+        Ok(())
+    }
+
     fn example_response(_: SubgraphRequest) -> Result<SubgraphResponse, BoxError> {
         Ok(SubgraphResponse::new_from_response(
             http::Response::default(),
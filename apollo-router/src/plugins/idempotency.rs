@@ -0,0 +1,599 @@
+//! Idempotency keys for mutations
+//!
+//! A client retrying a mutation after a network failure can't know whether the first attempt was
+//! executed. When it sends an `Idempotency-Key` header, the response of the completed mutation is
+//! kept for a time window, and replayed to the retries sending the same key for the same
+//! operation, instead of executing the mutation again.
+//!
+//! Keys are scoped by the identity of the caller, the subject of its JWT token or its
+//! `Authorization` header, so that a caller can't replay the response of another caller by reusing
+//! its key. The `Set-Cookie` headers, and the configured per-user headers, are not kept.
+
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use apollo_parser::ast;
+use futures::stream;
+use futures::StreamExt;
+use http::header::HeaderName;
+use http::header::AUTHORIZATION;
+use http::header::SET_COOKIE;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use lru::LruCache;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::clock::Clock;
+use crate::configuration::default_parser_recursion_limit;
+use crate::error::Error;
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::authentication::JWT_CLAIMS_CONTEXT_KEY;
+use crate::query_planner::normalization;
+use crate::register_plugin;
+use crate::services::supergraph;
+use crate::services::SupergraphResponse;
+
+const DEFAULT_HEADER_NAME: &str = "idempotency-key";
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_MAX_KEYS: usize = 10_000;
+/// Header set on the replayed responses
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Idempotency keys for mutations
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Name of the header carrying the idempotency key
+    /// default: `idempotency-key`
+    #[serde(default)]
+    header_name: Option<String>,
+
+    /// How long the responses of completed mutations are replayed
+    /// default: 24h
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    ttl: Option<Duration>,
+
+    /// Maximum number of idempotency keys kept, the least recently used are forgotten first
+    /// default: 10000
+    #[serde(default)]
+    max_keys: Option<usize>,
+
+    /// Response headers that are not kept and replayed, such as headers carrying per-user data.
+    /// `set-cookie` headers are never kept
+    #[serde(default)]
+    excluded_headers: Vec<String>,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+}
+
+/// The response of a completed mutation
+#[derive(Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    responses: Vec<graphql::Response>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// The mutation is executing
+    InFlight { operation: String },
+    /// The mutation completed, and its response is replayed until it expires
+    Completed {
+        operation: String,
+        response: Arc<StoredResponse>,
+        expires_at: SystemTime,
+    },
+}
+
+/// What to do with a request sending an idempotency key
+#[derive(Debug)]
+enum Lookup {
+    /// Execute the mutation, the key is reserved until it completes
+    Execute,
+    /// Replay the response of the completed mutation
+    Replay(Arc<StoredResponse>),
+    /// The mutation of the key is still executing
+    InFlight,
+    /// The key was used for another operation
+    Mismatch,
+}
+
+/// Idempotency keys, and the responses of their mutations
+#[derive(Clone, Debug)]
+struct Store {
+    entries: Arc<Mutex<LruCache<String, Entry>>>,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Store {
+    fn new(max_keys: NonZeroUsize, ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(LruCache::new(max_keys))),
+            ttl,
+            clock,
+        }
+    }
+
+    fn lookup(&self, key: &str, operation: &str) -> Lookup {
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        let lookup = match entries.get(key) {
+            None => Lookup::Execute,
+            Some(Entry::Completed { expires_at, .. }) if *expires_at <= self.clock.now() => {
+                Lookup::Execute
+            }
+            Some(
+                Entry::InFlight { operation: used }
+                | Entry::Completed {
+                    operation: used, ..
+                },
+            ) if used != operation => Lookup::Mismatch,
+            Some(Entry::InFlight { .. }) => Lookup::InFlight,
+            Some(Entry::Completed { response, .. }) => Lookup::Replay(response.clone()),
+        };
+        if let Lookup::Execute = lookup {
+            entries.put(
+                key.to_string(),
+                Entry::InFlight {
+                    operation: operation.to_string(),
+                },
+            );
+        }
+        lookup
+    }
+
+    fn complete(&self, key: &str, operation: &str, response: StoredResponse) {
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        entries.put(
+            key.to_string(),
+            Entry::Completed {
+                operation: operation.to_string(),
+                response: Arc::new(response),
+                expires_at: self.clock.now() + self.ttl,
+            },
+        );
+    }
+
+    /// Forgets a key whose mutation did not complete, so that it can be retried
+    fn release(&self, key: &str) {
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        if let Some(Entry::InFlight { .. }) = entries.peek(key) {
+            entries.pop(key);
+        }
+    }
+}
+
+/// A key reserved for the execution of a mutation
+#[derive(Clone, Debug)]
+struct Reserved {
+    key: String,
+    operation: String,
+}
+
+/// Releases the reserved key if the mutation does not complete, for example if the client
+/// disconnects
+struct Reservation {
+    store: Store,
+    reserved: Reserved,
+    completed: bool,
+}
+
+impl Reservation {
+    fn complete(mut self, response: StoredResponse) {
+        self.store
+            .complete(&self.reserved.key, &self.reserved.operation, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.release(&self.reserved.key);
+        }
+    }
+}
+
+/// Whether the requested operation of a document is a mutation. Documents that don't parse are
+/// handled as mutations, so that their key is not ignored
+fn is_mutation(query: &str, operation_name: Option<&str>, recursion_limit: usize) -> bool {
+    let tree = apollo_parser::Parser::new(query)
+        .recursion_limit(recursion_limit)
+        .parse();
+    if tree.errors().next().is_some() {
+        return true;
+    }
+    let mut operations = tree
+        .document()
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        });
+    let operation = match operation_name {
+        Some(operation_name) => operations.find(|operation| {
+            operation
+                .name()
+                .map(|name| name.text().to_string())
+                .as_deref()
+                == Some(operation_name)
+        }),
+        None => operations.next(),
+    };
+    operation
+        .and_then(|operation| operation.operation_type())
+        .map_or(false, |operation_type| {
+            operation_type.mutation_token().is_some()
+        })
+}
+
+/// Scopes an idempotency key by the identity of the caller: the subject of its JWT token, or its
+/// `Authorization` header
+fn scoped_key(request: &supergraph::Request, key: &str) -> String {
+    let subject = request
+        .context
+        .get::<_, serde_json::Value>(JWT_CLAIMS_CONTEXT_KEY)
+        .ok()
+        .flatten()
+        .and_then(|claims| claims.get("sub")?.as_str().map(str::to_string));
+    let mut hasher = Sha256::new();
+    match subject {
+        Some(subject) => {
+            hasher.update(b"sub\0");
+            hasher.update(subject.as_bytes());
+        }
+        None => {
+            hasher.update(b"authorization\0");
+            if let Some(authorization) = request.supergraph_request.headers().get(AUTHORIZATION) {
+                hasher.update(authorization.as_bytes());
+            }
+        }
+    }
+    hasher.update([0]);
+    hasher.update(key.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// The headers of a response that can be replayed to other requests of the same caller
+fn replayed_headers(headers: &HeaderMap, excluded: &[HeaderName]) -> HeaderMap {
+    let mut headers = headers.clone();
+    headers.remove(SET_COOKIE);
+    for name in excluded {
+        headers.remove(name);
+    }
+    headers
+}
+
+/// Identifies the operation and the variables of a request, so that a key is not replayed for
+/// another operation
+fn operation_hash(body: &graphql::Request, recursion_limit: usize) -> String {
+    let query = body.query.as_deref().unwrap_or_default();
+    let operation_name = body.operation_name.as_deref();
+    let normalized = normalization::normalize(query, operation_name, recursion_limit);
+    let mut hasher = Sha256::new();
+    match &normalized {
+        Some(normalized) => {
+            hasher.update(normalized.query.as_bytes());
+            hasher.update([0]);
+            hasher.update(normalized.operation.as_deref().unwrap_or_default());
+        }
+        None => {
+            hasher.update(query.as_bytes());
+            hasher.update([0]);
+            hasher.update(operation_name.unwrap_or_default());
+        }
+    }
+    hasher.update([0]);
+    hasher.update(serde_json::to_vec(&body.variables).unwrap_or_default());
+    format!("{:x}", hasher.finalize())
+}
+
+fn error_response(
+    message: &str,
+    code: &str,
+    status: StatusCode,
+    request: supergraph::Request,
+) -> Result<SupergraphResponse, BoxError> {
+    SupergraphResponse::builder()
+        .error(
+            Error::builder()
+                .message(message)
+                .extension_code(code)
+                .build(),
+        )
+        .status_code(status)
+        .context(request.context)
+        .build()
+}
+
+#[derive(Debug)]
+struct Idempotency {
+    header_name: String,
+    excluded_headers: Arc<Vec<HeaderName>>,
+    store: Store,
+    parser_recursion_limit: usize,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Idempotency {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let max_keys = NonZeroUsize::new(init.config.max_keys.unwrap_or(DEFAULT_MAX_KEYS))
+            .ok_or("idempotency max_keys must be greater than 0")?;
+        let excluded_headers = init
+            .config
+            .excluded_headers
+            .iter()
+            .map(|name| HeaderName::try_from(name.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Idempotency {
+            header_name: init
+                .config
+                .header_name
+                .unwrap_or_else(|| DEFAULT_HEADER_NAME.to_string()),
+            excluded_headers: Arc::new(excluded_headers),
+            store: Store::new(max_keys, init.config.ttl.unwrap_or(DEFAULT_TTL), init.clock),
+            parser_recursion_limit: init.config.parser_recursion_limit,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let header_name = self.header_name.clone();
+        let store = self.store.clone();
+        let reservation_store = self.store.clone();
+        let parser_recursion_limit = self.parser_recursion_limit;
+        let excluded_headers = self.excluded_headers.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |mut req: supergraph::Request| {
+                let key = match req
+                    .supergraph_request
+                    .headers()
+                    .get(header_name.as_str())
+                    .and_then(|key| key.to_str().ok())
+                {
+                    Some(key) => scoped_key(&req, key),
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                let body = req.supergraph_request.body();
+                let query = body.query.as_deref().unwrap_or_default();
+                if !is_mutation(
+                    query,
+                    body.operation_name.as_deref(),
+                    parser_recursion_limit,
+                ) {
+                    return Ok(ControlFlow::Continue(req));
+                }
+
+                let operation = operation_hash(body, parser_recursion_limit);
+                match store.lookup(&key, &operation) {
+                    Lookup::Execute => {
+                        req.supergraph_request
+                            .extensions_mut()
+                            .insert(Reserved { key, operation });
+                        Ok(ControlFlow::Continue(req))
+                    }
+                    Lookup::Replay(stored) => {
+                        tracing::info!(
+                            monotonic_counter.apollo_router_idempotency_replays_total = 1u64
+                        );
+                        let mut response =
+                            http::Response::new(stream::iter(stored.responses.clone()).boxed());
+                        *response.status_mut() = stored.status;
+                        *response.headers_mut() = stored.headers.clone();
+                        response
+                            .headers_mut()
+                            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+                        Ok(ControlFlow::Break(SupergraphResponse {
+                            response,
+                            context: req.context,
+                        }))
+                    }
+                    Lookup::InFlight => Ok(ControlFlow::Break(error_response(
+                        "a request with this idempotency key is being executed",
+                        "IDEMPOTENCY_KEY_IN_USE",
+                        StatusCode::CONFLICT,
+                        req,
+                    )?)),
+                    Lookup::Mismatch => Ok(ControlFlow::Break(error_response(
+                        "this idempotency key was used for another operation",
+                        "IDEMPOTENCY_KEY_MISMATCH",
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        req,
+                    )?)),
+                }
+            })
+            .map_future_with_request_data(
+                move |req: &supergraph::Request| {
+                    req.supergraph_request
+                        .extensions()
+                        .get::<Reserved>()
+                        .map(|reserved| Reservation {
+                            store: reservation_store.clone(),
+                            reserved: reserved.clone(),
+                            completed: false,
+                        })
+                },
+                move |reservation: Option<Reservation>, future| {
+                    let excluded_headers = excluded_headers.clone();
+                    async move {
+                        let response: supergraph::Response = future.await?;
+                        let reservation = match reservation {
+                            Some(reservation) => reservation,
+                            None => return Ok(response),
+                        };
+                        // failed requests can be retried
+                        if !response.response.status().is_success() {
+                            return Ok(response);
+                        }
+
+                        let SupergraphResponse { response, context } = response;
+                        let (parts, body) = response.into_parts();
+                        let responses: Vec<graphql::Response> = body.collect().await;
+                        reservation.complete(StoredResponse {
+                            status: parts.status,
+                            headers: replayed_headers(&parts.headers, &excluded_headers),
+                            responses: responses.clone(),
+                        });
+                        Ok(SupergraphResponse {
+                            response: http::Response::from_parts(
+                                parts,
+                                stream::iter(responses).boxed(),
+                            ),
+                            context,
+                        })
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "idempotency", Idempotency);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn stored() -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            responses: vec![graphql::Response::builder()
+                .data(serde_json_bytes::json!({ "pay": true }))
+                .build()],
+        }
+    }
+
+    #[test]
+    fn it_detects_mutations() {
+        let limit = default_parser_recursion_limit();
+        let document = "query A { me { id } } mutation B { pay }";
+        assert!(is_mutation(document, Some("B"), limit));
+        assert!(!is_mutation(document, Some("A"), limit));
+        assert!(!is_mutation(document, None, limit));
+        assert!(is_mutation("mutation { pay }", None, limit));
+        assert!(is_mutation("mutation { pay ", None, limit));
+        assert!(is_mutation("{ me { id } }", None, 1));
+    }
+
+    #[test]
+    fn it_replays_completed_mutations_until_they_expire() {
+        let clock = ManualClock::new();
+        let store = Store::new(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(60),
+            Arc::new(clock.clone()),
+        );
+
+        assert!(matches!(store.lookup("key", "pay"), Lookup::Execute));
+        assert!(matches!(store.lookup("key", "pay"), Lookup::InFlight));
+        assert!(matches!(store.lookup("key", "refund"), Lookup::Mismatch));
+
+        store.complete("key", "pay", stored());
+        assert!(matches!(store.lookup("key", "pay"), Lookup::Replay(_)));
+        assert!(matches!(store.lookup("key", "refund"), Lookup::Mismatch));
+
+        clock.advance(Duration::from_secs(61));
+        assert!(matches!(store.lookup("key", "refund"), Lookup::Execute));
+    }
+
+    #[test]
+    fn it_scopes_the_keys_by_caller() {
+        let request = |authorization: Option<&str>, subject: Option<&str>| {
+            let mut request = supergraph::Request::fake_builder()
+                .query("mutation { pay }")
+                .build()
+                .unwrap();
+            if let Some(authorization) = authorization {
+                request
+                    .supergraph_request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+            }
+            if let Some(subject) = subject {
+                request
+                    .context
+                    .insert(
+                        JWT_CLAIMS_CONTEXT_KEY,
+                        serde_json::json!({ "sub": subject }),
+                    )
+                    .unwrap();
+            }
+            request
+        };
+
+        let alice = scoped_key(&request(Some("Bearer alice"), None), "key");
+        assert_eq!(
+            alice,
+            scoped_key(&request(Some("Bearer alice"), None), "key")
+        );
+        assert_ne!(
+            alice,
+            scoped_key(&request(Some("Bearer alice"), None), "other")
+        );
+        assert_ne!(alice, scoped_key(&request(Some("Bearer bob"), None), "key"));
+        assert_ne!(alice, scoped_key(&request(None, None), "key"));
+        // the subject of the token identifies the caller across tokens
+        assert_eq!(
+            scoped_key(&request(Some("Bearer 1"), Some("alice")), "key"),
+            scoped_key(&request(Some("Bearer 2"), Some("alice")), "key")
+        );
+        assert_ne!(
+            scoped_key(&request(None, Some("alice")), "key"),
+            scoped_key(&request(None, Some("bob")), "key")
+        );
+    }
+
+    #[test]
+    fn it_does_not_replay_per_user_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, HeaderValue::from_static("session=1"));
+        headers.insert("x-user-id", HeaderValue::from_static("1"));
+        headers.insert("x-request-id", HeaderValue::from_static("a"));
+        let replayed = replayed_headers(&headers, &[HeaderName::from_static("x-user-id")]);
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed["x-request-id"], "a");
+    }
+
+    #[test]
+    fn it_releases_the_keys_of_mutations_that_did_not_complete() {
+        let store = Store::new(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(60),
+            Arc::new(ManualClock::new()),
+        );
+
+        assert!(matches!(store.lookup("key", "pay"), Lookup::Execute));
+        drop(Reservation {
+            store: store.clone(),
+            reserved: Reserved {
+                key: "key".to_string(),
+                operation: "pay".to_string(),
+            },
+            completed: false,
+        });
+        assert!(matches!(store.lookup("key", "pay"), Lookup::Execute));
+    }
+}
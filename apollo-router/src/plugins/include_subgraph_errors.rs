@@ -1,10 +1,13 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use schemars::JsonSchema;
 use serde::Deserialize;
+use serde::Serialize;
 use tower::BoxError;
 use tower::ServiceExt;
 
+use crate::graphql;
 use crate::json_ext::Object;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
@@ -22,15 +25,116 @@ register_plugin!("apollo", "include_subgraph_errors", IncludeSubgraphErrors);
 struct Config {
     /// Include errors from all subgraphs
     #[serde(default)]
-    all: bool,
+    all: ErrorPolicy,
 
     /// Include errors from specific subgraphs
     #[serde(default)]
-    subgraphs: HashMap<String, bool>,
+    subgraphs: HashMap<String, ErrorPolicy>,
+}
+
+/// Policy for the errors of a subgraph
+#[derive(Clone, Debug, JsonSchema, Deserialize, Serialize)]
+#[serde(untagged)]
+enum ErrorPolicy {
+    /// `true` to forward the errors verbatim, `false` to redact them
+    Include(bool),
+    /// Forward, redact or filter the errors
+    Policy(PolicyConfig),
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::Include(false)
+    }
+}
+
+impl From<ErrorPolicy> for PolicyConfig {
+    fn from(policy: ErrorPolicy) -> Self {
+        match policy {
+            ErrorPolicy::Include(include) => PolicyConfig {
+                mode: if include {
+                    ErrorMode::Forward
+                } else {
+                    ErrorMode::Redact
+                },
+                allowed_codes: Vec::new(),
+                allowed_extensions: None,
+            },
+            ErrorPolicy::Policy(config) => config,
+        }
+    }
+}
+
+#[derive(Clone, Debug, JsonSchema, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyConfig {
+    /// How the errors are sent to clients
+    mode: ErrorMode,
+
+    /// With the `filter` mode, the extension codes of the errors forwarded to clients. The other
+    /// errors are redacted
+    #[serde(default)]
+    allowed_codes: Vec<String>,
+
+    /// The extensions kept on forwarded errors. All extensions are kept if not set
+    #[serde(default)]
+    allowed_extensions: Option<Vec<String>>,
+}
+
+impl PolicyConfig {
+    fn forwards_all(&self) -> bool {
+        self.mode == ErrorMode::Forward && self.allowed_extensions.is_none()
+    }
+
+    fn allows(&self, error: &graphql::Error) -> bool {
+        match self.mode {
+            ErrorMode::Forward => true,
+            ErrorMode::Redact => false,
+            ErrorMode::Filter => error
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .map_or(false, |code| {
+                    self.allowed_codes.iter().any(|allowed| allowed == code)
+                }),
+        }
+    }
+
+    fn apply(&self, error: &mut graphql::Error) -> bool {
+        if !self.allows(error) {
+            error.message = REDACTED_ERROR_MESSAGE.to_string();
+            error.extensions = Object::default();
+            return true;
+        }
+        if let Some(allowed_extensions) = &self.allowed_extensions {
+            error.extensions = std::mem::take(&mut error.extensions)
+                .into_iter()
+                .filter(|(key, _)| {
+                    allowed_extensions
+                        .iter()
+                        .any(|allowed| allowed == key.as_str())
+                })
+                .collect();
+        }
+        false
+    }
+}
+
+/// How the errors of a subgraph are sent to clients
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorMode {
+    /// Forward the errors verbatim
+    Forward,
+    /// Replace the message of the errors with a generic message, and remove their extensions
+    Redact,
+    /// Forward the errors with an allowed extension code, and redact the others
+    Filter,
 }
 
 struct IncludeSubgraphErrors {
-    config: Config,
+    all: Arc<PolicyConfig>,
+    subgraphs: HashMap<String, Arc<PolicyConfig>>,
 }
 
 #[async_trait::async_trait]
@@ -39,40 +143,49 @@ impl Plugin for IncludeSubgraphErrors {
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
         Ok(IncludeSubgraphErrors {
-            config: init.config,
+            all: Arc::new(init.config.all.into()),
+            subgraphs: init
+                .config
+                .subgraphs
+                .into_iter()
+                .map(|(name, policy)| (name, Arc::new(policy.into())))
+                .collect(),
         })
     }
 
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         // Search for subgraph in our configured subgraph map.
         // If we can't find it, use the "all" value
-        if !*self.config.subgraphs.get(name).unwrap_or(&self.config.all) {
-            let sub_name_response = name.to_string();
-            let sub_name_error = name.to_string();
-            return service
-                .map_response(move |mut response: SubgraphResponse| {
-                    if !response.response.body().errors.is_empty() {
-                        tracing::info!("redacted subgraph({sub_name_response}) errors");
-                        for error in response.response.body_mut().errors.iter_mut() {
-                            error.message = REDACTED_ERROR_MESSAGE.to_string();
-                            error.extensions = Object::default();
-                        }
-                    }
-                    response
-                })
-                // _error to stop clippy complaining about unused assignments...
-                .map_err(move |mut _error: BoxError| {
-                    // Create a redacted error to replace whatever error we have
-                    tracing::info!("redacted subgraph({sub_name_error}) error");
-                    _error = Box::new(crate::error::FetchError::SubrequestHttpError {
-                        service: "redacted".to_string(),
-                        reason: "redacted".to_string(),
-                    });
-                    _error
-                })
-                .boxed();
+        let policy = self.subgraphs.get(name).unwrap_or(&self.all).clone();
+        if policy.forwards_all() {
+            return service;
         }
+        let sub_name_response = name.to_string();
+        let sub_name_error = name.to_string();
+        let redacts_failures = policy.mode != ErrorMode::Forward;
         service
+            .map_response(move |mut response: SubgraphResponse| {
+                let mut redacted = false;
+                for error in response.response.body_mut().errors.iter_mut() {
+                    redacted |= policy.apply(error);
+                }
+                if redacted {
+                    tracing::info!("redacted subgraph({sub_name_response}) errors");
+                }
+                response
+            })
+            .map_err(move |error: BoxError| {
+                if !redacts_failures {
+                    return error;
+                }
+                // Create a redacted error to replace whatever error we have
+                tracing::info!("redacted subgraph({sub_name_error}) error");
+                Box::new(crate::error::FetchError::SubrequestHttpError {
+                    service: "redacted".to_string(),
+                    reason: "redacted".to_string(),
+                }) as BoxError
+            })
+            .boxed()
     }
 }
 
@@ -116,6 +229,10 @@ mod test {
         )
     });
 
+    static FILTERED_EXTENSIONS_PRODUCT_RESPONSE: Lazy<Bytes> = Lazy::new(|| {
+        Bytes::from_static(r#"{"data":{"topProducts":null},"errors":[{"message":"couldn't find mock for query {\"query\":\"query ErrorTopProducts__products__0($first:Int){topProducts(first:$first){__typename upc name}}\",\"operationName\":\"ErrorTopProducts__products__0\",\"variables\":{\"first\":2}}","extensions":{"code":"FETCH_ERROR"}}]}"#.as_bytes())
+    });
+
     static EXPECTED_RESPONSE: Lazy<Bytes> = Lazy::new(|| {
         Bytes::from_static(r#"{"data":{"topProducts":[{"upc":"1","name":"Table","reviews":[{"id":"1","product":{"name":"Table"},"author":{"id":"1","name":"Ada Lovelace"}},{"id":"4","product":{"name":"Table"},"author":{"id":"2","name":"Alan Turing"}}]},{"upc":"2","name":"Couch","reviews":[{"id":"2","product":{"name":"Couch"},"author":{"id":"1","name":"Ada Lovelace"}}]}]}}"#.as_bytes())
     });
@@ -159,8 +276,8 @@ mod test {
 
         let account_mocks = vec![
             (
-                r#"{"query":"query TopProducts__accounts__3($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}","operationName":"TopProducts__accounts__3","variables":{"representations":[{"__typename":"User","id":"1"},{"__typename":"User","id":"2"},{"__typename":"User","id":"1"}]}}"#,
-                r#"{"data":{"_entities":[{"name":"Ada Lovelace"},{"name":"Alan Turing"},{"name":"Ada Lovelace"}]}}"#
+                r#"{"query":"query TopProducts__accounts__3($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}","operationName":"TopProducts__accounts__3","variables":{"representations":[{"__typename":"User","id":"1"},{"__typename":"User","id":"2"}]}}"#,
+                r#"{"data":{"_entities":[{"name":"Ada Lovelace"},{"name":"Alan Turing"}]}}"#
             )
         ].into_iter().map(|(query, response)| (serde_json::from_str(query).unwrap(), serde_json::from_str(response).unwrap())).collect();
         let account_service = MockSubgraph::new(account_mocks);
@@ -179,8 +296,8 @@ mod test {
                 r#"{"data":{"topProducts":[{"__typename":"Product","upc":"1","name":"Table"},{"__typename":"Product","upc":"2","name":"Couch"}]}}"#
             ),
             (
-                r#"{"query":"query TopProducts__products__2($representations:[_Any!]!){_entities(representations:$representations){...on Product{name}}}","operationName":"TopProducts__products__2","variables":{"representations":[{"__typename":"Product","upc":"1"},{"__typename":"Product","upc":"1"},{"__typename":"Product","upc":"2"}]}}"#,
-                r#"{"data":{"_entities":[{"name":"Table"},{"name":"Table"},{"name":"Couch"}]}}"#
+                r#"{"query":"query TopProducts__products__2($representations:[_Any!]!){_entities(representations:$representations){...on Product{name}}}","operationName":"TopProducts__products__2","variables":{"representations":[{"__typename":"Product","upc":"1"},{"__typename":"Product","upc":"2"}]}}"#,
+                r#"{"data":{"_entities":[{"name":"Table"},{"name":"Couch"}]}}"#
             )
             ].into_iter().map(|(query, response)| (serde_json::from_str(query).unwrap(), serde_json::from_str(response).unwrap())).collect();
 
@@ -211,6 +328,7 @@ mod test {
             &Configuration::default(),
         )
         .await
+        .expect("should build")
         .make()
         .boxed()
     }
@@ -318,4 +436,40 @@ mod test {
         let router = build_mock_router(plugin).await;
         execute_router_test(ERROR_ACCOUNT_QUERY, &REDACTED_ACCOUNT_RESPONSE, router).await;
     }
+
+    #[tokio::test]
+    async fn it_forwards_errors_with_an_allowed_code() {
+        let plugin = get_redacting_plugin(&serde_json::json!({
+            "subgraphs": { "products": { "mode": "filter", "allowed_codes": ["FETCH_ERROR"] } }
+        }))
+        .await;
+        let router = build_mock_router(plugin).await;
+        execute_router_test(ERROR_PRODUCT_QUERY, &UNREDACTED_PRODUCT_RESPONSE, router).await;
+    }
+
+    #[tokio::test]
+    async fn it_redacts_errors_without_an_allowed_code() {
+        let plugin = get_redacting_plugin(&serde_json::json!({
+            "all": true,
+            "subgraphs": { "products": { "mode": "filter", "allowed_codes": ["UNAUTHENTICATED"] } }
+        }))
+        .await;
+        let router = build_mock_router(plugin).await;
+        execute_router_test(ERROR_PRODUCT_QUERY, &REDACTED_PRODUCT_RESPONSE, router).await;
+    }
+
+    #[tokio::test]
+    async fn it_removes_extensions_not_allowed() {
+        let plugin = get_redacting_plugin(&serde_json::json!({
+            "all": { "mode": "forward", "allowed_extensions": ["code"] }
+        }))
+        .await;
+        let router = build_mock_router(plugin).await;
+        execute_router_test(
+            ERROR_PRODUCT_QUERY,
+            &FILTERED_EXTENSIONS_PRODUCT_RESPONSE,
+            router,
+        )
+        .await;
+    }
 }
@@ -0,0 +1,703 @@
+//! Customization via JavaScript.
+//!
+//! Scripts use the same hooks as Rhai scripts: the main file declares some of the
+//! `router_service`, `supergraph_service`, `execution_service` and `subgraph_service`
+//! functions, which register `map_request` and `map_response` callbacks on the service
+//! they receive. Callbacks modify the headers, context and body of the object they are
+//! called with, and throw to stop the request.
+//!
+//! The scripts run in a V8 isolate owned by a dedicated thread, because the isolate can not
+//! be shared between the tokio worker threads. Callbacks are executed one at a time.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use deno_core::v8;
+use deno_core::JsRuntime;
+use deno_core::RuntimeOptions;
+use futures::FutureExt;
+use http::HeaderMap;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tower::util::BoxService;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::error::Error;
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::external::externalize_header_map;
+use crate::plugins::external::internalize_header_map;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::router;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::Context;
+
+/// Defines the objects passed to the hooks, and runs the callbacks they registered.
+/// Console output is buffered, and logged by the router after each call.
+const BOOTSTRAP: &str = r#"
+globalThis.__router = (() => {
+  const callbacks = new Map();
+  let logs = [];
+  const log = (level) => (...args) => {
+    logs.push([level, args.map((arg) => typeof arg === "string" ? arg : JSON.stringify(arg)).join(" ")]);
+  };
+  globalThis.console = {
+    debug: log("debug"),
+    log: log("info"),
+    info: log("info"),
+    warn: log("warn"),
+    error: log("error"),
+  };
+  const service = (prefix) => ({
+    map_request(callback) { callbacks.set(`${prefix}:request`, callback); },
+    map_response(callback) { callbacks.set(`${prefix}:response`, callback); },
+  });
+  const output = (result) => {
+    result.logs = logs;
+    logs = [];
+    return JSON.stringify(result);
+  };
+  return {
+    register(stage, prefix, subgraph) {
+      const hook = globalThis[`${stage}_service`];
+      if (typeof hook === "function") {
+        hook(service(prefix), subgraph);
+      }
+      return output({
+        request: callbacks.has(`${prefix}:request`),
+        response: callbacks.has(`${prefix}:response`),
+      });
+    },
+    call(key, payload) {
+      try {
+        callbacks.get(key)(payload);
+        return output({ payload });
+      } catch (error) {
+        if (error !== null && typeof error === "object" && typeof error.status === "number") {
+          return output({ error: { status: error.status, message: String(error.message ?? "") } });
+        }
+        return output({ error: { status: 500, message: `javascript execution error: '${error}'` } });
+      }
+    },
+  };
+})();
+"#;
+
+/// Configuration for the JavaScript Plugin
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Conf {
+    /// The directory where JavaScript scripts can be found
+    scripts: Option<PathBuf>,
+    /// The main entry point for JavaScript script evaluation
+    main: Option<String>,
+}
+
+/// Headers, context and body passed to a callback, and read back after it ran
+#[derive(Debug, Deserialize, Serialize)]
+struct Payload {
+    headers: HashMap<String, Vec<String>>,
+    context: HashMap<String, serde_json_bytes::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Payload(Payload),
+    Error(ErrorDetails),
+}
+
+#[derive(Debug, Deserialize)]
+struct CallOutput {
+    #[serde(flatten)]
+    outcome: Outcome,
+    logs: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Registered {
+    request: bool,
+    response: bool,
+    #[serde(default)]
+    logs: Vec<(String, String)>,
+}
+
+/// Status and message of the error returned when a callback throws
+#[derive(Debug, Deserialize)]
+struct ErrorDetails {
+    status: u16,
+    message: String,
+}
+
+struct Job {
+    script: String,
+    reply: oneshot::Sender<Result<String, BoxError>>,
+}
+
+/// Handle to the thread running the scripts. The thread stops when the handle is dropped.
+struct Runtime {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl Runtime {
+    fn start(main: &Path) -> Result<Self, BoxError> {
+        let source = std::fs::read_to_string(main)
+            .map_err(|e| format!("could not read JavaScript file {}: {e}", main.display()))?;
+        let name = main.display().to_string();
+        let (jobs, mut receiver) = mpsc::unbounded_channel::<Job>();
+        let (ready_sender, ready) = std::sync::mpsc::sync_channel(1);
+
+        std::thread::Builder::new()
+            .name("javascript".to_string())
+            .spawn(move || {
+                let mut runtime = JsRuntime::new(RuntimeOptions::default());
+                let loaded = runtime
+                    .execute_script("bootstrap.js", BOOTSTRAP)
+                    .and_then(|_| runtime.execute_script(&name, &source))
+                    .map(|_| ())
+                    .map_err(|e| format!("could not evaluate JavaScript file {name}: {e}"));
+                let failed = loaded.is_err();
+                let _ = ready_sender.send(loaded);
+                if failed {
+                    return;
+                }
+                while let Some(job) = receiver.blocking_recv() {
+                    let _ = job.reply.send(evaluate(&mut runtime, &job.script));
+                }
+            })?;
+
+        ready
+            .recv()
+            .map_err(|_| "the JavaScript thread stopped")??;
+        Ok(Self { jobs })
+    }
+
+    async fn evaluate(&self, script: String) -> Result<String, BoxError> {
+        let (reply, output) = oneshot::channel();
+        self.jobs
+            .send(Job { script, reply })
+            .map_err(|_| "the JavaScript thread stopped")?;
+        output.await.map_err(|_| "the JavaScript thread stopped")?
+    }
+
+    /// Calls the hook of a stage, and returns the callbacks it registered
+    ///
+    /// Hooks are called while the services are built, which is not async,
+    /// so this waits for the JavaScript thread.
+    fn register(&self, stage: &str, prefix: &str, subgraph: Option<&str>) -> Registered {
+        let result = (|| {
+            let script = format!(
+                "__router.register({}, {}, {})",
+                serde_json::to_string(stage)?,
+                serde_json::to_string(prefix)?,
+                serde_json::to_string(&subgraph)?
+            );
+            let output = futures::executor::block_on(self.evaluate(script))?;
+            Ok::<_, BoxError>(serde_json::from_str::<Registered>(&output)?)
+        })();
+        match result {
+            Ok(registered) => {
+                emit_logs(&registered.logs);
+                registered
+            }
+            Err(error) => {
+                tracing::error!("{stage}_service function failed: {error}");
+                Registered::default()
+            }
+        }
+    }
+
+    /// Runs a callback, and returns the error details if it threw
+    async fn call<T: Exposed>(
+        &self,
+        key: &str,
+        exposed: &mut T,
+    ) -> Result<Option<ErrorDetails>, BoxError> {
+        let sent_context = exposed.context().snapshot();
+        let payload = Payload {
+            headers: externalize_header_map(exposed.headers_mut())?,
+            context: sent_context
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .collect(),
+            body: exposed.body()?,
+        };
+        let script = format!(
+            "__router.call({}, {})",
+            serde_json::to_string(key)?,
+            serde_json::to_string(&payload)?
+        );
+        let output: CallOutput = serde_json::from_str(&self.evaluate(script).await?)?;
+        emit_logs(&output.logs);
+
+        match output.outcome {
+            Outcome::Payload(payload) => {
+                *exposed.headers_mut() = internalize_header_map(payload.headers)?;
+                // Only the entries changed by the callback are merged, like in the co-processor
+                let context = Context::new();
+                for (key, value) in payload.context {
+                    context.insert_json_value(key, value);
+                }
+                // the callback changes the whole object, so the missing entries were deleted
+                for entry in sent_context.iter() {
+                    if context.get_json_value(entry.key()).is_none() {
+                        context
+                            .insert_json_value(entry.key().clone(), serde_json_bytes::Value::Null);
+                    }
+                }
+                exposed.context().merge_changes(&sent_context, &context);
+                if let Some(body) = payload.body {
+                    exposed.set_body(body)?;
+                }
+                Ok(None)
+            }
+            Outcome::Error(error) => {
+                tracing::error!("{key} callback failed: {}", error.message);
+                Ok(Some(error))
+            }
+        }
+    }
+}
+
+fn evaluate(runtime: &mut JsRuntime, script: &str) -> Result<String, BoxError> {
+    let value = runtime.execute_script("<router>", script)?;
+    let scope = &mut runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    Ok(value.to_rust_string_lossy(scope))
+}
+
+fn emit_logs(logs: &[(String, String)]) {
+    for (level, message) in logs {
+        match level.as_str() {
+            "debug" => tracing::debug!("{message}"),
+            "warn" => tracing::warn!("{message}"),
+            "error" => tracing::error!("{message}"),
+            _ => tracing::info!("{message}"),
+        }
+    }
+}
+
+/// A request or response passed to JavaScript callbacks
+trait Exposed: Sized + Send + 'static {
+    /// What the service returns instead when a callback throws
+    type Rejection;
+
+    fn context(&self) -> &Context;
+
+    fn headers_mut(&mut self) -> &mut HeaderMap;
+
+    /// The GraphQL request or response, if the stage gives access to it
+    fn body(&self) -> Result<Option<serde_json::Value>, BoxError> {
+        Ok(None)
+    }
+
+    fn set_body(&mut self, _body: serde_json::Value) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError>;
+}
+
+fn error_parts(error: ErrorDetails) -> Result<(Vec<Error>, StatusCode), BoxError> {
+    Ok((
+        vec![Error {
+            message: error.message,
+            ..Default::default()
+        }],
+        StatusCode::from_u16(error.status)?,
+    ))
+}
+
+impl Exposed for router::Request {
+    type Rejection = router::Response;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.router_request.headers_mut()
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        router::Response::reject(context, error)
+    }
+}
+
+impl Exposed for router::Response {
+    type Rejection = Self;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.response.headers_mut()
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        let (errors, status_code) = error_parts(error)?;
+        router::Response::error_builder()
+            .errors(errors)
+            .status_code(status_code)
+            .context(context)
+            .build()
+    }
+}
+
+impl Exposed for supergraph::Request {
+    type Rejection = supergraph::Response;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.supergraph_request.headers_mut()
+    }
+
+    fn body(&self) -> Result<Option<serde_json::Value>, BoxError> {
+        Ok(Some(serde_json::to_value(self.supergraph_request.body())?))
+    }
+
+    fn set_body(&mut self, body: serde_json::Value) -> Result<(), BoxError> {
+        *self.supergraph_request.body_mut() = serde_json::from_value::<graphql::Request>(body)?;
+        Ok(())
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        supergraph::Response::reject(context, error)
+    }
+}
+
+/// Supergraph and execution responses are streamed, so only their headers and context
+/// are passed to callbacks.
+impl Exposed for supergraph::Response {
+    type Rejection = Self;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.response.headers_mut()
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        let (errors, status_code) = error_parts(error)?;
+        supergraph::Response::error_builder()
+            .errors(errors)
+            .status_code(status_code)
+            .context(context)
+            .build()
+    }
+}
+
+impl Exposed for execution::Request {
+    type Rejection = execution::Response;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.supergraph_request.headers_mut()
+    }
+
+    fn body(&self) -> Result<Option<serde_json::Value>, BoxError> {
+        Ok(Some(serde_json::to_value(self.supergraph_request.body())?))
+    }
+
+    fn set_body(&mut self, body: serde_json::Value) -> Result<(), BoxError> {
+        *self.supergraph_request.body_mut() = serde_json::from_value::<graphql::Request>(body)?;
+        Ok(())
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        execution::Response::reject(context, error)
+    }
+}
+
+impl Exposed for subgraph::Request {
+    type Rejection = subgraph::Response;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.subgraph_request.headers_mut()
+    }
+
+    fn body(&self) -> Result<Option<serde_json::Value>, BoxError> {
+        Ok(Some(serde_json::to_value(self.subgraph_request.body())?))
+    }
+
+    fn set_body(&mut self, body: serde_json::Value) -> Result<(), BoxError> {
+        *self.subgraph_request.body_mut() = serde_json::from_value::<graphql::Request>(body)?;
+        Ok(())
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        subgraph::Response::reject(context, error)
+    }
+}
+
+impl Exposed for subgraph::Response {
+    type Rejection = Self;
+
+    fn context(&self) -> &Context {
+        &self.context
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.response.headers_mut()
+    }
+
+    fn body(&self) -> Result<Option<serde_json::Value>, BoxError> {
+        Ok(Some(serde_json::to_value(self.response.body())?))
+    }
+
+    fn set_body(&mut self, body: serde_json::Value) -> Result<(), BoxError> {
+        *self.response.body_mut() = serde_json::from_value::<graphql::Response>(body)?;
+        Ok(())
+    }
+
+    fn reject(context: Context, error: ErrorDetails) -> Result<Self::Rejection, BoxError> {
+        let (errors, status_code) = error_parts(error)?;
+        subgraph::Response::error_builder()
+            .errors(errors)
+            .status_code(status_code)
+            .context(context)
+            .build()
+    }
+}
+
+/// Plugin which implements JavaScript functionality
+struct JavaScript {
+    runtime: Arc<Runtime>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for JavaScript {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let scripts_path = match init.config.scripts {
+            Some(path) => path,
+            None => "./javascript".into(),
+        };
+
+        let main_file = match init.config.main {
+            Some(main) => main,
+            None => "main.js".to_string(),
+        };
+
+        Ok(Self {
+            runtime: Arc::new(Runtime::start(&scripts_path.join(main_file))?),
+        })
+    }
+
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        self.service(service, "router", "router".to_string(), None)
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        self.service(service, "supergraph", "supergraph".to_string(), None)
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        self.service(service, "execution", "execution".to_string(), None)
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        self.service(service, "subgraph", format!("subgraph:{name}"), Some(name))
+    }
+}
+
+impl JavaScript {
+    /// Wraps a service with the callbacks registered by the hook of its stage
+    fn service<Req, Res>(
+        &self,
+        service: BoxService<Req, Res, BoxError>,
+        stage: &str,
+        prefix: String,
+        subgraph: Option<&str>,
+    ) -> BoxService<Req, Res, BoxError>
+    where
+        Req: Exposed<Rejection = Res>,
+        Res: Exposed<Rejection = Res>,
+    {
+        let registered = self.runtime.register(stage, &prefix, subgraph);
+
+        let service = if registered.response {
+            let runtime = self.runtime.clone();
+            let key = format!("{prefix}:response");
+            service
+                .and_then(move |mut response: Res| async move {
+                    match runtime.call(&key, &mut response).await? {
+                        Some(error) => Res::reject(response.context().clone(), error),
+                        None => Ok(response),
+                    }
+                })
+                .boxed()
+        } else {
+            service
+        };
+
+        if registered.request {
+            let runtime = self.runtime.clone();
+            let key = format!("{prefix}:request");
+            ServiceBuilder::new()
+                .checkpoint_async(move |mut request: Req| {
+                    let runtime = runtime.clone();
+                    let key = key.clone();
+                    async move {
+                        match runtime.call(&key, &mut request).await? {
+                            Some(error) => Ok(ControlFlow::Break(Req::reject(
+                                request.context().clone(),
+                                error,
+                            )?)),
+                            None => Ok(ControlFlow::Continue(request)),
+                        }
+                    }
+                    .boxed()
+                })
+                .buffered()
+                .service(service)
+                .boxed()
+        } else {
+            service
+        }
+    }
+}
+
+register_plugin!("apollo", "experimental_javascript", JavaScript);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraphService;
+    use crate::plugin::test::MockSupergraphService;
+    use crate::plugin::test::PluginTestHarness;
+
+    async fn harness() -> PluginTestHarness<JavaScript> {
+        PluginTestHarness::new(json!({ "scripts": "tests/fixtures/javascript" }))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_rejects_requests() {
+        let mut downstream = MockSupergraphService::new();
+        downstream.expect_call().never();
+
+        let mut response = harness()
+            .await
+            .supergraph_service(downstream)
+            .oneshot(supergraph::Request::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.next_response().await.unwrap().errors[0].message,
+            "missing x-client-name header"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_modifies_supergraph_requests_and_responses() {
+        let mut downstream = MockSupergraphService::new();
+        downstream
+            .expect_call()
+            .times(1)
+            .returning(|request: supergraph::Request| {
+                assert_eq!(
+                    request.context.get::<_, String>("client_name").unwrap(),
+                    Some("web".to_string())
+                );
+                supergraph::Response::fake_builder()
+                    .context(request.context)
+                    .build()
+            });
+
+        let response = harness()
+            .await
+            .supergraph_service(downstream)
+            .oneshot(
+                supergraph::Request::fake_builder()
+                    .header("x-client-name", "web")
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+        assert_eq!(
+            response.response.headers().get("x-client-name").unwrap(),
+            "web"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_modifies_subgraph_requests_and_responses() {
+        let mut downstream = MockSubgraphService::new();
+        downstream
+            .expect_call()
+            .times(1)
+            .returning(|request: subgraph::Request| {
+                assert_eq!(
+                    request
+                        .subgraph_request
+                        .headers()
+                        .get("x-subgraph")
+                        .unwrap(),
+                    "products"
+                );
+                Ok(subgraph::Response::fake_builder()
+                    .data(serde_json_bytes::json!({ "me": "the original" }))
+                    .build())
+            });
+
+        let response = harness()
+            .await
+            .subgraph_service("products", downstream)
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.response.body().data,
+            Some(serde_json_bytes::json!({ "me": "rewritten by javascript" }))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_fails_on_missing_scripts() {
+        assert!(PluginTestHarness::<JavaScript>::new(json!({
+            "scripts": "tests/fixtures/javascript",
+            "main": "missing.js"
+        }))
+        .await
+        .is_err());
+    }
+}
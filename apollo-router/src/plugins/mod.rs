@@ -20,14 +20,38 @@ macro_rules! schemar_fn {
     };
 }
 
-mod authentication;
+pub(crate) mod authentication;
+mod client_ip;
 pub(crate) mod csrf;
+pub(crate) mod custom_scalars;
+mod error_messages;
+mod error_status_codes;
+mod event_subscriptions;
 mod expose_query_plan;
-mod external;
+pub(crate) mod external;
+pub(crate) mod fault_injection;
+pub(crate) mod feature_flags;
+pub(crate) mod file_uploads;
 mod forbid_mutations;
 mod headers;
+mod idempotency;
 mod include_subgraph_errors;
+#[cfg(feature = "javascript")]
+mod javascript;
+pub(crate) mod nats_subgraphs;
+pub(crate) mod operation_limits;
+pub(crate) mod operation_rules;
 pub(crate) mod override_url;
+mod partial_data;
+mod performance_extensions;
+mod request_capture;
+mod response_extensions;
 pub(crate) mod rhai;
+pub(crate) mod router_fields;
+mod script_limits;
+mod slow_operations;
 pub(crate) mod telemetry;
 pub(crate) mod traffic_shaping;
+mod usage_export;
+#[cfg(feature = "wasm")]
+mod wasm;
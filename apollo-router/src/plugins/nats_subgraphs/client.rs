@@ -0,0 +1,211 @@
+//! Request/reply client of the NATS protocol, sharing a connection between the requests
+//!
+//! The replies are received on a single subscription to a random inbox, with the token of each
+//! request as last token of its reply subject. The connection is opened by the first request, and
+//! opened again by the next request once it is closed.
+//!
+//! Queue groups do not apply here: they balance the messages of a subject between its
+//! subscribers, and the only subscription of the router is its own inbox. The subgraph instances
+//! subscribe to the subject of the subgraph with a queue group to share its requests.
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tower::BoxError;
+
+use crate::nats;
+use crate::nats::Incoming;
+
+/// Status of the replies sent by the server when no service is subscribed to the subject
+const NO_RESPONDERS_STATUS: &str = "503";
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Bytes, BoxError>>>>>;
+
+/// Why a request got no reply
+#[derive(Debug)]
+pub(super) enum RequestError {
+    Timeout,
+    Failed(BoxError),
+}
+
+pub(super) struct NatsClient {
+    url: url::Url,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+}
+
+impl NatsClient {
+    pub(super) fn new(url: url::Url) -> Self {
+        NatsClient {
+            url,
+            connection: Default::default(),
+        }
+    }
+
+    /// Publishes the payload to the subject, with the headers, and waits for its reply. The
+    /// timeout includes opening the connection
+    pub(super) async fn request(
+        &self,
+        subject: &str,
+        headers: &HeaderMap,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Bytes, RequestError> {
+        let deadline = Instant::now() + timeout;
+        let connection = tokio::time::timeout_at(deadline, self.connection())
+            .await
+            .map_err(|_| RequestError::Timeout)?
+            .map_err(RequestError::Failed)?;
+        let token = connection.next_token.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        connection
+            .pending
+            .lock()
+            .expect("lock poisoned")
+            .insert(token, sender);
+        let reply_to = format!("{}.{token}", connection.inbox);
+        connection
+            .commands
+            .send(nats::publish(subject, &reply_to, headers, payload))
+            .map_err(|_| RequestError::Failed("the NATS connection is closed".into()))?;
+
+        match tokio::time::timeout_at(deadline, receiver).await {
+            Ok(Ok(reply)) => reply.map_err(RequestError::Failed),
+            Ok(Err(_)) => Err(RequestError::Failed(
+                "the NATS connection was closed before the reply".into(),
+            )),
+            Err(_) => {
+                connection
+                    .pending
+                    .lock()
+                    .expect("lock poisoned")
+                    .remove(&token);
+                Err(RequestError::Timeout)
+            }
+        }
+    }
+
+    async fn connection(&self) -> Result<Arc<Connection>, BoxError> {
+        let mut connection = self.connection.lock().await;
+        match &*connection {
+            Some(connection) if !connection.closed.load(Ordering::Relaxed) => {
+                Ok(connection.clone())
+            }
+            _ => {
+                let new_connection = Arc::new(Connection::open(&self.url).await?);
+                *connection = Some(new_connection.clone());
+                Ok(new_connection)
+            }
+        }
+    }
+}
+
+struct Connection {
+    inbox: String,
+    next_token: AtomicU64,
+    pending: Pending,
+    commands: mpsc::UnboundedSender<Vec<u8>>,
+    closed: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+impl Connection {
+    async fn open(url: &url::Url) -> Result<Self, BoxError> {
+        let (reader, mut writer) = nats::connect(url, true).await?;
+        let inbox = format!("_INBOX.{}", uuid::Uuid::new_v4().simple());
+        writer
+            .write_all(format!("SUB {inbox}.* 1\r\n").as_bytes())
+            .await?;
+
+        let (commands, mut pending_commands) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer = tokio::spawn(async move {
+            while let Some(command) = pending_commands.recv().await {
+                if writer.write_all(&command).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let pending = Pending::default();
+        let closed = Arc::new(AtomicBool::new(false));
+        let reader = tokio::spawn({
+            let inbox = inbox.clone();
+            let pending = pending.clone();
+            let commands = commands.clone();
+            let closed = closed.clone();
+            async move {
+                if let Err(e) = read_replies(reader, &inbox, &pending, &commands).await {
+                    tracing::error!("the NATS connection of the subgraphs failed: {}", e);
+                }
+                closed.store(true, Ordering::Relaxed);
+                // the waiting requests fail instead of timing out
+                pending.lock().expect("lock poisoned").clear();
+            }
+        });
+
+        Ok(Connection {
+            inbox,
+            next_token: AtomicU64::new(0),
+            pending,
+            commands,
+            closed,
+            reader,
+            writer,
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+async fn read_replies(
+    mut reader: nats::Reader,
+    inbox: &str,
+    pending: &Pending,
+    commands: &mpsc::UnboundedSender<Vec<u8>>,
+) -> Result<(), BoxError> {
+    loop {
+        let (subject, headers, payload) = match reader.next().await? {
+            Incoming::Message {
+                subject,
+                headers,
+                payload,
+            } => (subject, headers, payload),
+            Incoming::Ping => {
+                commands.send(b"PONG\r\n".to_vec())?;
+                continue;
+            }
+            Incoming::Other => continue,
+        };
+        let reply = if nats::status(&headers) == Some(NO_RESPONDERS_STATUS) {
+            Err("no service is subscribed to the subject".into())
+        } else {
+            Ok(payload)
+        };
+
+        let token = subject
+            .strip_prefix(inbox)
+            .and_then(|token| token.strip_prefix('.'))
+            .and_then(|token| token.parse::<u64>().ok());
+        if let Some(sender) =
+            token.and_then(|token| pending.lock().expect("lock poisoned").remove(&token))
+        {
+            let _ = sender.send(reply);
+        }
+    }
+}
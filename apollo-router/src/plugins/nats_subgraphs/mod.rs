@@ -0,0 +1,317 @@
+//! Subgraph requests over NATS request/reply
+//!
+//! The configured subgraphs are called by publishing their requests to a NATS subject, instead of
+//! sending them over HTTP. The payload of a request is the JSON body of the HTTP request, and its
+//! reply must be the JSON body of the HTTP response. The headers of the subgraph request are sent
+//! as NATS headers, so that the header propagation and the trace context still apply.
+//!
+//! The router factory uses this transport instead of the HTTP client of the subgraph, so the
+//! layers of the other plugins and the traffic shaping still wrap it.
+
+mod client;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use opentelemetry::global::get_text_map_propagator;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use self::client::NatsClient;
+use self::client::RequestError;
+use crate::error::FetchError;
+use crate::graphql;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::subgraph_otel_context;
+use crate::register_plugin;
+use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
+
+pub(crate) const APOLLO_NATS_SUBGRAPHS: &str = "apollo.nats_subgraphs";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Subgraph requests over NATS request/reply
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Conf {
+    /// The URL of the server, such as `nats://localhost:4222`, with the user and password or the
+    /// token of the connection, if any
+    url: url::Url,
+    /// The subgraphs called over NATS, by name
+    subgraphs: HashMap<String, SubgraphConf>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SubgraphConf {
+    /// The subject the requests are published to
+    subject: String,
+    /// The longest wait for the reply of a request
+    /// Default: 30s
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    timeout: Option<Duration>,
+}
+
+pub(crate) struct NatsSubgraphs {
+    client: Arc<NatsClient>,
+    subgraphs: HashMap<String, SubgraphConf>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for NatsSubgraphs {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(NatsSubgraphs {
+            client: Arc::new(NatsClient::new(init.config.url)),
+            subgraphs: init.config.subgraphs,
+        })
+    }
+}
+
+impl NatsSubgraphs {
+    /// The NATS transport of the subgraph, if it is configured
+    pub(crate) fn subgraph_service(&self, name: &str) -> Option<NatsSubgraphService> {
+        let subgraph = self.subgraphs.get(name)?;
+        Some(NatsSubgraphService {
+            service: Arc::new(name.to_string()),
+            subject: Arc::new(subgraph.subject.clone()),
+            timeout: subgraph.timeout.unwrap_or(DEFAULT_TIMEOUT),
+            client: self.client.clone(),
+        })
+    }
+}
+
+/// Client for subgraphs called over NATS request/reply
+#[derive(Clone)]
+pub(crate) struct NatsSubgraphService {
+    service: Arc<String>,
+    subject: Arc<String>,
+    timeout: Duration,
+    client: Arc<NatsClient>,
+}
+
+impl tower::Service<SubgraphRequest> for NatsSubgraphService {
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let SubgraphRequest {
+            subgraph_request,
+            context,
+            ..
+        } = request;
+        let (mut parts, body) = subgraph_request.into_parts();
+        let service_name = self.service.clone();
+        let subject = self.subject.clone();
+        let timeout = self.timeout;
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let payload = serde_json::to_vec(&body).expect("JSON serialization should not fail");
+            // the payload is neither compressed nor sent with a length
+            parts.headers.remove(http::header::CONTENT_ENCODING);
+            parts.headers.remove(http::header::CONTENT_LENGTH);
+
+            let subgraph_req_span = tracing::info_span!("subgraph_request",
+                "otel.kind" = "CLIENT",
+                "messaging.system" = "nats",
+                "messaging.destination" = %subject,
+                "apollo.subgraph.name" = %service_name
+            );
+            get_text_map_propagator(|propagator| {
+                propagator.inject_context(
+                    &subgraph_otel_context(subgraph_req_span.context(), &context),
+                    &mut opentelemetry_http::HeaderInjector(&mut parts.headers),
+                );
+            });
+            let reply = client
+                .request(&subject, &parts.headers, &payload, timeout)
+                .instrument(subgraph_req_span)
+                .await
+                .map_err(|error| match error {
+                    RequestError::Timeout => FetchError::SubrequestTimeout {
+                        service: service_name.to_string(),
+                    },
+                    RequestError::Failed(error) => {
+                        tracing::error!(fetch_error = format!("{error:?}").as_str());
+                        FetchError::SubrequestHttpError {
+                            service: service_name.to_string(),
+                            reason: error.to_string(),
+                        }
+                    }
+                })?;
+
+            let graphql = graphql::Response::from_bytes(&service_name, reply).map_err(|error| {
+                FetchError::SubrequestMalformedResponse {
+                    service: service_name.to_string(),
+                    reason: error.to_string(),
+                }
+            })?;
+            Ok(SubgraphResponse::new_from_response(
+                http::Response::new(graphql),
+                context,
+            ))
+        })
+    }
+}
+
+register_plugin!("apollo", "nats_subgraphs", NatsSubgraphs);
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Checks the headers and the payload of the first request, and replies to it
+    async fn nats_server(listener: TcpListener) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(b"INFO {\"headers\":true}\r\n")
+            .await
+            .unwrap();
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            if line.starts_with("HPUB ") {
+                break;
+            }
+        }
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(parts[1], "accounts.graphql");
+        let reply_to = parts[2].to_string();
+        let header_size: usize = parts[3].parse().unwrap();
+        let size: usize = parts[4].parse().unwrap();
+        let mut message = vec![0; size + 2];
+        reader.read_exact(&mut message).await.unwrap();
+        let headers = String::from_utf8_lossy(&message[..header_size]).to_string();
+        assert!(headers.starts_with("NATS/1.0\r\n"));
+        assert!(headers.contains("x-reply: yes\r\n"));
+        let request: serde_json::Value =
+            serde_json::from_slice(&message[header_size..size]).unwrap();
+        assert_eq!(request["query"], "{ me { id } }");
+
+        let reply = Bytes::from_static(br#"{"data":{"me":{"id":"1"}}}"#);
+        writer
+            .write_all(format!("MSG {reply_to} 1 {}\r\n", reply.len()).as_bytes())
+            .await
+            .unwrap();
+        writer.write_all(&reply).await.unwrap();
+        writer.write_all(b"\r\n").await.unwrap();
+        // keeps the connection open until the client closes it
+        let _ = reader.read_to_end(&mut Vec::new()).await;
+    }
+
+    #[tokio::test]
+    async fn it_calls_subgraphs_over_nats() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(nats_server(listener));
+
+        let plugin = NatsSubgraphs {
+            client: Arc::new(NatsClient::new(url.parse().unwrap())),
+            subgraphs: [(
+                "accounts".to_string(),
+                SubgraphConf {
+                    subject: "accounts.graphql".to_string(),
+                    timeout: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        };
+        assert!(plugin.subgraph_service("products").is_none());
+
+        let request = SubgraphRequest::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .header("x-reply", "yes")
+                    .body(graphql::Request::builder().query("{ me { id } }").build())
+                    .unwrap(),
+            )
+            .build();
+        let response = plugin
+            .subgraph_service("accounts")
+            .unwrap()
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.response.body().data,
+            Some(serde_json_bytes::json!({ "me": { "id": "1" } }))
+        );
+
+        drop(plugin);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_times_out_without_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"INFO {}\r\n").await.unwrap();
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        });
+
+        let client = NatsClient::new(url.parse().unwrap());
+        let error = client
+            .request(
+                "accounts.graphql",
+                &Default::default(),
+                b"{}",
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RequestError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn it_times_out_while_connecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        // the server never sends its greeting
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        });
+
+        let client = NatsClient::new(url.parse().unwrap());
+        let error = client
+            .request(
+                "accounts.graphql",
+                &Default::default(),
+                b"{}",
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RequestError::Timeout));
+    }
+}
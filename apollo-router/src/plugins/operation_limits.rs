@@ -0,0 +1,362 @@
+//! Limits on the operations executed by the router
+//!
+//! Operations can be limited by the depth of their selections, by their estimated cost, and to a
+//! safelist of known operations. Each limit either rejects the operations exceeding it, or only
+//! logs them and counts them in the `apollo_router_operation_limit_exceeded_total` metric, so
+//! that a limit can be tried against production traffic before it is enforced.
+
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::default_parser_recursion_limit;
+use crate::error::Error;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::query_planner::normalization;
+use crate::query_planner::OPERATION_SIGNATURE_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::ExecutionRequest;
+use crate::services::ExecutionResponse;
+use crate::spec::Query;
+
+/// Limits on the operations executed by the router
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Conf {
+    /// Maximum depth of the selections of operations
+    #[serde(default)]
+    depth: Option<Limit>,
+
+    /// Maximum cost of operations: the number of fields they select, counting the fields of
+    /// fragments at each of their uses, plus the number of subgraph fetches of their query plan
+    #[serde(default)]
+    cost: Option<Limit>,
+
+    /// Operations allowed to execute
+    #[serde(default)]
+    safelist: Option<Safelist>,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+}
+
+/// A maximum value
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Limit {
+    /// The maximum value
+    max: usize,
+
+    /// What to do with the operations exceeding the limit
+    #[serde(default)]
+    mode: EnforcementMode,
+}
+
+/// Operations allowed to execute. Documents are compared once normalized, so that their
+/// formatting, comments and the order of arguments don't matter
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Safelist {
+    /// The documents of the allowed operations
+    #[serde(default)]
+    operations: Vec<String>,
+
+    /// Path of a JSON file containing an array of documents of allowed operations
+    #[serde(default)]
+    path: Option<PathBuf>,
+
+    /// What to do with the operations that are not in the safelist
+    #[serde(default)]
+    mode: EnforcementMode,
+}
+
+/// What to do with the operations exceeding a limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum EnforcementMode {
+    /// Reject the operations with an error
+    Enforce,
+    /// Execute the operations, but log them and count them in the
+    /// `apollo_router_operation_limit_exceeded_total` metric
+    Warn,
+}
+
+impl Default for EnforcementMode {
+    fn default() -> Self {
+        EnforcementMode::Enforce
+    }
+}
+
+impl EnforcementMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EnforcementMode::Enforce => "enforce",
+            EnforcementMode::Warn => "warn",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Limits {
+    depth: Option<Limit>,
+    cost: Option<Limit>,
+    /// Normalized documents of the safelist, and its mode
+    safelist: Option<(HashSet<String>, EnforcementMode)>,
+}
+
+#[derive(Debug)]
+struct OperationLimits {
+    limits: Arc<Limits>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for OperationLimits {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(OperationLimits {
+            limits: Arc::new(Limits::new(init.config)?),
+        })
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        let limits = self.limits.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |req: ExecutionRequest| {
+                let errors = limits.check(&req);
+                if errors.is_empty() {
+                    Ok(ControlFlow::Continue(req))
+                } else {
+                    let res = ExecutionResponse::builder()
+                        .errors(errors)
+                        .status_code(StatusCode::BAD_REQUEST)
+                        .context(req.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(res))
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+impl Limits {
+    pub(crate) fn new(config: Conf) -> Result<Self, BoxError> {
+        let parser_recursion_limit = config.parser_recursion_limit;
+        let safelist = match config.safelist {
+            Some(safelist) => {
+                let mut documents = safelist.operations;
+                if let Some(path) = &safelist.path {
+                    let content = std::fs::read_to_string(path).map_err(|e| {
+                        format!("could not read the safelist '{}': {e}", path.display())
+                    })?;
+                    let operations: Vec<String> = serde_json::from_str(&content)
+                        .map_err(|e| format!("invalid safelist '{}': {e}", path.display()))?;
+                    documents.extend(operations);
+                }
+                let normalized = documents
+                    .iter()
+                    .map(|document| {
+                        normalization::normalize(document, None, parser_recursion_limit)
+                            .map(|normalized| normalized.query)
+                            .ok_or_else(|| format!("invalid safelisted operation: {document}"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Some((normalized, safelist.mode))
+            }
+            None => None,
+        };
+        Ok(Limits {
+            depth: config.depth,
+            cost: config.cost,
+            safelist,
+        })
+    }
+
+    /// Returns the errors of the enforced limits exceeded by the operation
+    fn check(&self, request: &ExecutionRequest) -> Vec<Error> {
+        let signature = request
+            .context
+            .get::<_, String>(OPERATION_SIGNATURE_CONTEXT_KEY)
+            .ok()
+            .flatten();
+        self.check_operation(
+            &request.query_plan.query,
+            request.supergraph_request.body().operation_name.as_deref(),
+            request.query_plan.root.service_usage().count(),
+            signature.as_deref(),
+        )
+    }
+
+    /// Returns the errors of the enforced limits exceeded by an operation, whose query plan has
+    /// `fetches` subgraph fetches, and whose normalized document is `signature`
+    pub(crate) fn check_operation(
+        &self,
+        query: &Query,
+        operation_name: Option<&str>,
+        fetches: usize,
+        signature: Option<&str>,
+    ) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        if let Some(limit) = &self.depth {
+            let depth = query.depth(operation_name);
+            if depth > limit.max {
+                exceeded(
+                    limit.mode,
+                    "depth",
+                    "MAX_DEPTH_EXCEEDED",
+                    format!(
+                        "the operation has a depth of {depth}, exceeding the limit of {}",
+                        limit.max
+                    ),
+                    &mut errors,
+                );
+            }
+        }
+
+        if let Some(limit) = &self.cost {
+            let cost = query.field_count(operation_name) + fetches;
+            if cost > limit.max {
+                exceeded(
+                    limit.mode,
+                    "cost",
+                    "MAX_COST_EXCEEDED",
+                    format!(
+                        "the operation has a cost of {cost}, exceeding the limit of {}",
+                        limit.max
+                    ),
+                    &mut errors,
+                );
+            }
+        }
+
+        if let Some((operations, mode)) = &self.safelist {
+            if !signature.map_or(false, |signature| operations.contains(signature)) {
+                exceeded(
+                    *mode,
+                    "safelist",
+                    "OPERATION_NOT_SAFELISTED",
+                    "the operation is not in the safelist".to_string(),
+                    &mut errors,
+                );
+            }
+        }
+
+        errors
+    }
+}
+
+fn exceeded(
+    mode: EnforcementMode,
+    limit: &'static str,
+    code: &'static str,
+    message: String,
+    errors: &mut Vec<Error>,
+) {
+    tracing::info!(
+        monotonic_counter.apollo_router_operation_limit_exceeded_total = 1u64,
+        limit,
+        mode = mode.as_str(),
+    );
+    match mode {
+        EnforcementMode::Enforce => errors.push(
+            Error::builder()
+                .message(message)
+                .extension_code(code)
+                .build(),
+        ),
+        EnforcementMode::Warn => {
+            tracing::warn!(limit, "operation limit exceeded in warn mode: {message}")
+        }
+    }
+}
+
+register_plugin!("apollo", "operation_limits", OperationLimits);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::supergraph;
+
+    async fn errors(configuration: serde_json::Value) -> Vec<Error> {
+        let service = crate::TestHarness::builder()
+            .configuration_json(json!({ "operation_limits": configuration }))
+            .unwrap()
+            .build_supergraph()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut response = service.oneshot(request).await.unwrap();
+        response.response.body_mut().next().await.unwrap().errors
+    }
+
+    #[tokio::test]
+    async fn it_enforces_limits() {
+        // the canned operation has a depth of 4, 10 fields and 4 fetches
+        assert!(
+            errors(json!({ "depth": { "max": 4 }, "cost": { "max": 14 } }))
+                .await
+                .is_empty()
+        );
+
+        let errors = errors(json!({ "depth": { "max": 3 }, "cost": { "max": 13 } })).await;
+        let codes: Vec<_> = errors
+            .iter()
+            .map(|error| error.extensions.get("code").and_then(|code| code.as_str()))
+            .collect();
+        assert_eq!(
+            codes,
+            [Some("MAX_DEPTH_EXCEEDED"), Some("MAX_COST_EXCEEDED")]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_only_logs_limits_in_warn_mode() {
+        assert!(errors(json!({
+            "depth": { "max": 1, "mode": "warn" },
+            "safelist": { "operations": ["{ me { id } }"], "mode": "warn" }
+        }))
+        .await
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_rejects_operations_outside_of_the_safelist() {
+        let canned = "query TopProducts($first: Int) {
+            topProducts(first: $first) {
+                upc, name
+                reviews { id product { name } author { id name } }
+            }
+        }";
+        assert!(errors(json!({ "safelist": { "operations": [canned] } }))
+            .await
+            .is_empty());
+
+        let errors = errors(json!({ "safelist": { "operations": ["{ me { id } }"] } })).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0]
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str()),
+            Some("OPERATION_NOT_SAFELISTED")
+        );
+    }
+}
@@ -0,0 +1,487 @@
+//! Rules allowing or denying operations
+//!
+//! Operations are matched against the rules before they are planned, by their name, their kind,
+//! the name of the client sending them, and the path of the request. The first matching rule
+//! decides whether the operation is allowed, so that a read-only path can deny mutations, or a
+//! legacy client can be restricted to some operations.
+//!
+//! When the requested operation can't be determined, because the document doesn't parse or
+//! doesn't contain it, the rules with operation conditions deny it instead of being skipped, so
+//! that a document crafted to fail parsing here can't reach a less restricted rule.
+//!
+//! Operations can also be required to be named, so that they can be told apart in logs and
+//! metrics keyed by operation name.
+
+use std::ops::ControlFlow;
+
+use apollo_parser::ast;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::default_parser_recursion_limit;
+use crate::error::Error;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::register_plugin;
+use crate::services::supergraph;
+use crate::services::SupergraphResponse;
+
+/// Rules allowing or denying operations
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Conf {
+    /// Rules evaluated in order: the first rule matching an operation decides whether it is
+    /// allowed
+    #[serde(default)]
+    rules: Vec<Rule>,
+
+    /// What to do with the operations that don't match any rule
+    #[serde(default)]
+    default: Action,
+
+    /// Requirements on the names of operations, checked before the rules
+    #[serde(default)]
+    naming: Naming,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+}
+
+/// Requirements on the names of operations
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Naming {
+    /// Reject anonymous operations
+    #[serde(default)]
+    require_named_operations: bool,
+
+    /// Reject the requests without an `operationName` when their document contains several
+    /// operations, instead of executing the first one
+    #[serde(default)]
+    require_operation_name: bool,
+}
+
+/// A rule matching the operations meeting all of its conditions. Empty conditions match all
+/// operations
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// What to do with the matching operations
+    action: Action,
+
+    /// Names of the operations. A name ending with `*` matches all the names starting with it.
+    /// Anonymous operations have an empty name
+    #[serde(default)]
+    operation_names: Vec<String>,
+
+    /// Kinds of the operations
+    #[serde(default)]
+    kinds: Vec<Kind>,
+
+    /// Names of the clients, as sent in the client name header of Apollo telemetry. A name
+    /// ending with `*` matches all the names starting with it
+    #[serde(default)]
+    client_names: Vec<String>,
+
+    /// Paths of the requests. A path ending with `*` matches all the paths starting with it
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+/// What to do with an operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    /// Execute the operation
+    Allow,
+    /// Reject the operation with an error
+    Deny,
+}
+
+impl Default for Action {
+    fn default() -> Self {
+        Action::Allow
+    }
+}
+
+/// The kind of an operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    /// Queries, including the anonymous shorthand form
+    Query,
+    /// Mutations
+    Mutation,
+    /// Subscriptions
+    Subscription,
+}
+
+/// The requested operation of a document
+#[derive(Debug, PartialEq, Eq)]
+struct Operation {
+    kind: Kind,
+    name: String,
+}
+
+impl Operation {
+    /// Finds the requested operation, if the document parses and contains it
+    fn find(query: &str, operation_name: Option<&str>, recursion_limit: usize) -> Option<Self> {
+        let mut operations = Self::parse(query, recursion_limit)?.into_iter();
+        match operation_name {
+            Some(operation_name) => operations.find(|operation| operation.name == operation_name),
+            None => match (operations.next(), operations.next()) {
+                (Some(operation), None) => Some(operation),
+                _ => None,
+            },
+        }
+    }
+
+    /// The operations of a document, if it parses
+    fn parse(query: &str, recursion_limit: usize) -> Option<Vec<Self>> {
+        let tree = apollo_parser::Parser::new(query)
+            .recursion_limit(recursion_limit)
+            .parse();
+        if tree.errors().next().is_some() {
+            return None;
+        }
+        let operations = tree
+            .document()
+            .definitions()
+            .filter_map(|definition| match definition {
+                ast::Definition::OperationDefinition(operation) => Some(operation),
+                _ => None,
+            })
+            .map(|operation| {
+                let kind = operation
+                    .operation_type()
+                    .and_then(|op| {
+                        op.query_token()
+                            .map(|_| Kind::Query)
+                            .or_else(|| op.mutation_token().map(|_| Kind::Mutation))
+                            .or_else(|| op.subscription_token().map(|_| Kind::Subscription))
+                    })
+                    .unwrap_or(Kind::Query);
+                let name = operation
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default();
+                Operation { kind, name }
+            })
+            .collect();
+        Some(operations)
+    }
+}
+
+fn matches(patterns: &[String], value: &str) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => value.starts_with(prefix),
+                None => pattern == value,
+            })
+}
+
+impl Rule {
+    fn needs_operation(&self) -> bool {
+        !self.operation_names.is_empty() || !self.kinds.is_empty()
+    }
+
+    /// Whether the rule matches the request, or `None` if that depends on an operation that
+    /// can't be determined
+    fn matches(
+        &self,
+        operation: Option<&Operation>,
+        client_name: &str,
+        path: &str,
+    ) -> Option<bool> {
+        if !matches(&self.client_names, client_name) || !matches(&self.paths, path) {
+            return Some(false);
+        }
+        if !self.needs_operation() {
+            return Some(true);
+        }
+        operation.map(|operation| {
+            matches(&self.operation_names, &operation.name)
+                && (self.kinds.is_empty() || self.kinds.contains(&operation.kind))
+        })
+    }
+}
+
+impl Naming {
+    fn is_required(&self) -> bool {
+        self.require_named_operations || self.require_operation_name
+    }
+
+    /// Returns the error of the first requirement the request does not meet
+    fn check(&self, request: &supergraph::Request, recursion_limit: usize) -> Option<Error> {
+        if !self.is_required() {
+            return None;
+        }
+        let body = request.supergraph_request.body();
+        let operations = Operation::parse(body.query.as_deref()?, recursion_limit)?;
+        let operation_name = body.operation_name.as_deref();
+
+        if self.require_operation_name && operation_name.is_none() && operations.len() > 1 {
+            return Some(
+                Error::builder()
+                    .message("the document contains several operations, the request must name the operation to execute in `operationName`")
+                    .extension_code("OPERATION_NAME_REQUIRED")
+                    .build(),
+            );
+        }
+        // like the query planner, the first operation is executed if the request does not
+        // name one
+        let operation = match operation_name {
+            Some(operation_name) => operations
+                .iter()
+                .find(|operation| operation.name == operation_name),
+            None => operations.first(),
+        };
+        if self.require_named_operations && operation.map_or(false, |op| op.name.is_empty()) {
+            return Some(
+                Error::builder()
+                    .message("anonymous operations are not allowed, the operation must be named")
+                    .extension_code("ANONYMOUS_OPERATION")
+                    .build(),
+            );
+        }
+        None
+    }
+}
+
+impl Conf {
+    /// Returns the status and the error of the request if it is rejected, by the naming
+    /// requirements or by the rules
+    pub(crate) fn check(&self, request: &supergraph::Request) -> Option<(StatusCode, Error)> {
+        if let Some(error) = self.naming.check(request, self.parser_recursion_limit) {
+            return Some((StatusCode::BAD_REQUEST, error));
+        }
+        match self.action(request) {
+            Action::Allow => None,
+            Action::Deny => Some((
+                StatusCode::FORBIDDEN,
+                Error::builder()
+                    .message("the operation is not allowed")
+                    .extension_code("OPERATION_NOT_ALLOWED")
+                    .build(),
+            )),
+        }
+    }
+
+    /// Returns the action of the first rule matching the request. A rule that may match an
+    /// operation that can't be determined denies it
+    fn action(&self, request: &supergraph::Request) -> Action {
+        let body = request.supergraph_request.body();
+        let operation = if self.rules.iter().any(Rule::needs_operation) {
+            body.query.as_deref().and_then(|query| {
+                Operation::find(
+                    query,
+                    body.operation_name.as_deref(),
+                    self.parser_recursion_limit,
+                )
+            })
+        } else {
+            None
+        };
+        let client_name = request
+            .context
+            .get::<_, String>(CLIENT_NAME)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let path = request.supergraph_request.uri().path();
+
+        self.rules
+            .iter()
+            .find_map(
+                |rule| match rule.matches(operation.as_ref(), &client_name, path) {
+                    Some(true) => Some(rule.action),
+                    Some(false) => None,
+                    None => Some(Action::Deny),
+                },
+            )
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug)]
+struct OperationRules {
+    config: Conf,
+}
+
+#[async_trait::async_trait]
+impl Plugin for OperationRules {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(OperationRules {
+            config: init.config,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let config = self.config.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |req: supergraph::Request| match config.check(&req) {
+                None => Ok(ControlFlow::Continue(req)),
+                Some((status, error)) => {
+                    let res = SupergraphResponse::builder()
+                        .error(error)
+                        .status_code(status)
+                        .context(req.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(res))
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "operation_rules", OperationRules);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use super::*;
+
+    async fn status(configuration: serde_json::Value, client_name: &str) -> StatusCode {
+        let service = crate::TestHarness::builder()
+            .configuration_json(json!({ "operation_rules": configuration }))
+            .unwrap()
+            .build_supergraph()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder()
+            .header("apollographql-client-name", client_name)
+            .build()
+            .unwrap();
+        let mut response = service.oneshot(request).await.unwrap();
+        let status = response.response.status();
+        response.response.body_mut().next().await.unwrap();
+        status
+    }
+
+    #[test]
+    fn it_finds_the_requested_operation() {
+        let query = "query A { me { id } } mutation B { login }";
+        assert_eq!(
+            Operation::find(query, Some("B"), default_parser_recursion_limit()),
+            Some(Operation {
+                kind: Kind::Mutation,
+                name: "B".to_string()
+            })
+        );
+        assert_eq!(
+            Operation::find(query, None, default_parser_recursion_limit()),
+            None
+        );
+        assert_eq!(
+            Operation::find("{ me { id } }", None, default_parser_recursion_limit()),
+            Some(Operation {
+                kind: Kind::Query,
+                name: String::new()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn it_applies_the_first_matching_rule() {
+        // the canned operation is the `TopProducts` query
+        let rules = json!({
+            "rules": [
+                { "action": "deny", "kinds": ["mutation"] },
+                { "action": "allow", "operation_names": ["Top*"], "client_names": ["web"] },
+            ],
+            "default": "deny"
+        });
+        assert_eq!(status(rules.clone(), "web").await, StatusCode::OK);
+        assert_eq!(status(rules, "legacy").await, StatusCode::FORBIDDEN);
+
+        let rules = json!({ "rules": [{ "action": "deny", "kinds": ["query"] }] });
+        assert_eq!(status(rules, "web").await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn it_denies_operations_that_cannot_be_determined() {
+        let config: Conf = serde_json::from_value(json!({
+            "rules": [
+                { "action": "allow", "client_names": ["web"] },
+                { "action": "deny", "operation_names": ["Admin*"] },
+            ],
+            "parser_recursion_limit": 6
+        }))
+        .unwrap();
+        let action = |client_name: &str, query: &str, operation_name: Option<&str>| {
+            let request = supergraph::Request::fake_builder()
+                .query(query)
+                .and_operation_name(operation_name)
+                .build()
+                .unwrap();
+            request
+                .context
+                .insert(CLIENT_NAME, client_name.to_string())
+                .unwrap();
+            config.action(&request)
+        };
+
+        assert_eq!(action("web", "{ me { id } ", None), Action::Allow);
+        assert_eq!(action("ios", "{ me { id } }", None), Action::Allow);
+        assert_eq!(action("ios", "{ me { id } ", None), Action::Deny);
+        assert_eq!(
+            action("ios", "query A { me } query B { me }", None),
+            Action::Deny
+        );
+        assert_eq!(action("ios", "{ me { id } }", Some("Other")), Action::Deny);
+        // deeper than the configured recursion limit
+        assert_eq!(
+            action(
+                "ios",
+                "{ me { reviews { author { reviews { author { name } } } } } }",
+                None
+            ),
+            Action::Deny
+        );
+    }
+
+    #[test]
+    fn it_requires_operation_names() {
+        let naming = Naming {
+            require_named_operations: true,
+            require_operation_name: true,
+        };
+        let check = |query: &str, operation_name: Option<&str>| {
+            let request = supergraph::Request::fake_builder()
+                .query(query)
+                .and_operation_name(operation_name)
+                .build()
+                .unwrap();
+            naming
+                .check(&request, default_parser_recursion_limit())
+                .and_then(|error| error.extensions.get("code").cloned())
+        };
+
+        assert_eq!(check("query Me { me { id } }", None), None);
+        assert_eq!(
+            check("{ me { id } }", None),
+            Some("ANONYMOUS_OPERATION".into())
+        );
+        let document = "query A { me { id } } query B { me { name } }";
+        assert_eq!(check(document, Some("B")), None);
+        assert_eq!(
+            check(document, None),
+            Some("OPERATION_NAME_REQUIRED".into())
+        );
+    }
+}
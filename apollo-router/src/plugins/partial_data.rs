@@ -0,0 +1,344 @@
+//! Policies for responses with partial data
+//!
+//! When a subgraph fetch fails in the middle of a query plan, the router returns the data it could
+//! get, with errors. Some clients cannot handle partial data: this plugin can instead fail the
+//! whole response, or replace the fields left null with default values, depending on the client
+//! name of the request, read from the header configured in `telemetry.apollo`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::json_ext::ValueExt;
+use crate::layers::ServiceExt as _;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::register_plugin;
+use crate::services::supergraph;
+
+/// Policies for responses with partial data
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Policy applied to clients not listed in `clients`
+    #[serde(default)]
+    all: PolicyConfig,
+
+    /// Policies by client name
+    #[serde(default)]
+    clients: HashMap<String, PolicyConfig>,
+}
+
+/// Handling of responses with partial data
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct PolicyConfig {
+    /// What to do with the data of responses with errors
+    #[serde(default)]
+    mode: PartialDataMode,
+
+    /// Values replacing null fields in the `defaults` mode, by response path, such as
+    /// `topProducts/@/reviews`. `@` matches every element of a list
+    #[serde(default)]
+    defaults: HashMap<String, serde_json::Value>,
+}
+
+/// What to do with the data of responses with errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PartialDataMode {
+    /// Return the partial data with the errors
+    Partial,
+    /// Return null data with the errors
+    Fail,
+    /// Replace the fields left null with the configured defaults
+    Defaults,
+}
+
+impl Default for PartialDataMode {
+    fn default() -> Self {
+        PartialDataMode::Partial
+    }
+}
+
+#[derive(Debug)]
+struct Policy {
+    mode: PartialDataMode,
+    defaults: Vec<(Path, Value)>,
+}
+
+impl From<PolicyConfig> for Policy {
+    fn from(config: PolicyConfig) -> Self {
+        Policy {
+            mode: config.mode,
+            defaults: config
+                .defaults
+                .into_iter()
+                .map(|(path, value)| (Path::from(path.trim_start_matches('/')), value.into()))
+                .collect(),
+        }
+    }
+}
+
+impl Policy {
+    fn apply(&self, response: &mut graphql::Response) {
+        match self.mode {
+            PartialDataMode::Partial => {}
+            PartialDataMode::Fail => {
+                // responses without data are request errors, they are kept as they are
+                if !response.errors.is_empty() && response.data.is_some() {
+                    response.data = Some(Value::Null);
+                }
+                for incremental in &mut response.incremental {
+                    if !incremental.errors.is_empty() {
+                        incremental.data = Some(Value::Null);
+                    }
+                }
+            }
+            PartialDataMode::Defaults => {
+                if !response.errors.is_empty() {
+                    if let Some(data) = &mut response.data {
+                        self.substitute(data, &Path::default());
+                    }
+                }
+                for incremental in &mut response.incremental {
+                    if incremental.errors.is_empty() {
+                        continue;
+                    }
+                    if let Some(data) = &mut incremental.data {
+                        let base = incremental.path.clone().unwrap_or_default();
+                        self.substitute(data, &base);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the null values of `data`, located at `base` in the response, with the defaults
+    fn substitute(&self, data: &mut Value, base: &Path) {
+        for (path, default) in &self.defaults {
+            let path = match relative_path(path, base) {
+                Some(path) => path,
+                None => continue,
+            };
+            let mut nulls = Vec::new();
+            data.select_values_and_paths(&path, |path, value| {
+                if value.is_null() {
+                    nulls.push(path.clone());
+                }
+            });
+            for path in nulls {
+                let _ = data.insert(&path, default.clone());
+            }
+        }
+    }
+}
+
+/// Returns the part of `path` under `base`, where `@` matches any list index of `base`
+fn relative_path(path: &Path, base: &Path) -> Option<Path> {
+    if path.len() <= base.len() {
+        return None;
+    }
+    for (element, base_element) in path.iter().zip(base.iter()) {
+        match (element, base_element) {
+            (PathElement::Flatten, PathElement::Index(_)) => {}
+            (element, base_element) if element == base_element => {}
+            _ => return None,
+        }
+    }
+    Some(path.iter().skip(base.len()).cloned().collect())
+}
+
+#[derive(Debug)]
+struct PartialData {
+    all: Arc<Policy>,
+    clients: HashMap<String, Arc<Policy>>,
+}
+
+impl PartialData {
+    fn policy(&self, request: &supergraph::Request) -> Arc<Policy> {
+        // the client name is set by the telemetry plugin
+        request
+            .context
+            .get::<_, String>(CLIENT_NAME)
+            .ok()
+            .flatten()
+            .and_then(|name| self.clients.get(&name))
+            .unwrap_or(&self.all)
+            .clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for PartialData {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(PartialData {
+            all: Arc::new(init.config.all.into()),
+            clients: init
+                .config
+                .clients
+                .into_iter()
+                .map(|(name, policy)| (name, Arc::new(policy.into())))
+                .collect(),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.all.mode == PartialDataMode::Partial
+            && self
+                .clients
+                .values()
+                .all(|policy| policy.mode == PartialDataMode::Partial)
+        {
+            return service;
+        }
+        let plugin = Arc::new(PartialData {
+            all: self.all.clone(),
+            clients: self.clients.clone(),
+        });
+        service
+            .map_future_with_request_data(
+                move |request: &supergraph::Request| plugin.policy(request),
+                |policy: Arc<Policy>, future| async move {
+                    let response: supergraph::Response = future.await?;
+                    if policy.mode == PartialDataMode::Partial {
+                        return Ok(response);
+                    }
+                    Ok(response.map_stream(move |mut response| {
+                        policy.apply(&mut response);
+                        response
+                    }))
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "partial_data", PartialData);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::services::execution;
+
+    fn policy(config: serde_json::Value) -> Policy {
+        serde_json::from_value::<PolicyConfig>(config)
+            .unwrap()
+            .into()
+    }
+
+    fn partial_response() -> graphql::Response {
+        graphql::Response::builder()
+            .data(json!({
+                "topProducts": [
+                    { "name": "Table", "reviews": null },
+                    { "name": "Chair", "reviews": [{ "body": "Comfy" }] },
+                ]
+            }))
+            .errors(vec![graphql::Error::builder()
+                .message("Fetch error")
+                .path(Path::from("topProducts/@"))
+                .extension_code("SUBREQUEST_HTTP_ERROR")
+                .build()])
+            .build()
+    }
+
+    #[test]
+    fn it_fails_responses_with_errors() {
+        let mut response = partial_response();
+        policy(serde_json::json!({ "mode": "fail" })).apply(&mut response);
+        assert_eq!(response.data, Some(Value::Null));
+        assert_eq!(response.errors.len(), 1);
+
+        let mut response = graphql::Response::builder()
+            .errors(vec![graphql::Error::builder()
+                .message("invalid")
+                .extension_code("GRAPHQL_VALIDATION_FAILED")
+                .build()])
+            .build();
+        policy(serde_json::json!({ "mode": "fail" })).apply(&mut response);
+        assert_eq!(response.data, None);
+    }
+
+    #[test]
+    fn it_substitutes_default_values() {
+        let policy = policy(serde_json::json!({
+            "mode": "defaults",
+            "defaults": { "topProducts/@/reviews": [] }
+        }));
+
+        let mut response = partial_response();
+        policy.apply(&mut response);
+        assert_eq!(
+            response.data,
+            Some(json!({
+                "topProducts": [
+                    { "name": "Table", "reviews": [] },
+                    { "name": "Chair", "reviews": [{ "body": "Comfy" }] },
+                ]
+            }))
+        );
+
+        let mut response = graphql::Response::builder()
+            .incremental(vec![graphql::IncrementalResponse::builder()
+                .data(json!({ "reviews": null }))
+                .path(Path::from("topProducts/0"))
+                .errors(vec![graphql::Error::builder()
+                    .message("error")
+                    .extension_code("SUBREQUEST_HTTP_ERROR")
+                    .build()])
+                .build()])
+            .build();
+        policy.apply(&mut response);
+        assert_eq!(response.incremental[0].data, Some(json!({ "reviews": [] })));
+    }
+
+    #[tokio::test]
+    async fn it_selects_the_policy_of_the_client() {
+        let service = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "partial_data": { "clients": { "strict": { "mode": "fail" } } }
+            }))
+            .unwrap()
+            .execution_hook(|service: execution::BoxService| {
+                service
+                    .map_first_graphql_response(|_context, http_parts, mut graphql_response| {
+                        graphql_response.errors.push(
+                            graphql::Error::builder()
+                                .message("error")
+                                .extension_code("SUBREQUEST_HTTP_ERROR")
+                                .build(),
+                        );
+                        (http_parts, graphql_response)
+                    })
+                    .boxed()
+            })
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        for (client, failed) in [("strict", true), ("lenient", false)] {
+            let request = supergraph::Request::canned_builder()
+                .header("apollographql-client-name", client)
+                .build()
+                .unwrap();
+            let mut response = service.clone().oneshot(request).await.unwrap();
+            let response = response.response.body_mut().next().await.unwrap();
+            assert_eq!(response.data == Some(Value::Null), failed);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Performance data in response extensions
+//!
+//! Client teams investigating slow operations usually need the router logs or traces. This plugin
+//! adds the estimated cost of the operation, the time spent in each subgraph and the cache hits
+//! and misses of the request to the `performance` extension of responses, for the requests sent
+//! with a debug header.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use http::header::HeaderName;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::json;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::layers::ServiceExt as _;
+use crate::plugin::serde::deserialize_header_name;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::query_planner::QUERY_PLAN_CACHE_HIT_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::Context;
+
+const ENABLED_CONTEXT_KEY: &str = "apollo_router::performance_extensions::enabled";
+const COST_CONTEXT_KEY: &str = "apollo_router::performance_extensions::cost";
+const SUBGRAPHS_CONTEXT_KEY: &str = "apollo_router::performance_extensions::subgraphs";
+const PERSISTED_QUERY_HIT_CONTEXT_KEY: &str = "persisted_query_hit";
+
+const fn header_default_str() -> &'static str {
+    "apollo-router-debug"
+}
+
+const fn header_default() -> HeaderName {
+    HeaderName::from_static(header_default_str())
+}
+
+/// Performance data in response extensions
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Add the `performance` extension to the responses of requests with the debug header
+    /// default: false
+    #[serde(default)]
+    enabled: bool,
+
+    /// The name of the debug header
+    #[schemars(with = "Option<String>", default = "header_default_str")]
+    #[serde(
+        deserialize_with = "deserialize_header_name",
+        default = "header_default"
+    )]
+    header: HeaderName,
+
+    /// The value the debug header must have, such as a shared secret. Any value is accepted if
+    /// not set
+    #[serde(default)]
+    value: Option<String>,
+}
+
+/// Estimated cost of an operation
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct Cost {
+    /// Number of fields selected by the operation
+    fields: usize,
+    /// Number of subgraph fetches in the query plan
+    fetches: usize,
+}
+
+impl Cost {
+    pub(super) fn new(request: &execution::Request) -> Self {
+        let operation_name = request.supergraph_request.body().operation_name.as_deref();
+        Cost {
+            fields: request.query_plan.query.field_count(operation_name),
+            fetches: request.query_plan.root.service_usage().count(),
+        }
+    }
+}
+
+/// Time spent in the requests to a subgraph
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct SubgraphTiming {
+    pub(super) requests: u64,
+    pub(super) duration_ms: f64,
+}
+
+/// Adds the duration of a subgraph request to the timings stored in a context entry
+pub(super) fn record_subgraph_timing(
+    context: &Context,
+    key: &str,
+    subgraph: &str,
+    duration_ms: f64,
+) {
+    let _ = context.upsert(key, |mut subgraphs: HashMap<String, SubgraphTiming>| {
+        let timing = subgraphs.entry(subgraph.to_string()).or_default();
+        timing.requests += 1;
+        timing.duration_ms += duration_ms;
+        subgraphs
+    });
+}
+
+/// Hits and misses of the caches used by a request
+pub(super) fn cache_statuses(context: &Context) -> Value {
+    let cache_status = |hit: Option<bool>| {
+        hit.map(|hit| Value::from(if hit { "hit" } else { "miss" }))
+            .unwrap_or(Value::Null)
+    };
+    let mut cache = serde_json_bytes::Map::new();
+    cache.insert(
+        "query_plan",
+        cache_status(context.get(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY).ok().flatten()),
+    );
+    if let Ok(Some(hit)) = context.get(PERSISTED_QUERY_HIT_CONTEXT_KEY) {
+        cache.insert("persisted_query", cache_status(Some(hit)));
+    }
+    Value::Object(cache)
+}
+
+#[derive(Debug)]
+struct PerformanceExtensions {
+    enabled: bool,
+    header: HeaderName,
+    value: Option<String>,
+}
+
+impl PerformanceExtensions {
+    fn is_requested(&self, request: &supergraph::Request) -> bool {
+        match request.supergraph_request.headers().get(&self.header) {
+            Some(value) => self
+                .value
+                .as_deref()
+                .map_or(true, |expected| value.as_bytes() == expected.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+fn is_enabled(context: &Context) -> bool {
+    context
+        .get::<_, bool>(ENABLED_CONTEXT_KEY)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Builds the `performance` extension from the data collected in the context
+fn performance_extension(context: &Context) -> Value {
+    json!({
+        "cost": context.get_json_value(COST_CONTEXT_KEY).unwrap_or(Value::Null),
+        "subgraphs": context
+            .get_json_value(SUBGRAPHS_CONTEXT_KEY)
+            .unwrap_or_else(|| json!({})),
+        "cache": cache_statuses(context),
+    })
+}
+
+#[async_trait::async_trait]
+impl Plugin for PerformanceExtensions {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(PerformanceExtensions {
+            enabled: init.config.enabled,
+            header: init.config.header,
+            value: init.config.value,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if !self.enabled {
+            return service;
+        }
+        let plugin = Arc::new(PerformanceExtensions {
+            enabled: self.enabled,
+            header: self.header.clone(),
+            value: self.value.clone(),
+        });
+        service
+            .map_future_with_request_data(
+                move |request: &supergraph::Request| {
+                    let requested = plugin.is_requested(request);
+                    if requested {
+                        let _ = request.context.insert(ENABLED_CONTEXT_KEY, true);
+                    }
+                    requested
+                },
+                |requested: bool, future| async move {
+                    let response: supergraph::Response = future.await?;
+                    if !requested {
+                        return Ok(response);
+                    }
+                    let context = response.context.clone();
+                    // with @defer, the data is complete in the last response
+                    Ok(response.map_stream(move |mut response| {
+                        if !response.has_next.unwrap_or(false) {
+                            response
+                                .extensions
+                                .insert("performance", performance_extension(&context));
+                        }
+                        response
+                    }))
+                },
+            )
+            .boxed()
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        if !self.enabled {
+            return service;
+        }
+        service
+            .map_request(|request: execution::Request| {
+                if is_enabled(&request.context) {
+                    let _ = request
+                        .context
+                        .insert(COST_CONTEXT_KEY, Cost::new(&request));
+                }
+                request
+            })
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        if !self.enabled {
+            return service;
+        }
+        let name = name.to_string();
+        service
+            .map_future_with_request_data(
+                |request: &subgraph::Request| {
+                    is_enabled(&request.context).then(|| (request.context.clone(), Instant::now()))
+                },
+                move |timing: Option<(Context, Instant)>, future| {
+                    let name = name.clone();
+                    async move {
+                        let response = future.await;
+                        if let Some((context, start)) = timing {
+                            record_subgraph_timing(
+                                &context,
+                                SUBGRAPHS_CONTEXT_KEY,
+                                &name,
+                                start.elapsed().as_secs_f64() * 1000.0,
+                            );
+                        }
+                        response
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "performance_extensions", PerformanceExtensions);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_adds_performance_extensions_to_debug_requests() {
+        let service = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "performance_extensions": { "enabled": true, "value": "secret" }
+            }))
+            .unwrap()
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        for (value, cache) in [("secret", "miss"), ("secret", "hit"), ("invalid", "")] {
+            let request = supergraph::Request::canned_builder()
+                .header("apollo-router-debug", value)
+                .build()
+                .unwrap();
+            let mut response = service.clone().oneshot(request).await.unwrap();
+            let response = response.response.body_mut().next().await.unwrap();
+            let performance = match response.extensions.get("performance") {
+                Some(performance) => serde_json::to_value(performance).unwrap(),
+                None => {
+                    assert_eq!(value, "invalid");
+                    continue;
+                }
+            };
+            assert_eq!(
+                performance["cost"],
+                serde_json::json!({ "fields": 10, "fetches": 4 })
+            );
+            assert_eq!(
+                performance["cache"],
+                serde_json::json!({ "query_plan": cache })
+            );
+            assert_eq!(performance["subgraphs"]["products"]["requests"], 2);
+        }
+    }
+}
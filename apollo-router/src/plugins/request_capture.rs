@@ -0,0 +1,502 @@
+//! Capture of full request exchanges, for debugging
+//!
+//! The next requests matching a filter are captured with the requests and responses of all their
+//! subgraph fetches, after applying the redaction rules, and written as JSON files in a directory
+//! or sent to an endpoint. Once the configured number of exchanges is captured, the plugin stops
+//! capturing until the router reloads.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use futures::stream;
+use futures::StreamExt;
+use http::HeaderMap;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::CanaryHeader;
+use crate::configuration::Redaction;
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::external::externalize_header_map;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::redaction::Redactor;
+use crate::register_plugin;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::services::SupergraphResponse;
+
+const DEFAULT_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Capture of the next requests matching a filter, with their subgraph fetches
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Number of exchanges to capture, until the router reloads
+    #[serde(default = "default_limit")]
+    limit: usize,
+
+    /// Conditions on the captured requests. An empty filter captures all requests
+    #[serde(default)]
+    filter: Filter,
+
+    /// Where the captured exchanges are written
+    output: Output,
+
+    /// Redaction of the captured exchanges
+    #[serde(default)]
+    #[schemars(skip)]
+    redaction: Redaction,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+/// Conditions on the captured requests, all of which must match
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Filter {
+    /// Name of the operation
+    operation_name: Option<String>,
+
+    /// Name of the client, as sent in the client name header of Apollo telemetry
+    client_name: Option<String>,
+
+    /// Header of the request
+    header: Option<CanaryHeader>,
+}
+
+/// Destination of the captured exchanges
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum Output {
+    /// Directory where each exchange is written in its own JSON file
+    Directory(PathBuf),
+    /// URL receiving each exchange in a POST request, in JSON
+    Endpoint(url::Url),
+}
+
+impl Filter {
+    fn matches(&self, request: &supergraph::Request) -> bool {
+        let http_request = &request.supergraph_request;
+        if let Some(operation_name) = &self.operation_name {
+            if http_request.body().operation_name.as_ref() != Some(operation_name) {
+                return false;
+            }
+        }
+        if let Some(client_name) = &self.client_name {
+            let name = request.context.get::<_, String>(CLIENT_NAME).ok().flatten();
+            if name.as_ref() != Some(client_name) {
+                return false;
+            }
+        }
+        if let Some(header) = &self.header {
+            let matches = http_request
+                .headers()
+                .get(header.name.as_str())
+                .map_or(false, |value| match &header.value {
+                    Some(expected) => value.as_bytes() == expected.as_bytes(),
+                    None => true,
+                });
+            if !matches {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A captured request and its response
+#[derive(Debug, Serialize)]
+struct Exchange {
+    request: CapturedRequest,
+    response: CapturedResponse,
+}
+
+#[derive(Debug, Serialize)]
+struct CapturedRequest {
+    method: String,
+    uri: String,
+    headers: HashMap<String, Vec<String>>,
+    body: graphql::Request,
+}
+
+#[derive(Debug, Serialize)]
+struct CapturedResponse {
+    status: u16,
+    headers: HashMap<String, Vec<String>>,
+    /// The primary response, followed by the incremental responses, if any
+    body: Vec<graphql::Response>,
+}
+
+/// A subgraph fetch of a captured request
+#[derive(Debug, Serialize)]
+struct SubgraphExchange {
+    subgraph: String,
+    #[serde(flatten)]
+    exchange: Exchange,
+}
+
+/// Everything captured for a request
+#[derive(Debug, Serialize)]
+struct Capture {
+    captured_at: u128,
+    #[serde(flatten)]
+    exchange: Exchange,
+    subgraphs: Vec<SubgraphExchange>,
+}
+
+/// Subgraph fetches of a request being captured, stored in the extensions of the supergraph
+/// request so that the subgraph services can find them
+#[derive(Clone, Debug, Default)]
+struct Fetches(Arc<Mutex<Vec<SubgraphExchange>>>);
+
+/// Applies the redaction rules to the captured data
+#[derive(Clone, Debug)]
+struct Recorder {
+    redactor: Option<Arc<Redactor>>,
+}
+
+impl Recorder {
+    fn headers(&self, headers: &HeaderMap) -> HashMap<String, Vec<String>> {
+        let headers = match &self.redactor {
+            Some(redactor) => redactor.headers(headers),
+            None => headers.clone(),
+        };
+        externalize_header_map(&headers).unwrap_or_default()
+    }
+
+    fn request(&self, request: &http::Request<graphql::Request>) -> CapturedRequest {
+        CapturedRequest {
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            headers: self.headers(request.headers()),
+            body: match &self.redactor {
+                Some(redactor) => redactor.request(request.body()),
+                None => request.body().clone(),
+            },
+        }
+    }
+
+    fn response(
+        &self,
+        parts: &http::response::Parts,
+        body: &[graphql::Response],
+    ) -> CapturedResponse {
+        CapturedResponse {
+            status: parts.status.as_u16(),
+            headers: self.headers(&parts.headers),
+            body: body
+                .iter()
+                .map(|response| match &self.redactor {
+                    Some(redactor) => redactor.response(response),
+                    None => response.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Subgraph responses are left out when response fields are redacted, as the redacted paths
+    /// are relative to the responses sent to clients
+    fn subgraph_response(
+        &self,
+        parts: &http::response::Parts,
+        body: &graphql::Response,
+    ) -> CapturedResponse {
+        match &self.redactor {
+            Some(redactor) if redactor.redacts_response_fields() => CapturedResponse {
+                status: parts.status.as_u16(),
+                headers: self.headers(&parts.headers),
+                body: Vec::new(),
+            },
+            _ => self.response(parts, std::slice::from_ref(body)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RequestCapture {
+    filter: Filter,
+    output: Arc<Output>,
+    remaining: Arc<AtomicUsize>,
+    recorder: Recorder,
+}
+
+impl RequestCapture {
+    /// Claims one of the remaining captures
+    fn claim(remaining: &AtomicUsize) -> bool {
+        remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok()
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for RequestCapture {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        if let Output::Directory(directory) = &init.config.output {
+            std::fs::create_dir_all(directory)?;
+        }
+        Ok(RequestCapture {
+            recorder: Recorder {
+                redactor: Redactor::new(&init.config.redaction)?,
+            },
+            filter: init.config.filter,
+            output: Arc::new(init.config.output),
+            remaining: Arc::new(AtomicUsize::new(init.config.limit)),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let filter = self.filter.clone();
+        let remaining = self.remaining.clone();
+        let output = self.output.clone();
+        let request_recorder = self.recorder.clone();
+        let response_recorder = self.recorder.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |mut req: supergraph::Request| {
+                if filter.matches(&req) && Self::claim(&remaining) {
+                    req.supergraph_request
+                        .extensions_mut()
+                        .insert(Fetches::default());
+                }
+                Ok(ControlFlow::Continue(req))
+            })
+            .map_future_with_request_data(
+                move |req: &supergraph::Request| {
+                    req.supergraph_request
+                        .extensions()
+                        .get::<Fetches>()
+                        .map(|fetches| {
+                            (
+                                fetches.clone(),
+                                request_recorder.request(&req.supergraph_request),
+                            )
+                        })
+                },
+                move |capture: Option<(Fetches, CapturedRequest)>, future| {
+                    let recorder = response_recorder.clone();
+                    let output = output.clone();
+                    async move {
+                        let response: supergraph::Response = future.await?;
+                        let (fetches, request) = match capture {
+                            Some(capture) => capture,
+                            None => return Ok(response),
+                        };
+
+                        // the whole response is needed to capture the subgraph fetches of the
+                        // deferred fragments
+                        let SupergraphResponse { response, context } = response;
+                        let (parts, body) = response.into_parts();
+                        let responses: Vec<graphql::Response> = body.collect().await;
+                        let capture = Capture {
+                            captured_at: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map(|elapsed| elapsed.as_millis())
+                                .unwrap_or_default(),
+                            exchange: Exchange {
+                                request,
+                                response: recorder.response(&parts, &responses),
+                            },
+                            subgraphs: std::mem::take(
+                                &mut *fetches.0.lock().expect("lock poisoned"),
+                            ),
+                        };
+                        tokio::spawn(async move {
+                            if let Err(e) = write(&output, &capture).await {
+                                tracing::error!("could not write the captured request: {e}");
+                            }
+                        });
+
+                        Ok(SupergraphResponse {
+                            response: http::Response::from_parts(
+                                parts,
+                                stream::iter(responses).boxed(),
+                            ),
+                            context,
+                        })
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let name = name.to_string();
+        let request_recorder = self.recorder.clone();
+        let response_recorder = self.recorder.clone();
+        ServiceBuilder::new()
+            .map_future_with_request_data(
+                move |req: &subgraph::Request| {
+                    req.supergraph_request
+                        .extensions()
+                        .get::<Fetches>()
+                        .map(|fetches| {
+                            (
+                                fetches.clone(),
+                                request_recorder.request(&req.subgraph_request),
+                            )
+                        })
+                },
+                move |capture: Option<(Fetches, CapturedRequest)>, future| {
+                    let name = name.clone();
+                    let recorder = response_recorder.clone();
+                    async move {
+                        let response: subgraph::Response = future.await?;
+                        let (fetches, request) = match capture {
+                            Some(capture) => capture,
+                            None => return Ok(response),
+                        };
+
+                        let subgraph::Response { response, context } = response;
+                        let (parts, body) = response.into_parts();
+                        fetches
+                            .0
+                            .lock()
+                            .expect("lock poisoned")
+                            .push(SubgraphExchange {
+                                subgraph: name,
+                                exchange: Exchange {
+                                    request,
+                                    response: recorder.subgraph_response(&parts, &body),
+                                },
+                            });
+                        Ok(subgraph::Response {
+                            response: http::Response::from_parts(parts, body),
+                            context,
+                        })
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+async fn write(output: &Output, capture: &Capture) -> Result<(), BoxError> {
+    match output {
+        Output::Directory(directory) => {
+            let path = directory.join(format!(
+                "request-{}-{}.json",
+                capture.captured_at,
+                uuid::Uuid::new_v4()
+            ));
+            tokio::fs::write(&path, serde_json::to_vec_pretty(capture)?).await?;
+            tracing::info!("captured request written to {}", path.display());
+        }
+        Output::Endpoint(url) => {
+            reqwest::Client::builder()
+                .timeout(DEFAULT_ENDPOINT_TIMEOUT)
+                .build()?
+                .post(url.clone())
+                .json(capture)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+    Ok(())
+}
+
+register_plugin!("apollo", "experimental_request_capture", RequestCapture);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+    use crate::TestHarness;
+
+    #[tokio::test]
+    async fn it_captures_the_matching_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let products = MockSubgraph::builder()
+            .with_json(
+                json! {{"query": "{topProducts{upc}}"}},
+                json! {{"data": {"topProducts": [{"upc": "1"}]}}},
+            )
+            .build();
+        let service = TestHarness::builder()
+            .configuration_json(json!({
+                "redaction": { "headers": ["^authorization$"] },
+                "experimental_request_capture": {
+                    "limit": 1,
+                    "filter": { "header": { "name": "x-debug", "value": "1" } },
+                    "output": { "directory": dir.path() },
+                },
+            }))
+            .unwrap()
+            .subgraph_mock("products", products)
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        for debug in ["0", "1", "1"] {
+            let request = supergraph::Request::fake_builder()
+                .query("{ topProducts { upc } }")
+                .header("authorization", "secret")
+                .header("x-debug", debug)
+                .build()
+                .unwrap();
+            service
+                .clone()
+                .oneshot(request)
+                .await
+                .unwrap()
+                .next_response()
+                .await
+                .unwrap();
+        }
+
+        // the captures are written in the background
+        let mut files = Vec::new();
+        for _ in 0..50 {
+            files = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            if !files.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(files.len(), 1);
+        let capture: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&files[0]).unwrap()).unwrap();
+        assert_eq!(
+            capture["request"]["headers"]["authorization"],
+            json!(["[REDACTED]"])
+        );
+        assert_eq!(capture["request"]["headers"]["x-debug"], json!(["1"]));
+        assert_eq!(
+            capture["response"]["body"][0]["data"],
+            json!({"topProducts": [{"upc": "1"}]})
+        );
+        assert_eq!(capture["subgraphs"][0]["subgraph"], "products");
+        assert_eq!(
+            capture["subgraphs"][0]["request"]["body"]["query"],
+            "{topProducts{upc}}"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Filtering of response extensions by client
+//!
+//! Responses can carry extensions, such as tracing data, cost or cache hints, or entries added by
+//! plugins. This plugin keeps or removes them depending on the client name and version of the
+//! request, read from the headers configured in `telemetry.apollo`, so that internal tools can
+//! receive all of them while public clients do not.
+
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::layers::ServiceExt as _;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::plugins::telemetry::CLIENT_VERSION;
+use crate::register_plugin;
+use crate::services::supergraph;
+
+/// Filtering of response extensions by client
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Filter applied to clients not listed in `clients`
+    #[serde(default)]
+    all: ExtensionsFilter,
+
+    /// Filters by client. The first matching client applies
+    #[serde(default)]
+    clients: Vec<ClientFilter>,
+}
+
+/// Response extensions sent to clients
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ExtensionsFilter {
+    /// The extensions kept in responses. All extensions are kept if not set
+    #[serde(default)]
+    include: Option<Vec<String>>,
+
+    /// The extensions removed from responses
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl ExtensionsFilter {
+    fn keeps_all(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    fn keeps(&self, key: &str) -> bool {
+        self.include
+            .as_ref()
+            .map_or(true, |include| include.iter().any(|name| name == key))
+            && !self.exclude.iter().any(|name| name == key)
+    }
+
+    fn apply(&self, response: &mut graphql::Response) {
+        if self.keeps_all() {
+            return;
+        }
+        response.extensions = std::mem::take(&mut response.extensions)
+            .into_iter()
+            .filter(|(key, _)| self.keeps(key.as_str()))
+            .collect();
+    }
+}
+
+/// Response extensions sent to a client
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ClientFilter {
+    /// The client name
+    name: String,
+
+    /// The client version. Any version matches if not set
+    #[serde(default)]
+    version: Option<String>,
+
+    /// The extensions kept in responses. All extensions are kept if not set
+    #[serde(default)]
+    include: Option<Vec<String>>,
+
+    /// The extensions removed from responses
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl ClientFilter {
+    fn matches(&self, name: Option<&str>, version: Option<&str>) -> bool {
+        name == Some(self.name.as_str())
+            && self
+                .version
+                .as_deref()
+                .map_or(true, |expected| version == Some(expected))
+    }
+}
+
+#[derive(Debug)]
+struct ResponseExtensions {
+    all: Arc<ExtensionsFilter>,
+    clients: Vec<(ClientFilter, Arc<ExtensionsFilter>)>,
+}
+
+impl ResponseExtensions {
+    fn filter(&self, request: &supergraph::Request) -> Arc<ExtensionsFilter> {
+        // the client name and version are set by the telemetry plugin
+        let name: Option<String> = request.context.get(CLIENT_NAME).ok().flatten();
+        let version: Option<String> = request.context.get(CLIENT_VERSION).ok().flatten();
+        self.clients
+            .iter()
+            .find(|(client, _)| client.matches(name.as_deref(), version.as_deref()))
+            .map_or_else(|| self.all.clone(), |(_, filter)| filter.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for ResponseExtensions {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let clients = init
+            .config
+            .clients
+            .into_iter()
+            .map(|client| {
+                let filter = ExtensionsFilter {
+                    include: client.include.clone(),
+                    exclude: client.exclude.clone(),
+                };
+                (client, Arc::new(filter))
+            })
+            .collect();
+        Ok(ResponseExtensions {
+            all: Arc::new(init.config.all),
+            clients,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.all.keeps_all() && self.clients.is_empty() {
+            return service;
+        }
+        let plugin = Arc::new(ResponseExtensions {
+            all: self.all.clone(),
+            clients: self.clients.clone(),
+        });
+        service
+            .map_future_with_request_data(
+                move |request: &supergraph::Request| plugin.filter(request),
+                |filter: Arc<ExtensionsFilter>, future| async move {
+                    let response: supergraph::Response = future.await?;
+                    if filter.keeps_all() {
+                        return Ok(response);
+                    }
+                    Ok(response.map_stream(move |mut response| {
+                        filter.apply(&mut response);
+                        response
+                    }))
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "response_extensions", ResponseExtensions);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::services::execution;
+
+    async fn extensions(
+        config: serde_json::Value,
+        name: &str,
+        version: &str,
+    ) -> serde_json_bytes::Map<serde_json_bytes::ByteString, serde_json_bytes::Value> {
+        let request = supergraph::Request::canned_builder()
+            .header("apollographql-client-name", name)
+            .header("apollographql-client-version", version)
+            .build()
+            .unwrap();
+        let mut response = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({ "response_extensions": config }))
+            .unwrap()
+            .execution_hook(|service: execution::BoxService| {
+                service
+                    .map_first_graphql_response(|_context, http_parts, mut graphql_response| {
+                        graphql_response
+                            .extensions
+                            .insert("tracing", json!({ "duration": 1 }));
+                        graphql_response
+                            .extensions
+                            .insert("cost", json!({ "estimated": 2 }));
+                        (http_parts, graphql_response)
+                    })
+                    .boxed()
+            })
+            .build_supergraph()
+            .await
+            .unwrap()
+            .oneshot(request)
+            .await
+            .unwrap();
+        response
+            .response
+            .body_mut()
+            .next()
+            .await
+            .unwrap()
+            .extensions
+    }
+
+    #[tokio::test]
+    async fn it_filters_extensions_by_client() {
+        let config = serde_json::json!({
+            "all": { "include": [] },
+            "clients": [
+                { "name": "internal", "version": "2.0", "exclude": ["cost"] },
+                { "name": "internal" }
+            ]
+        });
+
+        let public = extensions(config.clone(), "public", "1.0").await;
+        assert!(public.is_empty());
+
+        let internal = extensions(config.clone(), "internal", "1.0").await;
+        assert!(internal.contains_key("tracing"));
+        assert!(internal.contains_key("cost"));
+
+        let internal = extensions(config, "internal", "2.0").await;
+        assert!(internal.contains_key("tracing"));
+        assert!(!internal.contains_key("cost"));
+    }
+
+    #[test]
+    fn it_applies_include_and_exclude_lists() {
+        let filter = ExtensionsFilter {
+            include: Some(vec!["tracing".to_string(), "cost".to_string()]),
+            exclude: vec!["cost".to_string()],
+        };
+        assert!(filter.keeps("tracing"));
+        assert!(!filter.keeps("cost"));
+        assert!(!filter.keeps("cacheControl"));
+
+        let filter = ExtensionsFilter {
+            include: None,
+            exclude: vec!["cost".to_string()],
+        };
+        assert!(filter.keeps("cacheControl"));
+        assert!(!filter.keeps("cost"));
+    }
+}
@@ -0,0 +1,458 @@
+//! Fields of the `Query` type resolved by the router
+//!
+//! Router fields are declared in the configuration, added to the schema shown by introspection,
+//! and answered from the configuration, the request context or the claims of the JWT token,
+//! without fetching from a subgraph. They are removed from operations before planning, and their
+//! values are added to the data of the response, in the order of the operation.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+use std::ops::Range;
+
+use apollo_parser::ast;
+use apollo_parser::ast::AstNode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::default_parser_recursion_limit;
+use crate::json_ext::Object;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::authentication::JWT_CLAIMS_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::supergraph;
+use crate::services::SupergraphResponse;
+use crate::Configuration;
+use crate::Context;
+
+const APOLLO_ROUTER_FIELDS: &str = "apollo.router_fields";
+const SCALARS: [&str; 5] = ["String", "Int", "Float", "Boolean", "ID"];
+
+/// Fields of the `Query` type resolved by the router
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// The fields, by name
+    #[serde(default)]
+    fields: BTreeMap<String, RouterField>,
+
+    /// Recursion limit of the parser, from `server.experimental_parser_recursion_limit`
+    #[serde(default = "default_parser_recursion_limit")]
+    #[schemars(skip)]
+    parser_recursion_limit: usize,
+}
+
+/// A field resolved by the router
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RouterField {
+    /// The type of the field: a nullable built-in scalar, or a nullable list of them, such as
+    /// `String` or `[Int]`
+    #[serde(rename = "type")]
+    field_type: String,
+
+    /// The description of the field in the schema
+    description: Option<String>,
+
+    /// Where the value of the field comes from
+    value: FieldValue,
+}
+
+/// The value of a router field
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum FieldValue {
+    /// The version of the router
+    RouterVersion,
+    /// A constant value
+    Static(serde_json::Value),
+    /// The value of a context entry, set by a plugin or a script
+    Context(String),
+    /// The value of a claim of the JWT token validated by the authentication plugin
+    Claim(String),
+}
+
+impl FieldValue {
+    fn resolve(&self, context: &Context) -> Value {
+        match self {
+            FieldValue::RouterVersion => Value::from(std::env!("CARGO_PKG_VERSION")),
+            FieldValue::Static(value) => Value::from(value.clone()),
+            FieldValue::Context(key) => context.get(key).ok().flatten().unwrap_or_default(),
+            FieldValue::Claim(claim) => context
+                .get::<_, Object>(JWT_CLAIMS_CONTEXT_KEY)
+                .ok()
+                .flatten()
+                .and_then(|mut claims| claims.remove(claim.as_str()))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Conf {
+    fn validate(&self) -> Result<(), BoxError> {
+        for (name, field) in &self.fields {
+            let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_name || name.starts_with("__") {
+                return Err(format!("'{name}' is not a valid field name").into());
+            }
+            let inner = field
+                .field_type
+                .strip_prefix('[')
+                .and_then(|list| list.strip_suffix(']'))
+                .unwrap_or(&field.field_type);
+            if !SCALARS.contains(&inner) {
+                return Err(format!(
+                    "the type of the field '{name}' must be a nullable built-in scalar or a nullable list of them, found '{}'",
+                    field.field_type
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The extension of the schema declaring the router fields, if any
+pub(crate) fn schema_extension(configuration: &Configuration, query_type: &str) -> Option<String> {
+    let conf: Conf =
+        serde_json::from_value(configuration.plugin_configuration(APOLLO_ROUTER_FIELDS)?).ok()?;
+    if conf.fields.is_empty() {
+        return None;
+    }
+    let mut extension = format!("extend type {query_type} {{\n");
+    for (name, field) in &conf.fields {
+        if let Some(description) = &field.description {
+            let _ = writeln!(
+                extension,
+                "  {}",
+                serde_json::Value::from(description.as_str())
+            );
+        }
+        let _ = writeln!(extension, "  {name}: {}", field.field_type);
+    }
+    extension.push_str("}\n");
+    Some(extension)
+}
+
+/// The router fields selected by an operation
+#[derive(Debug, Default)]
+struct Selected {
+    /// The operation without the router fields
+    query: String,
+    /// The response keys and the names of the router fields
+    fields: Vec<(String, String)>,
+    /// The response keys of the fields of the operation, in order
+    order: Vec<String>,
+    /// Whether the operation only selects router fields
+    only_router_fields: bool,
+}
+
+/// The router field values of a request, and the order of the response keys
+#[derive(Clone, Debug)]
+struct Values {
+    values: Object,
+    order: Vec<String>,
+}
+
+impl Values {
+    fn apply(self, data: &mut Option<Value>) {
+        let data = match data {
+            Some(Value::Object(data)) => data,
+            _ => return,
+        };
+        data.extend(self.values);
+        let mut entries: Vec<(ByteString, Value)> = std::mem::take(data).into_iter().collect();
+        entries.sort_by_key(|(key, _)| {
+            self.order
+                .iter()
+                .position(|ordered| ordered == key.as_str())
+                .unwrap_or(usize::MAX)
+        });
+        data.extend(entries);
+    }
+}
+
+/// Finds the router fields selected directly in the requested query operation
+fn select(conf: &Conf, query: &str, operation_name: Option<&str>) -> Option<Selected> {
+    let tree = apollo_parser::Parser::new(query)
+        .recursion_limit(conf.parser_recursion_limit)
+        .parse();
+    if tree.errors().next().is_some() {
+        return None;
+    }
+    let mut operations = tree
+        .document()
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let operation = match operation_name {
+        Some(operation_name) => operations.into_iter().find(|operation| {
+            operation
+                .name()
+                .map(|name| name.text().to_string())
+                .as_deref()
+                == Some(operation_name)
+        })?,
+        None if operations.len() == 1 => operations.remove(0),
+        None => return None,
+    };
+    let is_query = operation
+        .operation_type()
+        .map_or(true, |op| op.query_token().is_some());
+    if !is_query {
+        return None;
+    }
+
+    let mut selected = Selected {
+        only_router_fields: true,
+        ..Default::default()
+    };
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    for selection in operation.selection_set()?.selections() {
+        let field = match selection {
+            ast::Selection::Field(field) => field,
+            _ => {
+                selected.only_router_fields = false;
+                continue;
+            }
+        };
+        let name = field.name()?.text().to_string();
+        let key = field
+            .alias()
+            .and_then(|alias| alias.name())
+            .map_or_else(|| name.clone(), |alias| alias.text().to_string());
+        selected.order.push(key.clone());
+        let is_router_field = conf.fields.contains_key(&name)
+            && field.arguments().is_none()
+            && field.directives().is_none()
+            && field.selection_set().is_none();
+        if is_router_field {
+            let range = field.syntax().text_range();
+            removed.push(range.start().into()..range.end().into());
+            selected.fields.push((key, name));
+        } else {
+            selected.only_router_fields = false;
+        }
+    }
+    if selected.fields.is_empty() {
+        return None;
+    }
+
+    let mut start = 0;
+    for range in removed {
+        selected.query.push_str(&query[start..range.start]);
+        start = range.end;
+    }
+    selected.query.push_str(&query[start..]);
+    Some(selected)
+}
+
+#[derive(Debug)]
+struct RouterFields {
+    conf: Conf,
+}
+
+#[async_trait::async_trait]
+impl Plugin for RouterFields {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        init.config.validate()?;
+        Ok(RouterFields { conf: init.config })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.conf.fields.is_empty() {
+            return service;
+        }
+        let conf = self.conf.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |mut req: supergraph::Request| {
+                let body = req.supergraph_request.body();
+                let selected = match body
+                    .query
+                    .as_deref()
+                    .and_then(|query| select(&conf, query, body.operation_name.as_deref()))
+                {
+                    Some(selected) => selected,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                let values = selected
+                    .fields
+                    .iter()
+                    .map(|(key, name)| {
+                        let value = conf.fields[name].value.resolve(&req.context);
+                        (ByteString::from(key.as_str()), value)
+                    })
+                    .collect::<Object>();
+                let values = Values {
+                    values,
+                    order: selected.order,
+                };
+
+                if selected.only_router_fields {
+                    let mut data = Some(Value::Object(Object::new()));
+                    values.apply(&mut data);
+                    let res = SupergraphResponse::builder()
+                        .and_data(data)
+                        .context(req.context)
+                        .build()?;
+                    return Ok(ControlFlow::Break(res));
+                }
+
+                req.supergraph_request.body_mut().query = Some(selected.query);
+                req.supergraph_request.extensions_mut().insert(values);
+                Ok(ControlFlow::Continue(req))
+            })
+            .map_future_with_request_data(
+                |req: &supergraph::Request| {
+                    req.supergraph_request.extensions().get::<Values>().cloned()
+                },
+                |values: Option<Values>, future| async move {
+                    let response: supergraph::Response = future.await?;
+                    let mut values = match values {
+                        Some(values) => Some(values),
+                        None => return Ok(response),
+                    };
+                    Ok(response.map_stream(move |mut response| {
+                        if let Some(values) = values.take() {
+                            values.apply(&mut response.data);
+                        }
+                        response
+                    }))
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "router_fields", RouterFields);
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    fn conf() -> Conf {
+        serde_json::from_value(serde_json::json!({
+            "fields": {
+                "routerVersion": { "type": "String", "value": "router_version" },
+                "region": { "type": "String", "value": { "context": "region" } },
+                "flags": { "type": "[String]", "value": { "static": ["a", "b"] } },
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn it_validates_the_fields() {
+        assert!(conf().validate().is_ok());
+        let conf: Conf = serde_json::from_value(serde_json::json!({
+            "fields": { "me": { "type": "User", "value": { "static": null } } }
+        }))
+        .unwrap();
+        assert!(conf.validate().is_err());
+    }
+
+    #[test]
+    fn it_removes_router_fields_from_the_operation() {
+        let selected = select(&conf(), "query Q { me { id } r: region flags }", Some("Q")).unwrap();
+        assert_eq!(selected.query, "query Q { me { id }   }");
+        assert_eq!(
+            selected.fields,
+            vec![
+                ("r".to_string(), "region".to_string()),
+                ("flags".to_string(), "flags".to_string())
+            ]
+        );
+        assert_eq!(selected.order, vec!["me", "r", "flags"]);
+        assert!(!selected.only_router_fields);
+
+        assert!(select(&conf(), "mutation { region }", None).is_none());
+        assert!(select(&conf(), "{ me { region } }", None).is_none());
+    }
+
+    #[test]
+    fn it_extends_the_schema() {
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+        router_fields:
+          fields:
+            region:
+              type: String
+              description: The region of the router
+              value:
+                static: eu-west-1
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            schema_extension(&configuration, "Query").unwrap(),
+            "extend type Query {\n  \"The region of the router\"\n  region: String\n}\n"
+        );
+        assert!(schema_extension(&Configuration::default(), "Query").is_none());
+    }
+
+    #[tokio::test]
+    async fn it_answers_router_fields() {
+        let service = crate::TestHarness::builder()
+            .configuration_json(serde_json::json!({ "router_fields": {
+                "fields": {
+                    "region": { "type": "String", "value": { "context": "region" } },
+                    "flags": { "type": "[String]", "value": { "static": ["a", "b"] } },
+                }
+            }}))
+            .unwrap()
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let context = Context::new();
+        context.insert("region", "eu-west-1".to_string()).unwrap();
+        let request = supergraph::Request::fake_builder()
+            .query(
+                "query TopProducts($first: Int) {
+                    flags
+                    topProducts(first: $first) {
+                        upc name reviews { id product { name } author { id name } }
+                    }
+                    region
+                }",
+            )
+            .variable("first", 2usize)
+            .context(context)
+            .build()
+            .unwrap();
+        let mut response = service.clone().oneshot(request).await.unwrap();
+        let data = response.response.body_mut().next().await.unwrap().data;
+        let data = data.as_ref().and_then(|data| data.as_object()).unwrap();
+        assert_eq!(
+            data.keys().map(|key| key.as_str()).collect::<Vec<_>>(),
+            vec!["flags", "topProducts", "region"]
+        );
+        assert_eq!(data["flags"], json!(["a", "b"]));
+        assert_eq!(data["region"], json!("eu-west-1"));
+
+        let request = supergraph::Request::fake_builder()
+            .query("{ flags }")
+            .build()
+            .unwrap();
+        let mut response = service.oneshot(request).await.unwrap();
+        let data = response.response.body_mut().next().await.unwrap().data;
+        assert_eq!(data, Some(json!({ "flags": ["a", "b"] })));
+    }
+}
@@ -0,0 +1,46 @@
+//! Errors and metrics shared by the script runtimes when an invocation exceeds its limits
+
+use std::fmt;
+
+use crate::graphql;
+
+/// Extension code of the errors returned when a script is stopped
+pub(crate) const LIMIT_EXCEEDED_CODE: &str = "SCRIPT_LIMIT_EXCEEDED";
+
+/// The limit a script invocation exceeded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Limit {
+    /// Number of operations, or wasm fuel
+    Operations,
+    /// Execution time
+    Timeout,
+    /// Size of strings, arrays and maps, or wasm memory
+    Memory,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Operations => "operations",
+            Limit::Timeout => "timeout",
+            Limit::Memory => "memory",
+        })
+    }
+}
+
+/// Records that a script was stopped, and returns the error sent to the client
+pub(crate) fn limit_exceeded(runtime: &'static str, limit: Limit) -> graphql::Error {
+    // This is a metric and will not appear in the logs
+    tracing::info!(
+        monotonic_counter.apollo_router_script_limit_exceeded_total = 1u64,
+        runtime,
+        limit = %limit,
+    );
+    tracing::warn!(runtime, limit = %limit, "script stopped after exceeding its limit");
+
+    graphql::Error::builder()
+        .message(format!("{runtime} script exceeded its {limit} limit"))
+        .extension_code(LIMIT_EXCEEDED_CODE)
+        .extension("limit", limit.to_string())
+        .build()
+}
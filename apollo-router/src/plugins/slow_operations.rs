@@ -0,0 +1,265 @@
+//! Log of slow operations
+//!
+//! Operations taking longer than a threshold, from the start of their planning to their last
+//! response, or whose planning alone takes longer than another threshold, are logged with their
+//! signature, the time spent in each subgraph and the cache hits and misses of the request. Every
+//! slow operation is logged, on a dedicated target, whatever the sampling of traces.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use super::performance_extensions::cache_statuses;
+use super::performance_extensions::record_subgraph_timing;
+use super::performance_extensions::SubgraphTiming;
+use crate::layers::ServiceExt as _;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::query_planner::OPERATION_SIGNATURE_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::services::QUERY_PLANNING_DURATION_CONTEXT_KEY;
+use crate::Context;
+
+/// Target of the slow operation log entries, to filter them or send them to their own output
+const SLOW_OPERATIONS_TARGET: &str = "apollo_router::slow_operations";
+const SUBGRAPHS_CONTEXT_KEY: &str = "apollo_router::slow_operations::subgraphs";
+const DEFAULT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Log of slow operations
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Log the slow operations
+    /// default: false
+    #[serde(default)]
+    enabled: bool,
+
+    /// Operations taking longer, from the start of their planning to their last response, are
+    /// logged. Default: 1s
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    threshold: Option<Duration>,
+
+    /// Operations whose planning takes longer are logged, whatever their total duration. If not
+    /// set, only the total duration is checked
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    planning_threshold: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Thresholds {
+    total: Duration,
+    planning: Option<Duration>,
+}
+
+/// A slow operation log entry
+#[derive(Debug, Serialize)]
+struct SlowOperation {
+    operation_name: Option<String>,
+    operation_signature: Option<String>,
+    client_name: Option<String>,
+    duration_ms: f64,
+    planning_ms: Option<f64>,
+    subgraphs: HashMap<String, SubgraphTiming>,
+    cache: Value,
+}
+
+impl Thresholds {
+    /// Returns the log entry of the operation, if it is slow
+    fn check(
+        &self,
+        context: &Context,
+        operation_name: Option<&str>,
+        duration: Duration,
+    ) -> Option<SlowOperation> {
+        let planning_ms: Option<f64> = context
+            .get(QUERY_PLANNING_DURATION_CONTEXT_KEY)
+            .ok()
+            .flatten();
+        let slow_planning = match (self.planning, planning_ms) {
+            (Some(threshold), Some(planning_ms)) => planning_ms > threshold.as_secs_f64() * 1000.0,
+            _ => false,
+        };
+        if duration <= self.total && !slow_planning {
+            return None;
+        }
+
+        Some(SlowOperation {
+            operation_name: operation_name.map(str::to_string),
+            operation_signature: context.get(OPERATION_SIGNATURE_CONTEXT_KEY).ok().flatten(),
+            client_name: context.get(CLIENT_NAME).ok().flatten(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            planning_ms,
+            subgraphs: context
+                .get(SUBGRAPHS_CONTEXT_KEY)
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+            cache: cache_statuses(context),
+        })
+    }
+}
+
+impl SlowOperation {
+    fn log(&self) {
+        let subgraphs = serde_json::to_string(&self.subgraphs).unwrap_or_default();
+        let cache = serde_json::to_string(&self.cache).unwrap_or_default();
+        tracing::warn!(
+            target: SLOW_OPERATIONS_TARGET,
+            operation_name = self.operation_name.as_deref().unwrap_or_default(),
+            operation_signature = self.operation_signature.as_deref().unwrap_or_default(),
+            client_name = self.client_name.as_deref().unwrap_or_default(),
+            duration_ms = self.duration_ms,
+            planning_ms = self.planning_ms.unwrap_or_default(),
+            subgraphs = subgraphs.as_str(),
+            cache = cache.as_str(),
+            "slow operation"
+        );
+    }
+}
+
+#[derive(Debug)]
+struct SlowOperations {
+    enabled: bool,
+    thresholds: Arc<Thresholds>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for SlowOperations {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(SlowOperations {
+            enabled: init.config.enabled,
+            thresholds: Arc::new(Thresholds {
+                total: init.config.threshold.unwrap_or(DEFAULT_THRESHOLD),
+                planning: init.config.planning_threshold,
+            }),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if !self.enabled {
+            return service;
+        }
+        let thresholds = self.thresholds.clone();
+        service
+            .map_future_with_request_data(
+                |request: &supergraph::Request| {
+                    (
+                        Instant::now(),
+                        request.supergraph_request.body().operation_name.clone(),
+                    )
+                },
+                move |(start, operation_name): (Instant, Option<String>), future| {
+                    let thresholds = thresholds.clone();
+                    async move {
+                        let response: supergraph::Response = future.await?;
+                        let context = response.context.clone();
+                        // with @defer, the operation is complete with its last response
+                        Ok(response.map_stream(move |response| {
+                            if !response.has_next.unwrap_or(false) {
+                                if let Some(slow) = thresholds.check(
+                                    &context,
+                                    operation_name.as_deref(),
+                                    start.elapsed(),
+                                ) {
+                                    slow.log();
+                                }
+                            }
+                            response
+                        }))
+                    }
+                },
+            )
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        if !self.enabled {
+            return service;
+        }
+        let name = name.to_string();
+        service
+            .map_future_with_request_data(
+                |request: &subgraph::Request| (request.context.clone(), Instant::now()),
+                move |(context, start): (Context, Instant), future| {
+                    let name = name.clone();
+                    async move {
+                        let response = future.await;
+                        record_subgraph_timing(
+                            &context,
+                            SUBGRAPHS_CONTEXT_KEY,
+                            &name,
+                            start.elapsed().as_secs_f64() * 1000.0,
+                        );
+                        response
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "slow_operations", SlowOperations);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(planning_ms: f64) -> Context {
+        let context = Context::new();
+        let _ = context.insert(QUERY_PLANNING_DURATION_CONTEXT_KEY, planning_ms);
+        let _ = context.insert(
+            OPERATION_SIGNATURE_CONTEXT_KEY,
+            "query A{me{id}}".to_string(),
+        );
+        let _ = context.insert(CLIENT_NAME, "ios".to_string());
+        record_subgraph_timing(&context, SUBGRAPHS_CONTEXT_KEY, "accounts", 120.0);
+        record_subgraph_timing(&context, SUBGRAPHS_CONTEXT_KEY, "accounts", 80.0);
+        context
+    }
+
+    #[test]
+    fn it_detects_slow_operations() {
+        let thresholds = Thresholds {
+            total: Duration::from_millis(500),
+            planning: Some(Duration::from_millis(100)),
+        };
+
+        assert!(thresholds
+            .check(&context(10.0), Some("A"), Duration::from_millis(300))
+            .is_none());
+        assert!(thresholds
+            .check(&context(150.0), Some("A"), Duration::from_millis(300))
+            .is_some());
+
+        let slow = thresholds
+            .check(&context(10.0), Some("A"), Duration::from_millis(700))
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&slow).unwrap(),
+            serde_json::json!({
+                "operation_name": "A",
+                "operation_signature": "query A{me{id}}",
+                "client_name": "ios",
+                "duration_ms": 700.0,
+                "planning_ms": 10.0,
+                "subgraphs": { "accounts": { "requests": 2, "duration_ms": 200.0 } },
+                "cache": { "query_plan": null },
+            })
+        );
+    }
+}
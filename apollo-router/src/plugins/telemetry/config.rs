@@ -16,6 +16,7 @@ use serde::Deserialize;
 use super::metrics::MetricsAttributesConf;
 use super::*;
 use crate::configuration::ConfigurationError;
+use crate::configuration::Redaction;
 use crate::plugin::serde::deserialize_option_header_name;
 use crate::plugin::serde::deserialize_regex;
 use crate::plugins::telemetry::metrics;
@@ -63,6 +64,11 @@ pub struct Conf {
     pub(crate) tracing: Option<Tracing>,
     /// Apollo reporting configuration
     pub(crate) apollo: Option<apollo::Config>,
+
+    /// Redaction of the logged and traced values
+    #[serde(default)]
+    #[schemars(skip)]
+    pub(crate) redaction: Redaction,
 }
 
 /// Metrics configuration
@@ -90,6 +96,33 @@ pub(crate) struct MetricsCommon {
     #[serde(default)]
     /// Resources
     pub(crate) resources: HashMap<String, String>,
+    /// Add the client name and version to the metrics attributes
+    #[serde(default)]
+    pub(crate) client_awareness: ClientAwarenessMetrics,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) struct ClientAwarenessMetrics {
+    /// Add the `client.name` and `client.version` attributes to the supergraph and subgraph metrics
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// The maximum number of distinct client name and version pairs in the attributes, the next ones are reported as `other`
+    #[serde(default = "default_max_clients")]
+    pub(crate) max_clients: usize,
+}
+
+impl Default for ClientAwarenessMetrics {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_clients: default_max_clients(),
+        }
+    }
+}
+
+fn default_max_clients() -> usize {
+    100
 }
 
 /// Tracing configuration
@@ -296,6 +329,10 @@ pub(crate) struct Propagation {
     /// Propagate baggage https://www.w3.org/TR/baggage/
     #[serde(default)]
     pub(crate) baggage: bool,
+    /// Entries added to the baggage propagated to the subgraphs, by key. Enables the baggage
+    /// propagation
+    #[serde(default)]
+    pub(crate) baggage_entries: BTreeMap<String, BaggageEntry>,
     /// Propagate trace context https://www.w3.org/TR/trace-context/
     #[serde(default)]
     pub(crate) trace_context: bool,
@@ -310,6 +347,30 @@ pub(crate) struct Propagation {
     pub(crate) zipkin: bool,
 }
 
+/// The value of a baggage entry
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum BaggageEntry {
+    /// The name of the operation
+    OperationName,
+    /// The name of the client, from the client name header
+    ClientName,
+    /// The version of the client, from the client version header
+    ClientVersion,
+    /// A header of the client request
+    RequestHeader(String),
+    /// A string entry of the context
+    Context(String),
+    /// A static value
+    Static(String),
+}
+
+impl Propagation {
+    pub(crate) fn propagates_baggage(&self) -> bool {
+        self.baggage || !self.baggage_entries.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, JsonSchema, Default)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) struct RequestPropagation {
@@ -255,6 +255,7 @@ mod test {
                 metrics: None,
                 tracing: None,
                 apollo: Some(apollo_config),
+                redaction: Default::default(),
             },
             Default::default(),
         ))
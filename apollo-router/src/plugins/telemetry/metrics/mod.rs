@@ -1,6 +1,8 @@
 use std::any::Any;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use ::serde::Deserialize;
 use access_json::JSONQuery;
@@ -472,6 +474,44 @@ impl AttributesForwardConf {
     }
 }
 
+/// Caps the distinct client name and version pairs in the metrics attributes, to bound their cardinality
+#[derive(Debug, Default)]
+pub(crate) struct ClientAttributes {
+    max_clients: usize,
+    clients: Mutex<HashSet<(String, String)>>,
+}
+
+impl ClientAttributes {
+    pub(crate) fn new(max_clients: usize) -> Self {
+        Self {
+            max_clients,
+            clients: Default::default(),
+        }
+    }
+
+    /// The `client.name` and `client.version` attributes, set to `other` for the clients seen after `max_clients` other ones
+    pub(crate) fn get_attributes(
+        &self,
+        client_name: &str,
+        client_version: &str,
+    ) -> HashMap<String, String> {
+        let client = (client_name.to_string(), client_version.to_string());
+        let mut clients = self.clients.lock().expect("lock poisoned");
+        let known = clients.contains(&client)
+            || (clients.len() < self.max_clients && clients.insert(client.clone()));
+        let (client_name, client_version) = if known {
+            client
+        } else {
+            ("other".to_string(), "other".to_string())
+        };
+
+        HashMap::from([
+            ("client.name".to_string(), client_name),
+            ("client.version".to_string(), client_version),
+        ])
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct MetricsBuilder {
     exporters: Vec<MetricsExporterHandle>,
@@ -533,6 +573,8 @@ pub(crate) trait MetricsConfigurator {
 pub(crate) struct BasicMetrics {
     pub(crate) http_requests_total: Counter<u64>,
     pub(crate) http_requests_duration: Histogram<f64>,
+    pub(crate) query_plan_cache_hits: Counter<u64>,
+    pub(crate) query_plan_cache_misses: Counter<u64>,
 }
 
 impl Default for BasicMetrics {
@@ -547,6 +589,14 @@ impl Default for BasicMetrics {
                 .f64_histogram("apollo_router_http_request_duration_seconds")
                 .with_description("Total number of HTTP requests made.")
                 .init(),
+            query_plan_cache_hits: meter
+                .u64_counter("apollo_router_query_plan_cache_hits_total")
+                .with_description("Number of requests whose query plan was cached.")
+                .init(),
+            query_plan_cache_misses: meter
+                .u64_counter("apollo_router_query_plan_cache_misses_total")
+                .with_description("Number of requests whose query plan was not cached.")
+                .init(),
         }
     }
 }
@@ -1,4 +1,6 @@
 use opentelemetry::sdk::export::metrics::aggregation;
+use opentelemetry::sdk::export::metrics::aggregation::TemporalitySelector;
+use opentelemetry::sdk::metrics::controllers::BasicController;
 use opentelemetry::sdk::Resource;
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::HttpExporterBuilder;
@@ -8,6 +10,7 @@ use tower::BoxError;
 use crate::plugins::telemetry::config::MetricsCommon;
 use crate::plugins::telemetry::metrics::MetricsBuilder;
 use crate::plugins::telemetry::metrics::MetricsConfigurator;
+use crate::plugins::telemetry::otlp::Temporality;
 
 // TODO Remove MetricExporterBuilder once upstream issue is fixed
 // This has to exist because Http is not currently supported for metrics export
@@ -39,21 +42,25 @@ impl MetricsConfigurator for super::super::otlp::Config {
         let exporter: MetricExporterBuilder = self.exporter()?;
         match exporter.exporter {
             Some(exporter) => {
-                let exporter = opentelemetry_otlp::new_pipeline()
-                    .metrics(
-                        opentelemetry::sdk::metrics::selectors::simple::inexpensive(),
-                        aggregation::stateless_temporality_selector(),
-                        opentelemetry::runtime::Tokio,
-                    )
-                    .with_exporter(exporter)
-                    .with_resource(Resource::new(
-                        metrics_config
-                            .resources
-                            .clone()
-                            .into_iter()
-                            .map(|(k, v)| KeyValue::new(k, v)),
-                    ))
-                    .build()?;
+                let resource = Resource::new(
+                    metrics_config
+                        .resources
+                        .clone()
+                        .into_iter()
+                        .map(|(k, v)| KeyValue::new(k, v)),
+                );
+                let exporter = match self.temporality {
+                    Temporality::Cumulative => build_controller(
+                        exporter,
+                        aggregation::cumulative_temporality_selector(),
+                        resource,
+                    )?,
+                    Temporality::Delta => build_controller(
+                        exporter,
+                        aggregation::delta_temporality_selector(),
+                        resource,
+                    )?,
+                };
                 builder = builder.with_meter_provider(exporter.clone());
                 builder = builder.with_exporter(exporter);
                 Ok(builder)
@@ -62,3 +69,19 @@ impl MetricsConfigurator for super::super::otlp::Config {
         }
     }
 }
+
+fn build_controller(
+    exporter: TonicExporterBuilder,
+    temporality_selector: impl TemporalitySelector + Clone + Send + Sync + 'static,
+    resource: Resource,
+) -> Result<BasicController, BoxError> {
+    Ok(opentelemetry_otlp::new_pipeline()
+        .metrics(
+            opentelemetry::sdk::metrics::selectors::simple::inexpensive(),
+            temporality_selector,
+            opentelemetry::runtime::Tokio,
+        )
+        .with_exporter(exporter)
+        .with_resource(resource)
+        .build()?)
+}
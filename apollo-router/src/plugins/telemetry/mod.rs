@@ -28,6 +28,7 @@ use http::HeaderValue;
 use http::StatusCode;
 use multimap::MultiMap;
 use once_cell::sync::OnceCell;
+use opentelemetry::baggage::BaggageExt;
 use opentelemetry::propagation::text_map_propagator::FieldIter;
 use opentelemetry::propagation::Extractor;
 use opentelemetry::propagation::Injector;
@@ -65,6 +66,7 @@ use self::apollo::SingleReport;
 use self::apollo_exporter::Sender;
 use self::config::Conf;
 use self::metrics::AttributesForwardConf;
+use self::metrics::ClientAttributes;
 use self::metrics::MetricsAttributesConf;
 #[cfg(not(feature = "console"))]
 use crate::executable::GLOBAL_ENV_FILTER;
@@ -77,6 +79,7 @@ use crate::plugins::telemetry::apollo_exporter::proto::reports::StatsContext;
 use crate::plugins::telemetry::config::default_display_filename;
 #[cfg(not(feature = "console"))]
 use crate::plugins::telemetry::config::default_display_line_number;
+use crate::plugins::telemetry::config::BaggageEntry;
 use crate::plugins::telemetry::config::MetricsCommon;
 use crate::plugins::telemetry::config::Trace;
 #[cfg(not(feature = "console"))]
@@ -96,7 +99,10 @@ use crate::plugins::telemetry::metrics::MetricsConfigurator;
 use crate::plugins::telemetry::metrics::MetricsExporterHandle;
 use crate::plugins::telemetry::tracing::apollo_telemetry::APOLLO_PRIVATE_OPERATION_SIGNATURE;
 use crate::plugins::telemetry::tracing::TracingConfigurator;
+use crate::query_planner::QUERY_PLAN_CACHE_HIT_CONTEXT_KEY;
 use crate::query_planner::USAGE_REPORTING;
+use crate::redaction::Redactor;
+use crate::redaction::REDACTED;
 use crate::register_plugin;
 use crate::router_factory::Endpoint;
 use crate::services::execution;
@@ -110,6 +116,7 @@ use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
+use crate::services::SCHEMA_VERSION_CONTEXT_KEY;
 use crate::tracer::TraceId;
 use crate::Context;
 use crate::ListenAddr;
@@ -125,8 +132,10 @@ pub(crate) const SUPERGRAPH_SPAN_NAME: &str = "supergraph";
 pub(crate) const SUBGRAPH_SPAN_NAME: &str = "subgraph";
 pub(crate) const ROUTER_SPAN_NAME: &str = "router";
 pub(crate) const EXECUTION_SPAN_NAME: &str = "execution";
-const CLIENT_NAME: &str = "apollo_telemetry::client_name";
-const CLIENT_VERSION: &str = "apollo_telemetry::client_version";
+pub(crate) const CLIENT_NAME: &str = "apollo_telemetry::client_name";
+pub(crate) const CLIENT_VERSION: &str = "apollo_telemetry::client_version";
+/// Context key of the baggage propagated to the subgraphs, by key
+pub(crate) const BAGGAGE: &str = "apollo_telemetry::baggage";
 const ATTRIBUTES: &str = "apollo_telemetry::metrics_attributes";
 const SUBGRAPH_ATTRIBUTES: &str = "apollo_telemetry::subgraph_metrics_attributes";
 pub(crate) const STUDIO_EXCLUDE: &str = "apollo_telemetry::studio::exclude";
@@ -150,6 +159,8 @@ pub struct Telemetry {
     custom_endpoints: MultiMap<ListenAddr, Endpoint>,
     apollo_metrics_sender: apollo_exporter::Sender,
     field_level_instrumentation_ratio: f64,
+    redactor: Option<Arc<Redactor>>,
+    client_attributes: Option<Arc<ClientAttributes>>,
 }
 
 #[derive(Debug)]
@@ -228,6 +239,7 @@ impl Plugin for Telemetry {
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
         let config = self.config.clone();
         let config_later = self.config.clone();
+        let redactor = self.redactor.clone();
 
         ServiceBuilder::new()
             .instrument(move |request: &router::Request| {
@@ -252,10 +264,11 @@ impl Plugin for Telemetry {
                     "trace_id" = %trace_id,
                     "client.name" = client_name.to_str().unwrap_or_default(),
                     "client.version" = client_version.to_str().unwrap_or_default(),
+                    "client.address" = ::tracing::field::Empty,
                     "otel.kind" = "INTERNAL",
                     "otel.status_code" = ::tracing::field::Empty,
                     "apollo_private.duration_ns" = ::tracing::field::Empty,
-                    "apollo_private.http.request_headers" = Self::filter_headers(request.router_request.headers(), &apollo.send_headers).as_str(),
+                    "apollo_private.http.request_headers" = Self::filter_headers(request.router_request.headers(), &apollo.send_headers, redactor.as_deref()).as_str(),
                     "apollo_private.http.response_headers" = field::Empty
                 );
                 span
@@ -306,10 +319,14 @@ impl Plugin for Telemetry {
         let config = self.config.clone();
         let config_map_res_first = config.clone();
         let config_map_res = config.clone();
+        let redactor = self.redactor.clone();
+        let request_redactor = self.redactor.clone();
+        let client_attributes = self.client_attributes.clone();
         ServiceBuilder::new()
             .instrument(Self::supergraph_service_span(
                 self.field_level_instrumentation_ratio,
                 config.apollo.clone().unwrap_or_default(),
+                self.redactor.clone(),
             ))
             .map_response(move |mut resp: SupergraphResponse| {
                 let config = config_map_res_first.clone();
@@ -339,19 +356,39 @@ impl Plugin for Telemetry {
                 }
 
                 if resp.context.contains_key(LOGGING_DISPLAY_HEADERS) {
-                    ::tracing::info!(http.response.headers = ?resp.response.headers(), "Supergraph response headers");
+                    match &redactor {
+                        Some(redactor) => {
+                            ::tracing::info!(http.response.headers = ?redactor.headers(resp.response.headers()), "Supergraph response headers");
+                        }
+                        None => {
+                            ::tracing::info!(http.response.headers = ?resp.response.headers(), "Supergraph response headers");
+                        }
+                    }
                 }
                 let display_body = resp.context.contains_key(LOGGING_DISPLAY_BODY);
+                let redactor = redactor.clone();
                 resp.map_stream(move |gql_response| {
                     if display_body {
-                        ::tracing::info!(http.response.body = ?gql_response, "Supergraph GraphQL response");
+                        match &redactor {
+                            Some(redactor) => {
+                                ::tracing::info!(http.response.body = ?redactor.response(&gql_response), "Supergraph GraphQL response");
+                            }
+                            None => {
+                                ::tracing::info!(http.response.body = ?gql_response, "Supergraph GraphQL response");
+                            }
+                        }
                     }
                     gql_response
                 })
             })
             .map_future_with_request_data(
                 move |req: &SupergraphRequest| {
-                    Self::populate_context(config.clone(), req);
+                    Self::populate_context(
+                        config.clone(),
+                        req,
+                        request_redactor.as_deref(),
+                        client_attributes.as_deref(),
+                    );
                     req.context.clone()
                 },
                 move |ctx: Context, fut| {
@@ -394,6 +431,7 @@ impl Plugin for Telemetry {
         let subgraph_attribute = KeyValue::new("subgraph", name.to_string());
         let subgraph_metrics_conf_req = self.create_subgraph_metrics_conf(name);
         let subgraph_metrics_conf_resp = subgraph_metrics_conf_req.clone();
+        let client_awareness = self.client_attributes.is_some();
         let name = name.to_owned();
         let apollo_handler = self.apollo_handler();
         ServiceBuilder::new()
@@ -426,6 +464,7 @@ impl Plugin for Telemetry {
                 move |sub_request: &SubgraphRequest| {
                     Self::store_subgraph_request_attributes(
                         subgraph_metrics_conf_req.clone(),
+                        client_awareness,
                         sub_request,
                     );
                     sub_request.context.clone()
@@ -563,14 +602,16 @@ impl Telemetry {
                         config::LoggingFormat::Pretty => {
                             let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
 
-                            let subscriber = sub_builder
+                            let sub_builder = sub_builder
                                 .event_format(FilteringFormatter::new(
                                     TextFormatter::new(),
                                     filter_metric_events,
                                 ))
-                                .finish()
-                                .with(telemetry)
-                                .with(otel_metrics);
+                                .with_filter_reloading();
+                            let handle = sub_builder.reload_handle();
+                            crate::log_filter::install(move |filter| Ok(handle.reload(filter)?));
+                            let subscriber =
+                                sub_builder.finish().with(telemetry).with(otel_metrics);
                             if let Err(e) = set_global_default(subscriber) {
                                 ::tracing::error!("cannot set global subscriber: {:?}", e);
                             }
@@ -578,7 +619,7 @@ impl Telemetry {
                         config::LoggingFormat::Json => {
                             let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
 
-                            let subscriber = sub_builder
+                            let sub_builder = sub_builder
                                 .map_event_format(|e| {
                                     FilteringFormatter::new(
                                         e.json()
@@ -589,9 +630,11 @@ impl Telemetry {
                                     )
                                 })
                                 .map_fmt_fields(|_f| JsonFields::default())
-                                .finish()
-                                .with(telemetry)
-                                .with(otel_metrics);
+                                .with_filter_reloading();
+                            let handle = sub_builder.reload_handle();
+                            crate::log_filter::install(move |filter| Ok(handle.reload(filter)?));
+                            let subscriber =
+                                sub_builder.finish().with(telemetry).with(otel_metrics);
                             if let Err(e) = set_global_default(subscriber) {
                                 ::tracing::error!("cannot set global subscriber: {:?}", e);
                             }
@@ -605,6 +648,14 @@ impl Telemetry {
 
         let field_level_instrumentation_ratio =
             config.calculate_field_level_instrumentation_ratio()?;
+        let redactor = Redactor::new(&config.redaction)?;
+        let client_attributes = config
+            .metrics
+            .as_ref()
+            .and_then(|metrics| metrics.common.as_ref())
+            .map(|common| &common.client_awareness)
+            .filter(|client_awareness| client_awareness.enabled)
+            .map(|client_awareness| Arc::new(ClientAttributes::new(client_awareness.max_clients)));
 
         let plugin = Ok(Telemetry {
             custom_endpoints: builder.custom_endpoints(),
@@ -612,6 +663,8 @@ impl Telemetry {
             metrics: BasicMetrics::default(),
             apollo_metrics_sender: builder.apollo_metrics_provider(),
             field_level_instrumentation_ratio,
+            redactor,
+            client_attributes,
             config: Arc::new(config),
         });
 
@@ -635,7 +688,7 @@ impl Telemetry {
         if propagation.jaeger || tracing.jaeger.is_some() {
             propagators.push(Box::new(opentelemetry_jaeger::Propagator::default()));
         }
-        if propagation.baggage {
+        if propagation.propagates_baggage() {
             propagators.push(Box::new(BaggagePropagator::default()));
         }
         if propagation.trace_context || tracing.otlp.is_some() {
@@ -715,6 +768,7 @@ impl Telemetry {
     fn supergraph_service_span(
         field_level_instrumentation_ratio: f64,
         config: apollo::Config,
+        redactor: Option<Arc<Redactor>>,
     ) -> impl Fn(&SupergraphRequest) -> Span + Clone {
         move |request: &SupergraphRequest| {
             let http_request = &request.supergraph_request;
@@ -737,6 +791,7 @@ impl Telemetry {
                 apollo_private.graphql.variables = Self::filter_variables_values(
                     &request.supergraph_request.body().variables,
                     &config.send_variable_values,
+                    redactor.as_deref(),
                 ),
             );
 
@@ -744,7 +799,11 @@ impl Telemetry {
         }
     }
 
-    fn filter_headers(headers: &HeaderMap, forward_rules: &ForwardHeaders) -> String {
+    fn filter_headers(
+        headers: &HeaderMap,
+        forward_rules: &ForwardHeaders,
+        redactor: Option<&Redactor>,
+    ) -> String {
         let headers_map = headers
             .iter()
             .filter(|(name, _value)| {
@@ -759,10 +818,14 @@ impl Telemetry {
                     ForwardHeaders::Only(only) => only.contains(name),
                     ForwardHeaders::Except(except) => !except.contains(name),
                 } {
-                    (
-                        name.to_string(),
-                        value.to_str().unwrap_or("<unknown>").to_string(),
-                    )
+                    if redactor.map_or(false, |redactor| redactor.is_redacted_header(name)) {
+                        (name.to_string(), REDACTED.to_string())
+                    } else {
+                        (
+                            name.to_string(),
+                            value.to_str().unwrap_or("<unknown>").to_string(),
+                        )
+                    }
                 } else {
                     (name.to_string(), "".to_string())
                 }
@@ -786,6 +849,7 @@ impl Telemetry {
     fn filter_variables_values(
         variables: &Map<ByteString, Value>,
         forward_rules: &ForwardValues,
+        redactor: Option<&Redactor>,
     ) -> String {
         #[allow(clippy::mutable_key_type)] // False positive lint
         let variables = variables
@@ -797,10 +861,17 @@ impl Telemetry {
                     ForwardValues::Only(only) => only.contains(&name.as_str().to_string()),
                     ForwardValues::Except(except) => !except.contains(&name.as_str().to_string()),
                 } {
-                    (
-                        name,
-                        serde_json::to_string(value).unwrap_or_else(|_| "<unknown>".to_string()),
-                    )
+                    if redactor.map_or(false, |redactor| {
+                        redactor.is_redacted_variable(name.as_str())
+                    }) {
+                        (name, serde_json::to_string(REDACTED).unwrap_or_default())
+                    } else {
+                        (
+                            name,
+                            serde_json::to_string(value)
+                                .unwrap_or_else(|_| "<unknown>".to_string()),
+                        )
+                    }
                 } else {
                     (name, "".to_string())
                 }
@@ -839,6 +910,17 @@ impl Telemetry {
                     .collect::<Vec<KeyValue>>()
             })
             .unwrap_or_default();
+        // set when a canary schema serves a part of the requests
+        let schema_version = context
+            .get::<_, String>(SCHEMA_VERSION_CONTEXT_KEY)
+            .ok()
+            .flatten()
+            .map(|schema_version| KeyValue::new("schema_version", schema_version));
+        metric_attrs.extend(schema_version.clone());
+        let query_plan_cache_hit = context
+            .get::<_, bool>(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY)
+            .ok()
+            .flatten();
         let res = match result {
             Ok(response) => {
                 metric_attrs.push(KeyValue::new(
@@ -894,48 +976,110 @@ impl Telemetry {
             &metric_attrs,
         );
 
+        let cache_attrs: Vec<KeyValue> = schema_version.into_iter().collect();
+        match query_plan_cache_hit {
+            Some(true) => metrics.query_plan_cache_hits.add(
+                &opentelemetry::Context::current(),
+                1,
+                &cache_attrs,
+            ),
+            Some(false) => metrics.query_plan_cache_misses.add(
+                &opentelemetry::Context::current(),
+                1,
+                &cache_attrs,
+            ),
+            // the request was not planned
+            None => {}
+        }
+
         res
     }
 
-    fn populate_context(config: Arc<Conf>, req: &SupergraphRequest) {
+    /// The baggage of the client request, with the configured entries
+    fn baggage(
+        propagation: &config::Propagation,
+        req: &SupergraphRequest,
+    ) -> HashMap<String, String> {
+        let headers = req.supergraph_request.headers();
+        let mut baggage: HashMap<String, String> = BaggagePropagator::new()
+            .extract(&opentelemetry_http::HeaderExtractor(headers))
+            .baggage()
+            .iter()
+            .map(|(key, (value, _))| (key.to_string(), value.to_string()))
+            .collect();
+        for (key, entry) in &propagation.baggage_entries {
+            let value = match entry {
+                BaggageEntry::OperationName => req.supergraph_request.body().operation_name.clone(),
+                BaggageEntry::ClientName => req.context.get(CLIENT_NAME).ok().flatten(),
+                BaggageEntry::ClientVersion => req.context.get(CLIENT_VERSION).ok().flatten(),
+                BaggageEntry::RequestHeader(name) => headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from),
+                BaggageEntry::Context(key) => req.context.get(key).ok().flatten(),
+                BaggageEntry::Static(value) => Some(value.clone()),
+            };
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                baggage.insert(key.clone(), value);
+            }
+        }
+        baggage
+    }
+
+    fn populate_context(
+        config: Arc<Conf>,
+        req: &SupergraphRequest,
+        redactor: Option<&Redactor>,
+        client_attributes: Option<&ClientAttributes>,
+    ) {
         let apollo_config = config.apollo.clone().unwrap_or_default();
         let context = &req.context;
         let http_request = &req.supergraph_request;
         let headers = http_request.headers();
-        let client_name_header = &apollo_config.client_name_header;
-        let client_version_header = &apollo_config.client_version_header;
-        let _ = context.insert(
-            CLIENT_NAME,
-            headers
-                .get(client_name_header)
-                .cloned()
-                .unwrap_or_else(|| HeaderValue::from_static(""))
-                .to_str()
-                .unwrap_or_default()
-                .to_string(),
-        );
-        let _ = context.insert(
-            CLIENT_VERSION,
-            headers
-                .get(client_version_header)
-                .cloned()
-                .unwrap_or_else(|| HeaderValue::from_static(""))
-                .to_str()
-                .unwrap_or_default()
-                .to_string(),
-        );
+        let client_name = headers
+            .get(&apollo_config.client_name_header)
+            .and_then(|name| name.to_str().ok())
+            .unwrap_or_default();
+        let client_version = headers
+            .get(&apollo_config.client_version_header)
+            .and_then(|version| version.to_str().ok())
+            .unwrap_or_default();
+        let _ = context.insert(CLIENT_NAME, client_name.to_string());
+        let _ = context.insert(CLIENT_VERSION, client_version.to_string());
+        if let Some(propagation) = config
+            .tracing
+            .as_ref()
+            .and_then(|tracing| tracing.propagation.as_ref())
+            .filter(|propagation| propagation.propagates_baggage())
+        {
+            let _ = context.insert(BAGGAGE, Self::baggage(propagation, req));
+        }
         let (should_log_headers, should_log_body) = config
             .logging
             .as_ref()
             .map(|cfg| cfg.should_log(req))
             .unwrap_or_default();
         if should_log_headers {
-            ::tracing::info!(http.request.headers = ?req.supergraph_request.headers(), "Supergraph request headers");
+            match redactor {
+                Some(redactor) => {
+                    ::tracing::info!(http.request.headers = ?redactor.headers(req.supergraph_request.headers()), "Supergraph request headers");
+                }
+                None => {
+                    ::tracing::info!(http.request.headers = ?req.supergraph_request.headers(), "Supergraph request headers");
+                }
+            }
 
             let _ = req.context.insert(LOGGING_DISPLAY_HEADERS, true);
         }
         if should_log_body {
-            ::tracing::info!(http.request.body = ?req.supergraph_request.body(), "Supergraph request body");
+            match redactor {
+                Some(redactor) => {
+                    ::tracing::info!(http.request.body = ?redactor.request(req.supergraph_request.body()), "Supergraph request body");
+                }
+                None => {
+                    ::tracing::info!(http.request.body = ?req.supergraph_request.body(), "Supergraph request body");
+                }
+            }
 
             let _ = req.context.insert(LOGGING_DISPLAY_BODY, true);
         }
@@ -959,6 +1103,9 @@ impl Telemetry {
                 );
                 attributes.extend(router_attributes_conf.get_attributes_from_context(context));
             }
+            if let Some(client_attributes) = client_attributes {
+                attributes.extend(client_attributes.get_attributes(client_name, client_version));
+            }
 
             let _ = context.insert(ATTRIBUTES, attributes);
         }
@@ -1043,9 +1190,25 @@ impl Telemetry {
 
     fn store_subgraph_request_attributes(
         attribute_forward_config: Arc<Option<AttributesForwardConf>>,
+        client_awareness: bool,
         sub_request: &Request,
     ) {
         let mut attributes = HashMap::new();
+        if client_awareness {
+            // Reuse the client attributes of the supergraph request, which were capped already
+            if let Some(supergraph_attributes) = sub_request
+                .context
+                .get::<_, HashMap<String, String>>(ATTRIBUTES)
+                .ok()
+                .flatten()
+            {
+                attributes.extend(
+                    supergraph_attributes
+                        .into_iter()
+                        .filter(|(name, _)| name == "client.name" || name == "client.version"),
+                );
+            }
+        }
         if let Some(subgraph_attributes_conf) = &*attribute_forward_config {
             attributes.extend(subgraph_attributes_conf.get_attributes_from_request(
                 sub_request.subgraph_request.headers(),
@@ -1287,6 +1450,22 @@ impl Telemetry {
     }
 }
 
+/// The OpenTelemetry context injected in a subgraph request, whose baggage is the baggage of the
+/// request context, if it is propagated
+pub(crate) fn subgraph_otel_context(
+    otel_context: opentelemetry::Context,
+    context: &Context,
+) -> opentelemetry::Context {
+    match context.get::<_, HashMap<String, String>>(BAGGAGE) {
+        Ok(Some(baggage)) => otel_context.with_cleared_baggage().with_baggage(
+            baggage
+                .into_iter()
+                .map(|(key, value)| KeyValue::new(key, value)),
+        ),
+        _ => otel_context,
+    }
+}
+
 // Planner errors return stats report key that start with `## `
 // while successful planning stats report key start with `# `
 fn operation_count(stats_report_key: &str) -> u64 {
@@ -1435,11 +1614,13 @@ impl TextMapPropagator for CustomTraceIdPropagator {
 //
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::str::FromStr;
 
     use http::StatusCode;
     use insta::assert_snapshot;
     use itertools::Itertools;
+    use opentelemetry::propagation::TextMapPropagator;
     use serde_json::Value;
     use serde_json_bytes::json;
     use serde_json_bytes::ByteString;
@@ -1455,10 +1636,13 @@ mod tests {
     use crate::plugin::test::MockSubgraphService;
     use crate::plugin::test::MockSupergraphService;
     use crate::plugin::DynPlugin;
+    use crate::query_planner::QUERY_PLAN_CACHE_HIT_CONTEXT_KEY;
     use crate::services::SubgraphRequest;
     use crate::services::SubgraphResponse;
     use crate::services::SupergraphRequest;
     use crate::services::SupergraphResponse;
+    use crate::services::SCHEMA_VERSION_CONTEXT_KEY;
+    use crate::Context;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn plugin_registered() {
@@ -1473,6 +1657,80 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn it_caps_client_attributes() {
+        let client_attributes = super::ClientAttributes::new(2);
+        let attributes = |name: &str, version: &str| {
+            client_attributes
+                .get_attributes(name, version)
+                .into_iter()
+                .sorted()
+                .map(|(_, value)| value)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(attributes("web", "1.0"), ["web", "1.0"]);
+        assert_eq!(attributes("web", "1.1"), ["web", "1.1"]);
+        assert_eq!(attributes("ios", "1.0"), ["other", "other"]);
+        assert_eq!(attributes("web", "1.0"), ["web", "1.0"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_propagates_baggage_with_configured_entries() {
+        let propagation: super::config::Propagation = serde_json::from_value(serde_json::json!({
+            "baggage_entries": {
+                "operation": "operation_name",
+                "client": "client_name",
+                "tenant": { "request_header": "x-tenant" },
+                "region": { "static": "eu" },
+                "missing": { "context": "missing" }
+            }
+        }))
+        .unwrap();
+        assert!(propagation.propagates_baggage());
+
+        let request = SupergraphRequest::fake_builder()
+            .query("query Me { me { id } }")
+            .operation_name("Me")
+            .header("baggage", "user=1,region=us")
+            .header("x-tenant", "acme")
+            .build()
+            .unwrap();
+        request
+            .context
+            .insert(super::CLIENT_NAME, "web".to_string())
+            .unwrap();
+        let baggage = super::Telemetry::baggage(&propagation, &request);
+        assert_eq!(
+            baggage.into_iter().sorted().collect::<Vec<_>>(),
+            [
+                ("client", "web"),
+                ("operation", "Me"),
+                ("region", "eu"),
+                ("tenant", "acme"),
+                ("user", "1"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        let context = crate::Context::new();
+        context
+            .insert(
+                super::BAGGAGE,
+                [("user".to_string(), "1".to_string())]
+                    .into_iter()
+                    .collect::<HashMap<_, _>>(),
+            )
+            .unwrap();
+        let otel_context = super::subgraph_otel_context(opentelemetry::Context::new(), &context);
+        let mut headers = http::HeaderMap::new();
+        opentelemetry::sdk::propagation::BaggagePropagator::new().inject_context(
+            &otel_context,
+            &mut opentelemetry_http::HeaderInjector(&mut headers),
+        );
+        assert_eq!(headers["baggage"], "user=1");
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn attribute_serialization() {
         crate::plugin::plugins()
@@ -1628,6 +1886,9 @@ mod tests {
             .expect_call()
             .times(1)
             .returning(move |req: SupergraphRequest| {
+                req.context
+                    .insert(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY, true)
+                    .unwrap();
                 Ok(SupergraphResponse::fake_builder()
                     .context(req.context)
                     .header("x-custom", "coming_from_header")
@@ -1787,7 +2048,13 @@ mod tests {
             .await
             .unwrap();
         let mut supergraph_service = dyn_plugin.supergraph_service(BoxService::new(mock_service));
-        let router_req = SupergraphRequest::fake_builder().header("test", "my_value_set");
+        let context = Context::new();
+        context
+            .insert(SCHEMA_VERSION_CONTEXT_KEY, "canary".to_string())
+            .unwrap();
+        let router_req = SupergraphRequest::fake_builder()
+            .header("test", "my_value_set")
+            .context(context);
 
         let _router_response = supergraph_service
             .ready()
@@ -1891,6 +2158,9 @@ mod tests {
         let mut resp = web_endpoint.oneshot(http_req_prom).await.unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
         let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(
+            r#"apollo_router_query_plan_cache_hits_total{schema_version="canary",service_name="apollo-router"} 1"#
+        ));
         let prom_metrics = String::from_utf8_lossy(&body)
             .to_string()
             .split('\n')
@@ -45,6 +45,10 @@ pub(crate) struct Config {
     /// Batch processor settings
     #[serde(default)]
     pub(crate) batch_processor: BatchProcessorConfig,
+
+    /// Temporality of the exported metrics, either `cumulative` or `delta`. Unused by tracing
+    #[serde(default)]
+    pub(crate) temporality: Temporality,
 }
 
 impl Config {
@@ -211,6 +215,19 @@ impl Default for Protocol {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum Temporality {
+    Cumulative,
+    Delta,
+}
+
+impl Default for Temporality {
+    fn default() -> Self {
+        Temporality::Cumulative
+    }
+}
+
 mod metadata_map_serde {
     use tonic::metadata::KeyAndValueRef;
     use tonic::metadata::MetadataKey;
@@ -309,6 +326,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn temporality_configuration() {
+        let config: Config = serde_yaml::from_str("endpoint: default").unwrap();
+        assert_eq!(config.temporality, Temporality::Cumulative);
+
+        let config: Config = serde_yaml::from_str("endpoint: default\ntemporality: delta").unwrap();
+        assert_eq!(config.temporality, Temporality::Delta);
+
+        assert!(serde_yaml::from_str::<Config>("endpoint: default\ntemporality: gauge").is_err());
+    }
+
     #[test]
     fn endpoint_grpc_defaulting_no_scheme() {
         let url = Url::parse("api.apm.com:433").unwrap();
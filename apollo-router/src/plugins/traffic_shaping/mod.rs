@@ -14,6 +14,8 @@ mod timeout;
 
 use std::collections::HashMap;
 use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
@@ -37,6 +39,7 @@ pub(crate) use self::rate::RateLimited;
 use self::retry::RetryPolicy;
 pub(crate) use self::timeout::Elapsed;
 use self::timeout::TimeoutLayer;
+use crate::clock::Clock;
 use crate::error::ConfigurationError;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
@@ -60,8 +63,11 @@ trait Merge {
 struct Shaping {
     /// Enable query deduplication
     deduplicate_query: Option<bool>,
-    /// Enable compression for subgraphs (available compressions are deflate, br, gzip)
+    /// Enable compression for subgraphs (available compressions are deflate, br, gzip, zstd)
     compression: Option<Compression>,
+    /// Path to a zstd dictionary shared with the subgraph, used to compress requests and to
+    /// decompress responses with the zstd compression
+    compression_dictionary: Option<PathBuf>,
     /// Enable global rate limiting
     global_rate_limit: Option<RateLimitConf>,
     #[serde(deserialize_with = "humantime_serde::deserialize", default)]
@@ -82,6 +88,11 @@ impl Merge for Shaping {
             Some(fallback) => Shaping {
                 deduplicate_query: self.deduplicate_query.or(fallback.deduplicate_query),
                 compression: self.compression.or(fallback.compression),
+                compression_dictionary: self
+                    .compression_dictionary
+                    .as_ref()
+                    .or(fallback.compression_dictionary.as_ref())
+                    .cloned(),
                 timeout: self.timeout.or(fallback.timeout),
                 apq: self.apq.or(fallback.apq),
                 global_rate_limit: self
@@ -161,7 +172,9 @@ pub(crate) struct Config {
     #[serde(default)]
     /// Applied on specific subgraphs
     subgraphs: HashMap<String, Shaping>,
-    /// Enable variable deduplication optimization when sending requests to subgraphs (https://github.com/apollographql/router/issues/87)
+    /// Send each entity representation once in the `_entities` requests to subgraphs, and copy the
+    /// resolved entity to every reference of the response (https://github.com/apollographql/router/issues/87)
+    /// default: true
     deduplicate_variables: Option<bool>,
 }
 
@@ -194,6 +207,8 @@ pub(crate) struct TrafficShaping {
     config: Config,
     rate_limit_router: Option<RateLimitLayer>,
     rate_limit_subgraphs: Mutex<HashMap<String, RateLimitLayer>>,
+    compression_dictionaries: HashMap<PathBuf, Arc<[u8]>>,
+    clock: Arc<dyn Clock>,
 }
 
 #[async_trait::async_trait]
@@ -219,15 +234,40 @@ impl Plugin for TrafficShaping {
                     Ok(RateLimitLayer::new(
                         router_rate_limit_conf.capacity,
                         router_rate_limit_conf.interval,
+                        init.clock.clone(),
                     ))
                 }
             })
             .transpose()?;
 
+        let mut compression_dictionaries: HashMap<PathBuf, Arc<[u8]>> = HashMap::new();
+        for path in init
+            .config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .filter_map(|shaping| shaping.compression_dictionary.as_ref())
+        {
+            if compression_dictionaries.contains_key(path) {
+                continue;
+            }
+            let dictionary =
+                std::fs::read(path).map_err(|e| ConfigurationError::InvalidConfiguration {
+                    message: "bad configuration for traffic_shaping plugin",
+                    error: format!(
+                        "could not read the compression dictionary at '{}': {e}",
+                        path.display()
+                    ),
+                })?;
+            compression_dictionaries.insert(path.clone(), Arc::from(dictionary));
+        }
+
         Ok(Self {
             config: init.config,
             rate_limit_router,
             rate_limit_subgraphs: Mutex::new(HashMap::new()),
+            compression_dictionaries,
+            clock: init.clock,
         })
     }
 }
@@ -322,7 +362,11 @@ impl TrafficShaping {
                     .unwrap()
                     .entry(name.to_string())
                     .or_insert_with(|| {
-                        RateLimitLayer::new(rate_limit_conf.capacity, rate_limit_conf.interval)
+                        RateLimitLayer::new(
+                            rate_limit_conf.capacity,
+                            rate_limit_conf.interval,
+                            self.clock.clone(),
+                        )
                     })
                     .clone()
             });
@@ -352,7 +396,12 @@ impl TrafficShaping {
                 .map_request(move |mut req: SubgraphRequest| {
                     if let Some(compression) = config.compression {
                         let compression_header_val = HeaderValue::from_str(&compression.to_string()).expect("compression is manually implemented and already have the right values; qed");
-                        req.subgraph_request.headers_mut().insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br, deflate"));
+                        let accept_encoding = if compression == Compression::Zstd {
+                            "zstd, gzip, br, deflate"
+                        } else {
+                            "gzip, br, deflate"
+                        };
+                        req.subgraph_request.headers_mut().insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
                         req.subgraph_request.headers_mut().insert(CONTENT_ENCODING, compression_header_val);
                     }
 
@@ -366,14 +415,21 @@ impl TrafficShaping {
     pub(crate) fn get_apq(&self, name: &str) -> Option<bool> {
         self.config.subgraphs.get(name)?.apq
     }
+
+    pub(crate) fn get_compression_dictionary(&self, name: &str) -> Option<Arc<[u8]>> {
+        let config = Self::merge_config(self.config.all.as_ref(), self.config.subgraphs.get(name))?;
+        self.compression_dictionaries
+            .get(config.compression_dictionary.as_ref()?)
+            .cloned()
+    }
 }
 
 impl TrafficShaping {
     pub(crate) fn get_configuration_deduplicate_variables(configuration: &Configuration) -> bool {
         configuration
             .plugin_configuration(APOLLO_TRAFFIC_SHAPING)
-            .map(|conf| conf.get("deduplicate_variables") == Some(&serde_json::Value::Bool(true)))
-            .unwrap_or_default()
+            .map(|conf| conf.get("deduplicate_variables") != Some(&serde_json::Value::Bool(false)))
+            .unwrap_or(true)
     }
 }
 
@@ -391,6 +447,7 @@ mod test {
     use tower::Service;
 
     use super::*;
+    use crate::clock::ManualClock;
     use crate::json_ext::Object;
     use crate::plugin::test::MockSubgraph;
     use crate::plugin::test::MockSupergraphService;
@@ -513,6 +570,7 @@ mod test {
             &Configuration::default(),
         )
         .await
+        .expect("should build")
         .make()
         .boxed()
     }
@@ -527,6 +585,16 @@ mod test {
             .expect("Plugin not created")
     }
 
+    async fn get_traffic_shaping_plugin_with_clock(
+        config: &serde_json::Value,
+        clock: ManualClock,
+    ) -> TrafficShaping {
+        let init = PluginInit::try_new(config.clone(), Default::default())
+            .expect("invalid configuration")
+            .with_clock(clock);
+        TrafficShaping::new(init).await.expect("Plugin not created")
+    }
+
     #[tokio::test]
     async fn it_returns_valid_response_for_deduplicated_variables() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -541,6 +609,24 @@ mod test {
         execute_router_test(VALID_QUERY, &EXPECTED_RESPONSE, router).await;
     }
 
+    #[test]
+    fn it_deduplicates_variables_by_default() {
+        assert!(TrafficShaping::get_configuration_deduplicate_variables(
+            &Configuration::default()
+        ));
+
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+        traffic_shaping:
+            deduplicate_variables: false
+        "#,
+        )
+        .unwrap();
+        assert!(!TrafficShaping::get_configuration_deduplicate_variables(
+            &configuration
+        ));
+    }
+
     #[tokio::test]
     async fn it_add_correct_headers_for_compression() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -628,43 +714,28 @@ mod test {
         )
         .unwrap();
 
-        let plugin = get_traffic_shaping_plugin(&config).await;
+        let clock = ManualClock::new();
+        let plugin = get_traffic_shaping_plugin_with_clock(&config, clock.clone()).await;
 
         let test_service = MockSubgraph::new(HashMap::new());
 
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .subgraph_service_internal("test", test_service.clone())
             .oneshot(SubgraphRequest::fake_builder().build())
             .await
             .unwrap();
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .subgraph_service_internal("test", test_service.clone())
             .oneshot(SubgraphRequest::fake_builder().build())
             .await
             .expect_err("should be in error due to a timeout and rate limit");
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .subgraph_service_internal("another", test_service.clone())
             .oneshot(SubgraphRequest::fake_builder().build())
             .await
             .unwrap();
-        // Note: use `timeout` to guarantee 300ms has elapsed
-        let big_sleep = tokio::time::sleep(Duration::from_secs(10));
-        assert!(tokio::time::timeout(Duration::from_millis(300), big_sleep)
-            .await
-            .is_err());
+        clock.advance(Duration::from_millis(200));
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .subgraph_service_internal("test", test_service.clone())
             .oneshot(SubgraphRequest::fake_builder().build())
             .await
@@ -684,7 +755,8 @@ mod test {
         )
         .unwrap();
 
-        let plugin = get_traffic_shaping_plugin(&config).await;
+        let clock = ManualClock::new();
+        let plugin = get_traffic_shaping_plugin_with_clock(&config, clock.clone()).await;
         let mut mock_service = MockSupergraphService::new();
         mock_service.expect_clone().returning(|| {
             let mut mock_service = MockSupergraphService::new();
@@ -703,9 +775,6 @@ mod test {
         });
 
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .supergraph_service_internal(mock_service.clone())
             .oneshot(SupergraphRequest::fake_builder().build().unwrap())
             .await
@@ -715,22 +784,12 @@ mod test {
             .unwrap();
 
         assert!(plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .supergraph_service_internal(mock_service.clone())
             .oneshot(SupergraphRequest::fake_builder().build().unwrap())
             .await
             .is_err());
-        // Note: use `timeout` to guarantee 300ms has elapsed
-        let big_sleep = tokio::time::sleep(Duration::from_secs(10));
-        assert!(tokio::time::timeout(Duration::from_millis(300), big_sleep)
-            .await
-            .is_err());
+        clock.advance(Duration::from_millis(400));
         let _response = plugin
-            .as_any()
-            .downcast_ref::<TrafficShaping>()
-            .unwrap()
             .supergraph_service_internal(mock_service.clone())
             .oneshot(SupergraphRequest::fake_builder().build().unwrap())
             .await
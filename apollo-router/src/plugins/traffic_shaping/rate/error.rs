@@ -6,6 +6,9 @@ use std::fmt;
 use axum::response::IntoResponse;
 use http::StatusCode;
 
+use crate::error::router_error_response;
+use crate::graphql;
+
 /// The rate limit error.
 #[derive(Debug, Default)]
 pub(crate) struct RateLimited;
@@ -25,7 +28,11 @@ impl fmt::Display for RateLimited {
 
 impl IntoResponse for RateLimited {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::TOO_MANY_REQUESTS, self.to_string()).into_response()
+        let error = graphql::Error::builder()
+            .message(self.to_string())
+            .extension_code("REQUEST_RATE_LIMITED")
+            .build();
+        router_error_response(StatusCode::TOO_MANY_REQUESTS, error).into_response()
     }
 }
 
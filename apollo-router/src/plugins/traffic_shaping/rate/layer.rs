@@ -3,13 +3,13 @@ use std::sync::atomic::AtomicU64;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
-use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use tower::Layer;
 
 use super::Rate;
 use super::RateLimit;
+use crate::clock::Clock;
 /// Enforces a rate limit on the number of requests the underlying
 /// service can handle over a period of time.
 #[derive(Debug, Clone)]
@@ -18,22 +18,25 @@ pub(crate) struct RateLimitLayer {
     window_start: Arc<AtomicU64>,
     previous_nb_requests: Arc<AtomicUsize>,
     current_nb_requests: Arc<AtomicUsize>,
+    clock: Arc<dyn Clock>,
 }
 
 impl RateLimitLayer {
-    /// Create new rate limit layer.
-    pub(crate) fn new(num: NonZeroU64, per: Duration) -> Self {
+    /// Create new rate limit layer, with windows measured on `clock`.
+    pub(crate) fn new(num: NonZeroU64, per: Duration, clock: Arc<dyn Clock>) -> Self {
         let rate = Rate::new(num, per);
         RateLimitLayer {
             rate,
             window_start: Arc::new(AtomicU64::new(
-                SystemTime::now()
+                clock
+                    .now()
                     .duration_since(UNIX_EPOCH)
                     .expect("system time must be after EPOCH")
                     .as_millis() as u64,
             )),
             previous_nb_requests: Arc::default(),
             current_nb_requests: Arc::new(AtomicUsize::new(1)),
+            clock,
         }
     }
 }
@@ -48,6 +51,7 @@ impl<S> Layer<S> for RateLimitLayer {
             window_start: self.window_start.clone(),
             previous_nb_requests: self.previous_nb_requests.clone(),
             current_nb_requests: self.current_nb_requests.clone(),
+            clock: self.clock.clone(),
         }
     }
 }
@@ -4,7 +4,6 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
-use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use futures::ready;
@@ -12,6 +11,7 @@ use tower::Service;
 
 use super::future::ResponseFuture;
 use super::Rate;
+use crate::clock::Clock;
 use crate::plugins::traffic_shaping::rate::error::RateLimited;
 
 #[derive(Debug, Clone)]
@@ -24,6 +24,7 @@ pub(crate) struct RateLimit<T> {
     pub(crate) window_start: Arc<AtomicU64>,
     pub(crate) previous_nb_requests: Arc<AtomicUsize>,
     pub(crate) current_nb_requests: Arc<AtomicUsize>,
+    pub(crate) clock: Arc<dyn Clock>,
 }
 
 impl<S, Request> Service<Request> for RateLimit<S>
@@ -41,7 +42,9 @@ where
         let updated =
             self.window_start
                 .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |window_start| {
-                    let duration_now = SystemTime::now()
+                    let duration_now = self
+                        .clock
+                        .now()
                         .duration_since(UNIX_EPOCH)
                         .expect("system time must be after EPOCH")
                         .as_millis() as u64;
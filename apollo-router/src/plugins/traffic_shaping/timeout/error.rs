@@ -6,6 +6,9 @@ use std::fmt;
 use axum::response::IntoResponse;
 use http::StatusCode;
 
+use crate::error::router_error_response;
+use crate::graphql;
+
 /// The timeout elapsed.
 #[derive(Debug, Default)]
 pub(crate) struct Elapsed;
@@ -25,7 +28,11 @@ impl fmt::Display for Elapsed {
 
 impl IntoResponse for Elapsed {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::REQUEST_TIMEOUT, self.to_string()).into_response()
+        let error = graphql::Error::builder()
+            .message(self.to_string())
+            .extension_code("REQUEST_TIMEOUT")
+            .build();
+        router_error_response(StatusCode::REQUEST_TIMEOUT, error).into_response()
     }
 }
 
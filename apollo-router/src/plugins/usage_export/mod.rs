@@ -0,0 +1,312 @@
+//! Export of request summaries
+//!
+//! A summary of each request, with its operation signature, its client, its estimated cost, the
+//! time spent in each subgraph and the codes of its errors, is published to a Kafka topic or sent
+//! to an HTTP endpoint, to be loaded in an analytics warehouse. Summaries are sent in batches by a
+//! background task, at most once: they are dropped when the queue is full or when a batch cannot
+//! be sent.
+
+mod sink;
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use self::sink::Exporter;
+use self::sink::HttpSink;
+use self::sink::KafkaSink;
+use self::sink::Sink;
+use super::performance_extensions::record_subgraph_timing;
+use super::performance_extensions::Cost;
+use super::performance_extensions::SubgraphTiming;
+use crate::error::ConfigurationError;
+use crate::layers::ServiceExt as _;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::plugins::telemetry::CLIENT_VERSION;
+use crate::query_planner::OPERATION_SIGNATURE_CONTEXT_KEY;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::Context;
+
+const COST_CONTEXT_KEY: &str = "apollo_router::usage_export::cost";
+const SUBGRAPHS_CONTEXT_KEY: &str = "apollo_router::usage_export::subgraphs";
+
+const DEFAULT_MAX_BATCH_SIZE: usize = 512;
+const DEFAULT_MAX_QUEUE_SIZE: usize = 4096;
+const DEFAULT_SCHEDULED_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Export of request summaries to Kafka or an HTTP endpoint
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Conf {
+    /// Destination of the summaries
+    sink: SinkConf,
+
+    /// Batching of the summaries
+    #[serde(default)]
+    batch: BatchConf,
+}
+
+/// Destination of the summaries
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum SinkConf {
+    /// Kafka topic, where each summary is a JSON record. The batches are spread over the
+    /// partitions of the topic
+    Kafka {
+        /// Addresses of the bootstrap brokers, as `host:port`
+        brokers: Vec<String>,
+        /// The topic
+        topic: String,
+    },
+    /// HTTP endpoint receiving each batch as a JSON array, in a POST request
+    Http {
+        /// The URL of the endpoint
+        url: url::Url,
+        /// The timeout of the requests
+        /// Default: 10s
+        #[serde(with = "humantime_serde", default)]
+        #[schemars(with = "String", default)]
+        timeout: Option<Duration>,
+    },
+}
+
+/// Batching of the summaries
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct BatchConf {
+    /// The most summaries sent in a batch
+    /// Default: 512
+    max_size: Option<usize>,
+
+    /// The longest delay before the summaries waiting in the queue are sent
+    /// Default: 5s
+    #[serde(with = "humantime_serde", default)]
+    #[schemars(with = "String", default)]
+    scheduled_delay: Option<Duration>,
+
+    /// The most summaries waiting to be sent. Further summaries are dropped
+    /// Default: 4096
+    max_queue_size: Option<usize>,
+}
+
+/// Summary of a request
+#[derive(Debug, Serialize)]
+struct RequestSummary {
+    /// Milliseconds since the Unix epoch
+    timestamp: u64,
+    operation_name: Option<String>,
+    operation_signature: Option<String>,
+    client_name: Option<String>,
+    client_version: Option<String>,
+    status_code: u16,
+    duration_ms: f64,
+    cost: Option<Cost>,
+    subgraphs: HashMap<String, SubgraphTiming>,
+    error_codes: Vec<String>,
+}
+
+impl RequestSummary {
+    fn new(
+        context: &Context,
+        operation_name: Option<String>,
+        status_code: u16,
+        duration: Duration,
+        error_codes: Vec<String>,
+    ) -> Self {
+        RequestSummary {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            operation_name,
+            operation_signature: context.get(OPERATION_SIGNATURE_CONTEXT_KEY).ok().flatten(),
+            client_name: context.get(CLIENT_NAME).ok().flatten(),
+            client_version: context.get(CLIENT_VERSION).ok().flatten(),
+            status_code,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            cost: context.get(COST_CONTEXT_KEY).ok().flatten(),
+            subgraphs: context
+                .get(SUBGRAPHS_CONTEXT_KEY)
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+            error_codes,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UsageExport {
+    exporter: Exporter,
+}
+
+#[async_trait::async_trait]
+impl Plugin for UsageExport {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let sink: Box<dyn Sink> = match init.config.sink {
+            SinkConf::Kafka { brokers, topic } => {
+                if brokers.is_empty() {
+                    return Err(ConfigurationError::InvalidConfiguration {
+                        message: "bad configuration for usage_export plugin",
+                        error: "at least one Kafka broker is required".to_string(),
+                    }
+                    .into());
+                }
+                Box::new(KafkaSink::new(brokers, topic))
+            }
+            SinkConf::Http { url, timeout } => {
+                Box::new(HttpSink::new(url, timeout.unwrap_or(DEFAULT_HTTP_TIMEOUT))?)
+            }
+        };
+        let batch = init.config.batch;
+        Ok(UsageExport {
+            exporter: Exporter::start(
+                sink,
+                batch.max_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE).max(1),
+                batch.scheduled_delay.unwrap_or(DEFAULT_SCHEDULED_DELAY),
+                batch
+                    .max_queue_size
+                    .unwrap_or(DEFAULT_MAX_QUEUE_SIZE)
+                    .max(1),
+            ),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let exporter = self.exporter.clone();
+        service
+            .map_future_with_request_data(
+                |request: &supergraph::Request| {
+                    (
+                        Instant::now(),
+                        request.supergraph_request.body().operation_name.clone(),
+                    )
+                },
+                move |(start, operation_name): (Instant, Option<String>), future| {
+                    let exporter = exporter.clone();
+                    async move {
+                        let response: supergraph::Response = future.await?;
+                        let context = response.context.clone();
+                        let status_code = response.response.status().as_u16();
+                        let mut error_codes = Vec::new();
+                        let mut operation_name = Some(operation_name);
+                        // with @defer, the request is complete with its last response
+                        Ok(response.map_stream(move |response| {
+                            error_codes.extend(response.errors.iter().filter_map(|error| {
+                                error
+                                    .extensions
+                                    .get("code")
+                                    .and_then(|code| code.as_str())
+                                    .map(str::to_string)
+                            }));
+                            if !response.has_next.unwrap_or(false) {
+                                if let Some(operation_name) = operation_name.take() {
+                                    exporter.send(RequestSummary::new(
+                                        &context,
+                                        operation_name,
+                                        status_code,
+                                        start.elapsed(),
+                                        std::mem::take(&mut error_codes),
+                                    ));
+                                }
+                            }
+                            response
+                        }))
+                    }
+                },
+            )
+            .boxed()
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        service
+            .map_request(|request: execution::Request| {
+                let _ = request
+                    .context
+                    .insert(COST_CONTEXT_KEY, Cost::new(&request));
+                request
+            })
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let name = name.to_string();
+        service
+            .map_future_with_request_data(
+                |request: &subgraph::Request| (request.context.clone(), Instant::now()),
+                move |(context, start): (Context, Instant), future| {
+                    let name = name.clone();
+                    async move {
+                        let response = future.await;
+                        record_subgraph_timing(
+                            &context,
+                            SUBGRAPHS_CONTEXT_KEY,
+                            &name,
+                            start.elapsed().as_secs_f64() * 1000.0,
+                        );
+                        response
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+register_plugin!("apollo", "usage_export", UsageExport);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_summarizes_requests() {
+        let context = Context::new();
+        let _ = context.insert(
+            OPERATION_SIGNATURE_CONTEXT_KEY,
+            "query A{me{id}}".to_string(),
+        );
+        let _ = context.insert(CLIENT_NAME, "ios".to_string());
+        let _ = context.insert(CLIENT_VERSION, "1.2".to_string());
+        record_subgraph_timing(&context, SUBGRAPHS_CONTEXT_KEY, "accounts", 12.0);
+
+        let mut summary = serde_json::to_value(RequestSummary::new(
+            &context,
+            Some("A".to_string()),
+            200,
+            Duration::from_millis(30),
+            vec!["SUBREQUEST_HTTP_ERROR".to_string()],
+        ))
+        .unwrap();
+        summary.as_object_mut().unwrap().remove("timestamp");
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "operation_name": "A",
+                "operation_signature": "query A{me{id}}",
+                "client_name": "ios",
+                "client_version": "1.2",
+                "status_code": 200,
+                "duration_ms": 30.0,
+                "cost": null,
+                "subgraphs": { "accounts": { "requests": 1, "duration_ms": 12.0 } },
+                "error_codes": ["SUBREQUEST_HTTP_ERROR"],
+            })
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Batching and delivery of the request summaries
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rskafka::client::partition::Compression;
+use rskafka::client::partition::PartitionClient;
+use rskafka::client::ClientBuilder;
+use rskafka::record::Record;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tower::BoxError;
+
+use super::RequestSummary;
+
+/// Destination of the batches of summaries
+#[async_trait::async_trait]
+pub(super) trait Sink: Send + 'static {
+    async fn send(&mut self, batch: &[RequestSummary]) -> Result<(), BoxError>;
+}
+
+/// Publishes each summary as a JSON record of a Kafka topic
+pub(super) struct KafkaSink {
+    brokers: Vec<String>,
+    topic: String,
+    /// Clients of the partitions of the topic, connected on the first batch, and reconnected after
+    /// a failure
+    partitions: Vec<PartitionClient>,
+    next_partition: usize,
+}
+
+impl KafkaSink {
+    pub(super) fn new(brokers: Vec<String>, topic: String) -> Self {
+        KafkaSink {
+            brokers,
+            topic,
+            partitions: Vec::new(),
+            next_partition: 0,
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), BoxError> {
+        let client = ClientBuilder::new(self.brokers.clone()).build().await?;
+        let topic = client
+            .list_topics()
+            .await?
+            .into_iter()
+            .find(|topic| topic.name == self.topic)
+            .ok_or_else(|| format!("the Kafka topic '{}' does not exist", self.topic))?;
+        self.partitions = topic
+            .partitions
+            .iter()
+            .map(|partition| client.partition_client(self.topic.clone(), *partition))
+            .collect::<Result<_, _>>()?;
+        if self.partitions.is_empty() {
+            return Err(format!("the Kafka topic '{}' has no partitions", self.topic).into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for KafkaSink {
+    async fn send(&mut self, batch: &[RequestSummary]) -> Result<(), BoxError> {
+        if self.partitions.is_empty() {
+            self.connect().await?;
+        }
+        let records = batch
+            .iter()
+            .map(|summary| {
+                Ok(Record {
+                    key: None,
+                    value: Some(serde_json::to_vec(summary)?),
+                    headers: BTreeMap::new(),
+                    timestamp: OffsetDateTime::now_utc(),
+                })
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+        // the batches are spread over the partitions
+        self.next_partition = (self.next_partition + 1) % self.partitions.len();
+        let result = self.partitions[self.next_partition]
+            .produce(records, Compression::NoCompression)
+            .await;
+        if result.is_err() {
+            self.partitions.clear();
+        }
+        result?;
+        Ok(())
+    }
+}
+
+/// Sends each batch as a JSON array in a POST request
+pub(super) struct HttpSink {
+    client: reqwest::Client,
+    url: url::Url,
+}
+
+impl HttpSink {
+    pub(super) fn new(url: url::Url, timeout: Duration) -> Result<Self, BoxError> {
+        Ok(HttpSink {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for HttpSink {
+    async fn send(&mut self, batch: &[RequestSummary]) -> Result<(), BoxError> {
+        self.client
+            .post(self.url.clone())
+            .json(batch)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Queue of the summaries waiting to be sent by the background task
+#[derive(Clone, Debug)]
+pub(super) struct Exporter(mpsc::Sender<RequestSummary>);
+
+impl Exporter {
+    /// Starts the task sending the summaries in batches, until the exporter is dropped
+    pub(super) fn start(
+        mut sink: Box<dyn Sink>,
+        max_batch_size: usize,
+        scheduled_delay: Duration,
+        max_queue_size: usize,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<RequestSummary>(max_queue_size);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(scheduled_delay);
+            let mut batch = Vec::with_capacity(max_batch_size);
+            loop {
+                tokio::select! {
+                    summary = receiver.recv() => match summary {
+                        Some(summary) => {
+                            batch.push(summary);
+                            if batch.len() >= max_batch_size {
+                                flush(sink.as_mut(), &mut batch).await;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = interval.tick() => flush(sink.as_mut(), &mut batch).await,
+                }
+            }
+            flush(sink.as_mut(), &mut batch).await;
+        });
+        Exporter(sender)
+    }
+
+    /// Queues a summary, which is dropped if the queue is full
+    pub(super) fn send(&self, summary: RequestSummary) {
+        if self.0.try_send(summary).is_err() {
+            tracing::info!(
+                monotonic_counter.apollo_router_usage_export_dropped_total = 1u64,
+                reason = "queue_full",
+            );
+        }
+    }
+}
+
+/// Sends a batch once: it is dropped if it cannot be sent
+async fn flush(sink: &mut dyn Sink, batch: &mut Vec<RequestSummary>) {
+    if batch.is_empty() {
+        return;
+    }
+    if let Err(e) = sink.send(batch).await {
+        tracing::error!("could not export {} request summaries: {e}", batch.len());
+        tracing::info!(
+            monotonic_counter.apollo_router_usage_export_dropped_total = batch.len() as u64,
+            reason = "send_failed",
+        );
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::Context;
+
+    #[derive(Clone, Default)]
+    struct RecordingSink(Arc<Mutex<Vec<usize>>>);
+
+    #[async_trait::async_trait]
+    impl Sink for RecordingSink {
+        async fn send(&mut self, batch: &[RequestSummary]) -> Result<(), BoxError> {
+            self.0.lock().unwrap().push(batch.len());
+            Ok(())
+        }
+    }
+
+    fn summary() -> RequestSummary {
+        RequestSummary::new(&Context::new(), None, 200, Duration::ZERO, Vec::new())
+    }
+
+    #[tokio::test]
+    async fn it_sends_summaries_in_batches() {
+        let sink = RecordingSink::default();
+        let exporter = Exporter::start(Box::new(sink.clone()), 2, Duration::from_secs(3600), 10);
+        for _ in 0..5 {
+            exporter.send(summary());
+        }
+        // the remaining summary is sent when the exporter is dropped
+        drop(exporter);
+        for _ in 0..50 {
+            if sink.0.lock().unwrap().iter().sum::<usize>() == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*sink.0.lock().unwrap(), vec![2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn it_drops_summaries_when_the_queue_is_full() {
+        let sink = RecordingSink::default();
+        let exporter = Exporter::start(Box::new(sink.clone()), 10, Duration::from_secs(3600), 2);
+        // the exporter task cannot run before the test yields, so the queue is never drained
+        for _ in 0..5 {
+            exporter.send(summary());
+        }
+        drop(exporter);
+        for _ in 0..50 {
+            if !sink.0.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*sink.0.lock().unwrap(), vec![2]);
+    }
+}
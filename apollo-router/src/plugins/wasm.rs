@@ -0,0 +1,860 @@
+//! WebAssembly plugins
+//!
+//! Runs hooks exported by `.wasm` modules at the supergraph and subgraph stages, so that
+//! policies can be changed without rebuilding the router. Modules are compiled when the
+//! plugin is created, at startup and on every configuration or schema reload, and each hook
+//! call runs in a fresh instance limited in memory and fuel.
+//!
+//! Modules export some of the following functions, which take no parameters and return
+//! an `i32`: `on_supergraph_request`, `on_supergraph_response`, `on_subgraph_request` and
+//! `on_subgraph_response`. A request hook returning a non zero HTTP status code stops the
+//! request with that status. The return value of response hooks is ignored.
+//!
+//! Modules also export their `memory`, and can import the host functions of the
+//! `apollo_router` module:
+//!
+//! | Function | Signature | Description |
+//! |---|---|---|
+//! | `log` | `(level, ptr, len)` | Logs a message, from 0 (trace) to 4 (error) |
+//! | `header_get` | `(name_ptr, name_len, out_ptr, out_len) -> len` | Reads a header value |
+//! | `header_set` | `(name_ptr, name_len, value_ptr, value_len) -> status` | Replaces a header |
+//! | `header_remove` | `(name_ptr, name_len) -> status` | Removes a header |
+//! | `context_get` | `(key_ptr, key_len, out_ptr, out_len) -> len` | Reads a context entry as JSON |
+//! | `context_set` | `(key_ptr, key_len, value_ptr, value_len) -> status` | Sets a context entry from JSON |
+//! | `body_get` | `(out_ptr, out_len) -> len` | Reads the GraphQL request or response as JSON |
+//! | `body_set` | `(ptr, len) -> status` | Replaces the GraphQL request or response |
+//! | `subgraph_name` | `(out_ptr, out_len) -> len` | Reads the name of the subgraph |
+//! | `error_set` | `(ptr, len)` | Sets the error message returned when a request hook stops the request |
+//!
+//! Functions reading a value return its length, and only write it if it fits in the
+//! `out_len` bytes at `out_ptr`, or return -1 if there is no value. Functions changing a
+//! value return 0, or -1 if it is invalid or cannot be changed at this stage.
+//! Supergraph responses are streamed, so their body cannot be accessed.
+//!
+//! A hook running out of fuel or exceeding its timeout is stopped, and the request fails
+//! with a `SCRIPT_LIMIT_EXCEEDED` error.
+
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::header::HeaderName;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+use wasmtime::Caller;
+use wasmtime::Engine;
+use wasmtime::Extern;
+use wasmtime::InstancePre;
+use wasmtime::Linker;
+use wasmtime::Memory;
+use wasmtime::Module;
+use wasmtime::Store;
+use wasmtime::StoreLimits;
+use wasmtime::StoreLimitsBuilder;
+use wasmtime::Trap;
+
+use crate::error::Error;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::script_limits::limit_exceeded;
+use crate::plugins::script_limits::Limit;
+use crate::register_plugin;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::Context;
+
+const HOST_MODULE: &str = "apollo_router";
+const DEFAULT_MAX_MEMORY: usize = 16 * 1024 * 1024;
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_ERROR_MESSAGE: &str = "request rejected by WebAssembly module";
+/// Interval at which the engine epoch is incremented, which is the precision of timeouts
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Configuration for WebAssembly plugins
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Conf {
+    /// Modules to load. Their hooks run in the declared order
+    modules: Vec<ModuleConf>,
+}
+
+/// A WebAssembly module and the limits applied to each of its hook calls
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ModuleConf {
+    /// Path of the `.wasm` module
+    path: PathBuf,
+    /// Maximum size of the module memory, in bytes. Defaults to 16MiB
+    #[serde(default = "default_max_memory")]
+    max_memory: usize,
+    /// Fuel available to a hook call, roughly the number of instructions it can execute
+    #[serde(default = "default_fuel")]
+    fuel: u64,
+    /// Maximum execution time of a hook call. Unlimited by default
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    timeout: Option<Duration>,
+}
+
+fn default_max_memory() -> usize {
+    DEFAULT_MAX_MEMORY
+}
+
+fn default_fuel() -> u64 {
+    DEFAULT_FUEL
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Hook {
+    SupergraphRequest,
+    SupergraphResponse,
+    SubgraphRequest,
+    SubgraphResponse,
+}
+
+impl Hook {
+    fn export_name(self) -> &'static str {
+        match self {
+            Hook::SupergraphRequest => "on_supergraph_request",
+            Hook::SupergraphResponse => "on_supergraph_response",
+            Hook::SubgraphRequest => "on_subgraph_request",
+            Hook::SubgraphResponse => "on_subgraph_response",
+        }
+    }
+}
+
+/// Data of the request or response exposed to a hook
+struct HookData {
+    headers: HeaderMap,
+    context: Context,
+    /// JSON serialized GraphQL request or response, if the stage gives access to it
+    body: Option<Vec<u8>>,
+    body_modified: bool,
+    subgraph_name: Option<String>,
+}
+
+impl HookData {
+    fn new(
+        headers: HeaderMap,
+        context: Context,
+        body: Option<Vec<u8>>,
+        subgraph_name: Option<String>,
+    ) -> Self {
+        Self {
+            headers,
+            context,
+            body,
+            body_modified: false,
+            subgraph_name,
+        }
+    }
+
+    /// Deserializes the body if a hook replaced it
+    fn modified_body<T: serde::de::DeserializeOwned>(&self) -> Result<Option<T>, BoxError> {
+        match &self.body {
+            Some(body) if self.body_modified => Ok(Some(serde_json::from_slice(body)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+struct HostState {
+    module: Arc<str>,
+    data: HookData,
+    error: Option<String>,
+    limits: StoreLimits,
+}
+
+struct WasmModule {
+    name: Arc<str>,
+    module: Module,
+    instance: InstancePre<HostState>,
+    max_memory: usize,
+    fuel: u64,
+    timeout: Option<Duration>,
+}
+
+/// A hook stopping the request, with the status and error returned instead
+struct Rejection {
+    status: StatusCode,
+    error: Error,
+    /// Set if the module exceeded its limits, which also fails responses
+    stopped: bool,
+}
+
+impl WasmModule {
+    fn load(
+        engine: &Engine,
+        linker: &Linker<HostState>,
+        conf: &ModuleConf,
+    ) -> Result<Self, BoxError> {
+        let module = Module::from_file(engine, &conf.path).map_err(|e| {
+            format!(
+                "could not load WebAssembly module {}: {e}",
+                conf.path.display()
+            )
+        })?;
+        let instance = linker.instantiate_pre(&module).map_err(|e| {
+            format!(
+                "could not link WebAssembly module {}: {e}",
+                conf.path.display()
+            )
+        })?;
+        Ok(Self {
+            name: conf.path.display().to_string().into(),
+            module,
+            instance,
+            max_memory: conf.max_memory,
+            fuel: conf.fuel,
+            timeout: conf.timeout,
+        })
+    }
+
+    fn has_hook(&self, hook: Hook) -> bool {
+        self.module
+            .exports()
+            .any(|export| export.name() == hook.export_name())
+    }
+
+    /// Runs a hook in a new instance, and returns the status and error to stop the request with
+    /// if the hook rejected it or exceeded its limits.
+    fn call(&self, hook: Hook, data: HookData) -> Result<(HookData, Option<Rejection>), BoxError> {
+        let mut store = Store::new(
+            self.module.engine(),
+            HostState {
+                module: self.name.clone(),
+                data,
+                error: None,
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.max_memory)
+                    .build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.add_fuel(self.fuel)?;
+        store.set_epoch_deadline(match self.timeout {
+            // The epoch can be incremented right after the call starts
+            Some(timeout) => (timeout.as_millis() / EPOCH_TICK.as_millis()) as u64 + 1,
+            None => u64::MAX,
+        });
+
+        let instance = self.instance.instantiate(&mut store)?;
+        let function = instance.get_typed_func::<(), i32>(&mut store, hook.export_name())?;
+        let result = function.call(&mut store, ());
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                let limit = match e.downcast_ref::<Trap>() {
+                    Some(Trap::OutOfFuel) => Limit::Operations,
+                    Some(Trap::Interrupt) => Limit::Timeout,
+                    _ => {
+                        return Err(format!(
+                            "WebAssembly module {} failed in {}: {e}",
+                            self.name,
+                            hook.export_name()
+                        )
+                        .into())
+                    }
+                };
+                let rejection = Rejection {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    error: limit_exceeded("wasm", limit),
+                    stopped: true,
+                };
+                return Ok((store.into_data().data, Some(rejection)));
+            }
+        };
+
+        let state = store.into_data();
+        let rejection = if status == 0 {
+            None
+        } else {
+            let status = StatusCode::from_u16(u16::try_from(status)?)?;
+            let message = state
+                .error
+                .unwrap_or_else(|| DEFAULT_ERROR_MESSAGE.to_string());
+            Some(Rejection {
+                status,
+                error: Error {
+                    message,
+                    ..Default::default()
+                },
+                stopped: false,
+            })
+        };
+        Ok((state.data, rejection))
+    }
+}
+
+/// Runs a hook of every module that exports it, until one of them rejects the request
+fn run_hook(
+    modules: &[WasmModule],
+    hook: Hook,
+    mut data: HookData,
+) -> Result<(HookData, Option<Rejection>), BoxError> {
+    for module in modules.iter().filter(|module| module.has_hook(hook)) {
+        let (new_data, rejection) = module.call(hook, data)?;
+        data = new_data;
+        if rejection.is_some() {
+            return Ok((data, rejection));
+        }
+    }
+    Ok((data, None))
+}
+
+struct Wasm {
+    modules: Arc<Vec<WasmModule>>,
+    /// Stops the thread incrementing the engine epoch
+    epoch_stopped: Arc<AtomicBool>,
+}
+
+impl Drop for Wasm {
+    fn drop(&mut self) {
+        self.epoch_stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for Wasm {
+    type Config = Conf;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+        let linker = linker(&engine)?;
+        let modules = init
+            .config
+            .modules
+            .iter()
+            .map(|conf| WasmModule::load(&engine, &linker, conf))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let epoch_stopped = Arc::new(AtomicBool::new(false));
+        if modules.iter().any(|module| module.timeout.is_some()) {
+            let stopped = epoch_stopped.clone();
+            std::thread::Builder::new()
+                .name("wasm-epoch".to_string())
+                .spawn(move || {
+                    while !stopped.load(Ordering::Relaxed) {
+                        std::thread::sleep(EPOCH_TICK);
+                        engine.increment_epoch();
+                    }
+                })?;
+        }
+
+        Ok(Self {
+            modules: Arc::new(modules),
+            epoch_stopped,
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let request_modules = self.modules_with_hook(Hook::SupergraphRequest);
+        let response_modules = self.modules_with_hook(Hook::SupergraphResponse);
+
+        ServiceBuilder::new()
+            .option_layer(request_modules.map(|modules| {
+                ServiceBuilder::new().checkpoint(move |mut request: supergraph::Request| {
+                    let data = HookData::new(
+                        std::mem::take(request.supergraph_request.headers_mut()),
+                        request.context.clone(),
+                        Some(serde_json::to_vec(request.supergraph_request.body())?),
+                        None,
+                    );
+                    let (data, rejection) = run_hook(&modules, Hook::SupergraphRequest, data)?;
+                    if let Some(rejection) = rejection {
+                        return Ok(ControlFlow::Break(
+                            supergraph::Response::error_builder()
+                                .error(rejection.error)
+                                .status_code(rejection.status)
+                                .context(request.context)
+                                .build()?,
+                        ));
+                    }
+                    if let Some(body) = data.modified_body()? {
+                        *request.supergraph_request.body_mut() = body;
+                    }
+                    *request.supergraph_request.headers_mut() = data.headers;
+                    Ok(ControlFlow::Continue(request))
+                })
+            }))
+            .option_layer(response_modules.map(|modules| {
+                ServiceBuilder::new().map_result(
+                    move |result: Result<supergraph::Response, BoxError>| {
+                        let mut response = result?;
+                        let data = HookData::new(
+                            std::mem::take(response.response.headers_mut()),
+                            response.context.clone(),
+                            None,
+                            None,
+                        );
+                        let (data, rejection) = run_hook(&modules, Hook::SupergraphResponse, data)?;
+                        if let Some(rejection) = rejection.filter(|rejection| rejection.stopped) {
+                            return supergraph::Response::error_builder()
+                                .error(rejection.error)
+                                .status_code(rejection.status)
+                                .context(response.context)
+                                .build();
+                        }
+                        *response.response.headers_mut() = data.headers;
+                        Ok(response)
+                    },
+                )
+            }))
+            .service(service)
+            .boxed()
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let request_modules = self.modules_with_hook(Hook::SubgraphRequest);
+        let response_modules = self.modules_with_hook(Hook::SubgraphResponse);
+        let request_subgraph_name = name.to_string();
+        let response_subgraph_name = name.to_string();
+
+        ServiceBuilder::new()
+            .option_layer(request_modules.map(|modules| {
+                ServiceBuilder::new().checkpoint(move |mut request: subgraph::Request| {
+                    let data = HookData::new(
+                        std::mem::take(request.subgraph_request.headers_mut()),
+                        request.context.clone(),
+                        Some(serde_json::to_vec(request.subgraph_request.body())?),
+                        Some(request_subgraph_name.clone()),
+                    );
+                    let (data, rejection) = run_hook(&modules, Hook::SubgraphRequest, data)?;
+                    if let Some(rejection) = rejection {
+                        return Ok(ControlFlow::Break(
+                            subgraph::Response::error_builder()
+                                .error(rejection.error)
+                                .status_code(rejection.status)
+                                .context(request.context)
+                                .build()?,
+                        ));
+                    }
+                    if let Some(body) = data.modified_body()? {
+                        *request.subgraph_request.body_mut() = body;
+                    }
+                    *request.subgraph_request.headers_mut() = data.headers;
+                    Ok(ControlFlow::Continue(request))
+                })
+            }))
+            .option_layer(response_modules.map(|modules| {
+                ServiceBuilder::new().map_result(
+                    move |result: Result<subgraph::Response, BoxError>| {
+                        let mut response = result?;
+                        let data = HookData::new(
+                            std::mem::take(response.response.headers_mut()),
+                            response.context.clone(),
+                            Some(serde_json::to_vec(response.response.body())?),
+                            Some(response_subgraph_name.clone()),
+                        );
+                        let (data, rejection) = run_hook(&modules, Hook::SubgraphResponse, data)?;
+                        if let Some(rejection) = rejection.filter(|rejection| rejection.stopped) {
+                            return subgraph::Response::error_builder()
+                                .error(rejection.error)
+                                .status_code(rejection.status)
+                                .context(response.context)
+                                .build();
+                        }
+                        if let Some(body) = data.modified_body()? {
+                            *response.response.body_mut() = body;
+                        }
+                        *response.response.headers_mut() = data.headers;
+                        Ok(response)
+                    },
+                )
+            }))
+            .service(service)
+            .boxed()
+    }
+}
+
+impl Wasm {
+    /// Returns the modules if at least one of them exports the hook
+    fn modules_with_hook(&self, hook: Hook) -> Option<Arc<Vec<WasmModule>>> {
+        self.modules
+            .iter()
+            .any(|module| module.has_hook(hook))
+            .then(|| self.modules.clone())
+    }
+}
+
+fn linker(engine: &Engine) -> Result<Linker<HostState>, BoxError> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |mut caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let message = read_string(&mut caller, ptr, len)?;
+            let module = &caller.data().module;
+            match level {
+                0 => tracing::trace!(%module, "{message}"),
+                1 => tracing::debug!(%module, "{message}"),
+                2 => tracing::info!(%module, "{message}"),
+                3 => tracing::warn!(%module, "{message}"),
+                _ => tracing::error!(%module, "{message}"),
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "header_get",
+        |mut caller: Caller<'_, HostState>,
+         name_ptr: i32,
+         name_len: i32,
+         out_ptr: i32,
+         out_len: i32|
+         -> anyhow::Result<i32> {
+            let name = read_string(&mut caller, name_ptr, name_len)?;
+            let value = caller
+                .data()
+                .data
+                .headers
+                .get(name.as_str())
+                .map(|value| value.as_bytes().to_vec());
+            write_value(&mut caller, value.as_deref(), out_ptr, out_len)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "header_set",
+        |mut caller: Caller<'_, HostState>,
+         name_ptr: i32,
+         name_len: i32,
+         value_ptr: i32,
+         value_len: i32|
+         -> anyhow::Result<i32> {
+            let name = read(&mut caller, name_ptr, name_len)?;
+            let value = read(&mut caller, value_ptr, value_len)?;
+            match (
+                HeaderName::from_bytes(&name),
+                HeaderValue::from_bytes(&value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    caller.data_mut().data.headers.insert(name, value);
+                    Ok(0)
+                }
+                _ => Ok(-1),
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "header_remove",
+        |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32| -> anyhow::Result<i32> {
+            let name = read_string(&mut caller, name_ptr, name_len)?;
+            Ok(match caller.data_mut().data.headers.remove(name.as_str()) {
+                Some(_) => 0,
+                None => -1,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "context_get",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         out_ptr: i32,
+         out_len: i32|
+         -> anyhow::Result<i32> {
+            let key = read_string(&mut caller, key_ptr, key_len)?;
+            let value = caller
+                .data()
+                .data
+                .context
+                .get_json_value(key)
+                .map(|value| serde_json::to_vec(&value))
+                .transpose()?;
+            write_value(&mut caller, value.as_deref(), out_ptr, out_len)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "context_set",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         value_ptr: i32,
+         value_len: i32|
+         -> anyhow::Result<i32> {
+            let key = read_string(&mut caller, key_ptr, key_len)?;
+            let value = read(&mut caller, value_ptr, value_len)?;
+            Ok(match serde_json::from_slice(&value) {
+                Ok(value) => {
+                    caller.data().data.context.insert_json_value(key, value);
+                    0
+                }
+                Err(_) => -1,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "body_get",
+        |mut caller: Caller<'_, HostState>, out_ptr: i32, out_len: i32| -> anyhow::Result<i32> {
+            let body = caller.data().data.body.clone();
+            write_value(&mut caller, body.as_deref(), out_ptr, out_len)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "body_set",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<i32> {
+            let body = read(&mut caller, ptr, len)?;
+            let data = &mut caller.data_mut().data;
+            if data.body.is_none() || serde_json::from_slice::<serde_json::Value>(&body).is_err() {
+                return Ok(-1);
+            }
+            data.body = Some(body);
+            data.body_modified = true;
+            Ok(0)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "subgraph_name",
+        |mut caller: Caller<'_, HostState>, out_ptr: i32, out_len: i32| -> anyhow::Result<i32> {
+            let name = caller.data().data.subgraph_name.clone();
+            write_value(
+                &mut caller,
+                name.as_ref().map(|name| name.as_bytes()),
+                out_ptr,
+                out_len,
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "error_set",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let message = read_string(&mut caller, ptr, len)?;
+            caller.data_mut().error = Some(message);
+            Ok(())
+        },
+    )?;
+
+    Ok(linker)
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> anyhow::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow::anyhow!("the WebAssembly module does not export its memory"))
+}
+
+/// Reads `len` bytes at `ptr` in the module memory
+fn read(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<Vec<u8>> {
+    let memory = memory(caller)?;
+    let len = len as u32 as usize;
+    // Check the length before allocating, the read itself is bounds checked
+    if len > memory.data_size(&*caller) {
+        anyhow::bail!("out of bounds memory access");
+    }
+    let mut buffer = vec![0; len];
+    memory.read(&*caller, ptr as u32 as usize, &mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_string(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> anyhow::Result<String> {
+    Ok(String::from_utf8(read(caller, ptr, len)?)?)
+}
+
+/// Writes the value at `out_ptr` if it fits in `out_len` bytes, and returns its length,
+/// or -1 if there is no value
+fn write_value(
+    caller: &mut Caller<'_, HostState>,
+    value: Option<&[u8]>,
+    out_ptr: i32,
+    out_len: i32,
+) -> anyhow::Result<i32> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(-1),
+    };
+    if value.len() <= out_len as u32 as usize {
+        let memory = memory(caller)?;
+        memory.write(&mut *caller, out_ptr as u32 as usize, value)?;
+    }
+    Ok(i32::try_from(value.len())?)
+}
+
+register_plugin!("apollo", "experimental_wasm", Wasm);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraphService;
+    use crate::plugin::test::MockSupergraphService;
+    use crate::plugin::test::PluginTestHarness;
+    use crate::plugins::script_limits::LIMIT_EXCEEDED_CODE;
+
+    async fn harness(module: &str) -> PluginTestHarness<Wasm> {
+        PluginTestHarness::new(json!({
+            "modules": [{ "path": format!("tests/fixtures/wasm/{module}") }]
+        }))
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_rejects_requests() {
+        let mut downstream = MockSupergraphService::new();
+        downstream.expect_call().never();
+
+        let mut response = harness("client_name.wat")
+            .await
+            .supergraph_service(downstream)
+            .oneshot(supergraph::Request::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.next_response().await.unwrap().errors[0].message,
+            "missing x-client-name header"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_lets_accepted_requests_through() {
+        let mut downstream = MockSupergraphService::new();
+        downstream
+            .expect_call()
+            .times(1)
+            .returning(|request: supergraph::Request| {
+                assert_eq!(
+                    request
+                        .supergraph_request
+                        .headers()
+                        .get("x-client-name")
+                        .unwrap(),
+                    "web"
+                );
+                supergraph::Response::fake_builder()
+                    .context(request.context)
+                    .build()
+            });
+
+        let response = harness("client_name.wat")
+            .await
+            .supergraph_service(downstream)
+            .oneshot(
+                supergraph::Request::fake_builder()
+                    .header("x-client-name", "web")
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_modifies_subgraph_requests() {
+        let mut downstream = MockSubgraphService::new();
+        downstream
+            .expect_call()
+            .times(1)
+            .returning(|request: subgraph::Request| {
+                assert_eq!(
+                    request
+                        .subgraph_request
+                        .headers()
+                        .get("x-subgraph")
+                        .unwrap(),
+                    "products"
+                );
+                Ok(subgraph::Response::fake_builder().build())
+            });
+
+        harness("client_name.wat")
+            .await
+            .subgraph_service("products", downstream)
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_stops_modules_running_out_of_fuel() {
+        let mut downstream = MockSupergraphService::new();
+        downstream.expect_call().never();
+
+        let mut response = harness("infinite_loop.wat")
+            .await
+            .supergraph_service(downstream)
+            .oneshot(supergraph::Request::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.response.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let error = &response.next_response().await.unwrap().errors[0];
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&LIMIT_EXCEEDED_CODE.into())
+        );
+        assert_eq!(error.extensions.get("limit"), Some(&"operations".into()));
+    }
+
+    #[tokio::test]
+    async fn it_stops_modules_exceeding_their_timeout() {
+        let mut downstream = MockSupergraphService::new();
+        downstream.expect_call().never();
+
+        let mut response = PluginTestHarness::<Wasm>::new(json!({
+            "modules": [{
+                "path": "tests/fixtures/wasm/infinite_loop.wat",
+                "fuel": 1_000_000_000_000_000u64,
+                "timeout": "50ms"
+            }]
+        }))
+        .await
+        .unwrap()
+        .supergraph_service(downstream)
+        .oneshot(supergraph::Request::fake_builder().build().unwrap())
+        .await
+        .unwrap();
+
+        let error = &response.next_response().await.unwrap().errors[0];
+        assert_eq!(error.extensions.get("limit"), Some(&"timeout".into()));
+    }
+
+    #[tokio::test]
+    async fn it_fails_on_missing_modules() {
+        assert!(PluginTestHarness::<Wasm>::new(json!({
+            "modules": [{ "path": "tests/fixtures/wasm/missing.wasm" }]
+        }))
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Progressive override of fields
+//!
+//! A subgraph can take over a field from another one with `@override(from: "...", label: "...")`.
+//! The supergraph schema then keeps the field in both subgraphs, with `@join__field` directives
+//! carrying the `overrideLabel`: the field is resolved by the overriding subgraph when the label
+//! is enabled, and by the original one otherwise.
+//!
+//! Labels are enabled per request, by a percentage of the requests or a header set in the
+//! configuration, or by their own `percent(<value>)` name. Each combination of labels has its own
+//! variant of the supergraph schema, where the enabled overrides are applied and the others are
+//! rolled back, and the requests are planned and executed with the variant of their labels.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use apollo_parser::ast;
+use apollo_parser::ast::AstNode;
+use rand::Rng;
+
+use crate::configuration::CanaryHeader;
+use crate::configuration::OverrideLabel;
+use crate::services::SupergraphRequest;
+
+/// Each combination of labels needs its own query planner, so their number is limited
+pub(crate) const MAX_OVERRIDE_LABELS: usize = 3;
+
+/// Context key for the override labels enabled for the request
+pub(crate) const OVERRIDE_LABELS_CONTEXT_KEY: &str = "apollo_router::supergraph::override_labels";
+
+const JOIN_FIELD: &str = "join__field";
+const OVERRIDE_LABEL: &str = "overrideLabel";
+
+/// A `@join__field` directive with an `overrideLabel`
+struct LabeledJoinField {
+    label: String,
+    /// Whether the directive is the one of the overriding subgraph
+    overrides: bool,
+    directive: Range<usize>,
+    label_argument: Range<usize>,
+}
+
+fn range(node: &impl AstNode) -> Range<usize> {
+    let range = node.syntax().text_range();
+    range.start().into()..range.end().into()
+}
+
+fn labeled_join_fields(sdl: &str) -> Vec<LabeledJoinField> {
+    let tree = apollo_parser::Parser::new(sdl).parse();
+    tree.document()
+        .syntax()
+        .descendants()
+        .filter_map(ast::Directive::cast)
+        .filter(|directive| {
+            directive
+                .name()
+                .map(|name| name.text().to_string())
+                .as_deref()
+                == Some(JOIN_FIELD)
+        })
+        .filter_map(|directive| {
+            let arguments = directive.arguments()?.arguments().collect::<Vec<_>>();
+            let argument_name =
+                |argument: &ast::Argument| argument.name().map(|name| name.text().to_string());
+            let label_argument = arguments
+                .iter()
+                .find(|argument| argument_name(argument).as_deref() == Some(OVERRIDE_LABEL))?;
+            let label = match label_argument.value()? {
+                ast::Value::StringValue(value) => value.into(),
+                _ => return None,
+            };
+            Some(LabeledJoinField {
+                label,
+                overrides: arguments
+                    .iter()
+                    .any(|argument| argument_name(argument).as_deref() == Some("override")),
+                directive: range(&directive),
+                label_argument: range(label_argument),
+            })
+        })
+        .collect()
+}
+
+/// The override labels of a supergraph schema
+pub(crate) fn labels(sdl: &str) -> BTreeSet<String> {
+    labeled_join_fields(sdl)
+        .into_iter()
+        .map(|join_field| join_field.label)
+        .collect()
+}
+
+/// The variant of a supergraph schema where the overrides of the enabled labels are applied, and
+/// the others are rolled back
+pub(crate) fn with_enabled_labels(sdl: &str, enabled: &BTreeSet<String>) -> String {
+    let mut removed = labeled_join_fields(sdl)
+        .into_iter()
+        .map(|join_field| {
+            // the directive of the subgraph that does not resolve the field is removed, the other
+            // one becomes an unconditional `@join__field`
+            if join_field.overrides == enabled.contains(&join_field.label) {
+                join_field.label_argument
+            } else {
+                join_field.directive
+            }
+        })
+        .collect::<Vec<_>>();
+    removed.sort_by_key(|range| range.start);
+
+    let mut variant = String::with_capacity(sdl.len());
+    let mut start = 0;
+    for range in removed {
+        variant.push_str(&sdl[start..range.start]);
+        start = range.end;
+    }
+    variant.push_str(&sdl[start..]);
+    variant
+}
+
+/// Enables an override label for some requests
+#[derive(Debug)]
+pub(crate) struct LabelSelector {
+    pub(crate) label: String,
+    percentage: f64,
+    header: Option<CanaryHeader>,
+}
+
+impl LabelSelector {
+    /// `percent(<value>)` labels enable themselves for this percentage of requests, if they are
+    /// not configured
+    pub(crate) fn new(label: &str, configuration: Option<&OverrideLabel>) -> Self {
+        let percentage = match configuration {
+            Some(configuration) => configuration.percentage,
+            None => label
+                .strip_prefix("percent(")
+                .and_then(|percentage| percentage.strip_suffix(')'))
+                .and_then(|percentage| percentage.trim().parse::<f64>().ok())
+                .unwrap_or_default(),
+        };
+        LabelSelector {
+            label: label.to_string(),
+            percentage: percentage.clamp(0.0, 100.0),
+            header: configuration.and_then(|configuration| configuration.header.clone()),
+        }
+    }
+
+    pub(crate) fn enables(&self, request: &SupergraphRequest) -> bool {
+        let header = self.header.as_ref().and_then(|header| {
+            let value = request
+                .supergraph_request
+                .headers()
+                .get(header.name.as_str())?;
+            Some(match &header.value {
+                Some(expected) => value.as_bytes() == expected.as_bytes(),
+                None => true,
+            })
+        });
+        header.unwrap_or(false)
+            || (self.percentage > 0.0 && rand::thread_rng().gen_bool(self.percentage / 100.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::supergraph;
+
+    const SDL: &str = r#"
+type Product
+  @join__type(graph: INVENTORY, key: "upc")
+  @join__type(graph: PRODUCTS, key: "upc")
+{
+  upc: String!
+  price: Int @join__field(graph: INVENTORY, override: "products", overrideLabel: "migrate-price") @join__field(graph: PRODUCTS, overrideLabel: "migrate-price")
+}
+"#;
+
+    #[test]
+    fn it_applies_or_rolls_back_overrides() {
+        assert_eq!(labels(SDL), BTreeSet::from(["migrate-price".to_string()]));
+
+        let enabled = with_enabled_labels(SDL, &BTreeSet::from(["migrate-price".to_string()]));
+        assert!(enabled.contains(r#"@join__field(graph: INVENTORY, override: "products""#));
+        assert!(!enabled.contains("graph: PRODUCTS, overrideLabel"));
+        assert!(!enabled.contains(OVERRIDE_LABEL));
+
+        let disabled = with_enabled_labels(SDL, &BTreeSet::new());
+        assert!(disabled.contains("@join__field(graph: PRODUCTS"));
+        assert!(!disabled.contains("graph: INVENTORY, override"));
+        assert!(!disabled.contains(OVERRIDE_LABEL));
+    }
+
+    #[test]
+    fn it_selects_requests_by_label() {
+        let request = supergraph::Request::fake_builder()
+            .header("x-migrate-price", "true")
+            .build()
+            .unwrap();
+        let no_header = supergraph::Request::fake_builder().build().unwrap();
+
+        assert!(LabelSelector::new("percent(100)", None).enables(&no_header));
+        assert!(!LabelSelector::new("percent(0)", None).enables(&no_header));
+        assert!(!LabelSelector::new("migrate-price", None).enables(&request));
+
+        let configuration = OverrideLabel {
+            percentage: 0.0,
+            header: Some(CanaryHeader {
+                name: "x-migrate-price".to_string(),
+                value: Some("true".to_string()),
+            }),
+        };
+        let selector = LabelSelector::new("migrate-price", Some(&configuration));
+        assert!(selector.enables(&request));
+        assert!(!selector.enables(&no_header));
+    }
+}
@@ -12,15 +12,20 @@ use router_bridge::planner::QueryPlannerConfig;
 use router_bridge::planner::UsageReporting;
 use serde::Deserialize;
 use serde_json_bytes::json;
+use sha2::Digest;
+use sha2::Sha256;
 use tower::Service;
 use tracing::Instrument;
 
+use super::OperationKind;
 use super::PlanNode;
 use super::QueryKey;
 use super::QueryPlanOptions;
+use crate::compute_job;
 use crate::error::QueryPlannerError;
 use crate::graphql;
 use crate::introspection::Introspection;
+use crate::plugins::router_fields;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::services::QueryPlannerContent;
 use crate::services::QueryPlannerRequest;
@@ -28,6 +33,7 @@ use crate::services::QueryPlannerResponse;
 use crate::spec::query::TYPENAME;
 use crate::spec::Query;
 use crate::spec::Schema;
+use crate::spec::SpecError;
 use crate::Configuration;
 
 pub(crate) static USAGE_REPORTING: &str = "apollo_telemetry::usage_reporting";
@@ -39,6 +45,10 @@ pub(crate) static USAGE_REPORTING: &str = "apollo_telemetry::usage_reporting";
 pub(crate) struct BridgeQueryPlanner {
     planner: Arc<Planner<QueryPlanResult>>,
     schema: Arc<Schema>,
+    /// The supergraph schema, extended with the router fields
+    introspection_schema: Arc<String>,
+    /// Identifies the introspection schema in the introspection cache
+    introspection_schema_id: String,
     introspection: Option<Arc<Introspection>>,
     configuration: Arc<Configuration>,
     deduplicate_variables: bool,
@@ -53,6 +63,15 @@ impl BridgeQueryPlanner {
         // FIXME: The variables deduplication parameter lives in the traffic_shaping section of the config
         let deduplicate_variables =
             TrafficShaping::get_configuration_deduplicate_variables(&configuration);
+        let introspection_schema = match router_fields::schema_extension(
+            &configuration,
+            schema.root_operation_name(OperationKind::Query),
+        ) {
+            Some(extension) => Arc::new(format!("{}\n{extension}", schema.as_string())),
+            None => schema.as_string().clone(),
+        };
+        let introspection_schema_id =
+            format!("{:x}", Sha256::digest(introspection_schema.as_bytes()));
         Ok(Self {
             planner: Arc::new(
                 Planner::new(
@@ -66,23 +85,46 @@ impl BridgeQueryPlanner {
                 .await?,
             ),
             schema,
+            introspection_schema,
+            introspection_schema_id,
             introspection,
             configuration,
             deduplicate_variables,
         })
     }
 
+    /// Removes the cached introspection responses
+    pub(crate) async fn clear_introspection_cache(&self) {
+        if let Some(introspection) = &self.introspection {
+            introspection.clear().await;
+        }
+    }
+
     async fn parse_selections(&self, query: String) -> Result<Query, QueryPlannerError> {
         let schema = self.schema.clone();
         let configuration = self.configuration.clone();
-        let query_parsing_future =
-            tokio::task::spawn_blocking(move || Query::parse(query, &schema, &configuration))
-                .instrument(tracing::info_span!("parse_query", "otel.kind" = "INTERNAL"));
+        let query_parsing_future = compute_job::execute(move || {
+            #[cfg(feature = "allocation_metrics")]
+            {
+                let (query, allocations) =
+                    crate::allocation::measure(|| Query::parse(query, &schema, &configuration));
+                tracing::info!(
+                    histogram.apollo_router_query_parsing_allocations = allocations.count,
+                );
+                tracing::info!(
+                    histogram.apollo_router_query_parsing_allocated_bytes = allocations.bytes,
+                );
+                query
+            }
+            #[cfg(not(feature = "allocation_metrics"))]
+            Query::parse(query, &schema, &configuration)
+        })
+        .instrument(tracing::info_span!("parse_query", "otel.kind" = "INTERNAL"));
         match query_parsing_future.await {
             Ok(res) => res.map_err(QueryPlannerError::from),
             Err(err) => {
                 failfast_debug!("parsing query task failed: {}", err);
-                Err(QueryPlannerError::from(err))
+                Err(QueryPlannerError::JoinError(err.to_string()))
             }
         }
     }
@@ -91,9 +133,12 @@ impl BridgeQueryPlanner {
         match self.introspection.as_ref() {
             Some(introspection) => {
                 let response = introspection
-                    .execute(self.schema.as_string(), query)
-                    .await
-                    .map_err(QueryPlannerError::Introspection)?;
+                    .execute(
+                        &self.introspection_schema_id,
+                        &self.introspection_schema,
+                        query,
+                    )
+                    .await?;
 
                 Ok(QueryPlannerContent::Introspection {
                     response: Box::new(response),
@@ -223,6 +268,13 @@ impl Service<QueryPlannerRequest> for BridgeQueryPlanner {
 impl BridgeQueryPlanner {
     async fn get(&self, key: QueryKey) -> Result<QueryPlannerContent, QueryPlannerError> {
         let selections = self.parse_selections(key.0.clone()).await?;
+        if selections
+            .operations
+            .iter()
+            .any(|operation| *operation.kind() == OperationKind::Subscription)
+        {
+            return Err(SpecError::SubscriptionNotSupported.into());
+        }
 
         if selections.contains_introspection() {
             // If we have only one operation containing a single root field `__typename`
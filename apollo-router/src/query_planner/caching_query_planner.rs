@@ -1,7 +1,6 @@
 // This entire file is license key functionality
 
 use std::collections::HashMap;
-use std::ops::Deref;
 use std::sync::Arc;
 use std::task;
 
@@ -12,18 +11,30 @@ use serde_json_bytes::value::Serializer;
 use tower::ServiceExt;
 use tracing::Instrument;
 
+use super::normalization;
+use super::BridgeQueryPlanner;
 use super::USAGE_REPORTING;
 use crate::cache::DeduplicatingCache;
 use crate::error::CacheResolverError;
 use crate::error::QueryPlannerError;
+use crate::plugins::feature_flags;
+use crate::plugins::feature_flags::QUERY_PLAN_CACHE_FLAG;
 use crate::services::QueryPlannerContent;
 use crate::services::QueryPlannerRequest;
 use crate::services::QueryPlannerResponse;
 use crate::Context;
 
+/// Context entry set to `true` if the query plan of the request was found in the cache
+pub(crate) const QUERY_PLAN_CACHE_HIT_CONTEXT_KEY: &str = "apollo_router::query_planner::cache_hit";
+/// Context entry containing the normalized document of the request, which identifies the
+/// operations sharing a query plan
+pub(crate) const OPERATION_SIGNATURE_CONTEXT_KEY: &str =
+    "apollo_router::query_planner::operation_signature";
+
 /// A query planner wrapper that caches results.
 ///
-/// The query planner performs LRU caching.
+/// The query planner performs LRU caching. Queries are cached by their normalized form, so that
+/// cosmetically different documents share their query plan.
 #[derive(Clone)]
 pub(crate) struct CachingQueryPlanner<T: Clone> {
     cache: Arc<
@@ -31,6 +42,7 @@ pub(crate) struct CachingQueryPlanner<T: Clone> {
     >,
     delegate: T,
     schema_id: Option<String>,
+    parser_recursion_limit: usize,
 }
 
 impl<T: Clone + 'static> CachingQueryPlanner<T>
@@ -45,16 +57,34 @@ where
     pub(crate) async fn new(
         delegate: T,
         schema_id: Option<String>,
-        config: &crate::configuration::QueryPlanning,
+        configuration: &crate::Configuration,
     ) -> CachingQueryPlanner<T> {
         let cache = Arc::new(
-            DeduplicatingCache::from_configuration(&config.experimental_cache, "query planner")
-                .await,
+            DeduplicatingCache::from_configuration(
+                &configuration.supergraph.query_planning.experimental_cache,
+                "query planner",
+            )
+            .await,
         );
         Self {
             cache,
             delegate,
             schema_id,
+            parser_recursion_limit: configuration.server.experimental_parser_recursion_limit,
+        }
+    }
+
+    /// The cache key of a query, from its normalized form if it parses
+    fn caching_key(&self, query: &str, operation: Option<&str>) -> CachingQueryKey {
+        let (query, operation) =
+            match normalization::normalize(query, operation, self.parser_recursion_limit) {
+                Some(normalized) => (normalized.query, normalized.operation),
+                None => (query.to_string(), operation.map(str::to_string)),
+            };
+        CachingQueryKey {
+            schema_id: self.schema_id.clone(),
+            query,
+            operation,
         }
     }
 
@@ -67,15 +97,9 @@ where
     }
 
     pub(crate) async fn warm_up(&mut self, cache_keys: Vec<(String, Option<String>)>) {
-        let schema_id = self.schema_id.clone();
-
         let mut count = 0usize;
         for (query, operation) in cache_keys {
-            let caching_key = CachingQueryKey {
-                schema_id: schema_id.clone(),
-                query: query.clone(),
-                operation: operation.to_owned(),
-            };
+            let caching_key = self.caching_key(&query, operation.as_deref());
             let context = Context::new();
 
             let entry = self.cache.get(&caching_key).await;
@@ -111,6 +135,14 @@ where
     }
 }
 
+impl CachingQueryPlanner<BridgeQueryPlanner> {
+    /// Removes the cached query plans and introspection responses
+    pub(crate) async fn clear(&self) {
+        self.cache.clear().await;
+        self.delegate.clear_introspection_cache().await;
+    }
+}
+
 impl<T: Clone + Send + 'static> tower::Service<QueryPlannerRequest> for CachingQueryPlanner<T>
 where
     T: tower::Service<
@@ -130,16 +162,30 @@ where
 
     fn call(&mut self, request: QueryPlannerRequest) -> Self::Future {
         let mut qp = self.clone();
-        let schema_id = self.schema_id.clone();
         Box::pin(async move {
-            let caching_key = CachingQueryKey {
-                schema_id,
-                query: request.query.clone(),
-                operation: request.operation_name.to_owned(),
-            };
+            let caching_key = qp.caching_key(&request.query, request.operation_name.as_deref());
 
             let context = request.context.clone();
+            let _ = context.insert(OPERATION_SIGNATURE_CONTEXT_KEY, caching_key.query.clone());
+
+            if feature_flags::flag(&context, QUERY_PLAN_CACHE_FLAG) == Some(false) {
+                let _ = context.insert(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY, false);
+                return match qp.delegate.ready().await?.call(request).await {
+                    Ok(response) => {
+                        if let Some(content) = &response.content {
+                            insert_plan_usage_reporting(&response.context, content);
+                        }
+                        Ok(response)
+                    }
+                    Err(error) => {
+                        insert_error_usage_reporting(&context, &error);
+                        Err(error.into())
+                    }
+                };
+            }
+
             let entry = qp.cache.get(&caching_key).await;
+            let _ = context.insert(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY, !entry.is_first());
             if entry.is_first() {
                 // some clients might timeout and cancel the request before query planning is finished,
                 // so we execute it in a task that can continue even after the request was canceled and
@@ -159,18 +205,8 @@ where
                                     entry.insert(Ok(content.clone())).await;
                                 }
 
-                                if let Some(QueryPlannerContent::Plan { plan, .. }) = &content {
-                                    match (plan.usage_reporting).serialize(Serializer) {
-                                        Ok(v) => {
-                                            context.insert_json_value(USAGE_REPORTING, v);
-                                        }
-                                        Err(e) => {
-                                            tracing::error!(
-                                            "usage reporting was not serializable to context, {}",
-                                            e
-                                        );
-                                        }
-                                    }
+                                if let Some(content) = &content {
+                                    insert_plan_usage_reporting(&context, content);
                                 }
                                 Ok(QueryPlannerResponse {
                                     content,
@@ -201,19 +237,7 @@ where
 
                 match res {
                     Ok(content) => {
-                        if let QueryPlannerContent::Plan { plan, .. } = &content {
-                            match (plan.usage_reporting).serialize(Serializer) {
-                                Ok(v) => {
-                                    context.insert_json_value(USAGE_REPORTING, v);
-                                }
-                                Err(e) => {
-                                    tracing::error!(
-                                        "usage reporting was not serializable to context, {}",
-                                        e
-                                    );
-                                }
-                            }
-                        }
+                        insert_plan_usage_reporting(&context, &content);
 
                         Ok(QueryPlannerResponse::builder()
                             .content(content)
@@ -221,35 +245,7 @@ where
                             .build())
                     }
                     Err(error) => {
-                        match error.deref() {
-                            QueryPlannerError::PlanningErrors(pe) => {
-                                if let Err(inner_e) = request
-                                    .context
-                                    .insert(USAGE_REPORTING, pe.usage_reporting.clone())
-                                {
-                                    tracing::error!(
-                                        "usage reporting was not serializable to context, {}",
-                                        inner_e
-                                    );
-                                }
-                            }
-                            QueryPlannerError::SpecError(e) => {
-                                if let Err(inner_e) = request.context.insert(
-                                    USAGE_REPORTING,
-                                    UsageReporting {
-                                        stats_report_key: e.get_error_key().to_string(),
-                                        referenced_fields_by_type: HashMap::new(),
-                                    },
-                                ) {
-                                    tracing::error!(
-                                        "usage reporting was not serializable to context, {}",
-                                        inner_e
-                                    );
-                                }
-                            }
-                            _ => {}
-                        }
-
+                        insert_error_usage_reporting(&request.context, &error);
                         Err(CacheResolverError::RetrievalError(error))
                     }
                 }
@@ -258,6 +254,33 @@ where
     }
 }
 
+fn insert_plan_usage_reporting(context: &Context, content: &QueryPlannerContent) {
+    if let QueryPlannerContent::Plan { plan, .. } = content {
+        match (plan.usage_reporting).serialize(Serializer) {
+            Ok(v) => {
+                context.insert_json_value(USAGE_REPORTING, v);
+            }
+            Err(e) => {
+                tracing::error!("usage reporting was not serializable to context, {}", e);
+            }
+        }
+    }
+}
+
+fn insert_error_usage_reporting(context: &Context, error: &QueryPlannerError) {
+    let usage_reporting = match error {
+        QueryPlannerError::PlanningErrors(pe) => pe.usage_reporting.clone(),
+        QueryPlannerError::SpecError(e) => UsageReporting {
+            stats_report_key: e.get_error_key().to_string(),
+            referenced_fields_by_type: HashMap::new(),
+        },
+        _ => return,
+    };
+    if let Err(e) = context.insert(USAGE_REPORTING, usage_reporting) {
+        tracing::error!("usage reporting was not serializable to context, {}", e);
+    }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub(crate) struct CachingQueryKey {
     pub(crate) schema_id: Option<String>,
@@ -342,12 +365,7 @@ mod tests {
             planner
         });
 
-        let mut planner = CachingQueryPlanner::new(
-            delegate,
-            None,
-            &crate::configuration::QueryPlanning::default(),
-        )
-        .await;
+        let mut planner = CachingQueryPlanner::new(delegate, None, &Default::default()).await;
 
         for _ in 0..5 {
             assert!(planner
@@ -403,12 +421,7 @@ mod tests {
             planner
         });
 
-        let mut planner = CachingQueryPlanner::new(
-            delegate,
-            None,
-            &crate::configuration::QueryPlanning::default(),
-        )
-        .await;
+        let mut planner = CachingQueryPlanner::new(delegate, None, &Default::default()).await;
 
         for _ in 0..5 {
             assert!(planner
@@ -426,4 +439,108 @@ mod tests {
                 .is_some());
         }
     }
+
+    #[test(tokio::test)]
+    async fn test_normalized_cache_keys() {
+        let mut delegate = MockMyQueryPlanner::new();
+        delegate.expect_clone().returning(|| {
+            let mut planner = MockMyQueryPlanner::new();
+            planner.expect_sync_call().times(0..2).returning(|_| {
+                Err(QueryPlannerError::from(PlanErrors {
+                    errors: Default::default(),
+                    usage_reporting: UsageReporting {
+                        stats_report_key: "this is a test key".to_string(),
+                        referenced_fields_by_type: Default::default(),
+                    },
+                }))
+            });
+            planner
+        });
+
+        let mut planner = CachingQueryPlanner::new(delegate, None, &Default::default()).await;
+
+        for (query, operation_name, cache_hit) in [
+            ("query Me { me(id: 1, name: \"a\") { id } }", None, false),
+            (
+                "query Me {\n  me(name: \"a\", id: 1) {\n    id\n  }\n}",
+                None,
+                true,
+            ),
+            (
+                "query Me { me(id: 1 name: \"a\") { id } }",
+                Some("Me"),
+                true,
+            ),
+        ] {
+            let context = Context::new();
+            let _ = planner
+                .call(QueryPlannerRequest::new(
+                    query.into(),
+                    operation_name.map(str::to_string),
+                    context.clone(),
+                ))
+                .await;
+            assert_eq!(
+                context
+                    .get::<_, bool>(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY)
+                    .unwrap(),
+                Some(cache_hit)
+            );
+            assert_eq!(
+                context
+                    .get::<_, String>(OPERATION_SIGNATURE_CONTEXT_KEY)
+                    .unwrap()
+                    .as_deref(),
+                Some(r#"query Me{me(id:1 name:"a"){id}}"#)
+            );
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_query_plan_cache_flag() {
+        let mut delegate = MockMyQueryPlanner::new();
+        delegate.expect_clone().returning(|| {
+            let mut planner = MockMyQueryPlanner::new();
+            planner.expect_sync_call().times(0..2).returning(|_| {
+                Err(QueryPlannerError::from(PlanErrors {
+                    errors: Default::default(),
+                    usage_reporting: UsageReporting {
+                        stats_report_key: "this is a test key".to_string(),
+                        referenced_fields_by_type: Default::default(),
+                    },
+                }))
+            });
+            planner
+        });
+
+        let mut planner = CachingQueryPlanner::new(delegate, None, &Default::default()).await;
+
+        for _ in 0..2 {
+            let context = Context::new();
+            context
+                .insert(
+                    crate::plugins::feature_flags::FEATURE_FLAGS_CONTEXT_KEY,
+                    HashMap::from([(QUERY_PLAN_CACHE_FLAG.to_string(), false)]),
+                )
+                .unwrap();
+            assert!(planner
+                .call(QueryPlannerRequest::new(
+                    "query1".into(),
+                    None,
+                    context.clone()
+                ))
+                .await
+                .is_err());
+            assert_eq!(
+                context
+                    .get::<_, bool>(QUERY_PLAN_CACHE_HIT_CONTEXT_KEY)
+                    .unwrap(),
+                Some(false)
+            );
+            assert!(context
+                .get::<_, UsageReporting>(USAGE_REPORTING)
+                .unwrap()
+                .is_some());
+        }
+    }
 }
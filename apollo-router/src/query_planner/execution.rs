@@ -420,6 +420,9 @@ impl DeferredNode {
             let deferred_fetches = HashMap::new();
 
             if let Some(node) = deferred_inner {
+                // responses of the fragments deferred inside this one are held until this response
+                // is sent: their paths may point into data the client does not have yet
+                let (nested_sender, mut nested_receiver) = futures::channel::mpsc::channel(10);
                 let (mut v, node_subselection, err) = node
                     .execute_recursively(
                         &ExecutionParameters {
@@ -433,7 +436,7 @@ impl DeferredNode {
                         },
                         &Path::default(),
                         &value,
-                        tx.clone(),
+                        nested_sender,
                     )
                     .instrument(tracing::info_span!(
                         DEFER_DEFERRED_SPAN_NAME,
@@ -469,6 +472,16 @@ impl DeferredNode {
                         e
                     );
                 };
+
+                while let Some(response) = nested_receiver.next().await {
+                    if let Err(e) = tx.send(response).await {
+                        tracing::error!(
+                            "error sending nested deferred response under path {}: {:?}",
+                            deferred_path,
+                            e
+                        );
+                    }
+                }
                 tx.disconnect();
             } else {
                 let (primary_value, primary_errors) =
@@ -10,6 +10,8 @@ use tracing::instrument;
 use tracing::Instrument;
 
 use super::execution::ExecutionParameters;
+use super::rewrites;
+use super::rewrites::DataRewrite;
 use super::selection::select_object;
 use super::selection::Selection;
 use crate::error::Error;
@@ -83,8 +85,21 @@ pub(crate) struct FetchNode {
 
     /// Optional id used by Deferred nodes
     pub(crate) id: Option<String>,
+
+    /// Rewrites of the entity representations sent to the subgraph
+    #[serde(default)]
+    pub(crate) input_rewrites: Option<Vec<DataRewrite>>,
+
+    /// Rewrites of the entities returned by the subgraph
+    #[serde(default)]
+    pub(crate) output_rewrites: Option<Vec<DataRewrite>>,
 }
 
+/// Path in the client response of the data requested by a fetch, in the extensions of the
+/// subgraph request of the fetch. The entities of an entity fetch are found at this path
+#[derive(Clone, Debug)]
+pub(crate) struct FetchPath(pub(crate) Path);
+
 struct Variables {
     variables: Object,
     paths: HashMap<Path, usize>,
@@ -92,8 +107,10 @@ struct Variables {
 
 impl Variables {
     #[instrument(skip_all, level = "debug", name = "make_variables")]
+    #[allow(clippy::too_many_arguments)]
     async fn new(
         requires: &[Selection],
+        input_rewrites: &Option<Vec<DataRewrite>>,
         variable_usages: &[String],
         data: &Value,
         current_dir: &Path,
@@ -116,7 +133,8 @@ impl Variables {
                 let mut values: IndexSet<Value> = IndexSet::new();
                 data.select_values_and_paths(current_dir, |path, value| {
                     if let Value::Object(content) = value {
-                        if let Ok(Some(value)) = select_object(content, requires, schema) {
+                        if let Ok(Some(mut value)) = select_object(content, requires, schema) {
+                            rewrites::apply_rewrites(schema, &mut value, input_rewrites);
                             match values.get_index_of(&value) {
                                 Some(index) => {
                                     paths.insert(path.clone(), index);
@@ -139,7 +157,8 @@ impl Variables {
                 let mut values: Vec<Value> = Vec::new();
                 data.select_values_and_paths(current_dir, |path, value| {
                     if let Value::Object(content) = value {
-                        if let Ok(Some(value)) = select_object(content, requires, schema) {
+                        if let Ok(Some(mut value)) = select_object(content, requires, schema) {
+                            rewrites::apply_rewrites(schema, &mut value, input_rewrites);
                             paths.insert(path.clone(), values.len());
                             values.push(value);
                         }
@@ -203,6 +222,7 @@ impl FetchNode {
 
         let Variables { variables, paths } = match Variables::new(
             &self.requires,
+            &self.input_rewrites,
             self.variable_usages.as_ref(),
             data,
             current_dir,
@@ -219,7 +239,14 @@ impl FetchNode {
             }
         };
 
-        let subgraph_request = SubgraphRequest::builder()
+        // the variables, with the entity representations, are only kept for trace logs
+        let redactor = parameters.service_factory.redactor.as_deref();
+        let traced_variables = super::log::subfetch_enabled().then(|| match redactor {
+            Some(redactor) => redactor.variables(&variables),
+            None => variables.clone(),
+        });
+
+        let mut subgraph_request = SubgraphRequest::builder()
             .supergraph_request(parameters.supergraph_request.clone())
             .subgraph_request(
                 http_ext::Request::builder()
@@ -241,7 +268,7 @@ impl FetchNode {
                         Request::builder()
                             .query(operation)
                             .and_operation_name(operation_name.clone())
-                            .variables(variables.clone())
+                            .variables(variables)
                             .build(),
                     )
                     .build()
@@ -250,6 +277,10 @@ impl FetchNode {
             .operation_kind(*operation_kind)
             .context(parameters.context.clone())
             .build();
+        subgraph_request
+            .subgraph_request
+            .extensions_mut()
+            .insert(FetchPath(current_dir.clone()));
 
         let service = parameters
             .service_factory
@@ -265,14 +296,13 @@ impl FetchNode {
             // when errors have been redacted in the include_subgraph_errors module.
             // Unfortunately, not easy to fix here, because at this point we don't
             // know if we should be redacting errors for this subgraph...
-            .map_err(|e| FetchError::SubrequestHttpError {
-                service: service_name.to_string(),
-                reason: e.to_string(),
-            })?
+            .map_err(|e| FetchError::from_subrequest_error(service_name, e))?
             .response
             .into_parts();
 
-        super::log::trace_subfetch(service_name, operation, &variables, &response);
+        if let Some(variables) = traced_variables {
+            super::log::trace_subfetch(service_name, operation, &variables, &response, redactor);
+        }
 
         if !response.is_primary() {
             return Err(FetchError::SubrequestUnexpectedPatchResponse {
@@ -280,7 +310,8 @@ impl FetchNode {
             });
         }
 
-        let (value, errors) = self.response_at_path(current_dir, paths, response);
+        let (value, errors) =
+            self.response_at_path(parameters.schema, data, current_dir, paths, response);
         if let Some(id) = &self.id {
             if let Some(sender) = parameters.deferred_fetches.get(id.as_str()) {
                 if let Err(e) = sender.clone().send((value.clone(), errors.clone())) {
@@ -294,6 +325,8 @@ impl FetchNode {
     #[instrument(skip_all, level = "debug", name = "response_insert")]
     fn response_at_path<'a>(
         &'a self,
+        schema: &Schema,
+        data: &Value,
         current_dir: &'a Path,
         paths: HashMap<Path, usize>,
         response: graphql::Response,
@@ -352,7 +385,18 @@ impl FetchNode {
 
                         for (path, entity_idx) in paths {
                             if let Some(entity) = array.get(entity_idx) {
-                                let _ = value.insert(&path, entity.clone());
+                                let mut entity = entity.clone();
+                                rewrites::apply_rewrites(
+                                    schema,
+                                    &mut entity,
+                                    &self.output_rewrites,
+                                );
+                                rewrites::keep_concrete_typename(
+                                    schema,
+                                    data.get_path(&path).ok(),
+                                    &mut entity,
+                                );
+                                let _ = value.insert(&path, entity);
                             }
                         }
                         return (value, errors);
@@ -402,7 +446,6 @@ impl FetchNode {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn service_name(&self) -> &str {
         &self.service_name
     }
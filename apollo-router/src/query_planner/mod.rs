@@ -11,7 +11,9 @@ mod bridge_query_planner;
 mod caching_query_planner;
 mod execution;
 pub(crate) mod fetch;
+pub(crate) mod normalization;
 mod plan;
+pub(crate) mod rewrites;
 mod selection;
 pub use plan::*;
 
@@ -35,23 +37,38 @@ mod log {
     use serde_json_bytes::Value;
 
     use crate::query_planner::PlanNode;
+    use crate::redaction::Redactor;
 
     pub(crate) fn trace_query_plan(plan: &PlanNode) {
         tracing::trace!("query plan\n{:?}", plan);
     }
 
+    /// Whether subgraph fetches are logged, so their variables must be kept
+    pub(crate) fn subfetch_enabled() -> bool {
+        tracing::enabled!(tracing::Level::TRACE)
+    }
+
     pub(crate) fn trace_subfetch(
         service_name: &str,
         operation: &str,
         variables: &Map<ByteString, Value>,
         response: &crate::graphql::Response,
+        redactor: Option<&Redactor>,
     ) {
+        // the paths of redacted fields are relative to the client response, they cannot be
+        // found in subgraph responses
+        let response = match redactor {
+            Some(redactor) if redactor.redacts_response_fields() => {
+                crate::redaction::REDACTED.to_string()
+            }
+            _ => serde_json::to_string_pretty(&response).unwrap(),
+        };
         tracing::trace!(
             "subgraph fetch to {}: operation = '{}', variables = {:?}, response:\n{}",
             service_name,
             operation,
             variables,
-            serde_json::to_string_pretty(&response).unwrap()
+            response
         );
     }
 }
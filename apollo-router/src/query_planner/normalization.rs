@@ -0,0 +1,645 @@
+//! Normalization of operations for the query plan cache
+//!
+//! Clients generate documents that only differ by their formatting, the order of arguments, or
+//! whether the request names the single operation of the document. They have the same query plan,
+//! so the query plan cache is keyed by a normalized form of the operation. The normalized document
+//! is also the signature of the operation exposed in the request context.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use apollo_parser::ast;
+use apollo_parser::ast::AstNode;
+use apollo_parser::SyntaxNode;
+
+use crate::configuration::default_parser_recursion_limit;
+
+/// An operation in its normalized form
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct NormalizedOperation {
+    /// The document without comments, commas and insignificant whitespace, with the arguments of
+    /// fields and directives sorted by name
+    pub(crate) query: String,
+    /// The requested operation name, removed if it names the single operation of the document
+    pub(crate) operation: Option<String>,
+}
+
+/// Normalizes an operation. Documents that do not parse are not normalized: their planning fails
+/// anyway
+pub(crate) fn normalize(
+    query: &str,
+    operation_name: Option<&str>,
+    recursion_limit: usize,
+) -> Option<NormalizedOperation> {
+    let tree = apollo_parser::Parser::new(query)
+        .recursion_limit(recursion_limit)
+        .parse();
+    if tree.errors().next().is_some() {
+        return None;
+    }
+    let document = tree.document();
+
+    let mut printer = Printer::default();
+    printer.print(document.syntax());
+
+    let mut operations = document
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        });
+    let single_operation_name = match (operations.next(), operations.next()) {
+        (Some(operation), None) => Some(operation.name().map(|name| name.text().to_string())),
+        _ => None,
+    };
+    let operation = match (single_operation_name, operation_name) {
+        (Some(_), None) => None,
+        (Some(Some(name)), Some(requested)) if name == requested => None,
+        (_, requested) => requested.map(str::to_string),
+    };
+
+    Some(NormalizedOperation {
+        query: printer.output,
+        operation,
+    })
+}
+
+/// The signature of a GraphQL operation
+///
+/// Operations that only differ by their formatting, the order of the arguments of their fields
+/// and directives, or whether the request names the single operation of their document, have the
+/// same signature. The router keys its query plan cache by signature, and exposes the signature of
+/// each request in the `apollo_router::query_planner::operation_signature` context entry: plugins
+/// and external tools can compute the same value with [`OperationSignature::new`].
+///
+/// The signature reported to Apollo Studio is coarser, since it also hides literals and aliases:
+/// it is available with [`OperationSignature::usage_reporting_signature`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OperationSignature {
+    normalized: NormalizedOperation,
+    usage_reporting: Option<String>,
+}
+
+impl OperationSignature {
+    /// Computes the signature of an operation, as the router does.
+    ///
+    /// Returns `None` if the document does not parse.
+    pub fn new(query: &str, operation_name: Option<&str>) -> Option<Self> {
+        Self::with_recursion_limit(query, operation_name, default_parser_recursion_limit())
+    }
+
+    /// Computes the signature of an operation, for a router configured with a
+    /// `server.experimental_parser_recursion_limit`.
+    pub fn with_recursion_limit(
+        query: &str,
+        operation_name: Option<&str>,
+        recursion_limit: usize,
+    ) -> Option<Self> {
+        let normalized = normalize(query, operation_name, recursion_limit)?;
+        let document = apollo_parser::Parser::new(query)
+            .recursion_limit(recursion_limit)
+            .parse()
+            .document();
+        Some(OperationSignature {
+            normalized,
+            usage_reporting: usage_reporting_signature(&document, operation_name),
+        })
+    }
+
+    /// The normalized document, as found in the context entry.
+    pub fn document(&self) -> &str {
+        &self.normalized.query
+    }
+
+    /// The operation name, if it is needed to select the operation in the document.
+    pub fn operation_name(&self) -> Option<&str> {
+        self.normalized.operation.as_deref()
+    }
+
+    /// The key of the operation in the usage reports sent to Apollo Studio: `# ` followed by the
+    /// operation name (`-` if it is anonymous), a line break and the usage reporting signature.
+    ///
+    /// Returns `None` if the document does not contain the requested operation.
+    pub fn usage_reporting_signature(&self) -> Option<&str> {
+        self.usage_reporting.as_deref()
+    }
+}
+
+/// Computes the usage reporting key of an operation, like Apollo's `usageReportingSignature`: the
+/// document is reduced to the operation and the fragments it uses, literals and aliases are
+/// hidden, definitions, selections, arguments and directives are sorted, then it is printed with
+/// reduced whitespace
+fn usage_reporting_signature(
+    document: &ast::Document,
+    operation_name: Option<&str>,
+) -> Option<String> {
+    let mut operations = document
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        });
+    let operation = match operation_name {
+        Some(requested) => operations.find(|operation| {
+            operation
+                .name()
+                .map(|name| name.text().to_string())
+                .as_deref()
+                == Some(requested)
+        })?,
+        None => match (operations.next(), operations.next()) {
+            (Some(operation), None) => operation,
+            _ => return None,
+        },
+    };
+
+    let fragments = document
+        .definitions()
+        .filter_map(|definition| match definition {
+            ast::Definition::FragmentDefinition(fragment) => {
+                let name = fragment.fragment_name()?.name()?.text().to_string();
+                Some((name, fragment))
+            }
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let mut used = HashSet::new();
+    let mut pending = vec![operation.syntax().clone()];
+    while let Some(node) = pending.pop() {
+        for spread in node.descendants().filter_map(ast::FragmentSpread::cast) {
+            if let Some(name) = spread.fragment_name().and_then(|name| name.name()) {
+                let name = name.text().to_string();
+                if let Some(fragment) = fragments.get(&name) {
+                    if used.insert(name) {
+                        pending.push(fragment.syntax().clone());
+                    }
+                }
+            }
+        }
+    }
+    let mut used = used.into_iter().collect::<Vec<_>>();
+    used.sort();
+
+    let mut definitions = used
+        .iter()
+        .map(|name| print_fragment_definition(&fragments[name]))
+        .collect::<Vec<_>>();
+    definitions.push(print_operation_definition(&operation));
+
+    let name = operation.name().map(|name| name.text().to_string());
+    Some(format!(
+        "# {}\n{}",
+        name.as_deref().unwrap_or("-"),
+        reduce_whitespace(&definitions.join("\n\n"))
+    ))
+}
+
+// The following functions print like graphql-js, whose whitespace is then reduced
+
+/// Maximum length of a field with its arguments before graphql-js prints one argument per line
+const MAX_LINE_LENGTH: usize = 80;
+
+fn print_operation_definition(operation: &ast::OperationDefinition) -> String {
+    let kind = match operation.operation_type() {
+        Some(kind) if kind.mutation_token().is_some() => "mutation",
+        Some(kind) if kind.subscription_token().is_some() => "subscription",
+        _ => "query",
+    };
+    let name = operation
+        .name()
+        .map(|name| name.text().to_string())
+        .unwrap_or_default();
+    let variables = print_variable_definitions(operation.variable_definitions());
+    let directives = print_directives(operation.directives(), false);
+    let prefix = join(
+        &[kind.to_string(), format!("{name}{variables}"), directives],
+        " ",
+    );
+    let selection_set = print_selection_set(operation.selection_set());
+    if prefix == "query" {
+        selection_set
+    } else {
+        format!("{prefix} {selection_set}")
+    }
+}
+
+fn print_fragment_definition(fragment: &ast::FragmentDefinition) -> String {
+    let name = fragment
+        .fragment_name()
+        .and_then(|name| name.name())
+        .map(|name| name.text().to_string())
+        .unwrap_or_default();
+    let type_condition = fragment
+        .type_condition()
+        .and_then(|condition| condition.named_type())
+        .map(|named| print_tokens(named.syntax()))
+        .unwrap_or_default();
+    let directives = print_directives(fragment.directives(), true);
+    let directives = if directives.is_empty() {
+        directives
+    } else {
+        format!("{directives} ")
+    };
+    format!(
+        "fragment {name} on {type_condition} {directives}{}",
+        print_selection_set(fragment.selection_set())
+    )
+}
+
+fn print_variable_definitions(definitions: Option<ast::VariableDefinitions>) -> String {
+    let mut definitions = definitions
+        .iter()
+        .flat_map(|definitions| definitions.variable_definitions())
+        .map(|definition| {
+            let name = definition
+                .variable()
+                .and_then(|variable| variable.name())
+                .map(|name| name.text().to_string())
+                .unwrap_or_default();
+            let ty = definition
+                .ty()
+                .map(|ty| print_tokens(ty.syntax()))
+                .unwrap_or_default();
+            let default = definition
+                .default_value()
+                .and_then(|default| default.value())
+                .map(|value| format!(" = {}", print_value(&value)))
+                .unwrap_or_default();
+            let directives = print_directives(definition.directives(), false);
+            let directives = if directives.is_empty() {
+                directives
+            } else {
+                format!(" {directives}")
+            };
+            (name.clone(), format!("${name}: {ty}{default}{directives}"))
+        })
+        .collect::<Vec<_>>();
+    definitions.sort_by(|(a, _), (b, _)| a.cmp(b));
+    wrap(
+        "(",
+        &definitions
+            .into_iter()
+            .map(|(_, definition)| definition)
+            .collect::<Vec<_>>()
+            .join(", "),
+        ")",
+    )
+}
+
+fn print_selection_set(selection_set: Option<ast::SelectionSet>) -> String {
+    // fields first, then fragment spreads, then inline fragments, each sorted by name
+    let mut selections = selection_set
+        .iter()
+        .flat_map(|selection_set| selection_set.selections())
+        .map(|selection| match selection {
+            ast::Selection::Field(field) => {
+                let name = field
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default();
+                let arguments = print_arguments(field.arguments());
+                let mut arguments_line = format!("{name}{}", wrap("(", &arguments.join(", "), ")"));
+                if arguments_line.len() > MAX_LINE_LENGTH {
+                    arguments_line = format!("{name}{}", wrap("(\n", &arguments.join("\n"), "\n)"));
+                }
+                let printed = join(
+                    &[
+                        arguments_line,
+                        print_directives(field.directives(), false),
+                        print_selection_set(field.selection_set()),
+                    ],
+                    " ",
+                );
+                ((0, Some(name)), printed)
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                let name = spread
+                    .fragment_name()
+                    .and_then(|name| name.name())
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default();
+                let directives = print_directives(spread.directives(), true);
+                let printed = format!("...{name}{}", wrap(" ", &directives, ""));
+                ((1, Some(name)), printed)
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                let type_condition = fragment
+                    .type_condition()
+                    .and_then(|condition| condition.named_type())
+                    .map(|named| format!("on {}", print_tokens(named.syntax())))
+                    .unwrap_or_default();
+                let printed = join(
+                    &[
+                        "...".to_string(),
+                        type_condition,
+                        print_directives(fragment.directives(), true),
+                        print_selection_set(fragment.selection_set()),
+                    ],
+                    " ",
+                );
+                // inline fragments have no name, and are sorted after the named selections
+                ((2, None), printed)
+            }
+        })
+        .collect::<Vec<_>>();
+    selections.sort_by(|(a, _), (b, _)| match (a, b) {
+        ((a_kind, Some(a_name)), (b_kind, Some(b_name))) => {
+            a_kind.cmp(b_kind).then_with(|| a_name.cmp(b_name))
+        }
+        ((a_kind, _), (b_kind, _)) => a_kind.cmp(b_kind),
+    });
+    if selections.is_empty() {
+        return String::new();
+    }
+    format!(
+        "{{\n{}\n}}",
+        selections
+            .into_iter()
+            .map(|(_, selection)| selection)
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn print_directives(directives: Option<ast::Directives>, sorted: bool) -> String {
+    let mut directives = directives
+        .iter()
+        .flat_map(|directives| directives.directives())
+        .map(|directive| {
+            let name = directive
+                .name()
+                .map(|name| name.text().to_string())
+                .unwrap_or_default();
+            let arguments = print_arguments(directive.arguments()).join(", ");
+            (
+                name.clone(),
+                format!("@{name}{}", wrap("(", &arguments, ")")),
+            )
+        })
+        .collect::<Vec<_>>();
+    if sorted {
+        directives.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+    directives
+        .into_iter()
+        .map(|(_, directive)| directive)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prints the arguments sorted by name
+fn print_arguments(arguments: Option<ast::Arguments>) -> Vec<String> {
+    let mut arguments = arguments
+        .iter()
+        .flat_map(|arguments| arguments.arguments())
+        .map(|argument| {
+            let name = argument
+                .name()
+                .map(|name| name.text().to_string())
+                .unwrap_or_default();
+            let value = argument
+                .value()
+                .map(|value| print_value(&value))
+                .unwrap_or_default();
+            (name.clone(), format!("{name}: {value}"))
+        })
+        .collect::<Vec<_>>();
+    arguments.sort_by(|(a, _), (b, _)| a.cmp(b));
+    arguments
+        .into_iter()
+        .map(|(_, argument)| argument)
+        .collect()
+}
+
+/// Prints a value with its literals hidden
+fn print_value(value: &ast::Value) -> String {
+    match value {
+        ast::Value::IntValue(_) | ast::Value::FloatValue(_) => "0".to_string(),
+        ast::Value::StringValue(_) => "\"\"".to_string(),
+        ast::Value::ListValue(_) => "[]".to_string(),
+        ast::Value::ObjectValue(_) => "{}".to_string(),
+        ast::Value::Variable(_)
+        | ast::Value::BooleanValue(_)
+        | ast::Value::NullValue(_)
+        | ast::Value::EnumValue(_) => print_tokens(value.syntax()),
+    }
+}
+
+fn print_tokens(node: &SyntaxNode) -> String {
+    let mut printer = Printer::default();
+    printer.print(node);
+    printer.output
+}
+
+/// graphql-js' `join`, which skips empty parts
+fn join(parts: &[String], separator: &str) -> String {
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// graphql-js' `wrap`, which only wraps non empty strings
+fn wrap(start: &str, content: &str, end: &str) -> String {
+    if content.is_empty() {
+        String::new()
+    } else {
+        format!("{start}{content}{end}")
+    }
+}
+
+/// Collapses whitespace to single spaces, then removes the spaces that are not between two word
+/// characters
+fn reduce_whitespace(printed: &str) -> String {
+    let collapsed = printed.split_whitespace().collect::<Vec<_>>().join(" ");
+    let is_word = |c: Option<char>| c.map_or(false, |c| c.is_ascii_alphanumeric() || c == '_');
+    let chars = collapsed.chars().collect::<Vec<_>>();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(index, c)| {
+            **c != ' '
+                || (is_word(index.checked_sub(1).map(|index| chars[index]))
+                    && is_word(chars.get(index + 1).copied()))
+        })
+        .map(|(_, c)| c)
+        .collect()
+}
+
+#[derive(Default)]
+struct Printer {
+    output: String,
+}
+
+impl Printer {
+    fn print(&mut self, node: &SyntaxNode) {
+        for child in node.children_with_tokens() {
+            if let Some(node) = child.as_node() {
+                if ast::Arguments::can_cast(node.kind()) {
+                    self.print_arguments(node);
+                } else {
+                    self.print(node);
+                }
+            } else if let Some(token) = child.as_token() {
+                // whitespace, comments and commas are ignored
+                let text = token.text();
+                if !(text.trim().is_empty() || text.starts_with('#') || text == ",") {
+                    self.push(text);
+                }
+            }
+        }
+    }
+
+    fn print_arguments(&mut self, node: &SyntaxNode) {
+        let mut arguments = node
+            .children()
+            .map(|argument| {
+                let mut printer = Printer::default();
+                printer.print(&argument);
+                printer.output
+            })
+            .collect::<Vec<_>>();
+        arguments.sort();
+        self.push("(");
+        for argument in &arguments {
+            self.push(argument);
+        }
+        self.push(")");
+    }
+
+    /// Appends a token, separated from the previous one by a space if they would otherwise be
+    /// read as a single token
+    fn push(&mut self, text: &str) {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '"' || c == '-';
+        if let (Some(last), Some(first)) = (self.output.chars().last(), text.chars().next()) {
+            if is_word(last) && is_word(first) {
+                self.output.push(' ');
+            }
+        }
+        self.output.push_str(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(query: &str, operation_name: Option<&str>) -> NormalizedOperation {
+        normalize(query, operation_name, 4096).unwrap()
+    }
+
+    #[test]
+    fn it_removes_formatting() {
+        let normalized = normalized(
+            r#"
+            # the products
+            query TopProducts($first: Int = 5) {
+                topProducts(first: $first) {
+                    upc,
+                    name
+                    reviews @include(if: true) { body }
+                    ... on Product { tags: list(values: ["a" "b"], offset: -1) }
+                }
+            }
+            "#,
+            None,
+        );
+        assert_eq!(
+            normalized.query,
+            r#"query TopProducts($first:Int=5){topProducts(first:$first){upc name reviews@include(if:true){body}...on Product{tags:list(offset:-1 values:["a" "b"])}}}"#
+        );
+    }
+
+    #[test]
+    fn it_normalizes_cosmetic_differences() {
+        let a = normalized("{ me(id: 1, name: \"a\") { id } }", None);
+        let b = normalized("{\n  me(name: \"a\" id: 1) {\n    id\n  }\n}", None);
+        assert_eq!(a, b);
+
+        // the order of fields is significant
+        let c = normalized("{ me(id: 1, name: \"a\") { id name } }", None);
+        let d = normalized("{ me(id: 1, name: \"a\") { name id } }", None);
+        assert_ne!(c, d);
+    }
+
+    #[test]
+    fn it_removes_the_name_of_single_operations() {
+        let query = "query Me { me { id } }";
+        assert_eq!(normalized(query, Some("Me")), normalized(query, None));
+        assert_eq!(normalized(query, Some("Me")).operation, None);
+        assert_eq!(
+            normalized(query, Some("Other")).operation.as_deref(),
+            Some("Other")
+        );
+
+        let query = "query Me { me { id } } query You { me { name } }";
+        assert_eq!(
+            normalized(query, Some("Me")).operation.as_deref(),
+            Some("Me")
+        );
+        assert_eq!(normalized(query, None).operation, None);
+    }
+
+    #[test]
+    fn it_computes_the_signature_of_the_plan_cache() {
+        let signature =
+            OperationSignature::new("query Me { me(b: 1, a: 2) { id } }", Some("Me")).unwrap();
+        assert_eq!(signature.document(), "query Me{me(a:2 b:1){id}}");
+        assert_eq!(signature.operation_name(), None);
+        assert_eq!(
+            signature,
+            OperationSignature::new("query Me {\n  me(a: 2, b: 1) { id }\n}", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_computes_the_usage_reporting_signature() {
+        let signature = |query: &str, operation_name: Option<&str>| {
+            OperationSignature::new(query, operation_name)
+                .unwrap()
+                .usage_reporting_signature()
+                .map(str::to_string)
+        };
+        assert_eq!(
+            signature("{ me { name { first last } } }", None).as_deref(),
+            Some("# -\n{me{name{first last}}}")
+        );
+        assert_eq!(
+            signature(
+                r#"
+                query Products($offset: Int = 5, $first: Int!) {
+                    ... on Query { upc }
+                    all: topProducts(first: $first, offset: 10, names: ["a"], filter: { x: "y" }) {
+                        upc
+                        ...ProductName
+                        name @include(if: true)
+                    }
+                }
+                fragment Unused on Product { upc }
+                fragment ProductName on Product { name }
+                "#,
+                Some("Products"),
+            )
+            .as_deref(),
+            Some(
+                "# Products\nfragment ProductName on Product{name}\
+                query Products($first:Int!,$offset:Int=0){\
+                topProducts(filter:{},first:$first,names:[],offset:0){name@include(if:true)upc...ProductName}\
+                ...on Query{upc}}"
+            )
+        );
+        assert_eq!(
+            signature("query A { a } query B { b }", Some("B")).as_deref(),
+            Some("# B\nquery B{b}")
+        );
+        assert_eq!(signature("query A { a } query B { b }", None), None);
+        assert_eq!(signature("query A { a }", Some("B")), None);
+    }
+
+    #[test]
+    fn it_does_not_normalize_invalid_documents() {
+        assert_eq!(normalize("{ me { id }", None, 4096), None);
+    }
+}
@@ -288,7 +288,6 @@ impl PlanNode {
         }
     }
 
-    #[cfg(test)]
     /// Retrieves all the services used across all plan nodes.
     ///
     /// Note that duplicates are not filtered.
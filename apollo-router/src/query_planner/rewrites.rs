@@ -0,0 +1,213 @@
+//! Rewrites of the data sent to or received from subgraphs
+//!
+//! Subgraphs declaring an interface as an `@interfaceObject` do not know its implementations:
+//! the query planner asks to set the `__typename` of the entity representations sent to them to
+//! the name of the interface, and can rename keys of the entities they return.
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::json_ext::Value;
+use crate::spec::Schema;
+use crate::spec::TYPENAME;
+
+/// A rewrite of the data of a fetch
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase", tag = "kind")]
+pub(crate) enum DataRewrite {
+    ValueSetter(DataValueSetter),
+    KeyRenamer(DataKeyRenamer),
+}
+
+/// Sets the value at a path
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DataValueSetter {
+    pub(crate) path: Path,
+    pub(crate) set_value_to: Value,
+}
+
+/// Renames the key at a path
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DataKeyRenamer {
+    pub(crate) path: Path,
+    pub(crate) rename_key_to: String,
+}
+
+impl DataRewrite {
+    fn apply(&self, schema: &Schema, value: &mut Value) {
+        match self {
+            DataRewrite::ValueSetter(setter) => {
+                if let Some((parent, key)) = split_last_key(&setter.path) {
+                    for_each_object(schema, value, parent, &mut |object| {
+                        if object.contains_key(key) {
+                            object.insert(key, setter.set_value_to.clone());
+                        }
+                    });
+                }
+            }
+            DataRewrite::KeyRenamer(renamer) => {
+                if let Some((parent, key)) = split_last_key(&renamer.path) {
+                    for_each_object(schema, value, parent, &mut |object| {
+                        if let Some(renamed) = object.remove(key) {
+                            object.insert(renamer.rename_key_to.as_str(), renamed);
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Applies rewrites to a value, such as an entity representation
+pub(crate) fn apply_rewrites(
+    schema: &Schema,
+    value: &mut Value,
+    rewrites: &Option<Vec<DataRewrite>>,
+) {
+    for rewrite in rewrites.iter().flatten() {
+        rewrite.apply(schema, value);
+    }
+}
+
+/// Subgraphs declaring an interface as an `@interfaceObject` return the name of the interface as
+/// the `__typename` of its entities: the concrete type, which is already known, is kept
+pub(crate) fn keep_concrete_typename(schema: &Schema, known: Option<&Value>, entity: &mut Value) {
+    let known = match known
+        .and_then(|known| known.as_object())
+        .and_then(|known| known.get(TYPENAME))
+        .and_then(|typename| typename.as_str())
+    {
+        Some(known) => known,
+        None => return,
+    };
+    if let Value::Object(entity) = entity {
+        let is_abstract = entity
+            .get(TYPENAME)
+            .and_then(|typename| typename.as_str())
+            .map_or(false, |returned| {
+                returned != known && schema.is_subtype(returned, known)
+            });
+        if is_abstract {
+            entity.insert(TYPENAME, Value::String(known.into()));
+        }
+    }
+}
+
+fn split_last_key(path: &Path) -> Option<(&[PathElement], &str)> {
+    match path.0.split_last() {
+        Some((PathElement::Key(key), parent)) => Some((parent, key.as_str())),
+        _ => None,
+    }
+}
+
+/// Calls `f` on the objects at a path. Fragment elements, such as `... on Book`, only match the
+/// objects whose `__typename` is the type of the fragment or one of its subtypes
+fn for_each_object(
+    schema: &Schema,
+    value: &mut Value,
+    path: &[PathElement],
+    f: &mut dyn FnMut(&mut crate::json_ext::Object),
+) {
+    match path.split_first() {
+        None => {
+            if let Value::Object(object) = value {
+                f(object)
+            }
+        }
+        Some((PathElement::Fragment(fragment), rest)) => {
+            let type_condition = fragment.trim_start_matches("... on ").trim();
+            let matches = value
+                .as_object()
+                .and_then(|object| object.get(TYPENAME))
+                .and_then(|typename| typename.as_str())
+                .map_or(false, |typename| {
+                    typename == type_condition || schema.is_subtype(type_condition, typename)
+                });
+            if matches {
+                for_each_object(schema, value, rest, f)
+            }
+        }
+        Some((PathElement::Key(key), rest)) => {
+            if let Some(value) = value
+                .as_object_mut()
+                .and_then(|object| object.get_mut(key.as_str()))
+            {
+                for_each_object(schema, value, rest, f)
+            }
+        }
+        Some((PathElement::Flatten, rest)) => {
+            if let Value::Array(array) = value {
+                for value in array {
+                    for_each_object(schema, value, rest, f)
+                }
+            }
+        }
+        Some((PathElement::Index(index), rest)) => {
+            if let Some(value) = value.as_array_mut().and_then(|array| array.get_mut(*index)) {
+                for_each_object(schema, value, rest, f)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    #[test]
+    fn it_sets_the_typename_of_representations() {
+        let schema =
+            Schema::parse(include_str!("testdata/schema.graphql"), &Default::default()).unwrap();
+        let rewrites: Option<Vec<DataRewrite>> = serde_json::from_value(serde_json::json!([
+            {
+                "kind": "ValueSetter",
+                "path": ["... on Product", "__typename"],
+                "setValueTo": "Product"
+            },
+            {
+                "kind": "KeyRenamer",
+                "path": ["... on Product", "name__alias"],
+                "renameKeyTo": "name"
+            }
+        ]))
+        .unwrap();
+
+        let mut book = json!({ "__typename": "Book", "upc": "1", "name__alias": "Dune" });
+        apply_rewrites(&schema, &mut book, &rewrites);
+        assert_eq!(
+            book,
+            json!({ "__typename": "Product", "upc": "1", "name": "Dune" })
+        );
+
+        let mut car = json!({ "__typename": "Car", "id": "1", "name__alias": "T" });
+        apply_rewrites(&schema, &mut car, &rewrites);
+        assert_eq!(
+            car,
+            json!({ "__typename": "Car", "id": "1", "name__alias": "T" })
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_concrete_typename_of_entities() {
+        let schema =
+            Schema::parse(include_str!("testdata/schema.graphql"), &Default::default()).unwrap();
+        let known = json!({ "__typename": "Book", "upc": "1" });
+
+        let mut entity = json!({ "__typename": "Product", "reviewsCount": 2 });
+        keep_concrete_typename(&schema, Some(&known), &mut entity);
+        assert_eq!(entity, json!({ "__typename": "Book", "reviewsCount": 2 }));
+
+        let mut entity = json!({ "__typename": "Furniture", "reviewsCount": 2 });
+        keep_concrete_typename(&schema, Some(&known), &mut entity);
+        assert_eq!(
+            entity,
+            json!({ "__typename": "Furniture", "reviewsCount": 2 })
+        );
+    }
+}
@@ -101,6 +101,7 @@ async fn mock_subgraph_service_withf_panics_should_be_reported_as_service_closed
             Arc::new(mock_products_service) as Arc<dyn MakeSubgraphService>,
         )])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let result = query_plan
@@ -159,6 +160,7 @@ async fn fetch_includes_operation_name() {
             Arc::new(mock_products_service) as Arc<dyn MakeSubgraphService>,
         )])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let _response = query_plan
@@ -214,6 +216,7 @@ async fn fetch_makes_post_requests() {
             Arc::new(mock_products_service) as Arc<dyn MakeSubgraphService>,
         )])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let _response = query_plan
@@ -249,6 +252,8 @@ async fn defer() {
                         operation_name: Some("t".to_string()),
                         operation_kind: OperationKind::Query,
                         id: Some("fetch1".to_string()),
+                        input_rewrites: None,
+                        output_rewrites: None,
                     }))),
                 },
                 deferred: vec![DeferredNode {
@@ -289,6 +294,8 @@ async fn defer() {
                             operation_name: None,
                             operation_kind: OperationKind::Query,
                             id: Some("fetch2".to_string()),
+                            input_rewrites: None,
+                            output_rewrites: None,
                         })),
                     }))),
                 }],
@@ -354,6 +361,7 @@ async fn defer() {
             ),
         ])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let response = query_plan
@@ -377,6 +385,150 @@ async fn defer() {
     );
 }
 
+#[tokio::test]
+async fn nested_defer_is_sent_after_its_parent() {
+    // plan for { t { x ... @defer(label: "outer") { y ... @defer(label: "inner") { x } } } }
+    let inner = DeferredNode {
+        depends: vec![],
+        label: Some("inner".to_string()),
+        query_path: Path(vec![PathElement::Key("t".to_string())]),
+        subselection: Some("{ x }".to_string()),
+        node: None,
+    };
+    let query_plan: QueryPlan = QueryPlan {
+            formatted_query_plan: Default::default(),
+            root: PlanNode::Defer {
+                primary: Primary {
+                    path: None,
+                    subselection: Some("{ t { x } }".to_string()),
+                    node: Some(Box::new(PlanNode::Fetch(FetchNode {
+                        service_name: "X".to_string(),
+                        requires: vec![],
+                        variable_usages: vec![],
+                        operation: "{ t { id __typename x } }".to_string(),
+                        operation_name: Some("t".to_string()),
+                        operation_kind: OperationKind::Query,
+                        id: Some("fetch1".to_string()),
+                        input_rewrites: None,
+                        output_rewrites: None,
+                    }))),
+                },
+                deferred: vec![DeferredNode {
+                    depends: vec![Depends {
+                        id: "fetch1".to_string(),
+                        defer_label: None,
+                    }],
+                    label: Some("outer".to_string()),
+                    query_path: Path(vec![PathElement::Key("t".to_string())]),
+                    subselection: None,
+                    node: Some(Arc::new(PlanNode::Defer {
+                        primary: Primary {
+                            path: Some(Path(vec![PathElement::Key("t".to_string())])),
+                            subselection: Some("{ y }".to_string()),
+                            node: Some(Box::new(PlanNode::Flatten(FlattenNode {
+                                path: Path(vec![PathElement::Key("t".to_string())]),
+                                node: Box::new(PlanNode::Fetch(FetchNode {
+                                    service_name: "Y".to_string(),
+                                    requires: vec![query_planner::selection::Selection::InlineFragment(
+                                        query_planner::selection::InlineFragment {
+                                            type_condition: Some("T".into()),
+                                            selections: vec![
+                                                query_planner::selection::Selection::Field(
+                                                    query_planner::selection::Field {
+                                                        alias: None,
+                                                        name: "id".into(),
+                                                        selections: None,
+                                                    },
+                                                ),
+                                                query_planner::selection::Selection::Field(
+                                                    query_planner::selection::Field {
+                                                        alias: None,
+                                                        name: "__typename".into(),
+                                                        selections: None,
+                                                    },
+                                                ),
+                                            ],
+                                        },
+                                    )],
+                                    variable_usages: vec![],
+                                    operation: "query($representations:[_Any!]!){_entities(representations:$representations){...on T{y}}}".to_string(),
+                                    operation_name: None,
+                                    operation_kind: OperationKind::Query,
+                                    id: Some("fetch2".to_string()),
+                                    input_rewrites: None,
+                                    output_rewrites: None,
+                                })),
+                            }))),
+                        },
+                        deferred: vec![inner],
+                    })),
+                }],
+            },
+            usage_reporting: UsageReporting {
+                stats_report_key: "this is a test report key".to_string(),
+                referenced_fields_by_type: Default::default(),
+            },
+            query: Arc::new(Query::default()),
+            options: QueryPlanOptions::default(),
+        };
+
+    let mut mock_x_service = plugin::test::MockSubgraphService::new();
+    mock_x_service.expect_clone().return_once(|| {
+        let mut mock_x_service = plugin::test::MockSubgraphService::new();
+        mock_x_service.expect_call().times(1).returning(|_| {
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json::json! {{
+                    "t": {"id": 1234, "__typename": "T", "x": "X"}
+                }})
+                .build())
+        });
+        mock_x_service
+    });
+
+    let mut mock_y_service = plugin::test::MockSubgraphService::new();
+    mock_y_service.expect_clone().return_once(|| {
+        let mut mock_y_service = plugin::test::MockSubgraphService::new();
+        mock_y_service.expect_call().times(1).returning(|_| {
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json::json! {{
+                    "_entities": [{"y": "Y", "__typename": "T"}]
+                }})
+                .build())
+        });
+        mock_y_service
+    });
+
+    let (sender, receiver) = futures::channel::mpsc::channel(10);
+
+    let schema = include_str!("testdata/defer_schema.graphql");
+    let schema = Arc::new(Schema::parse(schema, &Default::default()).unwrap());
+    let sf = Arc::new(SubgraphServiceFactory {
+        services: Arc::new(HashMap::from([
+            (
+                "X".into(),
+                Arc::new(mock_x_service) as Arc<dyn MakeSubgraphService>,
+            ),
+            (
+                "Y".into(),
+                Arc::new(mock_y_service) as Arc<dyn MakeSubgraphService>,
+            ),
+        ])),
+        plugins: Default::default(),
+        redactor: None,
+    });
+
+    query_plan
+        .execute(&Context::new(), &sf, &Default::default(), &schema, sender)
+        .await;
+
+    // the inner fragment is ready as soon as the outer one is, but must not be sent before it
+    let labels: Vec<Option<String>> = receiver.map(|response| response.label).collect().await;
+    assert_eq!(
+        labels,
+        vec![Some("outer".to_string()), Some("inner".to_string())]
+    );
+}
+
 #[tokio::test]
 async fn defer_if_condition() {
     let query = r#"
@@ -438,6 +590,7 @@ async fn defer_if_condition() {
             Arc::new(mocked_accounts) as Arc<dyn MakeSubgraphService>,
         )])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let defer_primary_response = query_plan
@@ -605,6 +758,7 @@ async fn dependent_mutations() {
             ),
         ])),
         plugins: Default::default(),
+        redactor: None,
     });
 
     let (sender, _) = futures::channel::mpsc::channel(10);
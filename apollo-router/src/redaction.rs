@@ -0,0 +1,450 @@
+//! Redaction of sensitive data
+//!
+//! The values of the configured variables, headers and response fields are replaced with
+//! `[REDACTED]` in copies of the requests and responses, before they are logged, recorded in
+//! traces or sent to co-processors. The requests and responses executed by the router are left
+//! unchanged: when a co-processor returns a redacted value as it received it, the original value
+//! is restored.
+
+use std::sync::Arc;
+
+use http::header::HeaderName;
+use http::HeaderMap;
+use http::HeaderValue;
+use regex::RegexSet;
+
+use crate::configuration::Redaction;
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::json_ext::Value;
+
+/// The value replacing redacted values
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug)]
+pub(crate) struct Redactor {
+    variables: Vec<String>,
+    headers: RegexSet,
+    response_fields: Vec<Path>,
+}
+
+impl Redactor {
+    /// Returns the redactor of the configuration, if it redacts anything
+    pub(crate) fn new(configuration: &Redaction) -> Result<Option<Arc<Self>>, regex::Error> {
+        if !configuration.is_enabled() {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(Self {
+            variables: configuration.variables.clone(),
+            headers: RegexSet::new(&configuration.headers)?,
+            response_fields: configuration
+                .response_fields
+                .iter()
+                .map(|path| Path::from(path.trim_matches('/')))
+                .collect(),
+        })))
+    }
+
+    pub(crate) fn redacts_response_fields(&self) -> bool {
+        !self.response_fields.is_empty()
+    }
+
+    pub(crate) fn is_redacted_variable(&self, name: &str) -> bool {
+        self.variables
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            })
+    }
+
+    pub(crate) fn is_redacted_header(&self, name: &HeaderName) -> bool {
+        self.headers.is_match(name.as_str())
+    }
+
+    /// Returns a copy of the headers, with the redacted values
+    pub(crate) fn headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut redacted = headers.clone();
+        for (name, value) in redacted.iter_mut() {
+            if self.is_redacted_header(name) {
+                *value = HeaderValue::from_static(REDACTED);
+            }
+        }
+        redacted
+    }
+
+    /// Restores the original values of the redacted headers returned unchanged
+    pub(crate) fn restore_headers(&self, original: &HeaderMap, returned: &mut HeaderMap) {
+        let names = returned
+            .keys()
+            .filter(|name| self.is_redacted_header(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in names {
+            if returned
+                .get_all(&name)
+                .iter()
+                .all(|value| value == REDACTED)
+            {
+                returned.remove(&name);
+                for value in original.get_all(&name) {
+                    returned.append(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the variables, with the redacted values
+    pub(crate) fn variables(&self, variables: &Object) -> Object {
+        variables
+            .iter()
+            .map(|(name, value)| {
+                if self.is_redacted_variable(name.as_str()) {
+                    (name.clone(), Value::String(REDACTED.into()))
+                } else {
+                    (name.clone(), value.clone())
+                }
+            })
+            .collect()
+    }
+
+    /// Returns a copy of the request, with the redacted variables
+    pub(crate) fn request(&self, request: &graphql::Request) -> graphql::Request {
+        let mut redacted = request.clone();
+        redacted.variables = self.variables(&request.variables);
+        redacted
+    }
+
+    /// Restores the original values of the redacted variables returned unchanged
+    pub(crate) fn restore_request(
+        &self,
+        original: &graphql::Request,
+        returned: &mut graphql::Request,
+    ) {
+        for (name, value) in returned.variables.iter_mut() {
+            if self.is_redacted_variable(name.as_str()) && is_redacted(value) {
+                if let Some(original) = original.variables.get(name) {
+                    *value = original.clone();
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the response, with the redacted fields
+    pub(crate) fn response(&self, response: &graphql::Response) -> graphql::Response {
+        let mut redacted = response.clone();
+        let path = response.path.clone().unwrap_or_else(Path::empty);
+        if let Some(data) = &mut redacted.data {
+            self.redact_data(data, &path);
+        }
+        for incremental in &mut redacted.incremental {
+            let path = incremental.path.clone().unwrap_or_else(Path::empty);
+            if let Some(data) = &mut incremental.data {
+                self.redact_data(data, &path);
+            }
+        }
+        redacted
+    }
+
+    /// Restores the original values of the redacted fields returned unchanged
+    pub(crate) fn restore_response(
+        &self,
+        original: &graphql::Response,
+        returned: &mut graphql::Response,
+    ) {
+        let path = original.path.clone().unwrap_or_else(Path::empty);
+        if let (Some(returned), Some(original)) = (&mut returned.data, &original.data) {
+            self.restore_data(returned, original, &path);
+        }
+    }
+
+    /// Returns a copy of the response of a subgraph fetch made at `path` in the client response,
+    /// with the redacted fields. Each of the `_entities` of the response is the data found at
+    /// `path`, and the data of the other responses is found at `path`
+    pub(crate) fn subgraph_response(
+        &self,
+        response: &graphql::Response,
+        path: &Path,
+    ) -> graphql::Response {
+        let mut redacted = response.clone();
+        if let Some(data) = &mut redacted.data {
+            match entities_mut(data) {
+                Some(entities) => {
+                    for entity in entities {
+                        self.redact_data(entity, path);
+                    }
+                }
+                None => self.redact_data(data, path),
+            }
+        }
+        redacted
+    }
+
+    /// Restores the original values of the redacted fields of a subgraph response returned
+    /// unchanged
+    pub(crate) fn restore_subgraph_response(
+        &self,
+        original: &graphql::Response,
+        returned: &mut graphql::Response,
+        path: &Path,
+    ) {
+        let (returned, original) = match (&mut returned.data, &original.data) {
+            (Some(returned), Some(original)) => (returned, original),
+            _ => return,
+        };
+        let original_entities = original
+            .as_object()
+            .and_then(|data| data.get("_entities"))
+            .and_then(Value::as_array);
+        match (entities_mut(returned), original_entities) {
+            (Some(returned), Some(original)) => {
+                for (returned, original) in returned.iter_mut().zip(original) {
+                    self.restore_data(returned, original, path);
+                }
+            }
+            _ => self.restore_data(returned, original, path),
+        }
+    }
+
+    /// Redacts the fields of data found at `path` in the response
+    fn redact_data(&self, data: &mut Value, path: &Path) {
+        for field in &self.response_fields {
+            if let Some(relative) = relative_path(field, path) {
+                redact_at(data, relative);
+            }
+        }
+    }
+
+    /// Restores the fields of data found at `path` in the response
+    fn restore_data(&self, returned: &mut Value, original: &Value, path: &Path) {
+        for field in &self.response_fields {
+            if let Some(relative) = relative_path(field, path) {
+                restore_at(returned, original, relative);
+            }
+        }
+    }
+}
+
+fn entities_mut(data: &mut Value) -> Option<&mut Vec<Value>> {
+    data.as_object_mut()?.get_mut("_entities")?.as_array_mut()
+}
+
+fn is_redacted(value: &Value) -> bool {
+    value.as_str() == Some(REDACTED)
+}
+
+/// Returns the part of `field` below `path`, the whole data if the field contains `path`, or
+/// `None` if the field is not in the data at `path`
+fn relative_path<'a>(field: &'a Path, path: &Path) -> Option<&'a [PathElement]> {
+    let mut elements = field.0.iter();
+    for element in path.iter() {
+        let matches = match (elements.next(), element) {
+            // the data is inside the redacted field
+            (None, _) => return Some(&[]),
+            (Some(PathElement::Key(field)), PathElement::Key(key)) => field == key,
+            (Some(PathElement::Index(field)), PathElement::Index(index)) => field == index,
+            (Some(PathElement::Flatten), PathElement::Index(_) | PathElement::Flatten) => true,
+            // the entities fetched for all the elements of a list include this element
+            (Some(PathElement::Index(_)), PathElement::Flatten) => true,
+            _ => false,
+        };
+        if !matches {
+            return None;
+        }
+    }
+    Some(elements.as_slice())
+}
+
+fn redact_at(value: &mut Value, path: &[PathElement]) {
+    match (path.split_first(), value) {
+        (None, Value::Null) => {}
+        (None, value) => *value = Value::String(REDACTED.into()),
+        (Some((PathElement::Key(key), rest)), Value::Object(object)) => {
+            if let Some(value) = object.get_mut(key.as_str()) {
+                redact_at(value, rest);
+            }
+        }
+        (Some((PathElement::Index(index), rest)), Value::Array(array)) => {
+            if let Some(value) = array.get_mut(*index) {
+                redact_at(value, rest);
+            }
+        }
+        (Some((PathElement::Flatten, rest)), Value::Array(array)) => {
+            for value in array {
+                redact_at(value, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn restore_at(returned: &mut Value, original: &Value, path: &[PathElement]) {
+    match (path.split_first(), returned, original) {
+        (None, returned, original) => {
+            if is_redacted(returned) {
+                *returned = original.clone();
+            }
+        }
+        (Some((PathElement::Key(key), rest)), Value::Object(returned), Value::Object(original)) => {
+            if let (Some(returned), Some(original)) =
+                (returned.get_mut(key.as_str()), original.get(key.as_str()))
+            {
+                restore_at(returned, original, rest);
+            }
+        }
+        (
+            Some((PathElement::Index(index), rest)),
+            Value::Array(returned),
+            Value::Array(original),
+        ) => {
+            if let (Some(returned), Some(original)) =
+                (returned.get_mut(*index), original.get(*index))
+            {
+                restore_at(returned, original, rest);
+            }
+        }
+        (Some((PathElement::Flatten, rest)), Value::Array(returned), Value::Array(original)) => {
+            for (returned, original) in returned.iter_mut().zip(original) {
+                restore_at(returned, original, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    fn redactor() -> Arc<Redactor> {
+        Redactor::new(&Redaction {
+            variables: vec!["password".to_string(), "card*".to_string()],
+            headers: vec!["^authorization$".to_string(), "token".to_string()],
+            response_fields: vec!["me/email".to_string(), "users/@/ssn".to_string()],
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn it_is_disabled_without_rules() {
+        assert!(Redactor::new(&Redaction::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_redacts_variables() {
+        let redactor = redactor();
+        let request = graphql::Request::builder()
+            .query("mutation { login }")
+            .variables(
+                json!({ "name": "ada", "password": "secret", "cardNumber": "4242" })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .build();
+        let redacted = redactor.request(&request);
+        assert_eq!(
+            Value::Object(redacted.variables.clone()),
+            json!({ "name": "ada", "password": REDACTED, "cardNumber": REDACTED })
+        );
+
+        let mut returned = redacted;
+        returned
+            .variables
+            .insert("name", Value::String("grace".into()));
+        redactor.restore_request(&request, &mut returned);
+        assert_eq!(
+            Value::Object(returned.variables),
+            json!({ "name": "grace", "password": "secret", "cardNumber": "4242" })
+        );
+    }
+
+    #[test]
+    fn it_redacts_headers() {
+        let redactor = redactor();
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer abc"));
+        headers.insert("x-api-token", HeaderValue::from_static("abc"));
+        headers.insert("x-authorization-scheme", HeaderValue::from_static("bearer"));
+
+        let redacted = redactor.headers(&headers);
+        assert_eq!(redacted["authorization"], REDACTED);
+        assert_eq!(redacted["x-api-token"], REDACTED);
+        assert_eq!(redacted["x-authorization-scheme"], "bearer");
+
+        let mut returned = redacted;
+        returned.insert("x-api-token", HeaderValue::from_static("changed"));
+        redactor.restore_headers(&headers, &mut returned);
+        assert_eq!(returned["authorization"], "Bearer abc");
+        assert_eq!(returned["x-api-token"], "changed");
+    }
+
+    #[test]
+    fn it_redacts_response_fields() {
+        let redactor = redactor();
+        let response = graphql::Response::builder()
+            .data(json!({
+                "me": { "name": "ada", "email": "ada@example.com" },
+                "users": [{ "ssn": "1" }, { "ssn": null }, { "name": "grace" }]
+            }))
+            .build();
+        let redacted = redactor.response(&response);
+        assert_eq!(
+            redacted.data,
+            Some(json!({
+                "me": { "name": "ada", "email": REDACTED },
+                "users": [{ "ssn": REDACTED }, { "ssn": null }, { "name": "grace" }]
+            }))
+        );
+
+        let mut returned = redacted;
+        redactor.restore_response(&response, &mut returned);
+        assert_eq!(returned.data, response.data);
+
+        // deferred data is redacted relatively to its path
+        let deferred = graphql::Response::builder()
+            .path(Path::from("users/0"))
+            .data(json!({ "ssn": "1" }))
+            .build();
+        assert_eq!(
+            redactor.response(&deferred).data,
+            Some(json!({ "ssn": REDACTED }))
+        );
+        let deferred = graphql::Response::builder()
+            .path(Path::from("me/email"))
+            .data(json!({ "domain": "example.com" }))
+            .build();
+        assert_eq!(redactor.response(&deferred).data, Some(json!(REDACTED)));
+    }
+
+    #[test]
+    fn it_redacts_subgraph_responses_at_the_fetch_path() {
+        let redactor = redactor();
+        let entities = graphql::Response::builder()
+            .data(json!({ "_entities": [{ "ssn": "1", "name": "ada" }] }))
+            .build();
+        let path = Path::from("users/@");
+        let redacted = redactor.subgraph_response(&entities, &path);
+        assert_eq!(
+            redacted.data,
+            Some(json!({ "_entities": [{ "ssn": REDACTED, "name": "ada" }] }))
+        );
+        let mut returned = redacted;
+        redactor.restore_subgraph_response(&entities, &mut returned, &path);
+        assert_eq!(returned.data, entities.data);
+
+        // the root fields of other fetches are found at the root of the client response
+        let root = graphql::Response::builder()
+            .data(json!({ "me": { "email": "ada@example.com" } }))
+            .build();
+        assert_eq!(
+            redactor.subgraph_response(&root, &Path::empty()).data,
+            Some(json!({ "me": { "email": REDACTED } }))
+        );
+    }
+}
@@ -66,6 +66,14 @@ pub struct Request {
     /// [APQ]: https://www.apollographql.com/docs/apollo-server/performance/apq/
     #[serde(skip_serializing_if = "Object::is_empty", default)]
     pub extensions: Object,
+
+    /// The (optional) ID of a persisted operation, sent instead of its `query`.
+    ///
+    /// Relay and Apollo clients send the ID of the operation in their persisted
+    /// operation manifest as the `documentId` of the request. The router resolves
+    /// it against the manifests of its configuration.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub document_id: Option<String>,
 }
 
 // NOTE: this deserialize helper is used to transform `null` to Default::default()
@@ -95,12 +103,14 @@ impl Request {
         // Skip the `Object` type alias in order to use buildstructor’s map special-casing
         variables: JsonMap<ByteString, Value>,
         extensions: JsonMap<ByteString, Value>,
+        document_id: Option<String>,
     ) -> Self {
         Self {
             query,
             operation_name,
             variables,
             extensions,
+            document_id,
         }
     }
 
@@ -120,12 +130,14 @@ impl Request {
         // Skip the `Object` type alias in order to use buildstructor’s map special-casing
         variables: JsonMap<ByteString, Value>,
         extensions: JsonMap<ByteString, Value>,
+        document_id: Option<String>,
     ) -> Self {
         Self {
             query,
             operation_name,
             variables,
             extensions,
+            document_id,
         }
     }
 
@@ -147,6 +159,13 @@ impl Request {
             None
         };
 
+        let document_id =
+            if let Some(serde_json::Value::String(document_id)) = urldecoded.get("documentId") {
+                Some(document_id.clone())
+            } else {
+                None
+            };
+
         let query = if let Some(serde_json::Value::String(query)) = urldecoded.get("query") {
             Some(query.as_str())
         } else {
@@ -159,7 +178,8 @@ impl Request {
         let request_builder = Self::builder()
             .variables(variables)
             .and_operation_name(operation_name)
-            .extensions(extensions);
+            .extensions(extensions)
+            .and_document_id(document_id);
 
         let request = if let Some(query_str) = query {
             request_builder.query(query_str).build()
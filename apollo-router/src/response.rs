@@ -89,11 +89,10 @@ impl Response {
     ///
     /// This will return an error (identifying the faulty service) if the input is invalid.
     pub(crate) fn from_bytes(service_name: &str, b: Bytes) -> Result<Response, FetchError> {
-        let value =
-            Value::from_bytes(b).map_err(|error| FetchError::SubrequestMalformedResponse {
-                service: service_name.to_string(),
-                reason: error.to_string(),
-            })?;
+        let value = parse_value(b).map_err(|reason| FetchError::SubrequestMalformedResponse {
+            service: service_name.to_string(),
+            reason,
+        })?;
         let mut object =
             ensure_object!(value).map_err(|error| FetchError::SubrequestMalformedResponse {
                 service: service_name.to_string(),
@@ -167,6 +166,22 @@ impl Response {
     }
 }
 
+/// Parses the body of a subgraph response.
+#[cfg(not(feature = "simd_json"))]
+fn parse_value(b: Bytes) -> Result<Value, String> {
+    Value::from_bytes(b).map_err(|error| error.to_string())
+}
+
+/// Parses the body of a subgraph response with simd-json.
+///
+/// The parser works in place, so the body is copied to a mutable buffer first. Unlike
+/// `Value::from_bytes`, strings of the resulting value do not share the body's memory.
+#[cfg(feature = "simd_json")]
+fn parse_value(b: Bytes) -> Result<Value, String> {
+    let mut buffer = b.to_vec();
+    simd_json::serde::from_slice(&mut buffer).map_err(|error| error.to_string())
+}
+
 /// A graphql incremental response.
 /// Used with `@defer`
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Default)]
@@ -436,4 +451,26 @@ mod tests {
                 .build()
         );
     }
+
+    #[test]
+    fn test_from_bytes() {
+        // runs with both JSON parsers, the key order of objects must be kept
+        let body = Bytes::from_static(
+            br#"{"data":{"b":[1,2.5,"three"],"a":{"z":null,"y":true}},"errors":[{"message":"error","path":["b",0]}],"hasNext":false}"#,
+        );
+        let response = Response::from_bytes("products", body).unwrap();
+        assert_eq!(
+            serde_json::to_string(&response.data).unwrap(),
+            r#"{"b":[1,2.5,"three"],"a":{"z":null,"y":true}}"#
+        );
+        assert_eq!(response.errors[0].path, Some(Path::from("b/0")));
+        assert_eq!(response.has_next, Some(false));
+
+        let error =
+            Response::from_bytes("products", Bytes::from_static(b"{\"data\":")).unwrap_err();
+        assert!(matches!(
+            error,
+            FetchError::SubrequestMalformedResponse { service, .. } if service == "products"
+        ));
+    }
 }
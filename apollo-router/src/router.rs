@@ -3,7 +3,6 @@
 
 use std::fs;
 use std::net::IpAddr;
-use std::path::Path;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -17,6 +16,7 @@ use derive_more::From;
 use displaydoc::Display as DisplayDoc;
 use futures::channel::oneshot;
 use futures::prelude::*;
+use futures::stream::BoxStream;
 use futures::FutureExt;
 use http_body::Body as _;
 use hyper::Body;
@@ -65,7 +65,12 @@ async fn make_router_service<RF>(
         .create(configuration.clone(), schema, None, Some(extra_plugins))
         .await?;
     let web_endpoints = service_factory.web_endpoints();
-    let routers = make_axum_router(service_factory, &configuration, web_endpoints)?;
+    let routers = make_axum_router(
+        service_factory,
+        &configuration,
+        web_endpoints,
+        Default::default(),
+    )?;
     let ListenAddrAndRouter(_listener, router) = routers.main;
 
     Ok(router
@@ -166,6 +171,62 @@ pub enum SchemaSource {
         /// The HTTP client timeout for each poll
         timeout: Duration,
     },
+
+    /// A self-hosted registry implementing the Uplink protocol, delivering signed schemas.
+    #[display(fmt = "SelfHostedRegistry")]
+    SelfHostedRegistry {
+        /// The key sent to the registry
+        apollo_key: String,
+
+        /// The graph reference sent to the registry: <YOUR_GRAPH_ID>@<VARIANT>
+        apollo_graph_ref: String,
+
+        /// The endpoints of the registry.
+        urls: Vec<Url>,
+
+        /// The base64 encoded Ed25519 public key verifying the signature of the schemas.
+        public_key: String,
+
+        /// The duration between polling
+        poll_interval: Duration,
+
+        /// The HTTP client timeout for each poll
+        timeout: Duration,
+    },
+
+    /// A schema polled from object storage or from an HTTP URL.
+    #[display(fmt = "URL")]
+    Url {
+        /// The location of the schema: an `s3://`, `gs://`, `az://`, `http://` or `https://` URL.
+        url: Url,
+
+        /// The duration between polling
+        poll_interval: Duration,
+
+        /// The HTTP client timeout for each poll
+        timeout: Duration,
+    },
+
+    /// Subgraph schemas composed into the supergraph by the router, for local development.
+    #[display(fmt = "Subgraphs")]
+    Subgraphs {
+        /// The path of a Rover `supergraph.yaml` file listing the subgraphs.
+        path: PathBuf,
+
+        /// `true` to watch the file and the subgraph schemas for changes and compose again.
+        watch: bool,
+    },
+
+    /// A key of a Kubernetes ConfigMap or Secret, read through the API server.
+    #[display(fmt = "Kubernetes")]
+    Kubernetes {
+        /// The location of the schema: `k8s://configmap/<name>/<key>` or
+        /// `k8s://secret/<name>/<key>`, with an optional namespace before the name.
+        url: Url,
+
+        /// `true` to watch the object for changes and hot apply them.
+        watch: bool,
+    },
 }
 
 impl From<&'_ str> for SchemaSource {
@@ -234,6 +295,7 @@ impl SchemaSource {
                     urls,
                     poll_interval,
                     timeout,
+                    None,
                 )
                 .filter_map(|res| {
                     future::ready(match res {
@@ -246,6 +308,76 @@ impl SchemaSource {
                 })
                 .boxed()
             }
+            SchemaSource::SelfHostedRegistry {
+                apollo_key,
+                apollo_graph_ref,
+                urls,
+                public_key,
+                poll_interval,
+                timeout,
+            } => match crate::uplink::PublicKey::from_base64(&public_key) {
+                Ok(public_key) => crate::uplink::stream_supergraph(
+                    apollo_key,
+                    apollo_graph_ref,
+                    Some(urls),
+                    poll_interval,
+                    timeout,
+                    Some(public_key),
+                )
+                .filter_map(|res| {
+                    future::ready(match res {
+                        Ok(schema_result) => Some(UpdateSchema(schema_result.schema)),
+                        Err(e) => {
+                            tracing::error!("{}", e);
+                            None
+                        }
+                    })
+                })
+                .boxed(),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    stream::empty().boxed()
+                }
+            },
+            SchemaSource::Url {
+                url,
+                poll_interval,
+                timeout,
+            } => crate::object_storage::stream_supergraph(url, poll_interval, timeout)
+                .filter_map(|res| {
+                    future::ready(match res {
+                        Ok(schema) => Some(UpdateSchema(schema)),
+                        Err(e) => {
+                            tracing::error!("{}", e);
+                            None
+                        }
+                    })
+                })
+                .boxed(),
+            SchemaSource::Subgraphs { path, watch } => {
+                crate::composition::stream_supergraph(path, watch)
+                    .filter_map(|res| {
+                        future::ready(match res {
+                            Ok(schema) => Some(UpdateSchema(schema)),
+                            Err(e) => {
+                                tracing::error!("could not compose the supergraph: {}", e);
+                                None
+                            }
+                        })
+                    })
+                    .boxed()
+            }
+            SchemaSource::Kubernetes { url, watch } => crate::kubernetes::stream(url, watch)
+                .filter_map(|res| {
+                    future::ready(match res {
+                        Ok(schema) => Some(UpdateSchema(schema)),
+                        Err(e) => {
+                            tracing::error!("{}", e);
+                            None
+                        }
+                    })
+                })
+                .boxed(),
         }
         .chain(stream::iter(vec![NoMoreSchema]))
     }
@@ -271,6 +403,19 @@ pub enum ConfigurationSource {
     #[display(fmt = "Stream")]
     Stream(#[derivative(Debug = "ignore")] ConfigurationStream),
 
+    /// A base yaml file and overlays merged into it, that may be watched for changes.
+    ///
+    /// The files are merged in order: maps are merged recursively, and other values, including
+    /// lists, replace the values of the previous files.
+    #[display(fmt = "Files")]
+    Files {
+        /// The paths of the configuration files, from the base to the last overlay.
+        paths: Vec<PathBuf>,
+
+        /// `true` to watch the files for changes and hot apply them.
+        watch: bool,
+    },
+
     /// A yaml file that may be watched for changes
     #[display(fmt = "File")]
     File {
@@ -285,6 +430,18 @@ pub enum ConfigurationSource {
         #[deprecated]
         delay: Option<Duration>,
     },
+
+    /// A yaml configuration in a key of a Kubernetes ConfigMap or Secret, read through the API
+    /// server.
+    #[display(fmt = "Kubernetes")]
+    Kubernetes {
+        /// The location of the configuration: `k8s://configmap/<name>/<key>` or
+        /// `k8s://secret/<name>/<key>`, with an optional namespace before the name.
+        url: Url,
+
+        /// `true` to watch the object for changes and hot apply them.
+        watch: bool,
+    },
 }
 
 impl Default for ConfigurationSource {
@@ -308,84 +465,191 @@ impl ConfigurationSource {
                 path,
                 watch,
                 delay: _,
-            } => {
-                // Sanity check, does the config file exists, if it doesn't then bail.
-                if !path.exists() {
-                    tracing::error!(
-                        "configuration file at path '{}' does not exist.",
-                        path.to_string_lossy()
-                    );
-                    stream::empty().boxed()
-                } else if watch {
-                    crate::files::watch(&path)
-                        .map(move |_| match ConfigurationSource::read_config(&path) {
-                            Ok(config) => UpdateConfiguration(Box::new(config)),
-                            Err(err) => {
+            } => ConfigurationSource::stream_files(vec![path], watch),
+            ConfigurationSource::Files { paths, watch } => {
+                ConfigurationSource::stream_files(paths, watch)
+            }
+            ConfigurationSource::Kubernetes { url, watch } => {
+                // Once a configuration was loaded, invalid changes are skipped so that the
+                // router keeps running with the last valid configuration
+                let mut loaded = false;
+                crate::kubernetes::stream(url, watch)
+                    .filter_map(move |res| {
+                        let event = match res.map(|config| config.parse::<Configuration>()) {
+                            Ok(Ok(config)) => {
+                                loaded = true;
+                                Some(UpdateConfiguration(Box::new(config)))
+                            }
+                            Ok(Err(err)) if loaded => {
+                                tracing::error!(
+                                    "invalid configuration, keeping the previous one: {}",
+                                    err
+                                );
+                                None
+                            }
+                            Ok(Err(err)) => {
                                 tracing::error!("{}", err);
-                                NoMoreConfiguration
+                                Some(NoMoreConfiguration)
                             }
-                        })
-                        .boxed()
-                } else {
-                    match ConfigurationSource::read_config(&path) {
-                        Ok(configuration) => {
-                            #[cfg(any(test, not(unix)))]
-                            {
-                                stream::once(future::ready(UpdateConfiguration(Box::new(
-                                    configuration,
-                                ))))
-                                .boxed()
+                            // The object is fetched again after errors of the API server
+                            Err(err) => {
+                                tracing::error!("{}", err);
+                                None
                             }
+                        };
+                        future::ready(event)
+                    })
+                    .boxed()
+            }
+        }
+        .chain(stream::iter(vec![NoMoreConfiguration]))
+        .boxed()
+    }
 
-                            #[cfg(all(not(test), unix))]
-                            {
-                                let mut sighup_stream = tokio::signal::unix::signal(
-                                    tokio::signal::unix::SignalKind::hangup(),
-                                )
-                                .expect("Failed to install SIGHUP signal handler");
-
-                                let (mut tx, rx) = futures::channel::mpsc::channel(1);
-                                tokio::task::spawn(async move {
-                                    while let Some(()) = sighup_stream.recv().await {
-                                        tx.send(()).await.unwrap();
-                                    }
-                                });
-                                futures::stream::select(
-                                    stream::once(future::ready(UpdateConfiguration(Box::new(
-                                        configuration,
-                                    ))))
-                                    .boxed(),
-                                    rx.filter_map(
-                                        move |()| match ConfigurationSource::read_config(&path) {
-                                            Ok(configuration) => future::ready(Some(
-                                                UpdateConfiguration(Box::new(configuration)),
-                                            )),
-                                            Err(err) => {
-                                                tracing::error!("{}", err);
-                                                future::ready(None)
-                                            }
-                                        },
-                                    )
-                                    .boxed(),
-                                )
-                                .boxed()
-                            }
+    fn stream_files(paths: Vec<PathBuf>, watch: bool) -> BoxStream<'static, Event> {
+        // Sanity check, do the config files exist, if they don't then bail.
+        if let Some(path) = paths.iter().find(|path| !path.exists()) {
+            tracing::error!(
+                "configuration file at path '{}' does not exist.",
+                path.to_string_lossy()
+            );
+            stream::empty().boxed()
+        } else if watch {
+            // Files referenced by variables are watched too, so that the configuration
+            // is expanded again when a secret is rotated
+            let secrets = paths
+                .iter()
+                .filter_map(|path| fs::read_to_string(path).ok())
+                .flat_map(|config| crate::configuration::referenced_files(&config))
+                .filter(|secret| secret.exists());
+            let watches = paths
+                .iter()
+                .map(|path| crate::files::watch(path).boxed())
+                .chain(secrets.map(|secret| crate::files::watch(&secret).skip(1).boxed()))
+                .chain(
+                    ConfigurationSource::references_vault_secrets(&paths)
+                        .then(crate::configuration::secret_rotations),
+                );
+
+            // Once a configuration was loaded, invalid changes are skipped so that the
+            // router keeps running with the last valid configuration
+            let mut loaded = false;
+            stream::select_all(watches)
+                .filter_map(move |_| {
+                    let event = match ConfigurationSource::read_config(&paths) {
+                        Ok(config) => {
+                            loaded = true;
+                            Some(UpdateConfiguration(Box::new(config)))
+                        }
+                        Err(err) if loaded => {
+                            tracing::error!(
+                                "invalid configuration, keeping the previous one: {}",
+                                err
+                            );
+                            None
                         }
                         Err(err) => {
                             tracing::error!("{}", err);
-                            stream::empty().boxed()
+                            Some(NoMoreConfiguration)
                         }
+                    };
+                    future::ready(event)
+                })
+                .boxed()
+        } else {
+            match ConfigurationSource::read_config(&paths) {
+                Ok(configuration) => {
+                    // Vault secrets are rotated without hot reload too, as their leases expire
+                    let rotations = if ConfigurationSource::references_vault_secrets(&paths) {
+                        let paths = paths.clone();
+                        crate::configuration::secret_rotations()
+                            .filter_map(move |()| {
+                                future::ready(match ConfigurationSource::read_config(&paths) {
+                                    Ok(configuration) => {
+                                        Some(UpdateConfiguration(Box::new(configuration)))
+                                    }
+                                    Err(err) => {
+                                        tracing::error!(
+                                            "invalid configuration, keeping the previous one: {}",
+                                            err
+                                        );
+                                        None
+                                    }
+                                })
+                            })
+                            .boxed()
+                    } else {
+                        stream::empty().boxed()
+                    };
+
+                    #[cfg(any(test, not(unix)))]
+                    {
+                        stream::select(
+                            stream::once(future::ready(UpdateConfiguration(Box::new(
+                                configuration,
+                            )))),
+                            rotations,
+                        )
+                        .boxed()
+                    }
+
+                    #[cfg(all(not(test), unix))]
+                    {
+                        let mut sighup_stream =
+                            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                                .expect("Failed to install SIGHUP signal handler");
+
+                        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+                        tokio::task::spawn(async move {
+                            while let Some(()) = sighup_stream.recv().await {
+                                tx.send(()).await.unwrap();
+                            }
+                        });
+                        stream::select_all([
+                            stream::once(future::ready(UpdateConfiguration(Box::new(
+                                configuration,
+                            ))))
+                            .boxed(),
+                            rotations,
+                            rx.filter_map(move |()| {
+                                match ConfigurationSource::read_config(&paths) {
+                                    Ok(configuration) => future::ready(Some(UpdateConfiguration(
+                                        Box::new(configuration),
+                                    ))),
+                                    Err(err) => {
+                                        tracing::error!("{}", err);
+                                        future::ready(None)
+                                    }
+                                }
+                            })
+                            .boxed(),
+                        ])
+                        .boxed()
                     }
                 }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    stream::empty().boxed()
+                }
             }
         }
-        .chain(stream::iter(vec![NoMoreConfiguration]))
-        .boxed()
     }
 
-    fn read_config(path: &Path) -> Result<Configuration, ReadConfigError> {
-        let config = fs::read_to_string(path)?;
-        config.parse().map_err(ReadConfigError::Validation)
+    fn references_vault_secrets(paths: &[PathBuf]) -> bool {
+        paths
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .any(|config| crate::configuration::references_vault_secrets(&config))
+    }
+
+    fn read_config(paths: &[PathBuf]) -> Result<Configuration, ReadConfigError> {
+        let configs = paths
+            .iter()
+            .map(fs::read_to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        crate::configuration::merge_configurations(&configs)
+            .and_then(|config| config.parse())
+            .map_err(ReadConfigError::Validation)
     }
 }
 
@@ -661,6 +925,8 @@ fn generate_event_stream(
 #[cfg(test)]
 mod tests {
     use std::env::temp_dir;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
 
     use serde_json::json;
     use serde_json::to_string_pretty;
@@ -749,8 +1015,15 @@ mod tests {
 
         // This time write garbage, there should not be an update.
         write_and_flush(&mut file, ":garbage").await;
-        let event = stream.into_future().now_or_never();
-        assert!(event.is_none() || matches!(event, Some((Some(NoMoreConfiguration), _))));
+        let event = (&mut stream).into_future().now_or_never();
+        assert!(event.is_none());
+
+        // The watch goes on after an invalid configuration
+        write_and_flush(&mut file, contents).await;
+        assert!(matches!(
+            stream.next().await.unwrap(),
+            UpdateConfiguration(_)
+        ));
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -833,6 +1106,35 @@ mod tests {
         assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_by_files_merges_overlays() {
+        let (base_path, mut base) = create_temp_file();
+        write_and_flush(
+            &mut base,
+            "supergraph:\n  introspection: true\n  listen: 127.0.0.1:4000\n",
+        )
+        .await;
+        let (overlay_path, mut overlay) = create_temp_file();
+        write_and_flush(&mut overlay, "supergraph:\n  listen: 127.0.0.1:4010\n").await;
+
+        let mut stream = ConfigurationSource::Files {
+            paths: vec![base_path, overlay_path],
+            watch: false,
+        }
+        .into_stream();
+        match stream.next().await.unwrap() {
+            UpdateConfiguration(cfg) => {
+                assert!(cfg.supergraph.introspection);
+                assert_eq!(
+                    cfg.supergraph.listen,
+                    SocketAddr::from_str("127.0.0.1:4010").unwrap().into()
+                );
+            }
+            _ => panic!("the configuration should be merged"),
+        }
+        assert!(matches!(stream.next().await.unwrap(), NoMoreConfiguration));
+    }
+
     #[test(tokio::test)]
     async fn schema_by_file_watching() {
         let (path, mut file) = create_temp_file();
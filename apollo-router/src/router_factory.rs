@@ -1,8 +1,10 @@
+use std::collections::BTreeSet;
 use std::io;
 // With regards to ELv2 licensing, this entire file is license key functionality
 use std::sync::Arc;
 
 use axum::response::IntoResponse;
+use futures::future::BoxFuture;
 use http::StatusCode;
 use multimap::MultiMap;
 use once_cell::sync::Lazy;
@@ -16,20 +18,31 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 use tower_service::Service;
 
+use crate::compute_job;
 use crate::configuration::Configuration;
 use crate::configuration::ConfigurationError;
 use crate::configuration::TlsSubgraph;
 use crate::plugin::DynPlugin;
 use crate::plugin::Handler;
 use crate::plugin::PluginFactory;
+use crate::plugins::nats_subgraphs::NatsSubgraphService;
+use crate::plugins::nats_subgraphs::NatsSubgraphs;
+use crate::plugins::nats_subgraphs::APOLLO_NATS_SUBGRAPHS;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
+use crate::progressive_override;
+use crate::progressive_override::LabelSelector;
+use crate::progressive_override::MAX_OVERRIDE_LABELS;
+use crate::redaction::Redactor;
+use crate::schema_change::SchemaChange;
 use crate::services::new_service::ServiceFactory;
 use crate::services::router;
 use crate::services::router_service::RouterCreator;
+use crate::services::subgraph_service::MakeSubgraphService;
 use crate::services::transport;
 use crate::services::PluggableSupergraphServiceBuilder;
 use crate::services::SubgraphService;
+use crate::services::SubgraphServices;
 use crate::services::SupergraphCreator;
 use crate::spec::Schema;
 use crate::ListenAddr;
@@ -105,6 +118,24 @@ pub(crate) trait RouterFactory:
     type Future: Send;
 
     fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint>;
+
+    /// Notifies the plugins that the router switched to a new supergraph schema
+    fn schema_changed(&self, _change: &SchemaChange) {}
+
+    /// Names of the plugins loaded by the router
+    fn plugin_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Id of the supergraph schema
+    fn schema_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Removes the cached persisted queries, query plans and introspection responses
+    fn clear_caches(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
 }
 
 /// Factory for creating a RouterFactory
@@ -139,50 +170,56 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
         previous_router: Option<&'a Self::RouterFactory>,
         extra_plugins: Option<Vec<(String, Box<dyn DynPlugin>)>>,
     ) -> Result<Self::RouterFactory, BoxError> {
+        compute_job::configure(configuration.server.compute_threads);
+
         // Process the plugins.
         let plugins = create_plugins(&configuration, &schema, extra_plugins).await?;
 
-        let tls_root_store: Option<RootCertStore> = configuration
-            .tls
-            .subgraph
-            .all
-            .create_certificate_store()
-            .transpose()?;
-
-        let mut builder = PluggableSupergraphServiceBuilder::new(schema.clone());
-        builder = builder.with_configuration(configuration.clone());
-
-        for (name, _) in schema.subgraphs() {
-            let subgraph_root_store = configuration
-                .tls
-                .subgraph
-                .subgraphs
-                .get(name)
-                .as_ref()
-                .and_then(|subgraph| subgraph.create_certificate_store())
-                .transpose()?
-                .or_else(|| tls_root_store.clone());
-
-            let subgraph_service = match plugins
-                .iter()
-                .find(|i| i.0.as_str() == APOLLO_TRAFFIC_SHAPING)
-                .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<TrafficShaping>())
-            {
-                Some(shaping) => Either::A(shaping.subgraph_service_internal(
-                    name,
-                    SubgraphService::new(name, shaping.get_apq(name), subgraph_root_store),
-                )),
-                None => Either::B(SubgraphService::new(name, None, subgraph_root_store)),
-            };
-            builder = builder.with_subgraph_service(name, subgraph_service);
-        }
+        // the canary schema may have other subgraphs, served by their own subgraph services
+        let canary = match &configuration.supergraph.canary {
+            Some(canary) => {
+                let sdl = std::fs::read_to_string(&canary.schema_path).map_err(|e| {
+                    format!(
+                        "could not read the canary schema at '{}': {e}",
+                        canary.schema_path.display()
+                    )
+                })?;
+                let canary_schema = Arc::new(Schema::parse(&sdl, &configuration)?);
+                let subgraph_services =
+                    subgraph_services(&configuration, &canary_schema, &plugins)?;
+                Some((canary, canary_schema, subgraph_services))
+            }
+            None => None,
+        };
 
+        let mut builder = PluggableSupergraphServiceBuilder::new(schema.clone())
+            .with_configuration(configuration.clone())
+            .with_subgraph_services(subgraph_services(&configuration, &schema, &plugins)?);
         for (plugin_name, plugin) in plugins {
             builder = builder.with_dyn_plugin(plugin_name, plugin);
         }
 
         // We're good to go with the new service.
-        let mut supergraph_creator = builder.build().await?;
+        let primary_creator = builder.build().await?;
+
+        // the canary and the override variants share the plugins of the primary schema
+        let mut supergraph_creator =
+            Self::with_progressive_override(primary_creator.clone(), &configuration, &schema)
+                .await?;
+        if let Some((canary, canary_schema, subgraph_services)) = canary {
+            tracing::info!(
+                "sending {}% of the requests to the canary schema {}",
+                canary.percentage,
+                canary_schema.schema_id.as_deref().unwrap_or_default()
+            );
+            let canary_creator = primary_creator
+                .for_schema(canary_schema.clone(), Some(subgraph_services))
+                .await?;
+            let canary_creator =
+                Self::with_progressive_override(canary_creator, &configuration, &canary_schema)
+                    .await?;
+            supergraph_creator = supergraph_creator.with_canary(canary_creator, canary);
+        }
 
         if let Some(router) = previous_router {
             if configuration.supergraph.query_planning.warmed_up_queries > 0 {
@@ -201,7 +238,80 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
             }
         }
 
-        Ok(Self::RouterFactory::new(Arc::new(supergraph_creator), &configuration).await)
+        Self::RouterFactory::new(Arc::new(supergraph_creator), &configuration).await
+    }
+}
+
+/// The services of the subgraphs of a schema, over the transports configured by the plugins
+fn subgraph_services(
+    configuration: &Configuration,
+    schema: &Schema,
+    plugins: &[(String, Box<dyn DynPlugin>)],
+) -> Result<SubgraphServices, BoxError> {
+    let tls_root_store: Option<RootCertStore> = configuration
+        .tls
+        .subgraph
+        .all
+        .create_certificate_store()
+        .transpose()?;
+    let redactor = Redactor::new(&configuration.redaction)?;
+    let shaping = plugins
+        .iter()
+        .find(|i| i.0.as_str() == APOLLO_TRAFFIC_SHAPING)
+        .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<TrafficShaping>());
+
+    let mut services: SubgraphServices = Vec::new();
+    for (name, _) in schema.subgraphs() {
+        let subgraph_root_store = configuration
+            .tls
+            .subgraph
+            .subgraphs
+            .get(name)
+            .as_ref()
+            .and_then(|subgraph| subgraph.create_certificate_store())
+            .transpose()?
+            .or_else(|| tls_root_store.clone());
+
+        let service: Arc<dyn MakeSubgraphService> = match shaping {
+            Some(shaping) => Arc::new(
+                shaping.subgraph_service_internal(
+                    name,
+                    subgraph_transport(
+                        plugins,
+                        name,
+                        SubgraphService::new(name, shaping.get_apq(name), subgraph_root_store)
+                            .with_compression_dictionary(shaping.get_compression_dictionary(name))
+                            .with_redactor(redactor.clone()),
+                    ),
+                ),
+            ),
+            None => Arc::new(subgraph_transport(
+                plugins,
+                name,
+                SubgraphService::new(name, None, subgraph_root_store)
+                    .with_redactor(redactor.clone()),
+            )),
+        };
+        services.push((name.to_string(), service));
+    }
+    Ok(services)
+}
+
+/// The NATS transport of the subgraph if the nats_subgraphs plugin configures it, otherwise its
+/// HTTP client
+fn subgraph_transport(
+    plugins: &[(String, Box<dyn DynPlugin>)],
+    name: &str,
+    http_service: SubgraphService,
+) -> Either<NatsSubgraphService, SubgraphService> {
+    match plugins
+        .iter()
+        .find(|i| i.0.as_str() == APOLLO_NATS_SUBGRAPHS)
+        .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<NatsSubgraphs>())
+        .and_then(|nats| nats.subgraph_service(name))
+    {
+        Some(nats_service) => Either::A(nats_service),
+        None => Either::B(http_service),
     }
 }
 
@@ -216,47 +326,69 @@ impl YamlRouterFactory {
         // Process the plugins.
         let plugins = create_plugins(&configuration, &schema, extra_plugins).await?;
 
-        let tls_root_store = configuration
-            .tls
-            .subgraph
-            .all
-            .create_certificate_store()
-            .transpose()?;
-
-        let mut builder = PluggableSupergraphServiceBuilder::new(schema.clone());
-        builder = builder.with_configuration(configuration.clone());
-
-        for (name, _) in schema.subgraphs() {
-            let subgraph_root_store = configuration
-                .tls
-                .subgraph
-                .subgraphs
-                .get(name)
-                .as_ref()
-                .and_then(|subgraph| subgraph.create_certificate_store())
-                .transpose()?
-                .or_else(|| tls_root_store.clone());
-
-            let subgraph_service = match plugins
-                .iter()
-                .find(|i| i.0.as_str() == APOLLO_TRAFFIC_SHAPING)
-                .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<TrafficShaping>())
-            {
-                Some(shaping) => Either::A(shaping.subgraph_service_internal(
-                    name,
-                    SubgraphService::new(name, shaping.get_apq(name), subgraph_root_store),
-                )),
-                None => Either::B(SubgraphService::new(name, None, subgraph_root_store)),
-            };
-            builder = builder.with_subgraph_service(name, subgraph_service);
-        }
-
+        let mut builder = PluggableSupergraphServiceBuilder::new(schema.clone())
+            .with_configuration(configuration.clone())
+            .with_subgraph_services(subgraph_services(&configuration, &schema, &plugins)?);
         for (plugin_name, plugin) in plugins {
             builder = builder.with_dyn_plugin(plugin_name, plugin);
         }
 
         builder.build().await.map_err(BoxError::from)
     }
+
+    /// Creates a variant of the supergraph for each combination of the override labels of the
+    /// schema. The variants only have their own query planner, and share the plugins and the
+    /// subgraph services of the supergraph
+    async fn with_progressive_override(
+        supergraph_creator: SupergraphCreator,
+        configuration: &Configuration,
+        schema: &Schema,
+    ) -> Result<SupergraphCreator, BoxError> {
+        let labels = progressive_override::labels(schema.as_string());
+        let configured = &configuration.supergraph.progressive_override.labels;
+        for label in configured.keys().filter(|label| !labels.contains(*label)) {
+            tracing::warn!("the override label '{label}' is not used by the supergraph schema");
+        }
+        if labels.is_empty() {
+            return Ok(supergraph_creator);
+        }
+        if labels.len() > MAX_OVERRIDE_LABELS {
+            return Err(format!(
+                "the supergraph schema uses {} override labels, at most {MAX_OVERRIDE_LABELS} are supported",
+                labels.len()
+            )
+            .into());
+        }
+
+        let selectors = labels
+            .iter()
+            .map(|label| LabelSelector::new(label, configured.get(label)))
+            .collect::<Vec<_>>();
+        let mut variants = Vec::with_capacity(1 << selectors.len());
+        for index in 0..(1usize << selectors.len()) {
+            let enabled = selectors
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| index & (1 << bit) != 0)
+                .map(|(_, selector)| selector.label.clone())
+                .collect::<BTreeSet<_>>();
+            let variant = Schema::parse(
+                &progressive_override::with_enabled_labels(schema.as_string(), &enabled),
+                configuration,
+            )?;
+            variants.push(
+                supergraph_creator
+                    .for_schema(Arc::new(variant), None)
+                    .await?,
+            );
+        }
+        tracing::info!(
+            "planning requests with {} variants of the supergraph schema for the override labels {}",
+            variants.len(),
+            labels.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+        Ok(supergraph_creator.with_progressive_override(selectors, variants))
+    }
 }
 
 impl TlsSubgraph {
@@ -344,6 +476,9 @@ pub(crate) async fn create_plugins(
     let plugin_registry: Vec<&'static Lazy<PluginFactory>> = crate::plugin::plugins().collect();
     let mut plugin_instances = Vec::new();
     let extra = extra_plugins.unwrap_or_default();
+    let redaction = serde_json::to_value(&configuration.redaction)?;
+    let parser_recursion_limit = configuration.server.experimental_parser_recursion_limit;
+    let operation_checks = operation_checks(configuration)?;
 
     for (name, mut configuration) in configuration.plugins().into_iter() {
         if extra.iter().any(|(n, _)| *n == name) {
@@ -361,6 +496,18 @@ pub(crate) async fn create_plugins(
                 if name == "apollo.telemetry" {
                     inject_schema_id(schema, &mut configuration);
                 }
+                if name == "apollo.telemetry"
+                    || name == "experimental.external"
+                    || name == "apollo.experimental_request_capture"
+                {
+                    inject_redaction(&redaction, &mut configuration);
+                }
+                if PARSING_PLUGINS.contains(&name.as_str()) {
+                    inject_parser_recursion_limit(parser_recursion_limit, &mut configuration);
+                }
+                if name == "apollo.event_subscriptions" {
+                    inject_operation_checks(&operation_checks, &mut configuration);
+                }
                 match factory
                     .create_instance(&configuration, schema.as_string().clone())
                     .await
@@ -411,6 +558,7 @@ pub(crate) async fn create_plugins(
                         // This is *required* by the telemetry module or it will fail...
                         if *name == "apollo.telemetry" {
                             inject_schema_id(schema, &mut config);
+                            inject_redaction(&redaction, &mut config);
                         }
                         match factory
                             .create_instance(&config, schema.as_string().clone())
@@ -479,6 +627,61 @@ fn inject_schema_id(schema: &Schema, configuration: &mut Value) {
     }
 }
 
+/// Plugins parsing the operations, which must parse them with the recursion limit of the query
+/// planner
+const PARSING_PLUGINS: &[&str] = &[
+    "apollo.custom_scalars",
+    "apollo.event_subscriptions",
+    "apollo.idempotency",
+    "apollo.operation_limits",
+    "apollo.operation_rules",
+    "apollo.router_fields",
+];
+
+fn inject_parser_recursion_limit(limit: usize, configuration: &mut Value) {
+    if let Some(configuration) = configuration.as_object_mut() {
+        configuration.insert("parser_recursion_limit".to_string(), limit.into());
+    }
+}
+
+/// The checks of the operations that the event subscriptions must apply themselves, since they
+/// are not planned nor executed: the parser limits, and the configuration of the operation
+/// limits and rules, with the recursion limit of the parser
+fn operation_checks(configuration: &Configuration) -> Result<Map<String, Value>, BoxError> {
+    let mut checks = Map::new();
+    checks.insert(
+        "parser_limits".to_string(),
+        serde_json::to_value(&configuration.server.parser_limits)?,
+    );
+    for plugin in ["operation_limits", "operation_rules"] {
+        if let Some(mut plugin_configuration) =
+            configuration.plugin_configuration(&format!("apollo.{plugin}"))
+        {
+            inject_parser_recursion_limit(
+                configuration.server.experimental_parser_recursion_limit,
+                &mut plugin_configuration,
+            );
+            checks.insert(plugin.to_string(), plugin_configuration);
+        }
+    }
+    Ok(checks)
+}
+
+fn inject_operation_checks(checks: &Map<String, Value>, configuration: &mut Value) {
+    if let Some(configuration) = configuration.as_object_mut() {
+        configuration.extend(checks.clone());
+    }
+}
+
+/// Passes the top level `redaction` configuration to the plugins handling logs, traces,
+/// co-processors and request capture, so that they all redact the same values. It is injected in
+/// their `redaction` field, which is skipped in their configuration schema.
+fn inject_redaction(redaction: &Value, configuration: &mut Value) {
+    if let Some(configuration) = configuration.as_object_mut() {
+        configuration.insert("redaction".to_string(), redaction.clone());
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::error::Error;
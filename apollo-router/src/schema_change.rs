@@ -0,0 +1,179 @@
+//! Notifications of supergraph schema changes
+//!
+//! When the router switches to a new supergraph schema, the changes to the API schema are logged,
+//! the plugins are notified through [`Plugin::schema_changed`](crate::plugin::Plugin::schema_changed),
+//! and an optional webhook receives them. A metric records the hash of each schema loaded.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::configuration::SchemaChangeWebhook;
+use crate::spec::Schema;
+use crate::Configuration;
+
+const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Changes between two versions of the API schema
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct SchemaChange {
+    /// The hash of the previous supergraph schema
+    pub previous_schema_id: Option<String>,
+
+    /// The hash of the new supergraph schema
+    pub schema_id: Option<String>,
+
+    /// The types added to the schema
+    pub added_types: Vec<String>,
+
+    /// The types removed from the schema
+    pub removed_types: Vec<String>,
+
+    /// The fields and enum values added to existing types, as `Type.field`
+    pub added_fields: Vec<String>,
+
+    /// The fields and enum values removed from existing types, as `Type.field`
+    pub removed_fields: Vec<String>,
+}
+
+impl SchemaChange {
+    pub(crate) fn new(previous: &Schema, schema: &Schema) -> Self {
+        let previous_members = previous.api_schema().type_members();
+        let members = schema.api_schema().type_members();
+        let mut change = SchemaChange {
+            previous_schema_id: previous.schema_id.clone(),
+            schema_id: schema.schema_id.clone(),
+            ..Default::default()
+        };
+
+        for (name, fields) in &members {
+            match previous_members.get(name) {
+                Some(previous_fields) => change.added_fields.extend(
+                    fields
+                        .difference(previous_fields)
+                        .map(|field| format!("{name}.{field}")),
+                ),
+                None => change.added_types.push(name.to_string()),
+            }
+        }
+        for (name, previous_fields) in &previous_members {
+            match members.get(name) {
+                Some(fields) => change.removed_fields.extend(
+                    previous_fields
+                        .difference(fields)
+                        .map(|field| format!("{name}.{field}")),
+                ),
+                None => change.removed_types.push(name.to_string()),
+            }
+        }
+        change
+    }
+
+    /// Returns `true` if the supergraph schema did not change
+    pub(crate) fn is_unchanged(&self) -> bool {
+        self.previous_schema_id == self.schema_id
+    }
+}
+
+/// Records the hash of a schema the router switched to
+pub(crate) fn record_schema_load(schema: &Schema) {
+    tracing::info!(
+        monotonic_counter.apollo_router_schema_load_count = 1u64,
+        schema_id = schema.schema_id.as_deref().unwrap_or_default(),
+    );
+}
+
+/// Logs the changes and sends them to the webhook, if configured
+pub(crate) fn notify(configuration: &Configuration, change: &SchemaChange) {
+    tracing::info!(
+        previous_schema_id = change.previous_schema_id.as_deref().unwrap_or_default(),
+        schema_id = change.schema_id.as_deref().unwrap_or_default(),
+        added_types = ?change.added_types,
+        removed_types = ?change.removed_types,
+        added_fields = ?change.added_fields,
+        removed_fields = ?change.removed_fields,
+        "supergraph schema changed"
+    );
+
+    if let Some(webhook) = configuration.supergraph.schema_change_webhook.clone() {
+        let change = change.clone();
+        tokio::spawn(async move {
+            if let Err(e) = call_webhook(&webhook, &change).await {
+                tracing::error!("could not notify the schema change webhook: {e}");
+            }
+        });
+    }
+}
+
+async fn call_webhook(
+    webhook: &SchemaChangeWebhook,
+    change: &SchemaChange,
+) -> Result<(), reqwest::Error> {
+    reqwest::Client::builder()
+        .timeout(webhook.timeout.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT))
+        .build()?
+        .post(webhook.url.clone())
+        .json(change)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(types: &str) -> Schema {
+        let sdl = format!(
+            r#"
+            schema
+              @core(feature: "https://specs.apollo.dev/core/v0.1"),
+              @core(feature: "https://specs.apollo.dev/join/v0.1")
+            {{
+              query: Query
+            }}
+            directive @core(feature: String!) repeatable on SCHEMA
+            directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+            enum join__Graph {{
+              ACCOUNTS @join__graph(name: "accounts", url: "http://localhost:4001/graphql")
+            }}
+            {types}"#
+        );
+        Schema::parse(&sdl, &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn it_lists_added_and_removed_types_and_fields() {
+        let previous = schema(
+            r#"
+            scalar Legacy
+            enum Role { ADMIN USER }
+            type User { id: ID! name: String role: Role }
+            type Query { me: User legacy: Legacy }"#,
+        );
+        let new = schema(
+            r#"
+            enum Role { ADMIN USER GUEST }
+            type User { id: ID! email: String role: Role }
+            type Review { body: String }
+            type Query { me: User reviews: [Review] }"#,
+        );
+
+        let change = SchemaChange::new(&previous, &new);
+        assert!(!change.is_unchanged());
+        assert_eq!(change.added_types, vec!["Review"]);
+        assert_eq!(change.removed_types, vec!["Legacy"]);
+        assert_eq!(
+            change.added_fields,
+            vec!["Query.reviews", "Role.GUEST", "User.email"]
+        );
+        assert_eq!(change.removed_fields, vec!["Query.legacy", "User.name"]);
+
+        let change = SchemaChange::new(&new, &new);
+        assert!(change.is_unchanged());
+        assert!(change.added_types.is_empty() && change.added_fields.is_empty());
+        assert!(change.removed_types.is_empty() && change.removed_fields.is_empty());
+    }
+}
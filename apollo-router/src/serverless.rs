@@ -0,0 +1,504 @@
+//! Serverless handler
+//!
+//! Serves requests through the router pipeline without a listener, from AWS Lambda events or from
+//! the HTTP requests of functions platforms such as Google Cloud Functions. The supergraph schema
+//! is loaded on the first request, so that the function starts quickly, and the query plan cache
+//! can be saved to a file, to warm it up when an instance starts.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use http::header::SET_COOKIE;
+use http::HeaderMap;
+use http::HeaderValue;
+use hyper::Body;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tokio::sync::OnceCell;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::axum_factory::make_axum_router;
+use crate::axum_factory::ListenAddrAndRouter;
+use crate::configuration::Configuration;
+use crate::http_server_factory::ConnectionInfo;
+use crate::router_factory::RouterFactory;
+use crate::router_factory::RouterSuperServiceFactory;
+use crate::router_factory::YamlRouterFactory;
+use crate::services::router_service::RouterCreator;
+use crate::services::SupergraphCreator;
+use crate::spec::Schema;
+
+/// Serves requests through the router pipeline, in short-lived execution environments.
+///
+/// The handler is created when the function starts, and kept for the following invocations of
+/// the same instance. The configuration and the supergraph schema are read, and the pipeline is
+/// built, on the first request.
+///
+/// ```no_run
+/// # async fn example(event: serde_json::Value) -> Result<serde_json::Value, tower::BoxError> {
+/// let handler = apollo_router::ServerlessHandler::builder()
+///     .schema_path("supergraph.graphql")
+///     .configuration_path("router.yaml")
+///     .plan_cache_path("/tmp/query_plans.json")
+///     .build();
+/// let response = handler.handle_event(event).await?;
+/// # Ok(response)
+/// # }
+/// ```
+pub struct ServerlessHandler {
+    schema_path: PathBuf,
+    configuration_path: Option<PathBuf>,
+    plan_cache_path: Option<PathBuf>,
+    pipeline: OnceCell<Pipeline>,
+}
+
+struct Pipeline {
+    router: axum::Router,
+    service_factory: RouterCreator<SupergraphCreator>,
+    /// The cache keys last saved to the plan cache file
+    saved_cache_keys: Mutex<HashSet<(String, Option<String>)>>,
+}
+
+#[buildstructor::buildstructor]
+impl ServerlessHandler {
+    /// Creates a handler serving the supergraph schema at `schema_path`.
+    ///
+    /// If `plan_cache_path` is set, the query plan cache is warmed up with the operations saved in
+    /// this file when the pipeline is built, and they are saved again when new operations are
+    /// planned. Use a directory shared by the instances, such as a mounted network file system,
+    /// to warm up new instances, or `/tmp` to keep the file across the restarts of an instance.
+    #[builder(visibility = "pub")]
+    fn new(
+        schema_path: PathBuf,
+        configuration_path: Option<PathBuf>,
+        plan_cache_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            schema_path,
+            configuration_path,
+            plan_cache_path,
+            pipeline: OnceCell::new(),
+        }
+    }
+
+    /// Serves an AWS Lambda event from API Gateway (REST API or HTTP API) or from a function URL,
+    /// and returns the response in the format of the event
+    pub async fn handle_event(
+        &self,
+        event: serde_json::Value,
+    ) -> Result<serde_json::Value, BoxError> {
+        let event: LambdaEvent =
+            serde_json::from_value(event).map_err(|e| format!("unsupported Lambda event: {e}"))?;
+        let http_v2 = event.version.as_deref() == Some("2.0");
+        let request = event.into_request()?;
+        let response = self.handle_request(request).await?;
+        lambda_response(response, http_v2).await
+    }
+
+    /// Serves an HTTP request, as received by a functions platform
+    pub async fn handle_request(
+        &self,
+        request: http::Request<Body>,
+    ) -> Result<http::Response<Body>, BoxError> {
+        let pipeline = self
+            .pipeline
+            .get_or_try_init(|| self.build_pipeline())
+            .await?;
+        let response = match pipeline.router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(error) => match error {},
+        };
+        self.save_plan_cache(pipeline).await;
+
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok(http::Response::from_parts(parts, Body::from(body)))
+    }
+
+    async fn build_pipeline(&self) -> Result<Pipeline, BoxError> {
+        let configuration = match &self.configuration_path {
+            Some(path) => {
+                let yaml = tokio::fs::read_to_string(path).await.map_err(|e| {
+                    format!(
+                        "could not read the configuration at '{}': {e}",
+                        path.display()
+                    )
+                })?;
+                Configuration::from_str(&yaml)?
+            }
+            None => Configuration::default(),
+        };
+        let sdl = tokio::fs::read_to_string(&self.schema_path)
+            .await
+            .map_err(|e| {
+                format!(
+                    "could not read the schema at '{}': {e}",
+                    self.schema_path.display()
+                )
+            })?;
+        let configuration = Arc::new(configuration);
+        let schema = Arc::new(Schema::parse(&sdl, &configuration)?);
+
+        let service_factory = YamlRouterFactory
+            .create(configuration.clone(), schema, None, None)
+            .await?;
+
+        let saved_cache_keys = match self.read_plan_cache().await {
+            Some(cache_keys) => {
+                tracing::info!(
+                    "warming up the query plan cache with {} queries",
+                    cache_keys.len()
+                );
+                service_factory
+                    .warm_up_query_planner(cache_keys.clone())
+                    .await;
+                cache_keys.into_iter().collect()
+            }
+            None => HashSet::new(),
+        };
+
+        let web_endpoints = service_factory.web_endpoints();
+        let routers = make_axum_router(
+            service_factory.clone(),
+            &configuration,
+            web_endpoints,
+            Default::default(),
+        )?;
+        let ListenAddrAndRouter(_listener, router) = routers.main;
+        Ok(Pipeline {
+            router,
+            service_factory,
+            saved_cache_keys: Mutex::new(saved_cache_keys),
+        })
+    }
+
+    async fn read_plan_cache(&self) -> Option<Vec<(String, Option<String>)>> {
+        let path = self.plan_cache_path.as_ref()?;
+        let content = tokio::fs::read(path).await.ok()?;
+        match serde_json::from_slice::<Vec<PlanCacheEntry>>(&content) {
+            Ok(entries) => Some(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.query, entry.operation_name))
+                    .collect(),
+            ),
+            Err(e) => {
+                tracing::warn!("ignoring the invalid query plan cache file: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Saves the query plan cache keys, if they changed. The file is replaced atomically, as
+    /// several instances can share it
+    async fn save_plan_cache(&self, pipeline: &Pipeline) {
+        let path = match &self.plan_cache_path {
+            Some(path) => path,
+            None => return,
+        };
+        let cache_keys = pipeline.service_factory.cache_keys(usize::MAX).await;
+        let mut saved_cache_keys = pipeline.saved_cache_keys.lock().await;
+        if cache_keys.len() == saved_cache_keys.len()
+            && cache_keys.iter().all(|key| saved_cache_keys.contains(key))
+        {
+            return;
+        }
+
+        let entries: Vec<_> = cache_keys
+            .iter()
+            .map(|(query, operation_name)| PlanCacheEntry {
+                query: query.clone(),
+                operation_name: operation_name.clone(),
+            })
+            .collect();
+        let content = serde_json::to_vec(&entries).expect("cache keys serialize");
+        let temporary_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        let result = async {
+            tokio::fs::write(&temporary_path, content).await?;
+            tokio::fs::rename(&temporary_path, path).await
+        }
+        .await;
+        match result {
+            Ok(()) => *saved_cache_keys = cache_keys.into_iter().collect(),
+            Err(e) => tracing::warn!(
+                "could not save the query plan cache to '{}': {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct PlanCacheEntry {
+    query: String,
+    operation_name: Option<String>,
+}
+
+/// An API Gateway or function URL event, in the payload format 1.0 or 2.0
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LambdaEvent {
+    #[serde(default)]
+    version: Option<String>,
+    // 1.0
+    #[serde(default)]
+    http_method: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    multi_value_query_string_parameters: Option<HashMap<String, Vec<String>>>,
+    // 2.0
+    #[serde(default)]
+    raw_path: Option<String>,
+    #[serde(default)]
+    raw_query_string: Option<String>,
+    #[serde(default)]
+    cookies: Option<Vec<String>>,
+    // both
+    #[serde(default)]
+    headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(default)]
+    request_context: Option<RequestContext>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    is_base64_encoded: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestContext {
+    // 1.0
+    #[serde(default)]
+    identity: Option<SourceIp>,
+    // 2.0
+    #[serde(default)]
+    http: Option<HttpContext>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SourceIp {
+    #[serde(default)]
+    source_ip: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpContext {
+    method: String,
+    #[serde(default)]
+    source_ip: Option<String>,
+}
+
+impl LambdaEvent {
+    fn into_request(self) -> Result<http::Request<Body>, BoxError> {
+        let context = self.request_context.unwrap_or_default();
+        let (method, path, query, source_ip) = match self.raw_path {
+            Some(raw_path) => {
+                let http = context.http.ok_or("the Lambda event has no HTTP context")?;
+                (
+                    http.method,
+                    raw_path,
+                    self.raw_query_string.unwrap_or_default(),
+                    http.source_ip,
+                )
+            }
+            None => {
+                let query = match self.multi_value_query_string_parameters {
+                    Some(parameters) => serde_urlencoded::to_string(
+                        parameters
+                            .iter()
+                            .flat_map(|(name, values)| values.iter().map(move |v| (name, v)))
+                            .collect::<Vec<_>>(),
+                    )?,
+                    None => serde_urlencoded::to_string(
+                        self.query_string_parameters.unwrap_or_default(),
+                    )?,
+                };
+                (
+                    self.http_method
+                        .ok_or("the Lambda event has no HTTP method")?,
+                    self.path.unwrap_or_else(|| "/".to_string()),
+                    query,
+                    context.identity.and_then(|identity| identity.source_ip),
+                )
+            }
+        };
+
+        let uri = if query.is_empty() {
+            path
+        } else {
+            format!("{path}?{query}")
+        };
+        let body = match self.body {
+            Some(body) if self.is_base64_encoded => Body::from(base64::decode(body)?),
+            Some(body) => Body::from(body),
+            None => Body::empty(),
+        };
+        let mut request = http::Request::builder()
+            .method(method.as_str())
+            .uri(uri)
+            .body(body)?;
+
+        let headers = request.headers_mut();
+        match self.multi_value_headers {
+            Some(multi_value_headers) => {
+                for (name, values) in multi_value_headers {
+                    for value in values {
+                        headers.append(
+                            http::header::HeaderName::from_bytes(name.as_bytes())?,
+                            HeaderValue::from_str(&value)?,
+                        );
+                    }
+                }
+            }
+            None => {
+                for (name, value) in self.headers.unwrap_or_default() {
+                    headers.insert(
+                        http::header::HeaderName::from_bytes(name.as_bytes())?,
+                        HeaderValue::from_str(&value)?,
+                    );
+                }
+            }
+        }
+        if let Some(cookies) = self.cookies.filter(|cookies| !cookies.is_empty()) {
+            headers.insert(
+                http::header::COOKIE,
+                HeaderValue::from_str(&cookies.join("; "))?,
+            );
+        }
+
+        if let Some(ip) = source_ip.and_then(|ip| ip.parse::<IpAddr>().ok()) {
+            request.extensions_mut().insert(ConnectionInfo {
+                peer_address: SocketAddr::new(ip, 0),
+            });
+        }
+        Ok(request)
+    }
+}
+
+/// Converts the response to the format of the event. Bodies that are not UTF-8 are base64 encoded
+async fn lambda_response(
+    response: http::Response<Body>,
+    http_v2: bool,
+) -> Result<serde_json::Value, BoxError> {
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let (body, is_base64_encoded) = match String::from_utf8(body.to_vec()) {
+        Ok(body) => (body, false),
+        Err(_) => (base64::encode(&body), true),
+    };
+
+    if http_v2 {
+        let mut headers = parts.headers;
+        let cookies: Vec<_> = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok().map(str::to_string))
+            .collect();
+        headers.remove(SET_COOKIE);
+        Ok(json!({
+            "statusCode": parts.status.as_u16(),
+            "headers": joined_headers(&headers),
+            "cookies": cookies,
+            "body": body,
+            "isBase64Encoded": is_base64_encoded,
+        }))
+    } else {
+        let mut multi_value_headers: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (name, value) in parts.headers.iter() {
+            if let Ok(value) = value.to_str() {
+                multi_value_headers
+                    .entry(name.as_str())
+                    .or_default()
+                    .push(value);
+            }
+        }
+        Ok(json!({
+            "statusCode": parts.status.as_u16(),
+            "multiValueHeaders": multi_value_headers,
+            "body": body,
+            "isBase64Encoded": is_base64_encoded,
+        }))
+    }
+}
+
+/// Headers with several values are joined with commas, as in the payload format 2.0
+fn joined_headers(headers: &HeaderMap) -> HashMap<&str, String> {
+    let mut joined: HashMap<&str, String> = HashMap::new();
+    for (name, value) in headers.iter() {
+        if let Ok(value) = value.to_str() {
+            joined
+                .entry(name.as_str())
+                .and_modify(|values| {
+                    values.push_str(", ");
+                    values.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_converts_http_api_events() {
+        let event: LambdaEvent = serde_json::from_value(json!({
+            "version": "2.0",
+            "rawPath": "/graphql",
+            "rawQueryString": "query=%7B__typename%7D",
+            "cookies": ["a=1", "b=2"],
+            "headers": { "accept": "application/json" },
+            "requestContext": { "http": { "method": "GET", "sourceIp": "10.0.0.1" } },
+            "isBase64Encoded": false
+        }))
+        .unwrap();
+        let request = event.into_request().unwrap();
+        assert_eq!(request.method(), http::Method::GET);
+        assert_eq!(request.uri(), "/graphql?query=%7B__typename%7D");
+        assert_eq!(request.headers()["cookie"], "a=1; b=2");
+        assert_eq!(
+            request
+                .extensions()
+                .get::<ConnectionInfo>()
+                .unwrap()
+                .peer_address,
+            "10.0.0.1:0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn it_converts_rest_api_events() {
+        let event: LambdaEvent = serde_json::from_value(json!({
+            "httpMethod": "POST",
+            "path": "/graphql",
+            "multiValueHeaders": { "x-custom": ["a", "b"] },
+            "multiValueQueryStringParameters": null,
+            "body": "eyJxdWVyeSI6InsgX190eXBlbmFtZSB9In0=",
+            "isBase64Encoded": true,
+            "requestContext": { "identity": { "sourceIp": "10.0.0.2" } }
+        }))
+        .unwrap();
+        let request = event.into_request().unwrap();
+        assert_eq!(request.method(), http::Method::POST);
+        assert_eq!(request.uri(), "/graphql");
+        assert_eq!(request.headers().get_all("x-custom").iter().count(), 2);
+    }
+}
@@ -9,6 +9,7 @@ use futures::channel::mpsc::SendError;
 use futures::channel::mpsc::Sender;
 use futures::future::BoxFuture;
 use futures::stream::once;
+use futures::FutureExt;
 use futures::SinkExt;
 use futures::StreamExt;
 use serde_json_bytes::Value;
@@ -22,6 +23,8 @@ use super::layers::allow_only_http_post_mutations::AllowOnlyHttpPostMutationsLay
 use super::new_service::ServiceFactory;
 use super::Plugins;
 use super::SubgraphServiceFactory;
+use crate::compute_job;
+use crate::graphql;
 use crate::graphql::IncrementalResponse;
 use crate::graphql::Response;
 use crate::json_ext::Object;
@@ -93,8 +96,43 @@ impl Service<ExecutionRequest> for ExecutionService {
             let schema = this.schema.clone();
             let mut nullified_paths: Vec<Path> = vec![];
 
+            let format_query = query.clone();
+            let format_operation_name = operation_name.clone();
+            let format_variables = variables.clone();
             let stream = stream
-                .filter_map(move |mut response: Response| {
+                // formatting large responses takes time, it runs on the compute threads
+                .then(move |mut response: Response| {
+                    let query = format_query.clone();
+                    let operation_name = format_operation_name.clone();
+                    let variables = format_variables.clone();
+                    let schema = schema.clone();
+                    let has_next = response.has_next;
+                    compute_job::execute(move || {
+                        let paths = tracing::debug_span!("format_response").in_scope(|| {
+                            query.format_response(
+                                &mut response,
+                                operation_name.as_deref(),
+                                is_deferred,
+                                variables,
+                                schema.api_schema(),
+                            )
+                        });
+                        (response, paths)
+                    })
+                    .map(move |result| {
+                        result.unwrap_or_else(|error| {
+                            let response = Response::builder()
+                                .errors(vec![graphql::Error::builder()
+                                    .message(format!("could not format the response: {error}"))
+                                    .extension_code("INTERNAL_SERVER_ERROR")
+                                    .build()])
+                                .and_has_next(has_next)
+                                .build();
+                            (response, Vec::new())
+                        })
+                    })
+                })
+                .filter_map(move |(mut response, paths): (Response, Vec<Path>)| {
                     // responses that would fall under a path that was previously nullified are not sent
                     if nullified_paths.iter().any(|path| match &response.path {
                         None => false,
@@ -108,20 +146,10 @@ impl Service<ExecutionRequest> for ExecutionService {
                     }
 
                     let has_next = response.has_next.unwrap_or(true);
-                    tracing::debug_span!("format_response").in_scope(|| {
-                        let paths = query.format_response(
-                            &mut response,
-                            operation_name.as_deref(),
-                            is_deferred,
-                            variables.clone(),
-                            schema.api_schema(),
-                        );
-
-                        nullified_paths.extend(paths.into_iter());
-                    });
+                    nullified_paths.extend(paths.into_iter());
 
                     match (response.path.as_ref(), response.data.as_ref()) {
-                        (None, _) | (_, None) => {
+                        (None, _) => {
                             if is_deferred {
                                 response.has_next = Some(has_next);
                             }
@@ -132,6 +160,28 @@ impl Service<ExecutionRequest> for ExecutionService {
                                 });
                             ready(Some(response))
                         }
+                        // a deferred fragment without data, nullified by an error, is still
+                        // delivered as an incremental item so that clients see its label and errors
+                        (Some(response_path), None) => {
+                            let incremental = if response.errors.is_empty() && response.extensions.is_empty() {
+                                Vec::new()
+                            } else {
+                                vec![IncrementalResponse::builder()
+                                    .and_label(response.label.clone())
+                                    .data(Value::Null)
+                                    .path(response_path.clone())
+                                    .errors(response.errors.clone())
+                                    .extensions(response.extensions.clone())
+                                    .build()]
+                            };
+
+                            ready(Some(
+                                Response::builder()
+                                    .has_next(has_next)
+                                    .incremental(incremental)
+                                    .build(),
+                            ))
+                        }
                         // if the deferred response specified a path, we must extract the
                         // values matched by that path and create a separate response for
                         // each of them.
@@ -18,11 +18,16 @@ use serde::Serialize;
 use strum_macros::Display;
 use tower::BoxError;
 
+#[cfg(test)]
+pub(crate) use self::grpc::proto;
+pub(crate) use self::grpc::GrpcClient;
 use crate::error::LicenseError;
 use crate::services::apollo_graph_reference;
 use crate::tracer::TraceId;
 use crate::Context;
 
+mod grpc;
+
 const DEFAULT_EXTERNALIZATION_TIMEOUT: Duration = Duration::from_secs(1);
 
 static CLIENT: Lazy<Result<Client, BoxError>> = Lazy::new(|| {
@@ -33,6 +38,15 @@ static CLIENT: Lazy<Result<Client, BoxError>> = Lazy::new(|| {
 /// Version of our externalised data. Rev this if it changes
 const EXTERNALIZABLE_VERSION: u8 = 1;
 
+/// How the co-processor is called
+#[derive(Debug)]
+pub(crate) enum Transport {
+    /// One HTTP POST request per call to the url
+    Http(String),
+    /// One message per call on a bi-directional gRPC stream
+    Grpc(GrpcClient),
+}
+
 #[derive(Clone, Debug, Display, Deserialize, PartialEq, Serialize, JsonSchema)]
 pub(crate) enum PipelineStep {
     RouterRequest,
@@ -77,10 +91,18 @@ pub(crate) struct Externalizable<T> {
     pub(crate) stage: String,
     pub(crate) control: Control,
     pub(crate) id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) service_name: Option<String>,
     pub(crate) headers: Option<HashMap<String, Vec<String>>>,
     pub(crate) body: Option<T>,
     pub(crate) context: Option<Context>,
     pub(crate) sdl: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) status_code: Option<u16>,
 }
 
 impl<T> Externalizable<T>
@@ -99,17 +121,34 @@ where
             stage: stage.to_string(),
             control: Control::default(),
             id: TraceId::maybe_new().map(|id| id.to_string()),
+            service_name: None,
             headers,
             body,
             context,
             sdl,
+            method: None,
+            path: None,
+            status_code: None,
         }
     }
 
-    pub(crate) async fn call(self, url: &str, timeout: Option<Duration>) -> Result<Self, BoxError> {
+    pub(crate) async fn call(
+        self,
+        transport: &Transport,
+        timeout: Option<Duration>,
+    ) -> Result<Self, BoxError> {
         let my_client = CLIENT.as_ref().map_err(|e| e.to_string())?.clone();
         let t = timeout.unwrap_or(DEFAULT_EXTERNALIZATION_TIMEOUT);
 
+        let url = match transport {
+            Transport::Http(url) => url,
+            Transport::Grpc(client) => {
+                tracing::debug!("forwarding message: {:?}", self);
+                let reply = client.call(self.to_proto()?, t).await?;
+                return Self::from_proto(reply);
+            }
+        };
+
         tracing::debug!("forwarding json: {}", serde_json::to_string(&self)?);
         let response = my_client
             .post(url)
@@ -0,0 +1,393 @@
+// With regards to ELv2 licensing, this entire file is license key functionality
+
+//! gRPC transport for co-processors
+//!
+//! All the co-processor calls of a plugin are multiplexed on one bi-directional stream,
+//! which avoids the cost of an HTTP request per externalized stage.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Endpoint;
+use tower::BoxError;
+
+use super::Control;
+use super::Externalizable;
+
+#[allow(unreachable_pub)]
+pub(crate) mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+    tonic::include_proto!("coprocessor");
+}
+
+/// Number of messages waiting to be sent on the stream before callers wait
+const STREAM_BUFFER: usize = 1024;
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<proto::Externalizable>>>>;
+
+/// Client of a gRPC co-processor
+///
+/// The stream is opened on the first call, and opened again on the next call after it closes.
+/// Opening the stream counts in the timeout of the call, so that an unreachable co-processor
+/// fails the calls instead of blocking them.
+#[derive(Debug)]
+pub(crate) struct GrpcClient {
+    endpoint: Endpoint,
+    next_correlation_id: AtomicU64,
+    stream: tokio::sync::Mutex<Option<Stream>>,
+}
+
+#[derive(Clone, Debug)]
+struct Stream {
+    sender: mpsc::Sender<proto::Externalizable>,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+}
+
+impl GrpcClient {
+    pub(crate) fn new(url: &str, timeout: Option<Duration>) -> Result<Self, BoxError> {
+        Ok(Self {
+            endpoint: Endpoint::from_shared(url.to_string())?
+                .connect_timeout(timeout.unwrap_or(super::DEFAULT_EXTERNALIZATION_TIMEOUT)),
+            next_correlation_id: AtomicU64::new(0),
+            stream: Default::default(),
+        })
+    }
+
+    /// Sends a message and waits for the reply with the same correlation id
+    pub(crate) async fn call(
+        &self,
+        mut message: proto::Externalizable,
+        timeout: Duration,
+    ) -> Result<proto::Externalizable, BoxError> {
+        let correlation_id = self.next_correlation_id.fetch_add(1, Ordering::Relaxed);
+        message.correlation_id = correlation_id;
+
+        tokio::time::timeout(timeout, async {
+            let stream = self.stream().await?;
+            let (reply_sender, reply_receiver) = oneshot::channel();
+            stream
+                .pending
+                .lock()
+                .expect("lock poisoned")
+                .insert(correlation_id, reply_sender);
+            let _guard = PendingGuard {
+                pending: stream.pending.clone(),
+                correlation_id,
+            };
+
+            stream
+                .sender
+                .send(message)
+                .await
+                .map_err(|_| "the co-processor stream is closed")?;
+            Ok::<_, BoxError>(
+                reply_receiver
+                    .await
+                    .map_err(|_| "the co-processor stream is closed")?,
+            )
+        })
+        .await
+        .map_err(|_| "the co-processor did not reply in time")?
+    }
+
+    async fn stream(&self) -> Result<Stream, BoxError> {
+        let mut guard = self.stream.lock().await;
+        match &*guard {
+            Some(stream) if !stream.closed.load(Ordering::Acquire) => Ok(stream.clone()),
+            _ => {
+                let stream = self.connect().await?;
+                *guard = Some(stream.clone());
+                Ok(stream)
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Stream, BoxError> {
+        let channel = self.endpoint.connect().await?;
+        let mut client = proto::coprocessor_client::CoprocessorClient::new(channel);
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let mut replies = client
+            .process(ReceiverStream::new(receiver))
+            .await?
+            .into_inner();
+
+        let stream = Stream {
+            sender,
+            pending: Default::default(),
+            closed: Default::default(),
+        };
+        let pending = stream.pending.clone();
+        let closed = stream.closed.clone();
+        tokio::spawn(async move {
+            loop {
+                match replies.message().await {
+                    Ok(Some(reply)) => {
+                        let reply_sender = pending
+                            .lock()
+                            .expect("lock poisoned")
+                            .remove(&reply.correlation_id);
+                        match reply_sender {
+                            // The receiver is gone if the call timed out
+                            Some(reply_sender) => {
+                                let _ = reply_sender.send(reply);
+                            }
+                            None => tracing::warn!(
+                                correlation_id = reply.correlation_id,
+                                "unexpected reply from the co-processor"
+                            ),
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(status) => {
+                        tracing::error!(%status, "co-processor stream failed");
+                        break;
+                    }
+                }
+            }
+            closed.store(true, Ordering::Release);
+            // Dropping the reply senders fails the calls still waiting
+            pending.lock().expect("lock poisoned").clear();
+        });
+
+        Ok(stream)
+    }
+}
+
+/// Forgets the reply sender of a call when it completes, is cancelled or times out
+struct PendingGuard {
+    pending: Pending,
+    correlation_id: u64,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending
+            .lock()
+            .expect("lock poisoned")
+            .remove(&self.correlation_id);
+    }
+}
+
+impl<T> Externalizable<T>
+where
+    T: Debug + DeserializeOwned + Serialize + Send + Sync,
+{
+    pub(crate) fn to_proto(&self) -> Result<proto::Externalizable, BoxError> {
+        Ok(proto::Externalizable {
+            correlation_id: 0,
+            version: self.version.into(),
+            stage: self.stage.clone(),
+            break_status: match self.control {
+                Control::Continue => 0,
+                Control::Break(status) => status.into(),
+            },
+            id: self.id.clone(),
+            service_name: self.service_name.clone(),
+            headers: self.headers.as_ref().map(|headers| proto::Headers {
+                entries: headers
+                    .iter()
+                    .map(|(name, values)| {
+                        (
+                            name.clone(),
+                            proto::HeaderValues {
+                                values: values.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+            }),
+            body: self.body.as_ref().map(serde_json::to_string).transpose()?,
+            context: self
+                .context
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+            sdl: self.sdl.clone(),
+            method: self.method.clone(),
+            path: self.path.clone(),
+            status_code: self.status_code.map(u32::from),
+        })
+    }
+
+    pub(crate) fn from_proto(message: proto::Externalizable) -> Result<Self, BoxError> {
+        Ok(Self {
+            version: message.version.try_into()?,
+            stage: message.stage,
+            control: match message.break_status {
+                0 => Control::Continue,
+                status => Control::Break(status.try_into()?),
+            },
+            id: message.id,
+            service_name: message.service_name,
+            headers: message.headers.map(|headers| {
+                headers
+                    .entries
+                    .into_iter()
+                    .map(|(name, values)| (name, values.values))
+                    .collect()
+            }),
+            body: message
+                .body
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            context: message
+                .context
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            sdl: message.sdl,
+            method: message.method,
+            path: message.path,
+            status_code: message.status_code.map(u16::try_from).transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+
+    use futures::StreamExt;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::Request;
+    use tonic::Response;
+    use tonic::Status;
+    use tonic::Streaming;
+
+    use super::proto::coprocessor_server::Coprocessor;
+    use super::proto::coprocessor_server::CoprocessorServer;
+    use super::*;
+    use crate::services::external::PipelineStep;
+
+    /// Replies to messages by pairs in reverse order, adding a header
+    struct Echo;
+
+    #[tonic::async_trait]
+    impl Coprocessor for Echo {
+        type ProcessStream =
+            Pin<Box<dyn futures::Stream<Item = Result<proto::Externalizable, Status>> + Send>>;
+
+        async fn process(
+            &self,
+            request: Request<Streaming<proto::Externalizable>>,
+        ) -> Result<Response<Self::ProcessStream>, Status> {
+            let replies = request.into_inner().chunks(2).flat_map(|messages| {
+                futures::stream::iter(messages.into_iter().rev().map(|message| {
+                    message.map(|mut message| {
+                        message
+                            .headers
+                            .get_or_insert_with(Default::default)
+                            .entries
+                            .insert(
+                                "x-coprocessor".to_string(),
+                                proto::HeaderValues {
+                                    values: vec!["grpc".to_string()],
+                                },
+                            );
+                        message
+                    })
+                }))
+            });
+            Ok(Response::new(Box::pin(replies)))
+        }
+    }
+
+    async fn server() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(CoprocessorServer::new(Echo))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn it_matches_replies_to_calls() {
+        let client = Arc::new(GrpcClient::new(&server().await, None).unwrap());
+
+        let calls = ["a", "b"].map(|body| {
+            let client = client.clone();
+            let message = Externalizable::new(
+                PipelineStep::SubgraphRequest,
+                None,
+                Some(body.to_string()),
+                None,
+                None,
+            )
+            .to_proto()
+            .unwrap();
+            async move { client.call(message, Duration::from_secs(5)).await }
+        });
+        let [a, b]: [_; 2] = futures::future::join_all(calls).await.try_into().unwrap();
+
+        for (reply, expected) in [(a, "a"), (b, "b")] {
+            let reply = Externalizable::<String>::from_proto(reply.unwrap()).unwrap();
+            assert_eq!(reply.body.as_deref(), Some(expected));
+            assert_eq!(reply.headers.unwrap()["x-coprocessor"], vec!["grpc"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_times_out_connecting_to_an_unresponsive_coprocessor() {
+        // the connections are accepted by the kernel, but the HTTP/2 handshake never completes
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = GrpcClient::new(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            Some(Duration::from_millis(100)),
+        )
+        .unwrap();
+        let message =
+            Externalizable::<String>::new(PipelineStep::SubgraphRequest, None, None, None, None)
+                .to_proto()
+                .unwrap();
+
+        let reply = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.call(message, Duration::from_millis(100)),
+        )
+        .await
+        .expect("the call must time out by itself");
+        assert!(reply.is_err());
+    }
+
+    #[test]
+    fn it_converts_to_and_from_proto() {
+        let mut externalizable = Externalizable::new(
+            PipelineStep::RouterRequest,
+            Some(HashMap::from([(
+                "accept".to_string(),
+                vec!["application/json".to_string()],
+            )])),
+            Some(serde_json::json!({"query": "{ me }"})),
+            None,
+            Some("type Query { me: String }".to_string()),
+        );
+        externalizable.control = Control::Break(403);
+        externalizable.method = Some("POST".to_string());
+        externalizable.path = Some("/graphql".to_string());
+
+        let converted =
+            Externalizable::<serde_json::Value>::from_proto(externalizable.to_proto().unwrap())
+                .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&converted).unwrap(),
+            serde_json::to_value(&externalizable).unwrap()
+        );
+    }
+}
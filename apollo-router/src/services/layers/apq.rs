@@ -35,6 +35,11 @@ impl APQLayer {
         Self { cache }
     }
 
+    /// Removes the persisted queries from the in-memory cache
+    pub(crate) async fn clear(&self) {
+        self.cache.clear().await
+    }
+
     pub(crate) async fn request(
         &self,
         request: SupergraphRequest,
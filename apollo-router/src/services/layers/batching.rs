@@ -0,0 +1,320 @@
+//! Batching of operations
+//!
+//! Clients can send several operations as a JSON array in a single HTTP request. Each operation
+//! of the batch is executed as its own request, with a copy of the request context, and the batch
+//! is answered with the array of their responses, in order. The operations share the connection
+//! entries of the request context, and the `Set-Cookie` headers of their responses are all sent
+//! with the batch response.
+//!
+//! Only the bodies that start with a JSON array are buffered, up to `max_body_size`: the others
+//! are streamed to the router service.
+
+use std::task::Poll;
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use futures::future::BoxFuture;
+use futures::stream;
+use futures::StreamExt;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::header::SET_COOKIE;
+use http::HeaderValue;
+use http::Method;
+use http::StatusCode;
+use hyper::body::HttpBody;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+
+use super::content_negociation::ACCEPTS_JSON_CONTEXT_KEY;
+use super::content_negociation::ACCEPTS_MULTIPART_CONTEXT_KEY;
+use crate::configuration::Batching;
+use crate::error::router_error_response;
+use crate::graphql;
+use crate::http_server_factory::ConnectionInfo;
+use crate::services::router;
+use crate::Context;
+
+/// [`Layer`] splitting batches of operations
+#[derive(Clone)]
+pub(crate) struct BatchingLayer {
+    config: Batching,
+}
+
+impl BatchingLayer {
+    pub(crate) fn new(config: Batching) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> Layer<S> for BatchingLayer {
+    type Service = BatchingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BatchingService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct BatchingService<S> {
+    inner: S,
+    config: Batching,
+}
+
+impl<S> Service<router::Request> for BatchingService<S>
+where
+    S: Service<router::Request, Response = router::Response, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <S as Service<router::Request>>::Future: Send + 'static,
+{
+    type Response = router::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: router::Request) -> Self::Future {
+        if !self.config.enabled || request.router_request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let router::Request {
+                router_request,
+                context,
+            } = request;
+            let (parts, mut body) = router_request.into_parts();
+
+            // only the bodies starting with a JSON array are buffered
+            let mut chunks = Vec::new();
+            let first_byte = loop {
+                match body.data().await {
+                    Some(Ok(chunk)) => {
+                        let first_byte = chunk
+                            .iter()
+                            .find(|byte| !byte.is_ascii_whitespace())
+                            .copied();
+                        chunks.push(chunk);
+                        if first_byte.is_some() {
+                            break first_byte;
+                        }
+                    }
+                    Some(Err(error)) => return Ok(unreadable_body(error, context)),
+                    None => break None,
+                }
+            };
+            if first_byte != Some(b'[') {
+                let body = stream::iter(chunks.into_iter().map(Ok)).chain(body);
+                let request = http::Request::from_parts(parts, Body::wrap_stream(body));
+                return inner
+                    .oneshot(router::Request {
+                        router_request: request,
+                        context,
+                    })
+                    .await;
+            }
+
+            let mut bytes = BytesMut::new();
+            let mut chunks = stream::iter(chunks.into_iter().map(Ok)).chain(body);
+            while let Some(chunk) = chunks.next().await {
+                match chunk {
+                    Ok(chunk) => bytes.extend_from_slice(&chunk),
+                    Err(error) => return Ok(unreadable_body(error, context)),
+                }
+                if bytes.len() > config.max_body_size {
+                    let error = graphql::Error::builder()
+                        .message(format!(
+                            "the body of batches must not exceed {} bytes",
+                            config.max_body_size
+                        ))
+                        .extension_code("BATCH_TOO_LARGE")
+                        .build();
+                    return router::Response::error_builder()
+                        .error(error)
+                        .status_code(StatusCode::PAYLOAD_TOO_LARGE)
+                        .context(context)
+                        .build();
+                }
+            }
+            let bytes = bytes.freeze();
+
+            let operations = match serde_json::from_slice::<Vec<serde_json::Value>>(&bytes) {
+                Ok(operations) => operations,
+                // the router service answers with the deserialization error
+                Err(_) => {
+                    let request = http::Request::from_parts(parts, Body::from(bytes));
+                    return inner
+                        .oneshot(router::Request {
+                            router_request: request,
+                            context,
+                        })
+                        .await;
+                }
+            };
+
+            if operations.is_empty() || operations.len() > config.max_size {
+                let error = graphql::Error::builder()
+                    .message(format!(
+                        "batches must contain between 1 and {} operations, found {}",
+                        config.max_size,
+                        operations.len()
+                    ))
+                    .extension_code("BATCH_LIMIT_EXCEEDED")
+                    .build();
+                return router::Response::error_builder()
+                    .error(error)
+                    .status_code(StatusCode::BAD_REQUEST)
+                    .context(context)
+                    .build();
+            }
+
+            tracing::info!(histogram.apollo_router_batch_size = operations.len() as f64);
+            let requests = operations
+                .iter()
+                .map(|operation| {
+                    let mut request =
+                        http::Request::new(Body::from(serde_json::to_vec(operation)?));
+                    *request.method_mut() = parts.method.clone();
+                    *request.uri_mut() = parts.uri.clone();
+                    *request.version_mut() = parts.version;
+                    *request.headers_mut() = parts.headers.clone();
+                    request.headers_mut().remove(CONTENT_LENGTH);
+                    // the other extensions cannot be cloned
+                    if let Some(connection_info) = parts.extensions.get::<ConnectionInfo>() {
+                        request.extensions_mut().insert(*connection_info);
+                    }
+
+                    // the responses of batched operations are JSON objects, without incremental
+                    // delivery
+                    let operation_context = Context::for_connection(&context);
+                    for entry in context.iter() {
+                        operation_context
+                            .insert_json_value(entry.key().clone(), entry.value().clone());
+                    }
+                    operation_context.insert(ACCEPTS_JSON_CONTEXT_KEY, true)?;
+                    operation_context.insert(ACCEPTS_MULTIPART_CONTEXT_KEY, false)?;
+
+                    Ok(router::Request {
+                        router_request: request,
+                        context: operation_context,
+                    })
+                })
+                .collect::<Result<Vec<_>, BoxError>>()?;
+
+            // the calls are created before the stream so that it does not borrow the service
+            let calls = requests
+                .into_iter()
+                .map(|request| {
+                    let inner = inner.clone();
+                    async move { operation_response(inner.oneshot(request).await).await }
+                })
+                .collect::<Vec<_>>();
+            let responses: Vec<(Bytes, Vec<HeaderValue>)> = stream::iter(calls)
+                .buffered(config.max_concurrency.max(1))
+                .collect()
+                .await;
+
+            let mut body =
+                Vec::with_capacity(responses.iter().map(|(r, _)| r.len() + 1).sum::<usize>() + 1);
+            let mut response = http::Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str());
+            body.push(b'[');
+            for (index, (operation_body, set_cookies)) in responses.into_iter().enumerate() {
+                if index > 0 {
+                    body.push(b',');
+                }
+                body.extend_from_slice(&operation_body);
+                for set_cookie in set_cookies {
+                    response = response.header(SET_COOKIE, set_cookie);
+                }
+            }
+            body.push(b']');
+
+            Ok(router::Response {
+                response: response.body(Body::from(body))?,
+                context,
+            })
+        })
+    }
+}
+
+fn unreadable_body(error: hyper::Error, context: Context) -> router::Response {
+    ::tracing::error!(
+        monotonic_counter.apollo_router_http_requests_total = 1u64,
+        status = %400,
+        error = "failed to get the request body",
+    );
+    let error = graphql::Error::builder()
+        .message("Invalid GraphQL request")
+        .extension_code("INVALID_GRAPHQL_REQUEST")
+        .extension(
+            "details",
+            format!("failed to get the request body: {error}"),
+        )
+        .build();
+    router::Response {
+        response: router_error_response(StatusCode::BAD_REQUEST, error),
+        context,
+    }
+}
+
+/// The JSON response of an operation of a batch, and its `Set-Cookie` headers
+async fn operation_response(
+    response: Result<router::Response, BoxError>,
+) -> (Bytes, Vec<HeaderValue>) {
+    let mut set_cookies = Vec::new();
+    let (status, body) = match response {
+        Ok(response) => {
+            let (parts, body) = response.response.into_parts();
+            set_cookies.extend(parts.headers.get_all(SET_COOKIE).iter().cloned());
+            let is_json = parts
+                .headers
+                .get(CONTENT_TYPE)
+                .map_or(false, |content_type| {
+                    content_type
+                        .as_bytes()
+                        .starts_with(APPLICATION_JSON.essence_str().as_bytes())
+                });
+            match hyper::body::to_bytes(body).await {
+                Ok(body) if is_json => (parts.status, Ok(body)),
+                Ok(body) => (
+                    parts.status,
+                    Err(String::from_utf8_lossy(&body).into_owned()),
+                ),
+                Err(error) => (parts.status, Err(error.to_string())),
+            }
+        }
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, Err(error.to_string())),
+    };
+    tracing::info!(
+        monotonic_counter.apollo_router_batched_operations_total = 1u64,
+        status = %status.as_u16(),
+    );
+    let body = body.unwrap_or_else(|message| {
+        let response = graphql::Response::builder()
+            .error(
+                graphql::Error::builder()
+                    .message(message)
+                    .extension_code("BATCH_OPERATION_FAILED")
+                    .build(),
+            )
+            .build();
+        serde_json::to_vec(&response).unwrap_or_default().into()
+    });
+    (body, set_cookies)
+}
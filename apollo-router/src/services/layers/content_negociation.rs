@@ -19,6 +19,7 @@ use tower::Layer;
 use tower::Service;
 use tower::ServiceExt;
 
+use crate::error::router_error_response;
 use crate::graphql;
 use crate::layers::sync_checkpoint::CheckpointService;
 use crate::layers::ServiceExt as _;
@@ -50,24 +51,15 @@ where
                 if req.router_request.method() != Method::GET
                     && !content_type_is_json(req.router_request.headers())
                 {
-                    let response: http::Response<hyper::Body> = http::Response::builder()
-                        .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
-                        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-                        .body(
-                            hyper::Body::from(
-                                serde_json::to_string(
-                                    &graphql::Error::builder()
-                                        .message(format!(
-                                            r#"'content-type' header can't be different from {:?} or {:?}"#,
-                                            APPLICATION_JSON.essence_str(),
-                                            GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
-                                        ))
-                                        .extension_code("INVALID_ACCEPT_HEADER")
-                                        .build(),
-                                )
-                                .unwrap_or_else(|_| String::from("Invalid request"))
+                    let error = graphql::Error::builder()
+                        .message(format!(
+                            r#"'content-type' header can't be different from {:?} or {:?}"#,
+                            APPLICATION_JSON.essence_str(),
+                            GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
                         ))
-                        .expect("cannot fail");
+                        .extension_code("INVALID_CONTENT_TYPE_HEADER")
+                        .build();
+                    let response = router_error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, error);
 
                     return Ok(ControlFlow::Break(response.into()));
                 }
@@ -88,21 +80,16 @@ where
 
                     Ok(ControlFlow::Continue(req))
                 } else {
-                    let response: http::Response<hyper::Body> = http::Response::builder().status(StatusCode::NOT_ACCEPTABLE).header(CONTENT_TYPE, APPLICATION_JSON.essence_str()).body(
-                            hyper::Body::from(
-                                serde_json::to_string(
-                                    &graphql::Error::builder()
-                                        .message(format!(
-                                            r#"'accept' header can't be different from \"*/*\", {:?}, {:?} or {:?}"#,
-                                            APPLICATION_JSON.essence_str(),
-                                            GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
-                                            MULTIPART_DEFER_CONTENT_TYPE
-                                        ))
-                                        .extension_code("INVALID_ACCEPT_HEADER")
-                                        .build(),
-                                )
-                                .unwrap_or_else(|_| String::from("Invalid request"))
-                        )).expect("cannot fail");
+                    let error = graphql::Error::builder()
+                        .message(format!(
+                            r#"'accept' header can't be different from \"*/*\", {:?}, {:?} or {:?}"#,
+                            APPLICATION_JSON.essence_str(),
+                            GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
+                            MULTIPART_DEFER_CONTENT_TYPE
+                        ))
+                        .extension_code("INVALID_ACCEPT_HEADER")
+                        .build();
+                    let response = router_error_response(StatusCode::NOT_ACCEPTABLE, error);
 
                     Ok(ControlFlow::Break(response.into()))
                 }
@@ -1,5 +1,7 @@
 //! Layers that are internal to the execution pipeline.
 pub(crate) mod allow_only_http_post_mutations;
 pub(crate) mod apq;
+pub(crate) mod batching;
 pub(crate) mod content_negociation;
+pub(crate) mod persisted_operations;
 pub(crate) mod static_page;
@@ -0,0 +1,205 @@
+//! Persisted operations referenced by document ID
+//!
+//! Relay and Apollo clients can send the ID of an operation of their persisted operation manifest
+//! in the `documentId` field of requests, instead of its document. The manifests are loaded with
+//! the configuration, and the document of the operation is set as the query of the request.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use http::StatusCode;
+use serde::Deserialize;
+use tower::BoxError;
+
+use crate::configuration::PersistedOperations;
+use crate::services::SupergraphRequest;
+use crate::services::SupergraphResponse;
+
+/// A persisted operation manifest
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Manifest {
+    /// Apollo manifest: `{"format": "apollo-persisted-query-manifest", "operations": [...]}`
+    Apollo { operations: Vec<ManifestOperation> },
+    /// Relay manifest: the document of each operation, by ID
+    Relay(HashMap<String, String>),
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: String,
+    body: String,
+}
+
+/// [`Layer`] resolving the document IDs of persisted operations
+#[derive(Clone)]
+pub(crate) struct PersistedOperationsLayer {
+    /// Documents by ID
+    operations: Arc<HashMap<String, String>>,
+}
+
+impl PersistedOperationsLayer {
+    /// Loads the manifests, `None` if none is configured
+    pub(crate) fn from_configuration(
+        configuration: &PersistedOperations,
+    ) -> Result<Option<Self>, BoxError> {
+        if configuration.manifests.is_empty() {
+            return Ok(None);
+        }
+        let mut operations = HashMap::new();
+        for path in &configuration.manifests {
+            for (id, body) in read_manifest(path)? {
+                match operations.get(&id) {
+                    Some(existing) if *existing != body => {
+                        return Err(format!(
+                            "the persisted operation '{id}' of '{}' has several documents",
+                            path.display()
+                        )
+                        .into());
+                    }
+                    _ => {
+                        operations.insert(id, body);
+                    }
+                }
+            }
+        }
+        tracing::info!("loaded {} persisted operations", operations.len());
+        Ok(Some(Self {
+            operations: Arc::new(operations),
+        }))
+    }
+
+    /// Sets the document of the persisted operation as the query of the request. The requests
+    /// sending their query are executed with it
+    pub(crate) fn request(
+        &self,
+        mut request: SupergraphRequest,
+    ) -> Result<SupergraphRequest, SupergraphResponse> {
+        let body = request.supergraph_request.body_mut();
+        let document_id = match &body.document_id {
+            Some(document_id) if body.query.is_none() => document_id,
+            _ => return Ok(request),
+        };
+        match self.operations.get(document_id) {
+            Some(document) => {
+                body.query = Some(document.clone());
+                Ok(request)
+            }
+            None => {
+                let error = crate::error::Error::builder()
+                    .message(format!("unknown persisted operation '{document_id}'"))
+                    .extension_code("PERSISTED_OPERATION_NOT_FOUND")
+                    .build();
+                Err(SupergraphResponse::builder()
+                    .error(error)
+                    .status_code(StatusCode::BAD_REQUEST)
+                    .context(request.context)
+                    .build()
+                    .expect("response is valid"))
+            }
+        }
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Vec<(String, String)>, BoxError> {
+    let manifest = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "could not read the persisted operation manifest '{}': {e}",
+            path.display()
+        )
+    })?;
+    let manifest = serde_json::from_str(&manifest).map_err(|e| {
+        format!(
+            "invalid persisted operation manifest '{}': {e}",
+            path.display()
+        )
+    })?;
+    Ok(match manifest {
+        Manifest::Apollo { operations } => operations
+            .into_iter()
+            .map(|operation| (operation.id, operation.body))
+            .collect(),
+        Manifest::Relay(operations) => operations.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn layer(manifests: &[&str]) -> Result<Option<PersistedOperationsLayer>, BoxError> {
+        let files = manifests
+            .iter()
+            .map(|manifest| {
+                let mut file = tempfile::NamedTempFile::new().unwrap();
+                file.write_all(manifest.as_bytes()).unwrap();
+                file
+            })
+            .collect::<Vec<_>>();
+        PersistedOperationsLayer::from_configuration(&PersistedOperations {
+            manifests: files.iter().map(|file| file.path().to_owned()).collect(),
+        })
+    }
+
+    fn supergraph_request(query: Option<&str>, document_id: &str) -> SupergraphRequest {
+        let mut request = SupergraphRequest::fake_builder()
+            .and_query(query.map(str::to_string))
+            .build()
+            .unwrap();
+        request.supergraph_request.body_mut().document_id = Some(document_id.to_string());
+        request
+    }
+
+    #[test]
+    fn it_resolves_document_ids_of_apollo_and_relay_manifests() {
+        let layer = layer(&[
+            r#"{
+                "format": "apollo-persisted-query-manifest",
+                "version": 1,
+                "operations": [
+                    {"id": "a1", "name": "Me", "type": "query", "body": "query Me { me { id } }"}
+                ]
+            }"#,
+            r#"{"r1": "query TopProducts { topProducts { upc } }"}"#,
+        ])
+        .unwrap()
+        .unwrap();
+
+        let request = layer.request(supergraph_request(None, "a1")).ok().unwrap();
+        assert_eq!(
+            request.supergraph_request.body().query.as_deref(),
+            Some("query Me { me { id } }")
+        );
+        let request = layer.request(supergraph_request(None, "r1")).ok().unwrap();
+        assert_eq!(
+            request.supergraph_request.body().query.as_deref(),
+            Some("query TopProducts { topProducts { upc } }")
+        );
+        // the query sent with the request is kept
+        let request = layer
+            .request(supergraph_request(Some("{ me { id } }"), "a1"))
+            .ok()
+            .unwrap();
+        assert_eq!(
+            request.supergraph_request.body().query.as_deref(),
+            Some("{ me { id } }")
+        );
+
+        let response = layer
+            .request(supergraph_request(None, "unknown"))
+            .err()
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn it_rejects_conflicting_manifests() {
+        assert!(layer(&[]).unwrap().is_none());
+        assert!(layer(&[r#"{"a1": "{ me { id } }"}"#, r#"{"a1": "{ me { name } }"}"#]).is_err());
+        assert!(layer(&[r#"{"a1": "{ me { id } }"}"#, r#"{"a1": "{ me { id } }"}"#]).is_ok());
+        assert!(layer(&["[]"]).is_err());
+    }
+}
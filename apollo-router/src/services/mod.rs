@@ -28,6 +28,7 @@ pub(crate) mod external;
 pub(crate) mod layers;
 pub(crate) mod new_service;
 pub(crate) mod query_planner;
+mod response_memory;
 pub mod router;
 pub(crate) mod router_service;
 pub mod subgraph;
@@ -0,0 +1,149 @@
+//! Memory budget of the responses waiting to be read by clients
+//!
+//! The chunks of serialized responses and deferred parts reserve their size from the budget when
+//! they are handed to the HTTP server, and release it when the server asks for the next chunk,
+//! once the previous one was written to the connection. When the budget is exhausted, the
+//! responses wait for memory to be released before sending more chunks, which also stops their
+//! serialization. With the `shed` mode, new requests are rejected until memory is released.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::Stream;
+use futures::StreamExt;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tower::BoxError;
+
+use crate::configuration::ResponseMemory;
+use crate::configuration::ResponseMemoryMode;
+
+pub(crate) struct ResponseMemoryBudget {
+    limit: usize,
+    mode: ResponseMemoryMode,
+    available: Arc<Semaphore>,
+}
+
+impl ResponseMemoryBudget {
+    /// Returns the budget of the configuration, if it has a limit
+    pub(crate) fn from_configuration(configuration: &ResponseMemory) -> Option<Arc<Self>> {
+        let limit = configuration.limit?.clamp(1, Semaphore::MAX_PERMITS);
+        Some(Arc::new(Self {
+            limit,
+            mode: configuration.mode,
+            available: Arc::new(Semaphore::new(limit)),
+        }))
+    }
+
+    /// Number of bytes reserved by the responses waiting to be read
+    #[cfg(test)]
+    fn used(&self) -> usize {
+        self.limit - self.available.available_permits()
+    }
+
+    /// Whether new requests must be rejected
+    pub(crate) fn should_shed(&self) -> bool {
+        // released memory is handed to the waiting responses first, so no memory is available
+        // as long as responses are waiting
+        self.mode == ResponseMemoryMode::Shed && self.available.available_permits() == 0
+    }
+
+    /// Reserves memory for a chunk, waiting for it to be released if the budget is exhausted
+    async fn reserve(&self, bytes: usize) -> Option<OwnedSemaphorePermit> {
+        // a chunk larger than the budget waits for the whole budget
+        let permits = bytes.min(self.limit).min(u32::MAX as usize) as u32;
+        self.available
+            .clone()
+            .acquire_many_owned(permits)
+            .await
+            .ok()
+    }
+
+    /// Counts the chunks of a response body against the budget, until the next chunk is requested
+    pub(crate) fn account<S>(
+        self: Arc<Self>,
+        chunks: S,
+    ) -> impl Stream<Item = Result<Bytes, BoxError>> + Send
+    where
+        S: Stream<Item = Result<Bytes, BoxError>> + Send,
+    {
+        let mut reserved = None;
+        chunks
+            .then(move |chunk| {
+                let budget = self.clone();
+                async move {
+                    let reservation = match &chunk {
+                        Ok(bytes) => budget.reserve(bytes.len()).await,
+                        Err(_) => None,
+                    };
+                    (chunk, reservation)
+                }
+            })
+            .map(move |(chunk, reservation)| {
+                // the previous chunk was written, its memory is released
+                drop(std::mem::replace(&mut reserved, reservation));
+                chunk
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::stream;
+
+    use super::*;
+
+    fn budget(limit: usize, mode: ResponseMemoryMode) -> Arc<ResponseMemoryBudget> {
+        ResponseMemoryBudget::from_configuration(&ResponseMemory {
+            limit: Some(limit),
+            mode,
+        })
+        .unwrap()
+    }
+
+    fn chunks(sizes: &[usize]) -> impl Stream<Item = Result<Bytes, BoxError>> + Send {
+        stream::iter(
+            sizes
+                .iter()
+                .map(|size| Ok(Bytes::from(vec![0; *size])))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn it_reserves_memory_until_the_next_chunk() {
+        let budget = budget(100, ResponseMemoryMode::Backpressure);
+        let mut body = Box::pin(budget.clone().account(chunks(&[60, 30])));
+
+        body.next().await.unwrap().unwrap();
+        assert_eq!(budget.used(), 60);
+        body.next().await.unwrap().unwrap();
+        assert_eq!(budget.used(), 30);
+        assert!(body.next().await.is_none());
+        drop(body);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_memory_to_be_released() {
+        let budget = budget(100, ResponseMemoryMode::Shed);
+        let mut slow = Box::pin(budget.clone().account(chunks(&[80])));
+        slow.next().await.unwrap().unwrap();
+        assert!(!budget.should_shed());
+
+        let mut waiting = Box::pin(budget.clone().account(chunks(&[50])));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), waiting.next())
+                .await
+                .is_err()
+        );
+        assert!(budget.should_shed());
+
+        drop(slow);
+        waiting.next().await.unwrap().unwrap();
+        assert_eq!(budget.used(), 50);
+        assert!(!budget.should_shed());
+    }
+}
@@ -12,6 +12,7 @@ use futures::future::BoxFuture;
 use futures::stream;
 use futures::stream::once;
 use futures::stream::StreamExt;
+use http::header::CONTENT_LENGTH;
 use http::header::CONTENT_TYPE;
 use http::header::VARY;
 use http::HeaderMap;
@@ -29,23 +30,35 @@ use tower::ServiceExt;
 use tower_service::Service;
 
 use super::layers::apq::APQLayer;
+use super::layers::batching::BatchingLayer;
 use super::layers::content_negociation;
 use super::layers::content_negociation::ACCEPTS_JSON_CONTEXT_KEY;
 use super::layers::content_negociation::ACCEPTS_MULTIPART_CONTEXT_KEY;
 use super::layers::content_negociation::ACCEPTS_WILDCARD_CONTEXT_KEY;
+use super::layers::persisted_operations::PersistedOperationsLayer;
 use super::layers::static_page::StaticPageLayer;
 use super::new_service::ServiceFactory;
+use super::response_memory::ResponseMemoryBudget;
 use super::router;
 use super::supergraph;
+use super::Administrable;
 use super::HasPlugins;
+use super::Plugins;
 #[cfg(test)]
 use super::SupergraphCreator;
 use super::MULTIPART_DEFER_CONTENT_TYPE;
 use crate::cache::DeduplicatingCache;
+use crate::compute_job;
+use crate::error::count_graphql_errors;
+use crate::error::router_error_response;
+use crate::error::RouterError;
 use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Value;
 #[cfg(test)]
 use crate::plugin::test::MockSupergraphService;
 use crate::router_factory::RouterFactory;
+use crate::schema_change::SchemaChange;
 use crate::services::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
 use crate::services::RouterRequest;
 use crate::services::RouterResponse;
@@ -62,17 +75,29 @@ where
     SF: ServiceFactory<supergraph::Request> + Clone + Send + Sync + 'static,
 {
     supergraph_creator: Arc<SF>,
+    persisted_operations: Option<PersistedOperationsLayer>,
     apq_layer: Option<APQLayer>,
+    plugins: Arc<Plugins>,
+    response_memory: Option<Arc<ResponseMemoryBudget>>,
 }
 
 impl<SF> RouterService<SF>
 where
     SF: ServiceFactory<supergraph::Request> + Clone + Send + Sync + 'static,
 {
-    pub(crate) fn new(supergraph_creator: Arc<SF>, apq_layer: Option<APQLayer>) -> Self {
+    pub(crate) fn new(
+        supergraph_creator: Arc<SF>,
+        persisted_operations: Option<PersistedOperationsLayer>,
+        apq_layer: Option<APQLayer>,
+        plugins: Arc<Plugins>,
+        response_memory: Option<Arc<ResponseMemoryBudget>>,
+    ) -> Self {
         RouterService {
             supergraph_creator,
+            persisted_operations,
             apq_layer,
+            plugins,
+            response_memory,
         }
     }
 }
@@ -105,6 +130,7 @@ pub(crate) async fn from_supergraph_mock_callback_and_configuration(
         &configuration,
     )
     .await
+    .expect("the persisted operations are valid")
     .make()
 }
 
@@ -145,6 +171,7 @@ pub(crate) async fn empty() -> impl Service<
         &Configuration::default(),
     )
     .await
+    .expect("the persisted operations are valid")
     .make()
 }
 
@@ -173,7 +200,35 @@ where
         let (parts, body) = router_request.into_parts();
 
         let supergraph_creator = self.supergraph_creator.clone();
+        let persisted_operations = self.persisted_operations.clone();
         let apq = self.apq_layer.clone();
+        let plugins = self.plugins.clone();
+        let response_memory = self.response_memory.clone();
+
+        if response_memory
+            .as_ref()
+            .map_or(false, |budget| budget.should_shed())
+        {
+            let error = graphql::Error::builder()
+                .message("the memory budget of responses is exhausted")
+                .extension_code("RESPONSE_MEMORY_EXHAUSTED")
+                .build();
+            count_graphql_errors([&error]);
+            let mut response = http::Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+                .body(Body::from(
+                    serde_json::to_string(
+                        &graphql::Response::builder()
+                            .errors(vec![error.clone()])
+                            .build(),
+                    )
+                    .unwrap_or_else(|_| String::from("Service unavailable")),
+                ))
+                .expect("cannot fail");
+            response.extensions_mut().insert(RouterError(error));
+            return Box::pin(ready(Ok(router::Response { response, context })));
+        }
 
         let fut = async move {
             let graphql_request: Result<graphql::Request, (&str, String)> = if parts.method
@@ -222,9 +277,13 @@ where
                         context,
                     };
 
-                    let request_res = match apq {
+                    let request_res = match persisted_operations {
                         None => Ok(request),
-                        Some(apq) => apq.request(request).await,
+                        Some(persisted_operations) => persisted_operations.request(request),
+                    };
+                    let request_res = match (request_res, apq) {
+                        (Ok(request), Some(apq)) => apq.request(request).await,
+                        (request_res, _) => request_res,
                     };
 
                     let SupergraphResponse { response, context } =
@@ -273,9 +332,26 @@ where
                         .unwrap_or_default()
                         .unwrap_or_default();
 
-                    let (mut parts, mut body) = response.into_parts();
+                    let (mut parts, body) = response.into_parts();
                     process_vary_header(&mut parts.headers);
 
+                    // last chance for plugins to change the responses before serialization
+                    let format_context = context.clone();
+                    let mut body = body.map(move |mut response| {
+                        for plugin in plugins.values() {
+                            plugin.format_response(&format_context, &mut response);
+                        }
+                        count_graphql_errors(
+                            response.errors.iter().chain(
+                                response
+                                    .incremental
+                                    .iter()
+                                    .flat_map(|incremental| &incremental.errors),
+                            ),
+                        );
+                        response
+                    });
+
                     match body.next().await {
                         None => {
                             tracing::error!("router service is not available to process request",);
@@ -297,15 +373,15 @@ where
                                     CONTENT_TYPE,
                                     HeaderValue::from_static(APPLICATION_JSON.essence_str()),
                                 );
-                                tracing::trace_span!("serialize_response").in_scope(|| {
-                                    let body = serde_json::to_string(&response)?;
-                                    Ok(router::Response {
-                                        response: http::Response::from_parts(
-                                            parts,
-                                            Body::from(body),
-                                        ),
-                                        context,
-                                    })
+                                let body = serialize_response(
+                                    response,
+                                    response_memory,
+                                    &mut parts.headers,
+                                )
+                                .await?;
+                                Ok(router::Response {
+                                    response: http::Response::from_parts(parts, body),
+                                    context,
                                 })
                             } else if accepts_multipart {
                                 parts.headers.insert(
@@ -342,6 +418,10 @@ where
                                         Ok::<_, BoxError>(buf.into())
                                     },
                                 ));
+                                let body = match response_memory {
+                                    Some(budget) => budget.account(body).boxed(),
+                                    None => body.boxed(),
+                                };
 
                                 let response =
                                     (parts, StreamBody::new(body)).into_response().map(|body| {
@@ -365,28 +445,22 @@ where
                                 Ok(RouterResponse { response, context })
                             } else {
                                 // this should be unreachable due to a previous check, but just to be sure...
+                                let error = graphql::Error::builder()
+                                    .message(format!(
+                                        r#"'accept' header can't be different from \"*/*\", {:?}, {:?} or {:?}"#,
+                                        APPLICATION_JSON.essence_str(),
+                                        GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
+                                        MULTIPART_DEFER_CONTENT_TYPE
+                                    ))
+                                    .extension_code("INVALID_ACCEPT_HEADER")
+                                    .build();
                                 Ok(router::Response {
-                                response: http::Response::builder()
-                                    .status(StatusCode::NOT_ACCEPTABLE)
-                                    .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-                                    .body(
-                                    Body::from(
-                                        serde_json::to_string(
-                                            &graphql::Error::builder()
-                                                .message(format!(
-                                                    r#"'accept' header can't be different from \"*/*\", {:?}, {:?} or {:?}"#,
-                                                    APPLICATION_JSON.essence_str(),
-                                                    GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
-                                                    MULTIPART_DEFER_CONTENT_TYPE
-                                                ))
-                                                .extension_code("INVALID_ACCEPT_HEADER")
-                                                .build(),
-                                        )
-                                        .unwrap_or_else(|_| String::from("Invalid request"))
-                                    )
-                                ).expect("cannot fail"),
-                                context,
-                            })
+                                    response: router_error_response(
+                                        StatusCode::NOT_ACCEPTABLE,
+                                        error,
+                                    ),
+                                    context,
+                                })
                             }
                         }
                     }
@@ -399,21 +473,13 @@ where
                         error = %error,
                         %error
                     );
+                    let error = graphql::Error::builder()
+                        .message(String::from("Invalid GraphQL request"))
+                        .extension_code("INVALID_GRAPHQL_REQUEST")
+                        .extension("details", extension_details)
+                        .build();
                     Ok(router::Response {
-                        response: http::Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .header(CONTENT_TYPE, APPLICATION_JSON.to_string())
-                            .body(Body::from(
-                                serde_json::to_string(
-                                    &graphql::Error::builder()
-                                        .message(String::from("Invalid GraphQL request"))
-                                        .extension_code("INVALID_GRAPHQL_REQUEST")
-                                        .extension("details", extension_details)
-                                        .build(),
-                                )
-                                .unwrap_or_else(|_| String::from("Invalid GraphQL request")),
-                            ))
-                            .expect("cannot fail"),
+                        response: router_error_response(StatusCode::BAD_REQUEST, error),
                         context,
                     })
                 }
@@ -423,6 +489,181 @@ where
     }
 }
 
+/// Size of the chunks of serialized response bodies
+const SERIALIZATION_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A JSON array or object being serialized
+enum Frame {
+    Array(std::vec::IntoIter<Value>),
+    Object(<Object as IntoIterator>::IntoIter),
+}
+
+/// Serializes a response chunk by chunk, resuming where the previous chunk stopped
+struct ChunkedSerializer {
+    buffer: Vec<u8>,
+    /// Data of the response not serialized yet
+    data: Option<Value>,
+    /// Containers being serialized, the innermost last, and whether they have an element yet
+    stack: Vec<(Frame, bool)>,
+    /// Fields of the response after `data`, and the end of the response object
+    suffix: Vec<u8>,
+}
+
+impl ChunkedSerializer {
+    fn new(mut response: graphql::Response) -> Result<Self, serde_json::Error> {
+        let data = response.data.take();
+        // the label is the only field serialized before the data
+        let label = response.label.take();
+        let rest = serde_json::to_vec(&response)?;
+        // the other fields, without the braces of their object
+        let rest = &rest[1..rest.len() - 1];
+
+        let mut buffer = Vec::with_capacity(SERIALIZATION_CHUNK_SIZE);
+        buffer.push(b'{');
+        if let Some(label) = label {
+            buffer.extend_from_slice(b"\"label\":");
+            serde_json::to_writer(&mut buffer, &label)?;
+            if data.is_some() || !rest.is_empty() {
+                buffer.push(b',');
+            }
+        }
+        if data.is_some() {
+            buffer.extend_from_slice(b"\"data\":");
+        }
+        let mut suffix = Vec::with_capacity(rest.len() + 2);
+        if data.is_some() && !rest.is_empty() {
+            suffix.push(b',');
+        }
+        suffix.extend_from_slice(rest);
+        suffix.push(b'}');
+        Ok(ChunkedSerializer {
+            buffer,
+            data,
+            stack: Vec::new(),
+            suffix,
+        })
+    }
+
+    fn is_done(&self) -> bool {
+        self.buffer.is_empty()
+            && self.data.is_none()
+            && self.stack.is_empty()
+            && self.suffix.is_empty()
+    }
+
+    /// Serializes the next chunk of the response
+    fn next_chunk(&mut self) -> Result<Bytes, serde_json::Error> {
+        while self.buffer.len() < SERIALIZATION_CHUNK_SIZE {
+            if let Some(data) = self.data.take() {
+                self.write(data)?;
+                continue;
+            }
+            let next = match self.stack.last_mut() {
+                None => {
+                    self.buffer.append(&mut self.suffix);
+                    break;
+                }
+                Some((Frame::Array(values), has_element)) => values
+                    .next()
+                    .map(|value| (None, value, std::mem::replace(has_element, true))),
+                Some((Frame::Object(entries), has_element)) => entries
+                    .next()
+                    .map(|(key, value)| (Some(key), value, std::mem::replace(has_element, true))),
+            };
+            match next {
+                Some((key, value, has_element)) => {
+                    if has_element {
+                        self.buffer.push(b',');
+                    }
+                    if let Some(key) = key {
+                        serde_json::to_writer(&mut self.buffer, key.as_str())?;
+                        self.buffer.push(b':');
+                    }
+                    self.write(value)?;
+                }
+                None => {
+                    let end = match self.stack.pop() {
+                        Some((Frame::Array(_), _)) => b']',
+                        _ => b'}',
+                    };
+                    self.buffer.push(end);
+                }
+            }
+        }
+        let chunk = std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(SERIALIZATION_CHUNK_SIZE),
+        );
+        if self.is_done() {
+            self.buffer = Vec::new();
+        }
+        Ok(chunk.into())
+    }
+
+    /// Writes a scalar, or starts writing an array or an object
+    fn write(&mut self, value: Value) -> Result<(), serde_json::Error> {
+        match value {
+            Value::Array(values) => {
+                self.buffer.push(b'[');
+                self.stack.push((Frame::Array(values.into_iter()), false));
+            }
+            Value::Object(entries) => {
+                self.buffer.push(b'{');
+                self.stack.push((Frame::Object(entries.into_iter()), false));
+            }
+            scalar => serde_json::to_writer(&mut self.buffer, &scalar)?,
+        }
+        Ok(())
+    }
+}
+
+/// Serializes the next chunk of a response on the compute thread pool
+async fn next_chunk(
+    mut serializer: ChunkedSerializer,
+) -> Result<(Bytes, ChunkedSerializer), BoxError> {
+    let chunk =
+        compute_job::execute(move || serializer.next_chunk().map(|chunk| (chunk, serializer)))
+            .await??;
+    Ok(chunk)
+}
+
+/// Serializes a response to a body.
+///
+/// Large responses are serialized chunk by chunk as the client reads them, instead of being
+/// serialized in memory first, which would double the memory used by the response. Each chunk is
+/// serialized on the compute thread pool, and no thread waits for slow clients. The responses
+/// fitting in one chunk are sent with a `content-length`. The chunks are counted against the
+/// memory budget of responses, if there is one.
+async fn serialize_response(
+    response: graphql::Response,
+    response_memory: Option<Arc<ResponseMemoryBudget>>,
+    headers: &mut HeaderMap,
+) -> Result<Body, BoxError> {
+    let (first, serializer) = next_chunk(ChunkedSerializer::new(response)?).await?;
+    if serializer.is_done() {
+        return Ok(match response_memory {
+            None => Body::from(first),
+            Some(budget) => {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from(first.len()));
+                Body::wrap_stream(budget.account(stream::iter([Ok(first)])))
+            }
+        });
+    }
+
+    let rest = stream::unfold(Some(serializer), |serializer| async move {
+        let serializer = serializer.filter(|serializer| !serializer.is_done())?;
+        Some(match next_chunk(serializer).await {
+            Ok((chunk, serializer)) => (Ok(chunk), Some(serializer)),
+            Err(error) => (Err(error), None),
+        })
+    });
+    let chunks = stream::once(ready(Ok::<_, BoxError>(first))).chain(rest);
+    Ok(match response_memory {
+        Some(budget) => Body::wrap_stream(budget.account(chunks)),
+        None => Body::wrap_stream(chunks),
+    })
+}
+
 // Process the headers to make sure that `VARY` is set correctly
 fn process_vary_header(headers: &mut HeaderMap<HeaderValue>) {
     if headers.get(VARY).is_none() {
@@ -439,7 +680,10 @@ where
 {
     supergraph_creator: Arc<SF>,
     static_page: StaticPageLayer,
+    persisted_operations: Option<PersistedOperationsLayer>,
     apq_layer: Option<APQLayer>,
+    response_memory: Option<Arc<ResponseMemoryBudget>>,
+    batching: BatchingLayer,
 }
 
 impl<SF> ServiceFactory<router::Request> for RouterCreator<SF>
@@ -458,7 +702,13 @@ where
 
 impl<SF> RouterFactory for RouterCreator<SF>
 where
-    SF: HasPlugins + ServiceFactory<supergraph::Request> + Clone + Send + Sync + 'static,
+    SF: Administrable
+        + HasPlugins
+        + ServiceFactory<supergraph::Request>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     <SF as ServiceFactory<supergraph::Request>>::Service:
         Service<supergraph::Request, Response = supergraph::Response, Error = BoxError> + Send,
     <<SF as ServiceFactory<supergraph::Request>>::Service as Service<supergraph::Request>>::Future:
@@ -478,6 +728,32 @@ where
             .for_each(|p| mm.extend(p.web_endpoints()));
         mm
     }
+
+    fn schema_changed(&self, change: &SchemaChange) {
+        self.supergraph_creator
+            .plugins()
+            .values()
+            .for_each(|p| p.schema_changed(change));
+    }
+
+    fn plugin_names(&self) -> Vec<String> {
+        self.supergraph_creator.plugins().keys().cloned().collect()
+    }
+
+    fn schema_id(&self) -> Option<String> {
+        self.supergraph_creator.schema_id()
+    }
+
+    fn clear_caches(&self) -> BoxFuture<'static, ()> {
+        let apq_layer = self.apq_layer.clone();
+        let supergraph = self.supergraph_creator.clear_caches();
+        Box::pin(async move {
+            if let Some(apq_layer) = apq_layer {
+                apq_layer.clear().await;
+            }
+            supergraph.await;
+        })
+    }
 }
 
 impl<SF> RouterCreator<SF>
@@ -488,8 +764,14 @@ where
     <<SF as ServiceFactory<supergraph::Request>>::Service as Service<supergraph::Request>>::Future:
         Send,
 {
-    pub(crate) async fn new(supergraph_creator: Arc<SF>, configuration: &Configuration) -> Self {
+    pub(crate) async fn new(
+        supergraph_creator: Arc<SF>,
+        configuration: &Configuration,
+    ) -> Result<Self, BoxError> {
         let static_page = StaticPageLayer::new(configuration);
+        let persisted_operations = PersistedOperationsLayer::from_configuration(
+            &configuration.supergraph.persisted_operations,
+        )?;
         let apq_layer = if configuration.supergraph.apq.enabled {
             Some(APQLayer::with_cache(
                 DeduplicatingCache::from_configuration(
@@ -502,11 +784,16 @@ where
             None
         };
 
-        Self {
+        Ok(Self {
             supergraph_creator,
             static_page,
+            persisted_operations,
             apq_layer,
-        }
+            response_memory: ResponseMemoryBudget::from_configuration(
+                &configuration.server.experimental_response_memory,
+            ),
+            batching: BatchingLayer::new(configuration.supergraph.batching.clone()),
+        })
     }
 
     pub(crate) fn make(
@@ -517,10 +804,15 @@ where
         Error = BoxError,
         Future = BoxFuture<'static, router::ServiceResult>,
     > + Send {
-        let router_service = content_negociation::RouterLayer::default().layer(RouterService::new(
-            self.supergraph_creator.clone(),
-            self.apq_layer.clone(),
-        ));
+        let router_service = content_negociation::RouterLayer::default().layer(
+            self.batching.layer(RouterService::new(
+                self.supergraph_creator.clone(),
+                self.persisted_operations.clone(),
+                self.apq_layer.clone(),
+                self.supergraph_creator.plugins(),
+                self.response_memory.clone(),
+            )),
+        );
 
         ServiceBuilder::new()
             .layer(self.static_page.clone())
@@ -538,6 +830,12 @@ impl RouterCreator<crate::services::supergraph_service::SupergraphCreator> {
     pub(crate) async fn cache_keys(&self, count: usize) -> Vec<(String, Option<String>)> {
         self.supergraph_creator.cache_keys(count).await
     }
+
+    pub(crate) async fn warm_up_query_planner(&self, cache_keys: Vec<(String, Option<String>)>) {
+        self.supergraph_creator
+            .warm_up_query_planner(cache_keys)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -547,6 +845,7 @@ mod tests {
     use serde_json_bytes::json;
 
     use super::*;
+    use crate::http_server_factory::ConnectionInfo;
     use crate::services::supergraph;
     use crate::Context;
 
@@ -707,4 +1006,247 @@ mod tests {
         assert_eq!(expected_error, actual_error);
         assert!(response.errors[0].extensions.contains_key("code"));
     }
+
+    #[tokio::test]
+    async fn it_serializes_large_responses_in_chunks() {
+        let small = graphql::Response::builder()
+            .data(json!({ "name": "Table" }))
+            .build();
+        let body = serialize_response(small.clone(), None, &mut HeaderMap::new())
+            .await
+            .unwrap();
+        let expected = serde_json::to_vec(&small).unwrap();
+        assert_eq!(body.size_hint().exact(), Some(expected.len() as u64));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), expected);
+
+        let products: Vec<_> = (0..10_000)
+            .map(|id| json!({ "id": id, "name": "Table" }))
+            .collect();
+        let large = graphql::Response::builder()
+            .data(json!({ "products": products }))
+            .build();
+        let mut body = serialize_response(large.clone(), None, &mut HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(body.size_hint().exact(), None);
+        let mut chunks = 0;
+        let mut serialized = Vec::new();
+        while let Some(chunk) = body.data().await {
+            chunks += 1;
+            serialized.extend_from_slice(&chunk.unwrap());
+        }
+        assert!(chunks > 1);
+        assert_eq!(serialized, serde_json::to_vec(&large).unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_serializes_every_field_of_responses_in_chunks() {
+        let items: Vec<_> = (0..10_000)
+            .map(|id| json!({ "id": id, "tags": [], "details": {}, "price": [1.5, null, "€ \"9\""] }))
+            .collect();
+        let responses = [
+            graphql::Response::builder()
+                .label("products")
+                .data(json!({ "items": items }))
+                .path(crate::json_ext::Path::from("a/b"))
+                .error(
+                    graphql::Error::builder()
+                        .message("failed")
+                        .extension_code("FAILED")
+                        .build(),
+                )
+                .extension("cost", json!(12))
+                .has_next(true)
+                .build(),
+            graphql::Response::builder().label("empty").build(),
+            graphql::Response::builder().has_next(false).build(),
+            graphql::Response::builder().build(),
+        ];
+        for response in responses {
+            let body = serialize_response(response.clone(), None, &mut HeaderMap::new())
+                .await
+                .unwrap();
+            assert_eq!(
+                hyper::body::to_bytes(body).await.unwrap(),
+                serde_json::to_vec(&response).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_formats_responses_before_serialization() {
+        struct ErrorEnvelope;
+
+        #[async_trait::async_trait]
+        impl crate::plugin::Plugin for ErrorEnvelope {
+            type Config = ();
+
+            async fn new(_: crate::plugin::PluginInit<Self::Config>) -> Result<Self, BoxError> {
+                unreachable!()
+            }
+
+            fn format_response(&self, _context: &Context, response: &mut graphql::Response) {
+                for error in &mut response.errors {
+                    error
+                        .extensions
+                        .insert("envelope", serde_json_bytes::Value::from(1));
+                }
+            }
+        }
+
+        let router_service = crate::TestHarness::builder()
+            .extra_plugin(ErrorEnvelope)
+            .build_router()
+            .await
+            .unwrap();
+
+        // the query is rejected by the supergraph service, before any other plugin hook
+        let request = SupergraphRequest::fake_builder()
+            .query("{ unknownField }".to_string())
+            .build()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let response = router_service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .into_graphql_response_stream()
+            .await
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(!response.errors.is_empty());
+        for error in response.errors {
+            assert_eq!(error.extensions.get("envelope"), Some(&json!(1)));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_executes_batches_of_operations() {
+        let configuration = Configuration::fake_builder()
+            .supergraph(
+                crate::configuration::Supergraph::fake_builder()
+                    .batching(
+                        serde_json::from_value::<crate::configuration::Batching>(
+                            serde_json::json!({
+                                "enabled": true,
+                                "max_size": 2,
+                                "max_body_size": 100
+                            }),
+                        )
+                        .unwrap(),
+                    )
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let mut router_service = from_supergraph_mock_callback_and_configuration(
+            move |req| {
+                let query = req.supergraph_request.body().query.clone().unwrap();
+                let peer_address = req
+                    .supergraph_request
+                    .extensions()
+                    .get::<ConnectionInfo>()
+                    .map(|connection_info| connection_info.peer_address.to_string());
+                Ok(SupergraphResponse::new_from_graphql_response(
+                    graphql::Response::builder()
+                        .data(json!({ "query": query, "peer_address": peer_address }))
+                        .build(),
+                    req.context,
+                ))
+            },
+            Arc::new(configuration),
+        )
+        .await;
+
+        let batch = |body: serde_json::Value| {
+            let mut request = http::Request::builder()
+                .method(Method::POST)
+                .uri("/")
+                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            request.extensions_mut().insert(ConnectionInfo {
+                peer_address: "10.0.0.1:1234".parse().unwrap(),
+            });
+            request.into()
+        };
+
+        let response = router_service
+            .call(batch(
+                serde_json::json!([{ "query": "{ a }" }, { "query": "{ b }" }]),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.response.into_body())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!([
+                { "data": { "query": "{ a }", "peer_address": "10.0.0.1:1234" } },
+                { "data": { "query": "{ b }", "peer_address": "10.0.0.1:1234" } }
+            ])
+        );
+
+        // the requests that are not batches are not buffered by the batching layer
+        let response = router_service
+            .call(batch(serde_json::json!({ "query": "{ a }" })))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.response.into_body())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "data": { "query": "{ a }", "peer_address": "10.0.0.1:1234" } })
+        );
+
+        let response = router_service
+            .call(batch(serde_json::json!([
+                { "query": "{ a }", "variables": { "padding": "a".repeat(100) } }
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = router_service
+            .call(batch(serde_json::json!([
+                { "query": "{ a }" },
+                { "query": "{ b }" },
+                { "query": "{ c }" }
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+
+        let unreadable = http::Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+            .body(Body::wrap_stream(stream::iter([
+                Ok(Bytes::from_static(b"[{")),
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "connection reset",
+                )),
+            ])))
+            .unwrap()
+            .into();
+        let response = router_service.call(unreadable).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.response.into_body())
+            .await
+            .unwrap();
+        let error: graphql::Error = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&json!("INVALID_GRAPHQL_REQUEST"))
+        );
+    }
 }
@@ -13,6 +13,7 @@ use crate::error::Error;
 use crate::graphql;
 use crate::json_ext::Object;
 use crate::json_ext::Path;
+use crate::query_planner::fetch::FetchPath;
 use crate::query_planner::fetch::OperationKind;
 use crate::Context;
 
@@ -77,7 +78,7 @@ impl Request {
 impl Clone for Request {
     fn clone(&self) -> Self {
         // http::Request is not clonable so we have to rebuild a new one
-        // we don't use the extensions field for now
+        // the only extension we use is the path of the fetch
         let mut builder = http::Request::builder()
             .method(self.subgraph_request.method())
             .version(self.subgraph_request.version())
@@ -92,7 +93,10 @@ impl Clone for Request {
                     .map(|(name, value)| (name.clone(), value.clone())),
             );
         }
-        let subgraph_request = builder.body(self.subgraph_request.body().clone()).unwrap();
+        let mut subgraph_request = builder.body(self.subgraph_request.body().clone()).unwrap();
+        if let Some(path) = self.subgraph_request.extensions().get::<FetchPath>() {
+            subgraph_request.extensions_mut().insert(path.clone());
+        }
 
         Self {
             supergraph_request: self.supergraph_request.clone(),
@@ -15,6 +15,7 @@ use futures::future::BoxFuture;
 use global::get_text_map_propagator;
 use http::header::ACCEPT;
 use http::header::CONTENT_ENCODING;
+use http::header::CONTENT_LENGTH;
 use http::header::CONTENT_TYPE;
 use http::header::{self};
 use http::HeaderMap;
@@ -42,8 +43,12 @@ use super::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
 use super::Plugins;
 use crate::error::FetchError;
 use crate::graphql;
+use crate::plugins::file_uploads::SubgraphFiles;
+use crate::plugins::file_uploads::UploadedFiles;
+use crate::plugins::telemetry::subgraph_otel_context;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::telemetry::LOGGING_DISPLAY_HEADERS;
+use crate::redaction::Redactor;
 use crate::services::layers::apq;
 use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
@@ -54,6 +59,8 @@ const PERSISTED_QUERY_NOT_SUPPORTED_EXTENSION_CODE: &str = "PERSISTED_QUERY_NOT_
 const PERSISTED_QUERY_NOT_FOUND_MESSAGE: &str = "PersistedQueryNotFound";
 const PERSISTED_QUERY_NOT_SUPPORTED_MESSAGE: &str = "PersistedQueryNotSupported";
 const CODE_STRING: &str = "code";
+/// Lets subgraphs with CSRF prevention accept the multipart requests of file uploads
+const APOLLO_REQUIRE_PREFLIGHT: &str = "apollo-require-preflight";
 const PERSISTED_QUERY_KEY: &str = "persistedQuery";
 const HASH_VERSION_KEY: &str = "version";
 const HASH_VERSION_VALUE: i32 = 1;
@@ -74,6 +81,8 @@ pub(crate) enum Compression {
     Deflate,
     /// brotli
     Br,
+    /// zstd, with the compression dictionary of the subgraph if there is one
+    Zstd,
 }
 
 impl Display for Compression {
@@ -82,6 +91,7 @@ impl Display for Compression {
             Compression::Gzip => write!(f, "gzip"),
             Compression::Deflate => write!(f, "deflate"),
             Compression::Br => write!(f, "br"),
+            Compression::Zstd => write!(f, "zstd"),
         }
     }
 }
@@ -102,6 +112,12 @@ pub(crate) struct SubgraphService {
     /// If a subgraph sends the error message PERSISTED_QUERY_NOT_SUPPORTED,
     /// apq is set to false
     apq: Arc<AtomicBool>,
+
+    /// Dictionary shared with the subgraph, for the zstd compression of requests and responses
+    compression_dictionary: Option<Arc<[u8]>>,
+
+    /// Redaction of the logged headers and variables
+    redactor: Option<Arc<Redactor>>,
 }
 
 impl SubgraphService {
@@ -137,8 +153,20 @@ impl SubgraphService {
                 .service(hyper::Client::builder().build(connector)),
             service: Arc::new(service.into()),
             apq: Arc::new(<AtomicBool>::new(apq_enabled.unwrap_or(true))),
+            compression_dictionary: None,
+            redactor: None,
         }
     }
+
+    pub(crate) fn with_compression_dictionary(mut self, dictionary: Option<Arc<[u8]>>) -> Self {
+        self.compression_dictionary = dictionary;
+        self
+    }
+
+    pub(crate) fn with_redactor(mut self, redactor: Option<Arc<Redactor>>) -> Self {
+        self.redactor = redactor;
+        self
+    }
 }
 
 impl tower::Service<SubgraphRequest> for SubgraphService {
@@ -166,13 +194,42 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
         let service_name = (*self.service).to_owned();
 
         let arc_apq_enabled = self.apq.clone();
+        let dictionary = self.compression_dictionary.clone();
+        let redactor = self.redactor.clone();
 
         let make_calls = async move {
+            let files = match request
+                .supergraph_request
+                .extensions()
+                .get::<Arc<UploadedFiles>>()
+                .and_then(|files| files.take_for(&body))
+            {
+                Some(Ok(files)) => Some(files),
+                Some(Err(reason)) => {
+                    return Err(BoxError::from(FetchError::SubrequestHttpError {
+                        service: service_name,
+                        reason,
+                    }))
+                }
+                None => None,
+            };
+
             // If APQ is not enabled, simply make the graphql call
-            // with the same request body.
+            // with the same request body. The uploaded files are streamed,
+            // so the request cannot be retried without APQ.
             let apq_enabled = arc_apq_enabled.as_ref();
-            if !apq_enabled.load(Relaxed) {
-                return call_http(request, body, context, client, service_name).await;
+            if !apq_enabled.load(Relaxed) || files.is_some() {
+                return call_http(
+                    request,
+                    body,
+                    context,
+                    client,
+                    service_name,
+                    dictionary,
+                    redactor,
+                    files,
+                )
+                .await;
             }
 
             // Else, if APQ is enabled,
@@ -183,6 +240,7 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                 operation_name,
                 variables,
                 extensions,
+                ..
             } = body.clone();
 
             let hash_value = apq::calculate_hash_for_query(query.as_deref().unwrap_or_default());
@@ -200,6 +258,7 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                 operation_name,
                 variables,
                 extensions: extensions_with_apq,
+                document_id: None,
             };
 
             let response = call_http(
@@ -208,6 +267,9 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                 context.clone(),
                 client.clone(),
                 service_name.clone(),
+                dictionary.clone(),
+                redactor.clone(),
+                None,
             )
             .await?;
 
@@ -219,11 +281,31 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
             match get_apq_error(gql_response) {
                 APQError::PersistedQueryNotSupported => {
                     apq_enabled.store(false, Relaxed);
-                    call_http(request, body, context, client, service_name).await
+                    call_http(
+                        request,
+                        body,
+                        context,
+                        client,
+                        service_name,
+                        dictionary,
+                        redactor,
+                        None,
+                    )
+                    .await
                 }
                 APQError::PersistedQueryNotFound => {
                     apq_body.query = query;
-                    call_http(request, apq_body, context, client, service_name).await
+                    call_http(
+                        request,
+                        apq_body,
+                        context,
+                        client,
+                        service_name,
+                        dictionary,
+                        redactor,
+                        None,
+                    )
+                    .await
                 }
                 _ => Ok(response),
             }
@@ -234,37 +316,67 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
 }
 
 /// call_http makes http calls with modified graphql::Request (body)
+#[allow(clippy::too_many_arguments)]
 async fn call_http(
     request: SubgraphRequest,
     body: graphql::Request,
     context: Context,
     mut client: Decompression<Client<HttpsConnector<HttpConnector>>>,
     service_name: String,
+    dictionary: Option<Arc<[u8]>>,
+    redactor: Option<Arc<Redactor>>,
+    files: Option<SubgraphFiles>,
 ) -> Result<SubgraphResponse, BoxError> {
     let SubgraphRequest {
         subgraph_request, ..
     } = request;
 
-    let (parts, _) = subgraph_request.into_parts();
+    let (mut parts, _) = subgraph_request.into_parts();
 
-    let body = serde_json::to_string(&body).expect("JSON serialization should not fail");
-    let compressed_body = compress(body, &parts.headers)
-        .instrument(tracing::debug_span!("body_compression"))
-        .await
-        .map_err(|err| {
-            tracing::error!(compress_error = format!("{err:?}").as_str());
-
-            FetchError::CompressionError {
-                service: service_name.clone(),
-                reason: err.to_string(),
-            }
-        })?;
+    let display_headers = context.contains_key(LOGGING_DISPLAY_HEADERS);
+    let display_body = context.contains_key(LOGGING_DISPLAY_BODY);
+    // the body is logged before its compression, with the redacted variables
+    let displayed_body = display_body.then(|| match &redactor {
+        Some(redactor) => serde_json::to_string(&redactor.request(&body)),
+        None => serde_json::to_string(&body),
+    });
 
-    let mut request = http::request::Request::from_parts(parts, compressed_body.into());
+    let body = serde_json::to_string(&body).expect("JSON serialization should not fail");
     let app_json: HeaderValue = HeaderValue::from_static(APPLICATION_JSON.essence_str());
+    let (content_type, body) = match files {
+        // the files are streamed from the client request, without compression
+        Some(files) => {
+            parts.headers.remove(CONTENT_ENCODING);
+            parts
+                .headers
+                .insert(APOLLO_REQUIRE_PREFLIGHT, HeaderValue::from_static("true"));
+            files
+                .into_body(body)
+                .map_err(|err| FetchError::SubrequestHttpError {
+                    service: service_name.clone(),
+                    reason: err.to_string(),
+                })?
+        }
+        None => {
+            let compressed_body = compress(body, &parts.headers, dictionary.as_deref())
+                .instrument(tracing::debug_span!("body_compression"))
+                .await
+                .map_err(|err| {
+                    tracing::error!(compress_error = format!("{err:?}").as_str());
+
+                    FetchError::CompressionError {
+                        service: service_name.clone(),
+                        reason: err.to_string(),
+                    }
+                })?;
+            (app_json.clone(), compressed_body.into())
+        }
+    };
+
+    let mut request = http::request::Request::from_parts(parts, body);
     let app_graphql_json: HeaderValue =
         HeaderValue::from_static(GRAPHQL_JSON_RESPONSE_HEADER_VALUE);
-    request.headers_mut().insert(CONTENT_TYPE, app_json.clone());
+    request.headers_mut().insert(CONTENT_TYPE, content_type);
     request.headers_mut().insert(ACCEPT, app_json);
     request.headers_mut().append(ACCEPT, app_graphql_json);
 
@@ -280,13 +392,18 @@ async fn call_http(
             0
         }
     });
-    let display_headers = context.contains_key(LOGGING_DISPLAY_HEADERS);
-    let display_body = context.contains_key(LOGGING_DISPLAY_BODY);
     if display_headers {
-        tracing::info!(http.request.headers = ?request.headers(), apollo.subgraph.name = %service_name, "Request headers to subgraph {service_name:?}");
+        match &redactor {
+            Some(redactor) => {
+                tracing::info!(http.request.headers = ?redactor.headers(request.headers()), apollo.subgraph.name = %service_name, "Request headers to subgraph {service_name:?}");
+            }
+            None => {
+                tracing::info!(http.request.headers = ?request.headers(), apollo.subgraph.name = %service_name, "Request headers to subgraph {service_name:?}");
+            }
+        }
     }
-    if display_body {
-        tracing::info!(http.request.body = ?request.body(), apollo.subgraph.name = %service_name, "Request body to subgraph {service_name:?}");
+    if let Some(Ok(body)) = displayed_body {
+        tracing::info!(http.request.body = %body, apollo.subgraph.name = %service_name, "Request body to subgraph {service_name:?}");
     }
 
     let path = schema_uri.path().to_string();
@@ -302,7 +419,7 @@ async fn call_http(
     );
     get_text_map_propagator(|propagator| {
         propagator.inject_context(
-            &subgraph_req_span.context(),
+            &subgraph_otel_context(subgraph_req_span.context(), &context),
             &mut opentelemetry_http::HeaderInjector(request.headers_mut()),
         );
     });
@@ -320,7 +437,7 @@ async fn call_http(
                 }
             })?;
         // Keep our parts, we'll need them later
-        let (parts, body) = response.into_parts();
+        let (mut parts, body) = response.into_parts();
         if display_headers {
             tracing::info!(
                         http.response.headers = ?parts.headers, apollo.subgraph.name = %service_name, "Response headers from subgraph {service_name:?}"
@@ -363,6 +480,25 @@ async fn call_http(
                 }
             })?;
 
+        // zstd is not handled by the decompression layer, which does not support dictionaries
+        let zstd_encoded = parts
+            .headers
+            .get(CONTENT_ENCODING)
+            .map_or(false, |encoding| encoding == "zstd");
+        let body = if zstd_encoded {
+            let body = zstd_decode(&body, dictionary.as_deref()).map_err(|err| {
+                FetchError::SubrequestMalformedResponse {
+                    service: service_name.clone(),
+                    reason: format!("could not decompress the response: {err}"),
+                }
+            })?;
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            body.into()
+        } else {
+            body
+        };
+
         Ok((parts, body))
     }.instrument(subgraph_req_span).await?;
 
@@ -411,7 +547,11 @@ fn get_apq_error(gql_response: &graphql::Response) -> APQError {
     APQError::Other
 }
 
-pub(crate) async fn compress(body: String, headers: &HeaderMap) -> Result<Vec<u8>, BoxError> {
+pub(crate) async fn compress(
+    body: String,
+    headers: &HeaderMap,
+    dictionary: Option<&[u8]>,
+) -> Result<Vec<u8>, BoxError> {
     let content_encoding = headers.get(&CONTENT_ENCODING);
     match content_encoding {
         Some(content_encoding) => match content_encoding.to_str()? {
@@ -436,6 +576,7 @@ pub(crate) async fn compress(body: String, headers: &HeaderMap) -> Result<Vec<u8
 
                 Ok(df_encoder.into_inner())
             }
+            "zstd" => Ok(zstd_encode(body.as_bytes(), dictionary)?),
             "identity" => Ok(body.into_bytes()),
             unknown => {
                 tracing::error!("unknown content-encoding value '{:?}'", unknown);
@@ -448,21 +589,48 @@ pub(crate) async fn compress(body: String, headers: &HeaderMap) -> Result<Vec<u8
     }
 }
 
+fn zstd_encode(body: &[u8], dictionary: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    let level = zstd::DEFAULT_COMPRESSION_LEVEL;
+    let mut encoder = match dictionary {
+        Some(dictionary) => {
+            zstd::stream::write::Encoder::with_dictionary(Vec::new(), level, dictionary)?
+        }
+        None => zstd::stream::write::Encoder::new(Vec::new(), level)?,
+    };
+    std::io::Write::write_all(&mut encoder, body)?;
+    encoder.finish()
+}
+
+fn zstd_decode(body: &[u8], dictionary: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    let mut decoder = match dictionary {
+        Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(body, dictionary)?,
+        None => zstd::stream::read::Decoder::with_buffer(body)?,
+    };
+    let mut decoded = Vec::new();
+    std::io::Read::read_to_end(&mut decoder, &mut decoded)?;
+    Ok(decoded)
+}
+
 #[derive(Clone)]
 pub(crate) struct SubgraphServiceFactory {
     pub(crate) services: Arc<HashMap<String, Arc<dyn MakeSubgraphService>>>,
 
     pub(crate) plugins: Arc<Plugins>,
+
+    /// Redaction of the traced subgraph fetches
+    pub(crate) redactor: Option<Arc<Redactor>>,
 }
 
 impl SubgraphServiceFactory {
     pub(crate) fn new(
         services: Vec<(String, Arc<dyn MakeSubgraphService>)>,
         plugins: Arc<Plugins>,
+        redactor: Option<Arc<Redactor>>,
     ) -> Self {
         SubgraphServiceFactory {
             services: Arc::new(services.into_iter().collect()),
             plugins,
+            redactor,
         }
     }
 
@@ -1049,6 +1217,22 @@ mod tests {
         assert_eq!(resp.response.body(), &resp_from_subgraph);
     }
 
+    #[test]
+    fn test_zstd_dictionary_compression() {
+        let body = r#"{"query":"{ topProducts { upc name reviews { body } } }"}"#;
+        // a dictionary without the zstd magic number is used as raw content
+        let dictionary: &[u8] = br#"{"query":"{ topProducts { upc name reviews { body } } }"}"#;
+        let compressed = zstd_encode(body.as_bytes(), Some(dictionary)).unwrap();
+        assert_eq!(
+            zstd_decode(&compressed, Some(dictionary)).unwrap(),
+            body.as_bytes()
+        );
+        assert!(zstd_decode(&compressed, None).is_err());
+
+        let compressed = zstd_encode(body.as_bytes(), None).unwrap();
+        assert_eq!(zstd_decode(&compressed, None).unwrap(), body.as_bytes());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_unauthorized() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2828").unwrap();
@@ -2,6 +2,7 @@
 
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Instant;
 
 use futures::future::BoxFuture;
 use futures::stream::StreamExt;
@@ -9,6 +10,7 @@ use futures::TryFutureExt;
 use http::StatusCode;
 use indexmap::IndexMap;
 use multimap::MultiMap;
+use rand::Rng;
 use tower::util::Either;
 use tower::BoxError;
 use tower::ServiceBuilder;
@@ -23,18 +25,27 @@ use super::subgraph_service::MakeSubgraphService;
 use super::subgraph_service::SubgraphServiceFactory;
 use super::ExecutionServiceFactory;
 use super::QueryPlannerContent;
+use crate::configuration::CanaryHeader;
+use crate::configuration::VariableValidation;
 use crate::error::CacheResolverError;
 use crate::error::ServiceBuildError;
 use crate::graphql;
 use crate::graphql::IntoGraphQLErrors;
 use crate::introspection::Introspection;
+use crate::introspection::IntrospectionFilter;
 #[cfg(test)]
 use crate::plugin::test::MockSupergraphService;
 use crate::plugin::DynPlugin;
+use crate::plugins::feature_flags;
+use crate::plugins::feature_flags::CANARY_FLAG;
+use crate::plugins::feature_flags::DEFER_FLAG;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
+use crate::progressive_override::LabelSelector;
+use crate::progressive_override::OVERRIDE_LABELS_CONTEXT_KEY;
 use crate::query_planner::BridgeQueryPlanner;
 use crate::query_planner::CachingQueryPlanner;
+use crate::redaction::Redactor;
 use crate::services::supergraph;
 use crate::services::ExecutionRequest;
 use crate::services::ExecutionResponse;
@@ -49,16 +60,26 @@ use crate::Endpoint;
 use crate::ListenAddr;
 
 pub(crate) const QUERY_PLANNING_SPAN_NAME: &str = "query_planning";
+/// Context key for the version of the schema serving the request: `primary` or `canary`
+pub(crate) const SCHEMA_VERSION_CONTEXT_KEY: &str = "apollo_router::supergraph::schema_version";
+/// Context key for the time spent planning the request, in milliseconds
+pub(crate) const QUERY_PLANNING_DURATION_CONTEXT_KEY: &str =
+    "apollo_router::supergraph::query_planning_duration_ms";
 
 /// An [`IndexMap`] of available plugins.
 pub(crate) type Plugins = IndexMap<String, Box<dyn DynPlugin>>;
 
+/// The services of the subgraphs, by subgraph name.
+pub(crate) type SubgraphServices = Vec<(String, Arc<dyn MakeSubgraphService>)>;
+
 /// Containing [`Service`] in the request lifecyle.
 #[derive(Clone)]
 pub(crate) struct SupergraphService {
     execution_service_factory: ExecutionServiceFactory,
     query_planner_service: CachingQueryPlanner<BridgeQueryPlanner>,
     schema: Arc<Schema>,
+    variable_validation: VariableValidation,
+    introspection_filter: Option<Arc<IntrospectionFilter>>,
 }
 
 #[buildstructor::buildstructor]
@@ -68,11 +89,15 @@ impl SupergraphService {
         query_planner_service: CachingQueryPlanner<BridgeQueryPlanner>,
         execution_service_factory: ExecutionServiceFactory,
         schema: Arc<Schema>,
+        variable_validation: Option<VariableValidation>,
+        introspection_filter: Option<Arc<IntrospectionFilter>>,
     ) -> Self {
         SupergraphService {
             query_planner_service,
             execution_service_factory,
             schema,
+            variable_validation: variable_validation.unwrap_or_default(),
+            introspection_filter,
         }
     }
 }
@@ -96,28 +121,37 @@ impl Service<SupergraphRequest> for SupergraphService {
         let execution = self.execution_service_factory.create();
 
         let schema = self.schema.clone();
+        let variable_validation = self.variable_validation;
+        let introspection_filter = self.introspection_filter.clone();
 
         let context_cloned = req.context.clone();
-        let fut =
-            service_call(planning, execution, schema, req).or_else(|error: BoxError| async move {
-                let errors = vec![crate::error::Error {
-                    message: error.to_string(),
-                    extensions: serde_json_bytes::json!({
-                        "code": "INTERNAL_SERVER_ERROR",
-                    })
-                    .as_object()
-                    .unwrap()
-                    .to_owned(),
-                    ..Default::default()
-                }];
-
-                Ok(SupergraphResponse::builder()
-                    .errors(errors)
-                    .status_code(StatusCode::INTERNAL_SERVER_ERROR)
-                    .context(context_cloned)
-                    .build()
-                    .expect("building a response like this should not fail"))
-            });
+        let fut = service_call(
+            planning,
+            execution,
+            schema,
+            variable_validation,
+            introspection_filter,
+            req,
+        )
+        .or_else(|error: BoxError| async move {
+            let errors = vec![crate::error::Error {
+                message: error.to_string(),
+                extensions: serde_json_bytes::json!({
+                    "code": "INTERNAL_SERVER_ERROR",
+                })
+                .as_object()
+                .unwrap()
+                .to_owned(),
+                ..Default::default()
+            }];
+
+            Ok(SupergraphResponse::builder()
+                .errors(errors)
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .context(context_cloned)
+                .build()
+                .expect("building a response like this should not fail"))
+        });
 
         Box::pin(fut)
     }
@@ -127,6 +161,8 @@ async fn service_call<ExecutionService>(
     planning: CachingQueryPlanner<BridgeQueryPlanner>,
     execution: ExecutionService,
     schema: Arc<Schema>,
+    variable_validation: VariableValidation,
+    introspection_filter: Option<Arc<IntrospectionFilter>>,
     req: SupergraphRequest,
 ) -> Result<SupergraphResponse, BoxError>
 where
@@ -164,6 +200,19 @@ where
             .expect("this response build must not fail"));
     }
 
+    // introspection can be restricted to some requests
+    let content = match content {
+        Some(QueryPlannerContent::Introspection { .. })
+            if introspection_filter
+                .as_ref()
+                .map(|filter| !filter.allows(&req.supergraph_request, &context))
+                .unwrap_or_default() =>
+        {
+            Some(QueryPlannerContent::IntrospectionDisabled)
+        }
+        content => content,
+    };
+
     match content {
         Some(QueryPlannerContent::Introspection { response }) => Ok(
             SupergraphResponse::new_from_graphql_response(*response, context),
@@ -200,7 +249,23 @@ where
                     .build(), context);
                 *response.response.status_mut() = StatusCode::NOT_ACCEPTABLE;
                 Ok(response)
-            } else if let Some(err) = plan.query.validate_variables(body, &schema).err() {
+            } else if is_deferred && feature_flags::flag(&context, DEFER_FLAG) == Some(false) {
+                let mut response = SupergraphResponse::new_from_graphql_response(
+                    graphql::Response::builder()
+                        .errors(vec![crate::error::Error::builder()
+                            .message("the @defer directive is disabled for this request")
+                            .extension_code("DEFER_DISABLED")
+                            .build()])
+                        .build(),
+                    context,
+                );
+                *response.response.status_mut() = StatusCode::BAD_REQUEST;
+                Ok(response)
+            } else if let Some(err) = plan
+                .query
+                .validate_variables(body, &schema, variable_validation)
+                .err()
+            {
                 let mut res = SupergraphResponse::new_from_graphql_response(err, context);
                 *res.response.status_mut() = StatusCode::BAD_REQUEST;
                 Ok(res)
@@ -235,7 +300,8 @@ async fn plan_query(
     body: &graphql::Request,
     context: Context,
 ) -> Result<QueryPlannerResponse, CacheResolverError> {
-    planning
+    let start = Instant::now();
+    let response = planning
         .call(
             QueryPlannerRequest::builder()
                 .query(
@@ -244,7 +310,7 @@ async fn plan_query(
                         .expect("the query presence was already checked by a plugin"),
                 )
                 .and_operation_name(body.operation_name.clone())
-                .context(context)
+                .context(context.clone())
                 .build(),
         )
         .instrument(tracing::info_span!(
@@ -257,7 +323,12 @@ async fn plan_query(
             graphql.operation.name = body.operation_name.clone().unwrap_or_default().as_str(),
             "otel.kind" = "INTERNAL"
         ))
-        .await
+        .await;
+    let _ = context.insert(
+        QUERY_PLANNING_DURATION_CONTEXT_KEY,
+        start.elapsed().as_secs_f64() * 1000.0,
+    );
+    response
 }
 
 /// Builder which generates a plugin pipeline.
@@ -269,7 +340,7 @@ async fn plan_query(
 pub(crate) struct PluggableSupergraphServiceBuilder {
     schema: Arc<Schema>,
     plugins: Plugins,
-    subgraph_services: Vec<(String, Arc<dyn MakeSubgraphService>)>,
+    subgraph_services: SubgraphServices,
     configuration: Option<Arc<Configuration>>,
 }
 
@@ -292,6 +363,7 @@ impl PluggableSupergraphServiceBuilder {
         self
     }
 
+    #[cfg(test)]
     pub(crate) fn with_subgraph_service<S>(
         mut self,
         name: &str,
@@ -305,6 +377,14 @@ impl PluggableSupergraphServiceBuilder {
         self
     }
 
+    pub(crate) fn with_subgraph_services(
+        mut self,
+        services: SubgraphServices,
+    ) -> PluggableSupergraphServiceBuilder {
+        self.subgraph_services.extend(services);
+        self
+    }
+
     pub(crate) fn with_configuration(
         mut self,
         configuration: Arc<Configuration>,
@@ -329,23 +409,16 @@ impl PluggableSupergraphServiceBuilder {
             None
         };
 
-        // QueryPlannerService takes an UnplannedRequest and outputs PlannedRequest
-        let bridge_query_planner =
-            BridgeQueryPlanner::new(self.schema.clone(), introspection, configuration.clone())
-                .await
-                .map_err(ServiceBuildError::QueryPlannerError)?;
-        let query_planner_service = CachingQueryPlanner::new(
-            bridge_query_planner,
-            self.schema.schema_id.clone(),
-            &configuration.supergraph.query_planning,
-        )
-        .await;
+        let query_planner_service =
+            query_planner(self.schema.clone(), introspection.clone(), &configuration).await?;
 
         let plugins = Arc::new(self.plugins);
 
         let subgraph_service_factory = Arc::new(SubgraphServiceFactory::new(
             self.subgraph_services,
             plugins.clone(),
+            Redactor::new(&configuration.redaction)
+                .expect("the redaction patterns are validated with the configuration; qed"),
         ));
 
         Ok(SupergraphCreator {
@@ -353,10 +426,35 @@ impl PluggableSupergraphServiceBuilder {
             subgraph_service_factory,
             schema: self.schema,
             plugins,
+            canary: None,
+            overrides: None,
+            variable_validation: configuration.supergraph.variable_validation,
+            introspection_filter: IntrospectionFilter::new(&configuration),
+            introspection,
+            configuration,
         })
     }
 }
 
+/// The query planner of a schema
+async fn query_planner(
+    schema: Arc<Schema>,
+    introspection: Option<Arc<Introspection>>,
+    configuration: &Arc<Configuration>,
+) -> Result<CachingQueryPlanner<BridgeQueryPlanner>, ServiceBuildError> {
+    // QueryPlannerService takes an UnplannedRequest and outputs PlannedRequest
+    let bridge_query_planner =
+        BridgeQueryPlanner::new(schema.clone(), introspection, configuration.clone())
+            .await
+            .map_err(ServiceBuildError::QueryPlannerError)?;
+    Ok(CachingQueryPlanner::new(
+        bridge_query_planner,
+        schema.schema_id.clone(),
+        configuration,
+    )
+    .await)
+}
+
 /// Factory for creating a RouterService
 ///
 /// Instances of this traits are used by the HTTP server to generate a new
@@ -386,6 +484,72 @@ pub(crate) struct SupergraphCreator {
     subgraph_service_factory: Arc<SubgraphServiceFactory>,
     schema: Arc<Schema>,
     plugins: Arc<Plugins>,
+    canary: Option<Arc<Canary>>,
+    overrides: Option<Arc<Overrides>>,
+    variable_validation: VariableValidation,
+    introspection_filter: Option<Arc<IntrospectionFilter>>,
+    /// Introspection of the schema, shared with the other versions of the schema
+    introspection: Option<Arc<Introspection>>,
+    configuration: Arc<Configuration>,
+}
+
+/// Services of the variants of the schema, and the override labels enabling them
+struct Overrides {
+    selectors: Vec<LabelSelector>,
+    /// Variants indexed by the bit set of their enabled labels, in the order of `selectors`
+    variants: Vec<SupergraphCreator>,
+}
+
+impl Overrides {
+    fn variant(&self, request: &SupergraphRequest) -> &SupergraphCreator {
+        let mut index = 0;
+        let mut enabled = Vec::new();
+        for (bit, selector) in self.selectors.iter().enumerate() {
+            let is_enabled = selector.enables(request);
+            tracing::info!(
+                monotonic_counter.apollo_router_override_label_requests_count = 1u64,
+                label = %selector.label,
+                enabled = is_enabled
+            );
+            if is_enabled {
+                index |= 1 << bit;
+                enabled.push(selector.label.clone());
+            }
+        }
+        let _ = request.context.insert(OVERRIDE_LABELS_CONTEXT_KEY, enabled);
+        &self.variants[index]
+    }
+}
+
+/// Services of the canary schema, and the requests they serve
+struct Canary {
+    creator: SupergraphCreator,
+    selector: CanarySelector,
+}
+
+struct CanarySelector {
+    percentage: f64,
+    header: Option<CanaryHeader>,
+}
+
+impl CanarySelector {
+    fn selects(&self, request: &SupergraphRequest) -> bool {
+        if let Some(enabled) = feature_flags::flag(&request.context, CANARY_FLAG) {
+            return enabled;
+        }
+        let header = self.header.as_ref().and_then(|header| {
+            let value = request
+                .supergraph_request
+                .headers()
+                .get(header.name.as_str())?;
+            Some(match &header.value {
+                Some(expected) => value.as_bytes() == expected.as_bytes(),
+                None => true,
+            })
+        });
+        header.unwrap_or(false)
+            || (self.percentage > 0.0 && rand::thread_rng().gen_bool(self.percentage / 100.0))
+    }
 }
 
 pub(crate) trait HasPlugins {
@@ -398,14 +562,148 @@ impl HasPlugins for SupergraphCreator {
     }
 }
 
+/// State of a supergraph service factory exposed on the admin API
+pub(crate) trait Administrable {
+    /// Id of the supergraph schema
+    fn schema_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Removes the cached query plans and introspection responses
+    fn clear_caches(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+}
+
+impl Administrable for SupergraphCreator {
+    fn schema_id(&self) -> Option<String> {
+        self.schema.schema_id.clone()
+    }
+
+    fn clear_caches(&self) -> BoxFuture<'static, ()> {
+        let query_planner = self.query_planner_service.clone();
+        let mut variants = Vec::new();
+        if let Some(canary) = &self.canary {
+            variants.push(canary.creator.clear_caches());
+        }
+        if let Some(overrides) = &self.overrides {
+            variants.extend(
+                overrides
+                    .variants
+                    .iter()
+                    .map(|variant| variant.clear_caches()),
+            );
+        }
+        Box::pin(async move {
+            query_planner.clear().await;
+            futures::future::join_all(variants).await;
+        })
+    }
+}
+
 impl ServiceFactory<supergraph::Request> for SupergraphCreator {
     type Service = supergraph::BoxService;
     fn create(&self) -> Self::Service {
-        self.make().boxed()
+        if self.canary.is_none() && self.overrides.is_none() {
+            return self.make().boxed();
+        }
+        let primary = self.clone();
+        tower::service_fn(move |request: SupergraphRequest| {
+            let service = match &primary.canary {
+                Some(canary) => {
+                    let (version, service) = if canary.selector.selects(&request) {
+                        ("canary", canary.creator.make_for(&request))
+                    } else {
+                        ("primary", primary.make_for(&request))
+                    };
+                    tracing::info!(
+                        monotonic_counter.apollo_router_schema_version_requests_count = 1u64,
+                        schema_version = version
+                    );
+                    let _ = request
+                        .context
+                        .insert(SCHEMA_VERSION_CONTEXT_KEY, version.to_string());
+                    service
+                }
+                None => primary.make_for(&request),
+            };
+            service.oneshot(request)
+        })
+        .boxed()
     }
 }
 
 impl SupergraphCreator {
+    /// The services of another version of the schema, with the same plugins. Only the query
+    /// planner is created for the schema: the subgraph services are shared too, unless the schema
+    /// has its own subgraphs
+    pub(crate) async fn for_schema(
+        &self,
+        schema: Arc<Schema>,
+        subgraph_services: Option<SubgraphServices>,
+    ) -> Result<SupergraphCreator, ServiceBuildError> {
+        let query_planner_service = query_planner(
+            schema.clone(),
+            self.introspection.clone(),
+            &self.configuration,
+        )
+        .await?;
+        let subgraph_service_factory = match subgraph_services {
+            Some(services) => Arc::new(SubgraphServiceFactory::new(
+                services,
+                self.plugins.clone(),
+                self.subgraph_service_factory.redactor.clone(),
+            )),
+            None => self.subgraph_service_factory.clone(),
+        };
+        Ok(SupergraphCreator {
+            query_planner_service,
+            subgraph_service_factory,
+            schema,
+            canary: None,
+            overrides: None,
+            ..self.clone()
+        })
+    }
+
+    /// Sends a part of the requests to the services of another schema
+    pub(crate) fn with_canary(
+        mut self,
+        creator: SupergraphCreator,
+        configuration: &crate::configuration::Canary,
+    ) -> Self {
+        self.canary = Some(Arc::new(Canary {
+            creator,
+            selector: CanarySelector {
+                percentage: configuration.percentage,
+                header: configuration.header.clone(),
+            },
+        }));
+        self
+    }
+
+    /// Plans and executes the requests with the variant of the schema enabling their override
+    /// labels
+    pub(crate) fn with_progressive_override(
+        mut self,
+        selectors: Vec<LabelSelector>,
+        variants: Vec<SupergraphCreator>,
+    ) -> Self {
+        self.overrides = Some(Arc::new(Overrides {
+            selectors,
+            variants,
+        }));
+        self
+    }
+
+    /// The services serving a request
+    fn make_for(&self, request: &SupergraphRequest) -> supergraph::BoxService {
+        match &self.overrides {
+            Some(overrides) => overrides.variant(request).make().boxed(),
+            None => self.make().boxed(),
+        }
+    }
+
     pub(crate) fn make(
         &self,
     ) -> impl Service<
@@ -422,6 +720,8 @@ impl SupergraphCreator {
                 subgraph_service_factory: self.subgraph_service_factory.clone(),
             })
             .schema(self.schema.clone())
+            .variable_validation(self.variable_validation)
+            .and_introspection_filter(self.introspection_filter.clone())
             .build();
 
         let supergraph_service = match self
@@ -449,11 +749,9 @@ impl SupergraphCreator {
     pub(crate) async fn cache_keys(&self, count: usize) -> Vec<(String, Option<String>)> {
         self.query_planner_service.cache_keys(count).await
     }
-    pub(crate) async fn warm_up_query_planner(
-        &mut self,
-        cache_keys: Vec<(String, Option<String>)>,
-    ) {
-        self.query_planner_service.warm_up(cache_keys).await
+    pub(crate) async fn warm_up_query_planner(&self, cache_keys: Vec<(String, Option<String>)>) {
+        // the clones of the query planner share its cache
+        self.query_planner_service.clone().warm_up(cache_keys).await
     }
 
     /// Create a test service.
@@ -503,6 +801,9 @@ impl HasPlugins for MockSupergraphCreator {
     }
 }
 
+#[cfg(test)]
+impl Administrable for MockSupergraphCreator {}
+
 #[cfg(test)]
 impl ServiceFactory<supergraph::Request> for MockSupergraphCreator {
     type Service = supergraph::BoxService;
@@ -513,6 +814,7 @@ impl ServiceFactory<supergraph::Request> for MockSupergraphCreator {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
 
     use super::*;
     use crate::plugin::test::MockSubgraph;
@@ -1537,6 +1839,58 @@ mod tests {
         insta::assert_json_snapshot!(stream.next_response().await.unwrap());
     }
 
+    #[test]
+    fn canary_selects_requests_by_header_and_percentage() {
+        let request = |header: Option<&str>| match header {
+            Some(value) => supergraph::Request::fake_builder()
+                .header("x-canary", value)
+                .build()
+                .unwrap(),
+            None => supergraph::Request::fake_builder().build().unwrap(),
+        };
+        let selector = |percentage, value: Option<&str>| CanarySelector {
+            percentage,
+            header: Some(CanaryHeader {
+                name: "X-Canary".to_string(),
+                value: value.map(str::to_string),
+            }),
+        };
+
+        assert!(selector(0.0, None).selects(&request(Some("anything"))));
+        assert!(selector(0.0, Some("true")).selects(&request(Some("true"))));
+        assert!(!selector(0.0, Some("true")).selects(&request(Some("false"))));
+        assert!(!selector(0.0, None).selects(&request(None)));
+        assert!(selector(100.0, Some("true")).selects(&request(None)));
+    }
+
+    #[test]
+    fn canary_selects_requests_by_feature_flag() {
+        let request = |enabled: bool| {
+            let context = Context::new();
+            context
+                .insert(
+                    feature_flags::FEATURE_FLAGS_CONTEXT_KEY,
+                    HashMap::from([(CANARY_FLAG.to_string(), enabled)]),
+                )
+                .unwrap();
+            supergraph::Request::fake_builder()
+                .header("x-canary", "true")
+                .context(context)
+                .build()
+                .unwrap()
+        };
+        let selector = |percentage| CanarySelector {
+            percentage,
+            header: Some(CanaryHeader {
+                name: "x-canary".to_string(),
+                value: None,
+            }),
+        };
+
+        assert!(selector(0.0).selects(&request(true)));
+        assert!(!selector(100.0).selects(&request(false)));
+    }
+
     fn defer_context() -> Context {
         let context = Context::new();
         context.insert(ACCEPTS_MULTIPART_CONTEXT_KEY, true).unwrap();
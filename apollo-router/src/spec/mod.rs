@@ -4,6 +4,7 @@
 
 mod field_type;
 mod fragments;
+mod parser_limits;
 pub(crate) mod query;
 mod schema;
 mod selection;
@@ -34,6 +35,10 @@ pub(crate) enum SpecError {
     InvalidField(String, String),
     /// parsing error: {0}
     ParsingError(String),
+    /// parser limit exceeded: {0}
+    ParserLimitExceeded(String),
+    /// introspection limit exceeded: {0}
+    IntrospectionLimitExceeded(String),
     /// subscription operation is not supported
     SubscriptionNotSupported,
 }
@@ -41,7 +46,9 @@ pub(crate) enum SpecError {
 impl SpecError {
     pub(crate) const fn get_error_key(&self) -> &'static str {
         match self {
-            SpecError::ParsingError(_) => "## GraphQLParseFailure\n",
+            SpecError::ParsingError(_) | SpecError::ParserLimitExceeded(_) => {
+                "## GraphQLParseFailure\n"
+            }
             _ => "## GraphQLValidationFailure\n",
         }
     }
@@ -54,6 +61,8 @@ impl ErrorExtension for SpecError {
             SpecError::InvalidType(_) => "INVALID_TYPE",
             SpecError::InvalidField(_, _) => "INVALID_FIELD",
             SpecError::ParsingError(_) => "PARSING_ERROR",
+            SpecError::ParserLimitExceeded(_) => "PARSER_LIMIT_EXCEEDED",
+            SpecError::IntrospectionLimitExceeded(_) => "INTROSPECTION_LIMIT_EXCEEDED",
             SpecError::SubscriptionNotSupported => "SUBSCRIPTION_NOT_SUPPORTED",
         }
         .to_string()
@@ -0,0 +1,319 @@
+//! Limits on the documents of operations
+//!
+//! Adversarial documents can be small enough to be accepted by the HTTP server, yet expensive to
+//! validate and plan: millions of tokens, thousands of directives, or long chains of fragments
+//! spreading each other. The tokens and directives are counted by a scan of the source that stops
+//! at the first exceeded limit, before the syntax tree is built. The nesting of fragment spreads
+//! needs the fragment definitions, and is checked on the syntax tree, before the document is
+//! validated or planned. The rejected documents are counted in the
+//! `apollo_router_parser_limit_exceeded_total` metric.
+
+use std::collections::HashMap;
+
+use apollo_parser::ast;
+use apollo_parser::ast::AstNode;
+use apollo_parser::SyntaxNode;
+
+use super::SpecError;
+use crate::configuration::ParserLimits;
+
+/// Checks the limits on the tokens and directives of a document, before it is parsed
+pub(crate) fn check_source(source: &str, limits: &ParserLimits) -> Result<(), SpecError> {
+    if limits.max_tokens.is_none() && limits.max_directives.is_none() {
+        return Ok(());
+    }
+    let max_tokens = limits.max_tokens.unwrap_or(usize::MAX);
+    let max_directives = limits.max_directives.unwrap_or(usize::MAX);
+    let mut tokens = 0;
+    let mut directives = 0;
+    for token in Tokens::new(source) {
+        tokens += 1;
+        if tokens > max_tokens {
+            return Err(exceeded(
+                "tokens",
+                format!("the document has more than {max_tokens} tokens"),
+            ));
+        }
+        if token == b'@' {
+            directives += 1;
+            if directives > max_directives {
+                return Err(exceeded(
+                    "directives",
+                    format!("the document has more than {max_directives} directives"),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the limits on the structure of a parsed document
+pub(crate) fn check(document: &ast::Document, limits: &ParserLimits) -> Result<(), SpecError> {
+    if let Some(max) = limits.max_fragment_spread_depth {
+        let depth = fragment_spread_depth(document);
+        if depth > max {
+            return Err(exceeded(
+                "fragment_spread_depth",
+                format!("the document nests fragment spreads {depth} deep, exceeding the limit of {max}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The tokens of a GraphQL document, without whitespace, comments and commas, as their first byte.
+/// Invalid characters are one token each, and rejected by the parser
+struct Tokens<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(source: &'a str) -> Self {
+        Tokens {
+            source: source.as_bytes(),
+            position: 0,
+        }
+    }
+
+    fn skip_while(&mut self, predicate: impl Fn(u8) -> bool) {
+        while self.position < self.source.len() && predicate(self.source[self.position]) {
+            self.position += 1;
+        }
+    }
+
+    fn rest(&self) -> &[u8] {
+        &self.source[self.position..]
+    }
+
+    /// Skips a string, its opening quote included
+    fn skip_string(&mut self) {
+        if self.rest().starts_with(b"\"\"\"") {
+            self.position += 3;
+            while self.position < self.source.len() {
+                if self.rest().starts_with(b"\\\"\"\"") {
+                    self.position += 4;
+                } else if self.rest().starts_with(b"\"\"\"") {
+                    self.position += 3;
+                    return;
+                } else {
+                    self.position += 1;
+                }
+            }
+        } else {
+            self.position += 1;
+            while let Some(&byte) = self.source.get(self.position) {
+                self.position += 1;
+                match byte {
+                    b'\\' => self.position += 1,
+                    b'"' | b'\n' | b'\r' => return,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            let first = *self.source.get(self.position)?;
+            match first {
+                b' ' | b'\t' | b'\n' | b'\r' | b',' => self.position += 1,
+                // byte order mark
+                0xEF if self.rest().starts_with(&[0xEF, 0xBB, 0xBF]) => self.position += 3,
+                b'#' => self.skip_while(|byte| byte != b'\n' && byte != b'\r'),
+                b'"' => {
+                    self.skip_string();
+                    return Some(first);
+                }
+                b'.' if self.rest().starts_with(b"...") => {
+                    self.position += 3;
+                    return Some(first);
+                }
+                b'_' | b'a'..=b'z' | b'A'..=b'Z' => {
+                    self.skip_while(|byte| byte == b'_' || byte.is_ascii_alphanumeric());
+                    return Some(first);
+                }
+                // numbers, with their sign, fraction and exponent
+                b'-' | b'0'..=b'9' => {
+                    self.position += 1;
+                    let mut previous = first;
+                    while let Some(&byte) = self.source.get(self.position) {
+                        let continues = byte.is_ascii_alphanumeric()
+                            || (byte == b'.'
+                                && self
+                                    .source
+                                    .get(self.position + 1)
+                                    .map_or(false, u8::is_ascii_digit))
+                            || (matches!(byte, b'+' | b'-') && matches!(previous, b'e' | b'E'));
+                        if !continues {
+                            break;
+                        }
+                        previous = byte;
+                        self.position += 1;
+                    }
+                    return Some(first);
+                }
+                _ => {
+                    // a whole UTF-8 character
+                    self.position += 1;
+                    self.skip_while(|byte| byte & 0xC0 == 0x80);
+                    return Some(first);
+                }
+            }
+        }
+    }
+}
+
+fn exceeded(limit: &'static str, message: String) -> SpecError {
+    tracing::info!(
+        monotonic_counter.apollo_router_parser_limit_exceeded_total = 1u64,
+        limit,
+    );
+    SpecError::ParserLimitExceeded(message)
+}
+
+/// The deepest nesting of fragment spreads in the operations of the document. Fragments spreading
+/// themselves, which are invalid, nest infinitely
+fn fragment_spread_depth(document: &ast::Document) -> usize {
+    let mut fragments = HashMap::new();
+    let mut operations = Vec::new();
+    for definition in document.definitions() {
+        match definition {
+            ast::Definition::FragmentDefinition(fragment) => {
+                if let Some(name) = fragment.fragment_name().and_then(|name| name.name()) {
+                    fragments.insert(name.text().to_string(), fragment.syntax().clone());
+                }
+            }
+            ast::Definition::OperationDefinition(operation) => {
+                operations.push(operation.syntax().clone())
+            }
+            _ => {}
+        }
+    }
+
+    let mut depths = DepthCache {
+        fragments,
+        depths: HashMap::new(),
+    };
+    operations
+        .iter()
+        .map(|operation| depths.spreads_depth(operation))
+        .max()
+        .unwrap_or(0)
+}
+
+struct DepthCache {
+    fragments: HashMap<String, SyntaxNode>,
+    /// Depths by fragment name, `None` while the depth of the fragment is computed
+    depths: HashMap<String, Option<usize>>,
+}
+
+impl DepthCache {
+    /// The deepest nesting of the fragment spreads found in a node
+    fn spreads_depth(&mut self, node: &SyntaxNode) -> usize {
+        node.descendants()
+            .filter_map(ast::FragmentSpread::cast)
+            .filter_map(|spread| spread.fragment_name()?.name())
+            .map(|name| self.fragment_depth(&name.text()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The nesting of a fragment spread: 1, plus the nesting of the spreads of the fragment
+    fn fragment_depth(&mut self, name: &str) -> usize {
+        match self.depths.get(name) {
+            Some(Some(depth)) => return *depth,
+            Some(None) => return usize::MAX,
+            None => {}
+        }
+        let fragment = match self.fragments.get(name) {
+            Some(fragment) => fragment.clone(),
+            // unknown fragments are rejected by validation
+            None => return 1,
+        };
+        self.depths.insert(name.to_string(), None);
+        let depth = self.spreads_depth(&fragment).saturating_add(1);
+        self.depths.insert(name.to_string(), Some(depth));
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_query(query: &str, limits: ParserLimits) -> Result<(), SpecError> {
+        check_source(query, &limits)?;
+        let tree = apollo_parser::Parser::new(query).parse();
+        assert!(tree.errors().next().is_none());
+        check(&tree.document(), &limits)
+    }
+
+    #[test]
+    fn it_counts_the_tokens_of_the_parser() {
+        let queries = [
+            "{ me @include(if: true) { id, name } }",
+            "query Q($a: [Int!] = [1, -2.5e+3, 4E-1]) { f(a: $a, b: \"x, \\\"y\\\" # z\") }",
+            "# comment\n{ ...on User { f(s: \"\"\" a \\\"\"\" b \"\"\") } } fragment F on T { a: id }",
+            "{ me...F ...on User { id } }",
+        ];
+        for query in queries {
+            let tree = apollo_parser::Parser::new(query).parse();
+            let tokens = tree
+                .document()
+                .syntax()
+                .descendants_with_tokens()
+                .filter_map(|element| element.into_token())
+                // whitespace, comments and commas are ignored
+                .filter(|token| {
+                    let text = token.text();
+                    !(text.trim().is_empty() || text.starts_with('#') || text == ",")
+                })
+                .count();
+            assert_eq!(Tokens::new(query).count(), tokens, "{query}");
+        }
+    }
+
+    #[test]
+    fn it_limits_tokens_and_directives() {
+        let query = "{ me @include(if: true) { id, name } }";
+        let limits = |max_tokens, max_directives| ParserLimits {
+            max_tokens: Some(max_tokens),
+            max_directives: Some(max_directives),
+            max_fragment_spread_depth: None,
+        };
+        assert!(check_query(query, limits(20, 1)).is_ok());
+        assert!(matches!(
+            check_query(query, limits(10, 1)),
+            Err(SpecError::ParserLimitExceeded(_))
+        ));
+        assert!(matches!(
+            check_query(query, limits(20, 0)),
+            Err(SpecError::ParserLimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn it_limits_the_nesting_of_fragment_spreads() {
+        let query = "
+            { me { ...A ...C } }
+            fragment A on User { ...B }
+            fragment B on User { name }
+            fragment C on User { id }
+        ";
+        let limits = |max| ParserLimits {
+            max_fragment_spread_depth: Some(max),
+            ..Default::default()
+        };
+        assert!(check_query(query, limits(2)).is_ok());
+        assert!(check_query(query, limits(1)).is_err());
+
+        let query = "{ me { ...A } } fragment A on User { ...B } fragment B on User { ...A }";
+        assert!(check_query(query, limits(1000)).is_err());
+    }
+}
@@ -15,6 +15,7 @@ use serde::Serialize;
 use serde_json_bytes::ByteString;
 use tracing::level_filters::LevelFilter;
 
+use crate::configuration::VariableValidation;
 use crate::error::FetchError;
 use crate::graphql::Error;
 use crate::graphql::Request;
@@ -23,6 +24,7 @@ use crate::json_ext::Object;
 use crate::json_ext::Path;
 use crate::json_ext::PathElement;
 use crate::json_ext::Value;
+use crate::json_ext::ValueExt;
 use crate::query_planner::fetch::OperationKind;
 use crate::spec::FieldType;
 use crate::spec::Fragments;
@@ -259,6 +261,7 @@ impl Query {
         configuration: &Configuration,
     ) -> Result<Self, SpecError> {
         let query = query.into();
+        super::parser_limits::check_source(&query, &configuration.server.parser_limits)?;
         let parser = apollo_parser::Parser::new(query.as_str())
             .recursion_limit(configuration.server.experimental_parser_recursion_limit);
         let tree = parser.parse();
@@ -279,6 +282,7 @@ impl Query {
         }
 
         let document = tree.document();
+        super::parser_limits::check(&document, &configuration.server.parser_limits)?;
         let fragments = Fragments::from_ast(&document, schema)?;
 
         let operations: Vec<Operation> = document
@@ -893,7 +897,12 @@ impl Query {
         &self,
         request: &Request,
         schema: &Schema,
+        validation: VariableValidation,
     ) -> Result<(), Response> {
+        if validation == VariableValidation::Lenient {
+            return Ok(());
+        }
+
         let operation_name = request.operation_name.as_deref();
         let operation_variable_types =
             self.operations
@@ -905,17 +914,30 @@ impl Query {
                     acc
                 });
 
-        if LevelFilter::current() >= LevelFilter::DEBUG {
+        let mut unknown_variable_errors = Vec::new();
+        if validation == VariableValidation::Strict || LevelFilter::current() >= LevelFilter::DEBUG
+        {
             let known_variables = operation_variable_types.keys().cloned().collect();
             let provided_variables = request
                 .variables
                 .keys()
                 .map(|k| k.as_str())
                 .collect::<HashSet<_>>();
-            let unknown_variables = provided_variables
+            let mut unknown_variables = provided_variables
                 .difference(&known_variables)
                 .collect::<Vec<_>>();
-            if !unknown_variables.is_empty() {
+            if validation == VariableValidation::Strict {
+                unknown_variables.sort();
+                unknown_variable_errors = unknown_variables
+                    .iter()
+                    .map(|name| {
+                        FetchError::ValidationUnknownVariable {
+                            name: name.to_string(),
+                        }
+                        .to_graphql_error(None)
+                    })
+                    .collect();
+            } else if !unknown_variables.is_empty() {
                 failfast_debug!(
                     "Received variable unknown to the query: {:?}",
                     unknown_variables,
@@ -923,7 +945,7 @@ impl Query {
             }
         }
 
-        let errors = operation_variable_types
+        let mut errors = operation_variable_types
             .iter()
             .filter_map(
                 |(
@@ -947,6 +969,7 @@ impl Query {
                 },
             )
             .collect::<Vec<_>>();
+        errors.append(&mut unknown_variable_errors);
 
         if errors.is_empty() {
             Ok(())
@@ -963,6 +986,65 @@ impl Query {
         self.operations.iter().any(Operation::is_introspection)
     }
 
+    /// Returns the number of fields selected by an operation, counting the fields of fragments at
+    /// each of their uses
+    pub(crate) fn field_count(&self, operation_name: Option<&str>) -> usize {
+        self.operation(operation_name).map_or(0, |operation| {
+            self.selection_set_field_count(&operation.selection_set)
+        })
+    }
+
+    fn selection_set_field_count(&self, selection_set: &[Selection]) -> usize {
+        selection_set
+            .iter()
+            .map(|selection| match selection {
+                Selection::Field { selection_set, .. } => {
+                    1 + selection_set.as_deref().map_or(0, |selection_set| {
+                        self.selection_set_field_count(selection_set)
+                    })
+                }
+                Selection::InlineFragment { selection_set, .. } => {
+                    self.selection_set_field_count(selection_set)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    self.fragments.get(name).map_or(0, |fragment| {
+                        self.selection_set_field_count(&fragment.selection_set)
+                    })
+                }
+            })
+            .sum()
+    }
+
+    /// Returns the depth of the selections of an operation: 1 for an operation selecting only
+    /// scalar fields
+    pub(crate) fn depth(&self, operation_name: Option<&str>) -> usize {
+        self.operation(operation_name).map_or(0, |operation| {
+            self.selection_set_depth(&operation.selection_set)
+        })
+    }
+
+    fn selection_set_depth(&self, selection_set: &[Selection]) -> usize {
+        selection_set
+            .iter()
+            .map(|selection| match selection {
+                Selection::Field { selection_set, .. } => {
+                    1 + selection_set
+                        .as_deref()
+                        .map_or(0, |selection_set| self.selection_set_depth(selection_set))
+                }
+                Selection::InlineFragment { selection_set, .. } => {
+                    self.selection_set_depth(selection_set)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    self.fragments.get(name).map_or(0, |fragment| {
+                        self.selection_set_depth(&fragment.selection_set)
+                    })
+                }
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
     pub(crate) fn variable_value<'a>(
         &'a self,
         operation_name: Option<&str>,
@@ -1031,6 +1113,78 @@ impl Query {
             .iter()
             .any(|selection| selection.contains_error_path(&path.0, &self.fragments))
     }
+
+    /// Renames the fields of data keyed by field names, such as the events resolving a
+    /// subscription, to the response keys of the operation, so that it can be formatted like the
+    /// data returned by subgraphs
+    pub(crate) fn alias_fields(&self, operation_name: Option<&str>, data: &Object) -> Object {
+        let mut output = Object::default();
+        if let Some(operation) = self.operation(operation_name) {
+            self.alias_selection_set(&operation.selection_set, data, &mut output);
+        }
+        output
+    }
+
+    fn alias_selection_set(
+        &self,
+        selection_set: &[Selection],
+        input: &Object,
+        output: &mut Object,
+    ) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    ..
+                } => {
+                    if let Some(value) = input.get(name.as_str()) {
+                        let value = match selection_set {
+                            Some(selection_set) => self.alias_value(selection_set, value),
+                            None => value.clone(),
+                        };
+                        let key = alias.as_ref().unwrap_or(name);
+                        match output.get_mut(key.as_str()) {
+                            Some(existing) => existing.deep_merge(value),
+                            None => {
+                                output.insert(key.clone(), value);
+                            }
+                        }
+                    }
+                }
+                Selection::InlineFragment { selection_set, .. } => {
+                    self.alias_selection_set(selection_set, input, output)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    if let Some(fragment) = self.fragments.get(name) {
+                        self.alias_selection_set(&fragment.selection_set, input, output)
+                    }
+                }
+            }
+        }
+    }
+
+    fn alias_value(&self, selection_set: &[Selection], value: &Value) -> Value {
+        match value {
+            Value::Object(input) => {
+                let mut output = Object::default();
+                // the type conditions are applied when formatting the data
+                if let Some(typename) = input.get(TYPENAME) {
+                    output.insert(TYPENAME, typename.clone());
+                }
+                self.alias_selection_set(selection_set, input, &mut output);
+                Value::Object(output)
+            }
+            Value::Array(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|value| self.alias_value(selection_set, value))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
 }
 
 /// Intermediate structure for arguments passed through the entire formatting
@@ -1076,7 +1230,14 @@ impl Operation {
         let current_field_type = match kind {
             OperationKind::Query => FieldType::Named("Query".to_string()),
             OperationKind::Mutation => FieldType::Named("Mutation".to_string()),
-            OperationKind::Subscription => return Err(SpecError::SubscriptionNotSupported),
+            // subscriptions are planned only if they are resolved from events
+            OperationKind::Subscription => {
+                let name = schema.root_operation_name(OperationKind::Subscription);
+                if !schema.object_types.contains_key(name) {
+                    return Err(SpecError::SubscriptionNotSupported);
+                }
+                FieldType::Named(name.to_string())
+            }
         };
 
         let selection_set = operation
@@ -1016,6 +1016,9 @@ fn reformat_response_query_with_root_typename() {
 
 macro_rules! run_validation {
     ($schema:expr, $query:expr, $variables:expr $(,)?) => {{
+        run_validation!($schema, $query, $variables, VariableValidation::Standard)
+    }};
+    ($schema:expr, $query:expr, $variables:expr, $validation:expr $(,)?) => {{
         let variables = match $variables {
             Value::Object(object) => object,
             _ => unreachable!("variables must be an object"),
@@ -1034,7 +1037,7 @@ macro_rules! run_validation {
             &Default::default(),
         )
         .expect("could not parse query");
-        query.validate_variables(&request, &schema)
+        query.validate_variables(&request, &schema, $validation)
     }};
 }
 
@@ -1052,6 +1055,45 @@ macro_rules! assert_validation_error {
     }};
 }
 
+#[test]
+fn variable_validation_modes() {
+    let schema = with_supergraph_boilerplate("type Query { x: String }");
+    let query = "query($foo:Int!){x}";
+
+    let res = run_validation!(
+        schema,
+        query,
+        json!({ "foo": null }),
+        VariableValidation::Lenient
+    );
+    assert!(
+        res.is_ok(),
+        "lenient validation should forward invalid variables"
+    );
+
+    let res = run_validation!(schema, query, json!({"foo": 1, "bar": 2}));
+    assert!(
+        res.is_ok(),
+        "standard validation should ignore unknown variables"
+    );
+
+    let res = run_validation!(
+        schema,
+        query,
+        json!({"foo": 1, "bar": 2}),
+        VariableValidation::Strict
+    );
+    let errors = res
+        .expect_err("strict validation should reject unknown variables")
+        .errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].extensions.get("code"),
+        Some(&Value::from("VALIDATION_UNKNOWN_VARIABLE"))
+    );
+    assert_eq!(errors[0].extensions.get("name"), Some(&Value::from("bar")));
+}
+
 #[test]
 fn variable_validation() {
     let schema = "type Query { x: String }";
@@ -5236,3 +5278,110 @@ fn test_error_path_works_across_inline_fragments() {
         &Path::from("rootType/edges/0/node/subType/edges/0/node/myField")
     ));
 }
+
+#[test]
+fn it_counts_selected_fields() {
+    let schema = with_supergraph_boilerplate(
+        "type Query {
+        product: Product
+    }
+
+    type Product {
+        id: String!
+        name: String
+        review: Review
+    }
+
+    type Review {
+        id: String!
+        body: String
+    }",
+    );
+    let schema = Schema::parse(&schema, &Default::default()).expect("could not parse schema");
+
+    let query = Query::parse(
+        "query First {
+            product {
+                id
+                review { ...ReviewFields }
+            }
+        }
+        query Second {
+            product { ... on Product { name } }
+        }
+        fragment ReviewFields on Review {
+            id
+            body
+        }",
+        &schema,
+        &Default::default(),
+    )
+    .expect("could not parse query");
+    assert_eq!(query.field_count(Some("First")), 5);
+    assert_eq!(query.field_count(Some("Second")), 2);
+    assert_eq!(query.field_count(Some("Third")), 0);
+    assert_eq!(query.depth(Some("First")), 3);
+    assert_eq!(query.depth(Some("Second")), 2);
+    assert_eq!(query.depth(Some("Third")), 0);
+}
+
+#[test]
+fn it_aliases_the_fields_of_subscription_events() {
+    let schema = with_supergraph_boilerplate(
+        "type Query {
+            review: Review
+        }
+
+        type Subscription {
+            reviewAdded(productId: ID!): Review
+        }
+
+        type Review {
+            id: String!
+            body: String
+            product: Product
+        }
+
+        type Product {
+            id: String!
+            name: String
+        }",
+    );
+    let schema = Schema::parse(&schema, &Default::default()).expect("could not parse schema");
+
+    let query = Query::parse(
+        "subscription OnReview {
+            added: reviewAdded(productId: \"1\") {
+                id
+                text: body
+                product { ...ProductFields }
+            }
+        }
+        fragment ProductFields on Product {
+            title: name
+        }",
+        &schema,
+        &Default::default(),
+    )
+    .expect("could not parse query");
+    assert_eq!(query.operations[0].kind(), &OperationKind::Subscription);
+
+    let event = json!({
+        "reviewAdded": {
+            "id": "r1",
+            "body": "great",
+            "product": { "id": "1", "name": "Table" }
+        }
+    });
+    let data = query.alias_fields(Some("OnReview"), event.as_object().unwrap());
+    assert_eq!(
+        Value::Object(data),
+        json!({
+            "added": {
+                "id": "r1",
+                "text": "great",
+                "product": { "title": "Table" }
+            }
+        })
+    );
+}
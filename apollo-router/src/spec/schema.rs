@@ -1,5 +1,7 @@
 //! GraphQL schema.
 
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::str::FromStr;
@@ -762,6 +764,32 @@ impl Schema {
             .map(|s| s.as_str())
             .unwrap_or_else(|| kind.as_str())
     }
+
+    /// Names of the object, interface, input object, enum and custom scalar types, with the names
+    /// of their fields or enum values
+    pub(crate) fn type_members(&self) -> BTreeMap<&str, BTreeSet<&str>> {
+        fn names<V>(map: &HashMap<String, V>) -> BTreeSet<&str> {
+            map.keys().map(String::as_str).collect()
+        }
+
+        let mut members = BTreeMap::new();
+        for (name, object) in &self.object_types {
+            members.insert(name.as_str(), names(&object.fields));
+        }
+        for (name, interface) in &self.interfaces {
+            members.insert(name.as_str(), names(&interface.fields));
+        }
+        for (name, input) in &self.input_types {
+            members.insert(name.as_str(), names(&input.fields));
+        }
+        for (name, values) in &self.enums {
+            members.insert(name.as_str(), values.iter().map(String::as_str).collect());
+        }
+        for name in &self.custom_scalars {
+            members.insert(name.as_str(), BTreeSet::new());
+        }
+        members
+    }
 }
 
 #[derive(Debug)]
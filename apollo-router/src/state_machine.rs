@@ -25,7 +25,12 @@ use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
 use crate::router_factory::RouterFactory;
 use crate::router_factory::RouterSuperServiceFactory;
+use crate::schema_change;
+use crate::schema_change::SchemaChange;
 use crate::spec::Schema;
+use crate::version_metrics;
+use crate::version_metrics::ActiveVersions;
+use crate::version_metrics::ReloadKind;
 
 /// This state maintains private information that is not exposed to the user via state listener.
 #[derive(derivative::Derivative)]
@@ -77,6 +82,7 @@ where
     pub(crate) extra_listen_adresses: Arc<RwLock<Vec<ListenAddr>>>,
     extra_listen_addresses_guard: Option<OwnedRwLockWriteGuard<Vec<ListenAddr>>>,
     graphql_listen_address_guard: Option<OwnedRwLockWriteGuard<Option<ListenAddr>>>,
+    active_versions: ActiveVersions,
 }
 
 impl<S, FA> StateMachine<S, FA>
@@ -97,6 +103,7 @@ where
             graphql_listen_address_guard: Some(graphql_ready_guard),
             extra_listen_adresses: extra_ready,
             extra_listen_addresses_guard: Some(extra_ready_guard),
+            active_versions: ActiveVersions::default(),
         }
     }
 
@@ -165,8 +172,9 @@ where
                 ) => {
                     tracing::info!("reloading schema");
                     match Schema::parse(&new_schema, &configuration) {
-                        Ok(new_schema) => self
-                            .reload_server(
+                        Ok(new_schema) => {
+                            let change = SchemaChange::new(&schema, &new_schema);
+                            self.reload_server(
                                 configuration,
                                 schema,
                                 router_service_factory,
@@ -175,9 +183,32 @@ where
                                 Some(Arc::new(new_schema)),
                             )
                             .await
-                            .into_ok_or_err2(),
+                            .map_err(|s| {
+                                version_metrics::record_reload(ReloadKind::Schema, false);
+                                s
+                            })
+                            .map(|s| {
+                                version_metrics::record_reload(ReloadKind::Schema, true);
+                                if let Running {
+                                    configuration,
+                                    schema,
+                                    router_service_factory,
+                                    ..
+                                } = &s
+                                {
+                                    if !change.is_unchanged() {
+                                        schema_change::record_schema_load(schema);
+                                        schema_change::notify(configuration, &change);
+                                        router_service_factory.schema_changed(&change);
+                                    }
+                                }
+                                s
+                            })
+                            .into_ok_or_err2()
+                        }
                         Err(e) => {
                             tracing::error!("could not parse schema: {:?}", e);
+                            version_metrics::record_reload(ReloadKind::Schema, false);
                             Running {
                                 configuration,
                                 schema,
@@ -200,7 +231,10 @@ where
                 ) => {
                     tracing::info!("reloading configuration");
                     if let Err(e) = configuration.is_compatible(&new_configuration) {
-                        tracing::error!("could not reload configuration: {e}");
+                        tracing::error!(
+                            "could not reload configuration, keeping the previous one: {e}"
+                        );
+                        version_metrics::record_reload(ReloadKind::Configuration, false);
 
                         Running {
                             configuration,
@@ -218,8 +252,13 @@ where
                             None,
                         )
                         .await
+                        .map_err(|s| {
+                            version_metrics::record_reload(ReloadKind::Configuration, false);
+                            s
+                        })
                         .map(|s| {
                             tracing::info!("reloaded");
+                            version_metrics::record_reload(ReloadKind::Configuration, true);
                             s
                         })
                         .into_ok_or_err2()
@@ -334,6 +373,8 @@ where
                     tracing::error!("cannot start the router: {}", err);
                     Errored(err)
                 })?;
+            schema_change::record_schema_load(&schema);
+            self.active_versions.record(&configuration, &schema);
 
             Ok(Running {
                 configuration,
@@ -387,6 +428,7 @@ where
                         tracing::error!("cannot start the router: {}", err);
                         Errored(err)
                     })?;
+                self.active_versions.record(&new_configuration, &new_schema);
                 Ok(Running {
                     configuration: new_configuration,
                     schema: new_schema,
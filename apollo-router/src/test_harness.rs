@@ -1,6 +1,13 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
 
+use futures::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
 use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
@@ -9,11 +16,16 @@ use tracing_futures::Instrument;
 
 use crate::axum_factory::utils::PropagatingMakeSpan;
 use crate::configuration::Configuration;
+use crate::graphql;
+use crate::layers::ServiceBuilderExt;
 use crate::plugin::test::canned;
 use crate::plugin::test::MockSubgraph;
 use crate::plugin::DynPlugin;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::fault_injection;
+use crate::plugins::fault_injection::FaultInjection;
+use crate::plugins::fault_injection::SubgraphFaults;
 use crate::router_factory::YamlRouterFactory;
 use crate::services::execution;
 use crate::services::router;
@@ -43,6 +55,9 @@ pub(crate) mod http_client;
 /// * CORS (FIXME: should this include CORS?)
 /// * HTTP compression
 ///
+/// Subscriptions are resolved from Kafka or NATS events rather than by subgraphs: their events
+/// are mocked with [`subscription_events`][Self::subscription_events].
+///
 /// Example making a single request:
 ///
 /// ```
@@ -68,10 +83,14 @@ pub(crate) mod http_client;
 /// # Ok(()) }
 /// ```
 pub struct TestHarness<'a> {
-    schema: Option<&'a str>,
+    schema: Option<Cow<'a, str>>,
     configuration: Option<Arc<Configuration>>,
     extra_plugins: Vec<(String, Box<dyn DynPlugin>)>,
     subgraph_network_requests: bool,
+    recorded_subgraph_requests: Option<RecordedSubgraphRequests>,
+    subgraph_faults: HashMap<String, SubgraphFaults>,
+    subgraph_fixtures: Option<(PathBuf, SubgraphFixturesMode)>,
+    subscription_events: HashMap<String, Vec<serde_json::Value>>,
 }
 
 // Not using buildstructor because `extra_plugin` has non-trivial signature and behavior
@@ -83,6 +102,10 @@ impl<'a> TestHarness<'a> {
             configuration: None,
             extra_plugins: Vec::new(),
             subgraph_network_requests: false,
+            recorded_subgraph_requests: None,
+            subgraph_faults: HashMap::new(),
+            subgraph_fixtures: None,
+            subscription_events: HashMap::new(),
         }
     }
 
@@ -95,10 +118,21 @@ impl<'a> TestHarness<'a> {
     /// In that case, subgraph responses are overridden with some “canned” data.
     pub fn schema(mut self, schema: &'a str) -> Self {
         assert!(self.schema.is_none(), "schema was specified twice");
-        self.schema = Some(schema);
+        self.schema = Some(Cow::Borrowed(schema));
         self
     }
 
+    /// Specifies the (static) supergraph schema definition, read from a file.
+    ///
+    /// Panics if called more than once, or after [`schema`][Self::schema].
+    ///
+    /// This is equivalent to calling [`schema`][Self::schema] with the content of the file.
+    pub fn schema_file(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        assert!(self.schema.is_none(), "schema was specified twice");
+        self.schema = Some(Cow::Owned(std::fs::read_to_string(path)?));
+        Ok(self)
+    }
+
     /// Specifies the (static) router configuration.
     pub fn configuration(mut self, configuration: Arc<Configuration>) -> Self {
         assert!(
@@ -169,6 +203,98 @@ impl<'a> TestHarness<'a> {
         self.extra_plugin(SubgraphServicePlugin(callback))
     }
 
+    /// Replaces the subgraph service named `subgraph_name` with a [`MockSubgraph`].
+    ///
+    /// May be called multiple times, once per subgraph.
+    /// [`MockSubgraph`] clones share their call counters, so a clone can be kept
+    /// to assert on received requests once the test requests have been made.
+    pub fn subgraph_mock(self, subgraph_name: &str, mock: MockSubgraph) -> Self {
+        let subgraph_name = subgraph_name.to_string();
+        self.subgraph_hook(move |name, default| {
+            if name == subgraph_name {
+                mock.clone().boxed()
+            } else {
+                default
+            }
+        })
+    }
+
+    /// Records every request sent to subgraphs in `recorded`.
+    ///
+    /// Panics if called more than once.
+    ///
+    /// Requests are recorded after plugins specified in configuration modified them
+    /// (for example to propagate headers), just before they reach the subgraph service
+    /// or a mocked subgraph.
+    pub fn record_subgraph_requests(mut self, recorded: RecordedSubgraphRequests) -> Self {
+        assert!(
+            self.recorded_subgraph_requests.is_none(),
+            "subgraph requests recording was specified twice"
+        );
+        self.recorded_subgraph_requests = Some(recorded);
+        self
+    }
+
+    /// Injects faults (latency, connection resets, malformed responses, partial errors)
+    /// in a fraction of the requests sent to the subgraph named `subgraph_name`.
+    ///
+    /// May be called multiple times, once per subgraph.
+    /// This replaces the `experimental_fault_injection` section of the configuration, if any.
+    pub fn subgraph_faults(mut self, subgraph_name: &str, faults: SubgraphFaults) -> Self {
+        self.subgraph_faults
+            .insert(subgraph_name.to_string(), faults);
+        self
+    }
+
+    /// Records subgraph traffic to fixture files in `dir`, or replays it from them.
+    ///
+    /// Panics if called more than once.
+    ///
+    /// There is one fixture file per subgraph, named `<subgraph name>.json`, containing
+    /// the request and response pairs exchanged with that subgraph.
+    ///
+    /// * In [`Record`][SubgraphFixturesMode::Record] mode, requests are sent over the network
+    ///   (as with [`with_subgraph_network_requests`][Self::with_subgraph_network_requests])
+    ///   and the fixture files of the subgraphs that received requests are replaced.
+    /// * In [`Replay`][SubgraphFixturesMode::Replay] mode, each subgraph with a fixture file is
+    ///   replaced with a [`MockSubgraph`] serving the recorded responses.
+    ///   A request that was not recorded gets an error response.
+    ///
+    /// [`SubgraphFixturesMode::from_env`] lets the same test record or replay depending
+    /// on how it is run.
+    pub fn subgraph_fixtures(
+        mut self,
+        dir: impl Into<PathBuf>,
+        mode: SubgraphFixturesMode,
+    ) -> Self {
+        assert!(
+            self.subgraph_fixtures.is_none(),
+            "subgraph fixtures were specified twice"
+        );
+        if mode == SubgraphFixturesMode::Record {
+            self.subgraph_network_requests = true;
+        }
+        self.subgraph_fixtures = Some((dir.into(), mode));
+        self
+    }
+
+    /// Resolves the subscriptions to `field`, a field of the subscription type, with `events`
+    /// instead of consuming them from Kafka or NATS.
+    ///
+    /// May be called multiple times, once per field.
+    /// This replaces the source of the field in the `event_subscriptions` section of the
+    /// configuration, if any. The events are sent in order to the first subscription to the
+    /// field, whose response stream then ends. They are JSON values of the type of the field.
+    pub fn subscription_events(
+        mut self,
+        field: &str,
+        events: impl IntoIterator<Item = serde_json::Value>,
+    ) -> Self {
+        self.subscription_events
+            .insert(field.to_string(), events.into_iter().collect());
+        self
+    }
+
     /// Enables this test harness to make network requests to subgraphs.
     ///
     /// If this is not called, all subgraph requests get an empty response by default
@@ -180,7 +306,62 @@ impl<'a> TestHarness<'a> {
         self
     }
 
-    async fn build_common(self) -> Result<(Arc<Configuration>, SupergraphCreator), BoxError> {
+    async fn build_common(mut self) -> Result<(Arc<Configuration>, SupergraphCreator), BoxError> {
+        // Inserted first so that they wrap the subgraph hooks and mocks of other extra plugins
+        match self.subgraph_fixtures.take() {
+            Some((dir, SubgraphFixturesMode::Record)) => {
+                self.extra_plugins.insert(
+                    0,
+                    (
+                        "test_harness.record_subgraph_fixtures".to_string(),
+                        Box::new(RecordSubgraphFixturesPlugin {
+                            dir,
+                            fixtures: Default::default(),
+                        }),
+                    ),
+                );
+            }
+            Some((dir, SubgraphFixturesMode::Replay)) => {
+                let mocks = load_subgraph_fixtures(&dir)?;
+                self.extra_plugins.insert(
+                    0,
+                    (
+                        "test_harness.replay_subgraph_fixtures".to_string(),
+                        Box::new(SubgraphServicePlugin(
+                            move |subgraph_name: &str, default: subgraph::BoxService| {
+                                mocks
+                                    .get(subgraph_name)
+                                    .map(|mock| mock.clone().boxed())
+                                    .unwrap_or(default)
+                            },
+                        )),
+                    ),
+                );
+            }
+            None => {}
+        }
+        if !self.subgraph_faults.is_empty() {
+            let faults = FaultInjection::from_config(fault_injection::Config {
+                all: None,
+                subgraphs: std::mem::take(&mut self.subgraph_faults),
+            })?;
+            self.extra_plugins.insert(
+                0,
+                (
+                    fault_injection::APOLLO_FAULT_INJECTION.to_string(),
+                    Box::new(faults),
+                ),
+            );
+        }
+        if let Some(recorded) = self.recorded_subgraph_requests.take() {
+            self.extra_plugins.insert(
+                0,
+                (
+                    "test_harness.record_subgraph_requests".to_string(),
+                    Box::new(RecordSubgraphRequestsPlugin(recorded)),
+                ),
+            );
+        }
         let builder = if self.schema.is_none() {
             self.subgraph_hook(|subgraph_name, default| match subgraph_name {
                 "products" => canned::products_subgraph().boxed(),
@@ -205,9 +386,25 @@ impl<'a> TestHarness<'a> {
                 .boxed()
             })
         };
-        let config = builder.configuration.unwrap_or_default();
+        let mut config = builder.configuration.unwrap_or_default();
+        if !builder.subscription_events.is_empty() {
+            let fields = Arc::make_mut(&mut config)
+                .apollo_plugins
+                .plugins
+                .entry("event_subscriptions")
+                .or_insert_with(|| serde_json::json!({}))
+                .as_object_mut()
+                .ok_or("the event_subscriptions configuration is not an object")?
+                .entry("fields")
+                .or_insert_with(|| serde_json::json!({}))
+                .as_object_mut()
+                .ok_or("the event_subscriptions fields are not an object")?;
+            for (field, events) in builder.subscription_events {
+                fields.insert(field, serde_json::json!({ "mock": { "events": events } }));
+            }
+        }
         let canned_schema = include_str!("../testing_schema.graphql");
-        let schema = builder.schema.unwrap_or(canned_schema);
+        let schema = builder.schema.as_deref().unwrap_or(canned_schema);
         let schema = Arc::new(Schema::parse(schema, &config)?);
         let supergraph_creator = YamlRouterFactory
             .create_supergraph(config.clone(), schema, None, Some(builder.extra_plugins))
@@ -237,7 +434,7 @@ impl<'a> TestHarness<'a> {
     /// Builds the router service
     pub async fn build_router(self) -> Result<router::BoxCloneService, BoxError> {
         let (config, supergraph_creator) = self.build_common().await?;
-        let router_creator = RouterCreator::new(Arc::new(supergraph_creator), &config).await;
+        let router_creator = RouterCreator::new(Arc::new(supergraph_creator), &config).await?;
 
         Ok(tower::service_fn(move |request: router::Request| {
             let router = ServiceBuilder::new().service(router_creator.make()).boxed();
@@ -254,10 +451,10 @@ impl<'a> TestHarness<'a> {
         use crate::router_factory::RouterFactory;
 
         let (config, supergraph_creator) = self.build_common().await?;
-        let router_creator = RouterCreator::new(Arc::new(supergraph_creator), &config).await;
+        let router_creator = RouterCreator::new(Arc::new(supergraph_creator), &config).await?;
         let web_endpoints = router_creator.web_endpoints();
 
-        let routers = make_axum_router(router_creator, &config, web_endpoints)?;
+        let routers = make_axum_router(router_creator, &config, web_endpoints, Default::default())?;
         let ListenAddrAndRouter(_listener, router) = routers.main;
         Ok(router.boxed())
     }
@@ -344,6 +541,279 @@ where
     }
 }
 
+/// A request sent to a subgraph, as recorded by [`TestHarness::record_subgraph_requests`]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RecordedSubgraphRequest {
+    /// Name of the subgraph the request was sent to
+    pub subgraph_name: String,
+    /// HTTP method of the subgraph request
+    pub method: http::Method,
+    /// URI of the subgraph request
+    pub uri: http::Uri,
+    /// HTTP headers of the subgraph request
+    pub headers: http::HeaderMap,
+    /// GraphQL body of the subgraph request
+    pub body: graphql::Request,
+}
+
+/// Requests sent to subgraphs during a test, in the order they were issued
+///
+/// Clones share the same list, so a clone can be given to
+/// [`TestHarness::record_subgraph_requests`] and inspected once the test requests have been made.
+/// Fetches executed in parallel by a query plan are recorded in a nondeterministic order.
+#[derive(Clone, Debug, Default)]
+pub struct RecordedSubgraphRequests(Arc<Mutex<Vec<RecordedSubgraphRequest>>>);
+
+impl RecordedSubgraphRequests {
+    /// Creates an empty recording
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All the recorded requests
+    pub fn all(&self) -> Vec<RecordedSubgraphRequest> {
+        self.0.lock().expect("lock poisoned").clone()
+    }
+
+    /// The requests recorded for one subgraph
+    pub fn for_subgraph(&self, subgraph_name: &str) -> Vec<RecordedSubgraphRequest> {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter(|request| request.subgraph_name == subgraph_name)
+            .cloned()
+            .collect()
+    }
+
+    /// Number of recorded requests
+    pub fn len(&self) -> usize {
+        self.0.lock().expect("lock poisoned").len()
+    }
+
+    /// Returns true if no request was recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all the recorded requests
+    pub fn clear(&self) {
+        self.0.lock().expect("lock poisoned").clear()
+    }
+
+    fn record(&self, subgraph_name: &str, request: &subgraph::Request) {
+        let http_request = &request.subgraph_request;
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .push(RecordedSubgraphRequest {
+                subgraph_name: subgraph_name.to_string(),
+                method: http_request.method().clone(),
+                uri: http_request.uri().clone(),
+                headers: http_request.headers().clone(),
+                body: http_request.body().clone(),
+            });
+    }
+}
+
+struct RecordSubgraphRequestsPlugin(RecordedSubgraphRequests);
+
+#[async_trait::async_trait]
+impl Plugin for RecordSubgraphRequestsPlugin {
+    type Config = ();
+
+    async fn new(_: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        unreachable!()
+    }
+
+    fn subgraph_service(
+        &self,
+        subgraph_name: &str,
+        service: subgraph::BoxService,
+    ) -> subgraph::BoxService {
+        let recorded = self.0.clone();
+        let subgraph_name = subgraph_name.to_string();
+        service
+            .map_request(move |request: subgraph::Request| {
+                recorded.record(&subgraph_name, &request);
+                request
+            })
+            .boxed()
+    }
+}
+
+/// Whether [`TestHarness::subgraph_fixtures`] records or replays subgraph traffic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubgraphFixturesMode {
+    /// Send requests to subgraphs and save them with their responses
+    Record,
+    /// Serve the saved responses without sending requests to subgraphs
+    Replay,
+}
+
+impl SubgraphFixturesMode {
+    /// Environment variable selecting the mode in [`from_env`][Self::from_env]
+    pub const ENV_VAR: &'static str = "APOLLO_ROUTER_RECORD_SUBGRAPH_FIXTURES";
+
+    /// [`Record`][Self::Record] if the `APOLLO_ROUTER_RECORD_SUBGRAPH_FIXTURES`
+    /// environment variable is set to `true` or `1`, [`Replay`][Self::Replay] otherwise
+    pub fn from_env() -> Self {
+        match std::env::var(Self::ENV_VAR).as_deref() {
+            Ok("true") | Ok("1") => Self::Record,
+            _ => Self::Replay,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SubgraphFixture {
+    request: graphql::Request,
+    response: graphql::Response,
+}
+
+fn subgraph_fixture_path(dir: &Path, subgraph_name: &str) -> PathBuf {
+    dir.join(format!("{subgraph_name}.json"))
+}
+
+fn load_subgraph_fixtures(dir: &Path) -> Result<HashMap<String, MockSubgraph>, BoxError> {
+    let mut subgraphs = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+        let subgraph_name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_string(),
+            None => continue,
+        };
+        let fixtures: Vec<SubgraphFixture> = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|error| {
+                format!("invalid subgraph fixture file {}: {error}", path.display())
+            })?;
+        let mocks = fixtures
+            .into_iter()
+            .map(|fixture| (fixture.request, fixture.response))
+            .collect();
+        subgraphs.insert(subgraph_name, MockSubgraph::new(mocks));
+    }
+    Ok(subgraphs)
+}
+
+struct RecordSubgraphFixturesPlugin {
+    dir: PathBuf,
+    fixtures: Arc<Mutex<HashMap<String, Vec<SubgraphFixture>>>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for RecordSubgraphFixturesPlugin {
+    type Config = ();
+
+    async fn new(_: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        unreachable!()
+    }
+
+    fn subgraph_service(
+        &self,
+        subgraph_name: &str,
+        service: subgraph::BoxService,
+    ) -> subgraph::BoxService {
+        let path = subgraph_fixture_path(&self.dir, subgraph_name);
+        let fixtures = self.fixtures.clone();
+        let subgraph_name = subgraph_name.to_string();
+        ServiceBuilder::new()
+            .map_future_with_request_data(
+                |request: &subgraph::Request| request.subgraph_request.body().clone(),
+                move |request: graphql::Request, response| {
+                    let path = path.clone();
+                    let fixtures = fixtures.clone();
+                    let subgraph_name = subgraph_name.clone();
+                    async move {
+                        let response: subgraph::Response = response.await?;
+                        let fixture = SubgraphFixture {
+                            request,
+                            response: response.response.body().clone(),
+                        };
+                        // Serialized while holding the lock so that concurrent fetches
+                        // cannot write an older list after a newer one
+                        let mut fixtures = fixtures.lock().expect("lock poisoned");
+                        let subgraph_fixtures = fixtures.entry(subgraph_name).or_default();
+                        subgraph_fixtures.retain(|recorded| recorded.request != fixture.request);
+                        subgraph_fixtures.push(fixture);
+                        std::fs::write(&path, serde_json::to_string_pretty(subgraph_fixtures)?)?;
+                        Ok::<_, BoxError>(response)
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+/// A response parsed into its primary and incremental parts
+///
+/// With `@defer`, the router sends a `multipart/mixed` HTTP response:
+/// a primary part followed by parts carrying `incremental` payloads.
+/// This collects them so that tests can assert on typed values
+/// instead of comparing boundary-delimited bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MultipartResponse {
+    /// The first response, with the non-deferred data
+    pub primary: graphql::Response,
+    /// The payloads of the `incremental` arrays of the following parts, in order
+    pub incremental: Vec<graphql::IncrementalResponse>,
+    /// Number of responses received, including the primary one
+    pub parts: usize,
+}
+
+impl MultipartResponse {
+    /// Reads the whole body of a response returned by [`TestHarness::build_router`]
+    ///
+    /// Both `multipart/mixed` and single JSON responses are accepted,
+    /// the latter giving a primary response without incremental parts.
+    pub async fn from_router_response(response: router::Response) -> Result<Self, BoxError> {
+        let mut stream = response.into_graphql_response_stream().await;
+        let mut responses = Vec::new();
+        while let Some(response) = stream.next().await {
+            responses.push(response?);
+        }
+        Self::from_responses(responses)
+    }
+
+    /// Reads all the responses of a response returned by [`TestHarness::build_supergraph`]
+    pub async fn from_supergraph_response(
+        mut response: supergraph::Response,
+    ) -> Result<Self, BoxError> {
+        let mut responses = Vec::new();
+        while let Some(response) = response.next_response().await {
+            responses.push(response);
+        }
+        Self::from_responses(responses)
+    }
+
+    fn from_responses(responses: Vec<graphql::Response>) -> Result<Self, BoxError> {
+        let parts = responses.len();
+        let mut responses = responses.into_iter();
+        let primary = responses.next().ok_or("the response has no parts")?;
+        let incremental = responses
+            .flat_map(|response| response.incremental)
+            .collect();
+        Ok(Self {
+            primary,
+            incremental,
+            parts,
+        })
+    }
+
+    /// Returns the incremental payload with this `label`, if any
+    pub fn incremental_with_label(&self, label: &str) -> Option<&graphql::IncrementalResponse> {
+        self.incremental
+            .iter()
+            .find(|incremental| incremental.label.as_deref() == Some(label))
+    }
+}
+
 /// a list of subgraphs with pregenerated responses
 #[derive(Default)]
 pub struct MockedSubgraphs(pub(crate) HashMap<&'static str, MockSubgraph>);
@@ -374,3 +844,304 @@ impl Plugin for MockedSubgraphs {
             .unwrap_or(default)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn subgraph_mock_counts_calls() {
+        let products = MockSubgraph::builder()
+            .with_json_expected(
+                json! {{"query": "{topProducts{upc}}"}},
+                json! {{"data": {"topProducts": [{"upc": "1"}, {"upc": "2"}]}}},
+                2,
+            )
+            .build();
+
+        let service = TestHarness::builder()
+            .subgraph_mock("products", products.clone())
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let request = supergraph::Request::fake_builder()
+                .query("{ topProducts { upc } }")
+                .build()
+                .unwrap();
+            let response = service
+                .clone()
+                .oneshot(request)
+                .await
+                .unwrap()
+                .next_response()
+                .await
+                .unwrap();
+            assert_eq!(
+                serde_json::to_value(&response.data).unwrap(),
+                json! {{"topProducts": [{"upc": "1"}, {"upc": "2"}]}}
+            );
+        }
+
+        assert_eq!(products.calls(json! {{"query": "{topProducts{upc}}"}}), 2);
+        assert_eq!(products.total_calls(), 2);
+        products.assert_expectations();
+    }
+
+    #[tokio::test]
+    async fn schema_from_file() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("src");
+        path.push("testdata");
+        path.push("minimal_supergraph.graphql");
+
+        let accounts = MockSubgraph::builder()
+            .with_json(
+                json! {{"query": "{me}"}},
+                json! {{"data": {"me": "Ada Lovelace"}}},
+            )
+            .build();
+        let service = TestHarness::builder()
+            .schema_file(path)
+            .unwrap()
+            .subgraph_mock("accounts", accounts)
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .query("{ me }")
+            .build()
+            .unwrap();
+        let response = service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&response.data).unwrap(),
+            json! {{"me": "Ada Lovelace"}}
+        );
+    }
+
+    async fn query_with_fixtures(
+        dir: &Path,
+        mode: SubgraphFixturesMode,
+        query: &str,
+    ) -> graphql::Response {
+        let request = supergraph::Request::fake_builder()
+            .query(query)
+            .build()
+            .unwrap();
+        TestHarness::builder()
+            .subgraph_fixtures(dir, mode)
+            .build_supergraph()
+            .await
+            .unwrap()
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn record_and_replay_subgraph_fixtures() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // The canned subgraphs stand in for real ones while recording
+        let recorded = query_with_fixtures(
+            dir.path(),
+            SubgraphFixturesMode::Record,
+            "{ topProducts { upc } }",
+        )
+        .await;
+        assert!(recorded.errors.is_empty());
+        assert!(dir.path().join("products.json").exists());
+
+        let replayed = query_with_fixtures(
+            dir.path(),
+            SubgraphFixturesMode::Replay,
+            "{ topProducts { upc } }",
+        )
+        .await;
+        assert_eq!(replayed, recorded);
+
+        let not_recorded = query_with_fixtures(
+            dir.path(),
+            SubgraphFixturesMode::Replay,
+            "{ topProducts { name } }",
+        )
+        .await;
+        // The canned products subgraph would have answered this one
+        assert_eq!(not_recorded.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mock_subscription_events() {
+        let schema = r#"
+            schema
+              @core(feature: "https://specs.apollo.dev/core/v0.1")
+              @core(feature: "https://specs.apollo.dev/join/v0.1") {
+              query: Query
+              subscription: Subscription
+            }
+
+            directive @core(feature: String!) repeatable on SCHEMA
+            directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+
+            enum join__Graph {
+              REVIEWS @join__graph(name: "reviews", url: "http://localhost:4002/graphql")
+            }
+
+            type Query {
+              me: String
+            }
+
+            type Review {
+              productId: ID!
+              body: String
+            }
+
+            type Subscription {
+              reviewAdded(productId: ID!): Review
+            }
+        "#;
+        let service = TestHarness::builder()
+            .schema(schema)
+            .subscription_events(
+                "reviewAdded",
+                [
+                    json! {{"productId": "1", "body": "great"}},
+                    json! {{"productId": "2", "body": "not sent"}},
+                    json! {{"productId": "1", "body": "bad"}},
+                ],
+            )
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let context = crate::Context::new();
+        context
+            .insert(
+                crate::services::layers::content_negociation::ACCEPTS_MULTIPART_CONTEXT_KEY,
+                true,
+            )
+            .unwrap();
+        let request = supergraph::Request::fake_builder()
+            .query(r#"subscription { reviewAdded(productId: "1") { body } }"#)
+            .context(context)
+            .build()
+            .unwrap();
+        let mut response = service.oneshot(request).await.unwrap();
+
+        // The event of the other product is not sent to the subscription
+        for body in ["great", "bad"] {
+            let response = response.next_response().await.unwrap();
+            assert_eq!(
+                serde_json::to_value(&response.data).unwrap(),
+                json! {{"reviewAdded": {"body": body}}}
+            );
+            assert_eq!(response.has_next, Some(true));
+        }
+        assert!(response.next_response().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_router_response() {
+        let body = concat!(
+            "\r\n--graphql\r\ncontent-type: application/json\r\n\r\n",
+            r#"{"data":{"me":{"id":"1"}},"hasNext":true}"#,
+            "\r\n--graphql\r\ncontent-type: application/json\r\n\r\n",
+            r#"{"hasNext":true,"incremental":[{"label":"name","data":{"name":"Ada"},"path":["me"]}]}"#,
+            "\r\n--graphql\r\ncontent-type: application/json\r\n\r\n",
+            r#"{"hasNext":false}"#,
+            "\r\n--graphql--\r\n",
+        );
+        let response = http::Response::builder()
+            .header(
+                http::header::CONTENT_TYPE,
+                crate::services::MULTIPART_DEFER_CONTENT_TYPE,
+            )
+            .body(hyper::Body::from(body))
+            .unwrap();
+
+        let response = MultipartResponse::from_router_response(response.into())
+            .await
+            .unwrap();
+        assert_eq!(response.parts, 3);
+        assert_eq!(
+            serde_json::to_value(&response.primary).unwrap(),
+            json! {{"data": {"me": {"id": "1"}}, "hasNext": true}}
+        );
+        assert_eq!(response.incremental.len(), 1);
+        assert_eq!(
+            serde_json::to_value(response.incremental_with_label("name").unwrap()).unwrap(),
+            json! {{"label": "name", "data": {"name": "Ada"}, "path": ["me"]}}
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_single_router_response() {
+        let response = http::Response::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(r#"{"data":{"me":null}}"#))
+            .unwrap();
+
+        let response = MultipartResponse::from_router_response(response.into())
+            .await
+            .unwrap();
+        assert_eq!(response.parts, 1);
+        assert!(response.incremental.is_empty());
+        assert_eq!(
+            serde_json::to_value(&response.primary).unwrap(),
+            json! {{"data": {"me": null}}}
+        );
+    }
+
+    #[tokio::test]
+    async fn record_subgraph_requests() {
+        let recorded = RecordedSubgraphRequests::new();
+        let service = TestHarness::builder()
+            .configuration_json(json!({
+                "headers": {
+                    "all": {
+                        "request": [{"insert": {"name": "x-test", "value": "recorded"}}]
+                    }
+                }
+            }))
+            .unwrap()
+            .record_subgraph_requests(recorded.clone())
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .query("{ topProducts { upc } }")
+            .build()
+            .unwrap();
+        service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+
+        assert_eq!(recorded.len(), 1);
+        let products = recorded.for_subgraph("products");
+        assert_eq!(products.len(), 1);
+        assert_eq!(products[0].headers.get("x-test").unwrap(), "recorded");
+        assert_eq!(
+            products[0].body.query.as_deref(),
+            Some("{topProducts{upc}}")
+        );
+        assert!(recorded.for_subgraph("reviews").is_empty());
+    }
+}
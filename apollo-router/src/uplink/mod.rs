@@ -11,6 +11,8 @@ use futures::Stream;
 use graphql_client::GraphQLQuery;
 use graphql_client::QueryBody;
 use graphql_client::Response;
+use ring::signature::UnparsedPublicKey;
+use ring::signature::ED25519;
 use supergraph_sdl::FetchErrorCode;
 use tokio::sync::mpsc::channel;
 use tokio_stream::wrappers::ReceiverStream;
@@ -21,6 +23,8 @@ use self::supergraph_sdl::SupergraphSdlRouterConfigOnFetchError;
 
 const GCP_URL: &str = "https://uplink.api.apollographql.com/graphql";
 const AWS_URL: &str = "https://aws.uplink.api.apollographql.com/graphql";
+/// Response extension containing the signature of the schema, sent by self-hosted registries
+const SIGNATURE_EXTENSION: &str = "supergraphSdlSignature";
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -50,6 +54,37 @@ pub(crate) struct Schema {
     pub(crate) schema: String,
 }
 
+/// Ed25519 public key verifying the schemas delivered by a self-hosted registry
+///
+/// The registry implements the Uplink protocol, and signs the UTF-8 bytes of each supergraph
+/// schema with its private key. The base64 encoded signature is sent in the
+/// `supergraphSdlSignature` extension of the response, and schemas without a valid signature
+/// are rejected.
+#[derive(Clone, Debug)]
+pub(crate) struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+    pub(crate) fn from_base64(key: &str) -> Result<Self, String> {
+        let key = base64::decode(key.trim()).map_err(|e| format!("invalid public key: {e}"))?;
+        if key.len() != 32 {
+            return Err(format!(
+                "invalid public key: expected 32 bytes for an Ed25519 key, got {}",
+                key.len()
+            ));
+        }
+        Ok(PublicKey(key))
+    }
+
+    fn verify(&self, schema: &str, signature: Option<&str>) -> Result<(), String> {
+        let signature = signature.ok_or_else(|| "the schema is not signed".to_string())?;
+        let signature =
+            base64::decode(signature).map_err(|e| format!("invalid signature encoding: {e}"))?;
+        UnparsedPublicKey::new(&ED25519, &self.0)
+            .verify(schema.as_bytes(), &signature)
+            .map_err(|_| "the signature does not match the schema".to_string())
+    }
+}
+
 /// regularly download a schema from Uplink
 pub(crate) fn stream_supergraph(
     api_key: String,
@@ -57,6 +92,7 @@ pub(crate) fn stream_supergraph(
     urls: Option<Vec<Url>>,
     mut interval: Duration,
     timeout: Duration,
+    public_key: Option<PublicKey>,
 ) -> impl Stream<Item = Result<Schema, String>> {
     let (sender, receiver) = channel(2);
     let _ = tokio::task::spawn(async move {
@@ -75,10 +111,25 @@ pub(crate) fn stream_supergraph(
             )
             .await
             {
-                Ok(value) => match value.router_config {
+                Ok((value, signature)) => match value.router_config {
                     supergraph_sdl::SupergraphSdlRouterConfig::RouterConfigResult(
                         schema_config,
                     ) => {
+                        // The composition ID is not updated, so that the schema is downloaded
+                        // again at the next poll
+                        if let Some(Err(err)) = public_key.as_ref().map(|public_key| {
+                            public_key.verify(&schema_config.supergraph_sdl, signature.as_deref())
+                        }) {
+                            if sender
+                                .send(Err(format!("rejected the schema from the registry: {err}")))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                            tokio::time::sleep(interval).await;
+                            continue;
+                        }
                         composition_id = Some(schema_config.id.clone());
                         if sender
                             .send(Ok(Schema {
@@ -146,7 +197,7 @@ pub(crate) async fn fetch_supergraph(
     composition_id: Option<String>,
     url: Option<&Url>,
     timeout: Duration,
-) -> Result<supergraph_sdl::ResponseData, Error> {
+) -> Result<(supergraph_sdl::ResponseData, Option<String>), Error> {
     let variables = supergraph_sdl::Variables {
         api_key,
         graph_ref,
@@ -176,9 +227,15 @@ pub(crate) async fn fetch_supergraph(
         },
     };
 
+    let signature = response
+        .extensions
+        .as_ref()
+        .and_then(|extensions| extensions.get(SIGNATURE_EXTENSION))
+        .and_then(|signature| signature.as_str())
+        .map(str::to_string);
     match response.data {
         None => Err(Error::EmptyResponse),
-        Some(response_data) => Ok(response_data),
+        Some(response_data) => Ok((response_data, signature)),
     }
 }
 
@@ -228,3 +285,31 @@ fn test_uplink_schema_is_up_to_date() {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::Ed25519KeyPair;
+    use ring::signature::KeyPair;
+
+    use super::*;
+
+    #[test]
+    fn it_verifies_schema_signatures() {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let public_key =
+            PublicKey::from_base64(&base64::encode(key_pair.public_key().as_ref())).unwrap();
+        let schema = "type Query { me: String }";
+        let signature = base64::encode(key_pair.sign(schema.as_bytes()).as_ref());
+
+        assert!(public_key.verify(schema, Some(&signature)).is_ok());
+        assert_eq!(
+            public_key.verify("type Query { admin: String }", Some(&signature)),
+            Err("the signature does not match the schema".to_string())
+        );
+        assert_eq!(
+            public_key.verify(schema, None),
+            Err("the schema is not signed".to_string())
+        );
+        assert!(PublicKey::from_base64(&base64::encode([7; 16])).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Metrics identifying the schema and configuration served by the router
+//!
+//! Info metrics are up-down counters set to 1 for the active schema id and configuration hash,
+//! and brought back to 0 when they are replaced, so that dashboards can show which schema each
+//! replica is serving.
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::spec::Schema;
+use crate::Configuration;
+
+/// Kind of reload, recorded in the `kind` attribute of `apollo_router_reload_total`
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ReloadKind {
+    Schema,
+    Configuration,
+}
+
+/// The schema and configuration reported by the info metrics
+#[derive(Debug, Default)]
+pub(crate) struct ActiveVersions {
+    schema_id: Option<String>,
+    schema_loaded_at: f64,
+    config_hash: Option<String>,
+}
+
+impl ActiveVersions {
+    /// Records the schema and configuration the router switched to
+    pub(crate) fn record(&mut self, configuration: &Configuration, schema: &Schema) {
+        let schema_id = schema.schema_id.clone().unwrap_or_default();
+        if self.schema_id.as_ref() != Some(&schema_id) {
+            if let Some(previous) = self.schema_id.replace(schema_id.clone()) {
+                tracing::info!(
+                    counter.apollo_router_schema_info = -1i64,
+                    schema_id = previous.as_str(),
+                );
+            }
+            tracing::info!(
+                counter.apollo_router_schema_info = 1i64,
+                schema_id = schema_id.as_str(),
+            );
+
+            // the counter is moved by the time elapsed since the previous load, so that its value
+            // is the timestamp of the last one
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs_f64())
+                .unwrap_or_default();
+            tracing::info!(
+                counter.apollo_router_schema_load_timestamp_seconds = now - self.schema_loaded_at,
+            );
+            self.schema_loaded_at = now;
+        }
+
+        let config_hash = config_hash(configuration);
+        if self.config_hash.as_ref() != Some(&config_hash) {
+            if let Some(previous) = self.config_hash.replace(config_hash.clone()) {
+                tracing::info!(
+                    counter.apollo_router_config_info = -1i64,
+                    config_hash = previous.as_str(),
+                );
+            }
+            tracing::info!(
+                counter.apollo_router_config_info = 1i64,
+                config_hash = config_hash.as_str(),
+            );
+        }
+    }
+}
+
+/// Counts the successful and failed reloads
+pub(crate) fn record_reload(kind: ReloadKind, success: bool) {
+    let kind = match kind {
+        ReloadKind::Schema => "schema",
+        ReloadKind::Configuration => "configuration",
+    };
+    tracing::info!(
+        monotonic_counter.apollo_router_reload_total = 1u64,
+        kind,
+        result = if success { "success" } else { "failure" },
+    );
+}
+
+/// Hash of the configuration, as it was validated
+pub(crate) fn config_hash(configuration: &Configuration) -> String {
+    let serialized = match &configuration.validated_yaml {
+        Some(validated) => serde_json::to_vec(validated),
+        None => serde_json::to_vec(configuration),
+    }
+    .unwrap_or_default();
+    format!("{:x}", Sha256::digest(serialized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hashes_the_configuration() {
+        let configuration: Configuration = "supergraph:\n  path: /graphql\n".parse().unwrap();
+        let same: Configuration = "supergraph:\n  path: /graphql\n".parse().unwrap();
+        let other: Configuration = "supergraph:\n  path: /\n".parse().unwrap();
+
+        assert_eq!(config_hash(&configuration), config_hash(&same));
+        assert_ne!(config_hash(&configuration), config_hash(&other));
+    }
+}
@@ -0,0 +1,26 @@
+// Used by the javascript plugin tests
+
+function supergraph_service(service) {
+  service.map_request((request) => {
+    const client_name = request.headers["x-client-name"];
+    if (client_name === undefined) {
+      throw { status: 401, message: "missing x-client-name header" };
+    }
+    request.context.client_name = client_name[0];
+  });
+
+  service.map_response((response) => {
+    response.headers["x-client-name"] = [response.context.client_name];
+  });
+}
+
+function subgraph_service(service, subgraph) {
+  service.map_request((request) => {
+    request.headers["x-subgraph"] = [subgraph];
+  });
+
+  service.map_response((response) => {
+    console.log(`rewriting the response of ${subgraph}`);
+    response.body.data.me = "rewritten by javascript";
+  });
+}
@@ -0,0 +1,27 @@
+(module
+  (import "apollo_router" "header_get" (func $header_get (param i32 i32 i32 i32) (result i32)))
+  (import "apollo_router" "header_set" (func $header_set (param i32 i32 i32 i32) (result i32)))
+  (import "apollo_router" "subgraph_name" (func $subgraph_name (param i32 i32) (result i32)))
+  (import "apollo_router" "error_set" (func $error_set (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "x-client-name")
+  (data (i32.const 16) "missing x-client-name header")
+  (data (i32.const 48) "x-subgraph")
+
+  ;; Rejects supergraph requests without an x-client-name header
+  (func (export "on_supergraph_request") (result i32)
+    (if (i32.lt_s
+          (call $header_get (i32.const 0) (i32.const 13) (i32.const 256) (i32.const 256))
+          (i32.const 0))
+      (then
+        (call $error_set (i32.const 16) (i32.const 28))
+        (return (i32.const 401))))
+    (i32.const 0))
+
+  ;; Sends the subgraph name in an x-subgraph header
+  (func (export "on_subgraph_request") (result i32)
+    (local $len i32)
+    (local.set $len (call $subgraph_name (i32.const 256) (i32.const 256)))
+    (drop (call $header_set (i32.const 48) (i32.const 10) (i32.const 256) (local.get $len)))
+    (i32.const 0))
+)
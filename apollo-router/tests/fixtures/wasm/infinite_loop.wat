@@ -0,0 +1,9 @@
+(module
+  (memory (export "memory") 1)
+
+  ;; Never returns, so the hook runs out of fuel
+  (func (export "on_supergraph_request") (result i32)
+    (loop $forever
+      (br $forever))
+    (i32.const 0))
+)
@@ -0,0 +1,36 @@
+// A minimal co-processor for the experimental.external plugin.
+//
+// The router POSTs a JSON payload for each configured stage. The co-processor
+// returns the same payload, with any changes to `headers`, `body` or `context`
+// applied, or sets `control` to `{ "Break": <status> }` to stop the request.
+//
+// Usage: node coprocessor.js
+const http = require("http");
+
+function process(payload) {
+  switch (payload.stage) {
+    case "RouterRequest":
+      if (!payload.headers || !payload.headers["x-client-name"]) {
+        payload.control = { Break: 401 };
+        payload.body = "missing x-client-name header";
+      }
+      break;
+    case "SubgraphRequest":
+      payload.headers = payload.headers || {};
+      payload.headers["x-from-coprocessor"] = [payload.service_name];
+      break;
+  }
+  return payload;
+}
+
+http
+  .createServer((req, res) => {
+    let body = "";
+    req.on("data", (chunk) => (body += chunk));
+    req.on("end", () => {
+      const payload = process(JSON.parse(body));
+      res.setHeader("content-type", "application/json");
+      res.end(JSON.stringify(payload));
+    });
+  })
+  .listen(8081, "127.0.0.1");